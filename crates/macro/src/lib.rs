@@ -168,6 +168,17 @@ fn walrus_attrs(attrs: &mut Vec<syn::Attribute>) -> TokenStream {
     return ret.into();
 }
 
+/// The `#[cfg(...)]` attributes of a variant, which need to be repeated on
+/// every item generated for that variant.
+fn cfg_attrs(variant: &WalrusVariant) -> Vec<&syn::Attribute> {
+    variant
+        .syn
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("cfg"))
+        .collect()
+}
+
 fn create_types(attrs: &[syn::Attribute], variants: &[WalrusVariant]) -> impl quote::ToTokens {
     let types: Vec<_> = variants
         .iter()
@@ -183,6 +194,7 @@ fn create_types(attrs: &[syn::Attribute], variants: &[WalrusVariant]) -> impl qu
                     pub #name : #ty,
                 }
             });
+            let cfgs = cfg_attrs(v);
             quote! {
                 #( #attrs )*
                 #[derive(Clone, Debug)]
@@ -190,6 +202,7 @@ fn create_types(attrs: &[syn::Attribute], variants: &[WalrusVariant]) -> impl qu
                     #( #fields )*
                 }
 
+                #( #cfgs )*
                 impl From<#name> for Instr {
                     #[inline]
                     fn from(x: #name) -> Instr {
@@ -241,6 +254,8 @@ fn create_types(attrs: &[syn::Attribute], variants: &[WalrusVariant]) -> impl qu
 
             let is_name_doc = format!("Is this instruction a `{}`?", name);
 
+            let cfgs = cfg_attrs(v);
+
             let unwrap_name_doc = format!(
                 "
                 Get a shared reference to the underlying `{}`.
@@ -261,6 +276,7 @@ fn create_types(attrs: &[syn::Attribute], variants: &[WalrusVariant]) -> impl qu
 
             quote! {
                 #[doc=#ref_name_doc]
+                #( #cfgs )*
                 #[inline]
                 fn #ref_name(&self) -> Option<&#name> {
                     if let Instr::#name(ref x) = *self {
@@ -271,6 +287,7 @@ fn create_types(attrs: &[syn::Attribute], variants: &[WalrusVariant]) -> impl qu
                 }
 
                 #[doc=#mut_name_doc]
+                #( #cfgs )*
                 #[inline]
                 pub fn #mut_name(&mut self) -> Option<&mut #name> {
                     if let Instr::#name(ref mut x) = *self {
@@ -281,18 +298,21 @@ fn create_types(attrs: &[syn::Attribute], variants: &[WalrusVariant]) -> impl qu
                 }
 
                 #[doc=#is_name_doc]
+                #( #cfgs )*
                 #[inline]
                 pub fn #is_name(&self) -> bool {
                     self.#ref_name().is_some()
                 }
 
                 #[doc=#unwrap_name_doc]
+                #( #cfgs )*
                 #[inline]
                 pub fn #unwrap_name(&self) -> &#name {
                     self.#ref_name().unwrap()
                 }
 
                 #[doc=#unwrap_mut_name_doc]
+                #( #cfgs )*
                 #[inline]
                 pub fn #unwrap_mut_name(&mut self) -> &mut #name {
                     self.#mut_name().unwrap()
@@ -387,6 +407,7 @@ fn create_visit(variants: &[WalrusVariant]) -> impl quote::ToTokens {
 
     for variant in variants {
         let name = &variant.syn.ident;
+        let cfgs = cfg_attrs(variant);
 
         let mut method_name = "visit_".to_string();
         method_name.push_str(&name.to_string().to_snake_case());
@@ -424,12 +445,14 @@ fn create_visit(variants: &[WalrusVariant]) -> impl quote::ToTokens {
             });
 
         visit_impls.push(quote! {
+            #( #cfgs )*
             impl<'instr> Visit<'instr> for #name {
                 #[inline]
                 fn visit<V: Visitor<'instr>>(&self, visitor: &mut V) {
                     #(#recurse_fields);*
                 }
             }
+            #( #cfgs )*
             impl VisitMut for #name {
                 #[inline]
                 fn visit_mut<V: VisitorMut>(&mut self, visitor: &mut V) {
@@ -440,6 +463,7 @@ fn create_visit(variants: &[WalrusVariant]) -> impl quote::ToTokens {
 
         let doc = format!("Visit `{}`.", name.to_string());
         visitor_trait_methods.push(quote! {
+            #( #cfgs )*
             #[doc=#doc]
             #[inline]
             fn #method_name(&mut self, instr: &#name) {
@@ -447,10 +471,11 @@ fn create_visit(variants: &[WalrusVariant]) -> impl quote::ToTokens {
             }
         });
         visitor_mut_trait_methods.push(quote! {
+            #( #cfgs )*
             #[doc=#doc]
             #[inline]
             fn #method_name_mut(&mut self, instr: &mut #name) {
-                instr.visit_mut(self);
+                // ...
             }
         });
 
@@ -458,12 +483,14 @@ fn create_visit(variants: &[WalrusVariant]) -> impl quote::ToTokens {
         method_name.push_str(&name.to_string().to_snake_case());
         let method_name = syn::Ident::new(&method_name, Span::call_site());
         visit_impl.push(quote! {
+            #( #cfgs )*
             Instr::#name(e) => {
                 visitor.#method_name(e);
                 e.visit(visitor);
             }
         });
         visit_mut_impl.push(quote! {
+            #( #cfgs )*
             Instr::#name(e) => {
                 visitor.#method_name_mut(e);
                 e.visit_mut(visitor);
@@ -555,6 +582,27 @@ fn create_visit(variants: &[WalrusVariant]) -> impl quote::ToTokens {
                 // ...
             }
 
+            /// Visit `ElementId`.
+            #[inline]
+            fn visit_element_id(&mut self, elem: &crate::ElementId) {
+                // ...
+            }
+
+            /// Visit `TagId`.
+            #[cfg(feature = "unstable")]
+            #[inline]
+            fn visit_tag_id(&mut self, tag: &crate::TagId) {
+                // ...
+            }
+
+            /// Visit `ResumeHandler`, and by default its tag. Its block should
+            /// have already been visited.
+            #[cfg(feature = "unstable")]
+            #[inline]
+            fn visit_resume_handler(&mut self, handler: &crate::ir::ResumeHandler) {
+                self.visit_tag_id(&handler.tag);
+            }
+
             /// Visit `TypeId`
             #[inline]
             fn visit_type_id(&mut self, ty: &crate::TypeId) {
@@ -639,6 +687,27 @@ fn create_visit(variants: &[WalrusVariant]) -> impl quote::ToTokens {
                 // ...
             }
 
+            /// Visit `ElementId`.
+            #[inline]
+            fn visit_element_id_mut(&mut self, elem: &mut crate::ElementId) {
+                // ...
+            }
+
+            /// Visit `TagId`.
+            #[cfg(feature = "unstable")]
+            #[inline]
+            fn visit_tag_id_mut(&mut self, tag: &mut crate::TagId) {
+                // ...
+            }
+
+            /// Visit `ResumeHandler`, and by default its tag. Its block should
+            /// have already been visited.
+            #[cfg(feature = "unstable")]
+            #[inline]
+            fn visit_resume_handler_mut(&mut self, handler: &mut crate::ir::ResumeHandler) {
+                self.visit_tag_id_mut(&mut handler.tag);
+            }
+
             /// Visit `TypeId`
             #[inline]
             fn visit_type_id_mut(&mut self, ty: &mut crate::TypeId) {
@@ -721,14 +790,17 @@ fn create_builder(variants: &[WalrusVariant]) -> impl quote::ToTokens {
 
         let arg_names = &arg_names;
         let args = &args;
+        let cfgs = cfg_attrs(variant);
 
         builder_methods.push(quote! {
+            #( #cfgs )*
             #[inline]
             #[doc=#doc]
             pub fn #method_name(&mut self, #(#args),*) -> &mut Self {
                 self.instr(#name { #(#arg_names),* })
             }
 
+            #( #cfgs )*
             #[inline]
             #[doc=#at_doc]
             pub fn #method_name_at(&mut self, position: usize, #(#args),*) -> &mut Self {
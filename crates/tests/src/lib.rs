@@ -65,6 +65,7 @@
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
+use walrus::{Module, ModuleConfig};
 
 pub enum FileCheck {
     Exhaustive(Vec<String>, PathBuf),
@@ -219,3 +220,23 @@ fn update_output(path: &Path, output: &str) {
     );
     fs::write(path, new).unwrap();
 }
+
+/// The configuration that tests parse modules with, which leaves out the
+/// `producers` section so that emitted modules can be compared exactly.
+pub fn config() -> ModuleConfig {
+    let mut config = ModuleConfig::new();
+    config.generate_producers_section(false);
+    config
+}
+
+/// Parse the module in the text format `wat` with `config()`.
+pub fn parse(wat: impl AsRef<str>) -> anyhow::Result<Module> {
+    config().parse(&wat::parse_str(wat)?)
+}
+
+/// Emit `module`, checking that the result is valid by parsing it again.
+pub fn emit(module: &mut Module) -> anyhow::Result<Vec<u8>> {
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)?;
+    Ok(wasm)
+}
@@ -0,0 +1,68 @@
+//! Tests for mapping the offsets of emitted instructions back to the IR.
+
+use walrus::ir::{BinaryOp, Instr};
+use walrus::{FunctionBuilder, Module, ValType};
+
+#[test]
+fn maps_emitted_instructions() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (func $f (param i32) (result i32)
+            (block (result i32)
+              (i32.add (local.get 0) (i32.const 300))))
+          (func $g (drop (call $f (i32.const 1)))))
+        "#,
+    )?;
+    let mut module = Module::from_buffer(&wasm)?;
+    // An instruction that wasn't parsed has no original offset.
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[ValType::I32]);
+    builder.func_body().i32_const(7);
+    let h = builder.finish(vec![], &mut module.funcs);
+
+    let (emitted, map) = module.emit_wasm_with_address_map();
+    assert_eq!(map.functions().len(), 3);
+    assert_eq!(map.entries().len(), 4 + 3 + 1);
+
+    let mut last = 0;
+    for entry in map.entries() {
+        assert!(entry.offset > last);
+        last = entry.offset;
+        let instr = entry.instr(&module).unwrap();
+        let opcode = emitted[entry.offset];
+        match instr {
+            Instr::Block(_) => assert_eq!(opcode, 0x02),
+            Instr::LocalGet(_) => assert_eq!(opcode, 0x20),
+            Instr::Const(_) => assert_eq!(opcode, 0x41),
+            Instr::Binop(b) if b.op == BinaryOp::I32Add => assert_eq!(opcode, 0x6a),
+            Instr::Call(_) => assert_eq!(opcode, 0x10),
+            Instr::Drop(_) => assert_eq!(opcode, 0x1a),
+            other => panic!("unexpected instruction {:?}", other),
+        }
+        match entry.original {
+            Some(loc) => {
+                assert_ne!(entry.func, h);
+                assert_eq!(wasm[loc.data() as usize], opcode);
+            }
+            None => assert_eq!(entry.func, h),
+        }
+    }
+
+    // A `i32.const 300` takes three bytes, all of which map to it.
+    let add = map
+        .entries()
+        .iter()
+        .position(|e| matches!(e.instr(&module), Some(Instr::Binop(_))))
+        .unwrap();
+    let constant = &map.entries()[add - 1];
+    for offset in constant.offset..constant.offset + 3 {
+        assert_eq!(map.lookup(offset).unwrap().offset, constant.offset);
+    }
+    // The locals at the start of a body, and the sections after the code,
+    // aren't instructions.
+    let (_, range) = &map.functions()[0];
+    assert!(map.lookup(range.start).is_none());
+    assert!(map.lookup(emitted.len() - 1).is_none());
+    assert!(map.lookup(range.end - 1).is_some());
+    Ok(())
+}
@@ -0,0 +1,147 @@
+//! Tests for tracking heap allocations.
+
+use walrus::passes::alloc_stats::{self, Allocator, Config};
+use walrus::Module;
+use walrus_tests::parse;
+
+const RUST: &str = r#"
+    (module
+      (memory (export "memory") 1)
+      (global $next (mut i32) (i32.const 4096))
+      (func $__rust_alloc (export "__rust_alloc") (param i32 i32) (result i32)
+        (global.get $next)
+        (global.set $next (i32.add (global.get $next) (local.get 0))))
+      (func $__rust_dealloc (param i32 i32 i32))
+      (func $__rust_alloc_zeroed (param i32 i32) (result i32)
+        (call $__rust_alloc (local.get 0) (local.get 1)))
+      (func (export "run")
+        (call $__rust_dealloc (call $__rust_alloc (i32.const 16) (i32.const 8))
+          (i32.const 16) (i32.const 8))
+        (drop (call $__rust_alloc_zeroed (i32.const 32) (i32.const 8)))))
+"#;
+
+const C: &str = r#"
+    (module
+      (import "env" "malloc" (func $malloc (param i32) (result i32)))
+      (import "env" "free" (func $free (param i32)))
+      (import "env" "malloc_usable_size" (func $usable_size (param i32) (result i32)))
+      (memory (export "memory") 1)
+      (func (export "run")
+        (call $free (call $malloc (i32.const 24)))))
+"#;
+
+fn instrument(wat: &str, find: fn(&Module) -> Option<Allocator>) -> anyhow::Result<String> {
+    let mut module = parse(wat)?;
+    let config = Config {
+        memory: module.memories.iter().next().unwrap().id(),
+        base: 1024,
+        allocator: find(&module).unwrap(),
+    };
+    alloc_stats::run(&mut module, &config)?;
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)?;
+    wasmprinter::print_bytes(&wasm)
+}
+
+#[test]
+fn tracks_rust_allocations() -> anyhow::Result<()> {
+    let wat = instrument(RUST, Allocator::find_rust)?;
+    // Uses and exports of the allocator go through the wrappers, but the
+    // allocator's own calls don't.
+    assert!(wat.contains("(export \"__rust_alloc\" (func $alloc_stats:__rust_alloc))"));
+    assert_eq!(wat.matches("call $alloc_stats:__rust_alloc\n").count(), 1);
+    assert_eq!(
+        wat.matches("call $alloc_stats:__rust_alloc_zeroed\n")
+            .count(),
+        1
+    );
+    assert_eq!(wat.matches("call $alloc_stats:__rust_dealloc\n").count(), 1);
+    assert!(wat.contains(
+        "(func $__rust_alloc_zeroed (type 1) (param i32 i32) (result i32)
+    local.get 0
+    local.get 1
+    call $__rust_alloc)"
+    ));
+    assert!(wat.contains(
+        "call $__rust_dealloc
+    i32.const 0
+    i32.const 0
+    i64.load offset=1032
+    i64.const 1
+    i64.add
+    i64.store offset=1032
+    i32.const 0
+    i32.const 0
+    i64.load offset=1048
+    i64.const 0
+    local.get 1
+    i64.extend_i32_u
+    i64.sub
+    i64.add
+    i64.store offset=1048"
+    ));
+    // Allocations update the peak.
+    assert!(wat.contains(
+        "i32.const 0
+      i32.const 0
+      i64.load offset=1048
+      i32.const 0
+      i64.load offset=1056
+      i32.const 0
+      i64.load offset=1048
+      i32.const 0
+      i64.load offset=1056
+      i64.gt_s
+      select
+      i64.store offset=1056"
+    ));
+    Ok(())
+}
+
+#[test]
+fn tracks_c_allocations() -> anyhow::Result<()> {
+    let wat = instrument(C, Allocator::find_c)?;
+    assert!(wat.contains("call $alloc_stats:malloc\n    call $alloc_stats:free"));
+    // Live bytes are tracked by the usable sizes of blocks.
+    assert_eq!(wat.matches("call $usable_size").count(), 2);
+    assert!(wat.contains("i64.store offset=1056"));
+    Ok(())
+}
+
+#[test]
+fn c_allocations_without_usable_sizes() -> anyhow::Result<()> {
+    let wat = instrument(C, |module| match Allocator::find_c(module)? {
+        Allocator::C { malloc, free, .. } => Some(Allocator::C {
+            malloc,
+            free,
+            realloc: None,
+            calloc: None,
+            usable_size: None,
+        }),
+        _ => unreachable!(),
+    })?;
+    assert!(!wat.contains("call $usable_size"));
+    assert!(!wat.contains("offset=1048"));
+    assert!(wat.contains("i64.store offset=1064"));
+    Ok(())
+}
+
+#[test]
+fn rejects_invalid_configs() -> anyhow::Result<()> {
+    let mut module = parse(C)?;
+    let memory = module.memories.iter().next().unwrap().id();
+    assert!(Allocator::find_rust(&module).is_none());
+    let allocator = Allocator::find_c(&module).unwrap();
+    let mut config = Config {
+        memory,
+        base: 1020,
+        allocator,
+    };
+    assert!(alloc_stats::run(&mut module, &config).is_err());
+    config.base = 1024;
+    if let Allocator::C { malloc, free, .. } = &mut config.allocator {
+        std::mem::swap(malloc, free);
+    }
+    assert!(alloc_stats::run(&mut module, &config).is_err());
+    Ok(())
+}
@@ -0,0 +1,93 @@
+//! Tests for the registry annotation custom sections, such as `authors` and
+//! `licenses`.
+
+use walrus::{CustomSectionPolicy, Module, RawCustomSection};
+use walrus_tests::config;
+
+#[test]
+fn round_trip_annotations() -> anyhow::Result<()> {
+    let mut module = Module::with_config(config());
+    assert!(module.annotations.is_empty());
+    assert_eq!(module.emit_wasm().len(), 8);
+
+    module.annotations.authors = Some("Jane Doe <jane@example.com>".to_string());
+    module.annotations.licenses = Some("Apache-2.0 OR MIT".to_string());
+    module.annotations.description = Some("Does things ✨".to_string());
+    module.annotations.version = Some("1.0.0".to_string());
+
+    let wasm = module.emit_wasm();
+    let mut module = config().parse(&wasm)?;
+    assert_eq!(
+        module.annotations.authors.as_deref(),
+        Some("Jane Doe <jane@example.com>")
+    );
+    assert_eq!(
+        module.annotations.licenses.as_deref(),
+        Some("Apache-2.0 OR MIT")
+    );
+    assert_eq!(
+        module.annotations.description.as_deref(),
+        Some("Does things ✨")
+    );
+    assert_eq!(module.annotations.homepage, None);
+    // The sections aren't also kept around as raw custom sections.
+    assert_eq!(module.customs.iter().count(), 0);
+
+    let sections = module.annotations.iter().collect::<Vec<_>>();
+    assert_eq!(
+        sections,
+        [
+            ("authors", "Jane Doe <jane@example.com>"),
+            ("description", "Does things ✨"),
+            ("licenses", "Apache-2.0 OR MIT"),
+            ("version", "1.0.0"),
+        ]
+    );
+    let keys = module
+        .annotations
+        .oci_annotations()
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        keys,
+        [
+            "org.opencontainers.image.authors",
+            "org.opencontainers.image.description",
+            "org.opencontainers.image.licenses",
+            "org.opencontainers.image.version",
+        ]
+    );
+
+    module.annotations.clear();
+    assert_eq!(module.emit_wasm().len(), 8);
+    Ok(())
+}
+
+#[test]
+fn malformed_annotations() -> anyhow::Result<()> {
+    let mut module = Module::with_config(config());
+    module.customs.add(RawCustomSection {
+        name: "licenses".to_string(),
+        data: vec![0xff],
+    });
+    module.customs.add(RawCustomSection {
+        name: "registry-metadata".to_string(),
+        data: b"{}".to_vec(),
+    });
+    let wasm = module.emit_wasm();
+
+    let module = config().parse(&wasm)?;
+    assert!(module.annotations.is_empty());
+    let names = module
+        .customs
+        .iter()
+        .map(|(_, s)| s.name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["registry-metadata"]);
+
+    let mut config = config();
+    config.custom_section_policy(CustomSectionPolicy::Error);
+    let err = config.parse(&wasm).unwrap_err();
+    assert!(format!("{:#}", err).contains("failed to parse `licenses` custom section"));
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! Tests for keeping the function bodies that fail to parse.
+
+use walrus::ModuleConfig;
+
+const WAT: &str = r#"
+    (module
+      (func $bad (export "bad") (result i32)
+        i64.const 1)
+      (func $good (export "good") (result i32)
+        call $bad
+        i32.const 1
+        i32.add))
+"#;
+
+fn config() -> ModuleConfig {
+    let mut config = walrus_tests::config();
+    config.best_effort_parse(true);
+    config
+}
+
+#[test]
+fn keeps_invalid_bodies() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(WAT)?;
+    assert!(ModuleConfig::new().parse(&wasm).is_err());
+
+    let mut module = config().parse(&wasm)?;
+    let bad = module.funcs.by_name("bad").unwrap();
+    let good = module.funcs.by_name("good").unwrap();
+    assert!(module.funcs.invalid_body(good).is_none());
+    let invalid = module.funcs.invalid_bodies().collect::<Vec<_>>();
+    assert_eq!(invalid.len(), 1);
+    let (id, body) = invalid[0];
+    assert_eq!(id, bad);
+    // No locals, `i64.const 1` and `end`.
+    assert_eq!(body.bytes, [0x00, 0x42, 0x01, 0x0b]);
+    assert_eq!(&wasm[body.offset..][..body.bytes.len()], &body.bytes[..]);
+    assert!(!body.error.is_empty());
+
+    // The IR just traps, but the original body is what gets emitted.
+    let local = module.funcs.get(bad).kind.unwrap_local();
+    assert!(local.block(local.entry_block()).instrs[0]
+        .0
+        .is_unreachable());
+    let bytes = body.bytes.clone();
+    let wasm = module.emit_wasm();
+    assert!(ModuleConfig::new().parse(&wasm).is_err());
+    let mut module = config().parse(&wasm)?;
+    let bad = module.funcs.by_name("bad").unwrap();
+    assert_eq!(module.funcs.invalid_body(bad).unwrap().bytes, bytes);
+    assert_eq!(module.funcs.invalid_bodies().count(), 1);
+    let wat = wasmprinter::print_bytes(module.emit_wasm())?;
+    assert!(wat.contains("(func $bad (type 0) (result i32)\n    i64.const 1)"));
+    assert!(wat.contains("call $bad\n    i32.const 1\n    i32.add)"));
+    Ok(())
+}
+
+#[test]
+fn repairs_invalid_bodies() -> anyhow::Result<()> {
+    let mut module = config().parse(&wat::parse_str(WAT)?)?;
+    let bad = module.funcs.by_name("bad").unwrap();
+
+    // `i64.const 1` still doesn't return an `i32`.
+    let body = module.funcs.invalid_body(bad).unwrap().bytes.clone();
+    assert!(module.replace_func_body_from_bytes(bad, &body).is_err());
+    assert!(module.funcs.invalid_body(bad).is_some());
+
+    module.replace_func_body_from_bytes(bad, &[0x00, 0x41, 0x07, 0x0b])?;
+    assert!(module.funcs.invalid_body(bad).is_none());
+    assert_eq!(module.funcs.invalid_bodies().count(), 0);
+
+    let wasm = walrus::round_trip::check(&mut module)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    assert!(wat.contains("(func $bad (type 0) (result i32)\n    i32.const 7)"));
+    Ok(())
+}
@@ -0,0 +1,47 @@
+//! Tests for the `build_id` custom section.
+
+use walrus::{Module, ModuleConfig};
+use walrus_tests::config;
+
+#[test]
+fn round_trip_build_id() -> anyhow::Result<()> {
+    let mut module = Module::with_config(config());
+    assert_eq!(module.build_id(), None);
+    module.set_build_id(vec![1, 2, 3, 4]);
+
+    let wasm = module.emit_wasm();
+    let mut module = config().parse(&wasm)?;
+    assert_eq!(module.build_id(), Some(&[1, 2, 3, 4][..]));
+    assert_eq!(module.customs.iter().count(), 0);
+
+    module.clear_build_id();
+    let wasm = module.emit_wasm();
+    let module = config().parse(&wasm)?;
+    assert_eq!(module.build_id(), None);
+    Ok(())
+}
+
+#[test]
+fn generated_build_id() -> anyhow::Result<()> {
+    let mut config = config();
+    config.generate_build_id(true);
+
+    let mut module = Module::with_config(config.clone());
+    module.set_build_id(vec![1, 2, 3, 4]);
+    let wasm1 = module.emit_wasm();
+    let id = module.build_id().unwrap().to_vec();
+    assert_eq!(id.len(), 16);
+    assert_ne!(id, [0; 16]);
+
+    // Emitting again yields the same binary, and the id is what got emitted.
+    let wasm2 = module.emit_wasm();
+    assert_eq!(wasm1, wasm2);
+    let parsed = ModuleConfig::new().parse(&wasm1)?;
+    assert_eq!(parsed.build_id(), Some(&id[..]));
+
+    // A different module gets a different id.
+    module.name = Some("different".to_string());
+    module.emit_wasm();
+    assert_ne!(module.build_id(), Some(&id[..]));
+    Ok(())
+}
@@ -0,0 +1,214 @@
+//! Tests for bulk memory instructions that reference element segments.
+//!
+//! These can't be round-trip tests (yet) because our version of `wasmprinter`
+//! doesn't understand the final encoding of passive element segments.
+
+use walrus::ir::Instr;
+use walrus::{Element, ElementKind, FunctionBuilder, FunctionId, Module};
+
+fn instrs(module: &Module, func: FunctionId) -> Vec<Instr> {
+    let func = module.funcs.get(func).kind.unwrap_local();
+    func.block(func.entry_block())
+        .instrs
+        .iter()
+        .map(|(instr, _)| instr.clone())
+        .collect()
+}
+
+#[test]
+fn table_init_elem_drop_and_table_copy() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+            (module
+              (table 2 funcref)
+              (func $f)
+              (func $g)
+              (func $h)
+              (func (export "a")
+                (table.init 1 (i32.const 1) (i32.const 2) (i32.const 3))
+                (elem.drop 1)
+                (table.copy (i32.const 1) (i32.const 2) (i32.const 3)))
+              (elem (i32.const 0) $f)
+              (elem funcref (ref.func $g) (ref.null))
+              (elem funcref (ref.func $h)))
+        "#,
+    )?;
+    let mut module = Module::from_buffer(&wasm)?;
+    assert_eq!(module.elements.iter().count(), 2);
+    walrus::passes::gc::run(&mut module);
+
+    // The unused passive segment and the function only it references are
+    // gone, but the segment used by `table.init` and `elem.drop` is kept.
+    assert_eq!(module.elements.iter().count(), 1);
+    assert_eq!(module.funcs.iter().count(), 3);
+
+    let wasm = module.emit_wasm();
+    let module = Module::from_buffer(&wasm)?;
+    let elem = module.elements.iter().next().unwrap();
+    assert_eq!(elem.kind, ElementKind::Passive);
+    assert_eq!(elem.members.len(), 2);
+    assert!(elem.members[0].is_some());
+    assert!(elem.members[1].is_none());
+
+    let a = module.exports.iter().next().unwrap();
+    let a = match a.item {
+        walrus::ExportItem::Function(f) => f,
+        _ => unreachable!(),
+    };
+    let instrs = instrs(&module, a);
+    assert!(instrs.iter().any(|i| match i {
+        Instr::TableInit(t) => t.elem == elem.id(),
+        _ => false,
+    }));
+    assert!(instrs.iter().any(|i| match i {
+        Instr::ElemDrop(d) => d.elem == elem.id(),
+        _ => false,
+    }));
+    assert!(instrs.iter().any(|i| i.is_table_copy()));
+    Ok(())
+}
+
+#[test]
+fn element_segment_encodings() -> anyhow::Result<()> {
+    // `wat` always encodes segments without nulls as function indices.
+    #[rustfmt::skip]
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // A type section with `(func)`.
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00,
+        // A function section with one function.
+        0x03, 0x02, 0x01, 0x00,
+        // An element section with a passive `(elem funcref (ref.func 0))`.
+        0x09, 0x07, 0x01, 0x05, 0x70, 0x01, 0xd2, 0x00, 0x0b,
+        // A code section with an empty function.
+        0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b,
+    ];
+    let mut module = Module::from_buffer(&wasm)?;
+    let f = module.funcs.iter().next().unwrap().id();
+    assert!(module.elements.iter().next().unwrap().uses_exprs);
+
+    let indices = module.elements.add(ElementKind::Passive, vec![Some(f)]);
+    let nulls = module
+        .elements
+        .add(ElementKind::Declared, vec![Some(f), None]);
+    assert!(!module.elements.get(indices).uses_exprs);
+    assert!(module.elements.get(nulls).uses_exprs);
+
+    let module = Module::from_buffer(&module.emit_wasm())?;
+    let elements = module.elements.iter().collect::<Vec<_>>();
+    assert_eq!(elements.len(), 3);
+    assert!(elements[0].uses_exprs);
+    assert!(!elements[1].uses_exprs);
+    // Ids aren't comparable across modules, so compare the members' shapes.
+    let shape = |e: &Element| e.members.iter().map(|m| m.is_some()).collect::<Vec<_>>();
+    assert_eq!(shape(elements[1]), [true]);
+    assert!(elements[2].uses_exprs);
+    assert_eq!(elements[2].kind, ElementKind::Declared);
+    assert_eq!(shape(elements[2]), [true, false]);
+    Ok(())
+}
+
+#[test]
+fn build_passive_segments() -> anyhow::Result<()> {
+    let mut module = Module::default();
+    let memory = module.memories.add_local(false, 1, None);
+    let kind = walrus::TableKind::Function(Default::default());
+    let table = module.tables.add_local(1, None, kind);
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body();
+    let callee = builder.finish(vec![], &mut module.funcs);
+
+    let data = module.data.add_passive(b"hello".to_vec());
+    let elem = module.elements.add_passive(vec![Some(callee)]);
+    assert!(module.data.get(data).is_passive());
+    assert_eq!(module.elements.get(elem).kind, ElementKind::Passive);
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder
+        .func_body()
+        .i32_const(1024)
+        .memory_init_and_drop(memory, data, 5)
+        .i32_const(0)
+        .table_init_and_drop(table, elem, 1);
+    let init = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("init", init);
+
+    // The segments are kept, since `init` uses them.
+    walrus::passes::gc::run(&mut module);
+    let module = Module::from_buffer(&module.emit_wasm())?;
+    let data = module.data.iter().next().unwrap();
+    assert!(data.is_passive());
+    assert_eq!(data.value, b"hello");
+    let elem = module.elements.iter().next().unwrap();
+    assert_eq!(elem.kind, ElementKind::Passive);
+
+    let init = match module.exports.iter().next().unwrap().item {
+        walrus::ExportItem::Function(f) => f,
+        _ => unreachable!(),
+    };
+    let instrs = instrs(&module, init);
+    assert!(instrs.iter().any(|i| i.is_memory_init()));
+    assert!(instrs.iter().any(|i| i.is_data_drop()));
+    assert!(instrs.iter().any(|i| i.is_table_init()));
+    assert!(instrs.iter().any(|i| i.is_elem_drop()));
+    Ok(())
+}
+
+#[test]
+fn active_segments_are_already_dropped() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+            (module
+              (table 2 funcref)
+              (func $f)
+              (func (export "a")
+                (table.init 1 (i32.const 0) (i32.const 0) (i32.const 0))
+                (elem.drop 1))
+              (elem (i32.const 0) $f)
+              (elem (i32.const 1) $f))
+        "#,
+    )?;
+    let mut module = Module::from_buffer(&wasm)?;
+
+    // Only the segment that is referred to gets an empty stand-in.
+    let segments = module.elements.iter().collect::<Vec<_>>();
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].kind, ElementKind::Passive);
+    assert!(segments[0].members.is_empty());
+    let stand_in = segments[0].id();
+    let a = module.exports.iter().next().unwrap().id();
+    let a = match module.exports.get(a).item {
+        walrus::ExportItem::Function(f) => f,
+        _ => unreachable!(),
+    };
+    assert!(instrs(&module, a).iter().any(|instr| match instr {
+        Instr::ElemDrop(e) => e.elem == stand_in,
+        _ => false,
+    }));
+
+    // The active segments still initialize the table.
+    let module = Module::from_buffer(&module.emit_wasm())?;
+    assert_eq!(module.elements.iter().count(), 1);
+    let table = module.tables.iter().next().unwrap();
+    match &table.kind {
+        walrus::TableKind::Function(t) => assert_eq!(t.elements.len(), 2),
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+#[test]
+fn unused_active_segments_get_no_stand_in() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+            (module
+              (table 1 funcref)
+              (func $f)
+              (elem (i32.const 0) $f))
+        "#,
+    )?;
+    let module = Module::from_buffer(&wasm)?;
+    assert_eq!(module.elements.iter().count(), 0);
+    Ok(())
+}
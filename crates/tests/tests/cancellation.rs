@@ -0,0 +1,52 @@
+//! Tests for cancelling work on modules.
+
+use walrus::{CancellationToken, ErrorKind, ModuleConfig};
+
+const WAT: &str = r#"
+    (module
+      (func $used (export "used"))
+      (func $unused))
+"#;
+
+fn is_cancelled(err: &anyhow::Error) -> bool {
+    ErrorKind::of(err) == Some(ErrorKind::Cancelled)
+}
+
+#[test]
+fn cancels_parsing() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(WAT)?;
+    let token = CancellationToken::new();
+    let mut config = ModuleConfig::new();
+    config.cancellation_token(token.clone());
+    config.parse(&wasm)?;
+
+    token.cancel();
+    assert!(token.is_cancelled());
+    assert!(is_cancelled(&config.parse(&wasm).unwrap_err()));
+    Ok(())
+}
+
+#[test]
+fn cancels_gc_and_emitting() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(WAT)?;
+    let token = CancellationToken::new();
+    let mut module = ModuleConfig::new()
+        .cancellation_token(token.clone())
+        .parse(&wasm)?;
+    let mut copy = module.clone();
+    walrus::passes::gc::try_run(&mut copy)?;
+    assert_eq!(copy.funcs.iter().count(), 1);
+    copy.try_emit_wasm()?;
+
+    token.cancel();
+    let err = walrus::passes::gc::try_run(&mut module).unwrap_err();
+    assert!(is_cancelled(&err));
+    assert_eq!(module.funcs.iter().count(), 2);
+    assert!(is_cancelled(&module.try_emit_wasm().unwrap_err()));
+
+    // The infallible versions don't check the token.
+    walrus::passes::gc::run(&mut module);
+    assert_eq!(module.funcs.iter().count(), 1);
+    assert_eq!(module.emit_wasm(), copy.emit_wasm());
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//! Tests for canonicalizing the NaNs that float instructions produce.
+
+use walrus::passes::canonicalize_nans::{self, Options};
+
+const WAT: &str = r#"
+    (module
+      (func (export "scalar") (param f32 f64) (result f32)
+        (f32.neg (f32.add (local.get 0) (local.get 0)))
+        (block (param f32) (result f32)
+          (f32.demote_f64 (f64.sqrt (local.get 1)))
+          (f32.copysign))
+        (f32.abs))
+      (func (export "simd") (param v128) (result v128)
+        (f64x2.mul (f32x4.div (local.get 0) (local.get 0)) (local.get 0)))
+      (func (export "exact") (param i32) (result f32)
+        (f32.convert_i32_s (local.get 0))))
+"#;
+
+fn canonicalize(options: &Options) -> anyhow::Result<(usize, String)> {
+    let mut module = walrus_tests::parse(WAT)?;
+    let count = canonicalize_nans::run(&mut module, options);
+
+    let wasm = walrus_tests::emit(&mut module)?;
+    Ok((count, wasmprinter::print_bytes(&wasm)?))
+}
+
+#[test]
+fn canonicalizes_nans() -> anyhow::Result<()> {
+    let (count, wat) = canonicalize(&Options::default())?;
+    assert_eq!(count, 5);
+    assert!(wat.contains("f32.add\n    call $canonicalize_nan_f32\n    f32.neg\n"));
+    assert!(wat.contains(
+        "f64.sqrt\n      call $canonicalize_nan_f64\n      \
+         f32.demote_f64\n      call $canonicalize_nan_f32\n      f32.copysign\n"
+    ));
+    assert!(wat.contains("f32x4.div\n    call $canonicalize_nan_f32x4\n"));
+    assert!(wat.contains("f64x2.mul\n    call $canonicalize_nan_f64x2)"));
+    assert!(wat.contains("f32.convert_i32_s)"));
+
+    // NaNs are replaced by the canonical NaN, and other values are kept.
+    assert!(wat.contains(
+        "(param f32) (result f32)\n    f32.const nan (;=NaN;)\n    local.get 0\n    \
+         local.get 0\n    local.get 0\n    f32.ne\n    select)"
+    ));
+    assert!(wat.contains(
+        "(param v128) (result v128)\n    \
+         v128.const i32x4 0x7fc00000 0x7fc00000 0x7fc00000 0x7fc00000\n    local.get 0\n    \
+         local.get 0\n    local.get 0\n    f32x4.ne\n    v128.bitselect)"
+    ));
+    assert!(wat.contains("v128.const i32x4 0x00000000 0x7ff80000 0x00000000 0x7ff80000\n"));
+    Ok(())
+}
+
+#[test]
+fn leaves_simd_alone() -> anyhow::Result<()> {
+    let (count, wat) = canonicalize(&Options { simd: false })?;
+    assert_eq!(count, 3);
+    assert!(wat.contains("call $canonicalize_nan_f32"));
+    assert!(!wat.contains("canonicalize_nan_f32x4"));
+    assert!(!wat.contains("canonicalize_nan_f64x2"));
+    Ok(())
+}
@@ -0,0 +1,61 @@
+//! Tests for hardening indirect calls.
+
+use walrus::passes::cfi;
+use walrus::Module;
+
+const WAT: &str = r#"
+    (module
+      (import "env" "host" (func $host (param i32) (result i32)))
+      (table 3 funcref)
+      (elem (i32.const 0) $host $double $unused)
+      (func $double (param i32) (result i32)
+        (i32.add (local.get 0) (local.get 0)))
+      (func $unused)
+      (func $direct (param i32) (result i32)
+        (local.get 0))
+      (func $dispatch (export "dispatch") (param i32 i32) (result i32)
+        (call $direct
+          (call_indirect (param i32) (result i32) (local.get 1) (local.get 0))))
+      (export "host" (func $host)))
+"#;
+
+#[test]
+fn hardens_indirect_calls() -> anyhow::Result<()> {
+    let mut module = walrus_tests::parse(WAT)?;
+    cfi::run(&mut module);
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+
+    // Call sites set the expected tag, and check that it was cleared.
+    assert!(wat.contains(
+        "    local.get 0
+    i32.const 1
+    global.set 0
+    call_indirect (type 2)
+    call $cfi_check_cleared"
+    ));
+    // Targets check their tag.
+    assert!(wat.contains(
+        "(func $double (type 2) (param i32) (result i32)
+    i32.const 1
+    call $cfi_check_tag"
+    ));
+    assert!(
+        wat.contains("(func $dispatch (type 3) (param i32 i32) (result i32)\n    i32.const 3\n")
+    );
+    assert!(wat.contains("(func $unused (type 0)\n    i32.const 2\n"));
+    // But functions that are only called directly don't.
+    assert!(wat.contains("(func $direct (type 2) (param i32) (result i32)\n    local.get 0)"));
+    // Imported functions are wrapped.
+    assert!(wat.contains(
+        "(func $cfi_wrapper_host (type 2) (param i32) (result i32)
+    i32.const 1
+    call $cfi_check_tag
+    local.get 0
+    call $host)"
+    ));
+    assert!(wat.contains("(elem (;0;) (i32.const 0) $cfi_wrapper_host $double $unused)"));
+    assert!(wat.contains("(export \"host\" (func $cfi_wrapper_host))"));
+    Ok(())
+}
@@ -0,0 +1,136 @@
+//! Tests for compressing data segments.
+
+use walrus::passes::compress_data::{self, compress, decompress, Options};
+use walrus::{DataKind, Module};
+use walrus_tests::parse;
+
+/// Some data that compresses well, with long runs of both literals and
+/// matches.
+fn data() -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut state = 1u32;
+    for i in 0..4000 {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        if i % 1000 < 300 {
+            data.push((state >> 16) as u8);
+        } else {
+            data.extend_from_slice(b"walrus ");
+        }
+    }
+    data
+}
+
+#[test]
+fn round_trips() {
+    let inputs = vec![
+        Vec::new(),
+        b"abc".to_vec(),
+        b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_vec(),
+        vec![0; 100_000],
+        data(),
+    ];
+    for input in inputs {
+        let compressed = compress(&input);
+        assert_eq!(decompress(&compressed).as_ref(), Some(&input));
+    }
+    assert!(compress(&data()).len() < data().len() / 4);
+    assert_eq!(decompress(&[0x0f, 1, 0]), None);
+}
+
+fn wat(data: &str) -> String {
+    format!(
+        r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 16) "{}")
+          (data (i32.const 32768) "small")
+          (func $start)
+          (start $start))
+        "#,
+        data
+    )
+}
+
+fn escape(data: &[u8]) -> String {
+    data.iter().map(|b| format!("\\{:02x}", b)).collect()
+}
+
+#[test]
+fn compresses_large_segments() -> anyhow::Result<()> {
+    let data = data();
+    let mut module = parse(wat(&escape(&data)))?;
+    let memory = module.memories.iter().next().unwrap().id();
+    assert_eq!(
+        compress_data::run(&mut module, memory, &Options::default())?,
+        1
+    );
+
+    let passive = module.data.iter().find(|d| d.is_passive()).unwrap();
+    let compressed = passive.value.clone();
+    assert_eq!(decompress(&compressed), Some(data.clone()));
+    assert_eq!(module.memories.get(memory).data_segments.len(), 1);
+    let start = module.start.unwrap();
+    assert_eq!(
+        module.funcs.get(start).name.as_deref(),
+        Some("compress_data_init")
+    );
+
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    // The scratch area is right after the last segment, and cleared after
+    // decompressing.
+    let scratch = 32768 + 5;
+    assert!(wat.contains(&format!(
+        "i32.const {}
+    i32.const 0
+    i32.const {}
+    memory.init 0
+    data.drop 0
+    i32.const {}
+    i32.const {}
+    i32.const 16
+    call $compress_data_decompress",
+        scratch,
+        compressed.len(),
+        scratch,
+        compressed.len(),
+    )));
+    assert!(wat.contains("memory.fill\n    call $start)"));
+    Ok(())
+}
+
+#[test]
+fn leaves_other_segments_alone() -> anyhow::Result<()> {
+    // Too small to be compressed by default.
+    let mut module = parse(wat("hello"))?;
+    let memory = module.memories.iter().next().unwrap().id();
+    assert_eq!(
+        compress_data::run(&mut module, memory, &Options::default())?,
+        0
+    );
+    assert!(module.data.iter().all(|d| !d.is_passive()));
+
+    // Overlapping another segment.
+    let overlapping = wat(&escape(&data())).replace("i32.const 32768", "i32.const 2048");
+    let mut module = parse(&overlapping)?;
+    let memory = module.memories.iter().next().unwrap().id();
+    assert_eq!(
+        compress_data::run(&mut module, memory, &Options::default())?,
+        0
+    );
+
+    // Incompressible.
+    let options = Options {
+        min_size: 1,
+        scratch: None,
+    };
+    let mut module = parse(wat("hello"))?;
+    let memory = module.memories.iter().next().unwrap().id();
+    assert_eq!(compress_data::run(&mut module, memory, &options)?, 0);
+    assert!(module
+        .data
+        .iter()
+        .all(|d| matches!(d.kind, DataKind::Active(_))));
+    Ok(())
+}
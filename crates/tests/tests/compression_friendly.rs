@@ -0,0 +1,68 @@
+//! Tests for emitting modules in a layout that compresses well.
+
+use walrus::ir::*;
+use walrus::{Module, ModuleConfig};
+
+const WAT: &str = r#"
+    (module
+      (type $rare (func (param i32)))
+      (type $common (func (param i32) (result i32)))
+      (memory 1)
+      (table 1 funcref)
+      (func $f (export "zebra") (type $rare) (param i32))
+      (func $g (export "apple") (type $common) (param i32) (result i32)
+        (call_indirect (type $common) (local.get 0) (i32.const 0)))
+      (func $h (export "mango") (type $common) (param i32) (result i32)
+        (i32.load (local.get 0))))
+"#;
+
+fn emit(compression_friendly: bool) -> anyhow::Result<String> {
+    let mut config = walrus_tests::config();
+    config.compression_friendly(compression_friendly);
+    let mut module = config.parse(&wat::parse_str(WAT)?)?;
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)?;
+    wasmprinter::print_bytes(&wasm)
+}
+
+#[test]
+fn sorts_types_and_exports() -> anyhow::Result<()> {
+    let wat = emit(false)?;
+    assert!(wat.contains("(type (;0;) (func (param i32)))"));
+    assert!(wat.find("\"zebra\"").unwrap() < wat.find("\"apple\"").unwrap());
+
+    let wat = emit(true)?;
+    assert!(wat.contains("(type (;0;) (func (param i32) (result i32)))"));
+    assert!(wat.contains("(type (;1;) (func (param i32)))"));
+    assert!(wat.contains("call_indirect (type 0)"));
+    let apple = wat.find("(export \"apple\"").unwrap();
+    let mango = wat.find("(export \"mango\"").unwrap();
+    let zebra = wat.find("(export \"zebra\"").unwrap();
+    assert!(apple < mango && mango < zebra);
+    Ok(())
+}
+
+#[test]
+fn shortens_memargs() -> anyhow::Result<()> {
+    let mut config = ModuleConfig::new();
+    config.compression_friendly(true);
+    let mut module = config.parse(&wat::parse_str(WAT)?)?;
+    let (_, func) = module
+        .funcs
+        .iter_local_mut()
+        .find(|(_, f)| f.block(f.entry_block()).instrs.len() == 2)
+        .unwrap();
+    let entry = func.entry_block();
+    match &mut func.block_mut(entry).instrs[1].0 {
+        Instr::Load(load) => {
+            load.arg.encoding = Some(MemArgEncoding {
+                align_width: 3,
+                offset_width: 3,
+            })
+        }
+        _ => unreachable!(),
+    }
+    let wasm = module.emit_wasm();
+    assert!(wasm.windows(3).any(|w| w == [0x28, 0x02, 0x00]));
+    Ok(())
+}
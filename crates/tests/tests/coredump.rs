@@ -0,0 +1,176 @@
+//! Tests for the typed coredump custom sections, and for instrumenting modules
+//! to capture their stack for one.
+
+use walrus::passes::coredump::{self, Config, FUNCS_SECTION};
+use walrus::{
+    CoreInstance, CoreInstances, CoreModules, CoreStack, CoreStackFrame, CoreValue, Coredump,
+    ExportItem, Module, RawCustomSection,
+};
+
+#[test]
+fn round_trip_coredump() -> anyhow::Result<()> {
+    let config = walrus_tests::config();
+    let mut module = Module::with_config(config.clone());
+    module.memories.add_local(false, 1, None);
+
+    let core = Coredump {
+        executable_name: "main.wasm".to_string(),
+    };
+    let stack = CoreStack {
+        thread_name: "main".to_string(),
+        frames: vec![CoreStackFrame {
+            instance: 0,
+            func: 3,
+            code_offset: 42,
+            locals: vec![CoreValue::I32(-1), CoreValue::Missing, CoreValue::F64(1.5)],
+            stack: vec![CoreValue::I64(1 << 40), CoreValue::F32(-0.5)],
+        }],
+    };
+    let modules = CoreModules {
+        modules: vec!["main.wasm".to_string()],
+    };
+    let instances = CoreInstances {
+        instances: vec![CoreInstance {
+            module: 0,
+            memories: vec![0],
+            globals: vec![],
+        }],
+    };
+    module.customs.add(core.clone());
+    module.customs.add(stack.clone());
+    module.customs.add(modules.clone());
+    module.customs.add(instances.clone());
+
+    let wasm = module.emit_wasm();
+    let module = config.parse(&wasm)?;
+    assert_eq!(module.customs.get_typed::<Coredump>(), Some(&core));
+    assert_eq!(module.customs.get_typed::<CoreStack>(), Some(&stack));
+    assert_eq!(module.customs.get_typed::<CoreModules>(), Some(&modules));
+    assert_eq!(
+        module.customs.get_typed::<CoreInstances>(),
+        Some(&instances)
+    );
+    Ok(())
+}
+
+#[test]
+fn malformed_coredump_sections_are_kept_raw() -> anyhow::Result<()> {
+    let config = walrus_tests::config();
+    let mut module = Module::with_config(config.clone());
+    module.customs.add(RawCustomSection {
+        name: "core".to_string(),
+        data: vec![0x01],
+    });
+
+    let wasm = module.emit_wasm();
+    let module = config.parse(&wasm)?;
+    assert!(module.customs.get_typed::<Coredump>().is_none());
+    let raw = module.customs.get_typed::<RawCustomSection>().unwrap();
+    assert_eq!(raw.name, "core");
+    assert_eq!(raw.data, [0x01]);
+    Ok(())
+}
+
+const WAT: &str = r#"
+    (module
+      (import "env" "log" (func $log (param i32)))
+      (memory 1)
+      (global $cursor (mut i32) (i32.const 0))
+      (func $fail (param i32) (result i32)
+        local.get 0
+        call $log
+        unreachable)
+      (func $run (export "run") (param i64) (result i32)
+        (local f32)
+        i32.const 7
+        call $fail))
+"#;
+
+fn config(module: &Module, capacity: u32) -> Config {
+    Config {
+        memory: module.memories.iter().next().unwrap().id(),
+        base: 1024,
+        capacity,
+        cursor: module.globals.iter().next().unwrap().id(),
+    }
+}
+
+/// The index that `wat` gives the function named `name`.
+fn func_index(wat: &str, name: &str) -> u32 {
+    wat.split("(func $")
+        .skip(1)
+        .position(|func| func.starts_with(&format!("{} ", name)))
+        .unwrap() as u32
+}
+
+#[test]
+fn instrument() -> anyhow::Result<()> {
+    let mut module = walrus_tests::parse(WAT)?;
+    let unreachable = walrus::passes::traps::trapping_sites(&module)[0];
+    let config = config(&module, 4096);
+    coredump::run(&mut module, &config)?;
+    walrus::passes::gc::run(&mut module);
+
+    let wasm = walrus_tests::emit(&mut module)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    let mut module = Module::from_buffer(&wasm)?;
+
+    // The frames are stored at the base of the buffer, and both functions
+    // return when unwinding.
+    assert!(wat.contains("i32.store offset=1024"));
+    assert!(wat.contains("i64.store offset=1032"));
+    assert_eq!(wat.matches("return").count(), 2);
+    let offset = unreachable.offset.unwrap();
+    assert!(wat.contains(&format!("i32.const {}\n", offset)));
+
+    // `run` is exported through a wrapper that traps once the stack is
+    // unwound.
+    let run = match module.exports.iter().next().unwrap().item {
+        ExportItem::Function(func) => func,
+        _ => panic!("`run` should be a function"),
+    };
+    assert_eq!(module.funcs.get(run).name.as_deref(), Some("coredump:run"));
+
+    // A buffer as the instrumentation would write it, for `fail` trapping
+    // while called from `run`. Each frame starts with the number of its
+    // function and its code offset.
+    let mut buffer = Vec::new();
+    for word in [10 << 32, 7, 20 << 32 | 1, (-2i64) as u64].iter() {
+        buffer.extend_from_slice(&word.to_le_bytes());
+    }
+    let section = module.customs.remove_raw(FUNCS_SECTION).unwrap();
+    let frames = coredump::frames(&section.data, &buffer)?;
+    assert_eq!(
+        frames,
+        [
+            CoreStackFrame {
+                instance: 0,
+                func: func_index(&wat, "fail"),
+                code_offset: 10,
+                locals: vec![CoreValue::I32(7)],
+                stack: vec![],
+            },
+            CoreStackFrame {
+                instance: 0,
+                func: func_index(&wat, "run"),
+                code_offset: 20,
+                locals: vec![CoreValue::I64(-2)],
+                stack: vec![],
+            },
+        ]
+    );
+
+    assert!(coredump::frames(&section.data, &buffer[..12]).is_err());
+    assert!(coredump::frames(&section.data, &buffer[..8]).is_err());
+    Ok(())
+}
+
+#[test]
+fn frames_must_fit_in_the_buffer() -> anyhow::Result<()> {
+    let mut module = walrus_tests::parse(WAT)?;
+    let small = config(&module, 8);
+    assert!(coredump::run(&mut module, &small).is_err());
+    let large = config(&module, 16);
+    coredump::run(&mut module, &large)?;
+    Ok(())
+}
@@ -0,0 +1,40 @@
+//! Tests for instruction cost models.
+
+use walrus::ir::{DefaultCost, Drop, Instr, InstrCost};
+use walrus::Module;
+
+const WAT: &str = r#"
+    (module
+      (func $f (param i32 i32) (result i32)
+        (block
+          (br_if 0 (local.get 0))
+          (drop (call $f (i32.const 1) (i32.const 2))))
+        (i32.div_u (local.get 0) (local.get 1))))
+"#;
+
+#[test]
+fn default_cost() -> anyhow::Result<()> {
+    let module = Module::from_buffer(&wat::parse_str(WAT)?)?;
+    let f = module.funcs.iter_local().next().unwrap().1;
+    // `block` and `drop` are free, `br_if` costs 2, `call` 5, `i32.div_u`
+    // 20, and the two constants and three `local.get`s 1 each.
+    assert_eq!(f.cost(&DefaultCost), 32);
+
+    let div = f
+        .block(f.entry_block())
+        .instrs
+        .last()
+        .map(|(instr, _)| instr)
+        .unwrap();
+    assert!(DefaultCost.cost(div) > DefaultCost.cost(&Drop {}.into()));
+    Ok(())
+}
+
+#[test]
+fn custom_cost() -> anyhow::Result<()> {
+    let module = Module::from_buffer(&wat::parse_str(WAT)?)?;
+    let f = module.funcs.iter_local().next().unwrap().1;
+    let calls = |instr: &Instr| if instr.is_call() { 1 } else { 0 };
+    assert_eq!(f.cost(&calls), 1);
+    Ok(())
+}
@@ -0,0 +1,108 @@
+//! Tests for the common subexpression elimination pass.
+
+fn cse(body: &str) -> anyhow::Result<String> {
+    let wat = format!(
+        r#"
+        (module
+          (memory 1)
+          (global i32 (i32.const 8))
+          (global (mut i32) (i32.const 8))
+          (func (param i32 i32 i32) (result i32)
+            {}))
+        "#,
+        body
+    );
+    let mut module = walrus_tests::parse(wat)?;
+    walrus::passes::cse::run(&mut module);
+    let wasm = walrus_tests::emit(&mut module)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    let body = &wat[wat.find("(func (;0;)").unwrap()..];
+    let body = &body[body.find('\n').unwrap() + 1..body.find("\n  (memory").unwrap()];
+    Ok(body
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.starts_with("(local"))
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+#[test]
+fn eliminates_repeated_expressions() -> anyhow::Result<()> {
+    let body = cse("local.get 0 local.get 1 i32.add global.get 0 i32.mul
+         local.get 0 local.get 1 i32.add global.get 0 i32.mul
+         i32.add")?;
+    assert_eq!(
+        body,
+        "local.get 0 local.get 1 i32.add local.tee 3 global.get 0 i32.mul local.tee 4 \
+         local.get 4 i32.add)"
+    );
+    Ok(())
+}
+
+#[test]
+fn local_writes_kill_expressions() -> anyhow::Result<()> {
+    let body = cse("local.get 0 local.get 1 i32.sub
+         i32.const 1 local.set 0
+         local.get 0 local.get 1 i32.sub
+         local.get 2 local.get 1 i32.sub
+         i32.add
+         local.get 2 local.get 1 i32.sub
+         i32.add
+         i32.add")?;
+    assert_eq!(
+        body,
+        "local.get 0 local.get 1 i32.sub i32.const 1 local.set 0 \
+         local.get 0 local.get 1 i32.sub \
+         local.get 2 local.get 1 i32.sub local.tee 3 i32.add \
+         local.get 3 i32.add \
+         i32.add)"
+    );
+    Ok(())
+}
+
+#[test]
+fn leaves_impure_and_trapping_expressions() -> anyhow::Result<()> {
+    let src = "local.get 0 local.get 1 i32.div_u
+               local.get 0 local.get 1 i32.div_u
+               i32.add
+               local.get 0 i32.load
+               local.get 0 i32.load
+               i32.add
+               global.get 1 i32.const 1 i32.add
+               global.get 1 i32.const 1 i32.add
+               i32.add
+               i32.add
+               i32.add";
+    let body = cse(src)?;
+    assert!(!body.contains("local.tee"));
+    Ok(())
+}
+
+#[test]
+fn does_not_reuse_across_blocks() -> anyhow::Result<()> {
+    let body = cse("local.get 0 local.get 1 i32.add
+         block
+           i32.const 0 local.set 1
+         end
+         local.get 0 local.get 1 i32.add
+         i32.add")?;
+    assert!(!body.contains("local.tee"));
+    Ok(())
+}
+
+#[test]
+fn does_not_eliminate_across_statements() -> anyhow::Result<()> {
+    // The second `i32.add` adds `local.get 0` to `local.get 1`, but the
+    // instructions in between set a local, so they aren't a pure expression.
+    let src = "local.get 0 local.get 1 i32.add drop
+               local.get 0 i32.const 5 local.set 2 local.get 1 i32.add drop
+               local.get 2";
+    let body = cse(src)?;
+    assert_eq!(
+        body,
+        "local.get 0 local.get 1 i32.add drop \
+         local.get 0 i32.const 5 local.set 2 local.get 1 i32.add drop \
+         local.get 2)"
+    );
+    Ok(())
+}
@@ -0,0 +1,96 @@
+//! Tests for running static constructors before the rest of a module.
+
+use walrus::passes::ctors;
+use walrus::Module;
+use walrus_tests::parse;
+
+const WAT: &str = r#"
+    (module
+      (import "env" "log" (func $log (param i32)))
+      (func $__wasm_call_ctors (export "__wasm_call_ctors")
+        (call $log (i32.const 1)))
+      (func $_initialize (export "_initialize")
+        call $__wasm_call_ctors)
+      (func $add (export "add") (export "plus") (param i32 i32) (result i32)
+        (i32.add (local.get 0) (local.get 1))))
+"#;
+
+/// Print `module`, along with the name of its start function.
+fn print(module: &mut Module) -> anyhow::Result<(String, Option<String>)> {
+    let wasm = module.emit_wasm();
+    let module = Module::from_buffer(&wasm)?;
+    let start = module.start.and_then(|f| module.funcs.get(f).name.clone());
+    Ok((wasmprinter::print_bytes(&wasm)?, start))
+}
+
+const CTORS_ONCE: &str = "(func $__wasm_call_ctors (type 0)
+    global.get 0
+    br_if 0 (;@0;)
+    i32.const 1
+    global.set 0
+    i32.const 1
+    call $log)";
+
+#[test]
+fn finds_ctors() -> anyhow::Result<()> {
+    let module = parse(WAT)?;
+    assert_eq!(
+        ctors::find(&module),
+        module.funcs.by_name("__wasm_call_ctors")
+    );
+    assert!(ctors::find(&parse("(module)")?).is_none());
+    Ok(())
+}
+
+#[test]
+fn promotes_to_start() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    let start = ctors::promote_to_start(&mut module)?;
+    assert_eq!(Some(start), ctors::find(&module));
+    let (wat, start) = print(&mut module)?;
+    assert!(wat.contains(CTORS_ONCE));
+    assert_eq!(start.as_deref(), Some("__wasm_call_ctors"));
+    Ok(())
+}
+
+#[test]
+fn keeps_the_existing_start() -> anyhow::Result<()> {
+    let mut module = parse(WAT.replace("(func $add", "(start $_initialize) (func $add"))?;
+    ctors::promote_to_start(&mut module)?;
+    let (wat, start) = print(&mut module)?;
+    assert!(wat.contains(
+        "(func $__wasm_call_ctors_and_start (type 0)
+    call $__wasm_call_ctors
+    call $_initialize)"
+    ));
+    assert_eq!(start.as_deref(), Some("__wasm_call_ctors_and_start"));
+    Ok(())
+}
+
+#[test]
+fn wraps_exports() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    // `_initialize` and `add` are wrapped.
+    assert_eq!(ctors::wrap_exports(&mut module)?, 2);
+    let (wat, _) = print(&mut module)?;
+    assert!(wat.contains(CTORS_ONCE));
+    assert!(wat.contains(
+        "(func $add_with_ctors (type 2) (param i32 i32) (result i32)
+    call $__wasm_call_ctors
+    local.get 0
+    local.get 1
+    call $add)"
+    ));
+    assert!(wat.contains("(export \"__wasm_call_ctors\" (func $__wasm_call_ctors))"));
+    assert!(wat.contains("(export \"add\" (func $add_with_ctors))"));
+    assert!(wat.contains("(export \"plus\" (func $add_with_ctors))"));
+    Ok(())
+}
+
+#[test]
+fn needs_ctors() -> anyhow::Result<()> {
+    let mut module = parse("(module)")?;
+    assert!(ctors::promote_to_start(&mut module).is_err());
+    assert!(ctors::wrap_exports(&mut module).is_err());
+    Ok(())
+}
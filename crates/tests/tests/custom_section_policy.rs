@@ -0,0 +1,74 @@
+//! Tests for the policies for odd custom sections.
+
+use walrus::{CustomSectionPolicy, Module};
+
+/// A module with a `name` section naming its function `a`, then with an
+/// unknown subsection, then naming it `b` again in a duplicated subsection.
+fn odd_names() -> anyhow::Result<Vec<u8>> {
+    let mut wasm = wat::parse_str("(module (func))")?;
+    wasm.extend_from_slice(&[0x00, 21, 4, b'n', b'a', b'm', b'e']);
+    wasm.extend_from_slice(&[1, 4, 1, 0, 1, b'a']);
+    wasm.extend_from_slice(&[9, 2, 0xaa, 0xbb]);
+    wasm.extend_from_slice(&[1, 4, 1, 0, 1, b'b']);
+    Ok(wasm)
+}
+
+/// A module with a `producers` section cut short.
+fn odd_producers() -> anyhow::Result<Vec<u8>> {
+    let mut wasm = wat::parse_str("(module)")?;
+    wasm.extend_from_slice(&[0x00, 11, 9]);
+    wasm.extend_from_slice(b"producers");
+    wasm.push(5);
+    Ok(wasm)
+}
+
+fn parse(wasm: &[u8], policy: CustomSectionPolicy) -> anyhow::Result<Module> {
+    let mut config = walrus_tests::config();
+    config.custom_section_policy(policy);
+    config.parse(wasm)
+}
+
+fn func_name(module: &Module) -> Option<&str> {
+    module.funcs.iter().next().unwrap().name.as_deref()
+}
+
+#[test]
+fn odd_sections_fail_to_parse() -> anyhow::Result<()> {
+    let err = parse(&odd_names()?, CustomSectionPolicy::Error).unwrap_err();
+    assert!(format!("{:#}", err).contains("unknown name subsection 9"));
+    let err = parse(&odd_producers()?, CustomSectionPolicy::Error).unwrap_err();
+    assert!(err.to_string().contains("`producers`"));
+    Ok(())
+}
+
+#[test]
+fn odd_sections_are_skipped() -> anyhow::Result<()> {
+    let mut module = parse(&odd_names()?, CustomSectionPolicy::WarnAndSkip)?;
+    assert_eq!(func_name(&module), Some("a"));
+    let wasm = walrus::round_trip::check(&mut module)?;
+    // Sizes are emitted padded.
+    assert!(wasm.ends_with(&[b'e', 1, 132, 128, 128, 128, 0, 1, 0, 1, b'a']));
+
+    let mut module = parse(&odd_producers()?, CustomSectionPolicy::WarnAndSkip)?;
+    assert_eq!(module.customs.iter().count(), 0);
+    assert_eq!(module.emit_wasm(), wat::parse_str("(module)")?);
+    Ok(())
+}
+
+#[test]
+fn odd_sections_are_kept_raw() -> anyhow::Result<()> {
+    let mut module = parse(&odd_names()?, CustomSectionPolicy::KeepRaw)?;
+    assert_eq!(func_name(&module), Some("a"));
+    let wasm = walrus::round_trip::check(&mut module)?;
+    assert!(wasm.ends_with(&[
+        b'a', 9, 130, 128, 128, 128, 0, 0xaa, 0xbb, 1, 132, 128, 128, 128, 0, 1, 0, 1, b'b'
+    ]));
+
+    let mut module = parse(&odd_producers()?, CustomSectionPolicy::KeepRaw)?;
+    let sections = module.customs.iter().collect::<Vec<_>>();
+    assert_eq!(sections.len(), 1);
+    assert_eq!(sections[0].1.name(), "producers");
+    let wasm = walrus::round_trip::check(&mut module)?;
+    assert!(wasm.ends_with(b"producers\x05"));
+    Ok(())
+}
@@ -1,7 +1,7 @@
 //! Tests for working with custom sections that `walrus` doesn't know about.
 
 use std::borrow::Cow;
-use walrus::{CodeTransform, CustomSection, IdsToIndices, Module, ModuleConfig, ValType};
+use walrus::{CodeTransform, CustomSection, IdsToIndices, Module, ValType};
 
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 struct HelloCustomSection(String);
@@ -22,7 +22,7 @@ impl CustomSection for HelloCustomSection {
         "hello"
     }
 
-    fn data(&self, _: &IdsToIndices) -> Cow<[u8]> {
+    fn data(&self, _: &IdsToIndices) -> Cow<'_, [u8]> {
         let data = format!("Hello, {}!", self.0);
         data.into_bytes().into()
     }
@@ -30,8 +30,7 @@ impl CustomSection for HelloCustomSection {
 
 #[test]
 fn round_trip_unkown_custom_sections() {
-    let mut config = ModuleConfig::new();
-    config.generate_producers_section(false);
+    let config = walrus_tests::config();
 
     let indices = IdsToIndices::default();
 
@@ -64,37 +63,52 @@ fn round_trip_unkown_custom_sections() {
     assert_eq!(wasm, new_wasm);
 }
 
-// Insert a `(drop (i32.const 0))` at the start of the function and assert that
-// all instructions are pushed down by the size of a `(drop (i32.const 0))`,
-// which is 3.
-#[test]
-fn smoke_test_code_transform() {
-    use std::sync::atomic::{AtomicUsize, Ordering};
+/// A custom section holding the code offset of an instruction, like DWARF
+/// does, which code transforms keep pointing at the same instruction.
+#[derive(Debug)]
+struct CodeOffset(u32);
 
-    static APPLIED_CODE_TRANSFORM: AtomicUsize = AtomicUsize::new(0);
+impl CustomSection for CodeOffset {
+    fn name(&self) -> &str {
+        "code-offset"
+    }
 
-    #[derive(Debug)]
-    struct CheckCodeTransform;
-    impl CustomSection for CheckCodeTransform {
-        fn name(&self) -> &str {
-            "check-code-transform"
-        }
+    fn data(&self, _: &IdsToIndices) -> Cow<'_, [u8]> {
+        self.0.to_le_bytes().to_vec().into()
+    }
 
-        fn data(&self, _: &IdsToIndices) -> Cow<[u8]> {
-            vec![].into()
-        }
+    fn apply_code_transform(&mut self, transform: &CodeTransform) {
+        let (_, output) = transform
+            .iter()
+            .find(|(input, _)| input.data() == self.0)
+            .unwrap();
+        self.0 = *output as u32;
+    }
+}
 
-        fn apply_code_transform(&mut self, transform: &CodeTransform) {
-            APPLIED_CODE_TRANSFORM.store(1, Ordering::SeqCst);
-            assert!(!transform.is_empty());
-            for (input_offset, output_offset) in transform.iter().cloned() {
-                assert_eq!(input_offset.data() as usize + 3, output_offset);
-            }
-        }
+/// Insert a `(drop (i32.const 0))`, which is 3 bytes, at the start of each
+/// function of `module`.
+fn insert_drop(module: &mut Module) {
+    for (_id, f) in module.funcs.iter_local_mut() {
+        let builder = f.builder_mut();
+        builder.func_body().const_at(0, walrus::ir::Value::I32(0));
+        builder.func_body().drop_at(1);
     }
+}
+
+/// The offset in the emitted `wasm` that its `code-offset` section holds.
+fn code_offset(wasm: &[u8]) -> u32 {
+    let mut module = Module::from_buffer(wasm).unwrap();
+    let data = module.customs.remove_raw("code-offset").unwrap().data;
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
 
-    let mut config = ModuleConfig::new();
-    config.generate_producers_section(false);
+// Code transforms move the offsets in custom sections along with the
+// instructions, from the offsets that the sections were last brought up to
+// date with on every emit.
+#[test]
+fn smoke_test_code_transform() {
+    let mut config = walrus_tests::config();
 
     let wasm = {
         let mut module = Module::with_config(config.clone());
@@ -112,17 +126,156 @@ fn smoke_test_code_transform() {
     config.preserve_code_transform(true);
 
     let mut module = config.parse(&wasm).unwrap();
-    module.customs.add(CheckCodeTransform);
+    let (_, f) = module.funcs.iter_local().next().unwrap();
+    let offset = f.block(f.entry_block()).instrs[0].1.data();
+    module.customs.add(CodeOffset(offset));
 
-    for (_id, f) in module.funcs.iter_local_mut() {
-        let builder = f.builder_mut();
-        builder.func_body().const_at(0, walrus::ir::Value::I32(0));
-        builder.func_body().drop_at(1);
+    insert_drop(&mut module);
+    let wasm = module.emit_wasm();
+    assert_eq!(code_offset(&wasm), offset + 3);
+
+    // Emitting again without changes keeps the offset, and changing the
+    // module again moves it from where the last emit put it.
+    assert_eq!(module.emit_wasm(), wasm);
+    insert_drop(&mut module);
+    assert_eq!(code_offset(&module.emit_wasm()), offset + 6);
+}
+
+// Emitting the original binary of an unmodified module leaves its custom
+// sections pointing into the original binary.
+#[test]
+fn code_transform_with_preserved_encoding() {
+    let wasm =
+        wat::parse_str(r#"(module (func (export "f") (result i32) (i32.const 1337)))"#).unwrap();
+    let mut config = walrus_tests::config();
+    config.preserve_code_transform(true).preserve_encoding(true);
+    let mut wasm = walrus_tests::emit(&mut config.parse(&wasm).unwrap()).unwrap();
+    let offset = {
+        let module = config.parse(&wasm).unwrap();
+        let (_, f) = module.funcs.iter_local().next().unwrap();
+        f.block(f.entry_block()).instrs[0].1.data()
+    };
+    wasm_encoder::Section::append_to(
+        &wasm_encoder::CustomSection {
+            name: "code-offset".into(),
+            data: offset.to_le_bytes().as_ref().into(),
+        },
+        &mut wasm,
+    );
+
+    // Swap the raw section for one that follows code transforms, which is
+    // encoded the same, so the module is still unmodified.
+    let mut module = config.parse(&wasm).unwrap();
+    module.customs.remove_raw("code-offset").unwrap();
+    module.customs.add(CodeOffset(offset));
+    assert_eq!(module.emit_wasm(), wasm);
+    assert_eq!(module.emit_wasm(), wasm);
+
+    // walrus's encoding pads section sizes, unlike the original binary, so
+    // the offset moves by more than the inserted instructions.
+    insert_drop(&mut module);
+    let wasm = module.emit_wasm();
+    let emitted = config.parse(&wasm).unwrap();
+    let (_, f) = emitted.funcs.iter_local().next().unwrap();
+    let moved = f.block(f.entry_block()).instrs[2].1.data();
+    assert!(moved > offset + 3);
+    assert_eq!(code_offset(&wasm), moved);
+}
+
+// A custom section that refers to a function and a global keeps them alive
+// through the gc pass, and can still find their indices when it is emitted.
+#[test]
+fn custom_section_gc_roots() -> anyhow::Result<()> {
+    #[derive(Debug)]
+    struct Coverage {
+        func: walrus::FunctionId,
+        global: walrus::GlobalId,
+    }
+    impl CustomSection for Coverage {
+        fn name(&self) -> &str {
+            "coverage"
+        }
+
+        fn data(&self, indices: &IdsToIndices) -> Cow<'_, [u8]> {
+            let func = indices.get_func_index(self.func) as u8;
+            let global = indices.get_global_index(self.global) as u8;
+            vec![func, global].into()
+        }
+
+        fn add_gc_roots(&self, roots: &mut walrus::passes::Roots) {
+            roots.push_func(self.func).push_global(self.global);
+        }
+    }
+
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (global $unused i32 (i32.const 0))
+          (global $counter (mut i32) (i32.const 0))
+          (func $dead)
+          (func $counted
+            (global.set $counter (i32.add (global.get $counter) (i32.const 1))))
+          (func (export "f")))
+        "#,
+    )?;
+    let mut module = Module::from_buffer(&wasm)?;
+    let func = module.funcs.by_name("counted").unwrap();
+    let global = module.globals.iter().nth(1).unwrap().id();
+    module.customs.add(Coverage { func, global });
+    walrus::passes::gc::run(&mut module);
+    assert_eq!(module.funcs.iter().count(), 2);
+    assert_eq!(module.globals.iter().count(), 1);
+
+    let wasm = module.emit_wasm();
+    let mut module = Module::from_buffer(&wasm)?;
+    let coverage = module.customs.remove_raw("coverage").unwrap();
+    assert_eq!(coverage.data, [0, 0]);
+    Ok(())
+}
+
+// A custom section holding the raw index of a function gets its new index
+// after other functions are deleted, both before the first emit and between
+// emits.
+#[test]
+fn custom_section_index_remap() -> anyhow::Result<()> {
+    #[derive(Debug)]
+    struct Hot(u32);
+    impl CustomSection for Hot {
+        fn name(&self) -> &str {
+            "hot"
+        }
+
+        fn data(&self, _: &IdsToIndices) -> Cow<'_, [u8]> {
+            vec![self.0 as u8].into()
+        }
+
+        fn apply_index_remap(&mut self, remap: &walrus::IndexRemap) {
+            self.0 = remap.func(self.0).unwrap();
+        }
     }
 
-    // Emit the new, transformed wasm. This should trigger the
-    // `apply_code_transform` method to be called.
-    let _wasm = module.emit_wasm();
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (func $a)
+          (func $b)
+          (func $hot (export "hot")))
+        "#,
+    )?;
+    let mut module = Module::from_buffer(&wasm)?;
+    module.customs.add(Hot(2));
+    let a = module.funcs.by_name("a").unwrap();
+    let b = module.funcs.by_name("b").unwrap();
+    module.funcs.delete(a);
+
+    let wasm = module.emit_wasm();
+    assert_eq!(module.emit_wasm(), wasm);
+    let mut emitted = Module::from_buffer(&wasm)?;
+    assert_eq!(emitted.customs.remove_raw("hot").unwrap().data, [1]);
 
-    assert_eq!(APPLIED_CODE_TRANSFORM.load(Ordering::SeqCst), 1);
+    module.funcs.delete(b);
+    let wasm = module.emit_wasm();
+    let mut emitted = Module::from_buffer(&wasm)?;
+    assert_eq!(emitted.customs.remove_raw("hot").unwrap().data, [0]);
+    Ok(())
 }
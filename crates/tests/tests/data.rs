@@ -0,0 +1,139 @@
+//! Tests for splitting and rewiring data segments.
+
+use walrus::ir::Instr;
+use walrus::{ActiveDataLocation, DataKind, Module};
+
+const WAT: &str = r#"
+    (module
+      (global $g (import "env" "g") i32)
+      (memory $a 1)
+      (memory $b 1)
+      (data (i32.const 16) "hello world")
+      (data (global.get $g) "relative")
+      (func $init (export "init")
+        unreachable))
+"#;
+
+fn module() -> anyhow::Result<Module> {
+    // Multiple memories need the multi-memory proposal, which `wat` only
+    // parses and walrus only emits, so don't validate.
+    let mut config = walrus::ModuleConfig::new();
+    config.strict_validate(false);
+    config.parse(&wat::parse_str(WAT)?)
+}
+
+#[test]
+fn split_data() -> anyhow::Result<()> {
+    let mut module = module()?;
+    let mut data = module.data.iter().map(|d| d.id()).collect::<Vec<_>>();
+    let relative = data.pop().unwrap();
+    let absolute = data.pop().unwrap();
+
+    let tail = module.split_data(absolute, 6)?;
+    assert_eq!(module.data.get(absolute).value, b"hello ");
+    assert_eq!(module.data.get(tail).value, b"world");
+    match &module.data.get(tail).kind {
+        DataKind::Active(a) => {
+            assert_eq!(a.location, ActiveDataLocation::Absolute(22));
+            assert!(module.memories.get(a.memory).data_segments.contains(&tail));
+        }
+        DataKind::Passive => panic!("expected an active segment"),
+    }
+
+    assert!(module.split_data(absolute, 7).is_err());
+    assert!(module.split_data(relative, 1).is_err());
+    Ok(())
+}
+
+#[test]
+fn set_data_memory() -> anyhow::Result<()> {
+    let mut module = module()?;
+    let memories = module.memories.iter().map(|m| m.id()).collect::<Vec<_>>();
+    let data = module.data.iter().next().unwrap().id();
+    assert!(module
+        .memories
+        .get(memories[0])
+        .data_segments
+        .contains(&data));
+
+    module.set_data_memory(data, memories[1])?;
+    assert!(!module
+        .memories
+        .get(memories[0])
+        .data_segments
+        .contains(&data));
+    assert!(module
+        .memories
+        .get(memories[1])
+        .data_segments
+        .contains(&data));
+    match &module.data.get(data).kind {
+        DataKind::Active(a) => assert_eq!(a.memory, memories[1]),
+        DataKind::Passive => panic!("expected an active segment"),
+    }
+    Ok(())
+}
+
+#[test]
+fn active_to_passive_and_back() -> anyhow::Result<()> {
+    let mut module = module()?;
+    let memory = module.memories.iter().next().unwrap().id();
+    let data = module.data.iter().map(|d| d.id()).collect::<Vec<_>>();
+    let init = module.funcs.by_name("init").unwrap();
+
+    module.make_data_passive(data[0], init)?;
+    module.make_data_passive(data[1], init)?;
+    assert!(module.make_data_passive(data[1], init).is_err());
+    assert!(module.data.get(data[0]).is_passive());
+    assert!(module.memories.get(memory).data_segments.is_empty());
+
+    let func = module.funcs.get(init).kind.unwrap_local();
+    let instrs = func
+        .block(func.entry_block())
+        .instrs
+        .iter()
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    // The last converted segment is copied first, and the original body
+    // comes last.
+    assert!(instrs[0].is_global_get());
+    assert!(instrs[3].is_memory_init());
+    assert!(instrs[4].is_data_drop());
+    match instrs[5] {
+        Instr::Const(c) => assert!(matches!(c.value, walrus::ir::Value::I32(16))),
+        _ => panic!("expected the address of the first segment"),
+    }
+    assert!(instrs[10].is_unreachable());
+    assert_eq!(instrs.len(), 11);
+
+    module.make_data_active(data[0], memory, ActiveDataLocation::Absolute(32))?;
+    assert!(module
+        .make_data_active(data[0], memory, ActiveDataLocation::Absolute(32))
+        .is_err());
+    assert!(module.memories.get(memory).data_segments.contains(&data[0]));
+    Ok(())
+}
+
+#[test]
+fn passive_segments_round_trip() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (memory 1)
+          (data (i32.const 16) "hello")
+          (func $start)
+          (start $start))
+        "#,
+    )?;
+    let mut module = Module::from_buffer(&wasm)?;
+    let data = module.data.iter().next().unwrap().id();
+    module.make_data_passive(data, module.start.unwrap())?;
+
+    // The result validates, and the segment is now initialized by `start`.
+    let module = Module::from_buffer(&module.emit_wasm())?;
+    assert!(module.data.iter().next().unwrap().is_passive());
+    let start = module.funcs.get(module.start.unwrap()).kind.unwrap_local();
+    let instrs = &start.block(start.entry_block()).instrs;
+    assert!(instrs.iter().any(|(i, _)| i.is_memory_init()));
+    Ok(())
+}
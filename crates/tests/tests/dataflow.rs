@@ -0,0 +1,94 @@
+//! Tests for the dataflow framework and its lattices.
+
+use walrus::ir::*;
+use walrus::passes::dataflow::{self, Constant, KnownBits, Lattice};
+use walrus::{LocalFunction, Module};
+
+fn module(wat: &str) -> anyhow::Result<Module> {
+    Module::from_buffer(&wat::parse_str(wat)?)
+}
+
+/// Get the value on top of the stack before each `call_indirect` in the
+/// function's entry sequence.
+fn call_indirect_indices<L: Lattice>(module: &Module, func: &LocalFunction) -> Vec<Option<L>> {
+    let dataflow = dataflow::run::<L>(module, func);
+    let entry = func.entry_block();
+    func.block(entry)
+        .instrs
+        .iter()
+        .enumerate()
+        .filter(|(_, (instr, _))| instr.is_call_indirect())
+        .map(|(index, _)| {
+            dataflow
+                .before(entry, index)
+                .map(|state| state.stack.last().unwrap().clone())
+        })
+        .collect()
+}
+
+#[test]
+fn masked_table_index_is_in_range() -> anyhow::Result<()> {
+    let module = module(
+        r#"
+        (module
+          (type $t (func))
+          (table 4 funcref)
+          (func (param i32)
+            (call_indirect (type $t) (i32.and (local.get 0) (i32.const 3)))
+            (call_indirect (type $t) (i32.and (local.get 0) (i32.const 7)))
+            (call_indirect (type $t) (local.get 0))))
+        "#,
+    )?;
+    let (_, func) = module.funcs.iter_local().next().unwrap();
+    let indices = call_indirect_indices::<KnownBits>(&module, func);
+    let max = indices
+        .iter()
+        .map(|index| index.unwrap().max_unsigned(32))
+        .collect::<Vec<_>>();
+    assert_eq!(max, [3, 7, u32::MAX as u64]);
+    Ok(())
+}
+
+#[test]
+fn constants_through_control_flow() -> anyhow::Result<()> {
+    let module = module(
+        r#"
+        (module
+          (type $t (func))
+          (table 4 funcref)
+          (global $g i32 (i32.const 1))
+          (func (param i32) (local i32 i32)
+            (local.set 1 (i32.add (global.get $g) (i32.const 1)))
+            (if (local.get 0)
+              (then (local.set 2 (i32.const 5)))
+              (else (local.set 2 (i32.const 6))))
+            (loop
+              (br_if 0 (local.get 0))
+              (local.set 0 (i32.const 9)))
+            (call_indirect (type $t) (local.get 1))
+            (call_indirect (type $t) (local.get 2))
+            (call_indirect (type $t) (local.get 0))
+            (block
+              (local.set 1 (i32.const 3))
+              (br 0)
+              (local.set 1 (i32.const 4)))
+            (call_indirect (type $t) (local.get 1))))
+        "#,
+    )?;
+    let (_, func) = module.funcs.iter_local().next().unwrap();
+    let indices = call_indirect_indices::<Constant>(&module, func);
+    assert_eq!(
+        indices,
+        [
+            Some(Constant::Known(Value::I32(2))),
+            Some(Constant::Unknown),
+            Some(Constant::Known(Value::I32(9))),
+            Some(Constant::Known(Value::I32(3))),
+        ]
+    );
+
+    let indices = call_indirect_indices::<KnownBits>(&module, func);
+    assert_eq!(indices[1].unwrap().value(32), None);
+    assert_eq!(indices[1].unwrap().max_unsigned(32), 7);
+    Ok(())
+}
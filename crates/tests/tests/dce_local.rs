@@ -0,0 +1,52 @@
+//! Tests for removing unreachable instructions.
+
+use walrus::passes::dce_local;
+use walrus::{FunctionBuilder, Module};
+
+#[test]
+fn removes_unreachable_instructions() -> anyhow::Result<()> {
+    let config = walrus_tests::config();
+    let mut module = Module::with_config(config);
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    let mut body = builder.func_body();
+    body.block(None, |exit| {
+        let exit_id = exit.id();
+        exit.br(exit_id).i32_const(1).drop().block(None, |dead| {
+            dead.i32_const(2).drop();
+        });
+    })
+    .i32_const(3)
+    .drop()
+    .block(None, |trap| {
+        trap.unreachable();
+    })
+    .i32_const(4)
+    .drop()
+    .return_();
+    let f = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("f", f);
+
+    assert_eq!(dce_local::run(&mut module), 5 + 3);
+    assert_eq!(dce_local::run(&mut module), 0);
+
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)?;
+    assert_eq!(
+        wasmprinter::print_bytes(&wasm)?,
+        r#"(module
+  (type (;0;) (func))
+  (func (;0;) (type 0)
+    block  ;; label = @1
+      br 0 (;@1;)
+    end
+    i32.const 3
+    drop
+    block  ;; label = @1
+      unreachable
+    end
+    unreachable)
+  (export "f" (func 0)))"#
+    );
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! Tests for demangling function names.
+
+use walrus::{FunctionBuilder, FunctionId, Module, ModuleConfig};
+
+const RUST: &str = "_ZN4core9panicking5panic17h3a2fa3e2ef8ce8f0E";
+const CPP: &str = "_Z3fooi";
+
+fn module(config: ModuleConfig) -> (Module, Vec<FunctionId>) {
+    let mut module = Module::with_config(config);
+    let funcs = [RUST, CPP, "plain"]
+        .iter()
+        .map(|name| {
+            let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+            builder.func_body();
+            let f = builder.finish(vec![], &mut module.funcs);
+            module.funcs.get_mut(f).name = Some(name.to_string());
+            module.exports.add(name, f);
+            f
+        })
+        .collect();
+    (module, funcs)
+}
+
+fn demangled(module: &Module, f: FunctionId) -> String {
+    module.funcs.get(f).demangled_name().unwrap().into_owned()
+}
+
+#[test]
+fn unmangled_names_are_unchanged() {
+    let (module, funcs) = module(ModuleConfig::new());
+    assert_eq!(demangled(&module, funcs[2]), "plain");
+}
+
+#[test]
+#[cfg(not(any(feature = "demangle-rust", feature = "demangle-cpp")))]
+fn names_are_unchanged_without_demanglers() {
+    let (module, funcs) = module(ModuleConfig::new());
+    assert_eq!(demangled(&module, funcs[0]), RUST);
+    assert_eq!(demangled(&module, funcs[1]), CPP);
+}
+
+#[test]
+#[cfg(feature = "demangle-rust")]
+fn demangle_rust() {
+    let (module, funcs) = module(ModuleConfig::new());
+    assert_eq!(demangled(&module, funcs[0]), "core::panicking::panic");
+}
+
+#[test]
+#[cfg(feature = "demangle-cpp")]
+fn demangle_cpp() {
+    let (module, funcs) = module(ModuleConfig::new());
+    assert_eq!(demangled(&module, funcs[1]), "foo(int)");
+}
+
+#[test]
+fn demangled_name_section() -> anyhow::Result<()> {
+    let mut config = ModuleConfig::new();
+    config.demangle_names(true);
+    let (mut module, funcs) = module(config);
+    let expected = funcs
+        .iter()
+        .map(|f| demangled(&module, *f))
+        .collect::<Vec<_>>();
+
+    let wasm = module.emit_wasm();
+    let module = Module::from_buffer(&wasm)?;
+    let names = module
+        .funcs
+        .iter()
+        .map(|f| f.name.clone().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, expected);
+    Ok(())
+}
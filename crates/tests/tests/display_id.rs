@@ -0,0 +1,80 @@
+//! Tests for displaying ids along with what they identify.
+
+use walrus::{DisplayId, ImportKind, Module};
+
+#[test]
+fn displays_ids() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (import "env" "log" (func (param i32)))
+          (import "env" "counter" (global $counter i32))
+          (import "env" "memory" (memory 1))
+          (table 1 funcref)
+          (func $main (param $x i32) (result i32)
+            (local i64)
+            (local.get $x))
+          (data (i32.const 0) "hi"))
+        "#,
+    )?;
+    let mut module = Module::from_buffer(&wasm)?;
+    let log = match module.imports.iter().next().unwrap().kind {
+        ImportKind::Function(f) => f,
+        _ => unreachable!(),
+    };
+    let main = module.funcs.by_name("main").unwrap();
+    let local = module.funcs.get(main).kind.unwrap_local();
+    let x = local.args[0];
+    let ty = local.ty();
+    let counter = module.globals.iter().next().unwrap().id();
+    let memory = module.memories.iter().next().unwrap().id();
+    let table = module.tables.iter().next().unwrap().id();
+    let data = module.data.iter().next().unwrap().id();
+
+    let show = |s: &str, index: usize| s.replace('#', &index.to_string());
+    assert_eq!(
+        log.display(&module).to_string(),
+        show(r#"func[#] import "env" "log""#, log.index())
+    );
+    assert_eq!(
+        format!("{:?}", main.display(&module)),
+        show(r#"func[#] "main""#, main.index())
+    );
+    assert_eq!(
+        x.display(&module).to_string(),
+        show(r#"local[#] "x""#, x.index())
+    );
+    assert_eq!(
+        ty.display(&module).to_string(),
+        show("type[#] [i32] -> [i32]", ty.index())
+    );
+    assert_eq!(
+        counter.display(&module).to_string(),
+        show(r#"global[#] import "env" "counter""#, counter.index())
+    );
+    assert_eq!(
+        memory.display(&module).to_string(),
+        show(r#"memory[#] import "env" "memory""#, memory.index())
+    );
+    assert_eq!(
+        table.display(&module).to_string(),
+        show("table[#]", table.index())
+    );
+    assert_eq!(
+        data.display(&module).to_string(),
+        show("data[#]", data.index())
+    );
+
+    module.funcs.delete(main);
+    assert_eq!(
+        main.display(&module).to_string(),
+        show("func[#] (not in the module)", main.index())
+    );
+    // Ids from other modules aren't looked up in this one.
+    let other = Module::from_buffer(&wasm)?;
+    assert!(x
+        .display(&other)
+        .to_string()
+        .ends_with("(not in the module)"));
+    Ok(())
+}
@@ -0,0 +1,118 @@
+//! Tests for the textual dump of the IR.
+
+use walrus::{FunctionBuilder, Module, ValType};
+
+#[test]
+fn dump_module() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+            (module
+              (import "env" "f" (func $f (param i32)))
+              (memory 1 2)
+              (global (mut i32) (i32.const 8))
+              (func $g (export "g") (param i32) (result i32)
+                (local i64)
+                block
+                  local.get 0
+                  br_if 0
+                  local.get 0
+                  call $f
+                end
+                local.get 0
+                if (result i32)
+                  f32.const -0
+                  drop
+                  f64.const 1e300
+                  drop
+                  i32.const 1
+                else
+                  local.get 0
+                  i32.load offset=4
+                end)
+              (data (i32.const 16) "hi\00\"\\"))
+        "#,
+    )?;
+    let config = walrus_tests::config();
+    let module = config.parse(&wasm)?;
+    assert_eq!(
+        module.dump(),
+        r#"type[0] (param i32)
+type[1] (param i32) (result i32)
+type[2] (result i32)
+import[0] "env" "f" func[0]
+memory[0] 1 2
+global[0] i32 mut = i32.const 8
+func[0] "f" type[0] import[0]
+func[1] "g" type[1]
+  param l0 i32
+  entry seq0
+    block seq1
+      local.get l0
+      br_if seq1
+      local.get l0
+      call func[0]
+    end
+    local.get l0
+    if seq2 (result i32)
+      f32.const -0.0 (0x80000000)
+      drop
+      f64.const 1e300 (0x7e37e43c8800759c)
+      drop
+      i32.const 1
+    else seq3 (result i32)
+      local.get l0
+      load memory[0] I32 { atomic: false } offset=4 align=4
+    end
+export "g" func[1]
+data[0] memory[0] at 16 "hi\00\"\\"
+"#
+    );
+    Ok(())
+}
+
+/// Build a function that swaps its arguments through a temporary, after
+/// allocating `unrelated` locals elsewhere in the module.
+fn swap(unrelated: usize) -> Module {
+    let mut module = Module::default();
+    for _ in 0..unrelated {
+        module.locals.add(ValType::F64);
+    }
+    let tmp = module.locals.add(ValType::I32);
+    let a = module.locals.add(ValType::I32);
+    let b = module.locals.add(ValType::I32);
+    let params = [ValType::I32, ValType::I32];
+    let mut builder = FunctionBuilder::new(&mut module.types, &params, &[]);
+    builder
+        .func_body()
+        .local_get(a)
+        .local_set(tmp)
+        .local_get(b)
+        .local_set(a)
+        .local_get(tmp)
+        .local_set(b);
+    builder.finish(vec![a, b], &mut module.funcs);
+    module
+}
+
+#[test]
+fn dump_doesnt_depend_on_id_allocation() {
+    let expected = "\
+param l0
+param l1
+local l2
+entry seq0
+  local.get l0
+  local.set l2
+  local.get l1
+  local.set l0
+  local.get l2
+  local.set l1
+";
+    for unrelated in 0..3 {
+        let module = swap(unrelated);
+        let func = module.funcs.iter().next().unwrap();
+        let func = func.kind.unwrap_local();
+        assert_eq!(func.to_string(), expected);
+        assert_eq!(module.dump(), swap(0).dump());
+    }
+}
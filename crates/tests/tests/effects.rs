@@ -0,0 +1,52 @@
+//! Tests for finding the effects of functions.
+
+use walrus::passes::effects::{self, Effect};
+use walrus::Module;
+
+const WAT: &str = r#"
+    (module
+      (import "env" "log" (func $log (param i32)))
+      (memory 1)
+      (global $c i32 (i32.const 1))
+      (global $g (mut i32) (i32.const 0))
+      (table 1 funcref)
+      (func $pure (param i32) (result i32)
+        (i32.div_s (local.get 0) (global.get $c)))
+      (func $reads (result i32)
+        (i32.add (i32.load (i32.const 0)) (call $pure (global.get $g))))
+      (func $writes
+        (global.set $g (call $reads)))
+      (func $even (param i32) (result i32)
+        (if (result i32) (local.get 0)
+          (then (call $odd (i32.sub (local.get 0) (i32.const 1))))
+          (else (i32.const 1))))
+      (func $odd (param i32) (result i32)
+        (if (result i32) (local.get 0)
+          (then (call $even (i32.sub (local.get 0) (i32.const 1))))
+          (else (i32.const 0))))
+      (func $store_in_loop (param i32)
+        (loop
+          (i32.store (local.get 0) (call $even (local.get 0)))))
+      (func $logs
+        (call $writes)
+        (call $log (i32.const 0)))
+      (func $indirect
+        (call_indirect (i32.const 0))))
+"#;
+
+#[test]
+fn effects() -> anyhow::Result<()> {
+    let module = Module::from_buffer(&wat::parse_str(WAT)?)?;
+    let effects = effects::run(&module);
+    let effect = |name| effects[&module.funcs.by_name(name).unwrap()];
+    assert_eq!(effect("pure"), Effect::Pure);
+    assert_eq!(effect("reads"), Effect::ReadsMemory);
+    assert_eq!(effect("writes"), Effect::WritesMemory);
+    assert_eq!(effect("even"), Effect::Pure);
+    assert_eq!(effect("odd"), Effect::Pure);
+    assert_eq!(effect("store_in_loop"), Effect::WritesMemory);
+    assert_eq!(effect("log"), Effect::CallsImports);
+    assert_eq!(effect("logs"), Effect::CallsImports);
+    assert_eq!(effect("indirect"), Effect::CallsImports);
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! Tests for the kinds of errors from parsing modules.
+
+use walrus::{ErrorKind, Module, ModuleConfig};
+
+fn kind(err: &anyhow::Error) -> Option<ErrorKind> {
+    ErrorKind::of(err)
+}
+
+#[test]
+fn malformed_modules_fail_to_parse() {
+    let err = Module::from_buffer(b"\0asm\x01\0\0\0\x01\x05").unwrap_err();
+    assert_eq!(kind(&err), Some(ErrorKind::Parse));
+    let err = Module::from_buffer(b"not wasm at all").unwrap_err();
+    assert_eq!(kind(&err), Some(ErrorKind::Parse));
+    assert!(err.to_string().contains("Bad magic number"));
+
+    // Without validating the binary up front, walrus' own parsing notices.
+    let err = ModuleConfig::new()
+        .strict_validate(false)
+        .parse(b"\0asm\x01\0\0\0\x01\x05")
+        .unwrap_err();
+    assert_eq!(kind(&err), Some(ErrorKind::Parse));
+}
+
+#[test]
+fn invalid_modules_fail_to_validate() -> anyhow::Result<()> {
+    let wasm = wat::parse_str("(module (func (result i32) (i64.const 0)))")?;
+    let err = Module::from_buffer(&wasm).unwrap_err();
+    assert_eq!(kind(&err), Some(ErrorKind::Validate));
+    assert!(err.to_string().contains("in the body of function 0"));
+
+    let err = ModuleConfig::new()
+        .strict_validate(false)
+        .parse(&wasm)
+        .unwrap_err();
+    assert_eq!(kind(&err), Some(ErrorKind::Validate));
+    Ok(())
+}
+
+#[test]
+fn unsupported_features_are_named() -> anyhow::Result<()> {
+    let wasm = wat::parse_str("(module (func (result v128) (v128.const i32x4 0 0 0 0)))")?;
+    Module::from_buffer(&wasm)?;
+    let err = ModuleConfig::new()
+        .only_stable_features(true)
+        .parse(&wasm)
+        .unwrap_err();
+    assert_eq!(kind(&err), Some(ErrorKind::Unsupported { feature: "simd" }));
+    assert!(err.to_string().contains("SIMD support is not enabled"));
+
+    let err = Module::from_buffer(b"\0asm\x02\0\0\0").unwrap_err();
+    match kind(&err) {
+        Some(ErrorKind::Unsupported { .. }) => {}
+        other => panic!("unexpected kind {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn limits_are_kinded() -> anyhow::Result<()> {
+    let wasm = wat::parse_str("(module (func) (func))")?;
+    let err = ModuleConfig::new()
+        .max_functions(1)
+        .parse(&wasm)
+        .unwrap_err();
+    assert_eq!(kind(&err), Some(ErrorKind::LimitExceeded));
+    Ok(())
+}
+
+#[test]
+fn unkinded_errors() {
+    assert_eq!(kind(&anyhow::anyhow!("something else")), None);
+}
@@ -0,0 +1,82 @@
+//! Tests for shimming `i32` handles into `externref`s.
+
+use walrus::passes::externref_handles;
+use walrus::{ExportItem, ImportKind, Module, ValType};
+use walrus_tests::parse;
+
+const WAT: &str = r#"
+    (module
+      (import "env" "make" (func $make (param i32) (result i32)))
+      (import "env" "show" (func $show (param i32 i64)))
+      (func $use (export "use") (param i32) (result i32)
+        (call $show (local.get 0) (i64.const 1))
+        (call $make (i32.const 2))))
+"#;
+
+fn import(module: &Module, name: &str) -> walrus::FunctionId {
+    let import = module
+        .imports
+        .get(module.imports.find("env", name).unwrap());
+    match import.kind {
+        ImportKind::Function(f) => f,
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn shims_imports_and_exports() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    let handles = externref_handles::add_table(&mut module);
+    let make = import(&module, "make");
+    let show = import(&module, "show");
+    let used = module.funcs.by_name("use").unwrap();
+    externref_handles::shim_import(&mut module, &handles, make, &[], &[0])?;
+    externref_handles::shim_import(&mut module, &handles, show, &[0], &[])?;
+    let wrapper = externref_handles::shim_export(&mut module, &handles, used, &[0], &[0])?;
+
+    let ty = |f| {
+        let (params, results) = module.types.params_results(module.funcs.get(f).ty());
+        (params.to_vec(), results.to_vec())
+    };
+    assert_eq!(ty(make), (vec![ValType::I32], vec![ValType::Anyref]));
+    assert_eq!(ty(show), (vec![ValType::Anyref, ValType::I64], vec![]));
+    assert_eq!(ty(wrapper), (vec![ValType::Anyref], vec![ValType::Anyref]));
+    let export = module.exports.iter().find(|e| e.name == "use").unwrap();
+    assert!(matches!(export.item, ExportItem::Function(f) if f == wrapper));
+
+    let wasm = walrus::round_trip::check(&mut module)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    // The original function still takes and returns handles, and calls the
+    // imports through thunks converting them.
+    assert!(wat.contains(
+        "(func $use (type 1) (param i32) (result i32)\n    \
+         local.get 0\n    i64.const 1\n    call $externref_shim$show\n    \
+         i32.const 2\n    call $externref_shim$make)"
+    ));
+    assert!(wat.contains("local.get 0\n    call $externref_get\n    local.get 1\n    call $show)"));
+    assert!(
+        wat.contains("call $make\n    local.set 1\n    local.get 1\n    call $externref_alloc)")
+    );
+    assert!(wat.contains(
+        "local.get 0\n    call $externref_alloc\n    call $use\n    \
+         local.set 1\n    local.get 1\n    call $externref_get)"
+    ));
+    assert!(wat.contains("(table (;0;) 1 anyref)"));
+    Ok(())
+}
+
+#[test]
+fn rejects_non_handles() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    let handles = externref_handles::add_table(&mut module);
+    let show = import(&module, "show");
+    let used = module.funcs.by_name("use").unwrap();
+
+    let err = externref_handles::shim_import(&mut module, &handles, show, &[1], &[]).unwrap_err();
+    assert!(err.to_string().contains("isn't an `i32`"));
+    let err = externref_handles::shim_import(&mut module, &handles, used, &[0], &[]).unwrap_err();
+    assert!(err.to_string().contains("only shim imported functions"));
+    let err = externref_handles::shim_export(&mut module, &handles, used, &[], &[1]).unwrap_err();
+    assert!(err.to_string().contains("out of bounds"));
+    Ok(())
+}
@@ -0,0 +1,56 @@
+//! Tests for extracting functions into standalone modules.
+
+use walrus::Module;
+
+const WAT: &str = r#"
+    (module
+      (import "env" "log" (func $log (param i32)))
+      (memory (export "memory") 1)
+      (data (i32.const 0) "hi")
+      (global $counter (mut i32) (i32.const 0))
+      (global $unused i32 (i32.const 1))
+      (func $helper (param i32) (result i32)
+        (call $log (local.get 0))
+        (i32.load (local.get 0)))
+      (func $target (export "target") (param i32) (result i32)
+        (global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+        (call $helper (local.get 0)))
+      (func $other (export "other")
+        (drop (global.get $unused)))
+      (start $other))
+"#;
+
+#[test]
+fn extracts_function() -> anyhow::Result<()> {
+    let module = walrus_tests::parse(WAT)?;
+    let target = module.funcs.by_name("target").unwrap();
+
+    let mut extracted = module.extract(&[target]);
+    let wat = wasmprinter::print_bytes(extracted.emit_wasm())?;
+    assert!(wat.contains("(import \"env\" \"helper\" (func $helper (type 0)))"));
+    assert!(wat.contains("(global (;0;) (mut i32) (i32.const 0))"));
+    assert!(wat.contains("(export \"target\" (func $target))"));
+    // `helper` is only imported, so `log` and the memory aren't needed.
+    assert!(!wat.contains("log"));
+    assert!(!wat.contains("memory"));
+    assert!(!wat.contains("other"));
+    assert!(!wat.contains("start"));
+    assert!(!wat.contains("(i32.const 1))"));
+
+    // The original module is left as it was.
+    assert_eq!(module.exports.iter().count(), 3);
+    Ok(())
+}
+
+#[test]
+fn extracts_function_with_its_memory() -> anyhow::Result<()> {
+    let module = Module::from_buffer(&wat::parse_str(WAT)?)?;
+    let helper = module.funcs.by_name("helper").unwrap();
+    let mut extracted = module.extract(&[helper]);
+    let wat = wasmprinter::print_bytes(extracted.emit_wasm())?;
+    assert!(wat.contains("(import \"env\" \"log\" (func $log (type 0)))"));
+    assert!(wat.contains("(memory (;0;) 1)"));
+    assert!(wat.contains("(data (;0;) (i32.const 0) \"hi\")"));
+    assert!(wat.contains("(export \"helper\" (func $helper))"));
+    Ok(())
+}
@@ -0,0 +1,95 @@
+//! Tests for checking a module against a set of WebAssembly features.
+
+use walrus::{Module, WasmFeatures};
+
+fn module(wat: &str) -> anyhow::Result<Module> {
+    Module::from_buffer(&wat::parse_str(wat)?)
+}
+
+fn errors(module: &Module, features: WasmFeatures) -> String {
+    match module.assert_compatible(features) {
+        Ok(()) => String::new(),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn mvp_modules_are_always_compatible() -> anyhow::Result<()> {
+    let module = module(
+        r#"
+        (module
+          (memory 1)
+          (table 1 funcref)
+          (global (mut i32) (i32.const 0))
+          (data (i32.const 0) "x")
+          (func $f (param i64) (result f32)
+            local.get 0
+            f32.convert_i64_s)
+          (elem (i32.const 0) $f))
+        "#,
+    )?;
+    module.assert_compatible(WasmFeatures::mvp())?;
+    module.assert_compatible(WasmFeatures::all())?;
+    Ok(())
+}
+
+#[test]
+fn reports_instructions_with_locations() -> anyhow::Result<()> {
+    let module = module(
+        r#"
+        (module
+          (func $simd (param i32) (result i32)
+            local.get 0
+            i32x4.splat
+            i32x4.extract_lane 0
+            i32.extend8_s))
+        "#,
+    )?;
+    let features = WasmFeatures {
+        simd: false,
+        ..WasmFeatures::all()
+    };
+    let errs = errors(&module, features);
+    assert!(errs.contains("function `simd` at offset 0x"), "{}", errs);
+    assert!(errs.contains("`I32x4Splat` operator requires the simd feature"));
+    assert!(errs.contains("`I32x4ExtractLane { idx: 0 }` operator requires the simd"));
+    assert!(!errs.contains("sign extension"));
+
+    let errs = errors(&module, WasmFeatures::mvp());
+    assert!(errs.contains("`I32Extend8S` operator requires the sign extension feature"));
+    Ok(())
+}
+
+#[test]
+fn reports_module_level_uses() -> anyhow::Result<()> {
+    let module = module(
+        r#"
+        (module
+          (memory 1 1 shared)
+          (data passive "x")
+          (func (result i32 i32)
+            i32.const 0
+            i32.const 0
+            i32.const 1
+            memory.init 0
+            data.drop 0
+            i32.const 0
+            i32.const 0))
+        "#,
+    )?;
+    let errs = errors(&module, WasmFeatures::mvp());
+    assert!(errs.contains("memory 0: shared memory requires the threads feature"));
+    assert!(errs.contains("data segment 0: passive segment requires the bulk memory feature"));
+    assert!(errs.contains("`memory.init` requires the bulk memory feature"));
+    assert!(errs.contains("`data.drop` requires the bulk memory feature"));
+    assert!(errs.contains("function type with multiple results requires the multi-value"));
+
+    let features = WasmFeatures {
+        threads: true,
+        bulk_memory: true,
+        multi_value: true,
+        ..WasmFeatures::mvp()
+    };
+    module.assert_compatible(features)?;
+    Ok(())
+}
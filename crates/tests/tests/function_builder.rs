@@ -0,0 +1,462 @@
+//! Tests for building functions from scratch with `FunctionBuilder`.
+
+use walrus::{FunctionBuilder, Module};
+
+#[test]
+fn sign_extension_and_saturating_truncation() -> anyhow::Result<()> {
+    let config = walrus_tests::config();
+    let mut module = Module::with_config(config);
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder
+        .func_body()
+        .i32_const(1)
+        .i32_extend8_s()
+        .i32_extend16_s()
+        .drop()
+        .i64_const(1)
+        .i64_extend8_s()
+        .i64_extend16_s()
+        .i64_extend32_s()
+        .drop()
+        .f32_const(1.0)
+        .i32_trunc_sat_f32_s()
+        .drop()
+        .f32_const(1.0)
+        .i32_trunc_sat_f32_u()
+        .drop()
+        .f64_const(1.0)
+        .i32_trunc_sat_f64_s()
+        .drop()
+        .f64_const(1.0)
+        .i32_trunc_sat_f64_u()
+        .drop()
+        .f32_const(1.0)
+        .i64_trunc_sat_f32_s()
+        .drop()
+        .f32_const(1.0)
+        .i64_trunc_sat_f32_u()
+        .drop()
+        .f64_const(1.0)
+        .i64_trunc_sat_f64_s()
+        .drop()
+        .f64_const(1.0)
+        .i64_trunc_sat_f64_u()
+        .drop();
+    let f = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("f", f);
+
+    let wasm = module.emit_wasm();
+    // Parsing will validate the types of the operands of each instruction.
+    Module::from_buffer(&wasm)?;
+
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    for instr in &[
+        "i32.extend8_s",
+        "i32.extend16_s",
+        "i64.extend8_s",
+        "i64.extend16_s",
+        "i64.extend32_s",
+        "i32.trunc_sat_f32_s",
+        "i32.trunc_sat_f32_u",
+        "i32.trunc_sat_f64_s",
+        "i32.trunc_sat_f64_u",
+        "i64.trunc_sat_f32_s",
+        "i64.trunc_sat_f32_u",
+        "i64.trunc_sat_f64_s",
+        "i64.trunc_sat_f64_u",
+    ] {
+        assert!(wat.contains(instr), "missing `{}` in:\n{}", instr, wat);
+    }
+    Ok(())
+}
+
+#[test]
+fn atomic_rmw_helpers() -> anyhow::Result<()> {
+    use walrus::ir::{AtomicWidth, BinaryOp, MemArg, Value};
+    use walrus::ValType;
+
+    let config = walrus_tests::config();
+    let mut module = Module::with_config(config);
+    let memory = module.memories.add_local(true, 1, Some(1));
+
+    let widths = [
+        (AtomicWidth::I32, 4),
+        (AtomicWidth::I32_8, 1),
+        (AtomicWidth::I32_16, 2),
+        (AtomicWidth::I64, 8),
+        (AtomicWidth::I64_8, 1),
+        (AtomicWidth::I64_16, 2),
+        (AtomicWidth::I64_32, 4),
+    ];
+    for (i, &(width, align)) in widths.iter().enumerate() {
+        let (ty, one, mul) = if i < 3 {
+            (ValType::I32, Value::I32(1), BinaryOp::I32Mul)
+        } else {
+            (ValType::I64, Value::I64(1), BinaryOp::I64Mul)
+        };
+        let arg = MemArg::new(align, 0);
+
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[ty, ty]);
+        builder
+            .func_body()
+            .i32_const(0)
+            .atomic_rmw_loop(&mut module.locals, memory, width, arg, |new| {
+                new.const_(one).binop(mul);
+            })
+            .i32_const(0)
+            .const_(one)
+            .atomic_fetch_add_checked(&mut module.locals, memory, width, arg);
+        let f = builder.finish(vec![], &mut module.funcs);
+        module.exports.add(&format!("f{}", i), f);
+    }
+
+    let wasm = module.emit_wasm();
+    // Parsing will validate the types of the operands of each instruction, as
+    // well as the alignment of the atomic operations.
+    Module::from_buffer(&wasm)?;
+
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    assert!(wat.contains("i32.atomic.rmw8.cmpxchg_u"));
+    assert!(wat.contains("i64.atomic.rmw32.cmpxchg_u"));
+    assert!(wat.contains("unreachable"));
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "unstable")]
+fn shared_everything_threads() {
+    use walrus::ir::{AtomicOrdering, GlobalAtomicGet, GlobalAtomicSet, Instr};
+    use walrus::{InitExpr, ValType};
+
+    let config = walrus_tests::config();
+    let mut module = Module::with_config(config);
+
+    let shared = module.types.add_shared(&[], &[]);
+    assert!(module.types.get(shared).is_shared());
+    assert_ne!(module.types.add(&[], &[]), shared);
+    assert!(module.types.find(&[], &[]).is_some());
+
+    let init = InitExpr::Value(walrus::ir::Value::I32(0));
+    let global = module.globals.add_local(ValType::I32, true, init);
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder
+        .func_body()
+        .pause()
+        .global_atomic_get(global, AtomicOrdering::AcqRel)
+        .global_atomic_set(global, AtomicOrdering::SeqCst);
+    let f = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("f", f);
+    walrus::passes::validate::run(&module).unwrap();
+
+    let wasm = module.emit_wasm();
+    let contains = |bytes: &[u8]| wasm.windows(bytes.len()).any(|w| w == bytes);
+    assert!(contains(&[0x65, 0x60, 0x00, 0x00]));
+    assert!(contains(&[
+        0xfe, 0x04, 0xfe, 0x4f, 0x01, 0x00, 0xfe, 0x50, 0x00, 0x00
+    ]));
+
+    // Parsing it back gives the same type and instructions. No validator knows
+    // about `pause` yet, so this has to skip strict validation.
+    let mut config = walrus_tests::config();
+    config.strict_validate(false);
+    let mut module = config.parse(&wasm).unwrap();
+    assert_eq!(module.types.iter().filter(|ty| ty.is_shared()).count(), 1);
+    let f = match module.exports.iter().next().unwrap().item {
+        walrus::ExportItem::Function(f) => f,
+        _ => unreachable!(),
+    };
+    let func = module.funcs.get(f).kind.unwrap_local();
+    let instrs = &func.block(func.entry_block()).instrs;
+    assert!(matches!(instrs[0].0, Instr::Pause(_)));
+    assert!(matches!(
+        instrs[1].0,
+        Instr::GlobalAtomicGet(GlobalAtomicGet {
+            ordering: AtomicOrdering::AcqRel,
+            ..
+        })
+    ));
+    assert!(matches!(
+        instrs[2].0,
+        Instr::GlobalAtomicSet(GlobalAtomicSet {
+            ordering: AtomicOrdering::SeqCst,
+            ..
+        })
+    ));
+
+    // Without it, the module passes strict validation too.
+    let func = module.funcs.get_mut(f).kind.unwrap_local_mut();
+    let entry = func.entry_block();
+    func.block_mut(entry).instrs.remove(0);
+    walrus_tests::config().parse(&module.emit_wasm()).unwrap();
+}
+
+#[test]
+#[cfg(feature = "unstable")]
+fn stack_switching() {
+    use walrus::ir::{Instr, Suspend};
+
+    let config = walrus_tests::config();
+    let mut module = Module::with_config(config);
+
+    let func_ty = module.types.add(&[], &[]);
+    let cont_ty = module.types.add_cont(func_ty);
+    assert_eq!(module.types.get(cont_ty).cont_of(), Some(func_ty));
+    assert_eq!(module.types.find(&[], &[]), Some(func_ty));
+    let tag = module.tags.add(func_ty);
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body().suspend(tag);
+    let yielder = builder.finish(vec![], &mut module.funcs);
+    // Exported so that `ref.func` may refer to it.
+    module.exports.add("yielder", yielder);
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder
+        .func_body()
+        .ref_func(yielder)
+        .cont_new(cont_ty)
+        .resume(cont_ty, Box::new([]));
+    let f = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("f", f);
+    walrus::passes::validate::run(&module).unwrap();
+
+    // An unused tag is removed along with the type that only it refers to.
+    let unused_ty = module.types.add(&[walrus::ValType::I64], &[]);
+    module.tags.add(unused_ty);
+    walrus::passes::gc::run(&mut module);
+    assert_eq!(module.tags.iter().count(), 1);
+    assert_eq!(module.types.find(&[walrus::ValType::I64], &[]), None);
+
+    let wasm = module.emit_wasm();
+    let contains = |bytes: &[u8]| wasm.windows(bytes.len()).any(|w| w == bytes);
+    // The continuation type comes after the function type it refers to.
+    assert!(contains(&[0x02, 0x60, 0x00, 0x00, 0x5d, 0x00]));
+    // The tag section.
+    assert!(contains(&[
+        0x0d, 0x83, 0x80, 0x80, 0x80, 0x00, 0x01, 0x00, 0x00
+    ]));
+    assert!(contains(&[0xd2, 0x01, 0xe0, 0x01, 0xe3, 0x01, 0x00]));
+    assert!(contains(&[0xe2, 0x00, 0x0b]));
+
+    // Parsing it back gives the same types, tag and instructions.
+    let module = walrus_tests::config().parse(&wasm).unwrap();
+    assert!(module
+        .types
+        .iter()
+        .any(|ty| ty.cont_of() == module.types.find(&[], &[])));
+    let tag = module.tags.iter().next().unwrap().id();
+    let instrs = |name| {
+        let export = module.exports.iter().find(|e| e.name == name).unwrap();
+        let f = match export.item {
+            walrus::ExportItem::Function(f) => f,
+            _ => unreachable!(),
+        };
+        let func = module.funcs.get(f).kind.unwrap_local();
+        func.block(func.entry_block())
+            .instrs
+            .iter()
+            .map(|(instr, _)| instr.clone())
+            .collect::<Vec<_>>()
+    };
+    assert!(matches!(instrs("yielder")[..], [Instr::Suspend(Suspend { tag: t })] if t == tag));
+    assert!(matches!(
+        instrs("f")[..],
+        [Instr::RefFunc(_), Instr::ContNew(_), Instr::Resume(_)]
+    ));
+}
+
+#[test]
+#[cfg(feature = "unstable")]
+fn continuation_references() {
+    use walrus::ir::{Instr, InstrSeqType};
+    use walrus::ValType;
+
+    let mut module = Module::with_config(walrus_tests::config());
+    let func_ty = module.types.add(&[], &[]);
+    let cont_ty = module.types.add_cont(func_ty);
+    let cont_ref = ValType::Cont {
+        nullable: true,
+        ty: cont_ty,
+    };
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body();
+    let yielder = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("yielder", yielder);
+
+    // The type of this function refers to the continuation type, so it has
+    // to come after it in the type section.
+    let arg = module.locals.add(cont_ref);
+    let mut builder = FunctionBuilder::new(&mut module.types, &[cont_ref], &[]);
+    builder
+        .func_body()
+        .local_get(arg)
+        .resume(cont_ty, Box::new([]));
+    let resume = builder.finish(vec![arg], &mut module.funcs);
+
+    let k = module.locals.add(cont_ref);
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder
+        .func_body()
+        .ref_func(yielder)
+        .cont_new(cont_ty)
+        .local_set(k)
+        .block(cont_ref, |block| {
+            block.local_get(k);
+        })
+        .call(resume);
+    let f = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("f", f);
+    walrus::passes::validate::run(&module).unwrap();
+
+    // The references survive a round trip, and keep the continuation type
+    // alive.
+    let mut module = walrus_tests::config().parse(&module.emit_wasm()).unwrap();
+    walrus::passes::gc::run(&mut module);
+    let module = walrus_tests::config().parse(&module.emit_wasm()).unwrap();
+    let cont_ty = module.types.iter().find(|ty| ty.cont_of().is_some());
+    let cont_ref = ValType::Cont {
+        nullable: true,
+        ty: cont_ty.unwrap().id(),
+    };
+    assert!(module.types.iter().any(|ty| ty.params() == [cont_ref]));
+    let f = match module.exports.iter().find(|e| e.name == "f").unwrap().item {
+        walrus::ExportItem::Function(f) => f,
+        _ => unreachable!(),
+    };
+    let func = module.funcs.get(f).kind.unwrap_local();
+    let instrs = func.block(func.entry_block()).instrs.iter();
+    let instrs = instrs.map(|(instr, _)| instr).collect::<Vec<_>>();
+    let (k, block) = match instrs[..] {
+        [_, Instr::ContNew(_), Instr::LocalSet(set), Instr::Block(block), _] => {
+            (set.local, block.seq)
+        }
+        _ => panic!("unexpected instructions {:?}", instrs),
+    };
+    assert_eq!(func.block(block).ty, InstrSeqType::Simple(Some(cont_ref)));
+    assert_eq!(module.locals.get(k).ty(), cont_ref);
+}
+
+#[test]
+#[cfg(feature = "unstable")]
+fn resume_handlers() {
+    use walrus::ir::{Instr, ResumeHandler};
+    use walrus::ValType;
+
+    let mut module = Module::with_config(walrus_tests::config());
+    let func_ty = module.types.add(&[], &[]);
+    let cont_ty = module.types.add_cont(func_ty);
+    let tag = module.tags.add(func_ty);
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body();
+    let yielder = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("yielder", yielder);
+
+    // Suspending with `tag` branches to the block with the continuation.
+    let cont_ref = ValType::Cont {
+        nullable: false,
+        ty: cont_ty,
+    };
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder
+        .func_body()
+        .block(cont_ref, |block| {
+            let handlers = Box::new([ResumeHandler {
+                tag,
+                block: block.id(),
+            }]);
+            block
+                .ref_func(yielder)
+                .cont_new(cont_ty)
+                .resume(cont_ty, handlers)
+                .return_();
+        })
+        .drop();
+    let f = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("f", f);
+    walrus::passes::validate::run(&module).unwrap();
+
+    // The tag is only used by the handler, which keeps it alive.
+    walrus::passes::gc::run(&mut module);
+    assert_eq!(module.tags.iter().count(), 1);
+
+    let module = walrus_tests::config().parse(&module.emit_wasm()).unwrap();
+    let tag = module.tags.iter().next().unwrap().id();
+    let f = match module.exports.iter().find(|e| e.name == "f").unwrap().item {
+        walrus::ExportItem::Function(f) => f,
+        _ => unreachable!(),
+    };
+    let func = module.funcs.get(f).kind.unwrap_local();
+    let block = match func.block(func.entry_block()).instrs[0].0 {
+        Instr::Block(ref block) => block.seq,
+        _ => panic!("expected a block"),
+    };
+    match &func.block(block).instrs[2].0 {
+        Instr::Resume(resume) => assert_eq!(*resume.handlers, [ResumeHandler { tag, block }]),
+        instr => panic!("expected a resume, found {:?}", instr),
+    }
+}
+
+#[test]
+fn insert_preserving_stack() -> anyhow::Result<()> {
+    let config = walrus_tests::config();
+    let mut module = config.parse(&wat::parse_str(
+        r#"
+        (module
+          (import "env" "log" (func $log (param i32)))
+          (func $f (export "f") (result i32)
+            i32.const 10
+            block (result i32)
+              i32.const 1
+              i32.const 2
+              i32.add
+            end
+            i32.add))
+        "#,
+    )?)?;
+    let f = module.funcs.by_name("f").unwrap();
+    let log = module.funcs.by_name("log").unwrap();
+
+    // Log the right-hand side of the addition in the block.
+    let func = module.funcs.get(f).kind.unwrap_local();
+    let block = match &func.block(func.entry_block()).instrs[1].0 {
+        walrus::ir::Instr::Block(block) => block.seq,
+        _ => unreachable!(),
+    };
+    let types = walrus::passes::type_stack::run(&module, func);
+    let stack = types.seq_before(block, 2).unwrap().to_vec();
+    assert_eq!(stack, [walrus::ValType::I32, walrus::ValType::I32]);
+
+    let func = module.funcs.get_mut(f).kind.unwrap_local_mut();
+    func.builder_mut().instr_seq(block).insert_preserving_stack(
+        2,
+        &stack,
+        &mut module.locals,
+        |log_it, saved| {
+            log_it.local_get(saved[1]).call(log);
+        },
+    );
+
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    assert!(wat.contains(
+        "    i32.const 10
+    block (result i32)  ;; label = @1
+      i32.const 1
+      i32.const 2
+      local.set 1
+      local.set 0
+      local.get 1
+      call $log
+      local.get 0
+      local.get 1
+      i32.add
+    end
+    i32.add"
+    ));
+    Ok(())
+}
@@ -0,0 +1,58 @@
+//! Tests for reserving and filling slots in function tables.
+
+use walrus::{FunctionBuilder, Module};
+
+#[test]
+fn reserves_and_places_functions() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (table (export "table") 2 8 funcref)
+          (elem (i32.const 1) $a)
+          (func $a)
+          (func $b))
+        "#,
+    )?;
+    let config = walrus_tests::config();
+    let mut module = config.parse(&wasm)?;
+    let a = module.funcs.by_name("a").unwrap();
+    let b = module.funcs.by_name("b").unwrap();
+    let table = module.tables.main_function_table()?.unwrap();
+
+    let table = module.tables.get_mut(table);
+    assert_eq!(table.reserve_function_slots(3)?, 2);
+    assert_eq!(table.reserve_function_slots(2)?, 5);
+    assert_eq!(table.initial, 7);
+    assert!(table.reserve_function_slots(2).is_err());
+
+    assert_eq!(table.place_function(3, b)?, None);
+    assert_eq!(table.place_function(4, a)?, None);
+    assert_eq!(table.place_function(4, b)?, Some(a));
+    assert_eq!(table.place_function(6, a)?, None);
+    assert!(table.place_function(7, a).is_err());
+
+    let wasm = walrus::round_trip::check(&mut module)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    assert!(wat.contains("(table (;0;) 7 8 funcref)"));
+    assert!(wat.contains("(elem (;0;) (i32.const 1) $a)"));
+    assert!(wat.contains("(elem (;1;) (i32.const 3) $b $b)"));
+    assert!(wat.contains("(elem (;2;) (i32.const 6) $a)"));
+    Ok(())
+}
+
+#[test]
+fn rejects_anyref_tables() {
+    let mut module = Module::default();
+    let table = module.tables.add_local(
+        0,
+        None,
+        walrus::TableKind::Anyref(walrus::AnyrefTable::default()),
+    );
+    let builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    let f = builder.finish(vec![], &mut module.funcs);
+
+    let table = module.tables.get_mut(table);
+    assert!(table.reserve_function_slots(1).is_err());
+    table.initial = 1;
+    assert!(table.place_function(0, f).is_err());
+}
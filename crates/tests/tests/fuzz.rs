@@ -0,0 +1,92 @@
+//! Tests for generating and mutating random modules.
+
+#![cfg(feature = "fuzz")]
+
+use walrus::fuzz::{self, Arbitrary, Unstructured};
+use walrus::Module;
+
+/// Deterministic pseudo-random bytes standing in for fuzzer input.
+fn input(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+/// Emit `module` and parse it again, which validates it.
+fn round_trip(module: &mut Module) -> anyhow::Result<Module> {
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)
+}
+
+#[test]
+fn generated_modules_are_valid() -> anyhow::Result<()> {
+    for seed in 0..200 {
+        let data = input(seed, 1024);
+        let mut module = Module::arbitrary(&mut Unstructured::new(&data));
+        assert!(module.funcs.iter().count() >= 1);
+        round_trip(&mut module)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn generation_is_deterministic() {
+    let data = input(7, 1024);
+    let a = Module::arbitrary(&mut Unstructured::new(&data));
+    let b = Module::arbitrary(&mut Unstructured::new(&data));
+    assert_eq!(a.dump(), b.dump());
+}
+
+#[test]
+fn empty_input_generates_a_module() -> anyhow::Result<()> {
+    let mut module = Module::arbitrary(&mut Unstructured::new(&[]));
+    round_trip(&mut module)?;
+    Ok(())
+}
+
+#[test]
+fn mutated_modules_are_valid() -> anyhow::Result<()> {
+    for seed in 0..50 {
+        let data = input(seed, 4096);
+        let mut u = Unstructured::new(&data);
+        let mut module = Module::arbitrary(&mut u);
+        for _ in 0..20 {
+            fuzz::mutate(&mut module, &mut u);
+        }
+        round_trip(&mut module)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn mutate_existing_module() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+            (module
+              (global (mut i64) (i64.const 1))
+              (func (export "f") (param i32) (result i32)
+                local.get 0
+                i32.const 2
+                i32.add))
+        "#,
+    )?;
+    let original = Module::from_buffer(&wasm)?.dump();
+    let mut changed = 0;
+    for seed in 0..50 {
+        let mut module = Module::from_buffer(&wasm)?;
+        let data = input(seed, 64);
+        fuzz::mutate(&mut module, &mut Unstructured::new(&data));
+        let mut module = round_trip(&mut module)?;
+        if round_trip(&mut module)?.dump() != original {
+            changed += 1;
+        }
+    }
+    assert!(changed > 25);
+    Ok(())
+}
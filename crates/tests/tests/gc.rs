@@ -0,0 +1,64 @@
+//! Tests for garbage collection with custom roots.
+
+use walrus::passes::{gc, Roots};
+use walrus::Module;
+use walrus_tests::parse;
+
+const WAT: &str = r#"
+    (module
+      (func $helper (result i32)
+        i32.const 1)
+      (func $debug (export "debug") (result i32)
+        call $shared)
+      (func $shared (export "shared") (result i32)
+        i32.const 2)
+      (func $main (export "main") (result i32)
+        call $shared))
+"#;
+
+fn names(module: &Module) -> Vec<&str> {
+    module
+        .funcs
+        .iter()
+        .map(|f| f.name.as_deref().unwrap())
+        .collect()
+}
+
+#[test]
+fn extra_roots_are_kept() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    let helper = module.funcs.by_name("helper").unwrap();
+    gc::run_with_roots(&mut module, {
+        let mut roots = Roots::new();
+        roots.push_func(helper);
+        roots
+    });
+    assert_eq!(names(&module), ["helper", "debug", "shared", "main"]);
+
+    gc::run(&mut module);
+    assert_eq!(names(&module), ["debug", "shared", "main"]);
+    Ok(())
+}
+
+#[test]
+fn ignored_exports_are_removed() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    let mut roots = Roots::new();
+    for export in module.exports.iter() {
+        if export.name != "main" {
+            roots.ignore_export(export.id());
+        }
+    }
+    gc::run_with_roots(&mut module, roots);
+
+    // `shared` is still used by `main`, so its export is kept too.
+    assert_eq!(names(&module), ["shared", "main"]);
+    let exports = module
+        .exports
+        .iter()
+        .map(|e| &e.name[..])
+        .collect::<Vec<_>>();
+    assert_eq!(exports, ["shared", "main"]);
+    Module::from_buffer(&module.emit_wasm())?;
+    Ok(())
+}
@@ -0,0 +1,95 @@
+//! Tests for adding, exporting and merging globals.
+
+use walrus::ir::Value;
+use walrus::{ExportItem, GlobalKind, InitExpr, Module, ValType};
+
+#[test]
+fn imported_and_exported_globals() -> anyhow::Result<()> {
+    let mut module = Module::default();
+    let (imported, import) = module.add_import_global("env", "base", ValType::I32, false);
+    let (exported, export) = module.add_export_global(
+        "counter",
+        ValType::I64,
+        true,
+        InitExpr::Value(Value::I64(0)),
+    );
+    assert_eq!(module.imports.get(import).name, "base");
+    assert_eq!(module.exports.get(export).name, "counter");
+    assert_eq!(module.globals.by_name("counter"), Some(exported));
+    match module.exports.get(export).item {
+        ExportItem::Global(g) => assert_eq!(g, exported),
+        _ => panic!("expected a global export"),
+    }
+
+    module.add_export_global("relative", ValType::I32, false, InitExpr::Global(imported));
+
+    let module = Module::from_buffer(&module.emit_wasm())?;
+    assert_eq!(module.imports.iter().count(), 1);
+    let globals = module
+        .exports
+        .iter()
+        .map(|e| match e.item {
+            ExportItem::Global(g) => module.globals.get(g),
+            _ => panic!("expected a global export"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(globals.len(), 2);
+    assert!(globals[0].mutable);
+    assert_eq!(globals[0].ty, ValType::I64);
+    match globals[1].kind {
+        GlobalKind::Local(InitExpr::Global(g)) => {
+            assert!(matches!(module.globals.get(g).kind, GlobalKind::Import(_)));
+        }
+        _ => panic!("expected a global initialized by the imported global"),
+    }
+    Ok(())
+}
+
+#[test]
+fn dedup_merges_identical_globals() -> anyhow::Result<()> {
+    let wat = r#"
+        (module
+          (import "env" "base" (global $base i32))
+          (global $a i32 (i32.const 1))
+          (global $b i32 (i32.const 1))
+          (global $c i64 (i64.const 1))
+          (global $d (mut i32) (i32.const 1))
+          (global $e f32 (f32.const nan))
+          (global $f f32 (f32.const nan))
+          (global $g i32 (global.get $base))
+          (global $h i32 (global.get $base))
+          (export "b" (global $b))
+          (func (export "f") (result i32)
+            global.get $b
+            global.get $h
+            i32.add
+            global.get $d
+            i32.add
+            global.get $f
+            drop))
+    "#;
+    let mut module = Module::from_buffer(&wat::parse_str(wat)?)?;
+    assert_eq!(module.dedup_globals(), 3);
+    assert_eq!(module.dedup_globals(), 0);
+
+    let expected = r#"(module
+  (type (;0;) (func (result i32)))
+  (import "env" "base" (global (;0;) i32))
+  (func (;0;) (type 0) (result i32)
+    global.get 1
+    global.get 5
+    i32.add
+    global.get 3
+    i32.add
+    global.get 4
+    drop)
+  (global (;1;) i32 (i32.const 1))
+  (global (;2;) i64 (i64.const 1))
+  (global (;3;) (mut i32) (i32.const 1))
+  (global (;4;) f32 (f32.const nan (;=NaN;)))
+  (global (;5;) i32 (global.get 0))
+  (export "b" (global 1))
+  (export "f" (func 0)))"#;
+    assert_eq!(wasmprinter::print_bytes(module.emit_wasm())?, expected);
+    Ok(())
+}
@@ -0,0 +1,100 @@
+//! Tests for guarding the stack with canaries.
+
+use walrus::ir::Value;
+use walrus::passes::guard::{self, Options};
+use walrus::{ExportItem, GlobalId, GlobalKind, InitExpr, Module};
+
+fn module(stack_pointer: u32, heap_base: u32) -> anyhow::Result<Module> {
+    let wat = format!(
+        r#"
+        (module
+          (memory (export "memory") 1)
+          (global $__stack_pointer (mut i32) (i32.const {}))
+          (global $__heap_base (export "__heap_base") i32 (i32.const {}))
+          (data (i32.const 1024) "hello")
+          (func $main (export "main")))
+        "#,
+        stack_pointer, heap_base
+    );
+    walrus_tests::parse(&wat)
+}
+
+fn run(module: &mut Module, options: &Options) -> anyhow::Result<String> {
+    let memory = module.memories.iter().next().unwrap().id();
+    let sp = stack_pointer(module);
+    let check = guard::run(module, memory, sp, options)?;
+    module.exports.add("check", check);
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)?;
+    wasmprinter::print_bytes(&wasm)
+}
+
+// Global names aren't parsed, so find the globals by what they are instead.
+fn stack_pointer(module: &Module) -> GlobalId {
+    module.globals.iter().find(|g| g.mutable).unwrap().id()
+}
+
+fn heap_base(module: &Module) -> GlobalId {
+    match module
+        .exports
+        .iter()
+        .find(|e| e.name == "__heap_base")
+        .unwrap()
+        .item
+    {
+        ExportItem::Global(global) => global,
+        _ => unreachable!(),
+    }
+}
+
+fn initial(module: &Module, global: GlobalId) -> i32 {
+    match module.globals.get(global).kind {
+        GlobalKind::Local(InitExpr::Value(Value::I32(value))) => value,
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn guards_stack() -> anyhow::Result<()> {
+    let mut module = module(4096, 4096)?;
+    let wat = run(&mut module, &Options::default())?;
+    assert_eq!(initial(&module, stack_pointer(&module)), 5120);
+    assert_eq!(initial(&module, heap_base(&module)), 6144);
+
+    // The guards start right after the data, and right after the stack.
+    assert!(wat.contains("(data (;1;) (i32.const 1029) \"\\ef\\be\\ad\\de\\ce\\fa\\ed\\fe"));
+    assert!(wat.contains("(data (;2;) (i32.const 5120) \"\\ef\\be\\ad\\de\\ce\\fa\\ed\\fe"));
+    assert!(wat.contains("i64.load offset=1029 align=1"));
+    assert!(wat.contains("i64.load offset=5120 align=1"));
+    // Functions tick the countdown to the next check.
+    assert!(wat.contains("(func $main (type 0)\n    call $guard_tick)"));
+    assert!(wat.contains("i32.const 1024\n      global.set 2\n      call $guard_check"));
+    Ok(())
+}
+
+#[test]
+fn grows_memory() -> anyhow::Result<()> {
+    let mut module = module(65536, 65536)?;
+    let options = Options {
+        guard_size: 16,
+        interval: 0,
+    };
+    let wat = run(&mut module, &options)?;
+    assert!(wat.contains("(memory (;0;) 2)"));
+    assert!(!wat.contains("guard_tick"));
+    Ok(())
+}
+
+#[test]
+fn rejects_other_layouts() -> anyhow::Result<()> {
+    // The stack comes before the data.
+    assert!(run(&mut module(512, 4096)?, &Options::default()).is_err());
+    // The heap comes before the stack.
+    assert!(run(&mut module(4096, 2048)?, &Options::default()).is_err());
+    let options = Options {
+        guard_size: 12,
+        ..Options::default()
+    };
+    assert!(run(&mut module(4096, 4096)?, &options).is_err());
+    Ok(())
+}
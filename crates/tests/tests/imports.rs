@@ -0,0 +1,136 @@
+//! Tests for deduplicating, reordering and renaming imports.
+
+use walrus::Module;
+
+const WAT: &str = r#"
+    (module
+      (import "env" "f" (func $f1 (param i32)))
+      (import "wasi" "g" (global $g1 i32))
+      (import "env" "f" (func $f2 (param i32)))
+      (import "env" "f" (func $f3 (param i64)))
+      (import "wasi" "g" (global $g2 i32))
+      (import "env" "h" (func $h))
+      (global $copy i32 (global.get $g2))
+      (table 1 funcref)
+      (elem (i32.const 0) $f2)
+      (func $use (export "use")
+        global.get $g2
+        call $f2
+        i64.const 0
+        call $f3)
+      (export "f2" (func $f2))
+      (export "g2" (global $g2))
+      (start $h))
+"#;
+
+fn parse() -> anyhow::Result<Module> {
+    let mut config = walrus_tests::config();
+    config.generate_name_section(false);
+    config.parse(&wat::parse_str(WAT)?)
+}
+
+fn imports(wasm: &[u8]) -> anyhow::Result<Vec<String>> {
+    let module = Module::from_buffer(wasm)?;
+    Ok(module
+        .imports
+        .iter()
+        .map(|i| format!("{}.{}", i.module, i.name))
+        .collect())
+}
+
+#[test]
+fn dedup_merges_uses() -> anyhow::Result<()> {
+    let mut module = parse()?;
+    assert_eq!(module.dedup_imports(), 2);
+    assert_eq!(module.dedup_imports(), 0);
+
+    let wasm = module.emit_wasm();
+    let expected = r#"(module
+  (type (;0;) (func))
+  (type (;1;) (func (param i32)))
+  (type (;2;) (func (param i64)))
+  (import "env" "f" (func (;0;) (type 1)))
+  (import "wasi" "g" (global (;0;) i32))
+  (import "env" "f" (func (;1;) (type 2)))
+  (import "env" "h" (func (;2;) (type 0)))
+  (func (;3;) (type 0)
+    global.get 0
+    call 0
+    i64.const 0
+    call 1)
+  (table (;0;) 1 funcref)
+  (global (;1;) i32 (global.get 0))
+  (export "use" (func 3))
+  (export "f2" (func 0))
+  (export "g2" (global 0))
+  (start 2)
+  (elem (;0;) (i32.const 0) 0))"#;
+    assert_eq!(wasmprinter::print_bytes(&wasm)?, expected);
+    Ok(())
+}
+
+#[test]
+fn sort_groups_by_module() -> anyhow::Result<()> {
+    let mut module = parse()?;
+    module.imports.sort_by(|a, b| b.module.cmp(&a.module));
+    let wasm = module.emit_wasm();
+    assert_eq!(
+        imports(&wasm)?,
+        ["wasi.g", "wasi.g", "env.f", "env.f", "env.f", "env.h"]
+    );
+
+    // The uses of the imports follow them to their new indices.
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    assert!(wat.contains("global.get 1\n    call 1\n    i64.const 0\n    call 2)"));
+    assert!(wat.contains("(start 3)"));
+    Ok(())
+}
+
+#[test]
+fn explicit_emit_order() -> anyhow::Result<()> {
+    let mut module = parse()?;
+    assert!(module.imports.emit_order().is_empty());
+    let h = module.imports.find("env", "h").unwrap();
+    let g = module.imports.find("wasi", "g").unwrap();
+    module.imports.set_emit_order(vec![h, g, h]);
+    assert_eq!(
+        imports(&module.emit_wasm())?,
+        ["env.h", "wasi.g", "env.f", "env.f", "env.f", "wasi.g"]
+    );
+    Ok(())
+}
+
+#[test]
+fn remap_module() -> anyhow::Result<()> {
+    let mut module = parse()?;
+    assert_eq!(module.imports.remap_module("env", "host"), 4);
+    assert_eq!(module.imports.remap_module("env", "host"), 0);
+    assert_eq!(
+        imports(&module.emit_wasm())?,
+        ["host.f", "wasi.g", "host.f", "host.f", "wasi.g", "host.h"]
+    );
+    Ok(())
+}
+
+#[test]
+fn bulk_rename() -> anyhow::Result<()> {
+    let mut module = parse()?;
+    let renamed = module.imports.rename(|module, name| match (module, name) {
+        ("wasi", _) => Some(("wasi_snapshot_preview1".to_string(), name.to_uppercase())),
+        (_, "h") => Some((module.to_string(), "hook".to_string())),
+        _ => None,
+    });
+    assert_eq!(renamed, 3);
+    assert_eq!(
+        imports(&module.emit_wasm())?,
+        [
+            "env.f",
+            "wasi_snapshot_preview1.G",
+            "env.f",
+            "env.f",
+            "wasi_snapshot_preview1.G",
+            "env.hook"
+        ]
+    );
+    Ok(())
+}
@@ -0,0 +1,48 @@
+//! Tests for counting the instructions of functions by their class.
+
+use walrus::ir::{InstrClass, InstrStats};
+use walrus::Module;
+
+const WAT: &str = r#"
+    (module
+      (memory 1)
+      (global $g (mut i32) (i32.const 0))
+      (func $f (param i32) (result i32)
+        (block
+          (br_if 0 (local.get 0))
+          (global.set $g (i32.load (i32.const 0))))
+        (drop (call $f (i32.const 1)))
+        (i32.add (local.get 0) (global.get $g)))
+      (func $g
+        (loop
+          (br 0))))
+"#;
+
+#[test]
+fn counts_instructions_by_class() -> anyhow::Result<()> {
+    let module = Module::from_buffer(&wat::parse_str(WAT)?)?;
+    let f = module.funcs.by_name("f").unwrap();
+    let f = module.funcs.get(f).kind.unwrap_local();
+    let stats = f.instruction_stats();
+    assert_eq!(
+        stats.iter().collect::<Vec<_>>(),
+        [
+            (InstrClass::Control, 2),
+            (InstrClass::Call, 1),
+            (InstrClass::Parametric, 1),
+            (InstrClass::Variable, 4),
+            (InstrClass::Numeric, 3),
+            (InstrClass::Memory, 1),
+        ]
+    );
+    assert_eq!(stats.count(InstrClass::Table), 0);
+    assert_eq!(stats.total(), f.size());
+
+    let mut all = InstrStats::default();
+    for (_, func) in module.funcs.iter_local() {
+        all.merge(&func.instruction_stats());
+    }
+    assert_eq!(all.count(InstrClass::Control), 4);
+    assert_eq!(all.total(), stats.total() + 2);
+    Ok(())
+}
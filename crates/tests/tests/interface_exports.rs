@@ -0,0 +1,182 @@
+//! Tests for the interface-level signatures of exports, from `component-type`
+//! custom sections.
+
+use walrus::{ErrorKind, Module};
+use wasm_encoder::{
+    Alias, ComponentOuterAliasKind, ComponentType, ComponentTypeRef, ComponentTypeSection,
+    ComponentValType, CustomSection, Encode, InstanceType, PrimitiveValType, TypeBounds,
+};
+
+/// A `component-type` section for this world, encoded the way that
+/// `wit-component` encodes it:
+///
+/// ```wit
+/// package my:pkg;
+///
+/// interface types {
+///     record point { x: s32, y: s32 }
+///     resource blob {
+///         read: func(n: u32) -> list<u8>;
+///     }
+/// }
+///
+/// interface api {
+///     use types.{point};
+///     distance: func(a: point, b: point) -> f64;
+/// }
+///
+/// world app {
+///     export api;
+///     export types;
+///     export run: func() -> result<_, string>;
+/// }
+/// ```
+fn component_type() -> Vec<u8> {
+    let mut types = InstanceType::new();
+    types
+        .ty()
+        .defined_type()
+        .record([("x", PrimitiveValType::S32), ("y", PrimitiveValType::S32)]);
+    types.export("point", ComponentTypeRef::Type(TypeBounds::Eq(0)));
+    types.export("blob", ComponentTypeRef::Type(TypeBounds::SubResource));
+    types.ty().defined_type().borrow(2);
+    types.ty().defined_type().list(PrimitiveValType::U8);
+    types
+        .ty()
+        .function()
+        .params([
+            ("self", ComponentValType::Type(3)),
+            ("n", PrimitiveValType::U32.into()),
+        ])
+        .result(Some(ComponentValType::Type(4)));
+    types.export("[method]blob.read", ComponentTypeRef::Func(5));
+
+    let mut api = InstanceType::new();
+    api.alias(Alias::Outer {
+        kind: ComponentOuterAliasKind::Type,
+        count: 1,
+        index: 1,
+    });
+    api.export("point", ComponentTypeRef::Type(TypeBounds::Eq(0)));
+    api.ty()
+        .function()
+        .params([
+            ("a", ComponentValType::Type(1)),
+            ("b", ComponentValType::Type(1)),
+        ])
+        .result(Some(PrimitiveValType::F64.into()));
+    api.export("distance", ComponentTypeRef::Func(2));
+
+    let mut world = ComponentType::new();
+    world.ty().instance(&types);
+    world.import("my:pkg/types", ComponentTypeRef::Instance(0));
+    world.alias(Alias::InstanceExport {
+        instance: 0,
+        kind: wasm_encoder::ComponentExportKind::Type,
+        name: "point",
+    });
+    world.ty().instance(&api);
+    world.export("my:pkg/api", ComponentTypeRef::Instance(2));
+    world.export("my:pkg/types", ComponentTypeRef::Instance(0));
+    world
+        .ty()
+        .defined_type()
+        .result(None, Some(PrimitiveValType::String.into()));
+    world
+        .ty()
+        .function()
+        .params::<[(&str, ComponentValType); 0], _>([])
+        .result(Some(ComponentValType::Type(3)));
+    world.export("run", ComponentTypeRef::Func(4));
+
+    let mut package = ComponentType::new();
+    package.ty().component(&world);
+    package.export("my:pkg/app", ComponentTypeRef::Component(0));
+
+    let mut section = ComponentTypeSection::new();
+    section.component(&package);
+    let mut component = wasm_encoder::Component::new();
+    component.section(&CustomSection {
+        name: "wit-component-encoding".into(),
+        data: [4, 0][..].into(),
+    });
+    component.section(&section);
+    component.finish()
+}
+
+fn with_section(mut wasm: Vec<u8>, name: &str, data: &[u8]) -> Vec<u8> {
+    let mut contents = Vec::new();
+    name.encode(&mut contents);
+    contents.extend_from_slice(data);
+    wasm.push(0);
+    contents.len().encode(&mut wasm);
+    wasm.extend_from_slice(&contents);
+    wasm
+}
+
+#[test]
+fn reports_interface_exports() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (func (export "run") (result i32) (i32.const 0))
+          (func (export "my:pkg/api#distance") (param i32 i32 i32 i32) (result f64)
+            (f64.const 0))
+          (func (param i32 i32) (result i32) (i32.const 0)))
+        "#,
+    )?;
+    let wasm = with_section(wasm, "component-type:app", &component_type());
+    let module = Module::from_buffer(&wasm)?;
+
+    let exports = module.interface_exports()?;
+    let summary = exports
+        .iter()
+        .map(|e| {
+            assert_eq!(e.world, "my:pkg/app");
+            let export = e.export.map(|id| module.exports.get(id).name.clone());
+            (e.core_name(), e.func.to_string(), export)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            (
+                "my:pkg/api#distance".to_string(),
+                "func(a: point, b: point) -> f64".to_string(),
+                Some("my:pkg/api#distance".to_string()),
+            ),
+            (
+                "my:pkg/types#[method]blob.read".to_string(),
+                "func(self: borrow<blob>, n: u32) -> list<u8>".to_string(),
+                None,
+            ),
+            (
+                "run".to_string(),
+                "func() -> result<_, string>".to_string(),
+                Some("run".to_string()),
+            ),
+        ]
+    );
+    assert_eq!(exports[0].interface.as_deref(), Some("my:pkg/api"));
+    assert_eq!(exports[2].interface, None);
+    match &exports[0].func.params[0].1 {
+        walrus::InterfaceType::Named(name, ty) => {
+            assert_eq!(name, "point");
+            assert_eq!(ty.to_string(), "record { x: s32, y: s32 }");
+        }
+        other => panic!("unexpected type {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn modules_without_component_types() -> anyhow::Result<()> {
+    let module = Module::from_buffer(&wat::parse_str("(module)")?)?;
+    assert!(module.interface_exports()?.is_empty());
+
+    let wasm = with_section(wat::parse_str("(module)")?, "component-type", b"\0asm");
+    let err = Module::from_buffer(&wasm)?.interface_exports().unwrap_err();
+    assert_eq!(ErrorKind::of(&err), Some(ErrorKind::Parse));
+    assert!(err.to_string().contains("`component-type`"));
+    Ok(())
+}
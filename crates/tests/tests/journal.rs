@@ -0,0 +1,57 @@
+//! Tests for journaling the structural changes made to modules.
+
+use walrus::ir::Value;
+use walrus::passes::retained::Item;
+use walrus::{Change, FunctionBuilder, InitExpr, Module, ValType};
+
+const WAT: &str = r#"
+    (module
+      (global $g i32 (i32.const 0))
+      (func $unused)
+      (func $f (export "f")))
+"#;
+
+#[test]
+fn journals_changes() -> anyhow::Result<()> {
+    let mut module = Module::from_buffer(&wat::parse_str(WAT)?)?;
+    assert_eq!(module.changes(), None);
+    module.start_journal();
+    assert_eq!(module.changes(), Some(vec![]));
+
+    let unused = module.funcs.by_name("unused").unwrap();
+    let f = module.funcs.by_name("f").unwrap();
+    let g = module.globals.iter().next().unwrap().id();
+    walrus::passes::gc::run(&mut module);
+    module.funcs.get_mut(f).name = Some("main".to_string());
+    let h = module
+        .globals
+        .add_local(ValType::I64, false, InitExpr::Value(Value::I64(1)));
+    let export = module.exports.iter().next().unwrap().id();
+    module.exports.get_mut(export).name = "main".to_string();
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body();
+    let new = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("new", new);
+
+    assert_eq!(
+        module.changes(),
+        Some(vec![
+            Change::Removed(Item::Function(unused)),
+            Change::Removed(Item::Global(g)),
+            Change::Renamed {
+                item: Item::Function(f),
+                from: Some("f".to_string()),
+                to: Some("main".to_string()),
+            },
+            Change::Added(Item::Function(new)),
+            Change::Added(Item::Global(h)),
+            Change::Unexported("f".to_string()),
+            Change::Exported("main".to_string()),
+            Change::Exported("new".to_string()),
+        ])
+    );
+
+    assert!(module.stop_journal().is_some());
+    assert_eq!(module.changes(), None);
+    Ok(())
+}
@@ -0,0 +1,81 @@
+//! Tests for the names of labels in the `name` section.
+
+use walrus::ir::{Instr, InstrSeqId};
+use walrus::{FunctionBuilder, LocalFunction, Module, ValType};
+
+fn label_names(func: &LocalFunction, seq: InstrSeqId, names: &mut Vec<String>) {
+    let seq = func.block(seq);
+    names.extend(seq.name.clone());
+    for (instr, _) in &seq.instrs {
+        match instr {
+            Instr::Block(b) => label_names(func, b.seq, names),
+            Instr::Loop(l) => label_names(func, l.seq, names),
+            Instr::IfElse(i) => {
+                label_names(func, i.consequent, names);
+                label_names(func, i.alternative, names);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A function with a `block`, a `loop` and an `if`, along with a `name`
+/// section naming their labels `outer`, `again` and `check`.
+fn labelled() -> anyhow::Result<Vec<u8>> {
+    let mut wasm = wat::parse_str(
+        r#"
+        (module
+          (func (param i32)
+            (block
+              (loop
+                (br_if 1 (local.get 0))
+                (if (local.get 0)
+                  (then (br 1)))))))
+        "#,
+    )?;
+    let mut labels = vec![1, 0, 3];
+    for (index, name) in ["outer", "again", "check"].iter().enumerate() {
+        labels.extend_from_slice(&[index as u8, name.len() as u8]);
+        labels.extend_from_slice(name.as_bytes());
+    }
+    wasm.extend_from_slice(&[0x00, 7 + labels.len() as u8, 4]);
+    wasm.extend_from_slice(b"name");
+    wasm.extend_from_slice(&[3, labels.len() as u8]);
+    wasm.extend_from_slice(&labels);
+    Ok(wasm)
+}
+
+fn names(module: &Module) -> Vec<String> {
+    let local = module.funcs.iter().next().unwrap().kind.unwrap_local();
+    let mut names = Vec::new();
+    label_names(local, local.entry_block(), &mut names);
+    names
+}
+
+#[test]
+fn label_names_round_trip() -> anyhow::Result<()> {
+    let mut module = Module::from_buffer(&labelled()?)?;
+    assert_eq!(names(&module), ["outer", "again", "check"]);
+
+    let wasm = walrus::round_trip::check(&mut module)?;
+    assert_eq!(
+        names(&Module::from_buffer(&wasm)?),
+        ["outer", "again", "check"]
+    );
+    Ok(())
+}
+
+#[test]
+fn builder_names_labels() -> anyhow::Result<()> {
+    let mut module = Module::default();
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[ValType::I32]);
+    builder.func_body().block(ValType::I32, |block| {
+        block.name("result".to_string()).i32_const(1);
+    });
+    let f = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("f", f);
+
+    let wasm = module.emit_wasm();
+    assert_eq!(names(&Module::from_buffer(&wasm)?), ["result"]);
+    Ok(())
+}
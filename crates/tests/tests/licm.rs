@@ -0,0 +1,124 @@
+//! Tests for the loop-invariant code motion pass.
+
+fn licm(body: &str) -> anyhow::Result<String> {
+    let wat = format!(
+        r#"
+        (module
+          (global i32 (i32.const 8))
+          (global (mut i32) (i32.const 8))
+          (func (param i32) (result i32)
+            {}))
+        "#,
+        body
+    );
+    let mut module = walrus_tests::parse(wat)?;
+    walrus::passes::licm::run(&mut module);
+    let wasm = walrus_tests::emit(&mut module)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    let body = &wat[wat.find("(func (;0;)").unwrap()..];
+    let body = &body[body.find('\n').unwrap() + 1..body.find("\n  (global").unwrap()];
+    Ok(body
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.starts_with("(local"))
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+#[test]
+fn hoists_invariants_out_of_nested_loops() -> anyhow::Result<()> {
+    let body = licm(
+        "loop
+           loop
+             local.get 0
+             global.get 0
+             i32.const 4
+             i32.mul
+             i32.add
+             local.tee 0
+             br_if 1
+           end
+           local.get 0
+           global.get 0
+           i32.sub
+           local.set 0
+         end
+         local.get 0",
+    )?;
+    assert_eq!(
+        body,
+        "global.get 0 local.set 1 \
+         global.get 0 i32.const 4 i32.mul local.set 2 \
+         loop  ;; label = @1 \
+         loop  ;; label = @2 \
+         local.get 0 local.get 2 i32.add local.tee 0 br_if 1 (;@1;) \
+         end \
+         local.get 0 local.get 1 i32.sub local.set 0 \
+         end \
+         local.get 0)"
+    );
+    Ok(())
+}
+
+#[test]
+fn leaves_variants_and_lone_constants() -> anyhow::Result<()> {
+    let src = "loop
+                 local.get 0
+                 i32.const 1
+                 i32.add
+                 global.get 1
+                 i32.add
+                 local.tee 0
+                 br_if 0
+               end
+               local.get 0";
+    let body = licm(src)?;
+    assert!(body.starts_with("loop"));
+    assert!(!body.contains("local.set"));
+    Ok(())
+}
+
+#[test]
+fn reuses_hoisted_locals() -> anyhow::Result<()> {
+    let body = licm(
+        "loop
+           global.get 0
+           local.get 0
+           i32.add
+           global.get 0
+           i32.add
+           local.tee 0
+           br_if 0
+         end
+         local.get 0",
+    )?;
+    assert!(body.starts_with("global.get 0 local.set 1 loop"));
+    assert_eq!(body.matches("global.get").count(), 1);
+    Ok(())
+}
+
+#[test]
+fn does_not_hoist_across_statements() -> anyhow::Result<()> {
+    // Both `global.get 0`s are invariant, but the `local.set` in between
+    // isn't, so their sum can't be hoisted.
+    let body = licm(
+        "loop
+           global.get 0
+           local.get 0
+           local.set 0
+           global.get 0
+           i32.add
+           drop
+         end
+         local.get 0",
+    )?;
+    assert_eq!(
+        body,
+        "global.get 0 local.set 1 \
+         loop  ;; label = @1 \
+         local.get 1 local.get 0 local.set 0 local.get 1 i32.add drop \
+         end \
+         local.get 0)"
+    );
+    Ok(())
+}
@@ -0,0 +1,65 @@
+//! Tests for the limits on the resources that parsing takes.
+
+use walrus::ModuleConfig;
+
+const WAT: &str = r#"
+    (module
+      (import "env" "f" (func))
+      (func (result i32)
+        (i32.add (i32.const 1) (i32.const 2)))
+      (func))
+"#;
+
+#[test]
+fn limits_functions() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(WAT)?;
+    ModuleConfig::new().max_functions(3).parse(&wasm)?;
+    let err = ModuleConfig::new()
+        .max_functions(2)
+        .parse(&wasm)
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("more than the maximum of 2 functions"));
+    let err = ModuleConfig::new()
+        .max_functions(0)
+        .parse(&wasm)
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("more than the maximum of 0 functions"));
+    Ok(())
+}
+
+#[test]
+fn limits_function_body_sizes() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(WAT)?;
+    // No locals, two `i32.const`s, `i32.add` and `end`.
+    ModuleConfig::new().max_function_body_size(7).parse(&wasm)?;
+    let err = ModuleConfig::new()
+        .max_function_body_size(6)
+        .parse(&wasm)
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("function 1 has a body of 7 bytes"));
+    Ok(())
+}
+
+#[test]
+fn limits_ir_memory() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(WAT)?;
+    ModuleConfig::new().max_ir_memory(1 << 16).parse(&wasm)?;
+    let err = ModuleConfig::new()
+        .max_ir_memory(64)
+        .parse(&wasm)
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("more than the maximum of 64 bytes of IR"));
+
+    // A function declaring four billion locals in a few bytes.
+    let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+    wasm.extend_from_slice(&[0x01, 4, 1, 0x60, 0, 0]);
+    wasm.extend_from_slice(&[0x03, 2, 1, 0]);
+    wasm.extend_from_slice(&[0x0a, 10, 1, 8, 1, 0x80, 0xd0, 0xac, 0xf3, 0x0e, 0x7f, 0x0b]);
+    let err = ModuleConfig::new()
+        .strict_validate(false)
+        .max_ir_memory(1 << 20)
+        .parse(&wasm)
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("bytes of IR"));
+    Ok(())
+}
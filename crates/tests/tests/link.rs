@@ -0,0 +1,147 @@
+//! Tests for resolving imports against, and linking with, another module.
+
+use walrus::ir::{Call, Instr};
+use walrus::{ExportItem, ImportKind, Module};
+
+fn provider() -> anyhow::Result<Module> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (import "env" "log" (func $log (param i32)))
+          (memory (export "memory") 1 2)
+          (global $counter (export "counter") (mut i32) (i32.const 0))
+          (func $bump (export "bump") (param i32) (result i32)
+            (local $old i32)
+            (local.set $old (global.get $counter))
+            (global.set $counter (i32.add (local.get $old) (local.get 0)))
+            (call $log (local.get $old))
+            (local.get $old))
+          (func $init
+            (i32.store (i32.const 0) (i32.const 7)))
+          (data (i32.const 16) "hi")
+          (start $init))
+        "#,
+    )?;
+    Module::from_buffer(&wasm)
+}
+
+fn user() -> anyhow::Result<Module> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (import "lib" "bump" (func $bump (param i32) (result i32)))
+          (import "lib" "counter" (global $counter (mut i32)))
+          (import "lib" "memory" (memory 1))
+          (import "other" "f" (func $f))
+          (func (export "run") (result i32)
+            (call $f)
+            (drop (call $bump (i32.const 2)))
+            (i32.add (global.get $counter) (i32.load (i32.const 0)))))
+        "#,
+    )?;
+    Module::from_buffer(&wasm)
+}
+
+#[test]
+fn resolves_imports_to_exports() -> anyhow::Result<()> {
+    let provider = provider()?;
+    let user = user()?;
+    let resolution = user.resolve_imports("lib", &provider)?;
+    assert_eq!(resolution.iter().count(), 3);
+    for import in user.imports.iter() {
+        let export = resolution.get(import.id());
+        if import.module != "lib" {
+            assert!(export.is_none());
+            continue;
+        }
+        let export = provider.exports.get(export.unwrap());
+        assert_eq!(export.name, import.name);
+        match (&import.kind, export.item) {
+            (ImportKind::Function(_), ExportItem::Function(_))
+            | (ImportKind::Global(_), ExportItem::Global(_))
+            | (ImportKind::Memory(_), ExportItem::Memory(_)) => {}
+            _ => panic!("`{}` resolved to the wrong kind of export", import.name),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn rejects_mismatched_imports() -> anyhow::Result<()> {
+    let provider = provider()?;
+    let wasm = wat::parse_str(r#"(module (import "lib" "bump" (func (param i64))))"#)?;
+    let err = Module::from_buffer(&wasm)?
+        .resolve_imports("lib", &provider)
+        .unwrap_err();
+    assert!(format!("{:?}", err).contains("failed to resolve import `lib`.`bump`"));
+
+    let wasm = wat::parse_str(r#"(module (import "lib" "memory" (memory 1 1)))"#)?;
+    let err = Module::from_buffer(&wasm)?
+        .resolve_imports("lib", &provider)
+        .unwrap_err();
+    assert!(format!("{:?}", err).contains("larger than imported"));
+
+    let wasm = wat::parse_str(r#"(module (import "lib" "missing" (func)))"#)?;
+    assert!(Module::from_buffer(&wasm)?
+        .resolve_imports("lib", &provider)
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn links_modules() -> anyhow::Result<()> {
+    let provider = provider()?;
+    let mut user = user()?;
+    user.link("lib", &provider)?;
+
+    let mut imports = user
+        .imports
+        .iter()
+        .map(|i| format!("{}.{}", i.module, i.name))
+        .collect::<Vec<_>>();
+    imports.sort();
+    assert_eq!(imports, ["env.log", "other.f"]);
+    assert_eq!(user.memories.iter().count(), 1);
+    assert_eq!(user.globals.iter().count(), 1);
+    assert_eq!(user.data.iter().count(), 1);
+    assert!(user.start.is_some());
+
+    let wasm = walrus::round_trip::check(&mut user)?;
+    let printed = wasmprinter::print_bytes(&wasm)?;
+    assert!(printed.contains("(start "));
+    assert!(printed.contains(r#"(export "run" "#));
+    assert!(!printed.contains(r#"(export "bump" "#));
+    Ok(())
+}
+
+#[test]
+fn links_start_functions_in_order() -> anyhow::Result<()> {
+    let provider = provider()?;
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (import "lib" "memory" (memory 1))
+          (func $start
+            (i32.store (i32.const 4) (i32.const 8)))
+          (start $start))
+        "#,
+    )?;
+    let mut user = Module::from_buffer(&wasm)?;
+    user.link("lib", &provider)?;
+
+    let start = user.funcs.get(user.start.unwrap()).kind.unwrap_local();
+    let called = start
+        .block(start.entry_block())
+        .instrs
+        .iter()
+        .map(|(instr, _)| match instr {
+            Instr::Call(Call { func }) => user.funcs.get(*func),
+            _ => panic!("unexpected instruction in the start function"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(called.len(), 2);
+    assert_eq!(called[0].name.as_deref(), Some("init"));
+    assert_eq!(called[1].name.as_deref(), Some("start"));
+    walrus::round_trip::check(&mut user)?;
+    Ok(())
+}
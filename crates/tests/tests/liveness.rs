@@ -0,0 +1,73 @@
+//! Tests for finding the live locals of functions.
+
+use std::collections::HashSet;
+use walrus::passes::liveness;
+use walrus::{LocalId, Module};
+
+const WAT: &str = r#"
+    (module
+      (func $sum (export "sum") (param $a i32) (param $b i32) (result i32)
+        (local $i i32) (local $acc i32)
+        (local.set $i (local.get $a))
+        (loop $l
+          (local.set $acc (i32.add (local.get $acc) (local.get $i)))
+          (br_if $l (local.tee $i (i32.sub (local.get $i) (i32.const 1)))))
+        (local.get $acc))
+      (func $skip (export "skip") (param $x i32)
+        (local $y i32)
+        (block $b
+          (br_if $b (local.get $x))
+          (local.set $y (i32.const 1)))
+        (drop (local.get $y))))
+"#;
+
+fn locals(module: &Module, names: &[&str]) -> HashSet<LocalId> {
+    names
+        .iter()
+        .map(|name| {
+            module
+                .locals
+                .iter()
+                .find(|l| l.name.as_deref() == Some(*name))
+                .unwrap()
+                .id()
+        })
+        .collect()
+}
+
+#[test]
+fn loops() -> anyhow::Result<()> {
+    let module = Module::from_buffer(&wat::parse_str(WAT)?)?;
+    let func = module.funcs.by_name("sum").unwrap();
+    let func = module.funcs.get(func).kind.unwrap_local();
+    let live = liveness::run(func);
+    let entry = func.entry_block();
+
+    // `$acc` is read before it's written, and `$b` is never read.
+    assert_eq!(live.live_at_entry(), &locals(&module, &["a", "acc"]));
+    assert_eq!(live.live_before(entry, 1), &locals(&module, &["acc"]));
+    // Both are read again in the next iteration of the loop.
+    assert_eq!(live.live_before(entry, 2), &locals(&module, &["i", "acc"]));
+    assert_eq!(live.live_after(entry, 2), &locals(&module, &["acc"]));
+    assert_eq!(live.live_after(entry, 3), &locals(&module, &[]));
+    Ok(())
+}
+
+#[test]
+fn branches() -> anyhow::Result<()> {
+    let module = Module::from_buffer(&wat::parse_str(WAT)?)?;
+    let func = module.funcs.by_name("skip").unwrap();
+    let func = module.funcs.get(func).kind.unwrap_local();
+    let live = liveness::run(func);
+    let block = match func.block(func.entry_block()).instrs[0].0 {
+        walrus::ir::Instr::Block(walrus::ir::Block { seq }) => seq,
+        _ => unreachable!(),
+    };
+
+    // `$y` is live until it is written, since the `br_if` skips the write.
+    assert_eq!(live.live_at_entry(), &locals(&module, &["x", "y"]));
+    assert_eq!(live.live_before(block, 1), &locals(&module, &["y"]));
+    assert_eq!(live.live_before(block, 2), &locals(&module, &[]));
+    assert_eq!(live.live_after(block, 3), &locals(&module, &["y"]));
+    Ok(())
+}
@@ -0,0 +1,108 @@
+//! Tests for lowering bulk memory instructions to MVP code.
+
+use walrus::{ActiveDataLocation, DataKind, Module, WasmFeatures};
+
+fn lower(wat: &str) -> anyhow::Result<(Module, String)> {
+    let mut module = walrus_tests::parse(wat)?;
+    walrus::passes::lower_bulk_memory::run(&mut module)?;
+
+    let features = WasmFeatures {
+        bulk_memory: false,
+        ..WasmFeatures::all()
+    };
+    module.assert_compatible(features)?;
+
+    let wasm = module.emit_wasm();
+    let module = Module::from_buffer(&wasm)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    Ok((module, wat))
+}
+
+#[test]
+fn lowers_copy_and_fill() -> anyhow::Result<()> {
+    let (_, wat) = lower(
+        r#"
+        (module
+          (memory 1)
+          (func (export "copy") (param i32 i32 i32)
+            local.get 0
+            local.get 1
+            local.get 2
+            memory.copy)
+          (func (export "fill") (param i32 i32 i32)
+            local.get 0
+            local.get 1
+            local.get 2
+            memory.fill
+            local.get 0
+            local.get 1
+            local.get 2
+            memory.fill))
+        "#,
+    )?;
+    assert!(wat.contains("local.get 2\n    call $memory.copy)"));
+    assert!(wat.contains("local.get 2\n    call $memory.fill\n"));
+    // Both fills share a single helper.
+    assert_eq!(wat.matches("(func $memory.fill").count(), 1);
+    // Bounds are checked against the size of the memory before copying.
+    assert!(wat.contains("memory.size\n    i64.extend_i32_u\n    i64.const 16\n    i64.shl"));
+    assert!(wat.contains("i32.load8_u"));
+    assert!(wat.contains("i32.store8"));
+    assert!(!wat.contains("data count"));
+    Ok(())
+}
+
+#[test]
+fn makes_passive_segments_active() -> anyhow::Result<()> {
+    let (module, wat) = lower(
+        r#"
+        (module
+          (memory 1 3)
+          (data (i32.const 0) "active")
+          (data passive "hello")
+          (func (export "init") (param i32)
+            local.get 0
+            i32.const 1
+            i32.const 4
+            memory.init 1
+            data.drop 1))
+        "#,
+    )?;
+
+    let memory = module.memories.iter().next().unwrap();
+    assert_eq!(memory.initial, 2);
+    assert_eq!(memory.maximum, Some(3));
+    let data = module.data.iter().collect::<Vec<_>>();
+    assert_eq!(data.len(), 2);
+    match &data[1].kind {
+        DataKind::Active(a) => assert_eq!(a.location, ActiveDataLocation::Absolute(65536)),
+        DataKind::Passive => panic!("expected an active segment"),
+    }
+    assert_eq!(data[1].value, b"hello");
+
+    // The remaining length of the segment starts out as its size, is checked
+    // on every copy, and is cleared by `data.drop`.
+    assert!(wat.contains("(global (;0;) (mut i32) (i32.const 5))"));
+    assert!(wat.contains("global.get 0\n    i64.extend_i32_u\n    i64.gt_u"));
+    assert!(wat.contains("i32.const 65536\n    i32.add"));
+    assert!(wat.contains("i32.const 0\n    global.set 0"));
+    assert!(wat.contains("call $memory.init:1\n    call $data.drop:1)"));
+    Ok(())
+}
+
+#[test]
+fn needs_room_for_passive_segments() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (memory 1 1)
+          (data passive "hello"))
+        "#,
+    )?;
+    let mut module = Module::from_buffer(&wasm)?;
+    assert!(walrus::passes::lower_bulk_memory::run(&mut module).is_err());
+    // Nothing was changed.
+    assert!(module.data.iter().next().unwrap().is_passive());
+    assert_eq!(module.memories.iter().next().unwrap().initial, 1);
+    Ok(())
+}
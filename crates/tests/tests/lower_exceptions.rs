@@ -0,0 +1,203 @@
+//! Tests for lowering exception handling to calls to an imported abort.
+
+use std::collections::HashSet;
+use walrus::passes::lower_exceptions::{self, Options};
+use walrus::{FunctionId, ImportKind, Module};
+use wasm_encoder::{
+    BlockType, Catch, CodeSection, CustomSection, EntityType, ExportKind, ExportSection, Function,
+    FunctionSection, ImportSection, TagKind, TagSection, TagType, TypeSection, ValType,
+};
+
+/// A module with an imported `log` function, an exported `run` function that
+/// throws from within `try`s, and a `helper` function that throws from within
+/// a `try_table`, or doesn't throw at all without `throws`.
+fn exceptions(throws: bool) -> Vec<u8> {
+    let mut types = TypeSection::new();
+    types.ty().function([], []);
+    types.ty().function([ValType::I32], []);
+
+    let mut imports = ImportSection::new();
+    imports.import("env", "log", EntityType::Function(0));
+
+    let mut funcs = FunctionSection::new();
+    funcs.function(0);
+    funcs.function(0);
+
+    let mut tags = TagSection::new();
+    tags.tag(TagType {
+        kind: TagKind::Exception,
+        func_type_idx: 1,
+    });
+
+    let mut exports = ExportSection::new();
+    exports.export("run", ExportKind::Func, 1);
+    exports.export("error", ExportKind::Tag, 0);
+
+    let mut run = Function::new([]);
+    let mut body = run.instructions();
+    body.try_(BlockType::Empty).call(2);
+    if throws {
+        body.i32_const(1).throw(0);
+    }
+    body.catch(0)
+        .drop()
+        .block(BlockType::Empty)
+        .call(0)
+        .end()
+        .catch_all()
+        .call(0)
+        .end();
+    body.try_(BlockType::Empty).call(2).delegate(0);
+    body.end();
+
+    let mut helper = Function::new([]);
+    let mut body = helper.instructions();
+    body.block(BlockType::Empty)
+        .try_table(BlockType::Empty, [Catch::All { label: 0 }]);
+    if throws {
+        body.i32_const(2).throw(0);
+    }
+    body.end().end().end();
+
+    let mut code = CodeSection::new();
+    code.function(&run);
+    code.function(&helper);
+
+    let mut module = wasm_encoder::Module::new();
+    module
+        .section(&types)
+        .section(&imports)
+        .section(&funcs)
+        .section(&tags)
+        .section(&exports)
+        .section(&code)
+        .section(&CustomSection {
+            name: "extra".into(),
+            data: b"kept".as_ref().into(),
+        });
+    module.finish()
+}
+
+/// The functions that `func` calls.
+fn callees(module: &Module, func: FunctionId) -> HashSet<FunctionId> {
+    #[derive(Default)]
+    struct Callees(HashSet<FunctionId>);
+
+    impl<'instr> walrus::ir::Visitor<'instr> for Callees {
+        fn visit_call(&mut self, call: &walrus::ir::Call) {
+            self.0.insert(call.func);
+        }
+    }
+
+    let local = module.funcs.get(func).kind.unwrap_local();
+    let mut callees = Callees::default();
+    walrus::ir::dfs_in_order(&mut callees, local, local.entry_block());
+    callees.0
+}
+
+fn imported_func(module: &Module, name: &str) -> Option<FunctionId> {
+    let import = module.imports.find("env", name)?;
+    match module.imports.get(import).kind {
+        ImportKind::Function(func) => Some(func),
+        _ => None,
+    }
+}
+
+#[test]
+fn throws_become_aborts() {
+    let wasm = lower_exceptions::run(&exceptions(true), &Options::default()).unwrap();
+
+    // walrus can't parse exceptions, so this only works if all of them are
+    // lowered, including the tag section.
+    let module = Module::from_buffer(&wasm).unwrap();
+    let log = imported_func(&module, "log").unwrap();
+    let abort = imported_func(&module, "abort").unwrap();
+    assert_eq!(module.exports.iter().count(), 1);
+    assert!(module
+        .customs
+        .iter()
+        .any(|(_, custom)| custom.name() == "extra"));
+
+    // The handlers are gone, and the calls to the functions after the new
+    // import still call them.
+    let run = match module.exports.iter().next().unwrap().item {
+        walrus::ExportItem::Function(func) => func,
+        _ => panic!("`run` should be a function"),
+    };
+    let helper = module
+        .funcs
+        .iter_local()
+        .map(|(id, _)| id)
+        .find(|id| *id != run)
+        .unwrap();
+    let expected = [helper, abort].iter().copied().collect();
+    assert_eq!(callees(&module, run), expected);
+    assert!(!callees(&module, run).contains(&log));
+    assert_eq!(callees(&module, helper), [abort].iter().copied().collect());
+}
+
+#[test]
+fn abort_is_only_imported_if_needed() {
+    let options = Options {
+        abort_module: "env".to_string(),
+        abort_name: "panic".to_string(),
+    };
+    let wasm = lower_exceptions::run(&exceptions(false), &options).unwrap();
+    let module = Module::from_buffer(&wasm).unwrap();
+    assert_eq!(module.imports.iter().count(), 1);
+    assert!(imported_func(&module, "panic").is_none());
+
+    let wasm = lower_exceptions::run(&exceptions(true), &options).unwrap();
+    let module = Module::from_buffer(&wasm).unwrap();
+    assert_eq!(module.imports.iter().count(), 2);
+    assert!(imported_func(&module, "panic").is_some());
+}
+
+#[test]
+fn adds_an_import_section() {
+    let mut types = TypeSection::new();
+    types.ty().function([], []);
+    let mut funcs = FunctionSection::new();
+    funcs.function(0);
+    funcs.function(0);
+    let mut tags = TagSection::new();
+    tags.tag(TagType {
+        kind: TagKind::Exception,
+        func_type_idx: 0,
+    });
+    let mut code = CodeSection::new();
+    let mut throw = Function::new([]);
+    throw.instructions().throw(0).end();
+    code.function(&throw);
+    let mut call = Function::new([]);
+    call.instructions().call(0).end();
+    code.function(&call);
+    let mut module = wasm_encoder::Module::new();
+    module
+        .section(&types)
+        .section(&funcs)
+        .section(&tags)
+        .section(&code);
+
+    let wasm = lower_exceptions::run(&module.finish(), &Options::default()).unwrap();
+    let module = Module::from_buffer(&wasm).unwrap();
+    let abort = imported_func(&module, "abort").unwrap();
+    let funcs = module
+        .funcs
+        .iter_local()
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        callees(&module, funcs[0]),
+        [abort].iter().copied().collect()
+    );
+    assert_eq!(
+        callees(&module, funcs[1]),
+        [funcs[0]].iter().copied().collect()
+    );
+}
+
+#[test]
+fn rejects_invalid_binaries() {
+    assert!(lower_exceptions::run(b"\0asm\x01\0\0\0\x01", &Options::default()).is_err());
+}
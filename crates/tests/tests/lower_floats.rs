@@ -0,0 +1,141 @@
+//! Tests for lowering float instructions to calls to softfloat
+//! implementations.
+
+use walrus::passes::lower_floats::{self, Options};
+use walrus::{ImportKind, Module, ValType};
+use walrus_tests::parse;
+
+const WAT: &str = r#"
+    (module
+      (import "softfloat" "f32.add" (func $add (param f32 f32) (result f32)))
+      (memory 1)
+      (func $my_mul (param f64 f64) (result f64)
+        (f64.mul (local.get 0) (local.get 1)))
+      (func (export "run") (param f32 f64) (result i32)
+        (f32.store (i32.const 0) (f32.neg (f32.add (local.get 0) (f32.const 1))))
+        (f64.mul (f64.promote_f32 (f32.load (i32.const 0))) (local.get 1))
+        (i32.trunc_f64_s)
+        (i32.reinterpret_f32 (local.get 0))
+        (i32.add)
+        (f64.lt (local.get 1) (local.get 1))
+        (i32.add)))
+"#;
+
+fn print(module: &mut Module) -> anyhow::Result<String> {
+    let wasm = walrus_tests::emit(module)?;
+    wasmprinter::print_bytes(&wasm)
+}
+
+fn run_body(wat: &str) -> &str {
+    &wat[wat.find("(func (;").unwrap()..wat.find("(export").unwrap()]
+}
+
+#[test]
+fn lowers_to_imports() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    lower_floats::run(&mut module, &Options::default())?;
+
+    let imports = module
+        .imports
+        .iter()
+        .map(|import| {
+            let func = match import.kind {
+                ImportKind::Function(func) => func,
+                _ => unreachable!(),
+            };
+            let ty = module.types.get(module.funcs.get(func).ty());
+            assert_eq!(import.module, "softfloat");
+            assert_eq!(
+                lower_floats::signature(&import.name),
+                Some((ty.params().to_vec(), ty.results()[0]))
+            );
+            import.name.as_str()
+        })
+        .collect::<Vec<_>>();
+    // The existing import of `f32.add` is reused.
+    assert_eq!(
+        imports,
+        [
+            "f32.add",
+            "f64.mul",
+            "f32.neg",
+            "f64.promote_f32",
+            "i32.trunc_f64_s",
+            "f64.lt",
+        ]
+    );
+
+    let wat = print(&mut module)?;
+    let body = run_body(&wat);
+    for op in [
+        "f32.add",
+        "f32.neg",
+        "f64.mul",
+        "f64.promote",
+        "trunc",
+        "f64.lt",
+    ]
+    .iter()
+    {
+        assert!(!body.contains(&format!("    {}", op)), "{}", op);
+    }
+    assert!(body.contains("f32.const 0x1p+0 (;=1;)\n    call $add\n    call 2\n    f32.store"));
+    assert!(body.contains("f32.load\n    call 3\n"));
+    assert!(body.contains("i32.reinterpret_f32\n"));
+    Ok(())
+}
+
+#[test]
+fn lowers_to_implementations() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    let my_mul = module.funcs.by_name("my_mul").unwrap();
+    let mut options = Options {
+        import_module: "env".to_string(),
+        ..Options::default()
+    };
+    options.functions.insert("f64.mul".to_string(), my_mul);
+    lower_floats::run(&mut module, &options)?;
+
+    assert!(module.imports.find("env", "f64.mul").is_none());
+    assert!(module.imports.find("env", "f32.neg").is_some());
+    // The imported `f32.add` isn't in the right module, so it isn't reused.
+    assert!(module.imports.find("env", "f32.add").is_some());
+
+    let wat = print(&mut module)?;
+    assert!(run_body(&wat).contains("local.get 1\n    call $my_mul\n"));
+    // The implementation is left alone.
+    assert!(wat
+        .contains("(param f64 f64) (result f64)\n    local.get 0\n    local.get 1\n    f64.mul)"));
+    Ok(())
+}
+
+#[test]
+fn checks_implementations() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    let my_mul = module.funcs.by_name("my_mul").unwrap();
+    let before = module.emit_wasm();
+
+    let mut options = Options::default();
+    options.functions.insert("f32.mul".to_string(), my_mul);
+    let err = lower_floats::run(&mut module, &options).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "the implementation of `f32.mul` must take an `f32` and an `f32` and return an `f32`"
+    );
+
+    let mut options = Options::default();
+    options.functions.insert("i32.add".to_string(), my_mul);
+    let err = lower_floats::run(&mut module, &options).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "`i32.add` isn't a float instruction that can be lowered"
+    );
+    assert_eq!(module.emit_wasm(), before);
+
+    assert_eq!(
+        lower_floats::signature("f32.convert_i64_u"),
+        Some((vec![ValType::I64], ValType::F32))
+    );
+    assert_eq!(lower_floats::signature("f64.reinterpret_i64"), None);
+    Ok(())
+}
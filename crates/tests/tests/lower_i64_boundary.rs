@@ -0,0 +1,99 @@
+//! Tests for lowering `i64`s at a module's boundary.
+
+use walrus::passes::lower_i64_boundary::{self, I64Repr};
+use walrus::{ExportItem, Module};
+
+const WAT: &str = r#"
+    (module
+      (import "env" "now" (func $now (param i32) (result i64)))
+      (func $add (export "add") (param i64 i32) (result i64)
+        (i64.add (local.get 0) (call $now (local.get 1))))
+      (func (export "plain") (param i32) (result i32)
+        (local.get 0)))
+"#;
+
+fn lower(repr: I64Repr) -> anyhow::Result<(Module, String)> {
+    let mut module = walrus_tests::parse(WAT)?;
+    let wrappers = lower_i64_boundary::run(&mut module, repr);
+    assert_eq!(wrappers.len(), 2);
+
+    let wasm = walrus::round_trip::check(&mut module)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    Ok((module, wat))
+}
+
+#[test]
+fn lowers_to_i32_pairs() -> anyhow::Result<()> {
+    let (_, wat) = lower(I64Repr::I32Pair)?;
+    assert_eq!(
+        wat,
+        r#"(module
+  (type (;0;) (func (param i32) (result i32)))
+  (type (;1;) (func (param i32) (result i32 i32)))
+  (type (;2;) (func (param i32) (result i64)))
+  (type (;3;) (func (param i32 i32 i32) (result i32 i32)))
+  (type (;4;) (func (param i64 i32) (result i64)))
+  (import "env" "now" (func $now (type 1)))
+  (func $legalstub$add (type 3) (param i32 i32 i32) (result i32 i32)
+    (local i64)
+    local.get 0
+    i64.extend_i32_u
+    local.get 1
+    i64.extend_i32_u
+    i64.const 32
+    i64.shl
+    i64.or
+    local.get 2
+    call $add
+    local.set 3
+    local.get 3
+    i32.wrap_i64
+    local.get 3
+    i64.const 32
+    i64.shr_u
+    i32.wrap_i64)
+  (func $legalfunc$now (type 2) (param i32) (result i64)
+    (local i32 i32)
+    local.get 0
+    call $now
+    local.set 2
+    local.set 1
+    local.get 1
+    i64.extend_i32_u
+    local.get 2
+    i64.extend_i32_u
+    i64.const 32
+    i64.shl
+    i64.or)
+  (func $add (type 4) (param i64 i32) (result i64)
+    local.get 0
+    local.get 1
+    call $legalfunc$now
+    i64.add)
+  (func (;4;) (type 0) (param i32) (result i32)
+    local.get 0)
+  (export "add" (func $legalstub$add))
+  (export "plain" (func 4)))"#
+    );
+    Ok(())
+}
+
+#[test]
+fn lowers_to_f64s() -> anyhow::Result<()> {
+    let (module, wat) = lower(I64Repr::F64)?;
+    assert!(wat.contains(r#"(import "env" "now" (func $now (type "#));
+    assert!(wat.contains("(param i32) (result f64)"));
+    assert!(wat.contains(
+        "(func $legalstub$add (type 4) (param f64 i32) (result f64)\n    \
+         (local i64)\n    local.get 0\n    i64.trunc_f64_s\n"
+    ));
+    assert!(wat.contains("call $now\n    local.set 1\n    local.get 1\n    i64.trunc_f64_s)"));
+
+    // Exports without `i64`s are left alone.
+    let plain = module.exports.iter().find(|e| e.name == "plain").unwrap();
+    match plain.item {
+        ExportItem::Function(f) => assert!(module.funcs.get(f).name.is_none()),
+        _ => unreachable!(),
+    }
+    Ok(())
+}
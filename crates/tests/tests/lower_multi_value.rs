@@ -0,0 +1,134 @@
+//! Tests for lowering multi-value functions to out-parameters.
+
+use walrus::{Module, WasmFeatures};
+
+fn lower(wat: &str) -> anyhow::Result<(Module, String)> {
+    let mut module = walrus_tests::parse(wat)?;
+    walrus::passes::lower_multi_value::run(&mut module)?;
+
+    let features = WasmFeatures {
+        multi_value: false,
+        ..WasmFeatures::all()
+    };
+    module.assert_compatible(features)?;
+
+    let wasm = module.emit_wasm();
+    let module = Module::from_buffer(&wasm)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    Ok((module, wat))
+}
+
+#[test]
+fn lowers_functions_and_calls() -> anyhow::Result<()> {
+    let (module, wat) = lower(
+        r#"
+        (module
+          (import "env" "pair" (func $pair (param i32) (result i32 i64)))
+          (memory 1 2)
+          (table 1 funcref)
+          (elem (i32.const 0) $triple)
+          (type $triple (func (result i32 f64 i32)))
+          (func $triple (export "triple") (type $triple)
+            (if (i32.eqz (call $pair (i32.const 3)) (drop))
+              (then (return (i32.const 1) (f64.const 2) (i32.const 3))))
+            (i32.const 4) (f64.const 5) (i32.const 6))
+          (func (export "sum") (result i32)
+            (call_indirect (type $triple) (i32.const 0))
+            (drop)
+            (drop)))
+        "#,
+    )?;
+    assert_eq!(module.memories.iter().next().unwrap().initial, 2);
+    // Calls pass the scratch area and load the other results from it, and
+    // the results are stored through the out-parameter wherever the function
+    // returns.
+    assert_eq!(
+        wat,
+        r#"(module
+  (type (;0;) (func (result i32)))
+  (type (;1;) (func (param i32) (result i32)))
+  (type (;2;) (func (param i32 i32) (result i32)))
+  (import "env" "pair" (func $pair (type 2)))
+  (func $triple (type 1) (param i32) (result i32)
+    (local i32 f64)
+    i32.const 3
+    i32.const 65536
+    call $pair
+    i32.const 65536
+    i64.load
+    drop
+    i32.eqz
+    if  ;; label = @1
+      i32.const 1
+      f64.const 0x1p+1 (;=2;)
+      i32.const 3
+      local.set 1
+      local.set 2
+      local.get 0
+      local.get 2
+      f64.store
+      local.get 0
+      local.get 1
+      i32.store offset=8
+      return
+    else
+    end
+    i32.const 4
+    f64.const 0x1.4p+2 (;=5;)
+    i32.const 6
+    local.set 1
+    local.set 2
+    local.get 0
+    local.get 2
+    f64.store
+    local.get 0
+    local.get 1
+    i32.store offset=8)
+  (func (;2;) (type 0) (result i32)
+    (local i32)
+    i32.const 0
+    local.set 0
+    i32.const 65536
+    local.get 0
+    call_indirect (type 1)
+    i32.const 65536
+    f64.load
+    i32.const 65536
+    i32.load offset=8
+    drop
+    drop)
+  (table (;0;) 1 funcref)
+  (memory (;0;) 2 2)
+  (export "triple" (func $triple))
+  (export "sum" (func 2))
+  (elem (;0;) (i32.const 0) $triple))"#
+    );
+    Ok(())
+}
+
+#[test]
+fn leaves_single_value_modules_alone() -> anyhow::Result<()> {
+    let (module, _) = lower(
+        r#"
+        (module
+          (func (export "f") (param i32) (result i32)
+            (local.get 0)))
+        "#,
+    )?;
+    assert_eq!(module.memories.iter().count(), 0);
+    Ok(())
+}
+
+#[test]
+fn requires_a_memory() -> anyhow::Result<()> {
+    let err = lower(
+        r#"
+        (module
+          (func (export "f") (result i32 i32)
+            (i32.const 1) (i32.const 2)))
+        "#,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("without a memory"));
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! Tests for lowering sign extension and saturating truncation operators.
+
+use walrus::WasmFeatures;
+
+const WAT: &str = r#"
+    (module
+      (func (export "extend8") (param i32) (result i32)
+        local.get 0
+        i32.extend8_s)
+      (func (export "extend32") (param i64) (result i64)
+        local.get 0
+        i64.extend32_s)
+      (func (export "trunc_s") (param f32) (result i32)
+        local.get 0
+        i32.trunc_sat_f32_s)
+      (func (export "trunc_u") (param f64) (result i64)
+        local.get 0
+        i64.trunc_sat_f64_u))
+"#;
+
+fn lower(features: WasmFeatures) -> anyhow::Result<String> {
+    let mut module = walrus_tests::parse(WAT)?;
+    walrus::passes::lower_numeric::run(&mut module, features);
+    module.assert_compatible(features)?;
+
+    let wasm = walrus_tests::emit(&mut module)?;
+    wasmprinter::print_bytes(&wasm)
+}
+
+#[test]
+fn lowers_everything_for_the_mvp() -> anyhow::Result<()> {
+    let wat = lower(WasmFeatures::mvp())?;
+    assert!(
+        wat.contains("local.get 0\n    i32.const 24\n    i32.shl\n    i32.const 24\n    i32.shr_s")
+    );
+    assert!(
+        wat.contains("local.get 0\n    i64.const 32\n    i64.shl\n    i64.const 32\n    i64.shr_s")
+    );
+    assert!(wat.contains("local.get 0\n    call $i32.trunc_sat_f32_s)"));
+    assert!(wat.contains("local.get 0\n    call $i64.trunc_sat_f64_u)"));
+
+    // The generated truncations only truncate values that are in range.
+    let trunc = &wat[wat.find("(func $i32.trunc_sat_f32_s").unwrap()..];
+    assert!(trunc.contains("f32.const -0x1p+31 (;=-2147483600;)\n    f32.gt"));
+    assert!(trunc.contains("f32.const 0x1p+31 (;=2147483600;)\n    f32.lt"));
+    assert!(trunc.contains("i32.trunc_f32_s\n"));
+    assert!(trunc.contains("i32.const -2147483648\n"));
+    assert!(trunc.contains("i32.const 2147483647\n"));
+    Ok(())
+}
+
+#[test]
+fn only_lowers_disabled_features() -> anyhow::Result<()> {
+    let features = WasmFeatures {
+        sign_extension: true,
+        ..WasmFeatures::mvp()
+    };
+    let wat = lower(features)?;
+    assert!(wat.contains("i32.extend8_s"));
+    assert!(wat.contains("i64.extend32_s"));
+    assert!(!wat.contains("    i32.trunc_sat_f32_s"));
+    assert!(wat.contains("call $i32.trunc_sat_f32_s"));
+
+    let features = WasmFeatures {
+        saturating_float_to_int: true,
+        ..WasmFeatures::mvp()
+    };
+    let wat = lower(features)?;
+    assert!(!wat.contains("extend8_s"));
+    assert!(wat.contains("    i32.trunc_sat_f32_s"));
+    assert!(!wat.contains("call"));
+    Ok(())
+}
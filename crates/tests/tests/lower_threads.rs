@@ -0,0 +1,58 @@
+//! Tests for lowering threaded modules to single-threaded ones.
+
+use walrus::WasmFeatures;
+
+const WAT: &str = r#"
+    (module
+      (memory 1 1 shared)
+      (func (export "load") (param i32) (result i32)
+        local.get 0
+        i32.atomic.load)
+      (func (export "add") (param i32 i32) (result i32)
+        local.get 0
+        local.get 1
+        i32.atomic.rmw.add)
+      (func (export "cmpxchg") (param i32 i64 i64) (result i64)
+        local.get 0
+        local.get 1
+        local.get 2
+        i64.atomic.rmw8.cmpxchg_u)
+      (func (export "notify") (param i32) (result i32)
+        atomic.fence
+        local.get 0
+        i32.const 1
+        atomic.notify)
+      (func (export "wait") (param i32 i32) (result i32)
+        local.get 0
+        local.get 1
+        i64.const -1
+        i32.atomic.wait))
+"#;
+
+#[test]
+fn lowers_threads() -> anyhow::Result<()> {
+    let mut module = walrus_tests::parse(WAT)?;
+    walrus::passes::lower_threads::run(&mut module);
+    module.assert_compatible(WasmFeatures {
+        threads: false,
+        ..WasmFeatures::all()
+    })?;
+
+    let wasm = walrus_tests::emit(&mut module)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+
+    assert!(wat.contains("(memory (;0;) 1 1)"));
+    assert!(!wat.contains("atomic.fence"));
+    assert!(wat.contains("local.get 0\n    i32.load)"));
+    assert!(wat.contains("call $i32.atomic.rmw.add)"));
+    assert!(wat.contains("call $i64.atomic.rmw8.cmpxchg_u)"));
+    assert!(wat.contains("call $memory.atomic.notify)"));
+    assert!(wat.contains("call $memory.atomic.wait32)"));
+
+    // The generated functions only use plain accesses.
+    assert!(!wat.contains(".atomic.load"));
+    assert!(!wat.contains(".atomic.store"));
+    assert!(wat.contains("i64.load8_u\n"));
+    assert!(wat.contains("i64.const 255\n    i64.and\n"));
+    Ok(())
+}
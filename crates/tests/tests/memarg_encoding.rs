@@ -0,0 +1,113 @@
+//! Tests for keeping how the alignment and offset of memory instructions were
+//! encoded.
+
+use walrus::ir::*;
+use walrus::{Module, ModuleConfig};
+
+/// A module with a function whose `i32.load` and `i32.atomic.load` have padded
+/// LEB128 alignments and offsets.
+fn input() -> Vec<u8> {
+    let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+    // Type section: `(func (param i32) (result i32))`.
+    wasm.extend(&[0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f]);
+    // Function section.
+    wasm.extend(&[0x03, 0x02, 0x01, 0x00]);
+    // Memory section: a shared memory of one page.
+    wasm.extend(&[0x05, 0x04, 0x01, 0x03, 0x01, 0x01]);
+    // Code section.
+    wasm.extend(&[0x0a, 0x15, 0x01, 0x13, 0x00]);
+    // `i32.load align=4`, with a two byte alignment and a three byte offset.
+    wasm.extend(&[0x20, 0x00, 0x28, 0x82, 0x00, 0x80, 0x80, 0x00]);
+    // `i32.atomic.load align=4`, with a two byte alignment and offset.
+    wasm.extend(&[0x20, 0x00, 0xfe, 0x10, 0x82, 0x00, 0x80, 0x00]);
+    // `i32.add`, `end`.
+    wasm.extend(&[0x6a, 0x0b]);
+    wasm
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn loads(module: &mut Module) -> Vec<&mut MemArg> {
+    let func = module.funcs.iter_local_mut().next().unwrap().1;
+    let entry = func.entry_block();
+    func.block_mut(entry)
+        .instrs
+        .iter_mut()
+        .filter_map(|(instr, _)| match instr {
+            Instr::Load(e) => Some(&mut e.arg),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn parsed_encodings() -> anyhow::Result<()> {
+    let mut module = Module::from_buffer(&input())?;
+    let args = loads(&mut module);
+    assert_eq!(args.len(), 2);
+    for (arg, offset_width) in args.into_iter().zip(vec![3, 2]) {
+        assert_eq!(arg.align, 4);
+        assert_eq!(arg.offset, 0);
+        assert_eq!(
+            arg.encoding,
+            Some(MemArgEncoding {
+                align_width: 2,
+                offset_width,
+            })
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn padding_is_kept() -> anyhow::Result<()> {
+    let mut module = Module::from_buffer(&input())?;
+    let wasm = module.emit_wasm();
+    assert!(contains(&wasm, &[0x28, 0x82, 0x00, 0x80, 0x80, 0x00]));
+    assert!(contains(&wasm, &[0xfe, 0x10, 0x82, 0x00, 0x80, 0x00]));
+    Ok(())
+}
+
+#[test]
+fn canonicalized_memargs_are_shortest() -> anyhow::Result<()> {
+    let mut config = ModuleConfig::new();
+    config.canonicalize_memargs(true);
+    let mut module = config.parse(&input())?;
+    let wasm = module.emit_wasm();
+    assert!(contains(&wasm, &[0x20, 0x00, 0x28, 0x02, 0x00, 0x20]));
+    assert!(contains(&wasm, &[0xfe, 0x10, 0x02, 0x00, 0x6a]));
+    Ok(())
+}
+
+#[test]
+fn padding_grows_with_the_value() -> anyhow::Result<()> {
+    let mut module = Module::from_buffer(&input())?;
+    loads(&mut module)[0].offset = 1 << 28;
+    let wasm = module.emit_wasm();
+    assert!(contains(
+        &wasm,
+        &[0x28, 0x82, 0x00, 0x80, 0x80, 0x80, 0x80, 0x01]
+    ));
+
+    let mut module = Module::from_buffer(&wasm)?;
+    assert_eq!(loads(&mut module)[0].offset, 1 << 28);
+    Ok(())
+}
+
+#[test]
+fn encodings_are_ignored_by_equality() -> anyhow::Result<()> {
+    let mut module = Module::from_buffer(&input())?;
+    let args = loads(&mut module);
+    assert_ne!(args[0].encoding, args[1].encoding);
+    assert_eq!(*args[0], *args[1]);
+    assert_eq!(*args[0], MemArg::new(4, 0));
+
+    let set = args
+        .into_iter()
+        .map(|arg| *arg)
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(set.len(), 1);
+    Ok(())
+}
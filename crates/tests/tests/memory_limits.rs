@@ -0,0 +1,74 @@
+//! Tests for modifying the limits of memories.
+
+use walrus::Module;
+
+#[test]
+fn set_and_clear_limits() -> anyhow::Result<()> {
+    let mut module = Module::from_buffer(&wat::parse_str("(module (memory 1 2))")?)?;
+    let id = module.memories.iter().next().unwrap().id();
+    let memory = module.memories.get_mut(id);
+
+    assert!(memory.set_initial(3).is_err());
+    assert!(memory.set_maximum(0).is_err());
+    assert!(memory.set_maximum(65537).is_err());
+    memory.set_maximum(4)?;
+    memory.set_initial(3)?;
+
+    // Shared memories need a maximum.
+    memory.set_shared(true)?;
+    assert!(memory.clear_maximum().is_err());
+    memory.set_shared(false)?;
+    memory.clear_maximum()?;
+    assert!(memory.set_shared(true).is_err());
+    assert_eq!(
+        (memory.initial, memory.maximum, memory.shared),
+        (3, None, false)
+    );
+
+    Module::from_buffer(&module.emit_wasm())?;
+    Ok(())
+}
+
+#[test]
+fn shared_memories_round_trip() -> anyhow::Result<()> {
+    let mut module = Module::from_buffer(&wat::parse_str("(module (memory 1))")?)?;
+    let id = module.memories.iter().next().unwrap().id();
+    let memory = module.memories.get_mut(id);
+    memory.set_maximum(10)?;
+    memory.set_shared(true)?;
+
+    let module = Module::from_buffer(&module.emit_wasm())?;
+    let memory = module.memories.iter().next().unwrap();
+    assert!(memory.shared);
+    assert_eq!(memory.maximum, Some(10));
+    Ok(())
+}
+
+#[test]
+fn fit_memory_to_data() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (global $g (import "env" "g") i32)
+          (memory 1 3)
+          (data (i32.const 65530) "0123456789")
+          (data (global.get $g) "0123456789"))
+        "#,
+    )?;
+    let mut module = Module::from_buffer(&wasm)?;
+    let id = module.memories.iter().next().unwrap().id();
+    module.memories.get_mut(id).set_initial(0)?;
+    assert_eq!(module.fit_memory_to_data(id)?, 2);
+    assert_eq!(module.memories.get(id).initial, 2);
+
+    // The initial size is never shrunk.
+    module.memories.get_mut(id).set_initial(3)?;
+    assert_eq!(module.fit_memory_to_data(id)?, 3);
+
+    // Segments beyond the maximum size are an error.
+    module.memories.get_mut(id).set_initial(1)?;
+    module.memories.get_mut(id).set_maximum(1)?;
+    assert!(module.fit_memory_to_data(id).is_err());
+    assert_eq!(module.memories.get(id).initial, 1);
+    Ok(())
+}
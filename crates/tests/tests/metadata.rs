@@ -0,0 +1,52 @@
+//! Tests for the `walrus.metadata` custom section.
+
+use walrus::{Module, RawCustomSection, METADATA_SECTION};
+use walrus_tests::config;
+
+#[test]
+fn round_trip_metadata() -> anyhow::Result<()> {
+    let mut module = Module::with_config(config());
+    assert!(module.metadata.is_empty());
+    assert_eq!(module.emit_wasm().len(), 8);
+
+    module.metadata.set("pipeline-version", &b"1.2.3"[..]);
+    module.metadata.set("flags", vec![0, 1, 2]);
+    assert_eq!(module.metadata.set("flags", vec![3]), Some(vec![0, 1, 2]));
+
+    let wasm = module.emit_wasm();
+    let mut module = config().parse(&wasm)?;
+    assert_eq!(module.metadata.get("pipeline-version"), Some(&b"1.2.3"[..]));
+    assert_eq!(module.metadata.get("flags"), Some(&[3][..]));
+    assert_eq!(module.metadata.get("missing"), None);
+    let keys = module.metadata.iter().map(|(k, _)| k).collect::<Vec<_>>();
+    assert_eq!(keys, ["flags", "pipeline-version"]);
+    // The section isn't also kept around as a raw custom section.
+    assert_eq!(module.customs.iter().count(), 0);
+
+    // Processing a module again can tell that it was stamped already.
+    assert!(module.metadata.contains("pipeline-version"));
+    assert_eq!(module.metadata.remove("flags"), Some(vec![3]));
+    let wasm = module.emit_wasm();
+    let mut module = config().parse(&wasm)?;
+    assert_eq!(module.metadata.len(), 1);
+
+    module.metadata.clear();
+    let wasm = module.emit_wasm();
+    assert!(wasmprinter::print_bytes(&wasm)?
+        .find(METADATA_SECTION)
+        .is_none());
+    Ok(())
+}
+
+#[test]
+fn malformed_metadata_is_ignored() -> anyhow::Result<()> {
+    let mut module = Module::with_config(config());
+    module.customs.add(RawCustomSection {
+        name: METADATA_SECTION.to_string(),
+        data: vec![1, 1],
+    });
+    let wasm = module.emit_wasm();
+    let module = config().parse(&wasm)?;
+    assert!(module.metadata.is_empty());
+    Ok(())
+}
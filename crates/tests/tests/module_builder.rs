@@ -0,0 +1,55 @@
+//! Tests for building whole modules with `ModuleBuilder`.
+
+use walrus::ir::{BinaryOp, Value};
+use walrus::{InitExpr, ModuleBuilder, ValType};
+
+#[test]
+fn builds_module() -> anyhow::Result<()> {
+    let config = walrus_tests::config();
+    let mut builder = ModuleBuilder::with_config(config);
+    let log = builder.import_func("env", "log", &[ValType::I32], &[]);
+    let memory = builder.memory(1, Some(2));
+    let base = builder.global(ValType::I32, false, InitExpr::Value(Value::I32(8)));
+    let add = builder.func(
+        "add",
+        &[ValType::I32],
+        &[ValType::I32],
+        |body, args, locals| {
+            let sum = locals.add(ValType::I32);
+            body.local_get(args[0])
+                .global_get(base)
+                .binop(BinaryOp::I32Add)
+                .local_tee(sum)
+                .call(log)
+                .local_get(sum);
+        },
+    );
+    let init = builder.func("init", &[], &[], |body, _, _| {
+        body.i32_const(0).call(log);
+    });
+    builder
+        .export("add", add)
+        .export("memory", memory)
+        .start(init);
+
+    let wasm = builder.finish().emit_wasm();
+    walrus::Module::from_buffer(&wasm)?;
+    let wat = wasmprinter::print_bytes(wasm)?;
+    assert!(wat.contains("(import \"env\" \"log\" (func $log (type 1)))"));
+    assert!(wat.contains(
+        "(func $add (type 2) (param i32) (result i32)
+    (local i32)
+    local.get 0
+    global.get 0
+    i32.add
+    local.tee 1
+    call $log
+    local.get 1)"
+    ));
+    assert!(wat.contains("(memory (;0;) 1 2)"));
+    assert!(wat.contains("(global (;0;) i32 (i32.const 8))"));
+    assert!(wat.contains("(export \"add\" (func $add))"));
+    assert!(wat.contains("(export \"memory\" (memory 0))"));
+    assert!(wat.contains("(start 2)"));
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! Tests for cloning modules.
+
+use std::borrow::Cow;
+use walrus::ir::{Const, Value};
+use walrus::{CustomSection, IdsToIndices, Module, RawCustomSection};
+use walrus_tests::parse;
+
+const WAT: &str = r#"
+    (module
+      (func $f (export "f") (result i32)
+        i32.const 1)
+      (func $g (export "g") (result i32)
+        i32.const 2))
+"#;
+
+#[test]
+fn clones_share_bodies_until_mutated() -> anyhow::Result<()> {
+    let module = parse(WAT)?;
+    let mut clone = module.clone();
+    let f = module.funcs.by_name("f").unwrap();
+    let g = module.funcs.by_name("g").unwrap();
+    assert_eq!(clone.funcs.by_name("f"), Some(f));
+
+    let body = |module: &Module, id| module.funcs.get(id).kind.unwrap_local().builder() as *const _;
+    assert_eq!(body(&module, f), body(&clone, f));
+
+    let func = clone.funcs.get_mut(f).kind.unwrap_local_mut();
+    let entry = func.entry_block();
+    func.block_mut(entry).instrs[0].0 = Const {
+        value: Value::I32(3),
+    }
+    .into();
+    assert_ne!(body(&module, f), body(&clone, f));
+    assert_eq!(body(&module, g), body(&clone, g));
+
+    let original = wasmprinter::print_bytes(module.clone().emit_wasm())?;
+    let forked = wasmprinter::print_bytes(clone.emit_wasm())?;
+    assert!(original.contains("i32.const 1)"));
+    assert!(forked.contains("i32.const 3)"));
+    assert!(!forked.contains("i32.const 1)"));
+    assert!(forked.contains("i32.const 2)"));
+    Ok(())
+}
+
+#[derive(Debug)]
+struct Uncloned;
+
+impl CustomSection for Uncloned {
+    fn name(&self) -> &str {
+        "uncloned"
+    }
+
+    fn data(&self, _: &IdsToIndices) -> Cow<'_, [u8]> {
+        vec![].into()
+    }
+}
+
+#[test]
+fn clones_custom_sections_that_support_it() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    module.customs.add(RawCustomSection {
+        name: "raw".to_string(),
+        data: vec![1, 2, 3],
+    });
+    module.customs.add(Uncloned);
+
+    let mut clone = module.clone();
+    let names = |m: &Module| {
+        m.customs
+            .iter()
+            .map(|(_, s)| s.name().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&module), ["raw", "uncloned"]);
+    assert_eq!(names(&clone), ["raw"]);
+
+    // The clone doesn't emit the section that it doesn't contain.
+    let mut emitted = Module::from_buffer(&clone.emit_wasm())?;
+    assert!(emitted.customs.remove_raw("raw").is_some());
+    assert!(emitted.customs.remove_raw("uncloned").is_none());
+    Ok(())
+}
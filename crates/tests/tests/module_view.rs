@@ -0,0 +1,37 @@
+//! Tests for sharing frozen modules between threads.
+
+use std::thread;
+use walrus::Module;
+
+#[test]
+fn shares_module_between_threads() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (func $a (result i32) i32.const 1)
+          (func $b (result i32) i32.const 2))
+        "#,
+    )?;
+    let view = Module::from_buffer(&wasm)?.freeze();
+
+    let threads = ["a", "b", "c"]
+        .iter()
+        .map(|name| {
+            let view = view.clone();
+            thread::spawn(move || view.funcs.by_name(name).is_some())
+        })
+        .collect::<Vec<_>>();
+    let found = threads
+        .into_iter()
+        .map(|t| t.join().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(found, [true, true, false]);
+
+    let other = view.clone();
+    assert_eq!(view.view_count(), 2);
+    let view = view.try_into_module().unwrap_err();
+    drop(other);
+    let module = view.try_into_module().unwrap();
+    assert_eq!(module.funcs.iter_local().count(), 2);
+    Ok(())
+}
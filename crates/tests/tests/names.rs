@@ -0,0 +1,98 @@
+//! Tests for looking up functions, globals, and exports by name, and functions
+//! by their original index.
+
+use walrus::{InitExpr, Module, ValType};
+
+fn module() -> anyhow::Result<Module> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (func $a (export "a"))
+          (func $b (export "b"))
+          (func $dup)
+          (func $dup2))
+        "#,
+    )?;
+    Module::from_buffer(&wasm)
+}
+
+#[test]
+fn funcs_by_name() -> anyhow::Result<()> {
+    let mut module = module()?;
+    let a = module.funcs.by_name("a").unwrap();
+    let b = module.funcs.by_name("b").unwrap();
+    assert_ne!(a, b);
+    assert!(module.funcs.by_name("c").is_none());
+
+    // Renames are picked up by later lookups.
+    module.funcs.get_mut(a).name = Some("c".to_string());
+    assert_eq!(module.funcs.by_name("c"), Some(a));
+    assert!(module.funcs.by_name("a").is_none());
+
+    // So are deletions.
+    module.funcs.delete(b);
+    assert!(module.funcs.by_name("b").is_none());
+
+    // The first function with a duplicated name wins.
+    let dup = module.funcs.by_name("dup").unwrap();
+    let dup2 = module.funcs.by_name("dup2").unwrap();
+    module.funcs.get_mut(dup2).name = Some("dup".to_string());
+    assert_eq!(module.funcs.by_name("dup"), Some(dup));
+    Ok(())
+}
+
+#[test]
+fn funcs_by_original_index() -> anyhow::Result<()> {
+    let mut module = module()?;
+    let a = module.funcs.by_name("a").unwrap();
+    let b = module.funcs.by_name("b").unwrap();
+    assert_eq!(module.funcs.by_original_index(0), Some(a));
+    assert_eq!(module.funcs.by_original_index(1), Some(b));
+    assert_eq!(module.funcs.by_original_index(4), None);
+    assert_eq!(module.funcs.original_index(b), Some(1));
+
+    // Original indices don't change when functions are deleted, but deleted
+    // functions aren't found anymore.
+    module.funcs.delete(a);
+    assert_eq!(module.funcs.by_original_index(0), None);
+    assert_eq!(module.funcs.by_original_index(1), Some(b));
+
+    // New functions have no original index.
+    let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body();
+    let c = builder.finish(vec![], &mut module.funcs);
+    assert_eq!(module.funcs.original_index(c), None);
+
+    assert_eq!(Module::default().funcs.by_original_index(0), None);
+    Ok(())
+}
+
+#[test]
+fn globals_by_name() {
+    let mut module = Module::default();
+    let g = module.globals.add_local(
+        ValType::I32,
+        false,
+        InitExpr::Value(walrus::ir::Value::I32(0)),
+    );
+    assert!(module.globals.by_name("g").is_none());
+    module.globals.get_mut(g).name = Some("g".to_string());
+    assert_eq!(module.globals.by_name("g"), Some(g));
+    module.globals.delete(g);
+    assert!(module.globals.by_name("g").is_none());
+}
+
+#[test]
+fn exports_by_name() -> anyhow::Result<()> {
+    let mut module = module()?;
+    let a = module.exports.by_name("a").unwrap();
+    assert_eq!(module.exports.get(a).name, "a");
+    assert!(module.exports.by_name("c").is_none());
+
+    let b = module.funcs.by_name("b").unwrap();
+    let c = module.exports.add("c", b);
+    assert_eq!(module.exports.by_name("c"), Some(c));
+    module.exports.delete(a);
+    assert!(module.exports.by_name("a").is_none());
+    Ok(())
+}
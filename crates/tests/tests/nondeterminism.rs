@@ -0,0 +1,99 @@
+//! Tests for finding the sources of nondeterminism in a module.
+
+use walrus::passes::nondeterminism::{self, Cause};
+use walrus::wasi::Capability;
+use walrus::Module;
+
+#[test]
+fn finds_nondeterminism() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (import "wasi_snapshot_preview1" "fd_write"
+            (func (param i32 i32 i32 i32) (result i32)))
+          (import "wasi_snapshot_preview1" "clock_time_get"
+            (func (param i32 i64 i32) (result i32)))
+          (import "wasi_snapshot_preview1" "random_get"
+            (func (param i32 i32) (result i32)))
+          (import "env" "now" (func (result i64)))
+          (memory 1 1 shared)
+          (func $floats (param f32 f64) (result f32)
+            (f32.abs (local.get 0))
+            (f32.neg)
+            (f32.copysign (local.get 0))
+            (f32.add (local.get 0))
+            (f32.sqrt)
+            (f32.demote_f64 (f64.div (local.get 1) (local.get 1)))
+            (f32.convert_i32_s (i32.reinterpret_f32 (f32.mul)))
+            (drop)
+            (if (result f32) (f32.eq (local.get 0) (local.get 0))
+              (then (f32.ceil (local.get 0)))
+              (else (f32.const 0))))
+          (func $grows (result i32)
+            (memory.grow (i32.const 1)))
+          (func $waits (result i32)
+            (i32.atomic.wait (i32.const 0) (i32.const 0) (i64.const -1))))
+        "#,
+    )?;
+    let module = Module::from_buffer(&wasm)?;
+    let found = nondeterminism::run(&module);
+    assert!(!found.is_empty());
+
+    let causes = found.instrs.iter().map(|s| s.cause).collect::<Vec<_>>();
+    assert_eq!(
+        causes,
+        [
+            Cause::NanBits,
+            Cause::NanBits,
+            Cause::NanBits,
+            Cause::NanBits,
+            Cause::NanBits,
+            Cause::NanBits,
+            Cause::Growth,
+            Cause::AtomicWait,
+        ]
+    );
+    let opcodes = found
+        .instrs
+        .iter()
+        .map(|s| wasm[s.offset.unwrap() as usize])
+        .collect::<Vec<_>>();
+    assert_eq!(opcodes, [0x92, 0x91, 0xa3, 0xb6, 0x94, 0x8d, 0x40, 0xfe]);
+    let floats = module.funcs.by_name("floats").unwrap();
+    assert!(found.instrs[..6].iter().all(|s| s.func == floats));
+
+    let shared = module.memories.iter().next().unwrap().id();
+    assert_eq!(found.shared_memories, [shared]);
+    let imports = found
+        .imports
+        .iter()
+        .map(|(id, capability)| (module.imports.get(*id).name.as_str(), *capability))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        imports,
+        [
+            ("clock_time_get", Capability::Clock),
+            ("random_get", Capability::Random),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn deterministic_module() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (import "wasi_snapshot_preview1" "fd_write"
+            (func (param i32 i32 i32 i32) (result i32)))
+          (memory 1)
+          (func (param f32 i32) (result f32)
+            (f32.neg (f32.abs (local.get 0)))
+            (f32.convert_i32_u (local.get 1))
+            (f32.copysign)))
+        "#,
+    )?;
+    let module = Module::from_buffer(&wasm)?;
+    assert!(nondeterminism::run(&module).is_empty());
+    Ok(())
+}
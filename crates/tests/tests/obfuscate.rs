@@ -0,0 +1,111 @@
+//! Tests for obfuscating modules.
+
+use walrus::passes::obfuscate::{self, Names, Options};
+use walrus::Module;
+use walrus_tests::parse;
+
+const WAT: &str = r#"
+    (module $secret
+      (import "env" "log" (func $log (param i32)))
+      (global $counter (mut i32) (i32.const 0))
+      (func $compute_license_key (param $seed i32) (result i32)
+        (i32.mul (local.get $seed) (i32.const 31)))
+      (func $check_license (export "check_license") (param i32) (result i32)
+        (global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+        (call $log (local.get 0))
+        (call $compute_license_key (local.get 0)))
+      (func $main (export "main")
+        (drop (call $check_license (i32.const 42)))))
+"#;
+
+fn obfuscate(options: &Options) -> anyhow::Result<(String, obfuscate::Mapping)> {
+    let mut module = parse(WAT)?;
+    let mapping = obfuscate::run(&mut module, options);
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)?;
+    Ok((wasmprinter::print_bytes(&wasm)?, mapping))
+}
+
+#[test]
+fn strips_names() -> anyhow::Result<()> {
+    let (wat, mapping) = obfuscate(&Options::default())?;
+    assert!(!wat.contains("compute_license_key"));
+    assert!(!wat.contains("$check_license"));
+    assert!(!wat.contains("counter"));
+    assert!(!wat.contains("seed"));
+    assert!(!wat.contains("secret"));
+    // Exports and imports keep their names by default.
+    assert!(wat.contains("(export \"main\""));
+    assert!(wat.contains("(import \"env\" \"log\""));
+    assert_eq!(mapping, obfuscate::Mapping::default());
+    Ok(())
+}
+
+#[test]
+fn scrambles_names_and_renames_exports() -> anyhow::Result<()> {
+    let options = Options {
+        seed: 7,
+        names: Names::Scramble,
+        rename_exports: true,
+        keep_exports: vec!["main".to_string()],
+        ..Options::default()
+    };
+    let (wat, mapping) = obfuscate(&options)?;
+    assert!(!wat.contains("license"));
+    assert!(!wat.contains("seed"));
+    assert!(wat.contains("(export \"main\""));
+
+    let (renamed, original) = mapping.exports.iter().next().unwrap();
+    assert_eq!(mapping.exports.len(), 1);
+    assert_eq!(original, "check_license");
+    assert!(wat.contains(&format!("(export {:?}", renamed)));
+
+    let originals = mapping.functions.values().collect::<Vec<_>>();
+    assert_eq!(originals.len(), 4);
+    for name in &["log", "compute_license_key", "check_license", "main"] {
+        assert!(originals.iter().any(|n| n == name));
+    }
+    for new in mapping.functions.keys() {
+        assert!(wat.contains(&format!("${}", new)));
+    }
+    assert!(mapping
+        .to_string()
+        .contains(&format!("export {:?} \"check_license\"\n", renamed)));
+
+    // The same seed obfuscates the same way.
+    assert_eq!(obfuscate(&options)?, (wat, mapping));
+    Ok(())
+}
+
+#[test]
+fn shuffles_functions() -> anyhow::Result<()> {
+    let orders = (0..8)
+        .map(|seed| {
+            let mut module = parse(WAT)?;
+            obfuscate::run(
+                &mut module,
+                &Options {
+                    seed,
+                    names: Names::Keep,
+                    ..Options::default()
+                },
+            );
+            let wat = wasmprinter::print_bytes(module.emit_wasm())?;
+            Ok(wat.find("(func $main").unwrap())
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    assert!(orders.iter().any(|o| *o != orders[0]));
+    Ok(())
+}
+
+#[test]
+fn inserts_opaque_predicates() -> anyhow::Result<()> {
+    let (wat, _) = obfuscate(&Options {
+        opaque_predicates: true,
+        shuffle_functions: false,
+        ..Options::default()
+    })?;
+    assert_eq!(wat.matches("i32.mul").count(), 4);
+    assert_eq!(wat.matches("unreachable").count(), 3);
+    Ok(())
+}
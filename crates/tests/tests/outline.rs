@@ -0,0 +1,68 @@
+//! Tests for outlining repeated instruction sequences.
+
+use walrus::passes::outline::{self, Options};
+use walrus::Module;
+
+const WAT: &str = r#"
+    (module
+      (memory 1)
+      (func $a (export "a") (param i32) (result i32)
+        (i32.store (i32.const 16)
+          (i32.add (i32.mul (local.get 0) (i32.const 3)) (i32.load (i32.const 8))))
+        (i32.load (i32.const 16)))
+      (func $b (export "b") (param i64 i32) (result i32)
+        (i32.store (i32.const 16)
+          (i32.add (i32.mul (local.get 1) (i32.const 3)) (i32.load (i32.const 8))))
+        (i32.const 1))
+      (func $c (export "c") (param f32) (result f32)
+        (f32.store (i32.const 16)
+          (f32.add (f32.mul (local.get 0) (f32.const 3)) (f32.load (i32.const 8))))
+        (local.get 0)))
+"#;
+
+fn run(wat: &str, options: &Options) -> anyhow::Result<(usize, String)> {
+    let mut module = walrus_tests::parse(wat)?;
+    let helpers = outline::run(&mut module, options);
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)?;
+    Ok((helpers.len(), wasmprinter::print_bytes(&wasm)?))
+}
+
+#[test]
+fn outlines_repeated_sequences() -> anyhow::Result<()> {
+    let (helpers, wat) = run(WAT, &Options::default())?;
+    assert_eq!(helpers, 1);
+    // The sequence reads different locals in `a` and `b`, and `c` computes
+    // with floats instead.
+    assert!(wat.contains(
+        "(func $outlined_0 (type 0) (param i32)
+    i32.const 16
+    local.get 0
+    i32.const 3
+    i32.mul
+    i32.const 8
+    i32.load
+    i32.add
+    i32.store)"
+    ));
+    assert!(wat.contains("local.get 0\n    call $outlined_0\n    i32.const 16\n    i32.load)"));
+    assert!(wat.contains("local.get 1\n    call $outlined_0\n    i32.const 1)"));
+    assert!(wat.contains("f32.mul"));
+    Ok(())
+}
+
+#[test]
+fn respects_limits() -> anyhow::Result<()> {
+    let options = Options {
+        max_len: 32,
+        max_helpers: 0,
+    };
+    assert_eq!(run(WAT, &options)?.0, 0);
+    // Sequences of three instructions, read twice, don't save enough.
+    let options = Options {
+        max_len: 3,
+        max_helpers: 10,
+    };
+    assert_eq!(run(WAT, &options)?.0, 0);
+    Ok(())
+}
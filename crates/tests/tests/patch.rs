@@ -0,0 +1,61 @@
+//! Tests for emitting modules as patches against their original binaries.
+
+use walrus::{apply_patch, Module};
+
+/// A module with enough code that patches are noticeably smaller than it.
+fn wat() -> String {
+    let mut wat = String::from("(module (memory (export \"memory\") 1)");
+    for i in 0..50 {
+        wat.push_str(&format!(
+            "(func $f{i} (export \"f{i}\") (param i32) (result i32)
+               (i32.add (i32.mul (local.get 0) (i32.const {i})) (i32.const 12345)))",
+            i = i
+        ));
+    }
+    wat.push(')');
+    wat
+}
+
+fn parse(wasm: &[u8]) -> anyhow::Result<Module> {
+    let mut config = walrus_tests::config();
+    config.preserve_encoding(true);
+    config.parse(wasm)
+}
+
+#[test]
+fn patches_modified_module() -> anyhow::Result<()> {
+    let original = wat::parse_str(wat())?;
+    let mut module = parse(&original)?;
+    let export = module.exports.iter().find(|e| e.name == "f7").unwrap().id();
+    module.exports.get_mut(export).name = "seven".to_string();
+
+    let patch = module.emit_patch()?;
+    let patched = apply_patch(&original, &patch)?;
+    assert_eq!(patched, module.emit_wasm());
+    assert!(patch.len() * 4 < patched.len(), "{} bytes", patch.len());
+    let wat = wasmprinter::print_bytes(patched)?;
+    assert!(wat.contains("(export \"seven\" (func $f7))"));
+    Ok(())
+}
+
+#[test]
+fn patches_unmodified_module() -> anyhow::Result<()> {
+    let original = wat::parse_str(wat())?;
+    let patch = parse(&original)?.emit_patch()?;
+    assert_eq!(apply_patch(&original, &patch)?, original);
+    assert!(patch.len() < 40, "{} bytes", patch.len());
+    Ok(())
+}
+
+#[test]
+fn rejects_other_originals() -> anyhow::Result<()> {
+    let original = wat::parse_str(wat())?;
+    let patch = parse(&original)?.emit_patch()?;
+    let other = wat::parse_str("(module)")?;
+    assert!(apply_patch(&other, &patch).is_err());
+    assert!(apply_patch(&original, b"\0asm").is_err());
+
+    let mut module = Module::from_buffer(&original)?;
+    assert!(module.emit_patch().is_err());
+    Ok(())
+}
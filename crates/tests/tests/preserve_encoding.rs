@@ -0,0 +1,65 @@
+//! Tests for reproducing unmodified modules byte for byte.
+
+use walrus::{ExportItem, ModuleConfig};
+
+/// A module that walrus would encode differently: its type section's size
+/// uses a padded LEB128, its function locals aren't grouped the way walrus
+/// groups them, and it has a custom section.
+fn input() -> Vec<u8> {
+    let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+    // Type section: `(func (result i32))`, with a five byte size.
+    wasm.extend(&[
+        0x01, 0x85, 0x80, 0x80, 0x80, 0x00, 0x01, 0x60, 0x00, 0x01, 0x7f,
+    ]);
+    // Function section.
+    wasm.extend(&[0x03, 0x02, 0x01, 0x00]);
+    // Code section: two separate groups of one `i32` local each.
+    wasm.extend(&[0x0a, 0x0a, 0x01, 0x08, 0x02, 0x01, 0x7f, 0x01, 0x7f]);
+    wasm.extend(&[0x20, 0x01, 0x0b]);
+    // Custom section named "x".
+    wasm.extend(&[0x00, 0x04, 0x01, b'x', 0xab, 0xcd]);
+    wasm
+}
+
+#[test]
+fn unmodified_modules_are_reproduced() -> anyhow::Result<()> {
+    let wasm = input();
+    let mut config = ModuleConfig::new();
+    config.preserve_encoding(true);
+    let mut module = config.parse(&wasm)?;
+    assert_eq!(module.emit_wasm(), wasm);
+    assert_eq!(module.emit_wasm(), wasm);
+
+    // Without the flag, walrus encodes the module its own way.
+    let mut module = ModuleConfig::new().parse(&wasm)?;
+    assert_ne!(module.emit_wasm(), wasm);
+    Ok(())
+}
+
+#[test]
+fn modified_modules_are_reencoded() -> anyhow::Result<()> {
+    let wasm = input();
+    let mut config = ModuleConfig::new();
+    config.preserve_encoding(true);
+    let mut module = config.parse(&wasm)?;
+    let func = module.funcs.iter().next().unwrap().id();
+    module.exports.add("f", func);
+
+    let emitted = module.emit_wasm();
+    assert_ne!(emitted, wasm);
+    let module = ModuleConfig::new().parse(&emitted)?;
+    let export = module.exports.iter().next().unwrap();
+    assert_eq!(export.name, "f");
+    assert!(matches!(export.item, ExportItem::Function(_)));
+    assert_eq!(module.customs.iter().count(), 1);
+    Ok(())
+}
+
+#[test]
+fn custom_sections_survive_emitting() -> anyhow::Result<()> {
+    let mut module = ModuleConfig::new().parse(&input())?;
+    let first = module.emit_wasm();
+    assert_eq!(module.customs.iter().count(), 1);
+    assert_eq!(module.emit_wasm(), first);
+    Ok(())
+}
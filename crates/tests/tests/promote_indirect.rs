@@ -0,0 +1,100 @@
+//! Tests for promoting indirect calls to direct calls.
+
+use walrus::passes::promote_indirect::{self, Options, Profile};
+use walrus::Module;
+use walrus_tests::parse;
+
+const WAT: &str = r#"
+    (module
+      (type $unary (func (param i32) (result i32)))
+      (table 4 funcref)
+      (elem (i32.const 0) $double $square $wrong $double)
+      (func $double (param i32) (result i32) (i32.add (local.get 0) (local.get 0)))
+      (func $square (param i32) (result i32) (i32.mul (local.get 0) (local.get 0)))
+      (func $wrong (result i32) (i32.const 0))
+      (func $apply (export "apply") (param i32 i32) (result i32)
+        (call_indirect (type $unary) (local.get 1) (local.get 0))
+        (block (result i32)
+          (call_indirect (type $unary) (local.get 1) (local.get 0)))
+        (i32.add)))
+"#;
+
+fn promote(wat: &str, profile: &Profile, options: &Options) -> anyhow::Result<(usize, String)> {
+    let mut module = parse(wat)?;
+    let promoted = promote_indirect::run(&mut module, profile, options);
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)?;
+    Ok((promoted, wasmprinter::print_bytes(&wasm)?))
+}
+
+#[test]
+fn promotes_hot_targets() -> anyhow::Result<()> {
+    let mut profile = Profile::new();
+    profile
+        .add_name("apply", 0, 0, 900)
+        .add_name("apply", 0, 1, 50)
+        .add_index(3, 0, 1, 100)
+        .add_name("apply", 1, 2, 1000);
+    let (promoted, wat) = promote(WAT, &profile, &Options::default())?;
+    // The second site only calls a function of the wrong type.
+    assert_eq!(promoted, 1);
+    assert!(wat.contains(
+        "local.set 2
+    local.set 3
+    local.get 2
+    i32.const 0
+    i32.eq
+    if (result i32)  ;; label = @1
+      local.get 3
+      call $double
+    else
+      local.get 2
+      i32.const 1
+      i32.eq
+      if (result i32)  ;; label = @2
+        local.get 3
+        call $square
+      else
+        local.get 3
+        local.get 2
+        call_indirect (type 1)
+      end
+    end"
+    ));
+    assert_eq!(wat.matches("call_indirect").count(), 2);
+    Ok(())
+}
+
+#[test]
+fn respects_options() -> anyhow::Result<()> {
+    let mut profile = Profile::new();
+    profile
+        .add_name("apply", 0, 0, 900)
+        .add_name("apply", 0, 1, 200)
+        .add_name("apply", 1, 3, 50);
+    let options = Options {
+        max_targets: 1,
+        min_count: 100,
+    };
+    let (promoted, wat) = promote(WAT, &profile, &options)?;
+    assert_eq!(promoted, 1);
+    assert!(wat.contains("call $double"));
+    assert!(!wat.contains("call $square"));
+    Ok(())
+}
+
+#[test]
+fn leaves_mutable_tables_alone() -> anyhow::Result<()> {
+    let mut profile = Profile::new();
+    profile.add_name("apply", 0, 0, 1000);
+    let exported = WAT.replace("(table 4 funcref)", "(table (export \"table\") 4 funcref)");
+    let (promoted, _) = promote(&exported, &profile, &Options::default())?;
+    assert_eq!(promoted, 0);
+    let written = WAT.replace(
+        "(func $wrong (result i32) (i32.const 0))",
+        "(func $wrong (result i32) (table.copy (i32.const 0) (i32.const 1) (i32.const 1)) (i32.const 0))",
+    );
+    let (promoted, _) = promote(&written, &profile, &Options::default())?;
+    assert_eq!(promoted, 0);
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//! Tests for adding local functions from already encoded bodies.
+
+use walrus::ValType;
+use walrus_tests::parse;
+
+#[test]
+fn adds_encoded_body() -> anyhow::Result<()> {
+    let mut module = parse(
+        r#"
+        (module
+          (import "env" "f" (func $f (param i32) (result i32)))
+          (global $g i32 (i32.const 7)))
+        "#,
+    )?;
+    let ty = module.types.add(&[ValType::I32], &[ValType::I32]);
+    // One `i32` local, then
+    // `local.get 0; global.get 0; call 0; local.tee 1; i32.add; end`.
+    let body = [
+        0x01, 0x01, 0x7f, 0x20, 0x00, 0x23, 0x00, 0x10, 0x00, 0x22, 0x01, 0x6a, 0x0b,
+    ];
+    let id = module.add_local_func_from_bytes(ty, &body)?;
+    module.exports.add("g", id);
+
+    let wat = wasmprinter::print_bytes(module.emit_wasm())?;
+    assert!(wat.contains(
+        "(func (;1;) (type 0) (param i32) (result i32)
+    (local i32)
+    local.get 0
+    global.get 0
+    call $f
+    local.tee 1
+    i32.add)"
+    ));
+    Ok(())
+}
+
+#[test]
+fn rejects_invalid_body() -> anyhow::Result<()> {
+    let mut module = parse("(module)")?;
+    let ty = module.types.add(&[], &[ValType::I32]);
+    // Missing the result.
+    assert!(module.add_local_func_from_bytes(ty, &[0x00, 0x0b]).is_err());
+    // An `i64` result instead of an `i32`.
+    assert!(module
+        .add_local_func_from_bytes(ty, &[0x00, 0x42, 0x00, 0x0b])
+        .is_err());
+    // An out of bounds local.
+    assert!(module
+        .add_local_func_from_bytes(ty, &[0x00, 0x20, 0x00, 0x0b])
+        .is_err());
+    // Missing the final `end`.
+    assert!(module
+        .add_local_func_from_bytes(ty, &[0x00, 0x41, 0x00])
+        .is_err());
+    assert_eq!(module.funcs.iter().count(), 0);
+
+    assert!(module
+        .add_local_func_from_bytes(ty, &[0x00, 0x41, 0x00, 0x0b])
+        .is_ok());
+    assert_eq!(module.funcs.iter().count(), 1);
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//! Tests for the import record/replay pass.
+
+use walrus::passes::record_replay::{self, Config, Mode};
+use walrus::{FunctionKind, Module};
+
+const WAT: &str = r#"
+    (module
+      (import "env" "random" (func $random (param i32) (result i64)))
+      (memory 1)
+      (global $cursor (mut i32) (i32.const 0))
+      (table (export "t") 1 funcref)
+      (elem (i32.const 0) $random)
+      (func (export "f") (result i64)
+        i32.const 7
+        call $random))
+"#;
+
+fn run(mode: Mode) -> anyhow::Result<(Module, String)> {
+    let mut module = walrus_tests::parse(WAT)?;
+    let config = Config {
+        mode,
+        memory: module.memories.iter().next().unwrap().id(),
+        base: 1024,
+        capacity: 4096,
+        cursor: module.globals.iter().next().unwrap().id(),
+    };
+    let wrappers = record_replay::run(&mut module, &config)?;
+    assert_eq!(wrappers.len(), 1);
+    walrus::passes::gc::run(&mut module);
+
+    let wasm = module.emit_wasm();
+    let module = Module::from_buffer(&wasm)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    Ok((module, wat))
+}
+
+#[test]
+fn record() -> anyhow::Result<()> {
+    let (module, wat) = run(Mode::Record)?;
+    assert_eq!(module.imports.iter().count(), 1);
+    assert!(wat.contains("i32.const 7\n    call $record:env.random)"));
+    assert!(wat.contains("(elem (;0;) (i32.const 0) $record:env.random)"));
+    assert!(wat.contains("i32.store offset=1024 align=1"));
+    assert!(wat.contains("call $random"));
+    assert!(wat.contains("i64.store offset=1024 align=1"));
+    Ok(())
+}
+
+#[test]
+fn replay() -> anyhow::Result<()> {
+    let (module, wat) = run(Mode::Replay)?;
+    // The import is never called, so it was collected.
+    assert_eq!(module.imports.iter().count(), 0);
+    let func = module.funcs.by_name("replay:env.random").unwrap();
+    match &module.funcs.get(func).kind {
+        FunctionKind::Local(_) => {}
+        _ => panic!("expected the wrapper to be a local function"),
+    }
+    assert!(wat.contains("i64.load offset=1024 align=1"));
+    assert!(!wat.contains("i32.store"));
+    Ok(())
+}
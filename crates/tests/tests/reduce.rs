@@ -0,0 +1,71 @@
+//! Tests for reducing modules to smaller interesting ones.
+
+use walrus::passes::reduce;
+use walrus::Module;
+use walrus_tests::parse;
+
+const WAT: &str = r#"
+    (module
+      (import "env" "crash" (func $crash))
+      (import "env" "log" (func $log (param i32)))
+      (memory (export "memory") 1)
+      (data (i32.const 0) "some data that doesn't matter")
+      (func $helper (param i32)
+        (call $log (local.get 0))
+        (call $crash))
+      (func $main (export "main")
+        (call $log (i32.const 1))
+        (call $helper (i32.const 2)))
+      (func $other (export "other")
+        (call $log (i32.const 3)))
+      (start $other))
+"#;
+
+// Pretends that an engine crashes whenever `crash` is still imported.
+fn crashes(module: &Module) -> bool {
+    module.imports.find("env", "crash").is_some()
+}
+
+#[test]
+fn reduces_while_interesting() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    let before = module.emit_wasm().len();
+    let kept = reduce::run(&mut module, crashes)?;
+    assert!(kept > 0);
+    assert!(crashes(&module));
+
+    let wasm = module.emit_wasm();
+    assert!(wasm.len() < before);
+    Module::from_buffer(&wasm)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    assert!(wat.contains("call $crash"));
+    assert!(!wat.contains("other"));
+    assert!(!wat.contains("memory"));
+    assert!(!wat.contains("start"));
+    Ok(())
+}
+
+#[test]
+fn keeps_data_that_matters() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    // Interesting for as long as the data starts with "some".
+    reduce::run(&mut module, |m| {
+        m.data.iter().any(|d| d.value.starts_with(b"some"))
+    })?;
+    let data = module
+        .data
+        .iter()
+        .map(|d| d.value.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(data.len(), 1);
+    assert!(data[0].starts_with(b"some"));
+    assert!(data[0].len() < 8);
+    Ok(())
+}
+
+#[test]
+fn rejects_uninteresting_modules() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    assert!(reduce::run(&mut module, |_| false).is_err());
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! Tests for recomputing the types of instruction sequences after edits.
+
+use walrus::ir::*;
+use walrus::{FunctionBuilder, Module, ValType};
+
+fn module() -> Module {
+    let config = walrus_tests::config();
+    Module::with_config(config)
+}
+
+#[test]
+fn results_follow_the_instructions() -> anyhow::Result<()> {
+    let mut module = module();
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    let mut body = builder.func_body();
+    // Every sequence is built with the wrong type.
+    body.block(ValType::F32, |block| {
+        block.i32_const(1).i64_const(2);
+    })
+    .drop()
+    .drop();
+    body.i32_const(0)
+        .if_else(
+            None,
+            |then| {
+                then.f64_const(1.0);
+            },
+            |else_| {
+                else_.f64_const(2.0);
+            },
+        )
+        .drop();
+    body.block(ValType::I32, |exit| {
+        let exit_id = exit.id();
+        exit.i32_const(3).br(exit_id);
+    })
+    .drop();
+    let f = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("f", f);
+
+    module.refinalize(f);
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    assert!(wat.contains("(type (;1;) (func (result i32 i64)))"));
+    assert!(wat.contains("block (result i32 i64)"));
+    assert!(wat.contains("if (result f64)"));
+    // The block's end is unreachable, so it keeps its result.
+    assert!(wat.contains("block (result i32)"));
+    Ok(())
+}
+
+#[test]
+fn removed_result() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (func (export "f")
+            (block (result i32)
+              (i32.const 1))
+            drop))
+        "#,
+    )?;
+    let mut module = Module::from_buffer(&wasm)?;
+    let (f, _) = module.funcs.iter_local().next().unwrap();
+
+    // Remove the block's result, and the `drop` of it.
+    let func = module.funcs.get_mut(f).kind.unwrap_local_mut();
+    let entry = func.entry_block();
+    func.block_mut(entry).instrs.pop();
+    let block = match &func.block(entry).instrs[0].0 {
+        Instr::Block(block) => block.seq,
+        _ => unreachable!(),
+    };
+    func.block_mut(block).instrs.clear();
+
+    module.refinalize(f);
+    let func = module.funcs.get(f).kind.unwrap_local();
+    assert!(matches!(func.block(block).ty, InstrSeqType::Simple(None)));
+    Module::from_buffer(&module.emit_wasm())?;
+    Ok(())
+}
@@ -0,0 +1,65 @@
+//! Tests for reordering functions by a profile.
+
+use walrus::passes::reorder::{self, Profile};
+use walrus::Module;
+
+const WAT: &str = r#"
+    (module
+      (import "env" "f" (func $import))
+      (func $small)
+      (func $large (result i32)
+        i32.const 1
+        i32.const 2
+        i32.add
+        i32.const 3
+        i32.add)
+      (func $medium
+        call $import
+        call $small)
+      (func $cold (export "cold")))
+"#;
+
+fn names(wasm: &[u8]) -> anyhow::Result<Vec<String>> {
+    let module = Module::from_buffer(wasm)?;
+    Ok(module
+        .funcs
+        .iter()
+        .map(|f| f.name.clone().unwrap_or_default())
+        .collect())
+}
+
+#[test]
+fn hot_functions_come_first() -> anyhow::Result<()> {
+    let mut module = Module::from_buffer(&wat::parse_str(WAT)?)?;
+    let mut profile = Profile::new();
+    profile
+        .add_name("small", 10)
+        .add_name("cold", 0)
+        .add_name("missing", 100)
+        // `medium` is the fourth function, counting the import.
+        .add_index(3, 5)
+        .add_index(0, 100);
+    reorder::run(&mut module, &profile);
+
+    let wasm = module.emit_wasm();
+    assert_eq!(
+        names(&wasm)?,
+        ["import", "small", "medium", "large", "cold"]
+    );
+    Ok(())
+}
+
+#[test]
+fn explicit_emit_order() -> anyhow::Result<()> {
+    let mut module = Module::from_buffer(&wat::parse_str(WAT)?)?;
+    assert!(module.funcs.emit_order().is_empty());
+    let cold = module.funcs.by_name("cold").unwrap();
+    let import = module.funcs.by_name("import").unwrap();
+    module.funcs.set_emit_order(vec![import, cold]);
+    let wasm = module.emit_wasm();
+    assert_eq!(
+        names(&wasm)?,
+        ["import", "cold", "large", "medium", "small"]
+    );
+    Ok(())
+}
@@ -0,0 +1,55 @@
+//! Tests for repairing malformed modules.
+
+use walrus::{ExportItem, Repair};
+
+#[test]
+fn repairs_common_corruption() -> anyhow::Result<()> {
+    let mut wasm = wat::parse_str(
+        r#"
+        (module
+          (memory 1)
+          (func (result i32)
+            (i32.load8_u align=4 (i32.const 0)))
+          (func (param i64)
+            (i64.store align=8 (i32.const 0) (local.get 0))
+            (i64.store16 offset=2 align=16 (i32.const 0) (local.get 0)))
+          (export "e" (func 0))
+          (export "f" (func 1))
+          (export "e" (func 1)))
+        "#,
+    )?;
+    // A `name` section naming the first function `f`, truncated in the middle
+    // of the name of the second one.
+    wasm.extend_from_slice(&[0x00, 14, 4, b'n', b'a', b'm', b'e', 1, 7]);
+    wasm.extend_from_slice(&[2, 0, 1, b'f', 1, 5, b'g']);
+
+    let mut config = walrus_tests::config();
+    config.strict_validate(false);
+    let mut module = config.parse(&wasm)?;
+    let f = module.funcs.by_original_index(0).unwrap();
+    let g = module.funcs.by_original_index(1).unwrap();
+    assert_eq!(module.funcs.get(f).name.as_deref(), Some("f"));
+    assert_eq!(module.funcs.get(g).name, None);
+
+    let report = module.repair();
+    assert_eq!(report.len(), 4);
+    assert!(matches!(&report[0], Repair::NameSection { error } if !error.is_empty()));
+    assert!(matches!(report[1], Repair::Alignment { func, count: 1 } if func == f));
+    assert!(matches!(report[2], Repair::Alignment { func, count: 1 } if func == g));
+    assert!(matches!(
+        &report[3],
+        Repair::DuplicateExport { name, item: ExportItem::Function(func) }
+            if name == "e" && *func == g
+    ));
+    assert!(module.repair().is_empty());
+
+    let wasm = walrus::round_trip::check(&mut module)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    assert!(wat.contains("(func $f (type 0) (result i32)\n    i32.const 0\n    i32.load8_u)"));
+    assert!(wat.contains("i64.store\n"));
+    assert!(wat.contains("i64.store16 offset=2)"));
+    assert!(wat.contains("(export \"e\" (func $f))"));
+    assert!(wat.contains("(export \"f\" (func 0))"));
+    assert_eq!(wat.matches("(export").count(), 2);
+    Ok(())
+}
@@ -0,0 +1,60 @@
+//! Tests for explaining why items are kept by the gc pass.
+
+use walrus::passes::retained::{Item, Retained, Root};
+use walrus::Module;
+
+#[test]
+fn shortest_paths_from_roots() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (global $g i32 (i32.const 0))
+          (table 1 funcref)
+          (elem (i32.const 0) $indirect)
+          (func $leaf)
+          (func $indirect
+            call $leaf)
+          (func $middle
+            call $leaf)
+          (func $dead
+            global.get $g
+            drop)
+          (func $main (export "main")
+            call $middle
+            i32.const 0
+            call_indirect))
+        "#,
+    )?;
+    let module = Module::from_buffer(&wasm)?;
+    let func = |name| Item::Function(module.funcs.by_name(name).unwrap());
+    let main = func("main");
+    let retained = Retained::new(&module);
+
+    let path = retained.path(func("leaf")).unwrap();
+    assert_eq!(
+        path.root,
+        Root::Export(module.exports.iter().next().unwrap().id())
+    );
+    assert_eq!(path.items, [main, func("middle"), func("leaf")]);
+
+    let table = Item::Table(module.tables.iter().next().unwrap().id());
+    let path = retained.path(func("indirect")).unwrap();
+    assert_eq!(path.items, [main, table, func("indirect")]);
+
+    assert!(!retained.is_retained(func("dead")));
+    assert!(retained.path(func("dead")).is_none());
+    let global = Item::Global(module.globals.iter().next().unwrap().id());
+    assert!(!retained.is_retained(global));
+    Ok(())
+}
+
+#[test]
+fn start_function_is_a_root() -> anyhow::Result<()> {
+    let wasm = wat::parse_str("(module (func $start) (start $start))")?;
+    let module = Module::from_buffer(&wasm)?;
+    let start = Item::Function(module.start.unwrap());
+    let path = Retained::new(&module).path(start).unwrap();
+    assert_eq!(path.root, Root::Start);
+    assert_eq!(path.items, [start]);
+    Ok(())
+}
@@ -16,7 +16,8 @@ fn run(wat_path: &Path) -> Result<(), anyhow::Error> {
 
     let out_wasm_file = wat_path.with_extension("out.wasm");
     walrus::passes::gc::run(&mut module);
-    module.emit_wasm_file(&out_wasm_file)?;
+    let out_wasm = walrus::round_trip::check(&mut module)?;
+    std::fs::write(&out_wasm_file, out_wasm)?;
 
     let out_wat = wasmprinter::print_file(&out_wasm_file)?;
     let checker = walrus_tests::FileCheck::from_file(wat_path);
@@ -0,0 +1,18 @@
+;; Passive data segments are only kept if `memory.init` or `data.drop` uses
+;; them.
+
+(module
+  (memory 1)
+  (func (export "a")
+    (memory.init 1
+      (i32.const 1)
+      (i32.const 2)
+      (i32.const 3)))
+  (data "unused")
+  (data "used"))
+
+;; CHECK:      memory.init 0
+
+;; CHECK:    (memory (;0;) 1)
+;; NEXT:    (export "a" (func 0))
+;; NEXT:    (data (;0;) "used"))
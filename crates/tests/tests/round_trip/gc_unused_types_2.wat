@@ -0,0 +1,26 @@
+;; Can remove the types that only unused functions refer to.
+
+(module
+  (type (;0;) (func (result i32)))
+  (type (;1;) (func (param i64)))
+  (type (;2;) (func (param f32)))
+  (type (;3;) (func (result i32 i32)))
+  (table 1 funcref)
+  (func $unused (type 1) (param i64)
+    (block (type 3)
+      i32.const 1
+      i32.const 2)
+    drop
+    drop
+    f32.const 1
+    i32.const 0
+    call_indirect (type 2))
+  (func $f (type 0) (result i32)
+    i32.const 42)
+  (export "f" (func $f)))
+
+;; CHECK: (module
+;; NEXT:    (type (;0;) (func (result i32)))
+;; NEXT:    (func $f (type 0) (result i32)
+;; NEXT:      i32.const 42)
+;; NEXT:    (export "f" (func $f)))
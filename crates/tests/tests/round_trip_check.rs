@@ -0,0 +1,34 @@
+//! Tests for checking that modules round trip.
+
+use walrus::{round_trip, FunctionBuilder, Module, ValType};
+
+#[test]
+fn valid_module_round_trips() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (memory 1)
+          (func (export "f") (param i32) (result i32)
+            (block (result i32)
+              (i32.load (local.get 0)))))
+        "#,
+    )?;
+    let mut module = Module::from_buffer(&wasm)?;
+    walrus::passes::dce_local::run(&mut module);
+    round_trip::assert_round_trips(&mut module);
+    let emitted = round_trip::check(&mut module)?;
+    assert_eq!(emitted, module.emit_wasm());
+    Ok(())
+}
+
+#[test]
+fn ill_typed_module_does_not_round_trip() {
+    let mut module = Module::default();
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[ValType::I32]);
+    builder.func_body().f32_const(1.0);
+    let f = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("f", f);
+
+    let err = round_trip::check(&mut module).unwrap_err();
+    assert!(format!("{}", err).contains("failed to parse the emitted module"));
+}
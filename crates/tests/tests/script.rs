@@ -0,0 +1,134 @@
+//! Tests for recording and replaying scripts of edits.
+
+use walrus::script::{Edit, Script, ScriptInstr};
+use walrus::Module;
+use walrus_tests::parse;
+
+const V1: &str = r#"
+    (module
+      (import "env" "log" (func $log (param i32)))
+      (func $main (export "main") (param i32)
+        (call $log (local.get 0)))
+      (func $unused (export "unused")))
+"#;
+
+// A newer build of the same module, with another function first.
+const V2: &str = r#"
+    (module
+      (import "env" "log" (func $log (param i32)))
+      (func $helper (result i32)
+        i32.const 7)
+      (func $main (export "main") (param i32)
+        (call $log (i32.add (local.get 0) (call $helper))))
+      (func $unused (export "unused")))
+"#;
+
+fn print(mut module: Module) -> anyhow::Result<String> {
+    wasmprinter::print_bytes(module.emit_wasm())
+}
+
+fn record(module: &mut Module) -> anyhow::Result<Script> {
+    let mut script = Script::new();
+    script.perform(
+        module,
+        Edit::Insert {
+            function: "main".to_string(),
+            position: 0,
+            instrs: vec![
+                ScriptInstr::I32Const(-1),
+                ScriptInstr::Call("log".to_string()),
+            ],
+        },
+    )?;
+    script.perform(
+        module,
+        Edit::DeleteFunction {
+            name: "unused".to_string(),
+        },
+    )?;
+    script.perform(
+        module,
+        Edit::RenameExport {
+            from: "main".to_string(),
+            to: "run \"it\"".to_string(),
+        },
+    )?;
+    script.perform(
+        module,
+        Edit::RenameFunction {
+            from: "main".to_string(),
+            to: "entry".to_string(),
+        },
+    )?;
+    Ok(script)
+}
+
+#[test]
+fn round_trips_through_text() -> anyhow::Result<()> {
+    let script = record(&mut parse(V1)?)?;
+    let text = script.to_string();
+    assert_eq!(
+        text,
+        r#"insert "main" 0 i32.const -1 call "log"
+delete-function "unused"
+rename-export "main" "run \"it\""
+rename-function "main" "entry"
+"#
+    );
+    assert_eq!(Script::parse(&format!("# A patch.\n\n{}", text))?, script);
+    Ok(())
+}
+
+#[test]
+fn replays_on_a_newer_build() -> anyhow::Result<()> {
+    let script = Script::parse(&record(&mut parse(V1)?)?.to_string())?;
+    let mut module = parse(V2)?;
+    script.replay(&mut module)?;
+    let wat = print(module)?;
+    assert!(wat.contains(
+        "(func $entry (type 2) (param i32)
+    i32.const -1
+    call $log
+    local.get 0
+    call $helper"
+    ));
+    assert!(wat.contains(r#"(export "run \22it\22" (func $entry))"#));
+    assert!(!wat.contains("unused"));
+    Ok(())
+}
+
+#[test]
+fn rejects_invalid_edits() -> anyhow::Result<()> {
+    let mut module = parse(V1)?;
+    let mut script = Script::new();
+    let insert = |instrs| Edit::Insert {
+        function: "main".to_string(),
+        position: 0,
+        instrs,
+    };
+    // Leaves a value on the stack.
+    assert!(script
+        .perform(&mut module, insert(vec![ScriptInstr::I32Const(1)]))
+        .is_err());
+    // Calls with the wrong argument types.
+    assert!(script
+        .perform(
+            &mut module,
+            insert(vec![
+                ScriptInstr::I64Const(1),
+                ScriptInstr::Call("log".to_string())
+            ])
+        )
+        .is_err());
+    // Deletes a function that is still called.
+    let delete = Edit::DeleteFunction {
+        name: "log".to_string(),
+    };
+    assert!(script.perform(&mut module, delete).is_err());
+    assert!(script.edits().is_empty());
+
+    assert!(Script::parse("rename-export \"a\"").is_err());
+    assert!(Script::parse("insert \"a\" 0 nop").is_err());
+    assert!(Script::parse("delete-export \"a").is_err());
+    Ok(())
+}
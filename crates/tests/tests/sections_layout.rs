@@ -0,0 +1,33 @@
+//! Tests for finding where sections were in the parsed binary.
+
+use walrus::{Module, SectionLayout};
+
+#[test]
+fn sections_layout() -> anyhow::Result<()> {
+    let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+    // Type section: `(func)`.
+    wasm.extend(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]);
+    // A custom section named "hi" with the payload "yo!".
+    wasm.extend(&[0x00, 0x06, 0x02, b'h', b'i', b'y', b'o', b'!']);
+
+    let module = Module::from_buffer(&wasm)?;
+    assert_eq!(
+        module.sections_layout(),
+        [
+            SectionLayout {
+                name: "type".to_string(),
+                custom: false,
+                range: 10..14,
+            },
+            SectionLayout {
+                name: "hi".to_string(),
+                custom: true,
+                range: 19..22,
+            },
+        ]
+    );
+    assert_eq!(&wasm[19..22], b"yo!");
+
+    assert!(Module::default().sections_layout().is_empty());
+    Ok(())
+}
@@ -0,0 +1,136 @@
+//! Tests for the `if`/`else` and `select` conversion pass.
+
+use walrus::passes::select::{self, Options};
+
+fn convert(body: &str, options: &Options) -> anyhow::Result<String> {
+    let wat = format!(
+        r#"
+        (module
+          (import "env" "f" (func $f (result i32)))
+          (func (param i32 i32) (result i32)
+            {}))
+        "#,
+        body
+    );
+    let mut module = walrus_tests::parse(wat)?;
+    select::run(&mut module, options);
+    let wasm = walrus_tests::emit(&mut module)?;
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    let body = &wat[wat.find("(func (;1;)").unwrap()..];
+    // Skip the function's header and the module's closing paren.
+    let body = &body[body.find('\n').unwrap() + 1..body.rfind(')').unwrap()];
+    Ok(body
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.starts_with("(local"))
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+#[test]
+fn converts_pure_arms_to_select() -> anyhow::Result<()> {
+    let body = convert(
+        "local.get 0
+         local.get 1
+         i32.eqz
+         i32.and
+         if (result i32)
+           local.get 0
+           i32.const 1
+           i32.add
+         else
+           i32.const 2
+         end",
+        &Options::size(),
+    )?;
+    assert_eq!(
+        body,
+        "local.get 0 i32.const 1 i32.add i32.const 2 \
+         local.get 0 local.get 1 i32.eqz i32.and select)"
+    );
+    Ok(())
+}
+
+#[test]
+fn saves_impure_conditions() -> anyhow::Result<()> {
+    let body = convert(
+        "call $f
+         if (result i32)
+           local.get 0
+         else
+           local.get 1
+         end",
+        &Options::speed(),
+    )?;
+    assert_eq!(
+        body,
+        "call $f local.set 2 local.get 0 local.get 1 local.get 2 select)"
+    );
+    Ok(())
+}
+
+#[test]
+fn keeps_impure_and_long_arms() -> anyhow::Result<()> {
+    let src = "local.get 0
+               if (result i32)
+                 call $f
+               else
+                 local.get 0
+                 local.get 1
+                 i32.div_u
+               end
+               local.get 0
+               if (result i32)
+                 local.get 0
+                 local.get 1
+                 i32.add
+                 i32.const 1
+                 i32.add
+               else
+                 local.get 1
+               end
+               i32.add";
+    let body = convert(src, &Options::speed())?;
+    assert!(!body.contains("select"));
+    let body = convert(src, &Options::size())?;
+    assert_eq!(body.matches("select").count(), 1);
+    assert_eq!(body.matches("if (result i32)").count(), 1);
+    Ok(())
+}
+
+#[test]
+fn converts_expensive_selects_to_branches() -> anyhow::Result<()> {
+    let src = "local.get 0
+               local.get 1
+               i32.mul
+               local.get 1
+               i32.mul
+               local.get 1
+               local.get 0
+               i32.mul
+               local.get 0
+               i32.mul
+               local.get 0
+               select";
+    let options = Options {
+        max_select_arm: 0,
+        min_branch_operands: Some(8),
+    };
+    let body = convert(src, &options)?;
+    assert_eq!(
+        body,
+        "local.get 0 if (result i32)  ;; label = @1 \
+         local.get 0 local.get 1 i32.mul local.get 1 i32.mul \
+         else \
+         local.get 1 local.get 0 i32.mul local.get 0 i32.mul \
+         end)"
+    );
+
+    let options = Options {
+        max_select_arm: 0,
+        min_branch_operands: Some(11),
+    };
+    let body = convert(src, &options)?;
+    assert!(body.contains("select"));
+    Ok(())
+}
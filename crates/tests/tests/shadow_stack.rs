@@ -0,0 +1,94 @@
+//! Tests for the shadow stack spilling pass.
+
+use walrus::{Module, ValType};
+use walrus_tests::parse;
+
+fn print(module: &mut Module) -> anyhow::Result<String> {
+    let wasm = walrus_tests::emit(module)?;
+    wasmprinter::print_bytes(&wasm)
+}
+
+#[test]
+fn spills_selected_locals_around_calls() -> anyhow::Result<()> {
+    let mut module = parse(
+        r#"
+        (module
+          (import "env" "collect" (func $collect))
+          (memory 1)
+          (global $sp (mut i32) (i32.const 1024))
+          (func (export "f") (param i32) (result i32)
+            (local i64 f64)
+            local.get 0
+            if
+              i32.const 1
+              return
+            end
+            i64.const 1
+            local.set 1
+            f64.const 1
+            local.set 2
+            call $collect
+            local.get 0)
+          (func (export "no_calls") (param i32) (result i32)
+            local.get 0))
+        "#,
+    )?;
+    let memory = module.memories.iter().next().unwrap().id();
+    let sp = module.globals.iter().next().unwrap().id();
+    walrus::passes::shadow_stack::run(&mut module, memory, sp, |local| {
+        local.ty() == ValType::I32 || local.ty() == ValType::F64
+    })?;
+
+    let wat = print(&mut module)?;
+    let f = &wat[wat.find("(func (;1;)").unwrap()..wat.find("(func (;2;)").unwrap()];
+    let no_calls = &wat[wat.find("(func (;2;)").unwrap()..];
+
+    // The frame is pushed on entry and popped after the body.
+    assert!(f.contains("global.get 0\n    i32.const 16\n    i32.sub\n    global.set 0"));
+    assert!(f.contains("global.get 0\n    i32.const 16\n    i32.add\n    global.set 0"));
+    // The `f64` gets the first slot since it is the largest, and the `i64`
+    // isn't spilled at all.
+    assert!(f.contains("f64.store\n"));
+    assert!(f.contains("local.get 0\n      i32.store offset=8\n      call $collect"));
+    assert!(f.contains("call $collect\n      global.get 0\n      f64.load\n"));
+    assert!(f.contains("i32.load offset=8\n      local.set 0"));
+    assert!(!f.contains("i64.store"));
+    // Returns branch out of the body so that the frame is still popped.
+    assert!(!f.contains("return"));
+
+    assert!(!no_calls.contains("global.get 0"));
+    Ok(())
+}
+
+#[test]
+fn rejects_anyref_locals() -> anyhow::Result<()> {
+    let mut module = parse(
+        r#"
+        (module
+          (import "env" "collect" (func $collect))
+          (memory 1)
+          (global $sp (mut i32) (i32.const 1024))
+          (func (param anyref)
+            call $collect))
+        "#,
+    )?;
+    let memory = module.memories.iter().next().unwrap().id();
+    let sp = module.globals.iter().next().unwrap().id();
+    assert!(walrus::passes::shadow_stack::run(&mut module, memory, sp, |_| true).is_err());
+    Ok(())
+}
+
+#[test]
+fn requires_mutable_i32_stack_pointer() -> anyhow::Result<()> {
+    let mut module = parse(
+        r#"
+        (module
+          (memory 1)
+          (global $sp i32 (i32.const 1024)))
+        "#,
+    )?;
+    let memory = module.memories.iter().next().unwrap().id();
+    let sp = module.globals.iter().next().unwrap().id();
+    assert!(walrus::passes::shadow_stack::run(&mut module, memory, sp, |_| true).is_err());
+    Ok(())
+}
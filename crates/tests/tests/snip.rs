@@ -0,0 +1,122 @@
+//! Tests for the snip pass.
+
+use walrus::passes::snip::{self, Options};
+use walrus::Module;
+
+const WAT: &str = r#"
+    (module
+      (func $_ZN4core9panicking5panic17h0123456789abcdefE
+        call $_ZN4core3fmt5write17h0123456789abcdefE)
+      (func $_ZN4core3fmt5write17h0123456789abcdefE
+        call $helper)
+      (func $helper)
+      (func $keep_me
+        call $helper)
+      (func $snip_me (result i32)
+        call $helper
+        i32.const 1)
+      (func (export "main") (result i32)
+        call $_ZN4core9panicking5panic17h0123456789abcdefE
+        call $keep_me
+        call $snip_me))
+"#;
+
+fn names(module: &Module) -> Vec<&str> {
+    let mut names = module
+        .funcs
+        .iter()
+        .filter_map(|f| f.name.as_deref())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+fn snip(options: &Options) -> anyhow::Result<Module> {
+    let mut module = Module::from_buffer(&wat::parse_str(WAT)?)?;
+    snip::run(&mut module, options)?;
+    Module::from_buffer(&module.emit_wasm())
+}
+
+#[test]
+fn snips_by_name() -> anyhow::Result<()> {
+    let module = snip(&Options {
+        functions: vec!["snip_me".to_string()],
+        ..Default::default()
+    })?;
+    // `helper` is still called by the other functions, so nothing is
+    // collected.
+    assert_eq!(
+        names(&module),
+        [
+            "_ZN4core3fmt5write17h0123456789abcdefE",
+            "_ZN4core9panicking5panic17h0123456789abcdefE",
+            "helper",
+            "keep_me",
+            "snip_me",
+        ]
+    );
+    let snipped = module.funcs.by_name("snip_me").unwrap();
+    let local = module.funcs.get(snipped).kind.unwrap_local();
+    let body = &local.block(local.entry_block()).instrs;
+    assert_eq!(body.len(), 1);
+    assert!(body[0].0.is_unreachable());
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "snip-patterns")]
+fn snips_by_name_and_pattern() -> anyhow::Result<()> {
+    let module = snip(&Options {
+        functions: vec!["snip_me".to_string()],
+        patterns: vec!["^_ZN4core9.*".to_string()],
+        ..Default::default()
+    })?;
+    // The `fmt` function was only called by the snipped panicking function.
+    assert_eq!(
+        names(&module),
+        [
+            "_ZN4core9panicking5panic17h0123456789abcdefE",
+            "helper",
+            "keep_me",
+            "snip_me",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn snips_rust_fmt_and_panicking_code() -> anyhow::Result<()> {
+    let module = snip(&Options {
+        snip_rust_fmt_code: true,
+        snip_rust_panicking_code: true,
+        ..Default::default()
+    })?;
+    assert_eq!(
+        names(&module),
+        [
+            "_ZN4core9panicking5panic17h0123456789abcdefE",
+            "helper",
+            "keep_me",
+            "snip_me",
+        ]
+    );
+
+    let module = snip(&Options {
+        snip_rust_fmt_code: true,
+        ..Default::default()
+    })?;
+    // The snipped `fmt` function is still called, so nothing is collected.
+    assert_eq!(names(&module).len(), 5);
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "snip-patterns")]
+fn rejects_invalid_patterns() {
+    let options = Options {
+        patterns: vec!["(".to_string()],
+        ..Default::default()
+    };
+    let mut module = Module::default();
+    assert!(snip::run(&mut module, &options).is_err());
+}
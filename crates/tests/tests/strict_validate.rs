@@ -0,0 +1,29 @@
+//! Tests for validating modules against the specification when parsing them.
+
+use walrus::{Module, ModuleConfig};
+
+#[test]
+fn rejects_invalid_function_bodies() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (memory 1)
+          (func)
+          (func (drop (i32.load align=8 (i32.const 0)))))
+        "#,
+    )?;
+    let err = Module::from_buffer(&wasm).unwrap_err();
+    let msg = err.to_string();
+    assert!(msg.contains("in the body of function 1"), "{}", msg);
+    assert!(
+        msg.contains("alignment must not be larger than natural"),
+        "{}",
+        msg
+    );
+
+    // Without strict validation, only walrus's own checks are done.
+    let mut config = ModuleConfig::new();
+    config.strict_validate(false);
+    config.parse(&wasm)?;
+    Ok(())
+}
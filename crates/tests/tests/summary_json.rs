@@ -0,0 +1,44 @@
+//! Tests for summarizing modules as JSON.
+
+use walrus::{Module, RawCustomSection};
+
+#[test]
+fn summarizes_modules() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (import "env" "log" (func (param i32)))
+          (import "env" "memory" (memory 1 2 shared))
+          (table 3 funcref)
+          (global (mut i32) (i32.const 0))
+          (func $main (export "main") (export "start") (param i64) (result f32)
+            (f32.const 0))
+          (export "table" (table 0)))
+        "#,
+    )?;
+    let mut module = Module::from_buffer(&wasm)?;
+    module.customs.add(RawCustomSection {
+        name: "a \"quoted\"\nname".to_string(),
+        data: vec![],
+    });
+    let expected = concat!(
+        r#"{"imports":[{"module":"env","name":"log","kind":"func"},"#,
+        r#"{"module":"env","name":"memory","kind":"memory"}],"#,
+        r#""exports":[{"name":"main","kind":"func"},{"name":"start","kind":"func"},"#,
+        r#"{"name":"table","kind":"table"}],"#,
+        r#""memories":[{"initial":1,"maximum":2,"shared":true,"#,
+        r#""import":{"module":"env","name":"memory"}}],"#,
+        r#""tables":[{"element":"funcref","initial":3,"maximum":null,"index":"i32","import":null}],"#,
+        r#""functions":[{"name":null,"params":["i32"],"results":[],"#,
+        r#""import":{"module":"env","name":"log"},"exports":[]},"#,
+        r#"{"name":"main","params":["i64"],"results":["f32"],"import":null,"#,
+        r#""exports":["main","start"]}],"#,
+        r#""customs":["a \"quoted\"\nname"]}"#,
+    );
+    assert_eq!(module.summary_json(), expected);
+    assert_eq!(
+        Module::default().summary_json(),
+        r#"{"imports":[],"exports":[],"memories":[],"tables":[],"functions":[],"customs":[]}"#
+    );
+    Ok(())
+}
@@ -0,0 +1,52 @@
+//! Tests for the names generated for anonymous items.
+
+use walrus::{Module, ModuleConfig, SyntheticName};
+
+const WAT: &str = r#"
+    (module
+      (import "env" "f" (func))
+      (func (param i32) (local i64)))
+"#;
+
+fn names(module: &Module) -> Vec<String> {
+    let mut names = module
+        .funcs
+        .iter()
+        .filter_map(|f| f.name.clone())
+        .collect::<Vec<_>>();
+    names.extend(module.locals.iter().filter_map(|l| l.name.clone()));
+    names
+}
+
+#[test]
+fn default_synthetic_names() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(WAT)?;
+    let module = ModuleConfig::new()
+        .generate_synthetic_names_for_anonymous_items(true)
+        .parse(&wasm)?;
+    assert_eq!(names(&module), ["f1", "arg0", "l1"]);
+    Ok(())
+}
+
+#[test]
+fn custom_synthetic_names() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(WAT)?;
+    let mut config = ModuleConfig::new();
+    config.synthetic_names(|item| match item {
+        SyntheticName::Function { index } => format!("wasm-function[{}]", index),
+        SyntheticName::Argument { func, index } => format!("${}_arg{}", func, index),
+        _ => item.default_name(),
+    });
+    let mut module = config.clone().parse(&wasm)?;
+    assert_eq!(names(&module), ["wasm-function[1]", "$1_arg0", "l1"]);
+
+    // Functions added from bytes are named the same way.
+    let ty = module.funcs.iter().last().unwrap().ty();
+    module.add_local_func_from_bytes(ty, &[0, 0x0b])?;
+    assert!(names(&module).contains(&"$2_arg0".to_string()));
+
+    // Turning synthetic names off again leaves items anonymous.
+    config.generate_synthetic_names_for_anonymous_items(false);
+    assert!(names(&config.parse(&wasm)?).is_empty());
+    Ok(())
+}
@@ -0,0 +1,88 @@
+//! Tests for tables indexed by `i64`s.
+
+use walrus::ir::Value;
+use walrus::{
+    FunctionBuilder, FunctionTable, IndexType, InitExpr, Module, ModuleConfig, TableKind, ValType,
+};
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[test]
+fn emit_64_bit_table() {
+    let mut module = Module::default();
+    let ty = module.types.add(&[], &[]);
+    let table = module
+        .tables
+        .add_local(1, Some(1), TableKind::Function(FunctionTable::default()));
+    module.tables.get_mut(table).index_type = IndexType::I64;
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body().i64_const(0).call_indirect(ty, table);
+    let f = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("f", f);
+    match &mut module.tables.get_mut(table).kind {
+        TableKind::Function(t) => t.elements.push(Some(f)),
+        TableKind::Anyref(_) => unreachable!(),
+    }
+    walrus::passes::validate::run(&module).unwrap();
+
+    let wasm = module.emit_wasm();
+    // The table section has a single `funcref` table with the 64-bit flag set
+    // next to the flag for its maximum.
+    assert!(contains(&wasm, &[0x01, 0x70, 0x05, 0x01, 0x01]));
+    // The element segment is placed with an `i64.const`.
+    assert!(contains(&wasm, &[0x00, 0x42, 0x00, 0x0b, 0x01, 0x00]));
+    // The callee index is an `i64`.
+    assert!(contains(&wasm, &[0x42, 0x00, 0x11, 0x00, 0x00, 0x0b]));
+}
+
+#[test]
+fn element_offsets_have_the_index_type() {
+    let mut module = Module::default();
+    let global32 = module
+        .globals
+        .add_local(ValType::I32, false, InitExpr::Value(Value::I32(0)));
+    let global64 = module
+        .globals
+        .add_local(ValType::I64, false, InitExpr::Value(Value::I64(0)));
+    let table = module
+        .tables
+        .add_local(1, None, TableKind::Function(FunctionTable::default()));
+    match &mut module.tables.get_mut(table).kind {
+        TableKind::Function(t) => t.relative_elements.push((global32, vec![None])),
+        TableKind::Anyref(_) => unreachable!(),
+    }
+    walrus::passes::validate::run(&module).unwrap();
+
+    module.tables.get_mut(table).index_type = IndexType::I64;
+    assert!(walrus::passes::validate::run(&module).is_err());
+    match &mut module.tables.get_mut(table).kind {
+        TableKind::Function(t) => t.relative_elements[0].0 = global64,
+        TableKind::Anyref(_) => unreachable!(),
+    }
+    walrus::passes::validate::run(&module).unwrap();
+}
+
+#[test]
+fn not_a_stable_feature() {
+    let mut config = ModuleConfig::new();
+    config.only_stable_features(true);
+    let mut module = Module::with_config(config);
+    let table = module
+        .tables
+        .add_local(1, None, TableKind::Function(FunctionTable::default()));
+    walrus::passes::validate::run(&module).unwrap();
+    module.tables.get_mut(table).index_type = IndexType::I64;
+    assert!(walrus::passes::validate::run(&module).is_err());
+}
+
+#[test]
+fn parsed_tables_are_32_bit() -> anyhow::Result<()> {
+    let module = Module::from_buffer(&wat::parse_str("(module (table 1 funcref))")?)?;
+    let table = module.tables.iter().next().unwrap();
+    assert_eq!(table.index_type, IndexType::I32);
+    assert_eq!(table.index_type.value_type(), ValType::I32);
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//! Tests for timing functions from within modules.
+
+use walrus::passes::timing::{self, Config, DUMP_EXPORT};
+use walrus::Module;
+use walrus_tests::parse;
+
+const WAT: &str = r#"
+    (module
+      (import "env" "now" (func $now (result f64)))
+      (import "env" "report" (func $report (param i32 i64 i64 f64)))
+      (memory (export "memory") 1)
+      (func $fib (export "fib") (param i32) (result i32)
+        (if (result i32) (i32.lt_u (local.get 0) (i32.const 2))
+          (then (local.get 0))
+          (else
+            (i32.add
+              (call $fib (i32.sub (local.get 0) (i32.const 1)))
+              (call $fib (i32.sub (local.get 0) (i32.const 2)))))))
+      (func $untimed (export "untimed")))
+"#;
+
+fn config(module: &Module, sample_every: u32) -> Config {
+    let find = |name| module.imports.find("env", name).unwrap();
+    let func = |name| match module.imports.get(find(name)).kind {
+        walrus::ImportKind::Function(func) => func,
+        _ => unreachable!(),
+    };
+    Config {
+        memory: module.memories.iter().next().unwrap().id(),
+        base: 1024,
+        now: func("now"),
+        report: func("report"),
+        sample_every,
+    }
+}
+
+fn instrument(sample_every: u32) -> anyhow::Result<String> {
+    let mut module = parse(WAT)?;
+    let config = config(&module, sample_every);
+    let timed = timing::run(&mut module, &config, |f| f.name.as_deref() == Some("fib"))?;
+    assert_eq!(timed, vec![module.funcs.by_name("fib").unwrap()]);
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)?;
+    wasmprinter::print_bytes(&wasm)
+}
+
+#[test]
+fn times_every_call() -> anyhow::Result<()> {
+    let wat = instrument(1)?;
+    // The export and the recursive calls go through the wrapper.
+    assert!(wat.contains("(export \"fib\" (func $timing:fib))"));
+    assert_eq!(wat.matches("call $timing:fib").count(), 2);
+    assert!(wat.contains("(export \"untimed\" (func $untimed))"));
+    assert!(wat.contains(
+        "(func $timing:fib (type 2) (param i32) (result i32)
+    (local i32 f64)
+    i32.const 0
+    i32.const 0
+    i64.load offset=1024
+    i64.const 1
+    i64.add
+    i64.store offset=1024
+    call $now
+    local.set 2
+    local.get 0
+    call $fib
+    local.set 1
+    i32.const 0
+    i32.const 0
+    f64.load offset=1040
+    call $now
+    local.get 2
+    f64.sub
+    f64.add
+    f64.store offset=1040"
+    ));
+    assert!(wat.contains(&format!(
+        "(export {:?} (func ${}))",
+        DUMP_EXPORT, DUMP_EXPORT
+    )));
+    assert!(wat.contains(
+        "i32.const 0
+    i32.const 0
+    i64.load offset=1024
+    i32.const 0
+    i64.load offset=1032
+    i32.const 0
+    f64.load offset=1040
+    call $report"
+    ));
+    Ok(())
+}
+
+#[test]
+fn samples_calls() -> anyhow::Result<()> {
+    let wat = instrument(16)?;
+    assert!(wat.contains("i64.const 16\n    i64.rem_u\n    i64.eqz\n    if"));
+    assert_eq!(wat.matches("call $fib").count(), 2);
+    Ok(())
+}
+
+#[test]
+fn rejects_invalid_configs() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    let mut bad = config(&module, 0);
+    assert!(timing::run(&mut module, &bad, |_| true).is_err());
+    bad.sample_every = 1;
+    bad.base = 1028;
+    assert!(timing::run(&mut module, &bad, |_| true).is_err());
+    bad.base = 1024;
+    bad.now = bad.report;
+    assert!(timing::run(&mut module, &bad, |_| true).is_err());
+    Ok(())
+}
@@ -0,0 +1,97 @@
+//! Tests for generating trampolines between function signatures.
+
+use walrus::ir::Value;
+use walrus::{AdaptSpec, AdaptValue, ImportKind, Module, ValType};
+
+#[test]
+fn adapts_arguments_and_results() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (import "env" "target" (func (param i64 i32 f32) (result i32 f64))))
+        "#,
+    )?;
+    let config = walrus_tests::config();
+    let mut module = config.parse(&wasm)?;
+    let target = match module
+        .imports
+        .get(module.imports.find("env", "target").unwrap())
+        .kind
+    {
+        ImportKind::Function(f) => f,
+        _ => unreachable!(),
+    };
+    let to_ty = module.funcs.get(target).ty();
+    let from_ty = module
+        .types
+        .add(&[ValType::I32, ValType::I32], &[ValType::F64]);
+
+    let mut adapt = AdaptSpec::new(&module, target);
+    adapt.params = vec![
+        AdaptValue::ExtendU(1),
+        AdaptValue::Index(0),
+        AdaptValue::Const(Value::F32(1.5)),
+    ];
+    adapt.results = vec![AdaptValue::Index(1)];
+    let trampoline = module.add_trampoline(from_ty, to_ty, adapt);
+    module.exports.add("trampoline", trampoline);
+
+    let wasm = walrus::round_trip::check(&mut module)?;
+    assert_eq!(
+        wasmprinter::print_bytes(&wasm)?,
+        r#"(module
+  (type (;0;) (func (param i32 i32) (result f64)))
+  (type (;1;) (func (param i64 i32 f32) (result i32 f64)))
+  (import "env" "target" (func (;0;) (type 1)))
+  (func (;1;) (type 0) (param i32 i32) (result f64)
+    (local i32 f64)
+    local.get 1
+    i64.extend_i32_u
+    local.get 0
+    f32.const 0x1.8p+0 (;=1.5;)
+    call 0
+    local.set 3
+    local.set 2
+    local.get 3)
+  (export "trampoline" (func 1)))"#
+    );
+    Ok(())
+}
+
+#[test]
+fn passes_results_through() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (func $target (export "target") (param i32) (result i64)
+            (i64.extend_i32_s (local.get 0))))
+        "#,
+    )?;
+    let mut module = Module::from_buffer(&wasm)?;
+    let target = module.funcs.by_name("target").unwrap();
+    let to_ty = module.funcs.get(target).ty();
+    let from_ty = module.types.add(&[ValType::I64], &[ValType::I64]);
+
+    let mut adapt = AdaptSpec::new(&module, target);
+    adapt.params = vec![AdaptValue::Wrap(0)];
+    let trampoline = module.add_trampoline(from_ty, to_ty, adapt);
+
+    let func = module.funcs.get(trampoline).kind.unwrap_local();
+    assert_eq!(func.block(func.entry_block()).instrs.len(), 3);
+    walrus::round_trip::check(&mut module)?;
+    Ok(())
+}
+
+#[test]
+#[should_panic(expected = "adapted value 0 has the wrong type")]
+fn rejects_mistyped_adaptations() {
+    let mut module = Module::default();
+    let target = module.types.add(&[ValType::I64], &[]);
+    let (target, _) = module.add_import_func("env", "target", target);
+    let to_ty = module.funcs.get(target).ty();
+    let from_ty = module.types.add(&[ValType::F32], &[]);
+
+    let mut adapt = AdaptSpec::new(&module, target);
+    adapt.params = vec![AdaptValue::ExtendS(0)];
+    module.add_trampoline(from_ty, to_ty, adapt);
+}
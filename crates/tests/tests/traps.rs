@@ -0,0 +1,84 @@
+//! Tests for finding the instructions that may trap.
+
+use walrus::passes::traps::{trapping_sites, TrapKind};
+use walrus::ModuleConfig;
+
+#[test]
+fn trapping_sites_in_order() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (import "env" "f" (func (param i32)))
+          (memory 1)
+          (table 1 funcref)
+          (func $arith (param i32 i32) (result i32)
+            (i32.add (local.get 0) (local.get 1))
+            (i32.div_u (local.get 0))
+            (i64.rem_s (i64.const 1) (i64.extend_i32_u (local.get 1)))
+            (i32.wrap_i64)
+            (i32.trunc_f32_s (f32.const 1))
+            (i32.trunc_sat_f32_s (f32.const 1))
+            (drop)
+            (i32.add)
+            (i32.add))
+          (func $access (param i32)
+            (block
+              (i32.store (local.get 0) (i32.load (local.get 0)))
+              (if (local.get 0)
+                (then (unreachable))
+                (else (call_indirect (param i32) (local.get 0) (local.get 0)))))
+            (drop (table.get (local.get 0)))
+            (memory.fill (local.get 0) (local.get 0) (local.get 0))
+            (call 0 (local.get 0))))
+        "#,
+    )?;
+    let mut config = ModuleConfig::new();
+    config.generate_name_section(false);
+    let mut module = config.parse(&wasm)?;
+
+    let sites = trapping_sites(&module);
+    let kinds = sites.iter().map(|s| s.kind).collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [
+            TrapKind::Division,
+            TrapKind::Division,
+            TrapKind::Conversion,
+            TrapKind::MemoryAccess,
+            TrapKind::MemoryAccess,
+            TrapKind::Unreachable,
+            TrapKind::IndirectCall,
+            TrapKind::TableAccess,
+            TrapKind::MemoryAccess,
+        ]
+    );
+
+    // Each site points at its instruction, both in the IR and in the binary
+    // the module was parsed from.
+    let opcodes = sites
+        .iter()
+        .map(|s| wasm[s.offset.unwrap() as usize])
+        .collect::<Vec<_>>();
+    assert_eq!(
+        opcodes,
+        [0x6e, 0x81, 0xa8, 0x28, 0x36, 0x00, 0x11, 0x25, 0xfc]
+    );
+    let arith = module.funcs.by_name("arith").unwrap();
+    assert!(sites[..3].iter().all(|s| s.func == arith));
+    let local = module.funcs.get(sites[3].func).kind.unwrap_local();
+    let (store, _) = &local.block(sites[4].seq).instrs[sites[4].index];
+    assert!(store.is_store());
+    assert_eq!(sites[4].seq, sites[3].seq);
+    assert_eq!(sites[4].index, sites[3].index + 1);
+    assert_ne!(sites[5].seq, sites[6].seq);
+    assert_eq!(sites[7].seq, local.entry_block());
+
+    // Instructions added after parsing have no offset.
+    let local = module.funcs.get_mut(arith).kind.unwrap_local_mut();
+    local.builder_mut().func_body().unreachable();
+    let sites = trapping_sites(&module);
+    let last = sites.iter().rfind(|s| s.func == arith).unwrap();
+    assert_eq!(last.kind, TrapKind::Unreachable);
+    assert_eq!(last.offset, None);
+    Ok(())
+}
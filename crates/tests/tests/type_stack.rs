@@ -0,0 +1,69 @@
+//! Tests for finding the types on the operand stack.
+
+use walrus::ir::*;
+use walrus::passes::type_stack;
+use walrus::{FunctionBuilder, Module, ValType};
+
+#[test]
+fn types_through_calls_and_blocks() -> anyhow::Result<()> {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (type $bt (func (param i64) (result i32)))
+          (func $f (param i32) (result i64) (i64.const 0))
+          (func $g (param f32) (result i32)
+            f64.const 1
+            i32.const 2
+            call $f
+            block (type $bt)
+              drop
+              local.get 0
+              drop
+              i32.const 5
+            end
+            drop
+            drop
+            i32.const 0))
+        "#,
+    )?;
+    let module = Module::from_buffer(&wasm)?;
+    let g = module.funcs.by_name("g").unwrap();
+    let func = module.funcs.get(g).kind.unwrap_local();
+    let types = type_stack::run(&module, func);
+
+    use ValType::*;
+    let entry = func.entry_block();
+    assert_eq!(types.before(entry, 0), Some(&[][..]));
+    assert_eq!(types.before(entry, 2), Some(&[F64, I32][..]));
+    assert_eq!(types.before(entry, 3), Some(&[F64, I64][..]));
+    assert_eq!(types.before(entry, 4), Some(&[F64, I32][..]));
+    assert_eq!(types.before(entry, 6), Some(&[][..]));
+
+    let block = match &func.block(entry).instrs[3].0 {
+        Instr::Block(block) => block.seq,
+        _ => unreachable!(),
+    };
+    assert_eq!(types.before(block, 0), Some(&[F64, I64][..]));
+    assert_eq!(types.before(block, 2), Some(&[F64, F32][..]));
+    Ok(())
+}
+
+#[test]
+fn unreachable_code_has_no_types() {
+    let mut module = Module::default();
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder
+        .func_body()
+        .i32_const(1)
+        .unreachable()
+        .i32_const(2)
+        .drop();
+    let id = builder.finish(vec![], &mut module.funcs);
+    let func = module.funcs.get(id).kind.unwrap_local();
+    let types = type_stack::run(&module, func);
+
+    let entry = func.entry_block();
+    assert_eq!(types.before(entry, 1), Some(&[ValType::I32][..]));
+    assert_eq!(types.before(entry, 2), None);
+    assert_eq!(types.before(entry, 3), None);
+}
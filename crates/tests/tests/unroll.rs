@@ -0,0 +1,72 @@
+//! Tests for unrolling counted loops.
+
+use walrus::ir::DefaultCost;
+use walrus::passes::unroll::{self, Options};
+use walrus::Module;
+
+const WAT: &str = r#"
+    (module
+      (memory 1)
+      (func $sum (export "sum") (result i32)
+        (local $i i32) (local $acc i32)
+        (local.set $i (i32.const 0))
+        (loop $l
+          (block $skip
+            (br_if $skip (i32.eqz (local.get $i)))
+            (local.set $acc (i32.add (local.get $acc) (i32.load (local.get $i)))))
+          (br_if $l (i32.lt_s (local.tee $i (i32.add (local.get $i) (i32.const 4)))
+                              (i32.const 12))))
+        (local.get $acc))
+      (func $long (export "long")
+        (local $i i32)
+        (local.set $i (i32.const 0))
+        (loop $l
+          (i32.store (local.get $i) (i32.const 0))
+          (local.set $i (i32.add (local.get $i) (i32.const 1)))
+          (br_if $l (i32.ne (local.get $i) (i32.const 100))))))
+"#;
+
+fn run(options: &Options) -> anyhow::Result<(usize, String)> {
+    let mut module = walrus_tests::parse(WAT)?;
+    let unrolled = unroll::run(&mut module, &DefaultCost, options);
+    let wasm = module.emit_wasm();
+    Module::from_buffer(&wasm)?;
+    Ok((unrolled, wasmprinter::print_bytes(&wasm)?))
+}
+
+#[test]
+fn unrolls_small_loops() -> anyhow::Result<()> {
+    let (unrolled, wat) = run(&Options::default())?;
+    // `sum` runs three times, but `long` runs too often.
+    assert_eq!(unrolled, 1);
+    let sum = &wat[wat.find("(func $sum").unwrap()..wat.find("(func $long").unwrap()];
+    assert!(!sum.contains("loop"));
+    assert!(!sum.contains("i32.lt_s"));
+    assert_eq!(sum.matches("i32.load").count(), 3);
+    assert_eq!(sum.matches("br_if 0 (;@2;)").count(), 3);
+    assert_eq!(
+        sum.matches("i32.const 4\n      i32.add\n      local.set $i")
+            .count(),
+        3
+    );
+    assert!(wat.contains("loop"));
+    Ok(())
+}
+
+#[test]
+fn respects_limits() -> anyhow::Result<()> {
+    let options = Options {
+        max_trip_count: 100,
+        max_growth: 0,
+    };
+    assert_eq!(run(&options)?.0, 0);
+    let options = Options {
+        max_trip_count: 100,
+        max_growth: 10_000,
+    };
+    let (unrolled, wat) = run(&options)?;
+    assert_eq!(unrolled, 2);
+    assert!(!wat.contains("loop"));
+    assert_eq!(wat.matches("i32.store").count(), 100);
+    Ok(())
+}
@@ -11,7 +11,11 @@ fn run(wat: &Path) -> Result<(), anyhow::Error> {
     let wasm = wat::parse_file(wat)?;
 
     // NB: reading the module will do the validation.
-    let module = walrus::Module::from_buffer(&wasm)?;
+    let mut module = walrus::Module::from_buffer(&wasm)?;
+
+    // Parsing from a reader should work just as well as from a buffer.
+    let mut from_reader = walrus::Module::from_reader(&wasm[..])?;
+    assert_eq!(from_reader.emit_wasm(), module.emit_wasm());
 
     if env::var("WALRUS_TESTS_DOT").is_ok() {
         module.write_graphviz_dot(wat.with_extension("dot"))?;
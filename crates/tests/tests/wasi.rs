@@ -0,0 +1,76 @@
+//! Tests for the WASI helpers.
+
+use std::collections::BTreeSet;
+use walrus::wasi::{self, Capability};
+use walrus::Module;
+use walrus_tests::parse;
+
+const WAT: &str = r#"
+    (module
+      (import "wasi_snapshot_preview1" "fd_write"
+        (func $fd_write (param i32 i32 i32 i32) (result i32)))
+      (import "wasi_snapshot_preview1" "random_get"
+        (func $random_get (param i32 i32) (result i32)))
+      (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+      (import "wasi_snapshot_preview1" "made_up" (func))
+      (import "env" "path_open" (func $path_open))
+      (memory (export "memory") 1)
+      (func (export "_start")
+        (drop (call $random_get (i32.const 0) (i32.const 8)))
+        (drop (call $fd_write (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+        call $path_open
+        (call $proc_exit (i32.const 0))))
+"#;
+
+#[test]
+fn classifies_imports() -> anyhow::Result<()> {
+    let module = parse(WAT)?;
+    let names = wasi::imports(&module).map(|i| i.name.as_str());
+    assert_eq!(
+        names.collect::<Vec<_>>(),
+        ["fd_write", "random_get", "proc_exit", "made_up"]
+    );
+    let expected = vec![
+        Capability::FileDescriptors,
+        Capability::Process,
+        Capability::Random,
+    ];
+    assert_eq!(
+        wasi::capabilities(&module),
+        expected.into_iter().collect::<BTreeSet<_>>()
+    );
+    assert_eq!(Capability::of("path_open"), Some(Capability::Filesystem));
+    assert_eq!(Capability::of("made_up"), None);
+    Ok(())
+}
+
+#[test]
+fn stubs_functions() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    assert!(wasi::stub(&mut module, "random_get", wasi::ERRNO_NOSYS));
+    assert!(wasi::stub(&mut module, "proc_exit", 0));
+    assert!(!wasi::stub(&mut module, "path_open", 0));
+    assert_eq!(
+        wasi::capabilities(&module),
+        Some(Capability::FileDescriptors).into_iter().collect()
+    );
+
+    let wasm = module.emit_wasm();
+    let wat = wasmprinter::print_bytes(&wasm)?;
+    Module::from_buffer(&wasm)?;
+    assert!(
+        wat.contains("(func $random_get (type 2) (param i32 i32) (result i32)\n    i32.const 52)")
+    );
+    assert!(wat.contains("(func $proc_exit (type 1) (param i32)\n    unreachable)"));
+    assert!(wat.contains("call $random_get"));
+    Ok(())
+}
+
+#[test]
+fn renames_functions() -> anyhow::Result<()> {
+    let mut module = parse(WAT)?;
+    assert!(wasi::rename(&mut module, "made_up", "sched_yield"));
+    assert!(!wasi::rename(&mut module, "path_open", "path_close"));
+    assert!(wasi::capabilities(&module).contains(&Capability::Scheduling));
+    Ok(())
+}
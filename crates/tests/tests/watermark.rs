@@ -0,0 +1,104 @@
+//! Tests for embedding and detecting watermarks.
+
+use walrus::passes::obfuscate;
+use walrus::passes::watermark::{self, Method};
+use walrus::Module;
+
+const KEY: u64 = 0x5eed;
+
+// A module with `n` distinct functions.
+fn module(n: usize) -> anyhow::Result<Module> {
+    let mut wat = String::from("(module\n");
+    for i in 0..n {
+        wat.push_str(&format!(
+            "(func $f{0} (export \"f{0}\") (result i32) (i32.const {0}))\n",
+            i
+        ));
+    }
+    wat.push(')');
+    walrus_tests::parse(&wat)
+}
+
+fn round_trip(module: &mut Module) -> anyhow::Result<Module> {
+    Module::from_buffer(&module.emit_wasm())
+}
+
+#[test]
+fn ordering() -> anyhow::Result<()> {
+    let mut m = module(100)?;
+    assert_eq!(watermark::capacity(&m, Method::Ordering), 4);
+    watermark::embed(&mut m, Method::Ordering, KEY, b"v1.2")?;
+    assert_eq!(
+        watermark::detect(&m, Method::Ordering, KEY),
+        Some(b"v1.2".to_vec())
+    );
+
+    let parsed = round_trip(&mut m)?;
+    assert_eq!(
+        watermark::detect(&parsed, Method::Ordering, KEY),
+        Some(b"v1.2".to_vec())
+    );
+    assert_eq!(watermark::detect(&parsed, Method::Ordering, KEY + 1), None);
+    assert_eq!(
+        watermark::detect(&round_trip(&mut module(100)?)?, Method::Ordering, KEY),
+        None
+    );
+
+    assert!(watermark::embed(&mut m, Method::Ordering, KEY, b"v1.23").is_err());
+    Ok(())
+}
+
+#[test]
+fn constants() -> anyhow::Result<()> {
+    let mut m = module(3)?;
+    assert_eq!(
+        watermark::capacity(&m, Method::Constants),
+        watermark::MAX_LEN
+    );
+    watermark::embed(&mut m, Method::Constants, KEY, b"customer 1234")?;
+
+    // Survives stripping names, renaming exports and reordering functions.
+    let mut parsed = round_trip(&mut m)?;
+    obfuscate::run(
+        &mut parsed,
+        &obfuscate::Options {
+            rename_exports: true,
+            ..obfuscate::Options::default()
+        },
+    );
+    let parsed = round_trip(&mut parsed)?;
+    assert_eq!(
+        watermark::detect(&parsed, Method::Constants, KEY),
+        Some(b"customer 1234".to_vec())
+    );
+    assert_eq!(watermark::detect(&parsed, Method::Constants, KEY + 1), None);
+    assert_eq!(watermark::detect(&module(3)?, Method::Constants, KEY), None);
+
+    assert!(watermark::embed(&mut module(0)?, Method::Constants, KEY, b"x").is_err());
+    Ok(())
+}
+
+#[test]
+fn ordering_ignores_indices() -> anyhow::Result<()> {
+    let mut wat = String::from("(module\n(func $callee (param i32))\n");
+    for i in 0..100 {
+        wat.push_str(&format!(
+            "(func (export \"f{}\") (call $callee (i32.const {})))\n",
+            i, i
+        ));
+    }
+    wat.push(')');
+    let mut m = walrus_tests::parse(&wat)?;
+    watermark::embed(&mut m, Method::Ordering, KEY, b"v1.2")?;
+
+    // A new import shifts the index of `$callee` in every call to it.
+    let mut parsed = round_trip(&mut m)?;
+    let ty = parsed.types.add(&[], &[]);
+    parsed.add_import_func("env", "new", ty);
+    let parsed = round_trip(&mut parsed)?;
+    assert_eq!(
+        watermark::detect(&parsed, Method::Ordering, KEY),
+        Some(b"v1.2".to_vec())
+    );
+    Ok(())
+}
@@ -0,0 +1,22 @@
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use walrus::fuzz::{self, Arbitrary, Unstructured};
+
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let mut module = walrus::Module::arbitrary(&mut u);
+    while !u.is_empty() {
+        fuzz::mutate(&mut module, &mut u);
+    }
+    let serialized = module.emit_wasm();
+    let mut module =
+        walrus::Module::from_buffer(&serialized).expect("generated modules should be valid");
+    let reserialized = module.emit_wasm();
+    assert_eq!(
+        serialized, reserialized,
+        "emitting wasm should be deterministic"
+    );
+});
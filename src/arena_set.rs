@@ -5,7 +5,7 @@ use std::hash::Hash;
 use std::ops;
 
 /// A set of unique `T`s that are backed by an arena.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ArenaSet<T: Clone + Eq + Hash> {
     arena: TombstoneArena<T>,
     already_in_arena: HashMap<T, Id<T>>,
@@ -45,6 +45,11 @@ impl<T: Clone + Eq + Hash> ArenaSet<T> {
         self.arena.delete(id);
     }
 
+    /// Get the item with the given id, if it hasn't been removed.
+    pub fn get(&self, id: Id<T>) -> Option<&T> {
+        self.arena.get(id)
+    }
+
     /// Iterate over the items in this arena and their ids.
     pub fn iter(&self) -> impl Iterator<Item = (Id<T>, &T)> {
         self.arena.iter()
@@ -0,0 +1,41 @@
+//! Cancelling long-running work on modules.
+
+use crate::error::{ErrorKind, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A token for cancelling the parsing, garbage collection and emission of a
+/// module from another thread.
+///
+/// A token is configured with `ModuleConfig::cancellation_token`, and clones
+/// of it share whether it is cancelled. Once cancelled, the work that checks
+/// it stops at the next opportunity and fails with `ErrorKind::Cancelled`.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Create a new token that isn't cancelled.
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancel the work checking this token, and any later work checking it.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether this token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fail with `ErrorKind::Cancelled` if `token` is a cancelled token.
+    pub(crate) fn check(token: Option<&CancellationToken>) -> Result<()> {
+        match token {
+            Some(token) if token.is_cancelled() => Err(ErrorKind::Cancelled.into()),
+            _ => Ok(()),
+        }
+    }
+}
@@ -433,6 +433,10 @@ impl DotNode for InstrSeq {
                 self.edges.add_edge_from_port(&self.port, data);
             }
 
+            fn visit_element_id(&mut self, elem: &crate::ElementId) {
+                self.edges.add_edge_from_port(&self.port, elem);
+            }
+
             fn visit_type_id(&mut self, ty: &crate::TypeId) {
                 self.edges.add_edge_from_port(&self.port, ty);
             }
@@ -527,7 +531,7 @@ impl DotNode for Element {
     }
 
     fn edges(&self, edges: &mut impl EdgeAggregator) {
-        for m in self.members.iter() {
+        for m in self.members.iter().flatten() {
             edges.add_edge(m);
         }
     }
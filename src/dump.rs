@@ -0,0 +1,547 @@
+//! A stable textual dump of the IR, for golden tests.
+//!
+//! Unlike printing a module as WAT with external tools, the dump preserves
+//! walrus-specific structure such as instruction sequences, and it doesn't
+//! depend on how ids happened to be allocated:
+//!
+//! * Items of the module are referred to by the index of their id, such as
+//!   `func[3]` or `memory[0]`, and are listed in that order.
+//!
+//! * Locals and instruction sequences are numbered per function, in the order
+//!   they first appear: parameters come first, as `l0`, `l1`, etc, and the
+//!   entry sequence is `seq0`.
+
+use crate::ir::*;
+use crate::*;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+impl Module {
+    /// Dump this module's IR as text, in a deterministic format suitable for
+    /// snapshot tests.
+    ///
+    /// The same module always dumps to the same text, no matter in which
+    /// order its locals and instruction sequences were created.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.dump_into(&mut out).unwrap();
+        out
+    }
+
+    fn dump_into(&self, out: &mut String) -> fmt::Result {
+        for ty in self.types.iter() {
+            write!(out, "type[{}]", ty.id().index())?;
+            params_and_results(out, ty.params(), ty.results())?;
+            writeln!(out)?;
+        }
+
+        for import in self.imports.iter() {
+            write!(
+                out,
+                "import[{}] {:?} {:?} ",
+                import.id().index(),
+                import.module,
+                import.name
+            )?;
+            match import.kind {
+                ImportKind::Function(id) => writeln!(out, "func[{}]", id.index())?,
+                ImportKind::Table(id) => writeln!(out, "table[{}]", id.index())?,
+                ImportKind::Memory(id) => writeln!(out, "memory[{}]", id.index())?,
+                ImportKind::Global(id) => writeln!(out, "global[{}]", id.index())?,
+            }
+        }
+
+        for table in self.tables.iter() {
+            write!(out, "table[{}]", table.id().index())?;
+            if table.index_type == IndexType::I64 {
+                write!(out, " i64")?;
+            }
+            limits(out, table.initial, table.maximum)?;
+            match &table.kind {
+                TableKind::Function(f) => {
+                    write!(out, " funcref")?;
+                    if !f.elements.is_empty() {
+                        write!(out, " ")?;
+                        members(out, &f.elements)?;
+                    }
+                    for (global, elements) in f.relative_elements.iter() {
+                        write!(out, " at global[{}] ", global.index())?;
+                        members(out, elements)?;
+                    }
+                }
+                TableKind::Anyref(_) => write!(out, " anyref")?,
+            }
+            import_of(out, table.import)?;
+            writeln!(out)?;
+        }
+
+        for memory in self.memories.iter() {
+            write!(out, "memory[{}]", memory.id().index())?;
+            limits(out, memory.initial, memory.maximum)?;
+            if memory.shared {
+                write!(out, " shared")?;
+            }
+            import_of(out, memory.import)?;
+            writeln!(out)?;
+        }
+
+        #[cfg(feature = "unstable")]
+        for tag in self.tags.iter() {
+            writeln!(out, "tag[{}] type[{}]", tag.id().index(), tag.ty.index())?;
+        }
+
+        for global in self.globals.iter() {
+            write!(out, "global[{}] {}", global.id().index(), global.ty)?;
+            if global.mutable {
+                write!(out, " mut")?;
+            }
+            match global.kind {
+                GlobalKind::Import(id) => import_of(out, Some(id))?,
+                GlobalKind::Local(InitExpr::Value(value)) => {
+                    write!(out, " = ")?;
+                    constant(out, value)?;
+                }
+                GlobalKind::Local(InitExpr::Global(id)) => {
+                    write!(out, " = global[{}]", id.index())?;
+                }
+            }
+            writeln!(out)?;
+        }
+
+        for func in self.funcs.iter() {
+            write!(out, "func[{}]", func.id().index())?;
+            if let Some(name) = &func.name {
+                write!(out, " {:?}", name)?;
+            }
+            write!(out, " type[{}]", func.ty().index())?;
+            match &func.kind {
+                FunctionKind::Import(i) => {
+                    import_of(out, Some(i.import))?;
+                    writeln!(out)?;
+                }
+                FunctionKind::Local(l) => {
+                    writeln!(out)?;
+                    FunctionDump::new(l, Some(&self.locals)).write(out, 1)?;
+                }
+                FunctionKind::Uninitialized(_) => writeln!(out, " uninitialized")?,
+            }
+        }
+
+        for export in self.exports.iter() {
+            write!(out, "export {:?} ", export.name)?;
+            match export.item {
+                ExportItem::Function(id) => writeln!(out, "func[{}]", id.index())?,
+                ExportItem::Table(id) => writeln!(out, "table[{}]", id.index())?,
+                ExportItem::Memory(id) => writeln!(out, "memory[{}]", id.index())?,
+                ExportItem::Global(id) => writeln!(out, "global[{}]", id.index())?,
+            }
+        }
+
+        if let Some(start) = self.start {
+            writeln!(out, "start func[{}]", start.index())?;
+        }
+
+        for elem in self.elements.iter() {
+            write!(out, "elem[{}]", elem.id().index())?;
+            match elem.kind {
+                ElementKind::Passive => write!(out, " passive ")?,
+                ElementKind::Declared => write!(out, " declared ")?,
+            }
+            members(out, &elem.members)?;
+            writeln!(out)?;
+        }
+
+        for data in self.data.iter() {
+            write!(out, "data[{}]", data.id().index())?;
+            match &data.kind {
+                DataKind::Passive => write!(out, " passive")?,
+                DataKind::Active(active) => {
+                    write!(out, " memory[{}]", active.memory.index())?;
+                    match active.location {
+                        ActiveDataLocation::Absolute(offset) => write!(out, " at {}", offset)?,
+                        ActiveDataLocation::Relative(id) => {
+                            write!(out, " at global[{}]", id.index())?;
+                        }
+                    }
+                }
+            }
+            write!(out, " ")?;
+            string(out, &data.value)?;
+            writeln!(out)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Print a `LocalFunction`'s body in the format of `Module::dump`.
+///
+/// Since a `LocalFunction` doesn't know about the module's locals, the types
+/// of its locals are left out.
+impl fmt::Display for LocalFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        FunctionDump::new(self, None).write(&mut out, 0)?;
+        f.write_str(&out)
+    }
+}
+
+fn params_and_results(out: &mut String, params: &[ValType], results: &[ValType]) -> fmt::Result {
+    if !params.is_empty() {
+        write!(out, " (param")?;
+        for ty in params {
+            write!(out, " {}", ty)?;
+        }
+        write!(out, ")")?;
+    }
+    if !results.is_empty() {
+        write!(out, " (result")?;
+        for ty in results {
+            write!(out, " {}", ty)?;
+        }
+        write!(out, ")")?;
+    }
+    Ok(())
+}
+
+fn limits(out: &mut String, initial: u32, maximum: Option<u32>) -> fmt::Result {
+    write!(out, " {}", initial)?;
+    if let Some(maximum) = maximum {
+        write!(out, " {}", maximum)?;
+    }
+    Ok(())
+}
+
+fn import_of(out: &mut String, import: Option<ImportId>) -> fmt::Result {
+    match import {
+        Some(id) => write!(out, " import[{}]", id.index()),
+        None => Ok(()),
+    }
+}
+
+fn members(out: &mut String, members: &[Option<FunctionId>]) -> fmt::Result {
+    write!(out, "[")?;
+    for (i, member) in members.iter().enumerate() {
+        if i > 0 {
+            write!(out, " ")?;
+        }
+        match member {
+            Some(id) => write!(out, "func[{}]", id.index())?,
+            None => write!(out, "null")?,
+        }
+    }
+    write!(out, "]")
+}
+
+/// Write `bytes` as a string in the text format, escaping anything that
+/// isn't printable ASCII.
+fn string(out: &mut String, bytes: &[u8]) -> fmt::Result {
+    out.push('"');
+    for byte in bytes {
+        match byte {
+            b'"' | b'\\' => write!(out, "\\{}", *byte as char)?,
+            0x20..=0x7e => out.push(*byte as char),
+            _ => write!(out, "\\{:02x}", byte)?,
+        }
+    }
+    out.push('"');
+    Ok(())
+}
+
+fn constant(out: &mut String, value: Value) -> fmt::Result {
+    match value {
+        Value::I32(n) => write!(out, "i32.const {}", n),
+        Value::I64(n) => write!(out, "i64.const {}", n),
+        // Floats are printed with their bits, so that NaN payloads and the
+        // sign of zero are visible too.
+        Value::F32(n) => write!(out, "f32.const {:?} (0x{:08x})", n, n.to_bits()),
+        Value::F64(n) => write!(out, "f64.const {:?} (0x{:016x})", n, n.to_bits()),
+        Value::V128(n) => write!(out, "v128.const 0x{:032x}", n),
+    }
+}
+
+fn mem_arg(out: &mut String, arg: MemArg) -> fmt::Result {
+    write!(out, " offset={} align={}", arg.offset, arg.align)
+}
+
+/// The state for dumping one function, which numbers its locals and
+/// instruction sequences as they are encountered.
+struct FunctionDump<'a> {
+    func: &'a LocalFunction,
+    types: Option<&'a ModuleLocals>,
+    locals: HashMap<LocalId, usize>,
+    local_order: Vec<LocalId>,
+    seqs: HashMap<InstrSeqId, usize>,
+}
+
+impl<'a> FunctionDump<'a> {
+    fn new(func: &'a LocalFunction, types: Option<&'a ModuleLocals>) -> FunctionDump<'a> {
+        let mut dump = FunctionDump {
+            func,
+            types,
+            locals: HashMap::new(),
+            local_order: Vec::new(),
+            seqs: HashMap::new(),
+        };
+        for arg in func.args.iter() {
+            dump.local(*arg);
+        }
+        dump
+    }
+
+    fn local(&mut self, id: LocalId) -> usize {
+        let next = self.locals.len();
+        let order = &mut self.local_order;
+        *self.locals.entry(id).or_insert_with(|| {
+            order.push(id);
+            next
+        })
+    }
+
+    fn seq(&mut self, id: InstrSeqId) -> usize {
+        let next = self.seqs.len();
+        *self.seqs.entry(id).or_insert(next)
+    }
+
+    /// Write this function's locals and body, indented by `indent` levels.
+    ///
+    /// The body is dumped first, so that all the locals it uses are numbered
+    /// by the time they are declared.
+    fn write(mut self, out: &mut String, indent: usize) -> fmt::Result {
+        let mut body = String::new();
+        let entry = self.func.entry_block();
+        write_indent(&mut body, indent)?;
+        // The entry's type is the function's, which is already known.
+        writeln!(body, "entry seq{}", self.seq(entry))?;
+        self.instrs(&mut body, entry, indent + 1)?;
+
+        let args = self.func.args.len();
+        for (i, id) in self.local_order.iter().enumerate() {
+            write_indent(out, indent)?;
+            let kind = if i < args { "param" } else { "local" };
+            write!(out, "{} l{}", kind, i)?;
+            if let Some(types) = self.types {
+                write!(out, " {}", types.get(*id).ty())?;
+            }
+            writeln!(out)?;
+        }
+        out.push_str(&body);
+        Ok(())
+    }
+
+    fn seq_type(&self, out: &mut String, id: InstrSeqId) -> fmt::Result {
+        match self.func.block(id).ty {
+            InstrSeqType::Simple(None) => Ok(()),
+            InstrSeqType::Simple(Some(ty)) => write!(out, " (result {})", ty),
+            InstrSeqType::MultiValue(ty) => write!(out, " (type type[{}])", ty.index()),
+        }
+    }
+
+    /// Write a nested sequence's header and instructions.
+    fn nested(
+        &mut self,
+        out: &mut String,
+        what: &str,
+        id: InstrSeqId,
+        indent: usize,
+    ) -> fmt::Result {
+        write_indent(out, indent)?;
+        write!(out, "{} seq{}", what, self.seq(id))?;
+        self.seq_type(out, id)?;
+        writeln!(out)?;
+        self.instrs(out, id, indent + 1)
+    }
+
+    fn instrs(&mut self, out: &mut String, id: InstrSeqId, indent: usize) -> fmt::Result {
+        let func = self.func;
+        for (instr, _) in func.block(id).instrs.iter() {
+            self.instr(out, instr, indent)?;
+        }
+        Ok(())
+    }
+
+    fn instr(&mut self, out: &mut String, instr: &Instr, indent: usize) -> fmt::Result {
+        match instr {
+            Instr::Block(e) => {
+                self.nested(out, "block", e.seq, indent)?;
+                return end(out, indent);
+            }
+            Instr::Loop(e) => {
+                self.nested(out, "loop", e.seq, indent)?;
+                return end(out, indent);
+            }
+            Instr::IfElse(e) => {
+                self.nested(out, "if", e.consequent, indent)?;
+                self.nested(out, "else", e.alternative, indent)?;
+                return end(out, indent);
+            }
+            _ => {}
+        }
+
+        write_indent(out, indent)?;
+        match instr {
+            Instr::Block(_) | Instr::Loop(_) | Instr::IfElse(_) => unreachable!(),
+            Instr::Call(e) => write!(out, "call func[{}]", e.func.index())?,
+            Instr::CallIndirect(e) => write!(
+                out,
+                "call_indirect table[{}] type[{}]",
+                e.table.index(),
+                e.ty.index()
+            )?,
+            Instr::LocalGet(e) => write!(out, "local.get l{}", self.local(e.local))?,
+            Instr::LocalSet(e) => write!(out, "local.set l{}", self.local(e.local))?,
+            Instr::LocalTee(e) => write!(out, "local.tee l{}", self.local(e.local))?,
+            Instr::GlobalGet(e) => write!(out, "global.get global[{}]", e.global.index())?,
+            Instr::GlobalSet(e) => write!(out, "global.set global[{}]", e.global.index())?,
+            Instr::Const(e) => constant(out, e.value)?,
+            Instr::Binop(e) => write!(out, "binop {:?}", e.op)?,
+            Instr::Unop(e) => write!(out, "unop {:?}", e.op)?,
+            Instr::Select(e) => match e.ty {
+                Some(ty) => write!(out, "select {}", ty)?,
+                None => write!(out, "select")?,
+            },
+            Instr::Unreachable(_) => write!(out, "unreachable")?,
+            Instr::Br(e) => write!(out, "br seq{}", self.seq(e.block))?,
+            Instr::BrIf(e) => write!(out, "br_if seq{}", self.seq(e.block))?,
+            Instr::BrTable(e) => {
+                write!(out, "br_table [")?;
+                for (i, block) in e.blocks.iter().enumerate() {
+                    if i > 0 {
+                        write!(out, " ")?;
+                    }
+                    write!(out, "seq{}", self.seq(*block))?;
+                }
+                write!(out, "] seq{}", self.seq(e.default))?;
+            }
+            Instr::Drop(_) => write!(out, "drop")?,
+            Instr::Return(_) => write!(out, "return")?,
+            Instr::MemorySize(e) => write!(out, "memory.size memory[{}]", e.memory.index())?,
+            Instr::MemoryGrow(e) => write!(out, "memory.grow memory[{}]", e.memory.index())?,
+            Instr::MemoryInit(e) => write!(
+                out,
+                "memory.init memory[{}] data[{}]",
+                e.memory.index(),
+                e.data.index()
+            )?,
+            Instr::DataDrop(e) => write!(out, "data.drop data[{}]", e.data.index())?,
+            Instr::MemoryCopy(e) => write!(
+                out,
+                "memory.copy memory[{}] memory[{}]",
+                e.src.index(),
+                e.dst.index()
+            )?,
+            Instr::MemoryFill(e) => write!(out, "memory.fill memory[{}]", e.memory.index())?,
+            Instr::Load(e) => {
+                write!(out, "load memory[{}] {:?}", e.memory.index(), e.kind)?;
+                mem_arg(out, e.arg)?;
+            }
+            Instr::Store(e) => {
+                write!(out, "store memory[{}] {:?}", e.memory.index(), e.kind)?;
+                mem_arg(out, e.arg)?;
+            }
+            Instr::AtomicRmw(e) => {
+                write!(
+                    out,
+                    "atomic.rmw memory[{}] {:?} {:?}",
+                    e.memory.index(),
+                    e.op,
+                    e.width
+                )?;
+                mem_arg(out, e.arg)?;
+            }
+            Instr::Cmpxchg(e) => {
+                write!(
+                    out,
+                    "atomic.cmpxchg memory[{}] {:?}",
+                    e.memory.index(),
+                    e.width
+                )?;
+                mem_arg(out, e.arg)?;
+            }
+            Instr::AtomicNotify(e) => {
+                write!(out, "atomic.notify memory[{}]", e.memory.index())?;
+                mem_arg(out, e.arg)?;
+            }
+            Instr::AtomicWait(e) => {
+                let ty = if e.sixty_four { "i64" } else { "i32" };
+                write!(out, "atomic.wait memory[{}] {}", e.memory.index(), ty)?;
+                mem_arg(out, e.arg)?;
+            }
+            Instr::AtomicFence(_) => write!(out, "atomic.fence")?,
+            Instr::TableGet(e) => write!(out, "table.get table[{}]", e.table.index())?,
+            Instr::TableSet(e) => write!(out, "table.set table[{}]", e.table.index())?,
+            Instr::TableGrow(e) => write!(out, "table.grow table[{}]", e.table.index())?,
+            Instr::TableSize(e) => write!(out, "table.size table[{}]", e.table.index())?,
+            Instr::TableFill(e) => write!(out, "table.fill table[{}]", e.table.index())?,
+            Instr::TableInit(e) => write!(
+                out,
+                "table.init table[{}] elem[{}]",
+                e.table.index(),
+                e.elem.index()
+            )?,
+            Instr::ElemDrop(e) => write!(out, "elem.drop elem[{}]", e.elem.index())?,
+            Instr::TableCopy(e) => write!(
+                out,
+                "table.copy table[{}] table[{}]",
+                e.src.index(),
+                e.dst.index()
+            )?,
+            Instr::RefNull(_) => write!(out, "ref.null")?,
+            Instr::RefIsNull(_) => write!(out, "ref.is_null")?,
+            Instr::RefFunc(e) => write!(out, "ref.func func[{}]", e.func.index())?,
+            Instr::V128Bitselect(_) => write!(out, "v128.bitselect")?,
+            Instr::V128Swizzle(_) => write!(out, "v128.swizzle")?,
+            Instr::V128Shuffle(e) => {
+                write!(out, "v128.shuffle")?;
+                for idx in e.indices.iter() {
+                    write!(out, " {}", idx)?;
+                }
+            }
+            Instr::LoadSimd(e) => {
+                write!(out, "load_simd memory[{}] {:?}", e.memory.index(), e.kind)?;
+                mem_arg(out, e.arg)?;
+            }
+            #[cfg(feature = "unstable")]
+            Instr::Pause(_) => write!(out, "pause")?,
+            #[cfg(feature = "unstable")]
+            Instr::GlobalAtomicGet(e) => write!(
+                out,
+                "global.atomic.get global[{}] {:?}",
+                e.global.index(),
+                e.ordering
+            )?,
+            #[cfg(feature = "unstable")]
+            Instr::GlobalAtomicSet(e) => write!(
+                out,
+                "global.atomic.set global[{}] {:?}",
+                e.global.index(),
+                e.ordering
+            )?,
+            #[cfg(feature = "unstable")]
+            Instr::ContNew(e) => write!(out, "cont.new type[{}]", e.ty.index())?,
+            #[cfg(feature = "unstable")]
+            Instr::Suspend(e) => write!(out, "suspend tag[{}]", e.tag.index())?,
+            #[cfg(feature = "unstable")]
+            Instr::Resume(e) => {
+                write!(out, "resume type[{}]", e.ty.index())?;
+                for handler in e.handlers.iter() {
+                    let (tag, block) = (handler.tag.index(), self.seq(handler.block));
+                    write!(out, " (on tag[{}] seq{})", tag, block)?;
+                }
+            }
+        }
+        writeln!(out)
+    }
+}
+
+fn end(out: &mut String, indent: usize) -> fmt::Result {
+    write_indent(out, indent)?;
+    writeln!(out, "end")
+}
+
+fn write_indent(out: &mut String, indent: usize) -> fmt::Result {
+    for _ in 0..indent {
+        out.write_str("  ")?;
+    }
+    Ok(())
+}
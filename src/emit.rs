@@ -5,9 +5,11 @@
 use crate::encode::{Encoder, MAX_U32_LENGTH};
 use crate::ir::Local;
 use crate::map::{IdHashMap, IdHashSet};
+use crate::parse::IndexSpaces;
+use crate::{AddressMap, CancellationToken, Type, TypeId};
 use crate::{CodeTransform, Global, GlobalId, Memory, MemoryId, Module, Table, TableId};
 use crate::{Data, DataId, Element, ElementId, Function, FunctionId};
-use crate::{Type, TypeId};
+use id_arena::Id;
 use std::ops::{Deref, DerefMut};
 
 pub struct EmitContext<'a> {
@@ -16,6 +18,8 @@ pub struct EmitContext<'a> {
     pub encoder: Encoder<'a>,
     pub locals: IdHashMap<Function, IdHashSet<Local>>,
     pub code_transform: CodeTransform,
+    pub address_map: Option<&'a mut AddressMap>,
+    pub cancel: Option<&'a CancellationToken>,
 }
 
 pub struct SubContext<'a, 'cx> {
@@ -51,6 +55,8 @@ pub struct IdsToIndices {
     memories: IdHashMap<Memory, u32>,
     elements: IdHashMap<Element, u32>,
     data: IdHashMap<Data, u32>,
+    #[cfg(feature = "unstable")]
+    tags: IdHashMap<crate::Tag, u32>,
     pub(crate) locals: IdHashMap<Function, IdHashMap<Local, u32>>,
 }
 
@@ -103,13 +109,80 @@ define_get_push_index! {
     get_func_index, push_func, FunctionId, funcs;
     get_global_index, push_global, GlobalId, globals;
     get_memory_index, push_memory, MemoryId, memories;
-    get_element_index, push_element, ElementId, elements;
+}
+#[cfg(feature = "unstable")]
+define_get_push_index! {
+    get_tag_index, push_tag, crate::TagId, tags;
 }
 define_get_index! {
+    get_element_index, ElementId, elements;
     get_data_index, DataId, data;
 }
 
 impl IdsToIndices {
+    /// Get the index for the given function, if it was emitted.
+    pub(crate) fn find_func_index(&self, id: FunctionId) -> Option<u32> {
+        self.funcs.get(&id).copied()
+    }
+
+    /// Get the index for the given global, if it was emitted.
+    pub(crate) fn find_global_index(&self, id: GlobalId) -> Option<u32> {
+        self.globals.get(&id).copied()
+    }
+
+    /// Get the index for the given table, if it was emitted.
+    pub(crate) fn find_table_index(&self, id: TableId) -> Option<u32> {
+        self.tables.get(&id).copied()
+    }
+
+    /// Get the index for the given memory, if it was emitted.
+    pub(crate) fn find_memory_index(&self, id: MemoryId) -> Option<u32> {
+        self.memories.get(&id).copied()
+    }
+
+    /// The functions, globals, tables and memories that were emitted, by
+    /// their index.
+    pub(crate) fn index_spaces(&self) -> IndexSpaces {
+        fn by_index<T>(indices: &IdHashMap<T, u32>) -> Vec<Id<T>> {
+            let mut ids = indices.iter().map(|(id, i)| (*i, *id)).collect::<Vec<_>>();
+            ids.sort_unstable_by_key(|(i, _)| *i);
+            ids.into_iter().map(|(_, id)| id).collect()
+        }
+
+        IndexSpaces {
+            funcs: by_index(&self.funcs),
+            globals: by_index(&self.globals),
+            tables: by_index(&self.tables),
+            memories: by_index(&self.memories),
+        }
+    }
+
+    /// Indices that put every item of `module` at index 0, for encoding
+    /// instructions regardless of what they refer to.
+    pub(crate) fn zeroed(module: &Module) -> IdsToIndices {
+        fn zeroed<T>(ids: impl Iterator<Item = Id<T>>) -> IdHashMap<T, u32> {
+            ids.map(|id| (id, 0)).collect()
+        }
+
+        IdsToIndices {
+            tables: zeroed(module.tables.iter().map(|t| t.id())),
+            types: zeroed(module.types.iter().map(|t| t.id())),
+            funcs: zeroed(module.funcs.iter().map(|f| f.id())),
+            globals: zeroed(module.globals.iter().map(|g| g.id())),
+            memories: zeroed(module.memories.iter().map(|m| m.id())),
+            elements: zeroed(module.elements.iter().map(|e| e.id())),
+            data: zeroed(module.data.iter().map(|d| d.id())),
+            #[cfg(feature = "unstable")]
+            tags: zeroed(module.tags.iter().map(|t| t.id())),
+            locals: Default::default(),
+        }
+    }
+
+    /// Sets the element index to the specified value
+    pub(crate) fn set_element_index(&mut self, id: ElementId, idx: u32) {
+        self.elements.insert(id, idx);
+    }
+
     /// Sets the data index to the specified value
     pub(crate) fn set_data_index(&mut self, id: DataId, idx: u32) {
         self.data.insert(id, idx);
@@ -121,6 +194,12 @@ impl<'a> EmitContext<'a> {
         self.subsection(id as u8)
     }
 
+    /// Start a section or subsection with the given `id`, whose contents are
+    /// written to the returned context.
+    ///
+    /// Sections are framed here rather than with wasm-encoder, since their
+    /// contents are written in place: the size is reserved as a padded
+    /// LEB128 and filled in once the context is dropped.
     pub fn subsection<'b>(&'b mut self, id: u8) -> SubContext<'a, 'b> {
         self.encoder.byte(id);
         let start = self.encoder.reserve_u32();
@@ -186,4 +265,6 @@ pub enum Section {
     Code = 10,
     Data = 11,
     DataCount = 12,
+    #[cfg(feature = "unstable")]
+    Tag = 13,
 }
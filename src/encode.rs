@@ -1,3 +1,5 @@
+use wasm_encoder::Encode;
+
 pub const MAX_U32_LENGTH: usize = 5;
 
 #[derive(Debug)]
@@ -14,13 +16,18 @@ impl<'data> Encoder<'data> {
         self.dst.push(byte);
     }
 
+    /// Writes anything that `wasm-encoder` knows how to encode, such as an
+    /// instruction or a value type.
+    pub fn encode<T: Encode + ?Sized>(&mut self, value: &T) {
+        value.encode(self.dst);
+    }
+
     pub fn bytes(&mut self, bytes: &[u8]) {
-        self.usize(bytes.len());
-        self.raw(bytes);
+        self.encode(bytes);
     }
 
     pub fn str(&mut self, data: &str) {
-        self.bytes(data.as_bytes())
+        self.encode(data);
     }
 
     pub fn usize(&mut self, amt: usize) {
@@ -29,29 +36,35 @@ impl<'data> Encoder<'data> {
     }
 
     pub fn u32(&mut self, amt: u32) {
-        leb128::write::unsigned(&mut self.dst, amt.into()).unwrap();
+        self.encode(&amt);
+    }
+
+    /// Writes a uleb128 `u32` padded to at least `width` bytes.
+    pub fn u32_padded(&mut self, mut amt: u32, width: usize) {
+        let width = width.min(MAX_U32_LENGTH);
+        let mut written = 1;
+        while amt >= 0x80 || written < width {
+            self.byte((amt as u8) & 0x7f | 0x80);
+            amt >>= 7;
+            written += 1;
+        }
+        self.byte(amt as u8);
     }
 
     pub fn i32(&mut self, val: i32) {
-        leb128::write::signed(&mut self.dst, val.into()).unwrap();
+        self.encode(&val);
     }
 
     pub fn i64(&mut self, val: i64) {
-        leb128::write::signed(&mut self.dst, val).unwrap();
+        self.encode(&val);
     }
 
     pub fn f32(&mut self, val: f32) {
-        let bits = val.to_bits();
-        for i in 0..4 {
-            self.byte((bits >> (i * 8)) as u8);
-        }
+        self.encode(&wasm_encoder::Ieee32::new(val.to_bits()));
     }
 
     pub fn f64(&mut self, val: f64) {
-        let bits = val.to_bits();
-        for i in 0..8 {
-            self.byte((bits >> (i * 8)) as u8);
-        }
+        self.encode(&wasm_encoder::Ieee64::new(val.to_bits()));
     }
 
     pub fn raw(&mut self, raw: &[u8]) {
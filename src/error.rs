@@ -6,22 +6,91 @@ use std::fmt;
 /// Either `Ok(T)` or `Err(failure::Error)`.
 pub use anyhow::Result;
 
-/// A leaf wasm error type.
+/// What kind of error an `Error` is, for callers that handle some kinds of
+/// errors differently.
 ///
-/// Just an enum with no further information. Extra diagnostics are attached via
-/// failure's `context` method.
+/// Extra diagnostics are attached via anyhow's `context` method, so the kind
+/// of an error is found with `ErrorKind::of`, rather than from its message.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[non_exhaustive]
 pub enum ErrorKind {
-    /// Given invalid input wasm.
-    InvalidWasm,
+    /// The input wasm is malformed, and can't be decoded.
+    Parse,
+    /// The input wasm decodes, but isn't valid.
+    Validate,
+    /// The input wasm uses a feature that walrus doesn't support, or that was
+    /// disabled, as with `ModuleConfig::only_stable_features`.
+    Unsupported {
+        /// The feature, such as the name of a proposal like `simd`.
+        feature: &'static str,
+    },
+    /// Parsing the input wasm would take more than one of the limits set with
+    /// `ModuleConfig`, such as `ModuleConfig::max_functions`.
+    LimitExceeded,
+    /// Emitting a module failed.
+    Emit,
+    /// The work was cancelled through a `CancellationToken`.
+    Cancelled,
+}
+
+impl ErrorKind {
+    /// Get the kind of `error`, if it is known.
+    ///
+    /// Errors from parsing a module always have a kind; other errors may not.
+    pub fn of(error: &Error) -> Option<ErrorKind> {
+        if let Some(kind) = error.downcast_ref::<ErrorKind>() {
+            return Some(*kind);
+        }
+        error.downcast_ref::<Classified>().map(|c| c.kind)
+    }
+
+    /// Give `error` this kind, unless it already has one.
+    pub(crate) fn classify(self, error: Error) -> Error {
+        if ErrorKind::of(&error).is_some() {
+            return error;
+        }
+        Error::new(Classified { kind: self, error })
+    }
 }
 
 impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ErrorKind::InvalidWasm => "The input WebAssembly is invalid".fmt(f),
+            ErrorKind::Parse => "The input WebAssembly is malformed".fmt(f),
+            ErrorKind::Validate => "The input WebAssembly is invalid".fmt(f),
+            ErrorKind::Unsupported { feature } => {
+                write!(f, "The input WebAssembly uses unsupported `{}`", feature)
+            }
+            ErrorKind::LimitExceeded => "The input WebAssembly exceeds a limit".fmt(f),
+            ErrorKind::Emit => "Failed to emit the WebAssembly".fmt(f),
+            ErrorKind::Cancelled => "The work was cancelled".fmt(f),
         }
     }
 }
 
 impl std::error::Error for ErrorKind {}
+
+/// An error given a kind after the fact, which displays and has the same
+/// sources as the error itself.
+struct Classified {
+    kind: ErrorKind,
+    error: Error,
+}
+
+impl fmt::Debug for Classified {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.error, f)
+    }
+}
+
+impl fmt::Display for Classified {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for Classified {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.chain().nth(1)
+    }
+}
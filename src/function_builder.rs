@@ -1,6 +1,7 @@
 use crate::ir::*;
 use crate::tombstone_arena::TombstoneArena;
-use crate::{FunctionId, LocalFunction, ModuleFunctions, ModuleTypes, TypeId, ValType};
+use crate::{DataId, ElementId, FunctionId, LocalFunction, MemoryId, ModuleFunctions};
+use crate::{ModuleLocals, ModuleTypes, TableId, TypeId, ValType};
 use std::ops::{Deref, DerefMut};
 
 /// Build instances of `LocalFunction`.
@@ -12,7 +13,7 @@ use std::ops::{Deref, DerefMut};
 ///
 /// * For a bit more realistic example, see
 ///   [`examples/build-wasm-from-scratch.rs`](https://github.com/rustwasm/walrus/blob/master/examples/build-wasm-from-scratch.rs).
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FunctionBuilder {
     pub(crate) arena: TombstoneArena<InstrSeq>,
     pub(crate) ty: TypeId,
@@ -171,6 +172,14 @@ impl InstrSeqBuilder<'_> {
         self.id
     }
 
+    /// Name this instruction sequence's label, for the `name` section.
+    ///
+    /// The label of an `if`/`else` is named by naming its consequent.
+    pub fn name(&mut self, name: String) -> &mut Self {
+        self.builder.arena[self.id].name = Some(name);
+        self
+    }
+
     /// Get this instruction sequence's instructions.
     pub fn instrs(&self) -> &[(Instr, InstrLocId)] {
         &self.builder.arena[self.id]
@@ -227,6 +236,84 @@ impl InstrSeqBuilder<'_> {
         self.const_(Value::F64(val))
     }
 
+    /// Creates an `i32.extend8_s` instruction.
+    #[inline]
+    pub fn i32_extend8_s(&mut self) -> &mut Self {
+        self.unop(UnaryOp::I32Extend8S)
+    }
+
+    /// Creates an `i32.extend16_s` instruction.
+    #[inline]
+    pub fn i32_extend16_s(&mut self) -> &mut Self {
+        self.unop(UnaryOp::I32Extend16S)
+    }
+
+    /// Creates an `i64.extend8_s` instruction.
+    #[inline]
+    pub fn i64_extend8_s(&mut self) -> &mut Self {
+        self.unop(UnaryOp::I64Extend8S)
+    }
+
+    /// Creates an `i64.extend16_s` instruction.
+    #[inline]
+    pub fn i64_extend16_s(&mut self) -> &mut Self {
+        self.unop(UnaryOp::I64Extend16S)
+    }
+
+    /// Creates an `i64.extend32_s` instruction.
+    #[inline]
+    pub fn i64_extend32_s(&mut self) -> &mut Self {
+        self.unop(UnaryOp::I64Extend32S)
+    }
+
+    /// Creates an `i32.trunc_sat_f32_s` instruction.
+    #[inline]
+    pub fn i32_trunc_sat_f32_s(&mut self) -> &mut Self {
+        self.unop(UnaryOp::I32TruncSSatF32)
+    }
+
+    /// Creates an `i32.trunc_sat_f32_u` instruction.
+    #[inline]
+    pub fn i32_trunc_sat_f32_u(&mut self) -> &mut Self {
+        self.unop(UnaryOp::I32TruncUSatF32)
+    }
+
+    /// Creates an `i32.trunc_sat_f64_s` instruction.
+    #[inline]
+    pub fn i32_trunc_sat_f64_s(&mut self) -> &mut Self {
+        self.unop(UnaryOp::I32TruncSSatF64)
+    }
+
+    /// Creates an `i32.trunc_sat_f64_u` instruction.
+    #[inline]
+    pub fn i32_trunc_sat_f64_u(&mut self) -> &mut Self {
+        self.unop(UnaryOp::I32TruncUSatF64)
+    }
+
+    /// Creates an `i64.trunc_sat_f32_s` instruction.
+    #[inline]
+    pub fn i64_trunc_sat_f32_s(&mut self) -> &mut Self {
+        self.unop(UnaryOp::I64TruncSSatF32)
+    }
+
+    /// Creates an `i64.trunc_sat_f32_u` instruction.
+    #[inline]
+    pub fn i64_trunc_sat_f32_u(&mut self) -> &mut Self {
+        self.unop(UnaryOp::I64TruncUSatF32)
+    }
+
+    /// Creates an `i64.trunc_sat_f64_s` instruction.
+    #[inline]
+    pub fn i64_trunc_sat_f64_s(&mut self) -> &mut Self {
+        self.unop(UnaryOp::I64TruncSSatF64)
+    }
+
+    /// Creates an `i64.trunc_sat_f64_u` instruction.
+    #[inline]
+    pub fn i64_trunc_sat_f64_u(&mut self) -> &mut Self {
+        self.unop(UnaryOp::I64TruncUSatF64)
+    }
+
     /// Append a new, nested `block ... end` to this builder's sequence.
     ///
     /// # Example:
@@ -491,6 +578,293 @@ impl InstrSeqBuilder<'_> {
             },
         )
     }
+
+    /// Append a `memory.init` copying all of the `len` bytes of the data
+    /// segment `data` into `memory`, followed by a `data.drop` of the segment.
+    ///
+    /// Like `memory.init`, this expects the destination address on the stack.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut module = walrus::Module::default();
+    /// let memory = module.memories.add_local(false, 1, None);
+    /// let data = module.data.add_passive(b"hello".to_vec());
+    ///
+    /// // Lazily copy the segment to address 1024.
+    /// let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[]);
+    /// builder
+    ///     .func_body()
+    ///     .i32_const(1024)
+    ///     .memory_init_and_drop(memory, data, 5);
+    /// ```
+    pub fn memory_init_and_drop(&mut self, memory: MemoryId, data: DataId, len: u32) -> &mut Self {
+        self.i32_const(0)
+            .i32_const(len as i32)
+            .memory_init(memory, data)
+            .data_drop(data)
+    }
+
+    /// Append a `table.init` copying all of the `len` members of the element
+    /// segment `elem` into `table`, followed by an `elem.drop` of the segment.
+    ///
+    /// Like `table.init`, this expects the destination index on the stack.
+    pub fn table_init_and_drop(&mut self, table: TableId, elem: ElementId, len: u32) -> &mut Self {
+        self.i32_const(0)
+            .i32_const(len as i32)
+            .table_init(table, elem)
+            .elem_drop(elem)
+    }
+
+    /// Append a compare-and-exchange loop that atomically replaces a value in
+    /// memory with a new value computed from the old value.
+    ///
+    /// Like the `atomic.rmw` instructions, this expects the address on the
+    /// stack, and leaves the value that was replaced on the stack.
+    ///
+    /// The instructions built by `compute` are run with the old value on the
+    /// stack, and must leave the new value on the stack. If another thread
+    /// modified the value in the meantime, the new value is recomputed from
+    /// the updated old value and the exchange is retried.
+    ///
+    /// The values are `i32`s for the `AtomicWidth::I32*` widths and `i64`s
+    /// for the `AtomicWidth::I64*` widths. Fresh locals for the address and
+    /// intermediate values are added to `locals`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use walrus::ir::{AtomicWidth, BinaryOp, MemArg};
+    /// use walrus::ValType;
+    ///
+    /// let mut module = walrus::Module::default();
+    /// let memory = module.memories.add_local(true, 1, Some(1));
+    ///
+    /// // Atomically multiply the `i32` at address 16 by two.
+    /// let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[ValType::I32]);
+    /// let arg = MemArg::new(4, 0);
+    /// builder
+    ///     .func_body()
+    ///     .i32_const(16)
+    ///     .atomic_rmw_loop(&mut module.locals, memory, AtomicWidth::I32, arg, |new| {
+    ///         new.i32_const(2).binop(BinaryOp::I32Mul);
+    ///     });
+    /// ```
+    pub fn atomic_rmw_loop(
+        &mut self,
+        locals: &mut ModuleLocals,
+        memory: MemoryId,
+        width: AtomicWidth,
+        arg: MemArg,
+        compute: impl FnOnce(&mut InstrSeqBuilder),
+    ) -> &mut Self {
+        use crate::ir::ExtendedLoad::ZeroExtendAtomic;
+
+        let (ty, kind) = match width {
+            AtomicWidth::I32 => (ValType::I32, LoadKind::I32 { atomic: true }),
+            AtomicWidth::I32_8 => (
+                ValType::I32,
+                LoadKind::I32_8 {
+                    kind: ZeroExtendAtomic,
+                },
+            ),
+            AtomicWidth::I32_16 => (
+                ValType::I32,
+                LoadKind::I32_16 {
+                    kind: ZeroExtendAtomic,
+                },
+            ),
+            AtomicWidth::I64 => (ValType::I64, LoadKind::I64 { atomic: true }),
+            AtomicWidth::I64_8 => (
+                ValType::I64,
+                LoadKind::I64_8 {
+                    kind: ZeroExtendAtomic,
+                },
+            ),
+            AtomicWidth::I64_16 => (
+                ValType::I64,
+                LoadKind::I64_16 {
+                    kind: ZeroExtendAtomic,
+                },
+            ),
+            AtomicWidth::I64_32 => (
+                ValType::I64,
+                LoadKind::I64_32 {
+                    kind: ZeroExtendAtomic,
+                },
+            ),
+        };
+        let ne = if ty == ValType::I32 {
+            BinaryOp::I32Ne
+        } else {
+            BinaryOp::I64Ne
+        };
+        let addr = locals.add(ValType::I32);
+        let old = locals.add(ty);
+        let loaded = locals.add(ty);
+
+        self.local_tee(addr)
+            .load(memory, kind, arg)
+            .local_set(old)
+            .loop_(None, |retry| {
+                let retry_id = retry.id();
+                retry.local_get(addr).local_get(old).local_get(old);
+                compute(retry);
+                retry
+                    .cmpxchg(memory, width, arg)
+                    .local_tee(loaded)
+                    .local_get(old)
+                    .binop(ne)
+                    .if_else(
+                        None,
+                        |changed| {
+                            changed.local_get(loaded).local_set(old).br(retry_id);
+                        },
+                        |_| {},
+                    );
+            })
+            .local_get(old)
+    }
+
+    /// Append an atomic fetch-and-add which traps if the (unsigned) addition
+    /// overflows `width`.
+    ///
+    /// Like `atomic.rmw.add`, this expects the address and the value to add
+    /// on the stack, and leaves the value from before the addition on the
+    /// stack. Unlike `atomic.rmw.add`, the memory is left untouched when the
+    /// addition overflows.
+    ///
+    /// This is built on top of `atomic_rmw_loop`, see its documentation for
+    /// details on the types involved.
+    pub fn atomic_fetch_add_checked(
+        &mut self,
+        locals: &mut ModuleLocals,
+        memory: MemoryId,
+        width: AtomicWidth,
+        arg: MemArg,
+    ) -> &mut Self {
+        use crate::ir::BinaryOp::*;
+
+        // For the narrow widths, the result must also fit back into the narrow
+        // width, since the old value is zero-extended.
+        let (ty, add, lt_u, gt_u, max) = match width {
+            AtomicWidth::I32 => (ValType::I32, I32Add, I32LtU, I32GtU, None),
+            AtomicWidth::I32_8 => (ValType::I32, I32Add, I32LtU, I32GtU, Some(Value::I32(0xff))),
+            AtomicWidth::I32_16 => (
+                ValType::I32,
+                I32Add,
+                I32LtU,
+                I32GtU,
+                Some(Value::I32(0xffff)),
+            ),
+            AtomicWidth::I64 => (ValType::I64, I64Add, I64LtU, I64GtU, None),
+            AtomicWidth::I64_8 => (ValType::I64, I64Add, I64LtU, I64GtU, Some(Value::I64(0xff))),
+            AtomicWidth::I64_16 => (
+                ValType::I64,
+                I64Add,
+                I64LtU,
+                I64GtU,
+                Some(Value::I64(0xffff)),
+            ),
+            AtomicWidth::I64_32 => (
+                ValType::I64,
+                I64Add,
+                I64LtU,
+                I64GtU,
+                Some(Value::I64(0xffff_ffff)),
+            ),
+        };
+        let delta = locals.add(ty);
+        let old = locals.add(ty);
+        let new = locals.add(ty);
+
+        self.local_set(delta);
+        self.atomic_rmw_loop(locals, memory, width, arg, |rmw| {
+            // new = old + delta
+            rmw.local_tee(old)
+                .local_get(delta)
+                .binop(add)
+                .local_tee(new)
+                // overflowed = new < old
+                .local_get(new)
+                .local_get(old)
+                .binop(lt_u);
+            if let Some(max) = max {
+                // overflowed |= new > max
+                rmw.local_get(new).const_(max).binop(gt_u).binop(I32Or);
+            }
+            rmw.if_else(
+                None,
+                |overflowed| {
+                    overflowed.unreachable();
+                },
+                |_| {},
+            );
+        })
+    }
+
+    /// Splice instructions into this sequence at the given position, saving
+    /// the values on the stack there to fresh locals before them, and
+    /// restoring the values afterwards.
+    ///
+    /// `stack` is the types of the values that this sequence has on the stack
+    /// before `position`, from the bottom up, as given by
+    /// `passes::type_stack::TypeStack::seq_before`. The instructions built by
+    /// `make` run with none of these values on the stack, and must leave the
+    /// stack as they found it, but they can read the values from the locals,
+    /// which are passed in the same order as `stack`. The locals are added to
+    /// `locals`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `position > self.instrs.len()`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use walrus::ValType;
+    ///
+    /// let mut module = walrus::Module::default();
+    /// let log_ty = module.types.add(&[ValType::I32], &[]);
+    /// let (log, _) = module.add_import_func("env", "log", log_ty);
+    /// let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[]);
+    /// builder.func_body().i32_const(1).i32_const(2).drop().drop();
+    ///
+    /// // Log the value that the first `drop` drops.
+    /// builder.func_body().insert_preserving_stack(
+    ///     2,
+    ///     &[ValType::I32, ValType::I32],
+    ///     &mut module.locals,
+    ///     |log_it, saved| {
+    ///         log_it.local_get(saved[1]).call(log);
+    ///     },
+    /// );
+    /// ```
+    pub fn insert_preserving_stack(
+        &mut self,
+        position: usize,
+        stack: &[ValType],
+        locals: &mut ModuleLocals,
+        make: impl FnOnce(&mut InstrSeqBuilder, &[LocalId]),
+    ) -> &mut Self {
+        let saved = stack.iter().map(|ty| locals.add(*ty)).collect::<Vec<_>>();
+        let mut code = self.dangling_instr_seq(None);
+        for local in saved.iter().rev() {
+            code.local_set(*local);
+        }
+        make(&mut code, &saved);
+        for local in saved.iter() {
+            code.local_get(*local);
+        }
+
+        // The instructions were only built in their own sequence to give
+        // `make` a builder, so move them over and delete it.
+        let code = code.id;
+        let instrs = std::mem::take(&mut self.builder.arena[code].instrs);
+        self.builder.arena.delete(code);
+        self.instrs_mut().splice(position..position, instrs);
+        self
+    }
 }
 
 impl Deref for InstrSeqBuilder<'_> {
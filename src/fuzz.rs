@@ -0,0 +1,861 @@
+//! Generating and mutating random, valid modules, for fuzzing.
+//!
+//! This module lets pass authors differential-fuzz their transforms: generate
+//! a module from the fuzzer's input with `Module::arbitrary`, or `mutate` an
+//! existing one, run the pass, and compare the behavior of the module before
+//! and after.
+//!
+//! The `Unstructured` and `Arbitrary` types mirror those of the [`arbitrary`]
+//! crate, so that fuzz targets read the same, but they don't depend on it.
+//!
+//! Generated modules only use MVP numeric instructions and always validate.
+//! Functions only call functions that were generated before them and contain
+//! no loops, so running any of them always terminates, although it may trap.
+//!
+//! [`arbitrary`]: https://crates.io/crates/arbitrary
+
+use crate::ir::*;
+use crate::{
+    ActiveDataLocation, FunctionBuilder, FunctionId, GlobalId, GlobalKind, InitExpr,
+    InstrSeqBuilder, MemoryId, Module, ValType,
+};
+use std::ops::RangeInclusive;
+
+/// A source of random decisions, backed by raw fuzzer input.
+///
+/// Once the input is exhausted, every decision is made as if the remaining
+/// input were all zeros, so that generation always finishes.
+#[derive(Debug)]
+pub struct Unstructured<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Unstructured<'a> {
+    /// Make decisions based on `data`.
+    pub fn new(data: &'a [u8]) -> Unstructured<'a> {
+        Unstructured { data }
+    }
+
+    /// Get the number of bytes of input that are left.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Is the input exhausted?
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Take up to `len` bytes of input.
+    pub fn bytes(&mut self, len: usize) -> &'a [u8] {
+        let (bytes, rest) = self.data.split_at(len.min(self.data.len()));
+        self.data = rest;
+        bytes
+    }
+
+    /// Take an arbitrary `u64`, consuming up to eight bytes.
+    pub fn u64(&mut self) -> u64 {
+        self.bytes(8)
+            .iter()
+            .fold(0, |acc, byte| (acc << 8) | u64::from(*byte))
+    }
+
+    /// Pick an integer in `range`, consuming only as many bytes as the size
+    /// of the range requires.
+    pub fn int_in_range(&mut self, range: RangeInclusive<u32>) -> u32 {
+        let (start, end) = (*range.start(), *range.end());
+        assert!(start <= end);
+        let width = u64::from(end - start);
+        let mut value = 0u64;
+        let mut seen = 0u64;
+        while seen < width && !self.data.is_empty() {
+            value = (value << 8) | u64::from(self.bytes(1)[0]);
+            seen = (seen << 8) | 0xff;
+        }
+        start + (value % (width + 1)) as u32
+    }
+
+    /// Return `true` with a probability of about `numerator / denominator`.
+    pub fn ratio(&mut self, numerator: u8, denominator: u8) -> bool {
+        assert!(0 < numerator && numerator <= denominator);
+        self.int_in_range(1..=u32::from(denominator)) <= u32::from(numerator)
+    }
+
+    /// Pick one of `choices`, or `None` if there are none.
+    pub fn choose<'b, T>(&mut self, choices: &'b [T]) -> Option<&'b T> {
+        if choices.is_empty() {
+            return None;
+        }
+        let last = choices.len().min(u32::MAX as usize) - 1;
+        let i = self.int_in_range(0..=last as u32);
+        Some(&choices[i as usize])
+    }
+}
+
+/// Things that can be generated from fuzzer input.
+pub trait Arbitrary: Sized {
+    /// Generate an arbitrary value, making decisions with `u`.
+    fn arbitrary(u: &mut Unstructured) -> Self;
+}
+
+impl Arbitrary for ValType {
+    fn arbitrary(u: &mut Unstructured) -> ValType {
+        *u.choose(NUMERIC_TYPES).unwrap()
+    }
+}
+
+impl Arbitrary for Module {
+    fn arbitrary(u: &mut Unstructured) -> Module {
+        let mut module = Module::default();
+
+        let memory = if u.ratio(1, 2) {
+            let initial = u.int_in_range(0..=2);
+            let maximum = if u.ratio(1, 2) {
+                Some(u.int_in_range(initial..=initial + 2))
+            } else {
+                None
+            };
+            Some(module.memories.add_local(false, initial, maximum))
+        } else {
+            None
+        };
+        if let Some(memory) = memory {
+            let size = module.memories.get(memory).initial * PAGE_SIZE;
+            for _ in 0..u.int_in_range(0..=2) {
+                let value = u.bytes(16).to_vec();
+                if value.len() as u32 > size {
+                    break;
+                }
+                let offset = u.int_in_range(0..=size - value.len() as u32);
+                let id = module.data.add_passive(value);
+                let location = ActiveDataLocation::Absolute(offset);
+                module.make_data_active(id, memory, location).unwrap();
+            }
+        }
+
+        let mut globals = Vec::new();
+        for _ in 0..u.int_in_range(0..=4) {
+            let ty = ValType::arbitrary(u);
+            let mutable = u.ratio(1, 2);
+            let init = InitExpr::Value(value(u, ty));
+            let id = module.globals.add_local(ty, mutable, init);
+            globals.push((id, ty, mutable));
+        }
+
+        let mut gen = Generator {
+            u,
+            funcs: Vec::new(),
+            globals,
+            memory,
+            locals: Vec::new(),
+            fuel: 0,
+        };
+        for i in 0..gen.u.int_in_range(1..=6) {
+            let func = gen.function(&mut module);
+            module.funcs.get_mut(func).name = Some(format!("f{}", i));
+            module.exports.add(&format!("f{}", i), func);
+        }
+        module
+    }
+}
+
+/// Apply a random mutation to `module`, keeping it valid.
+///
+/// The mutation changes a constant, swaps an operator for another one of the
+/// same type, inserts an instruction, changes the initial value of a global,
+/// or adds a new exported function. If the chosen kind of mutation doesn't
+/// apply to `module`, nothing happens.
+pub fn mutate(module: &mut Module, u: &mut Unstructured) {
+    match u.int_in_range(0..=4) {
+        0 => {
+            let count = count_instrs(module, |i| matches!(i, Instr::Const(_)));
+            if count > 0 {
+                let n = u.int_in_range(0..=count - 1);
+                let mut f = |instr: &mut Instr| {
+                    if let Instr::Const(c) = instr {
+                        c.value = value(u, value_type(c.value));
+                    }
+                };
+                with_nth_instr(module, n, |i| matches!(i, Instr::Const(_)), &mut f);
+            }
+        }
+        1 => {
+            let count = count_instrs(module, is_swappable);
+            if count > 0 {
+                let n = u.int_in_range(0..=count - 1);
+                let mut f = |instr: &mut Instr| match instr {
+                    Instr::Binop(e) => {
+                        let (_, operand, result) = *BINOPS.iter().find(|b| b.0 == e.op).unwrap();
+                        let same = same_types(BINOPS, operand, result);
+                        e.op = *u.choose(&same).unwrap();
+                    }
+                    Instr::Unop(e) => {
+                        let (_, from, to) = *UNOPS.iter().find(|o| o.0 == e.op).unwrap();
+                        let same = same_types(UNOPS, from, to);
+                        e.op = *u.choose(&same).unwrap();
+                    }
+                    _ => {}
+                };
+                with_nth_instr(module, n, is_swappable, &mut f);
+            }
+        }
+        2 => {
+            let count = count_seqs(module);
+            if count > 0 {
+                let n = u.int_in_range(0..=count - 1);
+                let mut f = |seq: &mut InstrSeq| {
+                    let at = u.int_in_range(0..=seq.instrs.len() as u32) as usize;
+                    let ty = ValType::arbitrary(u);
+                    let value = value(u, ty);
+                    seq.instrs.insert(at, (Drop {}.into(), Default::default()));
+                    seq.instrs
+                        .insert(at, (Const { value }.into(), Default::default()));
+                };
+                with_nth_seq(module, n, &mut f);
+            }
+        }
+        3 => {
+            let globals = module
+                .globals
+                .iter()
+                .filter(|g| matches!(g.kind, GlobalKind::Local(InitExpr::Value(_))))
+                .map(|g| g.id())
+                .collect::<Vec<_>>();
+            if let Some(id) = u.choose(&globals).cloned() {
+                let global = module.globals.get_mut(id);
+                global.kind = GlobalKind::Local(InitExpr::Value(value(u, global.ty)));
+            }
+        }
+        _ => {
+            let mut gen = Generator::for_module(module, u);
+            let func = gen.function(module);
+            let name = format!("mutation{}", func.index());
+            module.funcs.get_mut(func).name = Some(name.clone());
+            module.exports.add(&name, func);
+        }
+    }
+}
+
+const PAGE_SIZE: u32 = 64 * 1024;
+
+const NUMERIC_TYPES: &[ValType] = &[ValType::I32, ValType::I64, ValType::F32, ValType::F64];
+
+/// The binary operators that never produce invalid code, with the type of
+/// their operands and of their result.
+const BINOPS: &[(BinaryOp, ValType, ValType)] = {
+    use crate::ValType::*;
+    use BinaryOp::*;
+    &[
+        (I32Add, I32, I32),
+        (I32Sub, I32, I32),
+        (I32Mul, I32, I32),
+        (I32DivS, I32, I32),
+        (I32DivU, I32, I32),
+        (I32RemS, I32, I32),
+        (I32RemU, I32, I32),
+        (I32And, I32, I32),
+        (I32Or, I32, I32),
+        (I32Xor, I32, I32),
+        (I32Shl, I32, I32),
+        (I32ShrS, I32, I32),
+        (I32ShrU, I32, I32),
+        (I32Rotl, I32, I32),
+        (I32Rotr, I32, I32),
+        (I64Add, I64, I64),
+        (I64Sub, I64, I64),
+        (I64Mul, I64, I64),
+        (I64DivS, I64, I64),
+        (I64DivU, I64, I64),
+        (I64RemS, I64, I64),
+        (I64RemU, I64, I64),
+        (I64And, I64, I64),
+        (I64Or, I64, I64),
+        (I64Xor, I64, I64),
+        (I64Shl, I64, I64),
+        (I64ShrS, I64, I64),
+        (I64ShrU, I64, I64),
+        (I64Rotl, I64, I64),
+        (I64Rotr, I64, I64),
+        (F32Add, F32, F32),
+        (F32Sub, F32, F32),
+        (F32Mul, F32, F32),
+        (F32Div, F32, F32),
+        (F32Min, F32, F32),
+        (F32Max, F32, F32),
+        (F32Copysign, F32, F32),
+        (F64Add, F64, F64),
+        (F64Sub, F64, F64),
+        (F64Mul, F64, F64),
+        (F64Div, F64, F64),
+        (F64Min, F64, F64),
+        (F64Max, F64, F64),
+        (F64Copysign, F64, F64),
+        (I32Eq, I32, I32),
+        (I32Ne, I32, I32),
+        (I32LtS, I32, I32),
+        (I32LtU, I32, I32),
+        (I32GtS, I32, I32),
+        (I32GtU, I32, I32),
+        (I32LeS, I32, I32),
+        (I32LeU, I32, I32),
+        (I32GeS, I32, I32),
+        (I32GeU, I32, I32),
+        (I64Eq, I64, I32),
+        (I64Ne, I64, I32),
+        (I64LtS, I64, I32),
+        (I64LtU, I64, I32),
+        (I64GtS, I64, I32),
+        (I64GtU, I64, I32),
+        (I64LeS, I64, I32),
+        (I64LeU, I64, I32),
+        (I64GeS, I64, I32),
+        (I64GeU, I64, I32),
+        (F32Eq, F32, I32),
+        (F32Ne, F32, I32),
+        (F32Lt, F32, I32),
+        (F32Gt, F32, I32),
+        (F32Le, F32, I32),
+        (F32Ge, F32, I32),
+        (F64Eq, F64, I32),
+        (F64Ne, F64, I32),
+        (F64Lt, F64, I32),
+        (F64Gt, F64, I32),
+        (F64Le, F64, I32),
+        (F64Ge, F64, I32),
+    ]
+};
+
+/// The unary operators that are generated, with the type of their operand and
+/// of their result. Trapping float-to-int conversions are left out.
+const UNOPS: &[(UnaryOp, ValType, ValType)] = {
+    use crate::ValType::*;
+    use UnaryOp::*;
+    &[
+        (I32Eqz, I32, I32),
+        (I32Clz, I32, I32),
+        (I32Ctz, I32, I32),
+        (I32Popcnt, I32, I32),
+        (I64Eqz, I64, I32),
+        (I64Clz, I64, I64),
+        (I64Ctz, I64, I64),
+        (I64Popcnt, I64, I64),
+        (F32Abs, F32, F32),
+        (F32Neg, F32, F32),
+        (F32Ceil, F32, F32),
+        (F32Floor, F32, F32),
+        (F32Trunc, F32, F32),
+        (F32Nearest, F32, F32),
+        (F32Sqrt, F32, F32),
+        (F64Abs, F64, F64),
+        (F64Neg, F64, F64),
+        (F64Ceil, F64, F64),
+        (F64Floor, F64, F64),
+        (F64Trunc, F64, F64),
+        (F64Nearest, F64, F64),
+        (F64Sqrt, F64, F64),
+        (I32WrapI64, I64, I32),
+        (I64ExtendSI32, I32, I64),
+        (I64ExtendUI32, I32, I64),
+        (F32ConvertSI32, I32, F32),
+        (F32ConvertUI32, I32, F32),
+        (F32ConvertSI64, I64, F32),
+        (F32ConvertUI64, I64, F32),
+        (F32DemoteF64, F64, F32),
+        (F64ConvertSI32, I32, F64),
+        (F64ConvertUI32, I32, F64),
+        (F64ConvertSI64, I64, F64),
+        (F64ConvertUI64, I64, F64),
+        (F64PromoteF32, F32, F64),
+        (I32ReinterpretF32, F32, I32),
+        (I64ReinterpretF64, F64, I64),
+        (F32ReinterpretI32, I32, F32),
+        (F64ReinterpretI64, I64, F64),
+    ]
+};
+
+/// The operators in `ops` with the given operand and result types.
+fn same_types<T: Copy>(ops: &[(T, ValType, ValType)], operand: ValType, result: ValType) -> Vec<T> {
+    ops.iter()
+        .filter(|(_, o, r)| *o == operand && *r == result)
+        .map(|(op, _, _)| *op)
+        .collect()
+}
+
+fn is_swappable(instr: &Instr) -> bool {
+    match instr {
+        Instr::Binop(e) => BINOPS.iter().any(|b| b.0 == e.op),
+        Instr::Unop(e) => UNOPS.iter().any(|o| o.0 == e.op),
+        _ => false,
+    }
+}
+
+/// An arbitrary value of type `ty`: half of the time a small integer, which
+/// is more likely to exercise interesting behavior than arbitrary bits.
+fn value(u: &mut Unstructured, ty: ValType) -> Value {
+    if u.ratio(1, 2) {
+        let small = u.int_in_range(0..=32) as i32 - 16;
+        return match ty {
+            ValType::I32 => Value::I32(small),
+            ValType::I64 => Value::I64(small.into()),
+            ValType::F32 => Value::F32(small as f32),
+            ValType::F64 => Value::F64(small.into()),
+            _ => unreachable!("only numeric values are generated"),
+        };
+    }
+    let bits = u.u64();
+    match ty {
+        ValType::I32 => Value::I32(bits as i32),
+        ValType::I64 => Value::I64(bits as i64),
+        ValType::F32 => Value::F32(f32::from_bits(bits as u32)),
+        ValType::F64 => Value::F64(f64::from_bits(bits)),
+        _ => unreachable!("only numeric values are generated"),
+    }
+}
+
+fn value_type(value: Value) -> ValType {
+    match value {
+        Value::I32(_) => ValType::I32,
+        Value::I64(_) => ValType::I64,
+        Value::F32(_) => ValType::F32,
+        Value::F64(_) => ValType::F64,
+        Value::V128(_) => ValType::V128,
+    }
+}
+
+/// Count the instructions in `module`'s local functions matching `pred`.
+fn count_instrs(module: &Module, pred: impl Fn(&Instr) -> bool) -> u32 {
+    struct Count<P>(P, u32);
+
+    impl<'instr, P: Fn(&Instr) -> bool> Visitor<'instr> for Count<P> {
+        fn visit_instr(&mut self, instr: &'instr Instr, _: &'instr InstrLocId) {
+            if (self.0)(instr) {
+                self.1 += 1;
+            }
+        }
+    }
+
+    let mut count = Count(pred, 0);
+    for (_, func) in module.funcs.iter_local() {
+        dfs_in_order(&mut count, func, func.entry_block());
+    }
+    count.1
+}
+
+/// Apply `f` to the `n`th instruction in `module`'s local functions matching
+/// `pred`.
+fn with_nth_instr(
+    module: &mut Module,
+    n: u32,
+    pred: impl Fn(&Instr) -> bool,
+    f: &mut dyn FnMut(&mut Instr),
+) {
+    struct Nth<'a, P> {
+        pred: P,
+        left: Option<u32>,
+        f: &'a mut dyn FnMut(&mut Instr),
+    }
+
+    impl<P: Fn(&Instr) -> bool> VisitorMut for Nth<'_, P> {
+        fn visit_instr_mut(&mut self, instr: &mut Instr, _: &mut InstrLocId) {
+            if !(self.pred)(instr) {
+                return;
+            }
+            self.left = match self.left {
+                Some(0) => {
+                    (self.f)(instr);
+                    None
+                }
+                left => left.map(|n| n - 1),
+            };
+        }
+    }
+
+    let mut nth = Nth {
+        pred,
+        left: Some(n),
+        f,
+    };
+    for (_, func) in module.funcs.iter_local_mut() {
+        let entry = func.entry_block();
+        dfs_pre_order_mut(&mut nth, func, entry);
+    }
+}
+
+/// Count the instruction sequences in `module`'s local functions.
+fn count_seqs(module: &Module) -> u32 {
+    struct Count(u32);
+
+    impl<'instr> Visitor<'instr> for Count {
+        fn start_instr_seq(&mut self, _: &'instr InstrSeq) {
+            self.0 += 1;
+        }
+    }
+
+    let mut count = Count(0);
+    for (_, func) in module.funcs.iter_local() {
+        dfs_in_order(&mut count, func, func.entry_block());
+    }
+    count.0
+}
+
+/// Apply `f` to the `n`th instruction sequence in `module`'s local functions.
+fn with_nth_seq(module: &mut Module, n: u32, f: &mut dyn FnMut(&mut InstrSeq)) {
+    struct Nth<'a> {
+        left: Option<u32>,
+        f: &'a mut dyn FnMut(&mut InstrSeq),
+    }
+
+    impl VisitorMut for Nth<'_> {
+        fn start_instr_seq_mut(&mut self, seq: &mut InstrSeq) {
+            self.left = match self.left {
+                Some(0) => {
+                    (self.f)(seq);
+                    None
+                }
+                left => left.map(|n| n - 1),
+            };
+        }
+    }
+
+    let mut nth = Nth { left: Some(n), f };
+    for (_, func) in module.funcs.iter_local_mut() {
+        let entry = func.entry_block();
+        dfs_pre_order_mut(&mut nth, func, entry);
+    }
+}
+
+/// Generates functions, keeping track of what they may use.
+struct Generator<'a, 'b> {
+    u: &'a mut Unstructured<'b>,
+    /// Functions that may be called, with their parameters and result.
+    funcs: Vec<(FunctionId, Vec<ValType>, Option<ValType>)>,
+    /// Globals that may be accessed, with their types and mutability.
+    globals: Vec<(GlobalId, ValType, bool)>,
+    memory: Option<MemoryId>,
+    /// The locals of the function being generated.
+    locals: Vec<(LocalId, ValType)>,
+    /// How many more non-leaf instructions the function may contain.
+    fuel: u32,
+}
+
+impl<'a, 'b> Generator<'a, 'b> {
+    /// A generator for new functions in an existing module, which may use
+    /// whatever in it has MVP numeric types.
+    fn for_module(module: &Module, u: &'a mut Unstructured<'b>) -> Generator<'a, 'b> {
+        let numeric = |tys: &[ValType]| tys.iter().all(|ty| NUMERIC_TYPES.contains(ty));
+        let funcs = module
+            .funcs
+            .iter()
+            .filter_map(|f| {
+                let ty = module.types.get(f.ty());
+                match ty.results() {
+                    _ if !numeric(ty.params()) || !numeric(ty.results()) => None,
+                    [] => Some((f.id(), ty.params().to_vec(), None)),
+                    [result] => Some((f.id(), ty.params().to_vec(), Some(*result))),
+                    _ => None,
+                }
+            })
+            .collect();
+        let globals = module
+            .globals
+            .iter()
+            .filter(|g| NUMERIC_TYPES.contains(&g.ty))
+            .map(|g| (g.id(), g.ty, g.mutable))
+            .collect();
+        let memory = module.memories.iter().next().map(|m| m.id());
+        Generator {
+            u,
+            funcs,
+            globals,
+            memory,
+            locals: Vec::new(),
+            fuel: 0,
+        }
+    }
+
+    /// Generate a new function, which later functions may call.
+    fn function(&mut self, module: &mut Module) -> FunctionId {
+        let params = (0..self.u.int_in_range(0..=3))
+            .map(|_| ValType::arbitrary(self.u))
+            .collect::<Vec<_>>();
+        let result = if self.u.ratio(2, 3) {
+            Some(ValType::arbitrary(self.u))
+        } else {
+            None
+        };
+        let extra = self.u.int_in_range(0..=3);
+        let args = params
+            .iter()
+            .map(|ty| module.locals.add(*ty))
+            .collect::<Vec<_>>();
+        self.locals = args.iter().cloned().zip(params.iter().cloned()).collect();
+        for _ in 0..extra {
+            let ty = ValType::arbitrary(self.u);
+            self.locals.push((module.locals.add(ty), ty));
+        }
+        self.fuel = self.u.int_in_range(1..=64);
+
+        let results = result.iter().cloned().collect::<Vec<_>>();
+        let mut builder = FunctionBuilder::new(&mut module.types, &params, &results);
+        let mut body = builder.func_body();
+        for _ in 0..self.u.int_in_range(0..=4) {
+            self.stmt(&mut body, 0);
+        }
+        if let Some(ty) = result {
+            self.expr(&mut body, ty, 0);
+        }
+        let id = builder.finish(args, &mut module.funcs);
+        self.funcs.push((id, params, result));
+        id
+    }
+
+    /// Should the instruction being generated at `depth` be a leaf?
+    fn leaf(&mut self, depth: u32) -> bool {
+        if self.fuel == 0 || depth >= 8 || self.u.is_empty() {
+            return true;
+        }
+        self.fuel -= 1;
+        false
+    }
+
+    /// Generate code that pushes one value of type `ty`.
+    fn expr(&mut self, b: &mut InstrSeqBuilder, ty: ValType, depth: u32) {
+        if self.leaf(depth) {
+            return self.leaf_expr(b, ty);
+        }
+        match self.u.int_in_range(0..=9) {
+            0 => {
+                let locals = self.locals_of_type(ty);
+                if let Some(local) = self.u.choose(&locals) {
+                    b.local_get(*local);
+                    return;
+                }
+            }
+            1 | 2 => {
+                let ops = BINOPS.iter().filter(|o| o.2 == ty).collect::<Vec<_>>();
+                if let Some((op, operand, _)) = self.u.choose(&ops).cloned() {
+                    self.expr(b, *operand, depth + 1);
+                    self.expr(b, *operand, depth + 1);
+                    b.binop(*op);
+                    return;
+                }
+            }
+            3 => {
+                let ops = UNOPS.iter().filter(|o| o.2 == ty).collect::<Vec<_>>();
+                if let Some((op, operand, _)) = self.u.choose(&ops).cloned() {
+                    self.expr(b, *operand, depth + 1);
+                    b.unop(*op);
+                    return;
+                }
+            }
+            4 => {
+                let mut block = b.dangling_instr_seq(ty);
+                self.stmt(&mut block, depth + 1);
+                self.expr(&mut block, ty, depth + 1);
+                let seq = block.id();
+                b.instr(Block { seq });
+                return;
+            }
+            5 => {
+                self.expr(b, ValType::I32, depth + 1);
+                let mut consequent = b.dangling_instr_seq(ty);
+                self.expr(&mut consequent, ty, depth + 1);
+                let consequent = consequent.id();
+                let mut alternative = b.dangling_instr_seq(ty);
+                self.expr(&mut alternative, ty, depth + 1);
+                let alternative = alternative.id();
+                b.instr(IfElse {
+                    consequent,
+                    alternative,
+                });
+                return;
+            }
+            6 => {
+                let funcs = self
+                    .funcs
+                    .iter()
+                    .filter(|f| f.2 == Some(ty))
+                    .map(|f| (f.0, f.1.clone()))
+                    .collect::<Vec<_>>();
+                if let Some((func, params)) = self.u.choose(&funcs).cloned() {
+                    for param in params {
+                        self.expr(b, param, depth + 1);
+                    }
+                    b.call(func);
+                    return;
+                }
+            }
+            7 => {
+                let globals = self
+                    .globals
+                    .iter()
+                    .filter(|g| g.1 == ty)
+                    .map(|g| g.0)
+                    .collect::<Vec<_>>();
+                if let Some(global) = self.u.choose(&globals) {
+                    b.global_get(*global);
+                    return;
+                }
+            }
+            8 => {
+                if let Some(memory) = self.memory {
+                    let kind = *self.u.choose(&loads(ty)).unwrap();
+                    let arg = self.mem_arg(kind.width());
+                    self.expr(b, ValType::I32, depth + 1);
+                    b.load(memory, kind, arg);
+                    return;
+                }
+            }
+            _ => {
+                self.expr(b, ty, depth + 1);
+                self.expr(b, ty, depth + 1);
+                self.expr(b, ValType::I32, depth + 1);
+                b.select(None);
+                return;
+            }
+        }
+        self.leaf_expr(b, ty)
+    }
+
+    fn leaf_expr(&mut self, b: &mut InstrSeqBuilder, ty: ValType) {
+        let locals = self.locals_of_type(ty);
+        match self.u.choose(&locals) {
+            Some(local) if self.u.ratio(1, 2) => {
+                b.local_get(*local);
+            }
+            _ => {
+                b.const_(value(self.u, ty));
+            }
+        }
+    }
+
+    /// Generate code that leaves the stack as it was.
+    fn stmt(&mut self, b: &mut InstrSeqBuilder, depth: u32) {
+        if self.leaf(depth) {
+            return;
+        }
+        match self.u.int_in_range(0..=6) {
+            0 => {
+                if let Some((local, ty)) = self.u.choose(&self.locals).cloned() {
+                    self.expr(b, ty, depth + 1);
+                    b.local_set(local);
+                }
+            }
+            1 => {
+                let globals = self.globals.iter().filter(|g| g.2).cloned();
+                let globals = globals.collect::<Vec<_>>();
+                if let Some((global, ty, _)) = self.u.choose(&globals).cloned() {
+                    self.expr(b, ty, depth + 1);
+                    b.global_set(global);
+                }
+            }
+            2 => {
+                if let Some(memory) = self.memory {
+                    let ty = ValType::arbitrary(self.u);
+                    let kind = *self.u.choose(&stores(ty)).unwrap();
+                    let arg = self.mem_arg(kind.width());
+                    self.expr(b, ValType::I32, depth + 1);
+                    self.expr(b, ty, depth + 1);
+                    b.store(memory, kind, arg);
+                }
+            }
+            3 => {
+                let ty = ValType::arbitrary(self.u);
+                self.expr(b, ty, depth + 1);
+                b.drop();
+            }
+            4 => {
+                self.expr(b, ValType::I32, depth + 1);
+                let mut consequent = b.dangling_instr_seq(None);
+                self.stmt(&mut consequent, depth + 1);
+                let consequent = consequent.id();
+                let mut alternative = b.dangling_instr_seq(None);
+                self.stmt(&mut alternative, depth + 1);
+                let alternative = alternative.id();
+                b.instr(IfElse {
+                    consequent,
+                    alternative,
+                });
+            }
+            5 => {
+                // A block that may be left early.
+                let mut block = b.dangling_instr_seq(None);
+                let seq = block.id();
+                self.stmt(&mut block, depth + 1);
+                self.expr(&mut block, ValType::I32, depth + 1);
+                block.br_if(seq);
+                self.stmt(&mut block, depth + 1);
+                b.instr(Block { seq });
+            }
+            _ => {
+                if let Some((func, params, result)) = self.u.choose(&self.funcs).cloned() {
+                    for param in params {
+                        self.expr(b, param, depth + 1);
+                    }
+                    b.call(func);
+                    if result.is_some() {
+                        b.drop();
+                    }
+                }
+            }
+        }
+    }
+
+    fn locals_of_type(&self, ty: ValType) -> Vec<LocalId> {
+        self.locals
+            .iter()
+            .filter(|l| l.1 == ty)
+            .map(|l| l.0)
+            .collect()
+    }
+
+    fn mem_arg(&mut self, width: u32) -> MemArg {
+        MemArg {
+            align: width,
+            offset: self.u.int_in_range(0..=64),
+            encoding: None,
+        }
+    }
+}
+
+fn loads(ty: ValType) -> Vec<LoadKind> {
+    use ExtendedLoad::*;
+    match ty {
+        ValType::I32 => vec![
+            LoadKind::I32 { atomic: false },
+            LoadKind::I32_8 { kind: SignExtend },
+            LoadKind::I32_8 { kind: ZeroExtend },
+            LoadKind::I32_16 { kind: SignExtend },
+            LoadKind::I32_16 { kind: ZeroExtend },
+        ],
+        ValType::I64 => vec![
+            LoadKind::I64 { atomic: false },
+            LoadKind::I64_8 { kind: SignExtend },
+            LoadKind::I64_8 { kind: ZeroExtend },
+            LoadKind::I64_16 { kind: SignExtend },
+            LoadKind::I64_16 { kind: ZeroExtend },
+            LoadKind::I64_32 { kind: SignExtend },
+            LoadKind::I64_32 { kind: ZeroExtend },
+        ],
+        ValType::F32 => vec![LoadKind::F32],
+        _ => vec![LoadKind::F64],
+    }
+}
+
+fn stores(ty: ValType) -> Vec<StoreKind> {
+    match ty {
+        ValType::I32 => vec![
+            StoreKind::I32 { atomic: false },
+            StoreKind::I32_8 { atomic: false },
+            StoreKind::I32_16 { atomic: false },
+        ],
+        ValType::I64 => vec![
+            StoreKind::I64 { atomic: false },
+            StoreKind::I64_8 { atomic: false },
+            StoreKind::I64_16 { atomic: false },
+            StoreKind::I64_32 { atomic: false },
+        ],
+        ValType::F32 => vec![StoreKind::F32],
+        _ => vec![StoreKind::F64],
+    }
+}
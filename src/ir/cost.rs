@@ -0,0 +1,119 @@
+//! Cost models for instructions.
+
+use super::*;
+
+/// A model of how expensive instructions are to execute, for analyses and
+/// transformations that trade code size against speed, or that meter
+/// execution.
+pub trait InstrCost {
+    /// Get the cost of executing `instr` once.
+    ///
+    /// This doesn't include the instructions nested in `instr`, like the
+    /// bodies of blocks or the callee of a call, and for instructions whose
+    /// cost depends on their operands, like `memory.copy`, it is only their
+    /// fixed cost.
+    fn cost(&self, instr: &Instr) -> u64;
+}
+
+impl<F> InstrCost for F
+where
+    F: Fn(&Instr) -> u64,
+{
+    fn cost(&self, instr: &Instr) -> u64 {
+        self(instr)
+    }
+}
+
+/// The default cost model, roughly approximating the number of cycles that
+/// engines spend on each instruction.
+///
+/// Simple integer operators, locals, and constants cost one, and everything
+/// else is scaled relative to that.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct DefaultCost;
+
+impl InstrCost for DefaultCost {
+    fn cost(&self, instr: &Instr) -> u64 {
+        match instr {
+            Instr::Block(..) | Instr::Loop(..) => 0,
+            Instr::IfElse(..) | Instr::BrIf(..) => 2,
+            Instr::BrTable(..) => 4,
+            Instr::Br(..) | Instr::Return(..) | Instr::Unreachable(..) => 1,
+
+            Instr::Call(..) => 5,
+            Instr::CallIndirect(..) => 10,
+
+            Instr::Drop(..) => 0,
+            Instr::Select(..) => 2,
+
+            Instr::LocalGet(..) | Instr::LocalSet(..) | Instr::LocalTee(..) => 1,
+            Instr::GlobalGet(..) | Instr::GlobalSet(..) => 2,
+
+            Instr::Const(..) => 1,
+            Instr::Binop(e) => binop_cost(e.op),
+            Instr::Unop(e) => unop_cost(e.op),
+
+            Instr::V128Bitselect(..) | Instr::V128Swizzle(..) | Instr::V128Shuffle(..) => 2,
+
+            Instr::Load(..) | Instr::Store(..) | Instr::LoadSimd(..) => 3,
+            Instr::MemorySize(..) => 1,
+            Instr::MemoryGrow(..) => 100,
+            Instr::MemoryInit(..) | Instr::MemoryCopy(..) | Instr::MemoryFill(..) => 10,
+            Instr::DataDrop(..) => 1,
+
+            Instr::AtomicRmw(..) | Instr::Cmpxchg(..) | Instr::AtomicFence(..) => 10,
+            Instr::AtomicNotify(..) => 50,
+            Instr::AtomicWait(..) => 100,
+
+            Instr::TableGet(..) | Instr::TableSet(..) => 3,
+            Instr::TableSize(..) | Instr::ElemDrop(..) => 1,
+            Instr::TableGrow(..) => 100,
+            Instr::TableFill(..) | Instr::TableInit(..) | Instr::TableCopy(..) => 10,
+
+            Instr::RefNull(..) | Instr::RefIsNull(..) | Instr::RefFunc(..) => 1,
+
+            #[cfg(feature = "unstable")]
+            Instr::Pause(..) => 1,
+            #[cfg(feature = "unstable")]
+            Instr::GlobalAtomicGet(..) | Instr::GlobalAtomicSet(..) => 10,
+            #[cfg(feature = "unstable")]
+            Instr::ContNew(..) | Instr::Suspend(..) | Instr::Resume(..) => 50,
+        }
+    }
+}
+
+fn binop_cost(op: BinaryOp) -> u64 {
+    use BinaryOp::*;
+    match op {
+        I32Mul | I64Mul => 3,
+        I32DivS | I32DivU | I32RemS | I32RemU => 20,
+        I64DivS | I64DivU | I64RemS | I64RemU => 40,
+        F32Add | F32Sub | F32Mul | F64Add | F64Sub | F64Mul => 4,
+        F32Min | F32Max | F64Min | F64Max => 4,
+        F32Div | F64Div => 15,
+        F32x4Add | F32x4Sub | F32x4Mul | F64x2Add | F64x2Sub | F64x2Mul => 4,
+        F32x4Div | F64x2Div => 20,
+        I8x16Mul | I16x8Mul | I32x4Mul => 5,
+        I64x2Mul => 10,
+        _ => 1,
+    }
+}
+
+fn unop_cost(op: UnaryOp) -> u64 {
+    use UnaryOp::*;
+    match op {
+        F32Sqrt | F64Sqrt => 15,
+        F32x4Sqrt | F64x2Sqrt => 20,
+        F32Ceil | F32Floor | F32Trunc | F32Nearest => 4,
+        F64Ceil | F64Floor | F64Trunc | F64Nearest => 4,
+        I32TruncSF32 | I32TruncUF32 | I32TruncSF64 | I32TruncUF64 => 5,
+        I64TruncSF32 | I64TruncUF32 | I64TruncSF64 | I64TruncUF64 => 5,
+        I32TruncSSatF32 | I32TruncUSatF32 | I32TruncSSatF64 | I32TruncUSatF64 => 5,
+        I64TruncSSatF32 | I64TruncUSatF32 | I64TruncSSatF64 | I64TruncUSatF64 => 5,
+        F32ConvertSI32 | F32ConvertUI32 | F32ConvertSI64 | F32ConvertUI64 => 4,
+        F64ConvertSI32 | F64ConvertUI32 | F64ConvertSI64 | F64ConvertUI64 => 4,
+        F32DemoteF64 | F64PromoteF32 => 4,
+        I32Popcnt | I64Popcnt => 2,
+        _ => 1,
+    }
+}
@@ -4,15 +4,23 @@
 //! the stack machine into an instruction tree. Additionally all control frames
 //! are representd as `Block`s.
 
+mod cost;
 mod traversals;
+pub use self::cost::*;
 pub use self::traversals::*;
 
 use crate::encode::Encoder;
+use crate::tombstone_arena::Tombstone;
+#[cfg(feature = "unstable")]
+use crate::TagId;
 use crate::{
-    DataId, FunctionId, GlobalId, LocalFunction, MemoryId, ModuleTypes, TableId, TypeId, ValType,
+    DataId, ElementId, FunctionId, GlobalId, LocalFunction, MemoryId, ModuleTypes, TableId, TypeId,
+    ValType,
 };
 use id_arena::Id;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::ops::{Deref, DerefMut};
 use walrus_macro::walrus_instr;
 
@@ -152,7 +160,7 @@ impl Default for InstrLocId {
 }
 
 /// A sequence of instructions.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct InstrSeq {
     id: InstrSeqId,
 
@@ -163,6 +171,11 @@ pub struct InstrSeq {
 
     /// The instructions that make up the body of this block.
     pub instrs: Vec<(Instr, InstrLocId)>,
+
+    /// The name of this block's label, as found in the `name` section.
+    ///
+    /// The label of an `if`/`else` is named by its consequent.
+    pub name: Option<String>,
 }
 
 impl Deref for InstrSeq {
@@ -181,11 +194,18 @@ impl DerefMut for InstrSeq {
     }
 }
 
+impl Tombstone for InstrSeq {}
+
 impl InstrSeq {
     /// Construct a new instruction sequence.
     pub(crate) fn new(id: InstrSeqId, ty: InstrSeqType) -> InstrSeq {
         let instrs = vec![];
-        InstrSeq { id, ty, instrs }
+        InstrSeq {
+            id,
+            ty,
+            instrs,
+            name: None,
+        }
     }
 
     /// Get the id of this instruction sequence.
@@ -384,7 +404,7 @@ pub enum Instr {
 
     /// `memory.init`
     MemoryInit {
-        /// The memory we're growing.
+        /// The memory we're initializing.
         memory: MemoryId,
         /// The data to copy in
         data: DataId,
@@ -519,6 +539,28 @@ pub enum Instr {
         table: TableId,
     },
 
+    /// `table.init`
+    TableInit {
+        /// The table we're initializing.
+        table: TableId,
+        /// The element segment to copy in.
+        elem: ElementId,
+    },
+
+    /// `elem.drop`
+    ElemDrop {
+        /// The element segment to drop.
+        elem: ElementId,
+    },
+
+    /// `table.copy`
+    TableCopy {
+        /// The source table.
+        src: TableId,
+        /// The destination table.
+        dst: TableId,
+    },
+
     /// `ref.null`
     RefNull {},
 
@@ -556,6 +598,66 @@ pub enum Instr {
         #[walrus(skip_visit)]
         arg: MemArg,
     },
+
+    /// `pause`, a hint that this thread is in a spin-wait loop.
+    ///
+    /// Part of the shared-everything-threads proposal.
+    #[cfg(feature = "unstable")]
+    Pause {},
+
+    /// `global.atomic.get`
+    ///
+    /// Part of the shared-everything-threads proposal.
+    #[cfg(feature = "unstable")]
+    GlobalAtomicGet {
+        /// The global we're reading.
+        global: GlobalId,
+        /// The memory ordering of this access.
+        #[walrus(skip_visit)]
+        ordering: AtomicOrdering,
+    },
+
+    /// `global.atomic.set`
+    ///
+    /// Part of the shared-everything-threads proposal.
+    #[cfg(feature = "unstable")]
+    GlobalAtomicSet {
+        /// The global we're writing.
+        global: GlobalId,
+        /// The memory ordering of this access.
+        #[walrus(skip_visit)]
+        ordering: AtomicOrdering,
+    },
+
+    /// `cont.new`, creating a continuation from a function reference.
+    ///
+    /// Part of the stack-switching proposal.
+    #[cfg(feature = "unstable")]
+    ContNew {
+        /// The continuation type to create.
+        ty: TypeId,
+    },
+
+    /// `suspend`, suspending the current continuation with the given tag.
+    ///
+    /// Part of the stack-switching proposal.
+    #[cfg(feature = "unstable")]
+    Suspend {
+        /// The tag to suspend with.
+        tag: TagId,
+    },
+
+    /// `resume`, resuming a continuation.
+    ///
+    /// Part of the stack-switching proposal. Suspensions with tags that none
+    /// of `handlers` handle must be handled by an enclosing `resume`.
+    #[cfg(feature = "unstable")]
+    Resume {
+        /// The type of the continuation being resumed.
+        ty: TypeId,
+        /// The handlers of suspensions of the resumed continuation.
+        handlers: Box<[ResumeHandler]>,
+    },
 }
 
 /// Argument in `V128Shuffle` of lane indices to select
@@ -579,22 +681,12 @@ pub enum Value {
 impl Value {
     pub(crate) fn emit(&self, encoder: &mut Encoder) {
         match *self {
-            Value::I32(n) => {
-                encoder.byte(0x41); // i32.const
-                encoder.i32(n);
-            }
-            Value::I64(n) => {
-                encoder.byte(0x42); // i64.const
-                encoder.i64(n);
-            }
-            Value::F32(n) => {
-                encoder.byte(0x43); // f32.const
-                encoder.f32(n);
-            }
-            Value::F64(n) => {
-                encoder.byte(0x44); // f64.const
-                encoder.f64(n);
-            }
+            Value::I32(n) => encoder.encode(&wasm_encoder::Instruction::I32Const(n)),
+            Value::I64(n) => encoder.encode(&wasm_encoder::Instruction::I64Const(n)),
+            Value::F32(n) => encoder.encode(&wasm_encoder::Instruction::F32Const(n.into())),
+            Value::F64(n) => encoder.encode(&wasm_encoder::Instruction::F64Const(n.into())),
+            // The SIMD opcodes changed after the version of the proposal that
+            // the parser reads, so they are encoded by hand.
             Value::V128(n) => {
                 encoder.raw(&[0xfd, 0x02]); // v128.const
                 for i in 0..16 {
@@ -619,7 +711,7 @@ impl fmt::Display for Value {
 
 /// Possible binary operations in wasm
 #[allow(missing_docs)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum BinaryOp {
     I32Eq,
     I32Ne,
@@ -820,7 +912,7 @@ pub enum BinaryOp {
 
 /// Possible unary operations in wasm
 #[allow(missing_docs)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum UnaryOp {
     I32Eqz,
     I32Clz,
@@ -1078,16 +1170,67 @@ impl StoreKind {
 
 /// Arguments to memory operations, containing a constant offset from a dynamic
 /// address as well as a predicted alignment.
+///
+/// This may gain fields in later versions, so it is created with `MemArg::new`
+/// outside of walrus.
+///
+/// Memory arguments are equal when their alignment and offset are, however
+/// they were encoded.
 #[derive(Debug, Copy, Clone)]
+#[non_exhaustive]
 pub struct MemArg {
     /// The alignment of the memory operation, must be a power of two
     pub align: u32,
     /// The offset of the memory operation, in bytes from the source address
     pub offset: u32,
+    /// How the alignment and offset were encoded in the original wasm binary,
+    /// if this was parsed from one.
+    pub encoding: Option<MemArgEncoding>,
+}
+
+impl MemArg {
+    /// Create the arguments to a memory operation with the given alignment and
+    /// offset, which are encoded in as few bytes as possible.
+    pub fn new(align: u32, offset: u32) -> MemArg {
+        MemArg {
+            align,
+            offset,
+            encoding: None,
+        }
+    }
+}
+
+impl PartialEq for MemArg {
+    fn eq(&self, other: &MemArg) -> bool {
+        self.align == other.align && self.offset == other.offset
+    }
+}
+
+impl Eq for MemArg {}
+
+impl Hash for MemArg {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.align.hash(state);
+        self.offset.hash(state);
+    }
+}
+
+/// The widths, in bytes, of the LEB128 numbers that a `MemArg` was encoded
+/// with.
+///
+/// LEB128 numbers may be padded with redundant bytes, and walrus emits them
+/// with the same padding again, unless `ModuleConfig::canonicalize_memargs` is
+/// enabled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MemArgEncoding {
+    /// The width of the encoded alignment.
+    pub align_width: u8,
+    /// The width of the encoded offset.
+    pub offset_width: u8,
 }
 
 /// The different kinds of atomic rmw operations
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[allow(missing_docs)]
 pub enum AtomicOp {
     Add,
@@ -1098,8 +1241,34 @@ pub enum AtomicOp {
     Xchg,
 }
 
+/// The memory ordering of an atomic access to a global, from the
+/// shared-everything-threads proposal.
+#[cfg(feature = "unstable")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AtomicOrdering {
+    /// Sequentially consistent ordering.
+    SeqCst,
+    /// Acquire-release ordering.
+    AcqRel,
+}
+
+/// A handler clause of a `resume`, `(on $tag $label)` in the text format,
+/// from the stack-switching proposal.
+///
+/// When the resumed continuation suspends with `tag`, this branches to
+/// `block` with the tag's parameters and the continuation of the suspended
+/// computation.
+#[cfg(feature = "unstable")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ResumeHandler {
+    /// The tag of the suspensions that are handled.
+    pub tag: TagId,
+    /// The block that is branched to.
+    pub block: InstrSeqId,
+}
+
 /// The different kinds of atomic rmw operations
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[allow(missing_docs)]
 pub enum AtomicWidth {
     I32,
@@ -1169,6 +1338,9 @@ impl Instr {
             | Instr::TableGrow(..)
             | Instr::TableSize(..)
             | Instr::TableFill(..)
+            | Instr::TableInit(..)
+            | Instr::ElemDrop(..)
+            | Instr::TableCopy(..)
             | Instr::RefNull(..)
             | Instr::RefIsNull(..)
             | Instr::RefFunc(..)
@@ -1178,8 +1350,142 @@ impl Instr {
             | Instr::LoadSimd(..)
             | Instr::AtomicFence(..)
             | Instr::Drop(..) => false,
+
+            #[cfg(feature = "unstable")]
+            Instr::Pause(..) | Instr::GlobalAtomicGet(..) | Instr::GlobalAtomicSet(..) => false,
+
+            #[cfg(feature = "unstable")]
+            Instr::ContNew(..) | Instr::Suspend(..) | Instr::Resume(..) => false,
         }
     }
+
+    /// Get the class of this instruction.
+    pub fn class(&self) -> InstrClass {
+        match *self {
+            Instr::Block(..)
+            | Instr::Loop(..)
+            | Instr::IfElse(..)
+            | Instr::Br(..)
+            | Instr::BrIf(..)
+            | Instr::BrTable(..)
+            | Instr::Return(..)
+            | Instr::Unreachable(..) => InstrClass::Control,
+
+            Instr::Call(..) | Instr::CallIndirect(..) => InstrClass::Call,
+
+            Instr::Drop(..) | Instr::Select(..) => InstrClass::Parametric,
+
+            Instr::LocalGet(..)
+            | Instr::LocalSet(..)
+            | Instr::LocalTee(..)
+            | Instr::GlobalGet(..)
+            | Instr::GlobalSet(..) => InstrClass::Variable,
+
+            Instr::Const(..) | Instr::Binop(..) | Instr::Unop(..) => InstrClass::Numeric,
+
+            Instr::V128Bitselect(..) | Instr::V128Swizzle(..) | Instr::V128Shuffle(..) => {
+                InstrClass::Vector
+            }
+
+            Instr::Load(..)
+            | Instr::Store(..)
+            | Instr::LoadSimd(..)
+            | Instr::MemorySize(..)
+            | Instr::MemoryGrow(..)
+            | Instr::MemoryInit(..)
+            | Instr::DataDrop(..)
+            | Instr::MemoryCopy(..)
+            | Instr::MemoryFill(..) => InstrClass::Memory,
+
+            Instr::AtomicRmw(..)
+            | Instr::Cmpxchg(..)
+            | Instr::AtomicNotify(..)
+            | Instr::AtomicWait(..)
+            | Instr::AtomicFence(..) => InstrClass::Atomic,
+
+            Instr::TableGet(..)
+            | Instr::TableSet(..)
+            | Instr::TableGrow(..)
+            | Instr::TableSize(..)
+            | Instr::TableFill(..)
+            | Instr::TableInit(..)
+            | Instr::ElemDrop(..)
+            | Instr::TableCopy(..) => InstrClass::Table,
+
+            Instr::RefNull(..) | Instr::RefIsNull(..) | Instr::RefFunc(..) => InstrClass::Reference,
+
+            #[cfg(feature = "unstable")]
+            Instr::Pause(..) | Instr::GlobalAtomicGet(..) | Instr::GlobalAtomicSet(..) => {
+                InstrClass::Atomic
+            }
+
+            #[cfg(feature = "unstable")]
+            Instr::ContNew(..) | Instr::Suspend(..) | Instr::Resume(..) => InstrClass::Control,
+        }
+    }
+}
+
+/// A coarse classification of instructions, see `Instr::class`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum InstrClass {
+    /// Blocks, branches, `return` and `unreachable`.
+    Control,
+    /// Direct and indirect calls.
+    Call,
+    /// `drop` and `select`.
+    Parametric,
+    /// Getting and setting locals and globals.
+    Variable,
+    /// Constants and unary and binary operators, including the lanewise SIMD
+    /// ones.
+    Numeric,
+    /// The SIMD operators that aren't lanewise, like shuffles.
+    Vector,
+    /// Non-atomic loads and stores, and the other memory instructions.
+    Memory,
+    /// Atomic memory operators.
+    Atomic,
+    /// Table instructions.
+    Table,
+    /// Reference instructions.
+    Reference,
+}
+
+/// The numbers of instructions of each class in a function, see
+/// `LocalFunction::instruction_stats`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InstrStats {
+    counts: BTreeMap<InstrClass, u64>,
+}
+
+impl InstrStats {
+    pub(crate) fn add(&mut self, instr: &Instr) {
+        *self.counts.entry(instr.class()).or_insert(0) += 1;
+    }
+
+    /// Add the counts of `other` to these, eg to get the statistics of a
+    /// whole module.
+    pub fn merge(&mut self, other: &InstrStats) {
+        for (class, count) in other.iter() {
+            *self.counts.entry(class).or_insert(0) += count;
+        }
+    }
+
+    /// Get the total number of instructions.
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// Get the number of instructions of the given class.
+    pub fn count(&self, class: InstrClass) -> u64 {
+        self.counts.get(&class).copied().unwrap_or(0)
+    }
+
+    /// Iterate over the classes with any instructions, along with how many
+    /// they have.
+    pub fn iter(&self) -> impl Iterator<Item = (InstrClass, u64)> + '_ {
+        self.counts.iter().map(|(class, count)| (*class, *count))
+    }
 }
 
 /// Anything that can be visited by a `Visitor`.
@@ -18,25 +18,36 @@ macro_rules! maybe_parallel {
 }
 
 mod arena_set;
+mod cancel;
 pub mod dot;
+mod dump;
 mod emit;
 mod encode;
 mod error;
 mod function_builder;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
 mod init_expr;
 pub mod ir;
 mod map;
 mod module;
+mod module_builder;
+mod name_index;
 mod parse;
 pub mod passes;
+pub mod round_trip;
+pub mod script;
 mod tombstone_arena;
 mod ty;
+pub mod wasi;
 
+pub use crate::cancel::CancellationToken;
 pub use crate::emit::IdsToIndices;
 pub use crate::error::{ErrorKind, Result};
 pub use crate::function_builder::{FunctionBuilder, InstrSeqBuilder};
 pub use crate::init_expr::InitExpr;
 pub use crate::ir::{Local, LocalId};
 pub use crate::module::*;
-pub use crate::parse::IndicesToIds;
+pub use crate::module_builder::ModuleBuilder;
+pub use crate::parse::{IndexRemap, IndicesToIds};
 pub use crate::ty::{Type, TypeId, ValType};
@@ -0,0 +1,128 @@
+//! Where each instruction of a module ended up in the binary it was emitted
+//! as.
+
+use crate::ir::{Instr, InstrLocId, InstrSeqId};
+use crate::{FunctionId, FunctionKind, Module};
+use std::ops::Range;
+
+/// A map from the offsets of instructions in an emitted binary to the
+/// instructions in the IR, and on to their offsets in the binary the module
+/// was parsed from, as returned by `Module::emit_wasm_with_address_map`.
+///
+/// This lets the code offsets that engines give, such as in profiles or stack
+/// traces, be turned back into walrus IR, and from there into the original
+/// binary and its debug info.
+#[derive(Debug, Clone, Default)]
+pub struct AddressMap {
+    entries: Vec<AddressMapEntry>,
+    funcs: Vec<(FunctionId, Range<usize>)>,
+}
+
+/// An instruction in an emitted binary, found in an `AddressMap`.
+#[derive(Debug, Clone, Copy)]
+pub struct AddressMapEntry {
+    /// The offset of the instruction in the emitted binary.
+    pub offset: usize,
+
+    /// The function the instruction is in.
+    pub func: FunctionId,
+
+    /// The instruction sequence the instruction is in.
+    pub seq: InstrSeqId,
+
+    /// The position of the instruction in its sequence.
+    pub index: usize,
+
+    /// Where the instruction was in the binary the module was parsed from, as
+    /// given by `ModuleConfig::on_instr_loc`, if it was parsed rather than
+    /// added afterwards.
+    pub original: Option<InstrLocId>,
+}
+
+impl AddressMapEntry {
+    /// Get the instruction in `module`, if it is still there.
+    pub fn instr<'a>(&self, module: &'a Module) -> Option<&'a Instr> {
+        let func = match &module.funcs.try_get(self.func)?.kind {
+            FunctionKind::Local(func) => func,
+            _ => return None,
+        };
+        let seq = func.builder().arena.get(self.seq)?;
+        seq.instrs.get(self.index).map(|(instr, _)| instr)
+    }
+}
+
+/// An instruction emitted in a function, at an offset within its body.
+pub(crate) struct EmittedInstr {
+    pub(crate) seq: InstrSeqId,
+    pub(crate) index: usize,
+    pub(crate) loc: InstrLocId,
+    pub(crate) pos: usize,
+}
+
+impl AddressMap {
+    /// Record the instructions emitted in `func`, whose body is at `range`.
+    pub(crate) fn add_function(
+        &mut self,
+        func: FunctionId,
+        range: Range<usize>,
+        instrs: &[EmittedInstr],
+    ) {
+        self.entries
+            .extend(instrs.iter().map(|instr| AddressMapEntry {
+                offset: range.start + instr.pos,
+                func,
+                seq: instr.seq,
+                index: instr.index,
+                original: if instr.loc.is_default() {
+                    None
+                } else {
+                    Some(instr.loc)
+                },
+            }));
+        self.funcs.push((func, range));
+    }
+
+    /// Every emitted instruction, in the order of their offsets.
+    pub fn entries(&self) -> &[AddressMapEntry] {
+        &self.entries
+    }
+
+    /// The emitted functions along with the ranges of their bodies, in the
+    /// order of their offsets.
+    pub fn functions(&self) -> &[(FunctionId, Range<usize>)] {
+        &self.funcs
+    }
+
+    /// Find the instruction at `offset` in the emitted binary.
+    ///
+    /// Offsets within an instruction's encoding, and those of the `end`s and
+    /// `else`s that close blocks, map to the instruction before them. Offsets
+    /// outside of any function's instructions map to nothing.
+    pub fn lookup(&self, offset: usize) -> Option<&AddressMapEntry> {
+        let i = self.funcs.partition_point(|(_, range)| range.end <= offset);
+        let (_, range) = self.funcs.get(i)?;
+        if !range.contains(&offset) {
+            return None;
+        }
+        let i = self.entries.partition_point(|entry| entry.offset <= offset);
+        let entry = self.entries[..i].last()?;
+        if entry.offset < range.start {
+            return None;
+        }
+        Some(entry)
+    }
+}
+
+impl Module {
+    /// Emit this module into an in-memory wasm buffer, along with a map from
+    /// the offsets of its instructions to the IR.
+    ///
+    /// Unlike `emit_wasm`, this always encodes the module afresh, even when
+    /// `ModuleConfig::preserve_encoding` would emit the original binary, so
+    /// that the map matches the binary returned.
+    pub fn emit_wasm_with_address_map(&mut self) -> (Vec<u8>, AddressMap) {
+        let mut map = AddressMap::default();
+        let wasm = self.encode(true, None, Some(&mut map));
+        (wasm, map)
+    }
+}
@@ -0,0 +1,143 @@
+//! Handling of the custom sections that wasm registries read a module's
+//! authors, licenses and so on from.
+//!
+//! Each of these sections holds a single UTF-8 string, and corresponds to one
+//! of the [OCI image annotations] that registries publish artifacts with, such
+//! as `org.opencontainers.image.authors` for the `authors` section. This is
+//! the encoding that `wasm-metadata` reads and writes, and that supersedes its
+//! older JSON `registry-metadata` section, which is kept as a raw custom
+//! section.
+//!
+//! [OCI image annotations]: https://github.com/opencontainers/image-spec/blob/main/annotations.md
+
+use crate::emit::{Emit, EmitContext};
+use crate::error::Result;
+use crate::module::Module;
+use anyhow::Context;
+
+/// The names of the custom sections holding registry annotations, each along
+/// with the OCI annotation that it corresponds to.
+const SECTIONS: [(&str, &str); 7] = [
+    ("authors", "org.opencontainers.image.authors"),
+    ("description", "org.opencontainers.image.description"),
+    ("licenses", "org.opencontainers.image.licenses"),
+    ("source", "org.opencontainers.image.source"),
+    ("homepage", "org.opencontainers.image.url"),
+    ("revision", "org.opencontainers.image.revision"),
+    ("version", "org.opencontainers.image.version"),
+];
+
+/// Metadata that registries publish a module with, each field of which is
+/// emitted in a custom section of the same name when it is `Some`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ModuleAnnotations {
+    /// The people or organizations that wrote the module, such as
+    /// `Jane Doe <jane@example.com>`.
+    pub authors: Option<String>,
+
+    /// A human-readable description of what the module does.
+    pub description: Option<String>,
+
+    /// The licenses the module is distributed under, as an SPDX license
+    /// expression such as `Apache-2.0 OR MIT`.
+    pub licenses: Option<String>,
+
+    /// The URL of the module's source code.
+    pub source: Option<String>,
+
+    /// The URL of the module's homepage.
+    pub homepage: Option<String>,
+
+    /// The revision of the source code that the module was built from, such
+    /// as a git commit hash.
+    pub revision: Option<String>,
+
+    /// The version of the module, such as `1.0.0`.
+    pub version: Option<String>,
+}
+
+impl ModuleAnnotations {
+    fn fields(&self) -> [&Option<String>; 7] {
+        [
+            &self.authors,
+            &self.description,
+            &self.licenses,
+            &self.source,
+            &self.homepage,
+            &self.revision,
+            &self.version,
+        ]
+    }
+
+    fn field_mut(&mut self, section: &str) -> Option<&mut Option<String>> {
+        Some(match section {
+            "authors" => &mut self.authors,
+            "description" => &mut self.description,
+            "licenses" => &mut self.licenses,
+            "source" => &mut self.source,
+            "homepage" => &mut self.homepage,
+            "revision" => &mut self.revision,
+            "version" => &mut self.version,
+            _ => return None,
+        })
+    }
+
+    /// Iterate over the annotations that are set, as the names of their
+    /// custom sections along with their values.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        SECTIONS
+            .iter()
+            .zip(self.fields().to_vec())
+            .filter_map(|((name, _), value)| Some((*name, value.as_deref()?)))
+    }
+
+    /// Iterate over the annotations that are set, as the keys of the OCI
+    /// annotations that they correspond to, such as
+    /// `org.opencontainers.image.licenses`, along with their values.
+    ///
+    /// These are the annotations to publish the module to an OCI registry
+    /// with.
+    pub fn oci_annotations(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        SECTIONS
+            .iter()
+            .zip(self.fields().to_vec())
+            .filter_map(|((_, key), value)| Some((*key, value.as_deref()?)))
+    }
+
+    /// Is no annotation set at all?
+    pub fn is_empty(&self) -> bool {
+        self.fields().iter().all(|value| value.is_none())
+    }
+
+    /// Unset all annotations, so that none of their sections are emitted.
+    pub fn clear(&mut self) {
+        *self = ModuleAnnotations::default();
+    }
+}
+
+/// Is `name` the name of a custom section that holds a registry annotation?
+pub(crate) fn is_annotation_section(name: &str) -> bool {
+    SECTIONS.iter().any(|(section, _)| *section == name)
+}
+
+impl Module {
+    /// Parse a registry annotation from the payload of its `name` custom
+    /// section.
+    pub(crate) fn parse_annotation_section(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        log::debug!("parse {} section", name);
+        let value = std::str::from_utf8(data).context("annotation is not valid UTF-8")?;
+        if let Some(field) = self.annotations.field_mut(name) {
+            *field = Some(value.to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Emit for ModuleAnnotations {
+    fn emit(&self, cx: &mut EmitContext) {
+        for (name, value) in self.iter() {
+            log::debug!("emit {} section", name);
+            cx.custom_section(name).encoder.raw(value.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Handling of the `build_id` custom section.
+//!
+//! See the [tool conventions] for details on this section.
+//!
+//! [tool conventions]: https://github.com/WebAssembly/tool-conventions/blob/main/BuildId.md
+
+use crate::emit::EmitContext;
+use crate::error::Result;
+use crate::module::Module;
+
+/// The length of build ids computed by `walrus` when emitting a module.
+const COMPUTED_BUILD_ID_LEN: usize = 16;
+
+impl Module {
+    /// Get this module's build id, if any.
+    ///
+    /// If `ModuleConfig::generate_build_id` is enabled, this is the id that
+    /// was computed the last time the module was emitted.
+    pub fn build_id(&self) -> Option<&[u8]> {
+        self.build_id.as_ref().map(|id| &id[..])
+    }
+
+    /// Set this module's build id, which is emitted in a `build_id` custom
+    /// section.
+    ///
+    /// Note that if `ModuleConfig::generate_build_id` is enabled then this id
+    /// is replaced with a freshly computed one when the module is emitted.
+    pub fn set_build_id(&mut self, id: Vec<u8>) {
+        self.build_id = Some(id);
+    }
+
+    /// Remove this module's build id, so that no `build_id` custom section is
+    /// emitted.
+    pub fn clear_build_id(&mut self) {
+        self.build_id = None;
+    }
+
+    /// Parse a `build_id` section from the custom section payload specified.
+    pub(crate) fn parse_build_id_section(
+        &mut self,
+        mut data: wasmparser::BinaryReader,
+    ) -> Result<()> {
+        log::debug!("parse build_id section");
+        let len = data.read_var_u32()?;
+        let id = data.read_bytes(len as usize)?;
+        if !data.eof() {
+            anyhow::bail!("trailing bytes at the end of the build_id section");
+        }
+        self.build_id = Some(id.to_vec());
+        Ok(())
+    }
+}
+
+/// Emit the `build_id` section, if there is one.
+///
+/// When the build id is going to be computed, this returns the position of
+/// the zeroed placeholder to fill in with `compute_build_id` once the rest of
+/// the module has been emitted.
+pub(crate) fn emit_build_id_section(cx: &mut EmitContext) -> Option<usize> {
+    if cx.module.config.generate_build_id {
+        log::debug!("emit computed build_id section");
+        let mut cx = cx.custom_section("build_id");
+        cx.encoder.usize(COMPUTED_BUILD_ID_LEN);
+        return Some(cx.encoder.reserve(COMPUTED_BUILD_ID_LEN));
+    }
+    if let Some(id) = &cx.module.build_id {
+        log::debug!("emit build_id section");
+        cx.custom_section("build_id").encoder.bytes(id);
+    }
+    None
+}
+
+/// Compute a build id from the contents of a fully emitted module, in which
+/// the build id itself is still zeroed out.
+///
+/// This is a 128-bit FNV-1a hash, which is deterministic across platforms and
+/// `walrus` versions. It is not a cryptographic hash.
+pub(crate) fn compute_build_id(wasm: &[u8]) -> [u8; COMPUTED_BUILD_ID_LEN] {
+    const OFFSET_BASIS: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+    const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+    let mut hash = OFFSET_BASIS;
+    for byte in wasm {
+        hash ^= u128::from(*byte);
+        hash = hash.wrapping_mul(PRIME);
+    }
+    hash.to_le_bytes()
+}
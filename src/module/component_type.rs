@@ -0,0 +1,653 @@
+//! Interface-level signatures of a module's exports, from the
+//! `component-type` custom sections that component toolchains embed.
+//!
+//! Tools like `wit-bindgen` embed the WIT world that a core module implements
+//! in a `component-type:<world>` custom section, which `wit-component` reads
+//! later to wrap the module in a component. The section is a small component
+//! binary, whose types describe the world's imports and exports. This decodes
+//! the types of the world's exported functions, and matches each with the
+//! core export that the canonical ABI names it by. See the [component model]
+//! for details of the encoding.
+//!
+//! [component model]: https://github.com/WebAssembly/component-model/blob/main/design/mvp/Binary.md
+
+use crate::error::{ErrorKind, Result};
+use crate::{ExportId, Module};
+use anyhow::{bail, Context};
+use std::fmt;
+use wasmparser::BinaryReader;
+
+/// A function exported by the WIT world of a `component-type` section, as
+/// found with `Module::interface_exports`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceExport {
+    /// The world that exports the function, such as `wasi:cli/command`.
+    pub world: String,
+
+    /// The interface that the function is in, such as `wasi:cli/run@0.2.0`,
+    /// or `None` for functions exported by the world itself.
+    pub interface: Option<String>,
+
+    /// The function's name, such as `run` or `[method]blob.read`.
+    pub name: String,
+
+    /// The function's interface-level signature.
+    pub func: InterfaceFunc,
+
+    /// The core export that implements the function, if the module has one.
+    pub export: Option<ExportId>,
+}
+
+impl InterfaceExport {
+    /// The name of the core export that implements this function, according
+    /// to the canonical ABI: `<interface>#<name>`, or just `<name>` for
+    /// functions exported by the world itself.
+    pub fn core_name(&self) -> String {
+        match &self.interface {
+            Some(interface) => format!("{}#{}", interface, self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// The signature of an interface-level function.
+///
+/// This displays as WIT, such as `func(a: u32, b: string) -> list<u8>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceFunc {
+    /// The function's parameters, along with their names.
+    pub params: Vec<(String, InterfaceType)>,
+
+    /// The function's result, if any.
+    pub result: Option<InterfaceType>,
+}
+
+/// An interface-level value type.
+///
+/// This displays as WIT, with named types displayed by their name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+#[allow(missing_docs)]
+pub enum InterfaceType {
+    Bool,
+    S8,
+    U8,
+    S16,
+    U16,
+    S32,
+    U32,
+    S64,
+    U64,
+    F32,
+    F64,
+    Char,
+    String,
+    ErrorContext,
+    List(Box<InterfaceType>),
+    /// A list with a fixed number of elements.
+    FixedList(Box<InterfaceType>, u32),
+    Map(Box<InterfaceType>, Box<InterfaceType>),
+    Tuple(Vec<InterfaceType>),
+    Option(Box<InterfaceType>),
+    Result {
+        ok: Option<Box<InterfaceType>>,
+        err: Option<Box<InterfaceType>>,
+    },
+    Record(Vec<(String, InterfaceType)>),
+    Variant(Vec<(String, Option<InterfaceType>)>),
+    Enum(Vec<String>),
+    Flags(Vec<String>),
+    /// An owned handle to the named resource.
+    Own(String),
+    /// A borrowed handle to the named resource.
+    Borrow(String),
+    Future(Option<Box<InterfaceType>>),
+    Stream(Option<Box<InterfaceType>>),
+    /// A type given a name, such as a record defined in an interface.
+    Named(String, Box<InterfaceType>),
+}
+
+impl Module {
+    /// Get the interface-level signatures of the functions that this module
+    /// exports, according to its `component-type` custom sections.
+    ///
+    /// Modules built for the component model carry the WIT world they
+    /// implement in these sections. Each function the world exports, whether
+    /// directly or in an exported interface, is matched with the core export
+    /// that implements it, if any. Modules without these sections have no
+    /// interface exports.
+    ///
+    /// Fails with `ErrorKind::Parse` if a section is malformed, and with
+    /// `ErrorKind::Unsupported` if it uses parts of the component binary
+    /// format that WIT worlds don't, such as core types.
+    pub fn interface_exports(&self) -> Result<Vec<InterfaceExport>> {
+        let mut exports = Vec::new();
+        for (_, section) in self.customs.iter() {
+            if !section.name().starts_with("component-type") {
+                continue;
+            }
+            let data = section.data(&Default::default());
+            let worlds = parse_worlds(&data)
+                .with_context(|| format!("failed to parse `{}` custom section", section.name()))
+                .map_err(|e| ErrorKind::Parse.classify(e))?;
+            for (world, items) in worlds {
+                for (name, def) in items {
+                    match def {
+                        Def::Func(func) => exports.push((world.clone(), None, name, func)),
+                        Def::Instance(items) => {
+                            for (func_name, def) in items {
+                                if let Def::Func(func) = def {
+                                    let interface = Some(name.clone());
+                                    exports.push((world.clone(), interface, func_name, func));
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(exports
+            .into_iter()
+            .map(|(world, interface, name, func)| {
+                let mut export = InterfaceExport {
+                    world,
+                    interface,
+                    name,
+                    func,
+                    export: None,
+                };
+                let core_name = export.core_name();
+                export.export = self
+                    .exports
+                    .iter()
+                    .find(|e| e.name == core_name)
+                    .map(|e| e.id());
+                export
+            })
+            .collect())
+    }
+}
+
+/// What an index in a type index space refers to, or what an import or export
+/// is, as far as it matters for finding the functions of a world.
+#[derive(Clone, Debug)]
+enum Def {
+    Val(InterfaceType),
+    Func(InterfaceFunc),
+    /// An instance or component, by its exports.
+    Instance(Exports),
+    Component(Exports),
+    Resource(String),
+    Other,
+}
+
+/// The named exports of an instance or component type.
+type Exports = Vec<(String, Def)>;
+
+/// The index spaces of a component or instance type.
+#[derive(Default)]
+struct Scope {
+    types: Vec<Def>,
+    instances: Vec<Exports>,
+}
+
+struct Decoder<'a> {
+    reader: BinaryReader<'a>,
+    scopes: Vec<Scope>,
+}
+
+/// Parse a `component-type` section, returning the worlds it defines along
+/// with their exports.
+fn parse_worlds(data: &[u8]) -> Result<Vec<(String, Exports)>> {
+    let mut reader = BinaryReader::new(data);
+    if reader.read_bytes(8)? != b"\0asm\x0d\0\x01\0" {
+        bail!("not a component");
+    }
+    let mut decoder = Decoder {
+        reader,
+        scopes: vec![Scope::default()],
+    };
+    let mut worlds = Vec::new();
+    while !decoder.reader.eof() {
+        let id = decoder.reader.read_u8()?;
+        let len = decoder.reader.read_var_u32()? as usize;
+        match id {
+            // Custom sections, and exports which only name the types defined
+            // before them.
+            0 | 11 => decoder.reader.skip_bytes(len)?,
+            7 => {
+                for _ in 0..decoder.reader.read_var_u32()? {
+                    let def = decoder.def()?;
+                    // Worlds are encoded as component types that are exported
+                    // by the component types of their packages.
+                    if let Def::Component(items) = &def {
+                        for (name, def) in items {
+                            if let Def::Component(exports) = def {
+                                worlds.push((name.clone(), exports.clone()));
+                            }
+                        }
+                    }
+                    decoder.scope().types.push(def);
+                }
+            }
+            _ => {
+                return Err(ErrorKind::Unsupported {
+                    feature: "component sections other than types",
+                })
+                .context(format!("unexpected component section {}", id))
+            }
+        }
+    }
+    Ok(worlds)
+}
+
+impl Decoder<'_> {
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn ty(&self, index: u32) -> Result<&Def> {
+        match self.scopes.last().unwrap().types.get(index as usize) {
+            Some(def) => Ok(def),
+            None => bail!("type index {} out of bounds", index),
+        }
+    }
+
+    /// Read a type index, returning what it refers to.
+    fn read_ty(&mut self) -> Result<Def> {
+        let index = self.reader.read_var_u32()?;
+        Ok(self.ty(index)?.clone())
+    }
+
+    /// Read a type definition.
+    fn def(&mut self) -> Result<Def> {
+        Ok(match self.reader.read_u8()? {
+            0x40 | 0x43 => Def::Func(self.func()?),
+            0x41 => Def::Component(self.decls()?),
+            0x42 => Def::Instance(self.decls()?),
+            0x3f => {
+                self.reader.read_u8()?;
+                if self.reader.read_u8()? == 0x01 {
+                    self.reader.read_var_u32()?;
+                }
+                Def::Resource(String::new())
+            }
+            code => Def::Val(self.defined(code as u8)?),
+        })
+    }
+
+    fn func(&mut self) -> Result<InterfaceFunc> {
+        let mut params = Vec::new();
+        for _ in 0..self.reader.read_var_u32()? {
+            let name = self.reader.read_string()?.to_string();
+            params.push((name, self.val()?));
+        }
+        let result = match self.reader.read_u8()? {
+            0x00 => Some(self.val()?),
+            0x01 if self.reader.read_var_u32()? == 0 => None,
+            _ => {
+                return Err(ErrorKind::Unsupported {
+                    feature: "named function results",
+                })
+                .context("functions with named results aren't supported")
+            }
+        };
+        Ok(InterfaceFunc { params, result })
+    }
+
+    /// Read the declarations of a component or instance type, returning its
+    /// exports.
+    fn decls(&mut self) -> Result<Exports> {
+        self.scopes.push(Scope::default());
+        let mut exports = Vec::new();
+        for _ in 0..self.reader.read_var_u32()? {
+            match self.reader.read_u8()? {
+                0x00 => {
+                    return Err(ErrorKind::Unsupported {
+                        feature: "core types in component types",
+                    })
+                    .context("core types in component types aren't supported")
+                }
+                0x01 => {
+                    let def = self.def()?;
+                    self.scope().types.push(def);
+                }
+                0x02 => self.alias()?,
+                0x03 => {
+                    let name = self.name()?;
+                    self.extern_desc(&name)?;
+                }
+                0x04 => {
+                    let name = self.name()?;
+                    let def = self.extern_desc(&name)?;
+                    exports.push((name, def));
+                }
+                byte => bail!("invalid component type declaration {:#x}", byte),
+            }
+        }
+        self.scopes.pop();
+        Ok(exports)
+    }
+
+    fn alias(&mut self) -> Result<()> {
+        let sort = match self.reader.read_u8()? {
+            0x00 => 0x100 | self.reader.read_u8()?,
+            sort => sort,
+        };
+        match self.reader.read_u8()? {
+            0x00 => {
+                let instance = self.reader.read_var_u32()?;
+                let name = self.reader.read_string()?;
+                let exports = match self.scopes.last().unwrap().instances.get(instance as usize) {
+                    Some(exports) => exports,
+                    None => bail!("instance index {} out of bounds", instance),
+                };
+                let def = match exports.iter().find(|(n, _)| n == name) {
+                    Some((_, def)) => def.clone(),
+                    None => bail!("instance {} has no export `{}`", instance, name),
+                };
+                match (sort, def) {
+                    (0x03, def) => self.scope().types.push(def),
+                    (0x05, Def::Instance(exports)) => self.scope().instances.push(exports),
+                    _ => {}
+                }
+            }
+            0x01 => {
+                self.reader.read_var_u32()?;
+                self.reader.read_string()?;
+            }
+            0x02 => {
+                let count = self.reader.read_var_u32()? as usize;
+                let index = self.reader.read_var_u32()? as usize;
+                if sort == 0x03 {
+                    let outer = self.scopes.len().checked_sub(count + 1);
+                    let def = match outer.and_then(|s| self.scopes[s].types.get(index)) {
+                        Some(def) => def.clone(),
+                        None => bail!("outer type {} {} out of bounds", count, index),
+                    };
+                    self.scope().types.push(def);
+                }
+            }
+            byte => bail!("invalid alias target {:#x}", byte),
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let kind = self.reader.read_u8()?;
+        let name = self.reader.read_string()?.to_string();
+        match kind {
+            0x00 | 0x01 => {}
+            0x02 => {
+                for _ in 0..self.reader.read_var_u32()? {
+                    self.reader.read_u8()?;
+                    self.reader.read_string()?;
+                }
+            }
+            byte => bail!("invalid extern name kind {:#x}", byte),
+        }
+        Ok(name)
+    }
+
+    /// Read what an import or export named `name` is, adding it to the index
+    /// spaces it belongs to.
+    fn extern_desc(&mut self, name: &str) -> Result<Def> {
+        Ok(match self.reader.read_u8()? {
+            0x00 => {
+                self.reader.read_u8()?;
+                self.reader.read_var_u32()?;
+                Def::Other
+            }
+            0x01 => match self.read_ty()? {
+                Def::Func(func) => Def::Func(func),
+                _ => bail!("`{}` isn't a function type", name),
+            },
+            0x02 => {
+                self.val()?;
+                Def::Other
+            }
+            0x03 => {
+                let def = match self.reader.read_u8()? {
+                    0x00 => match self.read_ty()? {
+                        Def::Val(InterfaceType::Named(_, ty)) => {
+                            Def::Val(InterfaceType::Named(name.to_string(), ty))
+                        }
+                        Def::Val(ty) => {
+                            Def::Val(InterfaceType::Named(name.to_string(), Box::new(ty)))
+                        }
+                        def => def,
+                    },
+                    0x01 => Def::Resource(name.to_string()),
+                    byte => bail!("invalid type bound {:#x}", byte),
+                };
+                self.scope().types.push(def.clone());
+                def
+            }
+            0x04 => self.read_ty()?,
+            0x05 => {
+                let def = self.read_ty()?;
+                if let Def::Instance(exports) = &def {
+                    self.scope().instances.push(exports.clone());
+                }
+                def
+            }
+            byte => bail!("invalid extern kind {:#x}", byte),
+        })
+    }
+
+    /// Read a value type: a primitive type, or the index of a defined one.
+    fn val(&mut self) -> Result<InterfaceType> {
+        let value = self.reader.read_var_s33()?;
+        if value < 0 {
+            return self.defined(value as u8 & 0x7f);
+        }
+        match self.ty(value as u32)? {
+            Def::Val(ty) => Ok(ty.clone()),
+            _ => bail!("type {} isn't a value type", value),
+        }
+    }
+
+    fn opt_val(&mut self) -> Result<Option<Box<InterfaceType>>> {
+        Ok(match self.reader.read_u8()? {
+            0x00 => None,
+            0x01 => Some(Box::new(self.val()?)),
+            byte => bail!("invalid optional type {:#x}", byte),
+        })
+    }
+
+    fn resource(&mut self) -> Result<String> {
+        let index = self.reader.read_var_u32()?;
+        match self.ty(index)? {
+            Def::Resource(name) => Ok(name.clone()),
+            _ => bail!("type {} isn't a resource", index),
+        }
+    }
+
+    fn names(&mut self) -> Result<Vec<String>> {
+        (0..self.reader.read_var_u32()?)
+            .map(|_| Ok(self.reader.read_string()?.to_string()))
+            .collect()
+    }
+
+    /// Read the rest of a defined value type, which starts with `code`.
+    fn defined(&mut self, code: u8) -> Result<InterfaceType> {
+        use InterfaceType::*;
+        Ok(match code {
+            0x7f => Bool,
+            0x7e => S8,
+            0x7d => U8,
+            0x7c => S16,
+            0x7b => U16,
+            0x7a => S32,
+            0x79 => U32,
+            0x78 => S64,
+            0x77 => U64,
+            0x76 => F32,
+            0x75 => F64,
+            0x74 => Char,
+            0x73 => String,
+            0x64 => ErrorContext,
+            0x72 => {
+                let mut fields = Vec::new();
+                for _ in 0..self.reader.read_var_u32()? {
+                    let name = self.reader.read_string()?.to_string();
+                    fields.push((name, self.val()?));
+                }
+                Record(fields)
+            }
+            0x71 => {
+                let mut cases = Vec::new();
+                for _ in 0..self.reader.read_var_u32()? {
+                    let name = self.reader.read_string()?.to_string();
+                    let ty = self.opt_val()?.map(|ty| *ty);
+                    if self.reader.read_u8()? != 0x00 {
+                        bail!("variant cases that refine others aren't supported");
+                    }
+                    cases.push((name, ty));
+                }
+                Variant(cases)
+            }
+            0x70 => List(Box::new(self.val()?)),
+            0x67 => {
+                let ty = self.val()?;
+                FixedList(Box::new(ty), self.reader.read_var_u32()?)
+            }
+            0x63 => {
+                let key = self.val()?;
+                Map(Box::new(key), Box::new(self.val()?))
+            }
+            0x6f => {
+                let count = self.reader.read_var_u32()?;
+                Tuple(
+                    (0..count)
+                        .map(|_| self.val())
+                        .collect::<anyhow::Result<_>>()?,
+                )
+            }
+            0x6e => Flags(self.names()?),
+            0x6d => Enum(self.names()?),
+            0x6b => Option(Box::new(self.val()?)),
+            0x6a => {
+                let ok = self.opt_val()?;
+                Result {
+                    ok,
+                    err: self.opt_val()?,
+                }
+            }
+            0x69 => Own(self.resource()?),
+            0x68 => Borrow(self.resource()?),
+            0x65 => Future(self.opt_val()?),
+            0x66 => Stream(self.opt_val()?),
+            code => bail!("invalid component value type {:#x}", code),
+        })
+    }
+}
+
+impl fmt::Display for InterfaceFunc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("func(")?;
+        for (i, (name, ty)) in self.params.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}: {}", name, ty)?;
+        }
+        f.write_str(")")?;
+        if let Some(result) = &self.result {
+            write!(f, " -> {}", result)?;
+        }
+        Ok(())
+    }
+}
+
+fn list<T>(
+    f: &mut fmt::Formatter,
+    items: &[T],
+    mut item: impl FnMut(&mut fmt::Formatter, &T) -> fmt::Result,
+) -> fmt::Result {
+    for (i, x) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        item(f, x)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for InterfaceType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use InterfaceType::*;
+        match self {
+            Bool => f.write_str("bool"),
+            S8 => f.write_str("s8"),
+            U8 => f.write_str("u8"),
+            S16 => f.write_str("s16"),
+            U16 => f.write_str("u16"),
+            S32 => f.write_str("s32"),
+            U32 => f.write_str("u32"),
+            S64 => f.write_str("s64"),
+            U64 => f.write_str("u64"),
+            F32 => f.write_str("f32"),
+            F64 => f.write_str("f64"),
+            Char => f.write_str("char"),
+            String => f.write_str("string"),
+            ErrorContext => f.write_str("error-context"),
+            List(ty) => write!(f, "list<{}>", ty),
+            FixedList(ty, len) => write!(f, "list<{}, {}>", ty, len),
+            Map(key, value) => write!(f, "map<{}, {}>", key, value),
+            Tuple(types) => {
+                f.write_str("tuple<")?;
+                list(f, types, |f, ty| write!(f, "{}", ty))?;
+                f.write_str(">")
+            }
+            Option(ty) => write!(f, "option<{}>", ty),
+            Result {
+                ok: None,
+                err: None,
+            } => f.write_str("result"),
+            Result {
+                ok: Some(ok),
+                err: None,
+            } => write!(f, "result<{}>", ok),
+            Result {
+                ok: None,
+                err: Some(err),
+            } => write!(f, "result<_, {}>", err),
+            Result {
+                ok: Some(ok),
+                err: Some(err),
+            } => write!(f, "result<{}, {}>", ok, err),
+            Record(fields) => {
+                f.write_str("record { ")?;
+                list(f, fields, |f, (name, ty)| write!(f, "{}: {}", name, ty))?;
+                f.write_str(" }")
+            }
+            Variant(cases) => {
+                f.write_str("variant { ")?;
+                list(f, cases, |f, (name, ty)| match ty {
+                    Some(ty) => write!(f, "{}({})", name, ty),
+                    None => f.write_str(name),
+                })?;
+                f.write_str(" }")
+            }
+            Enum(names) => {
+                f.write_str("enum { ")?;
+                list(f, names, |f, name| f.write_str(name))?;
+                f.write_str(" }")
+            }
+            Flags(names) => {
+                f.write_str("flags { ")?;
+                list(f, names, |f, name| f.write_str(name))?;
+                f.write_str(" }")
+            }
+            Own(name) => f.write_str(name),
+            Borrow(name) => write!(f, "borrow<{}>", name),
+            Future(None) => f.write_str("future"),
+            Future(Some(ty)) => write!(f, "future<{}>", ty),
+            Stream(None) => f.write_str("stream"),
+            Stream(Some(ty)) => write!(f, "stream<{}>", ty),
+            Named(name, _) => f.write_str(name),
+        }
+    }
+}
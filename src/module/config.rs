@@ -1,9 +1,78 @@
+use crate::cancel::CancellationToken;
 use crate::error::Result;
 use crate::ir::InstrLocId;
 use crate::module::Module;
 use crate::parse::IndicesToIds;
 use std::fmt;
+use std::io::Read;
 use std::path::Path;
+use std::sync::Arc;
+
+/// What to do with odd custom sections when parsing a module, as configured
+/// with `ModuleConfig::custom_section_policy`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum CustomSectionPolicy {
+    /// Fail to parse the module.
+    Error,
+    /// Log a warning, and skip what can't be parsed.
+    ///
+    /// Unknown and duplicated `name` subsections are skipped, and so is the
+    /// rest of a section that fails to parse, keeping whatever was parsed
+    /// before the error. `Module::repair` reports a `name` section that was
+    /// cut short like this.
+    #[default]
+    WarnAndSkip,
+    /// Keep what can't be parsed as it was encoded.
+    ///
+    /// Unknown and duplicated `name` subsections are emitted again after the
+    /// other subsections, and a section that fails to parse is kept whole as
+    /// a `RawCustomSection`, in place of anything parsed from it. The indices
+    /// in these are kept as they are, so they may no longer be right if the
+    /// module is modified.
+    KeepRaw,
+}
+
+/// An anonymous item that a synthetic name is generated for, as configured
+/// with `ModuleConfig::synthetic_names`.
+///
+/// Indices are those of the binary being parsed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SyntheticName {
+    /// The function at `index` of the function index space, which counts
+    /// imported functions first.
+    Function {
+        /// The function's index.
+        index: u32,
+    },
+    /// The argument at `index` of the function at `func`.
+    Argument {
+        /// The function's index.
+        func: u32,
+        /// The argument's index.
+        index: u32,
+    },
+    /// The local at `index` of the function at `func`, whose index counts the
+    /// function's arguments first.
+    Local {
+        /// The function's index.
+        func: u32,
+        /// The local's index.
+        index: u32,
+    },
+}
+
+impl SyntheticName {
+    /// The name that walrus generates for this item by default, such as
+    /// `f3`, `arg0` or `l2`.
+    pub fn default_name(&self) -> String {
+        match *self {
+            SyntheticName::Function { index } => format!("f{}", index),
+            SyntheticName::Argument { index, .. } => format!("arg{}", index),
+            SyntheticName::Local { index, .. } => format!("l{}", index),
+        }
+    }
+}
 
 /// Configuration for a `Module` which currently affects parsing.
 #[derive(Default)]
@@ -12,12 +81,25 @@ pub struct ModuleConfig {
     pub(crate) generate_synthetic_names_for_anonymous_items: bool,
     pub(crate) only_stable_features: bool,
     pub(crate) skip_strict_validate: bool,
+    pub(crate) best_effort_parse: bool,
     pub(crate) skip_producers_section: bool,
     pub(crate) skip_name_section: bool,
     pub(crate) preserve_code_transform: bool,
+    pub(crate) generate_build_id: bool,
+    pub(crate) demangle_names: bool,
+    pub(crate) preserve_encoding: bool,
+    pub(crate) canonicalize_memargs: bool,
+    pub(crate) compression_friendly: bool,
+    pub(crate) custom_section_policy: CustomSectionPolicy,
+    pub(crate) max_functions: Option<u32>,
+    pub(crate) max_function_body_size: Option<u32>,
+    pub(crate) max_ir_memory: Option<usize>,
+    pub(crate) cancellation_token: Option<CancellationToken>,
     pub(crate) on_parse:
         Option<Box<dyn Fn(&mut Module, &IndicesToIds) -> Result<()> + Sync + Send + 'static>>,
     pub(crate) on_instr_loc: Option<Box<dyn Fn(&usize) -> InstrLocId + Sync + Send + 'static>>,
+    pub(crate) synthetic_names:
+        Option<Arc<dyn Fn(SyntheticName) -> String + Sync + Send + 'static>>,
 }
 
 impl Clone for ModuleConfig {
@@ -29,9 +111,21 @@ impl Clone for ModuleConfig {
                 .generate_synthetic_names_for_anonymous_items,
             only_stable_features: self.only_stable_features,
             skip_strict_validate: self.skip_strict_validate,
+            best_effort_parse: self.best_effort_parse,
             skip_producers_section: self.skip_producers_section,
             skip_name_section: self.skip_name_section,
             preserve_code_transform: self.preserve_code_transform,
+            generate_build_id: self.generate_build_id,
+            demangle_names: self.demangle_names,
+            preserve_encoding: self.preserve_encoding,
+            canonicalize_memargs: self.canonicalize_memargs,
+            compression_friendly: self.compression_friendly,
+            custom_section_policy: self.custom_section_policy,
+            max_functions: self.max_functions,
+            max_function_body_size: self.max_function_body_size,
+            max_ir_memory: self.max_ir_memory,
+            cancellation_token: self.cancellation_token.clone(),
+            synthetic_names: self.synthetic_names.clone(),
 
             // ... and this is left empty.
             on_parse: None,
@@ -49,11 +143,23 @@ impl fmt::Debug for ModuleConfig {
             ref generate_synthetic_names_for_anonymous_items,
             ref only_stable_features,
             ref skip_strict_validate,
+            ref best_effort_parse,
             ref skip_producers_section,
             ref skip_name_section,
             ref preserve_code_transform,
+            ref generate_build_id,
+            ref demangle_names,
+            ref preserve_encoding,
+            ref canonicalize_memargs,
+            ref compression_friendly,
+            ref custom_section_policy,
+            ref max_functions,
+            ref max_function_body_size,
+            ref max_ir_memory,
+            ref cancellation_token,
             ref on_parse,
             ref on_instr_loc,
+            ref synthetic_names,
         } = self;
 
         f.debug_struct("ModuleConfig")
@@ -64,11 +170,23 @@ impl fmt::Debug for ModuleConfig {
             )
             .field("only_stable_features", only_stable_features)
             .field("skip_strict_validate", skip_strict_validate)
+            .field("best_effort_parse", best_effort_parse)
             .field("skip_producers_section", skip_producers_section)
             .field("skip_name_section", skip_name_section)
             .field("preserve_code_transform", preserve_code_transform)
+            .field("generate_build_id", generate_build_id)
+            .field("demangle_names", demangle_names)
+            .field("preserve_encoding", preserve_encoding)
+            .field("canonicalize_memargs", canonicalize_memargs)
+            .field("compression_friendly", compression_friendly)
+            .field("custom_section_policy", custom_section_policy)
+            .field("max_functions", max_functions)
+            .field("max_function_body_size", max_function_body_size)
+            .field("max_ir_memory", max_ir_memory)
+            .field("cancellation_token", cancellation_token)
             .field("on_parse", &on_parse.as_ref().map(|_| ".."))
             .field("on_instr_loc", &on_instr_loc.as_ref().map(|_| ".."))
+            .field("synthetic_names", &synthetic_names.as_ref().map(|_| ".."))
             .finish()
     }
 }
@@ -117,13 +235,55 @@ impl ModuleConfig {
         self
     }
 
+    /// Generate synthetic names for anonymous items with `namer`, rather than
+    /// with walrus' default names, so that they can follow the conventions of
+    /// other tools.
+    ///
+    /// This also enables `generate_synthetic_names_for_anonymous_items`.
+    /// `SyntheticName::default_name` gives the default names, for items that
+    /// `namer` doesn't care to name differently.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use walrus::{ModuleConfig, SyntheticName};
+    ///
+    /// let mut config = ModuleConfig::new();
+    /// config.synthetic_names(|item| match item {
+    ///     SyntheticName::Function { index } => format!("wasm-function[{}]", index),
+    ///     _ => item.default_name(),
+    /// });
+    /// ```
+    pub fn synthetic_names<F>(&mut self, namer: F) -> &mut ModuleConfig
+    where
+        F: Fn(SyntheticName) -> String + Send + Sync + 'static,
+    {
+        self.generate_synthetic_names_for_anonymous_items = true;
+        self.synthetic_names = Some(Arc::new(namer));
+        self
+    }
+
+    /// The synthetic name of `item`, if they are generated.
+    pub(crate) fn synthetic_name(&self, item: SyntheticName) -> Option<String> {
+        if !self.generate_synthetic_names_for_anonymous_items {
+            return None;
+        }
+        Some(match &self.synthetic_names {
+            Some(namer) => namer(item),
+            None => item.default_name(),
+        })
+    }
+
     /// Indicates whether the module, after parsing, performs strict validation
     /// of the wasm module to adhere with the current version of the wasm
     /// specification.
     ///
     /// This can be expensive for some modules and strictly isn't required to
     /// create a `Module` from a wasm file. This includes checks such as "atomic
-    /// instructions require a shared memory".
+    /// instructions require a shared memory". The binary is first checked with
+    /// wasmparser's validator, with the proposals walrus supports enabled
+    /// unless `only_stable_features` is set, and errors name the offset and
+    /// function at which validation failed.
     ///
     /// By default this flag is `true`
     pub fn strict_validate(&mut self, strict: bool) -> &mut ModuleConfig {
@@ -131,6 +291,24 @@ impl ModuleConfig {
         self
     }
 
+    /// Indicates whether function bodies that fail to parse are kept, rather
+    /// than failing to parse the whole module.
+    ///
+    /// This is for tooling that inspects and repairs partially corrupted
+    /// modules. Each function whose body fails to parse gets a body that just
+    /// traps, and its original body is recorded along with the error, to be
+    /// found with `ModuleFunctions::invalid_bodies` and fixed with
+    /// `Module::replace_func_body_from_bytes`. Until then, the original body
+    /// is emitted as it was. The binary isn't validated as
+    /// a whole up front, even with `strict_validate`, though the rest of the
+    /// module still has to parse; the IR is validated after parsing as usual.
+    ///
+    /// By default this flag is `false`
+    pub fn best_effort_parse(&mut self, best_effort: bool) -> &mut ModuleConfig {
+        self.best_effort_parse = best_effort;
+        self
+    }
+
     /// Indicates whether the module will have the "producers" custom section
     /// which preserves the original producers and also includes `walrus`.
     ///
@@ -200,6 +378,161 @@ impl ModuleConfig {
         self
     }
 
+    /// Sets a flag to whether a build id is computed for this module when it
+    /// is emitted.
+    ///
+    /// When enabled, a `build_id` custom section is emitted containing a hash
+    /// of the whole emitted module, replacing any build id set with
+    /// `Module::set_build_id` or parsed from the original module. The hash is
+    /// computed over the final binary with the build id zeroed out, so that
+    /// emitting the same module twice yields the same id.
+    ///
+    /// By default this flag is `false`.
+    pub fn generate_build_id(&mut self, generate: bool) -> &mut ModuleConfig {
+        self.generate_build_id = generate;
+        self
+    }
+
+    /// Sets a flag to whether function names are demangled when emitting the
+    /// "name" section.
+    ///
+    /// When enabled, the "name" section contains `Function::demangled_name`
+    /// instead of the original, possibly mangled, name of each function. Which
+    /// manglings are supported depends on the `demangle-rust` and
+    /// `demangle-cpp` features.
+    ///
+    /// By default this flag is `false`.
+    pub fn demangle_names(&mut self, demangle: bool) -> &mut ModuleConfig {
+        self.demangle_names = demangle;
+        self
+    }
+
+    /// Sets a flag to whether emitting a module that wasn't modified since it
+    /// was parsed reproduces the original binary byte for byte.
+    ///
+    /// Otherwise walrus always re-encodes modules in its own way, which may
+    /// differ from the original in LEB128 widths, section order, the grouping
+    /// of locals, etc. even when nothing changed. Any modification makes the
+    /// whole module be re-encoded, though.
+    ///
+    /// When enabled, the original binary is kept around, and parsing also
+    /// encodes the module once, to later tell whether anything changed.
+    /// Modules are never reproduced when `generate_build_id` is enabled,
+    /// since their build id is recomputed.
+    ///
+    /// The original binary is also what `Module::emit_patch` makes patches
+    /// against.
+    ///
+    /// By default this flag is `false`.
+    pub fn preserve_encoding(&mut self, preserve: bool) -> &mut ModuleConfig {
+        self.preserve_encoding = preserve;
+        self
+    }
+
+    /// Sets a flag to whether the alignment and offset of memory instructions
+    /// are emitted in their shortest encoding.
+    ///
+    /// Otherwise they keep the width they were encoded with in the parsed
+    /// binary, see `MemArgEncoding`, so that re-encoding a function doesn't
+    /// change how its untouched memory instructions are encoded.
+    ///
+    /// By default this flag is `false`.
+    pub fn canonicalize_memargs(&mut self, canonicalize: bool) -> &mut ModuleConfig {
+        self.canonicalize_memargs = canonicalize;
+        self
+    }
+
+    /// Sets a flag to whether the module is emitted in a layout that
+    /// compresses better with general purpose compressors like gzip and
+    /// brotli, for modules that are served compressed.
+    ///
+    /// This sorts the type section so that the most used types come first,
+    /// and get the shortest and most repeated indices, sorts the exports by
+    /// name, and emits all LEB128 numbers in their shortest encoding, as with
+    /// `canonicalize_memargs`. None of this makes the raw module any
+    /// larger.
+    ///
+    /// The effect depends on how far the module is from this layout already.
+    /// LLVM emits something close to it, so for example the gzipped size of
+    /// `benches/fixtures/dodrio-todomvc.wasm` only goes from 81202 to 81199
+    /// bytes, but modules whose types and exports were added by transforms,
+    /// or whose memory instructions have padded immediates, gain more.
+    ///
+    /// Note that the order of the exports is observable by embedders that
+    /// iterate over them, e.g. through `WebAssembly.Module.exports`.
+    ///
+    /// By default this flag is `false`.
+    pub fn compression_friendly(&mut self, compression_friendly: bool) -> &mut ModuleConfig {
+        self.compression_friendly = compression_friendly;
+        self
+    }
+
+    /// Sets what to do with odd custom sections, which walrus can otherwise
+    /// do without.
+    ///
+    /// This covers unknown and duplicated subsections of the `name` section,
+    /// and `name`, `producers`, `build_id`, `walrus.metadata` and registry
+    /// annotation sections that fail to parse. See `CustomSectionPolicy` for
+    /// the options.
+    ///
+    /// By default odd sections are skipped with a warning.
+    pub fn custom_section_policy(&mut self, policy: CustomSectionPolicy) -> &mut ModuleConfig {
+        self.custom_section_policy = policy;
+        self
+    }
+
+    /// Sets the maximum number of functions, imported and local, that a
+    /// module may have to be parsed.
+    ///
+    /// This, along with `max_function_body_size` and `max_ir_memory`, bounds
+    /// the resources that parsing untrusted wasm takes, failing with an error
+    /// before they are used up.
+    ///
+    /// By default there is no limit.
+    pub fn max_functions(&mut self, max: u32) -> &mut ModuleConfig {
+        self.max_functions = Some(max);
+        self
+    }
+
+    /// Sets the maximum size, in bytes, of each function body of a module to
+    /// be parsed, including its local declarations.
+    ///
+    /// By default there is no limit.
+    pub fn max_function_body_size(&mut self, max: u32) -> &mut ModuleConfig {
+        self.max_function_body_size = Some(max);
+        self
+    }
+
+    /// Sets the maximum memory, in bytes, that the IR of a module may take up
+    /// to be parsed.
+    ///
+    /// This is an estimate of the memory that the functions, locals and
+    /// instructions of the module take up, which is counted before they are
+    /// created. Instructions are counted generously, as if each byte of the
+    /// function bodies was an instruction of its own, but the memory taken up
+    /// by everything else is small next to the size of the binary. This
+    /// catches modules like those declaring billions of locals in a few bytes.
+    ///
+    /// By default there is no limit.
+    pub fn max_ir_memory(&mut self, max: usize) -> &mut ModuleConfig {
+        self.max_ir_memory = Some(max);
+        self
+    }
+
+    /// Sets a token for cancelling work on the module from another thread.
+    ///
+    /// Parsing checks the token as it goes, and so do `passes::gc::try_run`
+    /// and `Module::try_emit_wasm` for the parsed module, which keeps this
+    /// configuration. They fail with `ErrorKind::Cancelled` once the token is
+    /// cancelled, without finishing. The module isn't modified by a cancelled
+    /// `gc::try_run`.
+    ///
+    /// By default there is no token, and work can't be cancelled.
+    pub fn cancellation_token(&mut self, token: CancellationToken) -> &mut ModuleConfig {
+        self.cancellation_token = Some(token);
+        self
+    }
+
     /// Parses an in-memory WebAssembly file into a `Module` using this
     /// configuration.
     pub fn parse(&self, wasm: &[u8]) -> Result<Module> {
@@ -213,4 +546,13 @@ impl ModuleConfig {
     {
         Module::from_file_with_config(path, self)
     }
+
+    /// Parses WebAssembly read from `reader` into a `Module` using this
+    /// configuration.
+    pub fn parse_reader<R>(&self, reader: R) -> Result<Module>
+    where
+        R: Read,
+    {
+        Module::from_reader_with_config(reader, self)
+    }
 }
@@ -0,0 +1,361 @@
+//! Typed support for the custom sections of Wasm coredumps.
+//!
+//! A coredump is itself a Wasm module, whose data segments hold the memory of
+//! the crashed instance and whose custom sections describe its process,
+//! threads, modules, and instances. See the [tool conventions] for details.
+//!
+//! When parsing a module, well-formed coredump sections are added to
+//! `Module::customs` as the typed sections defined here, so that they can be
+//! retrieved with e.g. `module.customs.get_typed::<Coredump>()`. Malformed
+//! ones are kept as `RawCustomSection`s.
+//!
+//! [tool conventions]: https://github.com/WebAssembly/tool-conventions/blob/main/Coredump.md
+
+use crate::emit::IdsToIndices;
+use crate::encode::Encoder;
+use crate::error::Result;
+use crate::module::custom::{CustomSection, ModuleCustomSections};
+use anyhow::bail;
+use std::borrow::Cow;
+use wasmparser::BinaryReader;
+
+/// The `core` custom section, describing the crashed process.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coredump {
+    /// The name of the executable that crashed.
+    pub executable_name: String,
+}
+
+/// A `corestack` custom section, describing the stack of one thread.
+///
+/// A coredump has one of these sections per thread.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CoreStack {
+    /// The name of this thread.
+    pub thread_name: String,
+    /// The frames on this thread's stack, youngest first.
+    pub frames: Vec<CoreStackFrame>,
+}
+
+/// A single frame on a `CoreStack`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CoreStackFrame {
+    /// The index of the instance, in the `coreinstances` section, that this
+    /// frame's function belongs to.
+    pub instance: u32,
+    /// The index of this frame's function within its module.
+    ///
+    /// This refers to the module that crashed rather than the coredump, so it
+    /// is a raw index instead of a `FunctionId`.
+    pub func: u32,
+    /// The offset of the current instruction, relative to the start of the
+    /// function's body in the code section.
+    pub code_offset: u32,
+    /// The values of this frame's locals.
+    pub locals: Vec<CoreValue>,
+    /// The values on this frame's operand stack, from the bottom up.
+    pub stack: Vec<CoreValue>,
+}
+
+/// A value of a local or operand stack slot in a `CoreStackFrame`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CoreValue {
+    /// The value was optimized out or is otherwise unavailable.
+    Missing,
+    /// A 32-bit integer.
+    I32(i32),
+    /// A 64-bit integer.
+    I64(i64),
+    /// A 32-bit float.
+    F32(f32),
+    /// A 64-bit float.
+    F64(f64),
+}
+
+/// The `coremodules` custom section, listing the modules of the crashed
+/// process by name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CoreModules {
+    /// The names of the modules.
+    pub modules: Vec<String>,
+}
+
+/// The `coreinstances` custom section, listing the instances of the crashed
+/// process.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CoreInstances {
+    /// The instances.
+    pub instances: Vec<CoreInstance>,
+}
+
+/// A single instance in the `coreinstances` section.
+///
+/// Memories and globals are referenced by their index in the coredump
+/// module itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CoreInstance {
+    /// The index of this instance's module in the `coremodules` section.
+    pub module: u32,
+    /// The indices of this instance's memories.
+    pub memories: Vec<u32>,
+    /// The indices of this instance's globals.
+    pub globals: Vec<u32>,
+}
+
+impl Coredump {
+    /// Parse a `core` section from its payload.
+    pub fn parse(data: &[u8]) -> Result<Coredump> {
+        let mut reader = BinaryReader::new(data);
+        expect_zero(&mut reader, "process info")?;
+        let executable_name = reader.read_string()?.to_string();
+        expect_eof(&reader)?;
+        Ok(Coredump { executable_name })
+    }
+}
+
+impl CustomSection for Coredump {
+    fn name(&self) -> &str {
+        "core"
+    }
+
+    fn data(&self, _: &IdsToIndices) -> Cow<'_, [u8]> {
+        let mut data = Vec::new();
+        let mut encoder = Encoder::new(&mut data);
+        encoder.byte(0x00);
+        encoder.str(&self.executable_name);
+        data.into()
+    }
+
+    fn clone_section(&self) -> Option<Box<dyn CustomSection>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+impl CoreStack {
+    /// Parse a `corestack` section from its payload.
+    pub fn parse(data: &[u8]) -> Result<CoreStack> {
+        let mut reader = BinaryReader::new(data);
+        expect_zero(&mut reader, "thread info")?;
+        let thread_name = reader.read_string()?.to_string();
+        let frames = read_vec(&mut reader, CoreStackFrame::parse)?;
+        expect_eof(&reader)?;
+        Ok(CoreStack {
+            thread_name,
+            frames,
+        })
+    }
+}
+
+impl CustomSection for CoreStack {
+    fn name(&self) -> &str {
+        "corestack"
+    }
+
+    fn data(&self, _: &IdsToIndices) -> Cow<'_, [u8]> {
+        let mut data = Vec::new();
+        let mut encoder = Encoder::new(&mut data);
+        encoder.byte(0x00);
+        encoder.str(&self.thread_name);
+        encoder.usize(self.frames.len());
+        for frame in self.frames.iter() {
+            frame.emit(&mut encoder);
+        }
+        data.into()
+    }
+
+    fn clone_section(&self) -> Option<Box<dyn CustomSection>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+impl CoreStackFrame {
+    fn parse(reader: &mut BinaryReader) -> Result<CoreStackFrame> {
+        expect_zero(reader, "frame")?;
+        Ok(CoreStackFrame {
+            instance: reader.read_var_u32()?,
+            func: reader.read_var_u32()?,
+            code_offset: reader.read_var_u32()?,
+            locals: read_vec(reader, CoreValue::parse)?,
+            stack: read_vec(reader, CoreValue::parse)?,
+        })
+    }
+
+    fn emit(&self, encoder: &mut Encoder) {
+        encoder.byte(0x00);
+        encoder.u32(self.instance);
+        encoder.u32(self.func);
+        encoder.u32(self.code_offset);
+        for values in [&self.locals, &self.stack].iter() {
+            encoder.usize(values.len());
+            for value in values.iter() {
+                value.emit(encoder);
+            }
+        }
+    }
+}
+
+impl CoreValue {
+    fn parse(reader: &mut BinaryReader) -> Result<CoreValue> {
+        Ok(match reader.read_u8()? {
+            0x01 => CoreValue::Missing,
+            0x7f => CoreValue::I32(reader.read_var_i32()?),
+            0x7e => CoreValue::I64(reader.read_var_i64()?),
+            0x7d => CoreValue::F32(f32::from_bits(reader.read_f32()?.bits())),
+            0x7c => CoreValue::F64(f64::from_bits(reader.read_f64()?.bits())),
+            byte => bail!("invalid coredump value type: {:#x}", byte),
+        })
+    }
+
+    fn emit(&self, encoder: &mut Encoder) {
+        match *self {
+            CoreValue::Missing => encoder.byte(0x01),
+            CoreValue::I32(val) => {
+                encoder.byte(0x7f);
+                encoder.i32(val);
+            }
+            CoreValue::I64(val) => {
+                encoder.byte(0x7e);
+                encoder.i64(val);
+            }
+            CoreValue::F32(val) => {
+                encoder.byte(0x7d);
+                encoder.f32(val);
+            }
+            CoreValue::F64(val) => {
+                encoder.byte(0x7c);
+                encoder.f64(val);
+            }
+        }
+    }
+}
+
+impl CoreModules {
+    /// Parse a `coremodules` section from its payload.
+    pub fn parse(data: &[u8]) -> Result<CoreModules> {
+        let mut reader = BinaryReader::new(data);
+        let modules = read_vec(&mut reader, |reader| {
+            expect_zero(reader, "module")?;
+            Ok(reader.read_string()?.to_string())
+        })?;
+        expect_eof(&reader)?;
+        Ok(CoreModules { modules })
+    }
+}
+
+impl CustomSection for CoreModules {
+    fn name(&self) -> &str {
+        "coremodules"
+    }
+
+    fn data(&self, _: &IdsToIndices) -> Cow<'_, [u8]> {
+        let mut data = Vec::new();
+        let mut encoder = Encoder::new(&mut data);
+        encoder.usize(self.modules.len());
+        for module in self.modules.iter() {
+            encoder.byte(0x00);
+            encoder.str(module);
+        }
+        data.into()
+    }
+
+    fn clone_section(&self) -> Option<Box<dyn CustomSection>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+impl CoreInstances {
+    /// Parse a `coreinstances` section from its payload.
+    pub fn parse(data: &[u8]) -> Result<CoreInstances> {
+        let mut reader = BinaryReader::new(data);
+        let instances = read_vec(&mut reader, |reader| {
+            expect_zero(reader, "instance")?;
+            Ok(CoreInstance {
+                module: reader.read_var_u32()?,
+                memories: read_vec(reader, |r| Ok(r.read_var_u32()?))?,
+                globals: read_vec(reader, |r| Ok(r.read_var_u32()?))?,
+            })
+        })?;
+        expect_eof(&reader)?;
+        Ok(CoreInstances { instances })
+    }
+}
+
+impl CustomSection for CoreInstances {
+    fn name(&self) -> &str {
+        "coreinstances"
+    }
+
+    fn data(&self, _: &IdsToIndices) -> Cow<'_, [u8]> {
+        let mut data = Vec::new();
+        let mut encoder = Encoder::new(&mut data);
+        encoder.usize(self.instances.len());
+        for instance in self.instances.iter() {
+            encoder.byte(0x00);
+            encoder.u32(instance.module);
+            for indices in [&instance.memories, &instance.globals].iter() {
+                encoder.usize(indices.len());
+                for idx in indices.iter() {
+                    encoder.u32(*idx);
+                }
+            }
+        }
+        data.into()
+    }
+
+    fn clone_section(&self) -> Option<Box<dyn CustomSection>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+/// Try to parse the custom section `name` as a coredump section and add it to
+/// `customs`, returning whether it was added.
+pub(crate) fn add_coredump_section(
+    customs: &mut ModuleCustomSections,
+    name: &str,
+    data: &[u8],
+) -> bool {
+    let result = match name {
+        "core" => Coredump::parse(data).map(|s| {
+            customs.add(s);
+        }),
+        "corestack" => CoreStack::parse(data).map(|s| {
+            customs.add(s);
+        }),
+        "coremodules" => CoreModules::parse(data).map(|s| {
+            customs.add(s);
+        }),
+        "coreinstances" => CoreInstances::parse(data).map(|s| {
+            customs.add(s);
+        }),
+        _ => return false,
+    };
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("failed to parse `{}` custom section {}", name, e);
+            false
+        }
+    }
+}
+
+fn expect_zero(reader: &mut BinaryReader, what: &str) -> Result<()> {
+    match reader.read_u8()? {
+        0x00 => Ok(()),
+        byte => bail!("invalid coredump {} kind: {:#x}", what, byte),
+    }
+}
+
+fn expect_eof(reader: &BinaryReader) -> Result<()> {
+    if !reader.eof() {
+        bail!("trailing bytes at the end of coredump section");
+    }
+    Ok(())
+}
+
+fn read_vec<'a, T>(
+    reader: &mut BinaryReader<'a>,
+    mut read: impl FnMut(&mut BinaryReader<'a>) -> Result<T>,
+) -> Result<Vec<T>> {
+    let count = reader.read_var_u32()?;
+    (0..count).map(|_| read(reader)).collect()
+}
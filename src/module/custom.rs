@@ -3,7 +3,7 @@
 use crate::passes::Roots;
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
 use crate::CodeTransform;
-use crate::IdsToIndices;
+use crate::{IdsToIndices, IndexRemap};
 use std::any::Any;
 use std::borrow::Cow;
 use std::fmt::{self, Debug};
@@ -35,11 +35,41 @@ pub trait CustomSection: WalrusAny + Debug + Send + Sync {
     /// This function will add any referenced core wasm items into the `Roots`
     /// array provided.
     ///
+    /// Custom sections that refer to functions, globals, tables or memories by
+    /// id, like linking information or coverage maps, should add them here:
+    /// `passes::gc` doesn't know about these references otherwise, and would
+    /// delete the items if nothing else uses them, leaving the section with
+    /// ids that have no index when it is emitted.
+    ///
     /// The default provided method does nothing.
     fn add_gc_roots(&self, roots: &mut Roots) {
         drop(roots);
     }
 
+    /// Update the raw function, global, table and memory indices in this
+    /// custom section to the indices they are emitted at.
+    ///
+    /// If the module was not parsed from a Wasm binary, then this method is
+    /// never called.
+    ///
+    /// Like `apply_code_transform`, this method is called just before a
+    /// custom section's data is emitted into the Wasm binary. Custom sections
+    /// that refer to items by ID can look up their indices in `data` instead,
+    /// but sections that keep the indices of the original Wasm binary go stale
+    /// when items are added, deleted or reordered, unless they update them
+    /// here.
+    ///
+    /// Custom sections are kept for later emits, so on every emit after the
+    /// first one that writes walrus's own encoding of the module, `remap` maps
+    /// from the indices of that previous emit instead of the original binary.
+    /// Emits of the original binary, with `ModuleConfig::preserve_encoding`,
+    /// don't call this method.
+    ///
+    /// The default provided method does nothing.
+    fn apply_index_remap(&mut self, remap: &IndexRemap) {
+        let _ = remap;
+    }
+
     /// Apply the given code transformations to this custom section.
     ///
     /// If the module was not configured with `preserve_code_transform = true`,
@@ -55,10 +85,22 @@ pub trait CustomSection: WalrusAny + Debug + Send + Sync {
     /// into the code section, and we can use these transforms to fix those
     /// offsets after having transformed various functions and instructions.
     ///
+    /// Like the indices of `apply_index_remap`, the transforms map from the
+    /// offsets of the original binary on the first emit, and from those of the
+    /// previous emit of walrus's own encoding afterwards.
+    ///
     /// The default provided method does nothing.
     fn apply_code_transform(&mut self, transform: &CodeTransform) {
         let _ = transform;
     }
+
+    /// Clone this custom section, for clones of the module that it is in.
+    ///
+    /// The default provided method returns `None`, which leaves this custom
+    /// section out of clones of the module, so that the clones don't emit it.
+    fn clone_section(&self) -> Option<Box<dyn CustomSection>> {
+        None
+    }
 }
 
 /// A wrapper trait around `any` but implemented for all types that already
@@ -120,6 +162,10 @@ impl CustomSection for RawCustomSection {
     fn data(&self, _: &IdsToIndices) -> Cow<[u8]> {
         self.data.as_slice().into()
     }
+
+    fn clone_section(&self) -> Option<Box<dyn CustomSection>> {
+        Some(Box::new(self.clone()))
+    }
 }
 
 /// A common trait for custom section identifiers.
@@ -290,6 +336,28 @@ pub struct ModuleCustomSections {
     arena: TombstoneArena<Option<Box<dyn CustomSection>>>,
 }
 
+/// Clones only contain the custom sections that implement
+/// `CustomSection::clone_section`, and have their own ids for them: look the
+/// sections up again, eg with `get_typed`, rather than reusing the ids of the
+/// original.
+impl Clone for ModuleCustomSections {
+    fn clone(&self) -> ModuleCustomSections {
+        let mut arena = TombstoneArena::default();
+        for (_, section) in self.iter() {
+            match section.clone_section() {
+                Some(section) => {
+                    arena.alloc(Some(section));
+                }
+                None => log::warn!(
+                    "leaving custom section {} out of a clone, since it can't be cloned",
+                    section.name()
+                ),
+            }
+        }
+        ModuleCustomSections { arena }
+    }
+}
+
 impl ModuleCustomSections {
     /// Add a new custom section to the module.
     pub fn add<T>(&mut self, custom_section: T) -> TypedCustomSectionId<T>
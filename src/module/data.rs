@@ -4,7 +4,7 @@ use crate::emit::{Emit, EmitContext, Section};
 use crate::ir::Value;
 use crate::parse::IndicesToIds;
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
-use crate::{GlobalId, InitExpr, MemoryId, Module, Result, ValType};
+use crate::{FunctionId, FunctionKind, GlobalId, InitExpr, MemoryId, Module, Result, ValType};
 use anyhow::{bail, Context};
 
 /// A passive element segment identifier
@@ -18,7 +18,7 @@ pub type DataId = Id<Data>;
 /// memory (or memories) via the `memory.init` instruction (passive data
 /// segments). See the `kind` member and `DataKind` type for more details on the
 /// active/passive distinction.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Data {
     id: DataId,
     /// What kind of data segment is this? Passive or active?
@@ -28,7 +28,7 @@ pub struct Data {
 }
 
 /// The kind of data segment: passive or active.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum DataKind {
     /// An active data segment that is automatically initialized at some address
     /// in a static memory.
@@ -84,17 +84,35 @@ impl Data {
 
 /// All passive data sections of a wasm module, used to initialize memories via
 /// various instructions.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct ModuleData {
     arena: TombstoneArena<Data>,
 }
 
 impl ModuleData {
+    /// Add a new passive data segment with the given contents.
+    ///
+    /// Passive segments are copied into memories with `memory.init`, see
+    /// `InstrSeqBuilder::memory_init_and_drop`.
+    pub fn add_passive(&mut self, value: Vec<u8>) -> DataId {
+        self.arena.alloc_with_id(|id| Data {
+            id,
+            kind: DataKind::Passive,
+            value,
+        })
+    }
+
     /// Get an element associated with an ID
     pub fn get(&self, id: DataId) -> &Data {
         &self.arena[id]
     }
 
+    /// Get the data segment with the given id, if it is one of this module's and
+    /// hasn't been deleted.
+    pub(crate) fn try_get(&self, id: DataId) -> Option<&Data> {
+        self.arena.get(id)
+    }
+
     /// Get an element associated with an ID
     pub fn get_mut(&mut self, id: DataId) -> &mut Data {
         &mut self.arena[id]
@@ -172,6 +190,118 @@ impl Module {
         }
     }
 
+    /// Split the active data segment `id` at byte `at`, and return the id of
+    /// a new segment with the bytes from `at` onwards.
+    ///
+    /// The new segment is initialized right after the remaining bytes of the
+    /// original one, but is placed after all other segments. This only
+    /// matters if other segments overlap it. Segments at addresses relative
+    /// to a global can't be split, since their second half would need an
+    /// address that is not a constant or a global.
+    pub fn split_data(&mut self, id: DataId, at: usize) -> Result<DataId> {
+        let data = self.data.get_mut(id);
+        let (memory, address) = match &data.kind {
+            DataKind::Active(ActiveData {
+                memory,
+                location: ActiveDataLocation::Absolute(address),
+            }) => (*memory, *address),
+            DataKind::Active(_) => bail!("can't split a data segment at a relative address"),
+            DataKind::Passive => bail!("can't split a passive data segment"),
+        };
+        if at > data.value.len() {
+            bail!(
+                "can't split a data segment of {} bytes at byte {}",
+                data.value.len(),
+                at
+            );
+        }
+        let address = address
+            .checked_add(at as u32)
+            .ok_or_else(|| anyhow::anyhow!("data segment address overflows"))?;
+
+        let value = data.value.split_off(at);
+        let split = self.data.arena.alloc_with_id(|id| Data {
+            id,
+            kind: DataKind::Active(ActiveData {
+                memory,
+                location: ActiveDataLocation::Absolute(address),
+            }),
+            value,
+        });
+        self.memories.get_mut(memory).data_segments.insert(split);
+        Ok(split)
+    }
+
+    /// Make the active data segment `id` initialize `memory` instead of its
+    /// current memory.
+    pub fn set_data_memory(&mut self, id: DataId, memory: MemoryId) -> Result<()> {
+        let active = match &mut self.data.get_mut(id).kind {
+            DataKind::Active(active) => active,
+            DataKind::Passive => bail!("passive data segments have no memory"),
+        };
+        let old = std::mem::replace(&mut active.memory, memory);
+        self.memories.get_mut(old).data_segments.remove(&id);
+        self.memories.get_mut(memory).data_segments.insert(id);
+        Ok(())
+    }
+
+    /// Turn the active data segment `id` into a passive one, which is instead
+    /// copied into its memory by `memory.init` at the start of the local
+    /// function `init`.
+    ///
+    /// This is typically used to initialize memory lazily, or only on one
+    /// thread, with `init` being the start function or a function exported
+    /// for the embedder to call. Segments converted later are copied first.
+    pub fn make_data_passive(&mut self, id: DataId, init: FunctionId) -> Result<()> {
+        let (memory, location) = match &self.data.get(id).kind {
+            DataKind::Active(active) => (active.memory, active.location),
+            DataKind::Passive => bail!("data segment is already passive"),
+        };
+        let len = self.data.get(id).value.len() as u32;
+        let func = match &mut self.funcs.get_mut(init).kind {
+            FunctionKind::Local(func) => func,
+            _ => bail!("can only initialize data segments in local functions"),
+        };
+
+        let entry = func.entry_block();
+        let mut body = func.builder_mut().instr_seq(entry);
+        // Build the initialization at the end, and then move it to the start.
+        let start = body.instrs().len();
+        match location {
+            ActiveDataLocation::Absolute(address) => body.i32_const(address as i32),
+            ActiveDataLocation::Relative(global) => body.global_get(global),
+        };
+        body.memory_init_and_drop(memory, id, len);
+        let instrs = body.instrs_mut();
+        let built = instrs.len() - start;
+        instrs.rotate_right(built);
+
+        self.data.get_mut(id).kind = DataKind::Passive;
+        self.memories.get_mut(memory).data_segments.remove(&id);
+        Ok(())
+    }
+
+    /// Turn the passive data segment `id` into an active one, initializing
+    /// `memory` at `location` when the module is instantiated.
+    ///
+    /// It is up to you to remove any `memory.init` of the segment, since
+    /// active segments are dropped at instantiation, after which copying
+    /// anything out of them traps.
+    pub fn make_data_active(
+        &mut self,
+        id: DataId,
+        memory: MemoryId,
+        location: ActiveDataLocation,
+    ) -> Result<()> {
+        let data = self.data.get_mut(id);
+        if !data.is_passive() {
+            bail!("data segment is already active");
+        }
+        data.kind = DataKind::Active(ActiveData { memory, location });
+        self.memories.get_mut(memory).data_segments.insert(id);
+        Ok(())
+    }
+
     /// Parses a raw wasm section into a fully-formed `ModuleData` instance.
     pub(crate) fn parse_data(
         &mut self,
@@ -0,0 +1,165 @@
+//! Displaying ids along with what they identify, for debugging.
+
+use crate::{DataId, ElementId, FunctionId, FunctionKind, GlobalId, GlobalKind, ImportId};
+use crate::{LocalId, MemoryId, Module, TableId, TypeId};
+use std::fmt;
+
+/// Ids that can be displayed along with the items they identify in a module.
+///
+/// Ids on their own are opaque numbers, so this is handy when debugging
+/// passes: `format!("{}", func.display(&module))` gives something like
+/// `func[3] "main"`, naming items the same way as `Module::dump`, followed by
+/// their name, what they import, or their signature, as far as they have
+/// these.
+pub trait DisplayId: Copy {
+    /// Display this id along with what it identifies in `module`.
+    fn display(self, module: &Module) -> IdDisplay<'_, Self> {
+        IdDisplay { id: self, module }
+    }
+
+    /// Format this id along with what it identifies in `module`.
+    ///
+    /// Ids of items that are deleted, or aren't `module`'s to begin with, are
+    /// noted as such.
+    fn fmt_in(self, module: &Module, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+/// An id displayed along with what it identifies, as returned by
+/// `DisplayId::display`.
+///
+/// Both `Display` and `Debug` format the same way.
+pub struct IdDisplay<'a, T> {
+    id: T,
+    module: &'a Module,
+}
+
+impl<T: DisplayId> fmt::Display for IdDisplay<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.id.fmt_in(self.module, f)
+    }
+}
+
+impl<T: DisplayId> fmt::Debug for IdDisplay<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.id.fmt_in(self.module, f)
+    }
+}
+
+const MISSING: &str = " (not in the module)";
+
+fn fmt_name(name: &Option<String>, f: &mut fmt::Formatter) -> fmt::Result {
+    match name {
+        Some(name) => write!(f, " {:?}", name),
+        None => Ok(()),
+    }
+}
+
+fn fmt_import(module: &Module, import: Option<ImportId>, f: &mut fmt::Formatter) -> fmt::Result {
+    match import.and_then(|id| module.imports.try_get(id)) {
+        Some(import) => write!(f, " import {:?} {:?}", import.module, import.name),
+        None => Ok(()),
+    }
+}
+
+impl DisplayId for FunctionId {
+    fn fmt_in(self, module: &Module, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "func[{}]", self.index())?;
+        let func = match module.funcs.try_get(self) {
+            Some(func) => func,
+            None => return f.write_str(MISSING),
+        };
+        fmt_name(&func.name, f)?;
+        match &func.kind {
+            FunctionKind::Import(import) if func.name.is_none() => {
+                fmt_import(module, Some(import.import), f)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl DisplayId for GlobalId {
+    fn fmt_in(self, module: &Module, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "global[{}]", self.index())?;
+        let global = match module.globals.try_get(self) {
+            Some(global) => global,
+            None => return f.write_str(MISSING),
+        };
+        fmt_name(&global.name, f)?;
+        match global.kind {
+            GlobalKind::Import(import) if global.name.is_none() => {
+                fmt_import(module, Some(import), f)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl DisplayId for LocalId {
+    fn fmt_in(self, module: &Module, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "local[{}]", self.index())?;
+        match module.locals.try_get(self) {
+            Some(local) => fmt_name(&local.name, f),
+            None => f.write_str(MISSING),
+        }
+    }
+}
+
+impl DisplayId for TypeId {
+    fn fmt_in(self, module: &Module, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "type[{}]", self.index())?;
+        let ty = match module.types.try_get(self) {
+            Some(ty) => ty,
+            None => return f.write_str(MISSING),
+        };
+        fmt_name(&ty.name, f)?;
+        let list = |types: &[crate::ValType]| {
+            types
+                .iter()
+                .map(|ty| ty.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        write!(f, " [{}] -> [{}]", list(ty.params()), list(ty.results()))
+    }
+}
+
+impl DisplayId for TableId {
+    fn fmt_in(self, module: &Module, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "table[{}]", self.index())?;
+        match module.tables.try_get(self) {
+            Some(table) => fmt_import(module, table.import, f),
+            None => f.write_str(MISSING),
+        }
+    }
+}
+
+impl DisplayId for MemoryId {
+    fn fmt_in(self, module: &Module, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "memory[{}]", self.index())?;
+        match module.memories.try_get(self) {
+            Some(memory) => fmt_import(module, memory.import, f),
+            None => f.write_str(MISSING),
+        }
+    }
+}
+
+impl DisplayId for DataId {
+    fn fmt_in(self, module: &Module, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "data[{}]", self.index())?;
+        match module.data.try_get(self) {
+            Some(_) => Ok(()),
+            None => f.write_str(MISSING),
+        }
+    }
+}
+
+impl DisplayId for ElementId {
+    fn fmt_in(self, module: &Module, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "elem[{}]", self.index())?;
+        match module.elements.try_get(self) {
+            Some(_) => Ok(()),
+            None => f.write_str(MISSING),
+        }
+    }
+}
@@ -4,19 +4,44 @@ use crate::emit::{Emit, EmitContext, Section};
 use crate::ir::Value;
 use crate::parse::IndicesToIds;
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
-use crate::{FunctionId, InitExpr, Module, Result, TableKind, ValType};
+use crate::{ErrorKind, FunctionId, IndexType, InitExpr, Module, Result, TableKind, ValType};
 use anyhow::{bail, Context};
 
 /// A passive element segment identifier
 pub type ElementId = Id<Element>;
 
-/// A passive segment which contains a list of functions
-#[derive(Debug)]
+/// A passive or declared element segment which contains a list of functions
+#[derive(Debug, Clone)]
 pub struct Element {
     id: Id<Element>,
 
-    /// The function members of this passive elements segment.
-    pub members: Vec<FunctionId>,
+    /// The kind of this element segment.
+    pub kind: ElementKind,
+
+    /// The function members of this elements segment, where `None` is a null
+    /// function reference.
+    pub members: Vec<Option<FunctionId>>,
+
+    /// Whether this segment's members are encoded as `ref.func` and
+    /// `ref.null` expressions, or as a vector of function indices.
+    ///
+    /// Segments with null function references can only be encoded as
+    /// expressions, so they are regardless of this flag.
+    pub uses_exprs: bool,
+}
+
+/// The kind of a non-active element segment.
+///
+/// Active element segments are represented with their table's initializers
+/// instead, see `FunctionTable`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ElementKind {
+    /// A passive element segment, which is copied into a table with the
+    /// `table.init` instruction and then released via `elem.drop`.
+    Passive,
+    /// A declared element segment, which is never copied into a table and
+    /// only declares the functions that are referenced via `ref.func`.
+    Declared,
 }
 
 impl Element {
@@ -34,17 +59,47 @@ impl Tombstone for Element {
 
 /// All element segments of a wasm module, used to initialize `anyfunc` tables,
 /// used as function pointers.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct ModuleElements {
     arena: TombstoneArena<Element>,
 }
 
 impl ModuleElements {
+    /// Add a new passive or declared element segment with the given members.
+    ///
+    /// The segment is encoded with expressions if any of its members is a
+    /// null function reference, and as a vector of function indices
+    /// otherwise; set `Element::uses_exprs` to always encode it with
+    /// expressions.
+    pub fn add(&mut self, kind: ElementKind, members: Vec<Option<FunctionId>>) -> ElementId {
+        let uses_exprs = members.iter().any(|m| m.is_none());
+        self.arena.alloc_with_id(|id| Element {
+            id,
+            kind,
+            members,
+            uses_exprs,
+        })
+    }
+
+    /// Add a new passive element segment with the given members.
+    ///
+    /// Passive segments are copied into tables with `table.init`, see
+    /// `InstrSeqBuilder::table_init_and_drop`.
+    pub fn add_passive(&mut self, members: Vec<Option<FunctionId>>) -> ElementId {
+        self.add(ElementKind::Passive, members)
+    }
+
     /// Get an element associated with an ID
     pub fn get(&self, id: ElementId) -> &Element {
         &self.arena[id]
     }
 
+    /// Get the element segment with the given id, if it is one of this module's and
+    /// hasn't been deleted.
+    pub(crate) fn try_get(&self, id: ElementId) -> Option<&Element> {
+        self.arena.get(id)
+    }
+
     /// Get an element associated with an ID
     pub fn get_mut(&mut self, id: ElementId) -> &mut Element {
         &mut self.arena[id]
@@ -53,7 +108,7 @@ impl ModuleElements {
     /// Delete an elements entry from this module.
     ///
     /// It is up to you to ensure that all references to this deleted element
-    /// are removed.
+    /// are removed, eg `table.init` and `elem.drop` expressions.
     pub fn delete(&mut self, id: ElementId) {
         self.arena.delete(id);
     }
@@ -62,6 +117,11 @@ impl ModuleElements {
     pub fn iter(&self) -> impl Iterator<Item = &Element> {
         self.arena.iter().map(|(_, f)| f)
     }
+
+    /// Get a mutable reference to this module's passive elements.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Element> {
+        self.arena.iter_mut().map(|(_, f)| f)
+    }
 }
 
 impl Module {
@@ -75,19 +135,23 @@ impl Module {
         for (i, segment) in section.into_iter().enumerate() {
             let segment = segment?;
 
-            match segment.kind {
-                wasmparser::ElementKind::Passive { .. } | wasmparser::ElementKind::Declared => {
-                    bail!("passive element segments not supported yet");
-                }
+            let kind = match segment.kind {
+                wasmparser::ElementKind::Passive => ElementKind::Passive,
+                wasmparser::ElementKind::Declared => ElementKind::Declared,
                 wasmparser::ElementKind::Active {
                     table_index,
                     init_expr,
                 } => {
+                    let stand_in = self.elements.add_passive(Vec::new());
+                    ids.push_active_element(stand_in);
                     let table = ids.get_table(table_index)?;
                     let table = match &mut self.tables.get_mut(table).kind {
                         TableKind::Function(t) => t,
                         TableKind::Anyref(_) => {
-                            bail!("active anyref segments not supported yet");
+                            return Err(ErrorKind::Unsupported {
+                                feature: "active anyref element segments",
+                            })
+                            .context("active anyref segments not supported yet");
                         }
                     };
 
@@ -121,8 +185,34 @@ impl Module {
                         }
                         _ => bail!("non-i32 constant in segment {}", i),
                     }
+                    continue;
                 }
+            };
+
+            if segment.ty != wasmparser::Type::AnyFunc {
+                bail!(
+                    "only funcref element segments are supported, in segment {}",
+                    i
+                );
             }
+            let items = segment.items.get_items_reader()?;
+            let uses_exprs = items.uses_exprs();
+            let members = items
+                .into_iter()
+                .map(|e| -> Result<_> {
+                    Ok(match e? {
+                        wasmparser::ElementItem::Func(f) => Some(ids.get_func(f)?),
+                        wasmparser::ElementItem::Null => None,
+                    })
+                })
+                .collect::<Result<_>>()?;
+            let id = self.elements.arena.alloc_with_id(|id| Element {
+                id,
+                kind,
+                members,
+                uses_exprs,
+            });
+            ids.push_element(id);
         }
         Ok(())
     }
@@ -175,7 +265,8 @@ impl Emit for ModuleElements {
             .iter()
             .map(|(_, table)| table.relative_elements.len())
             .sum::<usize>();
-        let total = passive + relative + chunks.len();
+        let chunks_len = chunks.len();
+        let total = passive + relative + chunks_len;
 
         if total == 0 {
             return;
@@ -197,12 +288,30 @@ impl Emit for ModuleElements {
             }
         };
 
+        // Segments with an explicit table index (and all passive and declared
+        // segments) are followed by the type of their element after the
+        // offset.
+        let element_type = |cx: &mut EmitContext, exprs: bool| {
+            if exprs {
+                cx.encoder.byte(0x70); // funcref
+            } else {
+                cx.encoder.byte(0x00); // elemkind == funcref
+            }
+        };
+
         // Emit all contiguous chunks of functions pointers that are located at
         // constant offsets
         for (&id, table, offset, len) in chunks {
             let table_index = cx.indices.get_table_index(id);
             active_table_header(&mut cx, table_index, false);
-            InitExpr::Value(Value::I32(offset as i32)).emit(&mut cx);
+            let value = match cx.module.tables.get(id).index_type {
+                IndexType::I32 => Value::I32(offset as i32),
+                IndexType::I64 => Value::I64(offset as i64),
+            };
+            InitExpr::Value(value).emit(&mut cx);
+            if table_index != 0 {
+                element_type(&mut cx, false);
+            }
             cx.encoder.usize(len);
             for item in table.elements[offset..][..len].iter() {
                 let index = cx.indices.get_func_index(item.unwrap());
@@ -218,40 +327,53 @@ impl Emit for ModuleElements {
                 let exprs = list.iter().any(|i| i.is_none());
                 active_table_header(&mut cx, table_index, exprs);
                 InitExpr::Global(*global).emit(&mut cx);
-                cx.encoder.usize(list.len());
-                for func in list {
-                    match func {
-                        Some(id) => {
-                            let index = cx.indices.get_func_index(*id);
-                            if exprs {
-                                cx.encoder.byte(0xd2);
-                                cx.encoder.u32(index);
-                                cx.encoder.byte(0x0b);
-                            } else {
-                                cx.encoder.u32(index);
-                            }
-                        }
-                        None => {
-                            cx.encoder.byte(0xd1);
-                            cx.encoder.byte(0x0b);
-                        }
-                    }
+                if table_index != 0 {
+                    element_type(&mut cx, exprs);
                 }
+                emit_members(&mut cx, list, exprs);
+            }
+        }
+
+        // After all the active segments are added add passive and declared
+        // segments next. We may want to sort this more intelligently in the
+        // future.
+        let first_index = chunks_len + relative;
+        for (i, (id, element)) in self.arena.iter().enumerate() {
+            cx.indices.set_element_index(id, (first_index + i) as u32);
+
+            let exprs = element.uses_exprs || element.members.iter().any(|i| i.is_none());
+            let exprs_bit = if exprs { 0x4 } else { 0x0 };
+            match element.kind {
+                ElementKind::Passive => cx.encoder.byte(0x01 | exprs_bit),
+                ElementKind::Declared => cx.encoder.byte(0x03 | exprs_bit),
             }
+            element_type(&mut cx, exprs);
+            emit_members(&mut cx, &element.members, exprs);
         }
+    }
+}
 
-        // After all the active segments are added add passive segments next. We
-        // may want to sort this more intelligently in the future. Otherwise
-        // emitting a segment here is in general much simpler than above as we
-        // know there are no holes.
-        for (id, _) in self.arena.iter() {
-            cx.indices.push_element(id);
-            // TODO: sync this with the upstream spec
-            panic!(
-                "encoding a passive element segment requires either \
-                 `ref.null` or `ref.func` encodings, which aren't \
-                 currently implemented"
-            );
+/// Emit the list of function references of an element segment, either as a
+/// vector of function indices or as a vector of `ref.func`/`ref.null`
+/// expressions.
+fn emit_members(cx: &mut EmitContext, members: &[Option<FunctionId>], exprs: bool) {
+    cx.encoder.usize(members.len());
+    for func in members {
+        match func {
+            Some(id) => {
+                let index = cx.indices.get_func_index(*id);
+                if exprs {
+                    cx.encoder.byte(0xd2); // ref.func
+                    cx.encoder.u32(index);
+                    cx.encoder.byte(0x0b); // end
+                } else {
+                    cx.encoder.u32(index);
+                }
+            }
+            None => {
+                cx.encoder.byte(0xd0); // ref.null
+                cx.encoder.byte(0x0b); // end
+            }
         }
     }
 }
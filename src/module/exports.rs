@@ -1,9 +1,10 @@
 //! Exported items in a wasm module.
 
 use crate::emit::{Emit, EmitContext, Section};
+use crate::name_index::NameIndex;
 use crate::parse::IndicesToIds;
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
-use crate::{FunctionId, GlobalId, MemoryId, Module, Result, TableId};
+use crate::{FunctionId, GlobalId, InitExpr, MemoryId, Module, Result, TableId, ValType};
 
 /// The id of an export.
 pub type ExportId = Id<Export>;
@@ -45,10 +46,13 @@ pub enum ExportItem {
 }
 
 /// The set of exports in a module.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct ModuleExports {
     /// The arena containing this module's exports.
     arena: TombstoneArena<Export>,
+
+    /// An index of the exports by name, for `by_name`.
+    names: NameIndex<Export>,
 }
 
 impl ModuleExports {
@@ -59,11 +63,20 @@ impl ModuleExports {
 
     /// Gets a reference to an export given its id
     pub fn get_mut(&mut self, id: ExportId) -> &mut Export {
+        self.names.invalidate();
         &mut self.arena[id]
     }
 
+    /// Get an export ID by its name.
+    pub fn by_name(&self, name: &str) -> Option<ExportId> {
+        self.names.get(name, || {
+            self.arena.iter().map(|(id, e)| (id, e.name.as_str()))
+        })
+    }
+
     /// Delete an export entry from this module.
     pub fn delete(&mut self, id: ExportId) {
+        self.names.invalidate();
         self.arena.delete(id);
     }
 
@@ -74,11 +87,13 @@ impl ModuleExports {
 
     /// Get a mutable reference to this module's exports.
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Export> {
+        self.names.invalidate();
         self.arena.iter_mut().map(|(_, f)| f)
     }
 
     /// Add a new export to this module
     pub fn add(&mut self, name: &str, item: impl Into<ExportItem>) -> ExportId {
+        self.names.invalidate();
         self.arena.alloc_with_id(|id| Export {
             id,
             name: name.to_string(),
@@ -151,6 +166,23 @@ impl Module {
         }
         Ok(())
     }
+
+    /// Add a new global to this module, and export it as `name`.
+    ///
+    /// The global's debugging name is set to `name` as well. This is the
+    /// exporting counterpart of `add_import_global`.
+    pub fn add_export_global(
+        &mut self,
+        name: &str,
+        ty: ValType,
+        mutable: bool,
+        init: InitExpr,
+    ) -> (GlobalId, ExportId) {
+        let global = self.globals.add_local(ty, mutable, init);
+        self.globals.get_mut(global).name = Some(name.to_string());
+        let export = self.exports.add(name, global);
+        (global, export)
+    }
 }
 
 impl Emit for ModuleExports {
@@ -167,7 +199,12 @@ impl Emit for ModuleExports {
         let mut cx = cx.start_section(Section::Export);
         cx.encoder.usize(count);
 
-        for export in self.iter() {
+        let mut exports = self.iter().collect::<Vec<_>>();
+        if cx.module.config.compression_friendly {
+            exports.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+
+        for export in exports {
             cx.encoder.str(&export.name);
             match export.item {
                 ExportItem::Function(id) => {
@@ -0,0 +1,70 @@
+//! Extracting functions into standalone modules.
+
+use crate::module::custom::ModuleCustomSections;
+use crate::module::functions::{FunctionKind, ImportedFunction};
+use crate::module::Module;
+use crate::passes::gc;
+use crate::FunctionId;
+use std::collections::HashSet;
+
+/// The module that functions called by extracted functions are imported
+/// from.
+pub const EXTRACTED_IMPORTS_MODULE: &str = "env";
+
+impl Module {
+    /// Extract `funcs` into a new, standalone module.
+    ///
+    /// The new module exports each of `funcs`, under its name if it has one,
+    /// and contains everything that they use, like types, globals, memories
+    /// along with their data, and tables. Local functions that they call,
+    /// directly or through tables, are imported instead, from the
+    /// `EXTRACTED_IMPORTS_MODULE` module under their names. This is useful
+    /// for minimizing test cases, or for testing functions on their own.
+    ///
+    /// The new module doesn't have a start function, a build id, or any
+    /// custom sections, since those describe the whole module.
+    pub fn extract(&self, funcs: &[FunctionId]) -> Module {
+        let mut module = self.clone();
+        module.start = None;
+        module.build_id = None;
+        module.original = None;
+        module.customs = ModuleCustomSections::default();
+
+        let exports = module.exports.iter().map(|e| e.id()).collect::<Vec<_>>();
+        for export in exports {
+            module.exports.delete(export);
+        }
+        let mut names = HashSet::new();
+        for (i, &func) in funcs.iter().enumerate() {
+            let name = match &module.funcs.get(func).name {
+                Some(name) if !names.contains(name) => name.clone(),
+                _ => format!("func{}", i),
+            };
+            module.exports.add(&name, func);
+            names.insert(name);
+        }
+
+        // Collect everything that the functions use, then turn the functions
+        // that they call into imports, and collect again to drop what only
+        // those used.
+        gc::run(&mut module);
+        let callees = module
+            .funcs
+            .iter_local()
+            .map(|(id, _)| id)
+            .filter(|id| !funcs.contains(id))
+            .collect::<Vec<_>>();
+        for id in callees {
+            let func = module.funcs.get(id);
+            let name = match &func.name {
+                Some(name) => name.clone(),
+                None => format!("func{}", id.index()),
+            };
+            let ty = func.ty();
+            let import = module.imports.add(EXTRACTED_IMPORTS_MODULE, &name, id);
+            module.funcs.get_mut(id).kind = FunctionKind::Import(ImportedFunction { import, ty });
+        }
+        gc::run(&mut module);
+        module
+    }
+}
@@ -0,0 +1,548 @@
+//! Checking which WebAssembly features a module uses.
+
+use crate::ir::*;
+use crate::{DataKind, IndexType, Module, Result, TableKind, ValType};
+use anyhow::bail;
+
+/// A set of WebAssembly proposals that a module may rely on.
+///
+/// The default set is empty, which only allows the MVP. Use
+/// `WasmFeatures::all()` and switch off individual features to describe an
+/// engine that lacks them:
+///
+/// ```
+/// let features = walrus::WasmFeatures {
+///     simd: false,
+///     ..walrus::WasmFeatures::all()
+/// };
+/// assert!(features.threads && !features.simd);
+/// ```
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct WasmFeatures {
+    /// The 128-bit packed SIMD proposal: the `v128` type and its operators.
+    pub simd: bool,
+    /// The threads proposal: shared memories and atomic operators.
+    pub threads: bool,
+    /// The bulk memory proposal: passive segments and the `memory.*` and
+    /// `table.*` operators that copy, fill, and initialize memories and
+    /// tables.
+    pub bulk_memory: bool,
+    /// The reference types proposal: the `anyref` type, multiple tables,
+    /// typed `select`, `ref.*` operators and the `table.*` operators that
+    /// access individual table elements.
+    pub reference_types: bool,
+    /// The multi-value proposal: functions with more than one result, and
+    /// blocks with parameters or more than one result.
+    pub multi_value: bool,
+    /// The sign extension operators proposal.
+    pub sign_extension: bool,
+    /// The non-trapping float-to-int conversions proposal.
+    pub saturating_float_to_int: bool,
+    /// The multi-memory proposal: more than one memory.
+    pub multi_memory: bool,
+    /// The memory64 proposal: tables indexed by `i64`s.
+    pub memory64: bool,
+    /// The shared-everything-threads proposal: `shared` function types,
+    /// `pause` and atomic accesses to globals.
+    #[cfg(feature = "unstable")]
+    pub shared_everything_threads: bool,
+    /// The stack-switching proposal: continuation types, tags, and the
+    /// `cont.new`, `suspend` and `resume` operators.
+    #[cfg(feature = "unstable")]
+    pub stack_switching: bool,
+}
+
+impl WasmFeatures {
+    /// Only the MVP, without any proposals.
+    pub fn mvp() -> WasmFeatures {
+        WasmFeatures::default()
+    }
+
+    /// Every proposal that walrus supports.
+    pub fn all() -> WasmFeatures {
+        WasmFeatures {
+            simd: true,
+            threads: true,
+            bulk_memory: true,
+            reference_types: true,
+            multi_value: true,
+            sign_extension: true,
+            saturating_float_to_int: true,
+            multi_memory: true,
+            memory64: true,
+            #[cfg(feature = "unstable")]
+            shared_everything_threads: true,
+            #[cfg(feature = "unstable")]
+            stack_switching: true,
+        }
+    }
+}
+
+/// The proposals that `WasmFeatures` can switch off, used to describe what a
+/// module requires.
+#[derive(Debug, Copy, Clone)]
+enum Feature {
+    Simd,
+    Threads,
+    BulkMemory,
+    ReferenceTypes,
+    MultiValue,
+    SignExtension,
+    SaturatingFloatToInt,
+    MultiMemory,
+    Memory64,
+    #[cfg(feature = "unstable")]
+    SharedEverythingThreads,
+    #[cfg(feature = "unstable")]
+    StackSwitching,
+}
+
+impl Feature {
+    fn enabled(self, features: &WasmFeatures) -> bool {
+        match self {
+            Feature::Simd => features.simd,
+            Feature::Threads => features.threads,
+            Feature::BulkMemory => features.bulk_memory,
+            Feature::ReferenceTypes => features.reference_types,
+            Feature::MultiValue => features.multi_value,
+            Feature::SignExtension => features.sign_extension,
+            Feature::SaturatingFloatToInt => features.saturating_float_to_int,
+            Feature::MultiMemory => features.multi_memory,
+            Feature::Memory64 => features.memory64,
+            #[cfg(feature = "unstable")]
+            Feature::SharedEverythingThreads => features.shared_everything_threads,
+            #[cfg(feature = "unstable")]
+            Feature::StackSwitching => features.stack_switching,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Feature::Simd => "simd",
+            Feature::Threads => "threads",
+            Feature::BulkMemory => "bulk memory",
+            Feature::ReferenceTypes => "reference types",
+            Feature::MultiValue => "multi-value",
+            Feature::SignExtension => "sign extension",
+            Feature::SaturatingFloatToInt => "non-trapping float-to-int conversions",
+            Feature::MultiMemory => "multi-memory",
+            Feature::Memory64 => "memory64",
+            #[cfg(feature = "unstable")]
+            Feature::SharedEverythingThreads => "shared-everything threads",
+            #[cfg(feature = "unstable")]
+            Feature::StackSwitching => "stack switching",
+        }
+    }
+
+    /// The feature that values of type `ty` require, if any.
+    fn of_type(ty: ValType) -> Option<Feature> {
+        match ty {
+            ValType::V128 => Some(Feature::Simd),
+            ValType::Anyref => Some(Feature::ReferenceTypes),
+            #[cfg(feature = "unstable")]
+            ValType::Cont { .. } => Some(Feature::StackSwitching),
+            ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64 => None,
+        }
+    }
+}
+
+impl Module {
+    /// Check that this module only uses the WebAssembly features in
+    /// `features`.
+    ///
+    /// This is meant to be run on the final module, after all passes, to
+    /// ensure that it still runs on engines that lack some proposals. It
+    /// reports every use of a disabled feature at once, each along with where
+    /// it is: instructions are located by their function, which is identified
+    /// by its name or otherwise its index in `self.funcs`, and by their offset
+    /// in the original wasm binary, if they were parsed from one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error listing all uses of the features missing from
+    /// `features`.
+    pub fn assert_compatible(&self, features: WasmFeatures) -> Result<()> {
+        let mut cx = Check {
+            features: &features,
+            errs: Vec::new(),
+        };
+
+        for ty in self.types.iter().filter(|t| !t.is_for_function_entry()) {
+            let what = || format!("type {}", ty.id().index());
+            for &val in ty.params().iter().chain(ty.results()) {
+                if let Some(feature) = Feature::of_type(val) {
+                    cx.require(feature, || format!("{}: `{}` value", what(), val));
+                }
+            }
+            if ty.results().len() > 1 {
+                cx.require(Feature::MultiValue, || {
+                    format!("{}: function type with multiple results", what())
+                });
+            }
+            #[cfg(feature = "unstable")]
+            {
+                if ty.is_shared() {
+                    cx.require(Feature::SharedEverythingThreads, || {
+                        format!("{}: `shared` function type", what())
+                    });
+                }
+                if ty.cont_of().is_some() {
+                    cx.require(Feature::StackSwitching, || {
+                        format!("{}: continuation type", what())
+                    });
+                }
+            }
+        }
+
+        if self.tables.iter().count() > 1 {
+            cx.require(Feature::ReferenceTypes, || "multiple tables".to_string());
+        }
+        for table in self.tables.iter() {
+            let what = || format!("table {}", table.id().index());
+            if let TableKind::Anyref(_) = table.kind {
+                cx.require(Feature::ReferenceTypes, || {
+                    format!("{}: `anyref` table", what())
+                });
+            }
+            if table.index_type == IndexType::I64 {
+                cx.require(Feature::Memory64, || format!("{}: 64-bit table", what()));
+            }
+            if let TableKind::Function(t) = &table.kind {
+                let nulls = t.relative_elements.iter().any(|(_, l)| l.contains(&None));
+                if nulls {
+                    cx.require(Feature::ReferenceTypes, || {
+                        format!("{}: null element in an active segment", what())
+                    });
+                }
+            }
+        }
+
+        if self.memories.iter().count() > 1 {
+            cx.require(Feature::MultiMemory, || "multiple memories".to_string());
+        }
+        for memory in self.memories.iter() {
+            if memory.shared {
+                cx.require(Feature::Threads, || {
+                    format!("memory {}: shared memory", memory.id().index())
+                });
+            }
+        }
+
+        for global in self.globals.iter() {
+            if let Some(feature) = Feature::of_type(global.ty) {
+                cx.require(feature, || {
+                    format!("global {}: `{}` value", global.id().index(), global.ty)
+                });
+            }
+        }
+
+        for data in self.data.iter() {
+            if let DataKind::Passive = data.kind {
+                cx.require(Feature::BulkMemory, || {
+                    format!("data segment {}: passive segment", data.id().index())
+                });
+            }
+        }
+        for elem in self.elements.iter() {
+            let what = || format!("element segment {}", elem.id().index());
+            cx.require(Feature::BulkMemory, || {
+                format!("{}: passive segment", what())
+            });
+            if elem.members.contains(&None) {
+                cx.require(Feature::ReferenceTypes, || {
+                    format!("{}: null element", what())
+                });
+            }
+        }
+
+        #[cfg(feature = "unstable")]
+        for tag in self.tags.iter() {
+            cx.require(Feature::StackSwitching, || {
+                format!("tag {}: tag", tag.id().index())
+            });
+        }
+
+        for (id, func) in self.funcs.iter_local() {
+            let func_name = match &self.funcs.get(id).name {
+                Some(name) => format!("function `{}`", name),
+                None => format!("function {}", id.index()),
+            };
+            let mut visitor = CheckFunction {
+                cx: &mut cx,
+                module: self,
+                func_name,
+                entry: func.entry_block(),
+                loc: InstrLocId::default(),
+            };
+            dfs_in_order(&mut visitor, func, func.entry_block());
+        }
+
+        if cx.errs.is_empty() {
+            return Ok(());
+        }
+        let mut msg = "module uses disabled WebAssembly features:\n".to_string();
+        for err in cx.errs {
+            msg.push_str(&format!("  * {}\n", err));
+        }
+        bail!("{}", msg)
+    }
+}
+
+struct Check<'a> {
+    features: &'a WasmFeatures,
+    errs: Vec<String>,
+}
+
+impl Check<'_> {
+    fn require(&mut self, feature: Feature, what: impl FnOnce() -> String) {
+        if !feature.enabled(self.features) {
+            self.errs.push(format!(
+                "{} requires the {} feature",
+                what(),
+                feature.name()
+            ));
+        }
+    }
+}
+
+struct CheckFunction<'a, 'b> {
+    cx: &'a mut Check<'b>,
+    module: &'a Module,
+    func_name: String,
+    entry: InstrSeqId,
+    loc: InstrLocId,
+}
+
+impl CheckFunction<'_, '_> {
+    fn require(&mut self, feature: Feature, what: &str) {
+        let func_name = &self.func_name;
+        let loc = self.loc;
+        self.cx.require(feature, || {
+            if loc.is_default() {
+                format!("{}: {}", func_name, what)
+            } else {
+                format!("{} at offset {:#x}: {}", func_name, loc.data(), what)
+            }
+        });
+    }
+
+    fn require_op(&mut self, feature: Feature, op: &dyn std::fmt::Debug) {
+        self.require(feature, &format!("`{:?}` operator", op));
+    }
+}
+
+impl<'instr> Visitor<'instr> for CheckFunction<'_, '_> {
+    fn start_instr_seq(&mut self, seq: &'instr InstrSeq) {
+        if seq.id() == self.entry {
+            return;
+        }
+        if let InstrSeqType::MultiValue(ty) = seq.ty {
+            let ty = self.module.types.get(ty);
+            if !ty.params().is_empty() || ty.results().len() > 1 {
+                self.loc = InstrLocId::default();
+                self.require(
+                    Feature::MultiValue,
+                    "block with parameters or multiple results",
+                );
+            }
+        }
+    }
+
+    fn visit_local_id(&mut self, local: &crate::LocalId) {
+        let ty = self.module.locals.get(*local).ty();
+        if let Some(feature) = Feature::of_type(ty) {
+            self.require(feature, &format!("`{}` local", ty));
+        }
+    }
+
+    fn visit_instr(&mut self, instr: &'instr Instr, loc: &'instr InstrLocId) {
+        self.loc = *loc;
+        match instr {
+            Instr::Select(Select { ty: Some(_) }) => {
+                self.require(Feature::ReferenceTypes, "typed `select`")
+            }
+            Instr::Const(Const {
+                value: Value::V128(_),
+            }) => self.require(Feature::Simd, "`v128.const`"),
+            Instr::Binop(Binop { op }) if is_simd_binop(*op) => self.require_op(Feature::Simd, op),
+            Instr::Unop(Unop { op }) => {
+                if let Some(feature) = unop_feature(*op) {
+                    self.require_op(feature, op)
+                }
+            }
+            Instr::V128Bitselect(_) => self.require(Feature::Simd, "`v128.bitselect`"),
+            Instr::V128Swizzle(_) => self.require(Feature::Simd, "`v8x16.swizzle`"),
+            Instr::V128Shuffle(_) => self.require(Feature::Simd, "`v8x16.shuffle`"),
+            Instr::LoadSimd(e) => self.require_op(Feature::Simd, &e.kind),
+            Instr::Load(e) => {
+                if let LoadKind::V128 = e.kind {
+                    self.require(Feature::Simd, "`v128.load`");
+                }
+                if e.kind.atomic() {
+                    self.require_op(Feature::Threads, &e.kind);
+                }
+            }
+            Instr::Store(e) => {
+                if let StoreKind::V128 = e.kind {
+                    self.require(Feature::Simd, "`v128.store`");
+                }
+                if e.kind.atomic() {
+                    self.require_op(Feature::Threads, &e.kind);
+                }
+            }
+
+            Instr::AtomicRmw(e) => self.require_op(Feature::Threads, &e.op),
+            Instr::Cmpxchg(_) => self.require(Feature::Threads, "atomic `cmpxchg`"),
+            Instr::AtomicNotify(_) => self.require(Feature::Threads, "`atomic.notify`"),
+            Instr::AtomicWait(_) => self.require(Feature::Threads, "atomic `wait`"),
+            Instr::AtomicFence(_) => self.require(Feature::Threads, "`atomic.fence`"),
+
+            Instr::MemoryInit(_) => self.require(Feature::BulkMemory, "`memory.init`"),
+            Instr::DataDrop(_) => self.require(Feature::BulkMemory, "`data.drop`"),
+            Instr::MemoryCopy(_) => self.require(Feature::BulkMemory, "`memory.copy`"),
+            Instr::MemoryFill(_) => self.require(Feature::BulkMemory, "`memory.fill`"),
+            Instr::TableInit(_) => self.require(Feature::BulkMemory, "`table.init`"),
+            Instr::ElemDrop(_) => self.require(Feature::BulkMemory, "`elem.drop`"),
+            Instr::TableCopy(_) => self.require(Feature::BulkMemory, "`table.copy`"),
+
+            Instr::TableGet(_) => self.require(Feature::ReferenceTypes, "`table.get`"),
+            Instr::TableSet(_) => self.require(Feature::ReferenceTypes, "`table.set`"),
+            Instr::TableGrow(_) => self.require(Feature::ReferenceTypes, "`table.grow`"),
+            Instr::TableSize(_) => self.require(Feature::ReferenceTypes, "`table.size`"),
+            Instr::TableFill(_) => self.require(Feature::ReferenceTypes, "`table.fill`"),
+            Instr::RefNull(_) => self.require(Feature::ReferenceTypes, "`ref.null`"),
+            Instr::RefIsNull(_) => self.require(Feature::ReferenceTypes, "`ref.is_null`"),
+            Instr::RefFunc(_) => self.require(Feature::ReferenceTypes, "`ref.func`"),
+
+            #[cfg(feature = "unstable")]
+            Instr::Pause(_) => self.require(Feature::SharedEverythingThreads, "`pause`"),
+            #[cfg(feature = "unstable")]
+            Instr::GlobalAtomicGet(_) => {
+                self.require(Feature::SharedEverythingThreads, "`global.atomic.get`")
+            }
+            #[cfg(feature = "unstable")]
+            Instr::GlobalAtomicSet(_) => {
+                self.require(Feature::SharedEverythingThreads, "`global.atomic.set`")
+            }
+            #[cfg(feature = "unstable")]
+            Instr::ContNew(_) => self.require(Feature::StackSwitching, "`cont.new`"),
+            #[cfg(feature = "unstable")]
+            Instr::Suspend(_) => self.require(Feature::StackSwitching, "`suspend`"),
+            #[cfg(feature = "unstable")]
+            Instr::Resume(_) => self.require(Feature::StackSwitching, "`resume`"),
+
+            _ => {}
+        }
+    }
+}
+
+/// Whether `op` is one of the binary operators of the SIMD proposal.
+fn is_simd_binop(op: BinaryOp) -> bool {
+    use BinaryOp::*;
+    !matches!(
+        op,
+        I32Eq
+            | I32Ne
+            | I32LtS
+            | I32LtU
+            | I32GtS
+            | I32GtU
+            | I32LeS
+            | I32LeU
+            | I32GeS
+            | I32GeU
+            | I64Eq
+            | I64Ne
+            | I64LtS
+            | I64LtU
+            | I64GtS
+            | I64GtU
+            | I64LeS
+            | I64LeU
+            | I64GeS
+            | I64GeU
+            | F32Eq
+            | F32Ne
+            | F32Lt
+            | F32Gt
+            | F32Le
+            | F32Ge
+            | F64Eq
+            | F64Ne
+            | F64Lt
+            | F64Gt
+            | F64Le
+            | F64Ge
+            | I32Add
+            | I32Sub
+            | I32Mul
+            | I32DivS
+            | I32DivU
+            | I32RemS
+            | I32RemU
+            | I32And
+            | I32Or
+            | I32Xor
+            | I32Shl
+            | I32ShrS
+            | I32ShrU
+            | I32Rotl
+            | I32Rotr
+            | I64Add
+            | I64Sub
+            | I64Mul
+            | I64DivS
+            | I64DivU
+            | I64RemS
+            | I64RemU
+            | I64And
+            | I64Or
+            | I64Xor
+            | I64Shl
+            | I64ShrS
+            | I64ShrU
+            | I64Rotl
+            | I64Rotr
+            | F32Add
+            | F32Sub
+            | F32Mul
+            | F32Div
+            | F32Min
+            | F32Max
+            | F32Copysign
+            | F64Add
+            | F64Sub
+            | F64Mul
+            | F64Div
+            | F64Min
+            | F64Max
+            | F64Copysign
+    )
+}
+
+/// The feature that the unary operator `op` requires, if any.
+fn unop_feature(op: UnaryOp) -> Option<Feature> {
+    use UnaryOp::*;
+    match op {
+        I32Eqz | I32Clz | I32Ctz | I32Popcnt | I64Eqz | I64Clz | I64Ctz | I64Popcnt => None,
+        F32Abs | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt => None,
+        F64Abs | F64Neg | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt => None,
+        I32WrapI64 | I32TruncSF32 | I32TruncUF32 | I32TruncSF64 | I32TruncUF64 => None,
+        I64ExtendSI32 | I64ExtendUI32 | I64TruncSF32 | I64TruncUF32 | I64TruncSF64
+        | I64TruncUF64 => None,
+        F32ConvertSI32 | F32ConvertUI32 | F32ConvertSI64 | F32ConvertUI64 | F32DemoteF64 => None,
+        F64ConvertSI32 | F64ConvertUI32 | F64ConvertSI64 | F64ConvertUI64 | F64PromoteF32 => None,
+        I32ReinterpretF32 | I64ReinterpretF64 | F32ReinterpretI32 | F64ReinterpretI64 => None,
+
+        I32Extend8S | I32Extend16S | I64Extend8S | I64Extend16S | I64Extend32S => {
+            Some(Feature::SignExtension)
+        }
+
+        I32TruncSSatF32 | I32TruncUSatF32 | I32TruncSSatF64 | I32TruncUSatF64 | I64TruncSSatF32
+        | I64TruncUSatF32 | I64TruncSSatF64 | I64TruncUSatF64 => {
+            Some(Feature::SaturatingFloatToInt)
+        }
+
+        _ => Some(Feature::Simd),
+    }
+}
@@ -223,7 +223,7 @@ fn impl_pop_operand(
                 log::trace!("pop operand: None");
                 return Ok(None);
             }
-            return Err(ErrorKind::InvalidWasm)
+            return Err(ErrorKind::Validate)
                 .context("popped operand past control frame height in non-unreachable code");
         }
     }
@@ -241,8 +241,8 @@ fn impl_pop_operand_expected(
         (None, expected) => Ok(expected),
         (actual, None) => Ok(actual),
         (Some(actual), Some(expected)) => {
-            if actual != expected {
-                Err(ErrorKind::InvalidWasm)
+            if !actual.is_subtype_of(expected) {
+                Err(ErrorKind::Validate)
                     .context(format!("expected type {}", expected))
                     .context(format!("found type {}", actual))
             } else {
@@ -336,11 +336,11 @@ fn impl_pop_control(
 ) -> Result<ControlFrame> {
     let frame = controls
         .last()
-        .ok_or_else(|| ErrorKind::InvalidWasm)
+        .ok_or_else(|| ErrorKind::Validate)
         .context("attempted to pop a frame from an empty control stack")?;
     impl_pop_operands(operands, controls, &frame.end_types)?;
     if operands.len() != frame.height {
-        return Err(ErrorKind::InvalidWasm).context(format!(
+        return Err(ErrorKind::Validate).context(format!(
             "incorrect number of operands on the stack at the end of a control frame; \
              found {}, expected {}",
             operands.len(),
@@ -2,23 +2,29 @@ use crate::emit::IdsToIndices;
 use crate::encode::Encoder;
 use crate::ir::*;
 use crate::map::IdHashMap;
+use crate::module::address_map::EmittedInstr;
 use crate::module::functions::LocalFunction;
 use crate::module::memories::MemoryId;
+use std::borrow::Cow;
+use wasm_encoder::Instruction;
 
 pub(crate) fn run(
     func: &LocalFunction,
     indices: &IdsToIndices,
     local_indices: &IdHashMap<Local, u32>,
     encoder: &mut Encoder,
-    map: Option<&mut Vec<(InstrLocId, usize)>>,
+    map: Option<&mut Vec<EmittedInstr>>,
+    canonicalize_memargs: bool,
 ) {
     let v = &mut Emit {
         indices,
         blocks: vec![],
+        positions: vec![],
         block_kinds: vec![BlockKind::FunctionEntry],
         encoder,
         local_indices,
         map,
+        canonicalize_memargs,
     };
     dfs_in_order(v, func, func.entry_block());
 
@@ -36,6 +42,10 @@ struct Emit<'a, 'b> {
     // `branch_target` method.
     blocks: Vec<InstrSeqId>,
 
+    // The position of the next instruction in each of the blocks on the stack.
+    // This is parallel to `blocks`.
+    positions: Vec<usize>,
+
     // The kinds of blocks we have on the stack. This is parallel to `blocks`
     // 99% of the time, except we push a new block kind in `visit_instr`, before
     // we push the block in `start_instr_seq`, because this is when we know the
@@ -46,26 +56,30 @@ struct Emit<'a, 'b> {
     encoder: &'a mut Encoder<'b>,
 
     // Encoded ExprId -> offset map.
-    map: Option<&'a mut Vec<(InstrLocId, usize)>>,
+    map: Option<&'a mut Vec<EmittedInstr>>,
+
+    // Whether to ignore the original encoding of `MemArg`s.
+    canonicalize_memargs: bool,
 }
 
 impl<'instr> Visitor<'instr> for Emit<'_, '_> {
     fn start_instr_seq(&mut self, seq: &'instr InstrSeq) {
         self.blocks.push(seq.id());
+        self.positions.push(0);
         debug_assert_eq!(self.blocks.len(), self.block_kinds.len());
 
         match self.block_kinds.last().unwrap() {
             BlockKind::Block => {
-                self.encoder.byte(0x02); // block
-                self.block_type(seq.ty);
+                let ty = self.block_type(seq.ty);
+                self.instr(Instruction::Block(ty));
             }
             BlockKind::Loop => {
-                self.encoder.byte(0x03); // loop
-                self.block_type(seq.ty);
+                let ty = self.block_type(seq.ty);
+                self.instr(Instruction::Loop(ty));
             }
             BlockKind::If => {
-                self.encoder.byte(0x04); // if
-                self.block_type(seq.ty);
+                let ty = self.block_type(seq.ty);
+                self.instr(Instruction::If(ty));
             }
             // Function entries are implicitly started, and don't need any
             // opcode to start them. `Else` blocks are started when `If` blocks
@@ -78,6 +92,7 @@ impl<'instr> Visitor<'instr> for Emit<'_, '_> {
     fn end_instr_seq(&mut self, seq: &'instr InstrSeq) {
         let popped_block = self.blocks.pop();
         debug_assert_eq!(popped_block, Some(seq.id()));
+        self.positions.pop();
 
         let popped_kind = self.block_kinds.pop();
         debug_assert!(popped_kind.is_some());
@@ -89,20 +104,26 @@ impl<'instr> Visitor<'instr> for Emit<'_, '_> {
             //
             // TODO: don't emit `else` for empty else blocks
             self.block_kinds.push(BlockKind::Else);
-            self.encoder.byte(0x05); // else
+            self.instr(Instruction::Else);
         } else {
-            self.encoder.byte(0x0b); // end
+            self.instr(Instruction::End);
         }
     }
 
     fn visit_instr(&mut self, instr: &'instr Instr, instr_loc: &'instr InstrLocId) {
         use self::Instr::*;
 
+        let index = self.positions.last_mut().unwrap();
         if let Some(map) = self.map.as_mut() {
-            let pos = self.encoder.pos();
             // Save the encoded_at position for the specified ExprId.
-            map.push((instr_loc.clone(), pos));
+            map.push(EmittedInstr {
+                seq: *self.blocks.last().unwrap(),
+                index: *index,
+                loc: *instr_loc,
+                pos: self.encoder.pos(),
+            });
         }
+        *index += 1;
 
         match instr {
             Block(_) => self.block_kinds.push(BlockKind::Block),
@@ -115,151 +136,142 @@ impl<'instr> Visitor<'instr> for Emit<'_, '_> {
             IfElse(_) => self.block_kinds.push(BlockKind::If),
 
             BrTable(e) => {
-                self.encoder.byte(0x0e); // br_table
-                self.encoder.usize(e.blocks.len());
-                for b in e.blocks.iter() {
-                    let target = self.branch_target(*b);
-                    self.encoder.u32(target);
-                }
+                let targets = e
+                    .blocks
+                    .iter()
+                    .map(|b| self.branch_target(*b))
+                    .collect::<Vec<_>>();
                 let default = self.branch_target(e.default);
-                self.encoder.u32(default);
+                self.instr(Instruction::BrTable(Cow::Owned(targets), default));
             }
 
             Const(e) => e.value.emit(self.encoder),
-            Drop(_) => self.encoder.byte(0x1a),   // drop
-            Return(_) => self.encoder.byte(0x0f), // return
+            Drop(_) => self.instr(Instruction::Drop),
+            Return(_) => self.instr(Instruction::Return),
 
             MemorySize(e) => {
                 let idx = self.indices.get_memory_index(e.memory);
-                self.encoder.byte(0x3f); // memory.size
-                self.encoder.u32(idx);
+                self.instr(Instruction::MemorySize(idx));
             }
 
             MemoryGrow(e) => {
                 let idx = self.indices.get_memory_index(e.memory);
-                self.encoder.byte(0x40); // memory.grow
-                self.encoder.u32(idx);
+                self.instr(Instruction::MemoryGrow(idx));
             }
 
             MemoryInit(e) => {
-                self.encoder.raw(&[0xfc, 0x08]); // memory.init
-                let idx = self.indices.get_data_index(e.data);
-                self.encoder.u32(idx);
-                let idx = self.indices.get_memory_index(e.memory);
-                assert_eq!(idx, 0);
-                self.encoder.u32(idx);
+                let data_index = self.indices.get_data_index(e.data);
+                let mem = self.indices.get_memory_index(e.memory);
+                assert_eq!(mem, 0);
+                self.instr(Instruction::MemoryInit { mem, data_index });
             }
 
             DataDrop(e) => {
-                self.encoder.raw(&[0xfc, 0x09]); // data.drop
                 let idx = self.indices.get_data_index(e.data);
-                self.encoder.u32(idx);
+                self.instr(Instruction::DataDrop(idx));
             }
 
             MemoryCopy(e) => {
-                self.encoder.raw(&[0xfc, 0x0a]); // memory.copy
-                let idx = self.indices.get_memory_index(e.src);
-                assert_eq!(idx, 0);
-                self.encoder.u32(idx);
-                let idx = self.indices.get_memory_index(e.dst);
-                assert_eq!(idx, 0);
-                self.encoder.u32(idx);
+                let dst_mem = self.indices.get_memory_index(e.dst);
+                assert_eq!(dst_mem, 0);
+                let src_mem = self.indices.get_memory_index(e.src);
+                assert_eq!(src_mem, 0);
+                self.instr(Instruction::MemoryCopy { src_mem, dst_mem });
             }
 
             MemoryFill(e) => {
-                self.encoder.raw(&[0xfc, 0x0b]); // memory.fill
                 let idx = self.indices.get_memory_index(e.memory);
                 assert_eq!(idx, 0);
-                self.encoder.u32(idx);
+                self.instr(Instruction::MemoryFill(idx));
             }
 
             Binop(e) => {
                 use crate::ir::BinaryOp::*;
 
                 match e.op {
-                    I32Eq => self.encoder.byte(0x46),
-                    I32Ne => self.encoder.byte(0x47),
-                    I32LtS => self.encoder.byte(0x48),
-                    I32LtU => self.encoder.byte(0x49),
-                    I32GtS => self.encoder.byte(0x4a),
-                    I32GtU => self.encoder.byte(0x4b),
-                    I32LeS => self.encoder.byte(0x4c),
-                    I32LeU => self.encoder.byte(0x4d),
-                    I32GeS => self.encoder.byte(0x4e),
-                    I32GeU => self.encoder.byte(0x4f),
-
-                    I64Eq => self.encoder.byte(0x51),
-                    I64Ne => self.encoder.byte(0x52),
-                    I64LtS => self.encoder.byte(0x53),
-                    I64LtU => self.encoder.byte(0x54),
-                    I64GtS => self.encoder.byte(0x55),
-                    I64GtU => self.encoder.byte(0x56),
-                    I64LeS => self.encoder.byte(0x57),
-                    I64LeU => self.encoder.byte(0x58),
-                    I64GeS => self.encoder.byte(0x59),
-                    I64GeU => self.encoder.byte(0x5a),
-
-                    F32Eq => self.encoder.byte(0x5b),
-                    F32Ne => self.encoder.byte(0x5c),
-                    F32Lt => self.encoder.byte(0x5d),
-                    F32Gt => self.encoder.byte(0x5e),
-                    F32Le => self.encoder.byte(0x5f),
-                    F32Ge => self.encoder.byte(0x60),
-
-                    F64Eq => self.encoder.byte(0x61),
-                    F64Ne => self.encoder.byte(0x62),
-                    F64Lt => self.encoder.byte(0x63),
-                    F64Gt => self.encoder.byte(0x64),
-                    F64Le => self.encoder.byte(0x65),
-                    F64Ge => self.encoder.byte(0x66),
-
-                    I32Add => self.encoder.byte(0x6a),
-                    I32Sub => self.encoder.byte(0x6b),
-                    I32Mul => self.encoder.byte(0x6c),
-                    I32DivS => self.encoder.byte(0x6d),
-                    I32DivU => self.encoder.byte(0x6e),
-                    I32RemS => self.encoder.byte(0x6f),
-                    I32RemU => self.encoder.byte(0x70),
-                    I32And => self.encoder.byte(0x71),
-                    I32Or => self.encoder.byte(0x72),
-                    I32Xor => self.encoder.byte(0x73),
-                    I32Shl => self.encoder.byte(0x74),
-                    I32ShrS => self.encoder.byte(0x75),
-                    I32ShrU => self.encoder.byte(0x76),
-                    I32Rotl => self.encoder.byte(0x77),
-                    I32Rotr => self.encoder.byte(0x78),
-
-                    I64Add => self.encoder.byte(0x7c),
-                    I64Sub => self.encoder.byte(0x7d),
-                    I64Mul => self.encoder.byte(0x7e),
-                    I64DivS => self.encoder.byte(0x7f),
-                    I64DivU => self.encoder.byte(0x80),
-                    I64RemS => self.encoder.byte(0x81),
-                    I64RemU => self.encoder.byte(0x82),
-                    I64And => self.encoder.byte(0x83),
-                    I64Or => self.encoder.byte(0x84),
-                    I64Xor => self.encoder.byte(0x85),
-                    I64Shl => self.encoder.byte(0x86),
-                    I64ShrS => self.encoder.byte(0x87),
-                    I64ShrU => self.encoder.byte(0x88),
-                    I64Rotl => self.encoder.byte(0x89),
-                    I64Rotr => self.encoder.byte(0x8a),
-
-                    F32Add => self.encoder.byte(0x92),
-                    F32Sub => self.encoder.byte(0x93),
-                    F32Mul => self.encoder.byte(0x94),
-                    F32Div => self.encoder.byte(0x95),
-                    F32Min => self.encoder.byte(0x96),
-                    F32Max => self.encoder.byte(0x97),
-                    F32Copysign => self.encoder.byte(0x98),
-
-                    F64Add => self.encoder.byte(0xa0),
-                    F64Sub => self.encoder.byte(0xa1),
-                    F64Mul => self.encoder.byte(0xa2),
-                    F64Div => self.encoder.byte(0xa3),
-                    F64Min => self.encoder.byte(0xa4),
-                    F64Max => self.encoder.byte(0xa5),
-                    F64Copysign => self.encoder.byte(0xa6),
+                    I32Eq => self.instr(Instruction::I32Eq),
+                    I32Ne => self.instr(Instruction::I32Ne),
+                    I32LtS => self.instr(Instruction::I32LtS),
+                    I32LtU => self.instr(Instruction::I32LtU),
+                    I32GtS => self.instr(Instruction::I32GtS),
+                    I32GtU => self.instr(Instruction::I32GtU),
+                    I32LeS => self.instr(Instruction::I32LeS),
+                    I32LeU => self.instr(Instruction::I32LeU),
+                    I32GeS => self.instr(Instruction::I32GeS),
+                    I32GeU => self.instr(Instruction::I32GeU),
+
+                    I64Eq => self.instr(Instruction::I64Eq),
+                    I64Ne => self.instr(Instruction::I64Ne),
+                    I64LtS => self.instr(Instruction::I64LtS),
+                    I64LtU => self.instr(Instruction::I64LtU),
+                    I64GtS => self.instr(Instruction::I64GtS),
+                    I64GtU => self.instr(Instruction::I64GtU),
+                    I64LeS => self.instr(Instruction::I64LeS),
+                    I64LeU => self.instr(Instruction::I64LeU),
+                    I64GeS => self.instr(Instruction::I64GeS),
+                    I64GeU => self.instr(Instruction::I64GeU),
+
+                    F32Eq => self.instr(Instruction::F32Eq),
+                    F32Ne => self.instr(Instruction::F32Ne),
+                    F32Lt => self.instr(Instruction::F32Lt),
+                    F32Gt => self.instr(Instruction::F32Gt),
+                    F32Le => self.instr(Instruction::F32Le),
+                    F32Ge => self.instr(Instruction::F32Ge),
+
+                    F64Eq => self.instr(Instruction::F64Eq),
+                    F64Ne => self.instr(Instruction::F64Ne),
+                    F64Lt => self.instr(Instruction::F64Lt),
+                    F64Gt => self.instr(Instruction::F64Gt),
+                    F64Le => self.instr(Instruction::F64Le),
+                    F64Ge => self.instr(Instruction::F64Ge),
+
+                    I32Add => self.instr(Instruction::I32Add),
+                    I32Sub => self.instr(Instruction::I32Sub),
+                    I32Mul => self.instr(Instruction::I32Mul),
+                    I32DivS => self.instr(Instruction::I32DivS),
+                    I32DivU => self.instr(Instruction::I32DivU),
+                    I32RemS => self.instr(Instruction::I32RemS),
+                    I32RemU => self.instr(Instruction::I32RemU),
+                    I32And => self.instr(Instruction::I32And),
+                    I32Or => self.instr(Instruction::I32Or),
+                    I32Xor => self.instr(Instruction::I32Xor),
+                    I32Shl => self.instr(Instruction::I32Shl),
+                    I32ShrS => self.instr(Instruction::I32ShrS),
+                    I32ShrU => self.instr(Instruction::I32ShrU),
+                    I32Rotl => self.instr(Instruction::I32Rotl),
+                    I32Rotr => self.instr(Instruction::I32Rotr),
+
+                    I64Add => self.instr(Instruction::I64Add),
+                    I64Sub => self.instr(Instruction::I64Sub),
+                    I64Mul => self.instr(Instruction::I64Mul),
+                    I64DivS => self.instr(Instruction::I64DivS),
+                    I64DivU => self.instr(Instruction::I64DivU),
+                    I64RemS => self.instr(Instruction::I64RemS),
+                    I64RemU => self.instr(Instruction::I64RemU),
+                    I64And => self.instr(Instruction::I64And),
+                    I64Or => self.instr(Instruction::I64Or),
+                    I64Xor => self.instr(Instruction::I64Xor),
+                    I64Shl => self.instr(Instruction::I64Shl),
+                    I64ShrS => self.instr(Instruction::I64ShrS),
+                    I64ShrU => self.instr(Instruction::I64ShrU),
+                    I64Rotl => self.instr(Instruction::I64Rotl),
+                    I64Rotr => self.instr(Instruction::I64Rotr),
+
+                    F32Add => self.instr(Instruction::F32Add),
+                    F32Sub => self.instr(Instruction::F32Sub),
+                    F32Mul => self.instr(Instruction::F32Mul),
+                    F32Div => self.instr(Instruction::F32Div),
+                    F32Min => self.instr(Instruction::F32Min),
+                    F32Max => self.instr(Instruction::F32Max),
+                    F32Copysign => self.instr(Instruction::F32Copysign),
+
+                    F64Add => self.instr(Instruction::F64Add),
+                    F64Sub => self.instr(Instruction::F64Sub),
+                    F64Mul => self.instr(Instruction::F64Mul),
+                    F64Div => self.instr(Instruction::F64Div),
+                    F64Min => self.instr(Instruction::F64Min),
+                    F64Max => self.instr(Instruction::F64Max),
+                    F64Copysign => self.instr(Instruction::F64Copysign),
 
                     I8x16ReplaceLane { idx } => self.encoder.raw(&[0xfd, 0x07, idx]),
                     I16x8ReplaceLane { idx } => self.encoder.raw(&[0xfd, 0x0b, idx]),
@@ -380,65 +392,65 @@ impl<'instr> Visitor<'instr> for Emit<'_, '_> {
                 use crate::ir::UnaryOp::*;
 
                 match e.op {
-                    I32Eqz => self.encoder.byte(0x45),
-                    I32Clz => self.encoder.byte(0x67),
-                    I32Ctz => self.encoder.byte(0x68),
-                    I32Popcnt => self.encoder.byte(0x69),
-
-                    I64Eqz => self.encoder.byte(0x50),
-                    I64Clz => self.encoder.byte(0x79),
-                    I64Ctz => self.encoder.byte(0x7a),
-                    I64Popcnt => self.encoder.byte(0x7b),
-
-                    F32Abs => self.encoder.byte(0x8b),
-                    F32Neg => self.encoder.byte(0x8c),
-                    F32Ceil => self.encoder.byte(0x8d),
-                    F32Floor => self.encoder.byte(0x8e),
-                    F32Trunc => self.encoder.byte(0x8f),
-                    F32Nearest => self.encoder.byte(0x90),
-                    F32Sqrt => self.encoder.byte(0x91),
-
-                    F64Abs => self.encoder.byte(0x99),
-                    F64Neg => self.encoder.byte(0x9a),
-                    F64Ceil => self.encoder.byte(0x9b),
-                    F64Floor => self.encoder.byte(0x9c),
-                    F64Trunc => self.encoder.byte(0x9d),
-                    F64Nearest => self.encoder.byte(0x9e),
-                    F64Sqrt => self.encoder.byte(0x9f),
-
-                    I32WrapI64 => self.encoder.byte(0xa7),
-                    I32TruncSF32 => self.encoder.byte(0xa8),
-                    I32TruncUF32 => self.encoder.byte(0xa9),
-                    I32TruncSF64 => self.encoder.byte(0xaa),
-                    I32TruncUF64 => self.encoder.byte(0xab),
-                    I64ExtendSI32 => self.encoder.byte(0xac),
-                    I64ExtendUI32 => self.encoder.byte(0xad),
-                    I64TruncSF32 => self.encoder.byte(0xae),
-                    I64TruncUF32 => self.encoder.byte(0xaf),
-                    I64TruncSF64 => self.encoder.byte(0xb0),
-                    I64TruncUF64 => self.encoder.byte(0xb1),
-
-                    F32ConvertSI32 => self.encoder.byte(0xb2),
-                    F32ConvertUI32 => self.encoder.byte(0xb3),
-                    F32ConvertSI64 => self.encoder.byte(0xb4),
-                    F32ConvertUI64 => self.encoder.byte(0xb5),
-                    F32DemoteF64 => self.encoder.byte(0xb6),
-                    F64ConvertSI32 => self.encoder.byte(0xb7),
-                    F64ConvertUI32 => self.encoder.byte(0xb8),
-                    F64ConvertSI64 => self.encoder.byte(0xb9),
-                    F64ConvertUI64 => self.encoder.byte(0xba),
-                    F64PromoteF32 => self.encoder.byte(0xbb),
-
-                    I32ReinterpretF32 => self.encoder.byte(0xbc),
-                    I64ReinterpretF64 => self.encoder.byte(0xbd),
-                    F32ReinterpretI32 => self.encoder.byte(0xbe),
-                    F64ReinterpretI64 => self.encoder.byte(0xbf),
-
-                    I32Extend8S => self.encoder.byte(0xc0),
-                    I32Extend16S => self.encoder.byte(0xc1),
-                    I64Extend8S => self.encoder.byte(0xc2),
-                    I64Extend16S => self.encoder.byte(0xc3),
-                    I64Extend32S => self.encoder.byte(0xc4),
+                    I32Eqz => self.instr(Instruction::I32Eqz),
+                    I32Clz => self.instr(Instruction::I32Clz),
+                    I32Ctz => self.instr(Instruction::I32Ctz),
+                    I32Popcnt => self.instr(Instruction::I32Popcnt),
+
+                    I64Eqz => self.instr(Instruction::I64Eqz),
+                    I64Clz => self.instr(Instruction::I64Clz),
+                    I64Ctz => self.instr(Instruction::I64Ctz),
+                    I64Popcnt => self.instr(Instruction::I64Popcnt),
+
+                    F32Abs => self.instr(Instruction::F32Abs),
+                    F32Neg => self.instr(Instruction::F32Neg),
+                    F32Ceil => self.instr(Instruction::F32Ceil),
+                    F32Floor => self.instr(Instruction::F32Floor),
+                    F32Trunc => self.instr(Instruction::F32Trunc),
+                    F32Nearest => self.instr(Instruction::F32Nearest),
+                    F32Sqrt => self.instr(Instruction::F32Sqrt),
+
+                    F64Abs => self.instr(Instruction::F64Abs),
+                    F64Neg => self.instr(Instruction::F64Neg),
+                    F64Ceil => self.instr(Instruction::F64Ceil),
+                    F64Floor => self.instr(Instruction::F64Floor),
+                    F64Trunc => self.instr(Instruction::F64Trunc),
+                    F64Nearest => self.instr(Instruction::F64Nearest),
+                    F64Sqrt => self.instr(Instruction::F64Sqrt),
+
+                    I32WrapI64 => self.instr(Instruction::I32WrapI64),
+                    I32TruncSF32 => self.instr(Instruction::I32TruncF32S),
+                    I32TruncUF32 => self.instr(Instruction::I32TruncF32U),
+                    I32TruncSF64 => self.instr(Instruction::I32TruncF64S),
+                    I32TruncUF64 => self.instr(Instruction::I32TruncF64U),
+                    I64ExtendSI32 => self.instr(Instruction::I64ExtendI32S),
+                    I64ExtendUI32 => self.instr(Instruction::I64ExtendI32U),
+                    I64TruncSF32 => self.instr(Instruction::I64TruncF32S),
+                    I64TruncUF32 => self.instr(Instruction::I64TruncF32U),
+                    I64TruncSF64 => self.instr(Instruction::I64TruncF64S),
+                    I64TruncUF64 => self.instr(Instruction::I64TruncF64U),
+
+                    F32ConvertSI32 => self.instr(Instruction::F32ConvertI32S),
+                    F32ConvertUI32 => self.instr(Instruction::F32ConvertI32U),
+                    F32ConvertSI64 => self.instr(Instruction::F32ConvertI64S),
+                    F32ConvertUI64 => self.instr(Instruction::F32ConvertI64U),
+                    F32DemoteF64 => self.instr(Instruction::F32DemoteF64),
+                    F64ConvertSI32 => self.instr(Instruction::F64ConvertI32S),
+                    F64ConvertUI32 => self.instr(Instruction::F64ConvertI32U),
+                    F64ConvertSI64 => self.instr(Instruction::F64ConvertI64S),
+                    F64ConvertUI64 => self.instr(Instruction::F64ConvertI64U),
+                    F64PromoteF32 => self.instr(Instruction::F64PromoteF32),
+
+                    I32ReinterpretF32 => self.instr(Instruction::I32ReinterpretF32),
+                    I64ReinterpretF64 => self.instr(Instruction::I64ReinterpretF64),
+                    F32ReinterpretI32 => self.instr(Instruction::F32ReinterpretI32),
+                    F64ReinterpretI64 => self.instr(Instruction::F64ReinterpretI64),
+
+                    I32Extend8S => self.instr(Instruction::I32Extend8S),
+                    I32Extend16S => self.instr(Instruction::I32Extend16S),
+                    I64Extend8S => self.instr(Instruction::I64Extend8S),
+                    I64Extend16S => self.instr(Instruction::I64Extend16S),
+                    I64Extend32S => self.instr(Instruction::I64Extend32S),
 
                     I8x16Splat => self.simd(0x04),
                     I8x16ExtractLaneS { idx } => {
@@ -510,14 +522,14 @@ impl<'instr> Visitor<'instr> for Emit<'_, '_> {
                     F64x2ConvertSI64x2 => self.simd(0xb1),
                     F64x2ConvertUI64x2 => self.simd(0xb2),
 
-                    I32TruncSSatF32 => self.encoder.raw(&[0xfc, 0x00]),
-                    I32TruncUSatF32 => self.encoder.raw(&[0xfc, 0x01]),
-                    I32TruncSSatF64 => self.encoder.raw(&[0xfc, 0x02]),
-                    I32TruncUSatF64 => self.encoder.raw(&[0xfc, 0x03]),
-                    I64TruncSSatF32 => self.encoder.raw(&[0xfc, 0x04]),
-                    I64TruncUSatF32 => self.encoder.raw(&[0xfc, 0x05]),
-                    I64TruncSSatF64 => self.encoder.raw(&[0xfc, 0x06]),
-                    I64TruncUSatF64 => self.encoder.raw(&[0xfc, 0x07]),
+                    I32TruncSSatF32 => self.instr(Instruction::I32TruncSatF32S),
+                    I32TruncUSatF32 => self.instr(Instruction::I32TruncSatF32U),
+                    I32TruncSSatF64 => self.instr(Instruction::I32TruncSatF64S),
+                    I32TruncUSatF64 => self.instr(Instruction::I32TruncSatF64U),
+                    I64TruncSSatF32 => self.instr(Instruction::I64TruncSatF32S),
+                    I64TruncUSatF32 => self.instr(Instruction::I64TruncSatF32U),
+                    I64TruncSSatF64 => self.instr(Instruction::I64TruncSatF64S),
+                    I64TruncUSatF64 => self.instr(Instruction::I64TruncSatF64U),
 
                     I16x8WidenLowI8x16S => self.simd(0xca),
                     I16x8WidenHighI8x16S => self.simd(0xcb),
@@ -530,77 +542,60 @@ impl<'instr> Visitor<'instr> for Emit<'_, '_> {
                 }
             }
 
-            Select(e) => {
-                match e.ty {
-                    Some(ty) => {
-                        self.encoder.byte(0x1c);
-                        self.encoder.byte(0x01);
-                        ty.emit(self.encoder);
-                    }
-                    None => {
-                        self.encoder.byte(0x1b); // select
-                    }
-                }
-            }
+            Select(e) => match e.ty {
+                Some(ty) => self.instr(Instruction::TypedSelect(ty.to_wasm_encoder(self.indices))),
+                None => self.instr(Instruction::Select),
+            },
 
-            Unreachable(_) => {
-                self.encoder.byte(0x00); // unreachable
-            }
+            Unreachable(_) => self.instr(Instruction::Unreachable),
 
             Br(e) => {
                 let target = self.branch_target(e.block);
-                self.encoder.byte(0x0c); // br
-                self.encoder.u32(target);
+                self.instr(Instruction::Br(target));
             }
 
             BrIf(e) => {
                 let target = self.branch_target(e.block);
-                self.encoder.byte(0x0d); // br_if
-                self.encoder.u32(target);
+                self.instr(Instruction::BrIf(target));
             }
 
             Call(e) => {
                 let idx = self.indices.get_func_index(e.func);
-                self.encoder.byte(0x10); // call
-                self.encoder.u32(idx);
+                self.instr(Instruction::Call(idx));
             }
 
             CallIndirect(e) => {
-                let idx = self.indices.get_type_index(e.ty);
-                let table = self.indices.get_table_index(e.table);
-                self.encoder.byte(0x11); // call_indirect
-                self.encoder.u32(idx);
-                self.encoder.u32(table);
+                let type_index = self.indices.get_type_index(e.ty);
+                let table_index = self.indices.get_table_index(e.table);
+                self.instr(Instruction::CallIndirect {
+                    type_index,
+                    table_index,
+                });
             }
 
             LocalGet(e) => {
                 let idx = self.local_indices[&e.local];
-                self.encoder.byte(0x20); // local.get
-                self.encoder.u32(idx);
+                self.instr(Instruction::LocalGet(idx));
             }
 
             LocalSet(e) => {
                 let idx = self.local_indices[&e.local];
-                self.encoder.byte(0x21); // local.set
-                self.encoder.u32(idx);
+                self.instr(Instruction::LocalSet(idx));
             }
 
             LocalTee(e) => {
                 let idx = self.local_indices[&e.local];
-                self.encoder.byte(0x22); // local.tee
-                self.encoder.u32(idx);
+                self.instr(Instruction::LocalTee(idx));
             }
 
             GlobalGet(e) => {
                 let idx = self.indices.get_global_index(e.global);
-                self.encoder.byte(0x23); // global.get
-                self.encoder.u32(idx);
+                self.instr(Instruction::GlobalGet(idx));
             }
 
             GlobalSet(e) => {
                 let idx = self.indices.get_global_index(e.global);
-                self.encoder.byte(0x24); // global.set
-                self.encoder.u32(idx);
+                self.instr(Instruction::GlobalSet(idx));
             }
 
             Load(e) => {
@@ -760,41 +755,100 @@ impl<'instr> Visitor<'instr> for Emit<'_, '_> {
                 self.encoder.byte(0x00);
             }
 
+            #[cfg(feature = "unstable")]
+            Pause(_e) => {
+                self.encoder.raw(&[0xfe, 0x04]); // pause
+            }
+
+            #[cfg(feature = "unstable")]
+            GlobalAtomicGet(e) => {
+                self.encoder.raw(&[0xfe, 0x4f]); // global.atomic.get
+                self.atomic_ordering(e.ordering);
+                let idx = self.indices.get_global_index(e.global);
+                self.encoder.u32(idx);
+            }
+
+            #[cfg(feature = "unstable")]
+            GlobalAtomicSet(e) => {
+                self.encoder.raw(&[0xfe, 0x50]); // global.atomic.set
+                self.atomic_ordering(e.ordering);
+                let idx = self.indices.get_global_index(e.global);
+                self.encoder.u32(idx);
+            }
+
+            #[cfg(feature = "unstable")]
+            ContNew(e) => {
+                self.encoder.byte(0xe0); // cont.new
+                let idx = self.indices.get_type_index(e.ty);
+                self.encoder.u32(idx);
+            }
+            #[cfg(feature = "unstable")]
+            Suspend(e) => {
+                self.encoder.byte(0xe2); // suspend
+                let idx = self.indices.get_tag_index(e.tag);
+                self.encoder.u32(idx);
+            }
+            #[cfg(feature = "unstable")]
+            Resume(e) => {
+                self.encoder.byte(0xe3); // resume
+                let idx = self.indices.get_type_index(e.ty);
+                self.encoder.u32(idx);
+                self.encoder.usize(e.handlers.len());
+                for handler in e.handlers.iter() {
+                    self.encoder.byte(0x00); // on
+                    let idx = self.indices.get_tag_index(handler.tag);
+                    self.encoder.u32(idx);
+                    let target = self.branch_target(handler.block);
+                    self.encoder.u32(target);
+                }
+            }
+
             TableGet(e) => {
-                self.encoder.byte(0x25);
                 let idx = self.indices.get_table_index(e.table);
-                self.encoder.u32(idx);
+                self.instr(Instruction::TableGet(idx));
             }
             TableSet(e) => {
-                self.encoder.byte(0x26);
                 let idx = self.indices.get_table_index(e.table);
-                self.encoder.u32(idx);
+                self.instr(Instruction::TableSet(idx));
             }
             TableGrow(e) => {
-                self.encoder.raw(&[0xfc, 0x0f]);
                 let idx = self.indices.get_table_index(e.table);
-                self.encoder.u32(idx);
+                self.instr(Instruction::TableGrow(idx));
             }
             TableSize(e) => {
-                self.encoder.raw(&[0xfc, 0x10]);
                 let idx = self.indices.get_table_index(e.table);
-                self.encoder.u32(idx);
+                self.instr(Instruction::TableSize(idx));
             }
             TableFill(e) => {
-                self.encoder.raw(&[0xfc, 0x11]);
                 let idx = self.indices.get_table_index(e.table);
-                self.encoder.u32(idx);
+                self.instr(Instruction::TableFill(idx));
             }
+            TableInit(e) => {
+                let elem_index = self.indices.get_element_index(e.elem);
+                let table = self.indices.get_table_index(e.table);
+                self.instr(Instruction::TableInit { elem_index, table });
+            }
+            ElemDrop(e) => {
+                let idx = self.indices.get_element_index(e.elem);
+                self.instr(Instruction::ElemDrop(idx));
+            }
+            TableCopy(e) => {
+                let dst_table = self.indices.get_table_index(e.dst);
+                let src_table = self.indices.get_table_index(e.src);
+                self.instr(Instruction::TableCopy {
+                    src_table,
+                    dst_table,
+                });
+            }
+            // The parser reads `ref.null` as in the reference types proposal
+            // before it gained a heap type immediate.
             RefNull(_e) => {
                 self.encoder.byte(0xd0);
             }
-            RefIsNull(_e) => {
-                self.encoder.byte(0xd1);
-            }
+            RefIsNull(_e) => self.instr(Instruction::RefIsNull),
             RefFunc(e) => {
-                self.encoder.byte(0xd2);
                 let idx = self.indices.get_func_index(e.func);
-                self.encoder.u32(idx);
+                self.instr(Instruction::RefFunc(idx));
             }
 
             V128Bitselect(_) => {
@@ -833,26 +887,55 @@ impl Emit<'_, '_> {
         ) as u32
     }
 
-    fn block_type(&mut self, ty: InstrSeqType) {
+    fn block_type(&self, ty: InstrSeqType) -> wasm_encoder::BlockType {
         match ty {
-            InstrSeqType::Simple(None) => self.encoder.byte(0x40),
-            InstrSeqType::Simple(Some(ty)) => ty.emit(self.encoder),
+            InstrSeqType::Simple(None) => wasm_encoder::BlockType::Empty,
+            InstrSeqType::Simple(Some(ty)) => {
+                wasm_encoder::BlockType::Result(ty.to_wasm_encoder(self.indices))
+            }
             InstrSeqType::MultiValue(ty) => {
                 let index = self.indices.get_type_index(ty);
                 assert!(index < std::i32::MAX as u32);
-                self.encoder.i32(index as i32);
+                wasm_encoder::BlockType::FunctionType(index)
             }
         }
     }
 
+    fn instr(&mut self, instr: Instruction) {
+        self.encoder.encode(&instr);
+    }
+
     fn memarg(&mut self, id: MemoryId, arg: &MemArg) {
         assert_eq!(self.indices.get_memory_index(id), 0);
-        self.encoder.u32(arg.align.trailing_zeros());
-        self.encoder.u32(arg.offset);
+        let align = arg.align.trailing_zeros();
+        match arg.encoding {
+            Some(encoding) if !self.canonicalize_memargs => {
+                self.encoder.u32_padded(align, encoding.align_width.into());
+                self.encoder
+                    .u32_padded(arg.offset, encoding.offset_width.into());
+            }
+            _ => {
+                self.encoder.u32(align);
+                self.encoder.u32(arg.offset);
+            }
+        }
     }
 
+    /// Emit the SIMD `opcode`.
+    ///
+    /// SIMD instructions are encoded by hand, rather than with wasm-encoder,
+    /// because they were renumbered after the version of the proposal that the
+    /// parser reads.
     fn simd(&mut self, opcode: u32) {
         self.encoder.byte(0xfd);
         self.encoder.u32(opcode);
     }
+
+    #[cfg(feature = "unstable")]
+    fn atomic_ordering(&mut self, ordering: AtomicOrdering) {
+        self.encoder.byte(match ordering {
+            AtomicOrdering::SeqCst => 0x00,
+            AtomicOrdering::AcqRel => 0x01,
+        });
+    }
 }
@@ -6,21 +6,27 @@ mod emit;
 use self::context::ValidationContext;
 use crate::emit::IdsToIndices;
 use crate::encode::Encoder;
+use crate::error::ErrorKind;
 use crate::ir::*;
 use crate::map::{IdHashMap, IdHashSet};
+use crate::module::address_map::EmittedInstr;
 use crate::parse::IndicesToIds;
 use crate::{
-    Data, DataId, FunctionBuilder, FunctionId, Module, Result, TableKind, TypeId, ValType,
+    Data, DataId, FunctionBuilder, FunctionId, Module, Result, TableId, TableKind, TypeId, ValType,
 };
 use anyhow::{bail, Context};
 use std::collections::BTreeMap;
+use std::sync::Arc;
 use wasmparser::Operator;
 
 /// A function defined locally within the wasm module.
-#[derive(Debug)]
+///
+/// Cloning a local function is cheap: clones share its instructions until
+/// either of them is mutated, which copies them first.
+#[derive(Debug, Clone)]
 pub struct LocalFunction {
     /// All of this function's instructions, contained in the arena.
-    builder: FunctionBuilder,
+    builder: Arc<FunctionBuilder>,
 
     /// Arguments to this function, and the locals that they're assigned to.
     pub args: Vec<LocalId>,
@@ -32,7 +38,10 @@ pub struct LocalFunction {
 impl LocalFunction {
     /// Creates a new definition of a local function from its components.
     pub(crate) fn new(args: Vec<LocalId>, builder: FunctionBuilder) -> LocalFunction {
-        LocalFunction { args, builder }
+        LocalFunction {
+            args,
+            builder: Arc::new(builder),
+        }
     }
 
     /// Construct a new `LocalFunction`.
@@ -45,11 +54,39 @@ impl LocalFunction {
         id: FunctionId,
         ty: TypeId,
         args: Vec<LocalId>,
-        mut body: wasmparser::OperatorsReader,
+        body: wasmparser::FunctionBody,
+        on_instr_pos: Option<&(dyn Fn(&usize) -> InstrLocId + Sync + Send + 'static)>,
+    ) -> Result<LocalFunction> {
+        LocalFunction::parse_body(module, indices, id, ty, args, body, on_instr_pos).map_err(|e| {
+            // Bodies that decode fine but fail to parse are invalid.
+            if e.root_cause().is::<wasmparser::BinaryReaderError>() {
+                ErrorKind::Parse.classify(e)
+            } else {
+                ErrorKind::Validate.classify(e)
+            }
+        })
+    }
+
+    fn parse_body(
+        module: &Module,
+        indices: &IndicesToIds,
+        id: FunctionId,
+        ty: TypeId,
+        args: Vec<LocalId>,
+        body: wasmparser::FunctionBody,
         on_instr_pos: Option<&(dyn Fn(&usize) -> InstrLocId + Sync + Send + 'static)>,
     ) -> Result<LocalFunction> {
+        // Keep the raw bytes of the body around too, to recover how the
+        // immediates of instructions were encoded.
+        let mut raw = body.get_binary_reader();
+        let raw_offset = raw.original_position();
+        let raw = raw.read_bytes(raw.bytes_remaining())?;
+        // Read the instructions with a plain `BinaryReader`, so that those
+        // that `wasmparser` can't decode can be decoded here instead.
+        let (_, mut body) = read_locals(&body, indices)?;
+
         let mut func = LocalFunction {
-            builder: FunctionBuilder::without_entry(ty),
+            builder: Arc::new(FunctionBuilder::without_entry(ty)),
             args,
         };
 
@@ -66,15 +103,22 @@ impl LocalFunction {
             "the function entry type should have already been created before parsing the body",
         );
         let entry = ctx.push_control_with_ty(BlockKind::FunctionEntry, ty);
-        ctx.func.builder.entry = Some(entry);
+        ctx.func.builder_mut().entry = Some(entry);
         while !body.eof() {
-            let (inst, pos) = body.read_with_offset()?;
+            let pos = body.original_position();
             let loc = if let Some(ref on_instr_pos) = on_instr_pos {
                 on_instr_pos(&pos)
             } else {
                 InstrLocId::new(pos as u32)
             };
-            validate_instruction(&mut ctx, inst, loc)?;
+            #[cfg(feature = "unstable")]
+            {
+                if validate_unstable_instruction(&mut ctx, &mut body, loc)? {
+                    continue;
+                }
+            }
+            let inst = body.read_operator()?;
+            validate_instruction(&mut ctx, inst, &raw[pos - raw_offset..], loc)?;
         }
         if !ctx.controls.is_empty() {
             bail!("function failed to end with `end`");
@@ -96,7 +140,7 @@ impl LocalFunction {
         &mut self,
         make_block: impl FnOnce(InstrSeqId) -> InstrSeq,
     ) -> InstrSeqId {
-        self.builder.arena.alloc_with_id(make_block)
+        self.builder_mut().arena.alloc_with_id(make_block)
     }
 
     /// Get the id of this function's entry block.
@@ -111,7 +155,7 @@ impl LocalFunction {
 
     /// Get the block associated with the given id.
     pub fn block_mut(&mut self, id: InstrSeqId) -> &mut InstrSeq {
-        &mut self.builder.arena[id]
+        &mut self.builder_mut().arena[id]
     }
 
     /// Get access to a `FunctionBuilder` to continue adding instructions to
@@ -123,7 +167,7 @@ impl LocalFunction {
     /// Get access to a `FunctionBuilder` to continue adding instructions to
     /// this function.
     pub fn builder_mut(&mut self) -> &mut FunctionBuilder {
-        &mut self.builder
+        Arc::make_mut(&mut self.builder)
     }
 
     /// Get the size of this function, in number of instructions.
@@ -144,6 +188,72 @@ impl LocalFunction {
         }
     }
 
+    /// Count the instructions of this function, by their class.
+    ///
+    /// The total is the same as `size`.
+    pub fn instruction_stats(&self) -> InstrStats {
+        let mut v = StatsVisitor::default();
+        dfs_in_order(&mut v, self, self.entry_block());
+        return v.stats;
+
+        #[derive(Default)]
+        struct StatsVisitor {
+            stats: InstrStats,
+        }
+
+        impl<'instr> Visitor<'instr> for StatsVisitor {
+            fn visit_instr(&mut self, instr: &'instr Instr, _: &'instr InstrLocId) {
+                self.stats.add(instr);
+            }
+        }
+    }
+
+    /// Get the cost of executing every instruction of this function once,
+    /// according to `model`.
+    pub fn cost(&self, model: &impl InstrCost) -> u64 {
+        let mut v = CostVisitor { model, cost: 0 };
+        dfs_in_order(&mut v, self, self.entry_block());
+        return v.cost;
+
+        struct CostVisitor<'a, C> {
+            model: &'a C,
+            cost: u64,
+        }
+
+        impl<'instr, C: InstrCost> Visitor<'instr> for CostVisitor<'_, C> {
+            fn visit_instr(&mut self, instr: &'instr Instr, _: &'instr InstrLocId) {
+                self.cost += self.model.cost(instr);
+            }
+        }
+    }
+
+    /// Get the instruction sequences of this function's labels, in the order
+    /// that the `name` section numbers them: that of the `block`s, `loop`s and
+    /// `if`s that start them.
+    ///
+    /// The label of an `if` is its consequent.
+    pub(crate) fn labels(&self) -> Vec<InstrSeqId> {
+        let mut v = LabelsVisitor::default();
+        dfs_in_order(&mut v, self, self.entry_block());
+        return v.labels;
+
+        #[derive(Default)]
+        struct LabelsVisitor {
+            labels: Vec<InstrSeqId>,
+        }
+
+        impl<'instr> Visitor<'instr> for LabelsVisitor {
+            fn visit_instr(&mut self, instr: &'instr Instr, _: &'instr InstrLocId) {
+                match instr {
+                    Instr::Block(b) => self.labels.push(b.seq),
+                    Instr::Loop(l) => self.labels.push(l.seq),
+                    Instr::IfElse(i) => self.labels.push(i.consequent),
+                    _ => {}
+                }
+            }
+        }
+    }
+
     /// Is this function's body a [constant
     /// instruction](https://webassembly.github.io/spec/core/valid/instructions.html#constant-instructions)?
     pub fn is_const(&self) -> bool {
@@ -193,6 +303,7 @@ impl LocalFunction {
     pub(crate) fn emit_locals(
         &self,
         module: &Module,
+        indices: &IdsToIndices,
         encoder: &mut Encoder,
     ) -> (IdHashSet<Local>, IdHashMap<Local, u32>) {
         let used_set = self.used_locals();
@@ -239,7 +350,7 @@ impl LocalFunction {
         encoder.usize(ty_to_locals.len());
         for (ty, locals) in ty_to_locals.iter() {
             encoder.usize(locals.len());
-            ty.emit(encoder);
+            ty.emit(encoder, indices);
         }
 
         (used_set, local_map)
@@ -251,12 +362,190 @@ impl LocalFunction {
         indices: &IdsToIndices,
         local_indices: &IdHashMap<Local, u32>,
         dst: &mut Encoder,
-        map: Option<&mut Vec<(InstrLocId, usize)>>,
+        map: Option<&mut Vec<EmittedInstr>>,
+        canonicalize_memargs: bool,
     ) {
-        emit::run(self, indices, local_indices, dst, map)
+        emit::run(self, indices, local_indices, dst, map, canonicalize_memargs)
+    }
+}
+
+/// Validate and construct the instruction at `reader` if it is one of those
+/// from the proposals behind the `unstable` feature, which `wasmparser` can't
+/// decode, returning whether it was.
+/// Read the locals declared at the start of `body`, as pairs of how many there
+/// are of each type, and return them along with a reader of the instructions
+/// after them.
+pub(crate) fn read_locals<'a>(
+    body: &wasmparser::FunctionBody<'a>,
+    ids: &IndicesToIds,
+) -> Result<(Vec<(u32, ValType)>, wasmparser::BinaryReader<'a>)> {
+    let mut reader = body.get_binary_reader();
+    let count = reader.read_var_u32()?;
+    let locals = (0..count)
+        .map(|_| Ok((reader.read_var_u32()?, ValType::read(&mut reader, ids)?)))
+        .collect::<Result<_>>()?;
+    Ok((locals, reader))
+}
+
+#[cfg(feature = "unstable")]
+fn validate_unstable_instruction(
+    ctx: &mut ValidationContext,
+    reader: &mut wasmparser::BinaryReader,
+    loc: InstrLocId,
+) -> Result<bool> {
+    let mut peek = reader.clone();
+    match peek.read_u8()? {
+        0xfe => match peek.read_var_u32()? {
+            0x04 => ctx.alloc_instr(Pause {}, loc),
+            0x4f => {
+                let ordering = atomic_ordering(&mut peek)?;
+                let global = ctx
+                    .indices
+                    .get_global(peek.read_var_u32()?)
+                    .context("invalid global.atomic.get")?;
+                let ty = ctx.module.globals.get(global).ty;
+                ctx.alloc_instr(GlobalAtomicGet { global, ordering }, loc);
+                ctx.push_operand(Some(ty));
+            }
+            0x50 => {
+                let ordering = atomic_ordering(&mut peek)?;
+                let global = ctx
+                    .indices
+                    .get_global(peek.read_var_u32()?)
+                    .context("invalid global.atomic.set")?;
+                let ty = ctx.module.globals.get(global).ty;
+                ctx.pop_operand_expected(Some(ty))?;
+                ctx.alloc_instr(GlobalAtomicSet { global, ordering }, loc);
+            }
+            _ => return Ok(false),
+        },
+        // Blocks whose result is a reference type that `wasmparser` doesn't
+        // know about.
+        kind @ 0x02..=0x04 if matches!(peek.clone().read_u8()?, 0x63 | 0x64) => {
+            let result = ValType::read(&mut peek, ctx.indices)?;
+            let kind = match kind {
+                0x02 => BlockKind::Block,
+                0x03 => BlockKind::Loop,
+                _ => BlockKind::If,
+            };
+            validate_block_start(ctx, kind, Box::new([]), Box::new([result]), loc)?;
+        }
+        0xe0 => {
+            let ty = cont_type(ctx, &mut peek).context("invalid cont.new")?;
+            ctx.pop_operand_expected(Some(ValType::Anyref))?;
+            ctx.alloc_instr(ContNew { ty }, loc);
+            ctx.push_operand(Some(ValType::Cont {
+                nullable: false,
+                ty,
+            }));
+        }
+        0xe2 => {
+            let tag = ctx
+                .indices
+                .get_tag(peek.read_var_u32()?)
+                .context("invalid suspend")?;
+            let fun_ty = ctx.module.tags.get(tag).ty;
+            let (params, results) = ctx.module.types.params_results(fun_ty);
+            let results = results.to_vec();
+            ctx.pop_operands(params)?;
+            ctx.alloc_instr(Suspend { tag }, loc);
+            ctx.push_operands(&results);
+        }
+        0xe3 => {
+            let ty = cont_type(ctx, &mut peek).context("invalid resume")?;
+            let mut handlers = Vec::new();
+            for _ in 0..peek.read_var_u32()? {
+                handlers.push(resume_handler(ctx, &mut peek)?);
+            }
+            let handlers = handlers.into_boxed_slice();
+            let fun_ty = ctx.module.types.get(ty).cont_of().unwrap();
+            let (params, results) = ctx.module.types.params_results(fun_ty);
+            let results = results.to_vec();
+            ctx.pop_operand_expected(Some(ValType::Cont { nullable: true, ty }))?;
+            ctx.pop_operands(params)?;
+            ctx.alloc_instr(Resume { ty, handlers }, loc);
+            ctx.push_operands(&results);
+        }
+        _ => return Ok(false),
+    }
+    *reader = peek;
+    Ok(true)
+}
+
+#[cfg(feature = "unstable")]
+fn cont_type(ctx: &ValidationContext, reader: &mut wasmparser::BinaryReader) -> Result<TypeId> {
+    let ty = ctx.indices.get_type(reader.read_var_u32()?)?;
+    if ctx.module.types.get(ty).cont_of().is_none() {
+        bail!("not a continuation type");
+    }
+    Ok(ty)
+}
+
+/// Read a handler clause of a `resume`, whose label has to take the tag's
+/// parameters and a continuation.
+#[cfg(feature = "unstable")]
+fn resume_handler(
+    ctx: &ValidationContext,
+    reader: &mut wasmparser::BinaryReader,
+) -> Result<ResumeHandler> {
+    if reader.read_u8()? != 0x00 {
+        bail!("`switch` handlers aren't supported");
+    }
+    let tag = ctx
+        .indices
+        .get_tag(reader.read_var_u32()?)
+        .context("invalid resume handler")?;
+    let control = ctx.control(reader.read_var_u32()? as usize)?;
+    let params = ctx.module.types.params(ctx.module.tags.get(tag).ty);
+    match control.label_types().split_last() {
+        Some((ValType::Cont { .. }, label_params))
+            if label_params.len() == params.len()
+                && params
+                    .iter()
+                    .zip(label_params)
+                    .all(|(a, b)| a.is_subtype_of(*b)) => {}
+        _ => bail!("type mismatch in resume handler"),
+    }
+    Ok(ResumeHandler {
+        tag,
+        block: control.block,
+    })
+}
+
+#[cfg(feature = "unstable")]
+fn atomic_ordering(reader: &mut wasmparser::BinaryReader) -> Result<AtomicOrdering> {
+    match reader.read_u8()? {
+        0x00 => Ok(AtomicOrdering::SeqCst),
+        0x01 => Ok(AtomicOrdering::AcqRel),
+        _ => bail!("invalid memory ordering"),
     }
 }
 
+/// Validate the start of a `block`, `loop` or `if` with the given parameters
+/// and results.
+fn validate_block_start(
+    ctx: &mut ValidationContext,
+    kind: BlockKind,
+    param_tys: Box<[ValType]>,
+    result_tys: Box<[ValType]>,
+    loc: InstrLocId,
+) -> Result<()> {
+    if kind == BlockKind::If {
+        ctx.pop_operand_expected(Some(ValType::I32))?;
+    }
+    ctx.pop_operands(&param_tys)?;
+    let seq = ctx.push_control(kind, param_tys, result_tys)?;
+    match kind {
+        BlockKind::Block => ctx.alloc_instr_in_control(1, Block { seq }, loc)?,
+        BlockKind::Loop => ctx.alloc_instr_in_control(1, Loop { seq }, loc)?,
+        _ => ctx.if_else.push(context::IfElseState {
+            consequent: seq,
+            alternative: None,
+        }),
+    }
+    Ok(())
+}
+
 fn block_result_tys(
     ctx: &ValidationContext,
     ty: wasmparser::TypeOrFuncType,
@@ -283,9 +572,35 @@ fn block_param_tys(
     }
 }
 
+fn table_index_type(ctx: &ValidationContext, table: TableId) -> ValType {
+    ctx.module.tables.get(table).index_type.value_type()
+}
+
+/// Get the widths of the alignment and offset of the `memarg` immediate of the
+/// instruction at the start of `raw`.
+fn mem_arg_encoding(raw: &[u8]) -> MemArgEncoding {
+    // The `memarg` follows the opcode, and the LEB128 encoded sub-opcode of
+    // prefixed instructions.
+    let mut pos = 1;
+    if let 0xfc..=0xfe = raw[0] {
+        pos += leb128_width(&raw[pos..]);
+    }
+    let align_width = leb128_width(&raw[pos..]);
+    let offset_width = leb128_width(&raw[pos + align_width..]);
+    MemArgEncoding {
+        align_width: align_width as u8,
+        offset_width: offset_width as u8,
+    }
+}
+
+fn leb128_width(raw: &[u8]) -> usize {
+    raw.iter().position(|byte| byte & 0x80 == 0).unwrap() + 1
+}
+
 fn validate_instruction<'context>(
     ctx: &'context mut ValidationContext,
     inst: Operator,
+    raw: &[u8],
     loc: InstrLocId,
 ) -> Result<()> {
     use crate::ir::ExtendedLoad::*;
@@ -324,6 +639,7 @@ fn validate_instruction<'context>(
         Ok(MemArg {
             align: 1 << (arg.flags as i32),
             offset: arg.offset,
+            encoding: Some(mem_arg_encoding(raw)),
         })
     };
 
@@ -404,7 +720,7 @@ fn validate_instruction<'context>(
                 .indices
                 .get_table(table_index)
                 .context("invalid call_indirect")?;
-            ctx.pop_operand_expected(Some(I32))?;
+            ctx.pop_operand_expected(Some(table_index_type(ctx, table)))?;
             ctx.pop_operands(ty.params())?;
             ctx.alloc_instr(CallIndirect { table, ty: type_id }, loc);
             ctx.push_operands(ty.results());
@@ -633,29 +949,17 @@ fn validate_instruction<'context>(
         Operator::Block { ty } => {
             let param_tys = block_param_tys(ctx, ty)?;
             let result_tys = block_result_tys(ctx, ty)?;
-            ctx.pop_operands(&param_tys)?;
-            let seq = ctx.push_control(BlockKind::Block, param_tys, result_tys)?;
-            ctx.alloc_instr_in_control(1, Block { seq }, loc)?;
+            validate_block_start(ctx, BlockKind::Block, param_tys, result_tys, loc)?;
         }
         Operator::Loop { ty } => {
-            let result_tys = block_result_tys(ctx, ty)?;
             let param_tys = block_param_tys(ctx, ty)?;
-            ctx.pop_operands(&param_tys)?;
-            let seq = ctx.push_control(BlockKind::Loop, param_tys, result_tys)?;
-            ctx.alloc_instr_in_control(1, Loop { seq }, loc)?;
+            let result_tys = block_result_tys(ctx, ty)?;
+            validate_block_start(ctx, BlockKind::Loop, param_tys, result_tys, loc)?;
         }
         Operator::If { ty } => {
-            let result_tys = block_result_tys(ctx, ty)?;
             let param_tys = block_param_tys(ctx, ty)?;
-
-            ctx.pop_operand_expected(Some(I32))?;
-            ctx.pop_operands(&param_tys)?;
-
-            let consequent = ctx.push_control(BlockKind::If, param_tys, result_tys)?;
-            ctx.if_else.push(context::IfElseState {
-                consequent,
-                alternative: None,
-            });
+            let result_tys = block_result_tys(ctx, ty)?;
+            validate_block_start(ctx, BlockKind::If, param_tys, result_tys, loc)?;
         }
         Operator::End => {
             let (frame, _block) = ctx.pop_control()?;
@@ -1153,7 +1457,7 @@ fn validate_instruction<'context>(
 
         Operator::TableGet { table } => {
             let table = ctx.indices.get_table(table)?;
-            ctx.pop_operand_expected(Some(I32))?;
+            ctx.pop_operand_expected(Some(table_index_type(ctx, table)))?;
             ctx.alloc_instr(TableGet { table }, loc);
             ctx.push_operand(Some(Anyref));
         }
@@ -1161,39 +1465,87 @@ fn validate_instruction<'context>(
             let table = ctx.indices.get_table(table)?;
             let expected_ty = match ctx.module.tables.get(table).kind {
                 TableKind::Anyref(_) => Anyref,
-                TableKind::Function(_) => bail!("cannot set function table yet"),
+                TableKind::Function(_) => {
+                    return Err(ErrorKind::Unsupported {
+                        feature: "function table instructions",
+                    })
+                    .context("cannot set function table yet")
+                }
             };
             ctx.pop_operand_expected(Some(expected_ty))?;
-            ctx.pop_operand_expected(Some(I32))?;
+            ctx.pop_operand_expected(Some(table_index_type(ctx, table)))?;
             ctx.alloc_instr(TableSet { table }, loc);
         }
         Operator::TableGrow { table } => {
             let table = ctx.indices.get_table(table)?;
             let expected_ty = match ctx.module.tables.get(table).kind {
                 TableKind::Anyref(_) => Anyref,
-                TableKind::Function(_) => bail!("cannot grow function table yet"),
+                TableKind::Function(_) => {
+                    return Err(ErrorKind::Unsupported {
+                        feature: "function table instructions",
+                    })
+                    .context("cannot grow function table yet")
+                }
             };
-            ctx.pop_operand_expected(Some(I32))?;
+            let index_ty = table_index_type(ctx, table);
+            ctx.pop_operand_expected(Some(index_ty))?;
             ctx.pop_operand_expected(Some(expected_ty))?;
             ctx.alloc_instr(TableGrow { table }, loc);
-            ctx.push_operand(Some(I32));
+            ctx.push_operand(Some(index_ty));
         }
         Operator::TableSize { table } => {
             let table = ctx.indices.get_table(table)?;
             ctx.alloc_instr(TableSize { table }, loc);
-            ctx.push_operand(Some(I32));
+            ctx.push_operand(Some(table_index_type(ctx, table)));
         }
         Operator::TableFill { table } => {
             let table = ctx.indices.get_table(table)?;
             let expected_ty = match ctx.module.tables.get(table).kind {
                 TableKind::Anyref(_) => Anyref,
-                TableKind::Function(_) => bail!("cannot set function table yet"),
+                TableKind::Function(_) => {
+                    return Err(ErrorKind::Unsupported {
+                        feature: "function table instructions",
+                    })
+                    .context("cannot set function table yet")
+                }
             };
-            ctx.pop_operand_expected(Some(I32))?;
+            let index_ty = table_index_type(ctx, table);
+            ctx.pop_operand_expected(Some(index_ty))?;
             ctx.pop_operand_expected(Some(expected_ty))?;
-            ctx.pop_operand_expected(Some(I32))?;
+            ctx.pop_operand_expected(Some(index_ty))?;
             ctx.alloc_instr(TableFill { table }, loc);
         }
+        Operator::TableInit { segment, table } => {
+            let table = ctx.indices.get_table(table)?;
+            let elem = ctx.indices.get_element(segment)?;
+            ctx.pop_operand_expected(Some(I32))?;
+            ctx.pop_operand_expected(Some(I32))?;
+            ctx.pop_operand_expected(Some(table_index_type(ctx, table)))?;
+            ctx.alloc_instr(TableInit { table, elem }, loc);
+        }
+        Operator::ElemDrop { segment } => {
+            let elem = ctx.indices.get_element(segment)?;
+            ctx.alloc_instr(ElemDrop { elem }, loc);
+        }
+        Operator::TableCopy {
+            src_table,
+            dst_table,
+        } => {
+            let src = ctx.indices.get_table(src_table)?;
+            let dst = ctx.indices.get_table(dst_table)?;
+            let src_ty = table_index_type(ctx, src);
+            let dst_ty = table_index_type(ctx, dst);
+            // The length only needs to be 64-bit if both tables are.
+            let len_ty = if src_ty == I64 && dst_ty == I64 {
+                I64
+            } else {
+                I32
+            };
+            ctx.pop_operand_expected(Some(len_ty))?;
+            ctx.pop_operand_expected(Some(src_ty))?;
+            ctx.pop_operand_expected(Some(dst_ty))?;
+            ctx.alloc_instr(TableCopy { src, dst }, loc);
+        }
         Operator::RefNull => {
             ctx.alloc_instr(RefNull {}, loc);
             ctx.push_operand(Some(Anyref));
@@ -1451,12 +1803,6 @@ fn validate_instruction<'context>(
         }
         Operator::I8x16RoundingAverageU => binop(ctx, V128, BinaryOp::I8x16RoundingAverageU)?,
         Operator::I16x8RoundingAverageU => binop(ctx, V128, BinaryOp::I16x8RoundingAverageU)?,
-
-        op @ Operator::TableInit { .. }
-        | op @ Operator::ElemDrop { .. }
-        | op @ Operator::TableCopy { .. } => {
-            bail!("Have not implemented support for opcode yet: {:?}", op)
-        }
     }
     Ok(())
 }
@@ -2,18 +2,25 @@
 
 mod local_function;
 
+use crate::cancel::CancellationToken;
 use crate::emit::{Emit, EmitContext, Section};
 use crate::encode::Encoder;
-use crate::error::Result;
-use crate::ir::InstrLocId;
+use crate::error::{ErrorKind, Result};
+use crate::function_builder::FunctionBuilder;
+use crate::ir::{Instr, InstrLocId, Local, LocalId};
+use crate::map::IdHashMap;
+use crate::module::address_map::EmittedInstr;
 use crate::module::imports::ImportId;
-use crate::module::Module;
+use crate::module::{Module, SyntheticName};
+use crate::name_index::NameIndex;
 use crate::parse::IndicesToIds;
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
 use crate::ty::TypeId;
-use crate::ty::ValType;
-use anyhow::bail;
+use anyhow::{bail, Context};
+use std::borrow::Cow;
 use std::cmp;
+use std::collections::HashMap;
+use std::mem;
 
 #[cfg(feature = "parallel")]
 use rayon::prelude::*;
@@ -26,7 +33,7 @@ pub type FunctionId = Id<Function>;
 /// A wasm function.
 ///
 /// Either defined locally or externally and then imported; see `FunctionKind`.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Function {
     // NB: Not public so that it can't get out of sync with the arena that this
     // function lives within.
@@ -69,10 +76,46 @@ impl Function {
             FunctionKind::Uninitialized(t) => *t,
         }
     }
+
+    /// Get this function's name, demangled if it is a mangled symbol name.
+    ///
+    /// Rust symbols are demangled when the `demangle-rust` feature is enabled,
+    /// and Itanium C++ symbols when the `demangle-cpp` feature is. Names that
+    /// aren't mangled, or whose mangling isn't supported, are returned as
+    /// they are.
+    pub fn demangled_name(&self) -> Option<Cow<'_, str>> {
+        let name = self.name.as_ref()?;
+        Some(match demangle(name) {
+            Some(demangled) => Cow::Owned(demangled),
+            None => Cow::Borrowed(name),
+        })
+    }
+}
+
+/// Demangle `name` with whichever demanglers are enabled, if it is mangled.
+#[allow(unused_variables)]
+pub(crate) fn demangle(name: &str) -> Option<String> {
+    #[cfg(feature = "demangle-rust")]
+    {
+        if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+            return Some(format!("{:#}", demangled));
+        }
+    }
+    #[cfg(feature = "demangle-cpp")]
+    {
+        if name.starts_with("_Z") {
+            if let Ok(symbol) = cpp_demangle::Symbol::new(name) {
+                if let Ok(demangled) = symbol.demangle(&Default::default()) {
+                    return Some(demangled);
+                }
+            }
+        }
+    }
+    None
 }
 
 /// The local- or external-specific bits of a function.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum FunctionKind {
     /// An externally defined, imported wasm function.
     Import(ImportedFunction),
@@ -126,7 +169,7 @@ impl FunctionKind {
 }
 
 /// An externally defined, imported function.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ImportedFunction {
     /// The import that brings this function into the module.
     pub import: ImportId,
@@ -135,10 +178,43 @@ pub struct ImportedFunction {
 }
 
 /// The set of functions within a module.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct ModuleFunctions {
     /// The arena containing this module's functions.
     arena: TombstoneArena<Function>,
+
+    /// An index of this module's functions by name, for `by_name`.
+    names: NameIndex<Function>,
+
+    /// Local functions to emit first, in this order.
+    emit_order: Vec<FunctionId>,
+
+    /// The functions of the binary this module was parsed from, by their
+    /// index in it, and the other way around.
+    original_ids: Vec<FunctionId>,
+    original_indices: IdHashMap<Function, u32>,
+
+    /// The bodies that failed to parse in best-effort mode, by the functions
+    /// standing in for them.
+    invalid: IdHashMap<Function, InvalidBody>,
+}
+
+/// A function body that failed to parse, kept as it was encoded.
+///
+/// When `ModuleConfig::best_effort_parse` is enabled, a function whose body
+/// fails to parse gets a body consisting of just `unreachable` instead, and
+/// its original body is recorded as one of these, until it's replaced with
+/// `Module::replace_func_body_from_bytes`. Until then, the original body is
+/// what gets emitted for the function, byte for byte, so the indices in it
+/// are only still right if the items it refers to keep their indices.
+#[derive(Debug, Clone)]
+pub struct InvalidBody {
+    /// The body as it appears in the code section, without its size prefix.
+    pub bytes: Vec<u8>,
+    /// The offset of the body within the binary that it was parsed from.
+    pub offset: usize,
+    /// The error that parsing the body failed with.
+    pub error: String,
 }
 
 impl ModuleFunctions {
@@ -149,6 +225,7 @@ impl ModuleFunctions {
 
     /// Create a new externally defined, imported function.
     pub fn add_import(&mut self, ty: TypeId, import: ImportId) -> FunctionId {
+        self.names.invalidate();
         self.arena.alloc_with_id(|id| Function {
             id,
             kind: FunctionKind::Import(ImportedFunction { import, ty }),
@@ -158,6 +235,7 @@ impl ModuleFunctions {
 
     /// Create a new internally defined function
     pub fn add_local(&mut self, func: LocalFunction) -> FunctionId {
+        self.names.invalidate();
         let func_name = func.builder().name.clone();
         self.arena.alloc_with_id(|id| Function {
             id,
@@ -171,8 +249,15 @@ impl ModuleFunctions {
         &self.arena[id]
     }
 
+    /// Get the function with the given id, if it is one of this module's and
+    /// hasn't been deleted.
+    pub(crate) fn try_get(&self, id: FunctionId) -> Option<&Function> {
+        self.arena.get(id)
+    }
+
     /// Gets a reference to a function given its id
     pub fn get_mut(&mut self, id: FunctionId) -> &mut Function {
+        self.names.invalidate();
         &mut self.arena[id]
     }
 
@@ -183,25 +268,72 @@ impl ModuleFunctions {
     ///
     /// Note that function names are *not* guaranteed to be unique. This will
     /// return the first function in the module with the given name.
+    ///
+    /// The first lookup builds an index of all functions by name, which is
+    /// reused by later lookups until the functions are next mutated.
     pub fn by_name(&self, name: &str) -> Option<FunctionId> {
-        self.arena.iter().find_map(|(id, f)| {
-            if f.name.as_ref().map(|s| s.as_str()) == Some(name) {
-                Some(id)
-            } else {
-                None
-            }
+        self.names.get(name, || {
+            self.arena
+                .iter()
+                .filter_map(|(id, f)| Some((id, f.name.as_deref()?)))
         })
     }
 
+    /// Get the ID of the function at `index` in the binary this module was
+    /// parsed from.
+    ///
+    /// This maps the indices that crash reports and profiles of the original
+    /// binary refer to back to functions. Returns `None` if there was no such
+    /// function, or if it has been deleted since.
+    pub fn by_original_index(&self, index: u32) -> Option<FunctionId> {
+        let id = *self.original_ids.get(index as usize)?;
+        if self.arena.contains(id) {
+            Some(id)
+        } else {
+            None
+        }
+    }
+
+    /// Get the index that a function had in the binary this module was
+    /// parsed from.
+    ///
+    /// Returns `None` for functions that were added since.
+    pub fn original_index(&self, id: FunctionId) -> Option<u32> {
+        self.original_indices.get(&id).copied()
+    }
+
+    pub(crate) fn set_original_ids(&mut self, ids: &[FunctionId]) {
+        self.original_ids = ids.to_vec();
+        self.original_indices = ids
+            .iter()
+            .enumerate()
+            .map(|(index, id)| (*id, index as u32))
+            .collect();
+    }
+
     /// Removes a function from this module.
     ///
     /// It is up to you to ensure that any potential references to the deleted
     /// function are also removed, eg `call` expressions, exports, table
     /// elements, etc.
     pub fn delete(&mut self, id: FunctionId) {
+        self.names.invalidate();
+        self.invalid.remove(&id);
         self.arena.delete(id);
     }
 
+    /// Get the original body of `id`, if it failed to parse in best-effort
+    /// mode and hasn't been replaced since.
+    pub fn invalid_body(&self, id: FunctionId) -> Option<&InvalidBody> {
+        self.invalid.get(&id)
+    }
+
+    /// Iterate over the functions whose bodies failed to parse in best-effort
+    /// mode, along with their original bodies.
+    pub fn invalid_bodies(&self) -> impl Iterator<Item = (FunctionId, &InvalidBody)> {
+        self.invalid.iter().map(|(id, body)| (*id, body))
+    }
+
     /// Get a shared reference to this module's functions.
     pub fn iter(&self) -> impl Iterator<Item = &Function> {
         self.arena.iter().map(|(_, f)| f)
@@ -236,6 +368,7 @@ impl ModuleFunctions {
 
     /// Get a mutable reference to this module's functions.
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Function> {
+        self.names.invalidate();
         self.arena.iter_mut().map(|(_, f)| f)
     }
 
@@ -244,6 +377,7 @@ impl ModuleFunctions {
     /// Requires the `parallel` feature of this crate to be enabled.
     #[cfg(feature = "parallel")]
     pub fn par_iter_mut(&mut self) -> impl ParallelIterator<Item = &mut Function> {
+        self.names.invalidate();
         self.arena.par_iter_mut().map(|(_, f)| f)
     }
 
@@ -274,6 +408,25 @@ impl ModuleFunctions {
         })
     }
 
+    /// Get the local functions that are emitted before all others, in order.
+    ///
+    /// See `set_emit_order` for details.
+    pub fn emit_order(&self) -> &[FunctionId] {
+        &self.emit_order
+    }
+
+    /// Emit the given local functions first, in the given order.
+    ///
+    /// The listed functions get the smallest indices after the imported
+    /// functions, and are placed at the start of the code section. All other
+    /// local functions follow in the default order, largest first. Functions
+    /// that are imported or deleted are ignored.
+    ///
+    /// See `passes::reorder` for ordering functions by a profile.
+    pub fn set_emit_order(&mut self, order: impl IntoIterator<Item = FunctionId>) {
+        self.emit_order = order.into_iter().collect();
+    }
+
     pub(crate) fn emit_func_section(&self, cx: &mut EmitContext) {
         log::debug!("emit function section");
         let functions = used_local_functions(cx);
@@ -297,6 +450,139 @@ impl ModuleFunctions {
 }
 
 impl Module {
+    /// Add a local function of type `ty` whose body is already encoded as
+    /// wasm, rather than built up as IR.
+    ///
+    /// `body` is a function body as it appears in the code section, without
+    /// its size prefix: the local declarations, followed by the instructions
+    /// and their final `end`. It is parsed and validated against `ty` and the
+    /// declared locals, the same as the bodies of a parsed module.
+    ///
+    /// Indices in `body` refer to the items of this module in the order that
+    /// their collections' `iter` methods yield them; for example function
+    /// index `i` is `self.funcs.iter().nth(i)`. For a module that was parsed
+    /// and only appended to since, these are the indices of the original
+    /// binary.
+    pub fn add_local_func_from_bytes(&mut self, ty: TypeId, body: &[u8]) -> Result<FunctionId> {
+        let mut indices = self.iter_order_indices();
+        let id = self
+            .funcs
+            .arena
+            .alloc_with_id(|id| Function::new_uninitialized(id, ty));
+        let body = wasmparser::FunctionBody::new(0, body);
+        let func = self
+            .add_function_locals(
+                id,
+                self.funcs.arena.len() as u32 - 1,
+                ty,
+                &body,
+                &mut indices,
+            )
+            .and_then(|args| LocalFunction::parse(self, &indices, id, ty, args, body, None));
+        match func {
+            Ok(func) => {
+                self.funcs.names.invalidate();
+                self.funcs.arena[id].kind = FunctionKind::Local(func);
+                Ok(id)
+            }
+            Err(e) => {
+                self.funcs.delete(id);
+                Err(e)
+            }
+        }
+    }
+
+    /// Replace the body of the local function `func` with `body`, which is
+    /// already encoded as wasm.
+    ///
+    /// `body` is parsed and validated the same as with
+    /// `add_local_func_from_bytes`, and its indices refer to the items of this
+    /// module in the same order. This is how the bodies that failed to parse
+    /// in best-effort mode are repaired; once `func` has a new body, it's no
+    /// longer one of `ModuleFunctions::invalid_bodies`.
+    ///
+    /// On error, `func` is left as it was.
+    pub fn replace_func_body_from_bytes(&mut self, func: FunctionId, body: &[u8]) -> Result<()> {
+        let ty = match &self.funcs.get(func).kind {
+            FunctionKind::Local(local) => local.ty(),
+            _ => bail!("can only replace the bodies of local functions"),
+        };
+        let mut indices = self.iter_order_indices();
+        let index = self.funcs.iter().position(|f| f.id() == func).unwrap() as u32;
+        let body = wasmparser::FunctionBody::new(0, body);
+        let args = self.add_function_locals(func, index, ty, &body, &mut indices)?;
+        let local = LocalFunction::parse(self, &indices, func, ty, args, body, None)?;
+        self.funcs.invalid.remove(&func);
+        self.funcs.get_mut(func).kind = FunctionKind::Local(local);
+        Ok(())
+    }
+
+    /// Index the items of this module in the order that their collections'
+    /// `iter` methods yield them.
+    fn iter_order_indices(&self) -> IndicesToIds {
+        let mut indices = IndicesToIds::default();
+        for t in self.types.iter() {
+            indices.push_type(t.id());
+        }
+        for f in self.funcs.iter() {
+            indices.push_func(f.id());
+        }
+        for t in self.tables.iter() {
+            indices.push_table(t.id());
+        }
+        for m in self.memories.iter() {
+            indices.push_memory(m.id());
+        }
+        for g in self.globals.iter() {
+            indices.push_global(g.id());
+        }
+        for e in self.elements.iter() {
+            indices.push_element(e.id());
+        }
+        for d in self.data.iter() {
+            indices.push_data(d.id());
+        }
+        indices
+    }
+
+    /// Recompute the types of the `block`, `loop` and `if` sequences of the
+    /// local function `func` from the instructions in them.
+    ///
+    /// Passes that change what a sequence leaves on the stack, such as by
+    /// removing the instruction that computes its result, can call this
+    /// afterwards instead of updating the sequence's type themselves. Each
+    /// sequence keeps its parameters, and gets the results that its
+    /// instructions leave on the stack at its end. Sequences whose end is
+    /// unreachable, like those ending in a `br`, keep their results, and the
+    /// arms of an `if` get the same type. The function's body keeps the
+    /// function's results.
+    ///
+    /// Panics if `func` is an imported function.
+    pub fn refinalize(&mut self, func: FunctionId) {
+        let local = self.funcs.get(func).kind.unwrap_local();
+        let refinalized = crate::passes::type_stack::refinalize(self, local);
+        for (seq, (params, results)) in refinalized {
+            let ty = crate::ir::InstrSeqType::new(&mut self.types, &params, &results);
+            let local = self.funcs.get_mut(func).kind.unwrap_local_mut();
+            local.block_mut(seq).ty = ty;
+        }
+    }
+
+    /// Account for `count` more parsed functions, checking them against the
+    /// configured limits.
+    pub(crate) fn charge_parsed_funcs(&mut self, count: u32) -> Result<()> {
+        if let Some(max) = self.config.max_functions {
+            let total = self.funcs.arena.len() as u64 + u64::from(count);
+            if total > u64::from(max) {
+                return Err(ErrorKind::LimitExceeded).context(format!(
+                    "module has more than the maximum of {} functions",
+                    max
+                ));
+            }
+        }
+        self.charge_ir_memory(count as usize, mem::size_of::<Function>())
+    }
+
     /// Declare local functions after seeing the `function` section of a wasm
     /// executable.
     pub(crate) fn declare_local_functions(
@@ -305,15 +591,19 @@ impl Module {
         ids: &mut IndicesToIds,
     ) -> Result<()> {
         log::debug!("parse function section");
+        self.charge_parsed_funcs(section.get_count())?;
         for func in section {
             let ty = ids.get_type(func?)?;
             let id = self
                 .funcs
                 .arena
                 .alloc_with_id(|id| Function::new_uninitialized(id, ty));
-            let idx = ids.push_func(id);
-            if self.config.generate_synthetic_names_for_anonymous_items {
-                self.funcs.get_mut(id).name = Some(format!("f{}", idx));
+            let index = ids.push_func(id);
+            let name = self
+                .config
+                .synthetic_name(SyntheticName::Function { index });
+            if name.is_some() {
+                self.funcs.get_mut(id).name = name;
             }
         }
 
@@ -341,6 +631,7 @@ impl Module {
         // necessary and it's a bottleneck!
         let mut bodies = Vec::with_capacity(amt as usize);
         for (i, body) in section.into_iter().enumerate() {
+            CancellationToken::check(self.config.cancellation_token.as_ref())?;
             let body = body?;
             let index = (num_imports + i) as u32;
             let id = indices.get_func(index)?;
@@ -348,78 +639,162 @@ impl Module {
                 FunctionKind::Uninitialized(ty) => ty,
                 _ => unreachable!(),
             };
-
-            // First up, implicitly add locals for all function arguments. We also
-            // record these in the function itself for later processing.
-            let mut args = Vec::new();
-            let type_ = self.types.get(ty);
-            for ty in type_.params().iter() {
-                let local_id = self.locals.add(*ty);
-                let idx = indices.push_local(id, local_id);
-                args.push(local_id);
-                if self.config.generate_synthetic_names_for_anonymous_items {
-                    let name = format!("arg{}", idx);
-                    self.locals.get_mut(local_id).name = Some(name);
+            let size = body.get_binary_reader().bytes_remaining();
+            if let Some(max) = self.config.max_function_body_size {
+                if size > max as usize {
+                    return Err(ErrorKind::LimitExceeded).context(format!(
+                        "function {} has a body of {} bytes, more than the maximum of {}",
+                        index, size, max
+                    ));
                 }
             }
+            // At most one instruction is parsed from each byte of the body.
+            self.charge_ir_memory(size, mem::size_of::<(Instr, InstrLocId)>())?;
 
-            // Ensure that there exists a `Type` for the function's entry
-            // block. This is required because multi-value blocks reference a
-            // `Type`, however function entry's type is implicit in the
-            // encoding, and doesn't already exist in the `ModuleTypes`.
-            let results = type_.results().to_vec();
-            self.types.add_entry_ty(&results);
-
-            // WebAssembly local indices are 32 bits, so it's a validation error to
-            // have more than 2^32 locals. Sure enough there's a spec test for this!
-            let mut total = 0u32;
-            for local in body.get_locals_reader()? {
-                let (count, _) = local?;
-                total = match total.checked_add(count) {
-                    Some(n) => n,
-                    None => bail!("can't have more than 2^32 locals"),
-                };
-            }
-
-            // Now that we know we have a reasonable amount of locals, put them in
-            // our map.
-            for local in body.get_locals_reader()? {
-                let (count, ty) = local?;
-                let ty = ValType::parse(&ty)?;
-                for _ in 0..count {
-                    let local_id = self.locals.add(ty);
-                    let idx = indices.push_local(id, local_id);
-                    if self.config.generate_synthetic_names_for_anonymous_items {
-                        let name = format!("l{}", idx);
-                        self.locals.get_mut(local_id).name = Some(name);
-                    }
+            match self.add_function_locals(id, index, ty, &body, indices) {
+                Ok(args) => bodies.push((id, body, args, ty)),
+                Err(e) if self.config.best_effort_parse => {
+                    self.add_invalid_body(id, ty, body.get_binary_reader(), e)?
                 }
+                Err(e) => return Err(e),
             }
-
-            let body = body.get_operators_reader()?;
-            bodies.push((id, body, args, ty));
         }
 
         // Wasm modules can often have a lot of functions and this operation can
         // take some time, so parse all function bodies in parallel.
+        let cancel = self.config.cancellation_token.as_ref();
         let results = maybe_parallel!(bodies.(into_iter | into_par_iter))
             .map(|(id, body, args, ty)| {
-                (
-                    id,
-                    LocalFunction::parse(self, indices, id, ty, args, body, on_instr_pos),
-                )
+                // Hold onto the body's bytes in case it fails to parse.
+                let raw = body.get_binary_reader();
+                let func = CancellationToken::check(cancel).and_then(|()| {
+                    LocalFunction::parse(self, indices, id, ty, args, body, on_instr_pos)
+                });
+                (id, ty, raw, func)
             })
             .collect::<Vec<_>>();
+        // Don't mistake functions skipped after cancelling for invalid ones.
+        CancellationToken::check(cancel)?;
 
         // After all the function bodies are collected and finished push them
         // into our function arena.
-        for (id, func) in results {
-            let func = func?;
-            self.funcs.arena[id].kind = FunctionKind::Local(func);
+        for (id, ty, raw, func) in results {
+            match func {
+                Ok(func) => self.funcs.arena[id].kind = FunctionKind::Local(func),
+                Err(e) if self.config.best_effort_parse => self.add_invalid_body(id, ty, raw, e)?,
+                Err(e) => return Err(e),
+            }
         }
 
         Ok(())
     }
+
+    /// Record that the body of the function `id` of type `ty`, read by
+    /// `reader`, failed to parse with `error`, and give the function a body
+    /// that just traps in the IR instead.
+    fn add_invalid_body(
+        &mut self,
+        id: FunctionId,
+        ty: TypeId,
+        mut reader: wasmparser::BinaryReader,
+        error: anyhow::Error,
+    ) -> Result<()> {
+        log::debug!("keeping the invalid body of {:?}: {}", id, error);
+        let offset = reader.original_position();
+        let bytes = reader.read_bytes(reader.bytes_remaining())?.to_vec();
+
+        let args = self
+            .types
+            .params(ty)
+            .to_vec()
+            .into_iter()
+            .map(|ty| self.locals.add(ty))
+            .collect();
+        let results = self.types.results(ty).to_vec();
+        let mut builder = FunctionBuilder::without_entry(ty);
+        let entry_ty = self.types.add_entry_ty(&results);
+        let entry = builder.dangling_instr_seq(entry_ty).unreachable().id();
+        builder.entry = Some(entry);
+        self.funcs.arena[id].kind = FunctionKind::Local(LocalFunction::new(args, builder));
+
+        let error = format!("{:#}", error);
+        self.funcs.invalid.insert(
+            id,
+            InvalidBody {
+                bytes,
+                offset,
+                error,
+            },
+        );
+        Ok(())
+    }
+
+    /// Create the locals of the function `id` of type `ty`, at index `func`,
+    /// both for its arguments and those declared by its `body`, and record
+    /// their indices.
+    ///
+    /// Returns the locals of the arguments.
+    fn add_function_locals(
+        &mut self,
+        id: FunctionId,
+        func: u32,
+        ty: TypeId,
+        body: &wasmparser::FunctionBody,
+        indices: &mut IndicesToIds,
+    ) -> Result<Vec<LocalId>> {
+        // First up, implicitly add locals for all function arguments. We also
+        // record these in the function itself for later processing.
+        let mut args = Vec::new();
+        let type_ = self.types.get(ty);
+        for ty in type_.params().iter() {
+            let local_id = self.locals.add(*ty);
+            let index = indices.push_local(id, local_id);
+            args.push(local_id);
+            let name = self
+                .config
+                .synthetic_name(SyntheticName::Argument { func, index });
+            if name.is_some() {
+                self.locals.get_mut(local_id).name = name;
+            }
+        }
+
+        // Ensure that there exists a `Type` for the function's entry
+        // block. This is required because multi-value blocks reference a
+        // `Type`, however function entry's type is implicit in the
+        // encoding, and doesn't already exist in the `ModuleTypes`.
+        let results = type_.results().to_vec();
+        self.types.add_entry_ty(&results);
+
+        // WebAssembly local indices are 32 bits, so it's a validation error to
+        // have more than 2^32 locals. Sure enough there's a spec test for this!
+        let (locals, _) = local_function::read_locals(body, indices)?;
+        let mut total = 0u32;
+        for &(count, _) in locals.iter() {
+            total = match total.checked_add(count) {
+                Some(n) => n,
+                None => bail!("can't have more than 2^32 locals"),
+            };
+        }
+
+        self.charge_ir_memory(total as usize, mem::size_of::<Local>())?;
+
+        // Now that we know we have a reasonable amount of locals, put them in
+        // our map.
+        for (count, ty) in locals {
+            for _ in 0..count {
+                let local_id = self.locals.add(ty);
+                let index = indices.push_local(id, local_id);
+                let name = self
+                    .config
+                    .synthetic_name(SyntheticName::Local { func, index });
+                if name.is_some() {
+                    self.locals.get_mut(local_id).name = name;
+                }
+            }
+        }
+
+        Ok(args)
+    }
 }
 
 fn used_local_functions<'a>(cx: &mut EmitContext<'a>) -> Vec<(FunctionId, &'a LocalFunction, u64)> {
@@ -443,18 +818,29 @@ fn used_local_functions<'a>(cx: &mut EmitContext<'a>) -> Vec<(FunctionId, &'a Lo
     // longer to compile.
     functions.sort_by_key(|(id, _, size)| (cmp::Reverse(*size), *id));
 
+    // Functions with an explicit order come first, though.
+    let order = &cx.module.funcs.emit_order;
+    if !order.is_empty() {
+        let ranks = order
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(rank, id)| (*id, rank))
+            .collect::<HashMap<_, _>>();
+        functions.sort_by_key(|(id, _, _)| ranks.get(id).copied().unwrap_or(usize::MAX));
+    }
+
     functions
 }
 
 fn collect_non_default_code_offsets(
     code_transform: &mut Vec<(InstrLocId, usize)>,
     code_offset: usize,
-    map: Vec<(InstrLocId, usize)>,
+    map: &[EmittedInstr],
 ) {
-    for (src, dst) in map {
-        let dst = dst + code_offset;
-        if !src.is_default() {
-            code_transform.push((src, dst));
+    for instr in map {
+        if !instr.loc.is_default() {
+            code_transform.push((instr.loc, instr.pos + code_offset));
         }
     }
 }
@@ -470,20 +856,40 @@ impl Emit for ModuleFunctions {
         let mut cx = cx.start_section(Section::Code);
         cx.encoder.usize(functions.len());
 
-        let generate_map = cx.module.config.preserve_code_transform;
+        let preserve_code_transform = cx.module.config.preserve_code_transform;
+        let generate_map = preserve_code_transform || cx.address_map.is_some();
+        let canonicalize_memargs =
+            cx.module.config.canonicalize_memargs || cx.module.config.compression_friendly;
 
         // Functions can typically take awhile to serialize, so serialize
         // everything in parallel. Afterwards we'll actually place all the
         // functions together.
+        let cancel = cx.cancel;
         let bytes = maybe_parallel!(functions.(into_iter | into_par_iter))
             .map(|(id, func, _size)| {
                 log::debug!("emit function {:?} {:?}", id, cx.module.funcs.get(id).name);
                 let mut wasm = Vec::new();
                 let mut encoder = Encoder::new(&mut wasm);
                 let mut map = if generate_map { Some(Vec::new()) } else { None };
+                if CancellationToken::check(cancel).is_err() {
+                    return (wasm, id, Default::default(), Default::default(), map);
+                }
+
+                // Bodies that failed to parse are written as they were.
+                if let Some(invalid) = cx.module.funcs.invalid.get(&id) {
+                    wasm.extend_from_slice(&invalid.bytes);
+                    return (wasm, id, Default::default(), Default::default(), None);
+                }
 
-                let (used_locals, local_indices) = func.emit_locals(cx.module, &mut encoder);
-                func.emit_instructions(cx.indices, &local_indices, &mut encoder, map.as_mut());
+                let (used_locals, local_indices) =
+                    func.emit_locals(cx.module, cx.indices, &mut encoder);
+                func.emit_instructions(
+                    cx.indices,
+                    &local_indices,
+                    &mut encoder,
+                    map.as_mut(),
+                    canonicalize_memargs,
+                );
                 (wasm, id, used_locals, local_indices, map)
             })
             .collect::<Vec<_>>();
@@ -494,7 +900,12 @@ impl Emit for ModuleFunctions {
             let code_offset = cx.encoder.pos();
             cx.encoder.raw(&wasm);
             if let Some(map) = map {
-                collect_non_default_code_offsets(&mut cx.code_transform, code_offset, map);
+                if preserve_code_transform {
+                    collect_non_default_code_offsets(&mut cx.code_transform, code_offset, &map);
+                }
+                if let Some(address_map) = cx.address_map.as_mut() {
+                    address_map.add_function(id, code_offset..code_offset + wasm.len(), &map);
+                }
             }
             cx.indices.locals.insert(id, local_indices);
             cx.locals.insert(id, used_locals);
@@ -1,14 +1,18 @@
 //! Globals within a wasm module.
 use crate::emit::{Emit, EmitContext, Section};
+use crate::ir::Value;
+use crate::module::imports::Redirect;
+use crate::name_index::NameIndex;
 use crate::parse::IndicesToIds;
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
 use crate::{ImportId, InitExpr, Module, Result, ValType};
+use std::collections::hash_map::{Entry, HashMap};
 
 /// The id of a global.
 pub type GlobalId = Id<Global>;
 
 /// A wasm global.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Global {
     // NB: Not public so that it can't get out of sync with the arena this is
     // contained within.
@@ -22,12 +26,18 @@ pub struct Global {
 
     /// The kind of global this is
     pub kind: GlobalKind,
+
+    /// An optional name for debugging.
+    ///
+    /// This is not parsed from or emitted into the "name" section, but is
+    /// useful for finding globals with `ModuleGlobals::by_name`.
+    pub name: Option<String>,
 }
 
 impl Tombstone for Global {}
 
 /// The different kinds of globals a wasm module can have
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum GlobalKind {
     /// An imported global without a known initializer
     Import(ImportId),
@@ -50,31 +60,38 @@ impl Emit for Global {
 }
 
 /// The set of globals in each function in this module.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct ModuleGlobals {
     /// The arena where the globals are stored.
     arena: TombstoneArena<Global>,
+
+    /// An index of the globals by name, for `by_name`.
+    names: NameIndex<Global>,
 }
 
 impl ModuleGlobals {
     /// Adds a new imported global to this list.
     pub fn add_import(&mut self, ty: ValType, mutable: bool, import_id: ImportId) -> GlobalId {
+        self.names.invalidate();
         self.arena.alloc_with_id(|id| Global {
             id,
             ty,
             mutable,
             kind: GlobalKind::Import(import_id),
+            name: None,
         })
     }
 
     /// Construct a new global, that does not originate from any of the input
     /// wasm globals.
     pub fn add_local(&mut self, ty: ValType, mutable: bool, init: InitExpr) -> GlobalId {
+        self.names.invalidate();
         self.arena.alloc_with_id(|id| Global {
             id,
             ty,
             mutable,
             kind: GlobalKind::Local(init),
+            name: None,
         })
     }
 
@@ -83,16 +100,36 @@ impl ModuleGlobals {
         &self.arena[id]
     }
 
+    /// Get the global with the given id, if it is one of this module's and
+    /// hasn't been deleted.
+    pub(crate) fn try_get(&self, id: GlobalId) -> Option<&Global> {
+        self.arena.get(id)
+    }
+
     /// Gets a reference to a memory given its id
     pub fn get_mut(&mut self, id: GlobalId) -> &mut Global {
+        self.names.invalidate();
         &mut self.arena[id]
     }
 
+    /// Get a global ID by its name.
+    ///
+    /// Note that global names are *not* guaranteed to be unique. This will
+    /// return the first global in the module with the given name.
+    pub fn by_name(&self, name: &str) -> Option<GlobalId> {
+        self.names.get(name, || {
+            self.arena
+                .iter()
+                .filter_map(|(id, g)| Some((id, g.name.as_deref()?)))
+        })
+    }
+
     /// Removes a global from this module.
     ///
     /// It is up to you to ensure that any potential references to the deleted
     /// global are also removed, eg `get_global` expressions.
     pub fn delete(&mut self, id: GlobalId) {
+        self.names.invalidate();
         self.arena.delete(id);
     }
 
@@ -121,6 +158,64 @@ impl Module {
         }
         Ok(())
     }
+
+    /// Merge identical immutable local globals.
+    ///
+    /// Immutable local globals with the same type and initializer are
+    /// identical, where floats are compared by their bits, and initializers
+    /// that get identical globals are identical too. All uses of an identical
+    /// global are redirected to the first one, and the others are deleted.
+    /// Returns how many globals were deleted.
+    pub fn dedup_globals(&mut self) -> usize {
+        let mut firsts = HashMap::new();
+        let mut redirect = Redirect::default();
+        let mut duplicates = Vec::new();
+        for global in self.globals.iter() {
+            let init = match global.kind {
+                GlobalKind::Local(init) if !global.mutable => init,
+                _ => continue,
+            };
+            let init = match init {
+                InitExpr::Value(Value::I32(n)) => InitKey::I32(n),
+                InitExpr::Value(Value::I64(n)) => InitKey::I64(n),
+                InitExpr::Value(Value::F32(n)) => InitKey::F32(n.to_bits()),
+                InitExpr::Value(Value::F64(n)) => InitKey::F64(n.to_bits()),
+                InitExpr::Value(Value::V128(n)) => InitKey::V128(n),
+                InitExpr::Global(id) => {
+                    InitKey::Global(redirect.globals.get(&id).copied().unwrap_or(id))
+                }
+            };
+            match firsts.entry((global.ty, init)) {
+                Entry::Vacant(entry) => {
+                    entry.insert(global.id());
+                }
+                Entry::Occupied(entry) => {
+                    redirect.globals.insert(global.id(), *entry.get());
+                    duplicates.push(global.id());
+                }
+            }
+        }
+        if duplicates.is_empty() {
+            return 0;
+        }
+
+        redirect.run(self);
+        for id in duplicates.iter() {
+            self.globals.delete(*id);
+        }
+        duplicates.len()
+    }
+}
+
+/// What identical global initializers have in common.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum InitKey {
+    I32(i32),
+    I64(i64),
+    F32(u32),
+    F64(u64),
+    V128(u128),
+    Global(GlobalId),
 }
 
 impl Emit for ModuleGlobals {
@@ -1,11 +1,15 @@
 //! A wasm module's imports.
 
 use crate::emit::{Emit, EmitContext, Section};
+use crate::ir::{dfs_pre_order_mut, VisitorMut};
 use crate::parse::IndicesToIds;
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
+use crate::{ActiveDataLocation, DataKind, ExportItem, GlobalKind, InitExpr};
 use crate::{FunctionId, FunctionTable, GlobalId, MemoryId, Result, TableId};
 use crate::{Module, TableKind, TypeId, ValType};
 use anyhow::bail;
+use std::cmp::Ordering;
+use std::collections::hash_map::{Entry, HashMap};
 
 /// The id of an import.
 pub type ImportId = Id<Import>;
@@ -50,9 +54,10 @@ pub enum ImportKind {
 }
 
 /// The set of imports in a module.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct ModuleImports {
     arena: TombstoneArena<Import>,
+    emit_order: Vec<ImportId>,
 }
 
 impl ModuleImports {
@@ -61,6 +66,12 @@ impl ModuleImports {
         &self.arena[id]
     }
 
+    /// Get the import with the given id, if it is one of this module's and
+    /// hasn't been deleted.
+    pub(crate) fn try_get(&self, id: ImportId) -> Option<&Import> {
+        self.arena.get(id)
+    }
+
     /// Gets a reference to an import given its id
     pub fn get_mut(&mut self, id: ImportId) -> &mut Import {
         &mut self.arena[id]
@@ -103,6 +114,85 @@ impl ModuleImports {
 
         Some(import?.0)
     }
+
+    /// Rename the imports from module `old` to be imported from module `new`
+    /// instead, eg when retargeting `wasi_unstable` to
+    /// `wasi_snapshot_preview1`.
+    ///
+    /// Returns how many imports were renamed.
+    pub fn remap_module(&mut self, old: &str, new: &str) -> usize {
+        self.rename(|module, name| {
+            if module == old {
+                Some((new.to_string(), name.to_string()))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Rename imports in bulk.
+    ///
+    /// `rename` is called with the module and name of each import, and
+    /// returns its new module and name, or `None` to leave it as is.
+    ///
+    /// Returns how many imports were renamed.
+    pub fn rename(
+        &mut self,
+        mut rename: impl FnMut(&str, &str) -> Option<(String, String)>,
+    ) -> usize {
+        let mut renamed = 0;
+        for import in self.iter_mut() {
+            if let Some((module, name)) = rename(&import.module, &import.name) {
+                import.module = module;
+                import.name = name;
+                renamed += 1;
+            }
+        }
+        renamed
+    }
+
+    /// Get the imports that are emitted before all others, in order.
+    ///
+    /// See `set_emit_order` for details.
+    pub fn emit_order(&self) -> &[ImportId] {
+        &self.emit_order
+    }
+
+    /// Emit the given imports first, in the given order.
+    ///
+    /// The listed imports get the smallest indices in their index spaces. All
+    /// other imports follow in the order they were added. Imports that are
+    /// deleted are ignored.
+    pub fn set_emit_order(&mut self, order: impl IntoIterator<Item = ImportId>) {
+        self.emit_order = order.into_iter().collect();
+    }
+
+    /// Sort the imports, by setting their emit order.
+    ///
+    /// The sort is stable, starting from the current emit order, so that for
+    /// example sorting by `module` groups imports from the same module while
+    /// keeping their order within the group.
+    pub fn sort_by(&mut self, mut compare: impl FnMut(&Import, &Import) -> Ordering) {
+        let mut order = self.in_emit_order();
+        order.sort_by(|a, b| compare(a, b));
+        self.emit_order = order.iter().map(|import| import.id).collect();
+    }
+
+    /// Get the imports in the order they are emitted.
+    fn in_emit_order(&self) -> Vec<&Import> {
+        let mut imports = self.iter().collect::<Vec<_>>();
+        if !self.emit_order.is_empty() {
+            let ranks = self
+                .emit_order
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(rank, id)| (*id, rank))
+                .collect::<HashMap<_, _>>();
+            imports.sort_by_key(|import| ranks.get(&import.id).copied().unwrap_or(usize::MAX));
+        }
+        imports
+    }
 }
 
 impl Module {
@@ -117,6 +207,7 @@ impl Module {
             let entry = entry?;
             match entry.ty {
                 wasmparser::ImportSectionEntryType::Function(idx) => {
+                    self.charge_parsed_funcs(1)?;
                     let ty = ids.get_type(idx)?;
                     let id = self.add_import_func(entry.module, entry.field, ty);
                     ids.push_func(id.0);
@@ -216,20 +307,204 @@ impl Module {
         self.imports.add(module, name, global);
         (global, import)
     }
+
+    /// Merge identical function and global imports.
+    ///
+    /// Imports with the same module, name and type are identical. All uses of
+    /// an identical import are redirected to the first one, and the others
+    /// are deleted along with their function or global. Returns how many
+    /// imports were deleted.
+    pub fn dedup_imports(&mut self) -> usize {
+        let mut firsts = HashMap::new();
+        let mut redirect = Redirect::default();
+        let mut duplicates = Vec::new();
+        for import in self.imports.iter() {
+            let (key, first) = match import.kind {
+                ImportKind::Function(id) => {
+                    let ty = self.funcs.get(id).ty();
+                    (DedupKey::Function(ty), ImportKind::Function(id))
+                }
+                ImportKind::Global(id) => {
+                    let global = self.globals.get(id);
+                    let key = DedupKey::Global(global.ty, global.mutable);
+                    (key, ImportKind::Global(id))
+                }
+                ImportKind::Table(_) | ImportKind::Memory(_) => continue,
+            };
+            let key = (import.module.as_str(), import.name.as_str(), key);
+            match firsts.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert(first);
+                }
+                Entry::Occupied(entry) => {
+                    match (&import.kind, entry.get()) {
+                        (ImportKind::Function(from), ImportKind::Function(to)) => {
+                            redirect.funcs.insert(*from, *to);
+                        }
+                        (ImportKind::Global(from), ImportKind::Global(to)) => {
+                            redirect.globals.insert(*from, *to);
+                        }
+                        _ => unreachable!(),
+                    }
+                    duplicates.push(import.id());
+                }
+            }
+        }
+        if duplicates.is_empty() {
+            return 0;
+        }
+
+        redirect.run(self);
+        for id in duplicates.iter() {
+            match self.imports.get(*id).kind {
+                ImportKind::Function(id) => self.funcs.delete(id),
+                ImportKind::Global(id) => self.globals.delete(id),
+                ImportKind::Table(_) | ImportKind::Memory(_) => unreachable!(),
+            }
+            self.imports.delete(*id);
+        }
+        duplicates.len()
+    }
+
+    /// Redirect all uses of the function imported by `import` to `func`, and
+    /// delete the import along with the imported function.
+    pub(crate) fn replace_imported_func(&mut self, import: ImportId, func: FunctionId) {
+        let imported = match self.imports.get(import).kind {
+            ImportKind::Function(id) => id,
+            _ => panic!("not a function import"),
+        };
+        let mut redirect = Redirect::default();
+        redirect.funcs.insert(imported, func);
+        redirect.run(self);
+        self.funcs.delete(imported);
+        self.imports.delete(import);
+    }
+}
+
+/// What, besides their module and name, identical imports have in common.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum DedupKey {
+    Function(TypeId),
+    Global(ValType, bool),
+}
+
+/// Redirects all uses of some functions, globals, memories and tables to
+/// others.
+#[derive(Default)]
+pub(crate) struct Redirect {
+    pub(crate) funcs: HashMap<FunctionId, FunctionId>,
+    pub(crate) globals: HashMap<GlobalId, GlobalId>,
+    pub(crate) memories: HashMap<MemoryId, MemoryId>,
+    pub(crate) tables: HashMap<TableId, TableId>,
+}
+
+impl Redirect {
+    pub(crate) fn run(&mut self, module: &mut Module) {
+        for (_, func) in module.funcs.iter_local_mut() {
+            let entry = func.entry_block();
+            dfs_pre_order_mut(self, func, entry);
+        }
+
+        let globals = module.globals.iter().map(|g| g.id()).collect::<Vec<_>>();
+        for id in globals {
+            if let GlobalKind::Local(InitExpr::Global(global)) =
+                &mut module.globals.get_mut(id).kind
+            {
+                self.visit_global_id_mut(global);
+            }
+        }
+
+        let data = module.data.iter().map(|d| d.id()).collect::<Vec<_>>();
+        for id in data {
+            if let DataKind::Active(active) = &mut module.data.get_mut(id).kind {
+                if let ActiveDataLocation::Relative(global) = &mut active.location {
+                    self.visit_global_id_mut(global);
+                }
+                if let Some(to) = self.memories.get(&active.memory) {
+                    module
+                        .memories
+                        .get_mut(active.memory)
+                        .data_segments
+                        .remove(&id);
+                    module.memories.get_mut(*to).data_segments.insert(id);
+                    active.memory = *to;
+                }
+            }
+        }
+
+        for table in module.tables.iter_mut() {
+            if let TableKind::Function(table) = &mut table.kind {
+                for func in table.elements.iter_mut().flatten() {
+                    self.visit_function_id_mut(func);
+                }
+                for (global, elements) in table.relative_elements.iter_mut() {
+                    self.visit_global_id_mut(global);
+                    for func in elements.iter_mut().flatten() {
+                        self.visit_function_id_mut(func);
+                    }
+                }
+            }
+        }
+
+        for element in module.elements.iter_mut() {
+            for func in element.members.iter_mut().flatten() {
+                self.visit_function_id_mut(func);
+            }
+        }
+
+        for export in module.exports.iter_mut() {
+            match &mut export.item {
+                ExportItem::Function(func) => self.visit_function_id_mut(func),
+                ExportItem::Global(global) => self.visit_global_id_mut(global),
+                ExportItem::Memory(memory) => self.visit_memory_id_mut(memory),
+                ExportItem::Table(table) => self.visit_table_id_mut(table),
+            }
+        }
+
+        if let Some(start) = &mut module.start {
+            self.visit_function_id_mut(start);
+        }
+    }
+}
+
+impl VisitorMut for Redirect {
+    fn visit_function_id_mut(&mut self, func: &mut FunctionId) {
+        if let Some(to) = self.funcs.get(func) {
+            *func = *to;
+        }
+    }
+
+    fn visit_global_id_mut(&mut self, global: &mut GlobalId) {
+        if let Some(to) = self.globals.get(global) {
+            *global = *to;
+        }
+    }
+
+    fn visit_memory_id_mut(&mut self, memory: &mut MemoryId) {
+        if let Some(to) = self.memories.get(memory) {
+            *memory = *to;
+        }
+    }
+
+    fn visit_table_id_mut(&mut self, table: &mut TableId) {
+        if let Some(to) = self.tables.get(table) {
+            *table = *to;
+        }
+    }
 }
 
 impl Emit for ModuleImports {
     fn emit(&self, cx: &mut EmitContext) {
         log::debug!("emit import section");
-        let count = self.iter().count();
-        if count == 0 {
+        let imports = self.in_emit_order();
+        if imports.is_empty() {
             return;
         }
 
         let mut cx = cx.start_section(Section::Import);
-        cx.encoder.usize(count);
+        cx.encoder.usize(imports.len());
 
-        for import in self.iter() {
+        for import in imports {
             cx.encoder.str(&import.module);
             cx.encoder.str(&import.name);
             match import.kind {
@@ -0,0 +1,165 @@
+//! Journals of the structural changes made to a module.
+//!
+//! Passes add, remove and rename items by mutating a module's pieces
+//! directly, so rather than hooking every mutation, a journal remembers the
+//! items a module had when it was started, and compares them with the items
+//! the module has when changes are queried. This also catches the changes
+//! that third party passes make.
+
+use crate::passes::retained::Item;
+use crate::{ExportId, Module};
+use std::collections::{HashMap, HashSet};
+
+/// A structural change to a module, found by `Module::changes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// The item was added.
+    Added(Item),
+    /// The item was removed.
+    Removed(Item),
+    /// The name of the item changed.
+    Renamed {
+        /// The renamed item.
+        item: Item,
+        /// The item's name when the journal was started.
+        from: Option<String>,
+        /// The item's current name.
+        to: Option<String>,
+    },
+    /// An export with this name was added.
+    Exported(String),
+    /// The export with this name was removed.
+    Unexported(String),
+}
+
+/// The items of a module when its journal was started.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Journal {
+    items: Vec<(Item, Option<String>)>,
+    exports: Vec<(ExportId, String)>,
+}
+
+impl Journal {
+    fn new(module: &Module) -> Journal {
+        Journal {
+            items: items(module),
+            exports: exports(module),
+        }
+    }
+
+    fn changes(&self, module: &Module) -> Vec<Change> {
+        let mut changes = Vec::new();
+
+        let current = items(module);
+        let before = self.items.iter().cloned().collect::<HashMap<_, _>>();
+        let after = current
+            .iter()
+            .map(|(item, _)| *item)
+            .collect::<HashSet<_>>();
+        for (item, _) in self.items.iter() {
+            if !after.contains(item) {
+                changes.push(Change::Removed(*item));
+            }
+        }
+        for (item, name) in current {
+            match before.get(&item) {
+                None => changes.push(Change::Added(item)),
+                Some(from) if *from != name => changes.push(Change::Renamed {
+                    item,
+                    from: from.clone(),
+                    to: name,
+                }),
+                Some(_) => {}
+            }
+        }
+
+        let current = exports(module);
+        let before = self.exports.iter().cloned().collect::<HashSet<_>>();
+        let after = current.iter().cloned().collect::<HashSet<_>>();
+        for export in self.exports.iter() {
+            if !after.contains(export) {
+                changes.push(Change::Unexported(export.1.clone()));
+            }
+        }
+        for export in current {
+            if !before.contains(&export) {
+                changes.push(Change::Exported(export.1));
+            }
+        }
+        changes
+    }
+}
+
+fn items(module: &Module) -> Vec<(Item, Option<String>)> {
+    let mut items = Vec::new();
+    for ty in module.types.iter() {
+        if !ty.is_for_function_entry() {
+            items.push((Item::Type(ty.id()), ty.name.clone()));
+        }
+    }
+    for func in module.funcs.iter() {
+        items.push((Item::Function(func.id()), func.name.clone()));
+    }
+    for table in module.tables.iter() {
+        items.push((Item::Table(table.id()), None));
+    }
+    for memory in module.memories.iter() {
+        items.push((Item::Memory(memory.id()), None));
+    }
+    for global in module.globals.iter() {
+        items.push((Item::Global(global.id()), global.name.clone()));
+    }
+    for data in module.data.iter() {
+        items.push((Item::Data(data.id()), None));
+    }
+    for element in module.elements.iter() {
+        items.push((Item::Element(element.id()), None));
+    }
+    #[cfg(feature = "unstable")]
+    for tag in module.tags.iter() {
+        items.push((Item::Tag(tag.id()), None));
+    }
+    items
+}
+
+fn exports(module: &Module) -> Vec<(ExportId, String)> {
+    module
+        .exports
+        .iter()
+        .map(|export| (export.id(), export.name.clone()))
+        .collect()
+}
+
+impl Module {
+    /// Start journaling the structural changes made to this module.
+    ///
+    /// This remembers the module's current items, and `Module::changes`
+    /// reports how they differ from the items that the module has at that
+    /// point. Starting the journal again forgets the earlier items.
+    pub fn start_journal(&mut self) {
+        self.journal = Some(Journal::new(self));
+    }
+
+    /// Stop journaling the structural changes made to this module, returning
+    /// the changes since the journal was started.
+    ///
+    /// Returns `None` if the journal wasn't started.
+    pub fn stop_journal(&mut self) -> Option<Vec<Change>> {
+        let changes = self.changes();
+        self.journal = None;
+        changes
+    }
+
+    /// Get the structural changes made to this module since the journal was
+    /// started with `Module::start_journal`.
+    ///
+    /// Items that were removed come first, followed by the items that were
+    /// added or renamed, in the order the module has them, followed by the
+    /// exports that were removed and added. An export whose name changed is
+    /// reported as removed under its old name, and added under the new one.
+    ///
+    /// Returns `None` if the journal wasn't started.
+    pub fn changes(&self) -> Option<Vec<Change>> {
+        Some(self.journal.as_ref()?.changes(self))
+    }
+}
@@ -0,0 +1,153 @@
+//! Where sections were in the binary a module was parsed from.
+
+use crate::error::Result;
+use crate::Module;
+use anyhow::bail;
+use std::ops::Range;
+use wasmparser::BinaryReader;
+
+/// A section of the binary a module was parsed from, found in
+/// `Module::sections_layout`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionLayout {
+    /// The section's name.
+    ///
+    /// This is the name of a custom section, and otherwise the name that the
+    /// spec gives the section, like "type", "code" or "datacount".
+    pub name: String,
+
+    /// Whether this is a custom section.
+    pub custom: bool,
+
+    /// The byte range of the section's payload in the binary.
+    ///
+    /// The payload starts after the section's id and size, and for custom
+    /// sections also after its name.
+    pub range: Range<usize>,
+}
+
+impl SectionLayout {
+    pub(crate) fn new(section: &BinarySection) -> SectionLayout {
+        use SectionCode::*;
+        let name = match section.code {
+            Custom(name) => name,
+            Type => "type",
+            Import => "import",
+            Function => "function",
+            Table => "table",
+            Memory => "memory",
+            Global => "global",
+            Export => "export",
+            Start => "start",
+            Element => "element",
+            Code => "code",
+            Data => "data",
+            DataCount => "datacount",
+            #[cfg(feature = "unstable")]
+            Tag => "tag",
+        };
+        SectionLayout {
+            name: name.to_string(),
+            custom: matches!(section.code, Custom(_)),
+            range: section.offset..section.offset + section.data.len(),
+        }
+    }
+}
+
+/// A section of a wasm binary, as read by `BinarySection::read`.
+///
+/// Sections are read by hand rather than with `wasmparser::ModuleReader`, which
+/// rejects the sections of the proposals behind the `unstable` feature.
+pub(crate) struct BinarySection<'a> {
+    pub(crate) code: SectionCode<'a>,
+    /// The section's payload, after the name of custom sections.
+    pub(crate) data: &'a [u8],
+    /// The offset of `data` in the binary.
+    pub(crate) offset: usize,
+}
+
+/// The kind of a `BinarySection`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SectionCode<'a> {
+    Custom(&'a str),
+    Type,
+    Import,
+    Function,
+    Table,
+    Memory,
+    Global,
+    Export,
+    Start,
+    Element,
+    Code,
+    Data,
+    DataCount,
+    #[cfg(feature = "unstable")]
+    Tag,
+}
+
+impl<'a> BinarySection<'a> {
+    /// Read the next section with `reader`.
+    pub(crate) fn read(reader: &mut BinaryReader<'a>) -> Result<BinarySection<'a>> {
+        let id = reader.read_u8()?;
+        let len = reader.read_var_u32()? as usize;
+        let offset = reader.original_position();
+        let data = reader.read_bytes(len)?;
+        let code = match id {
+            0 => {
+                let mut payload = BinaryReader::new_with_offset(data, offset);
+                let name = payload.read_string()?;
+                let start = payload.original_position();
+                return Ok(BinarySection {
+                    code: SectionCode::Custom(name),
+                    data: &data[start - offset..],
+                    offset: start,
+                });
+            }
+            1 => SectionCode::Type,
+            2 => SectionCode::Import,
+            3 => SectionCode::Function,
+            4 => SectionCode::Table,
+            5 => SectionCode::Memory,
+            6 => SectionCode::Global,
+            7 => SectionCode::Export,
+            8 => SectionCode::Start,
+            9 => SectionCode::Element,
+            10 => SectionCode::Code,
+            11 => SectionCode::Data,
+            12 => SectionCode::DataCount,
+            #[cfg(feature = "unstable")]
+            13 => SectionCode::Tag,
+            _ => bail!("invalid section code {}", id),
+        };
+        Ok(BinarySection { code, data, offset })
+    }
+
+    /// Get a reader of this section's payload.
+    pub(crate) fn reader(&self) -> BinaryReader<'a> {
+        BinaryReader::new_with_offset(self.data, self.offset)
+    }
+
+    /// Read the single index that is the payload of start and data count
+    /// sections.
+    pub(crate) fn index(&self) -> Result<u32> {
+        let mut reader = self.reader();
+        let index = reader.read_var_u32()?;
+        if !reader.eof() {
+            bail!("unexpected data at the end of the section");
+        }
+        Ok(index)
+    }
+}
+
+impl Module {
+    /// Get the sections of the binary this module was parsed from, in the
+    /// order they appeared in.
+    ///
+    /// This lets items be correlated with the offsets that other tools, like
+    /// disassemblers, fuzzers or crash reporters, give for the original
+    /// binary. Modules that weren't parsed from a binary have no sections.
+    pub fn sections_layout(&self) -> &[SectionLayout] {
+        &self.sections_layout
+    }
+}
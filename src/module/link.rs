@@ -0,0 +1,431 @@
+//! Linking modules together through their imports and exports.
+
+use crate::ir::{dfs_pre_order_mut, Local, VisitorMut};
+use crate::map::IdHashMap;
+use crate::module::imports::Redirect;
+use crate::ty::Type;
+use crate::{ActiveData, Data, DataId, DataKind, Element, ElementId, ErrorKind, ExportId};
+use crate::{ActiveDataLocation, ModuleLocals, Result, Table, TableId, TableKind, TypeId};
+use crate::{ExportItem, FunctionBuilder, FunctionId, FunctionKind, Global, GlobalId, GlobalKind};
+use crate::{Import, ImportId, ImportKind, InitExpr, LocalId, Memory, MemoryId, Module};
+use anyhow::{bail, Context};
+
+/// How the imports of a module from another module resolve to that other
+/// module's exports.
+///
+/// A resolution is computed by `Module::resolve_imports`, and connects the two
+/// modules without changing either of them, so that they can still be emitted
+/// separately. `Module::link` merges them into one module instead.
+#[derive(Clone, Debug, Default)]
+pub struct Resolution {
+    exports: Vec<(ImportId, ExportId)>,
+}
+
+impl Resolution {
+    /// Get the export that `import` resolves to, if it is resolved.
+    pub fn get(&self, import: ImportId) -> Option<ExportId> {
+        self.exports
+            .iter()
+            .find(|(i, _)| *i == import)
+            .map(|(_, export)| *export)
+    }
+
+    /// Iterate over the resolved imports, along with the exports that they
+    /// resolve to.
+    pub fn iter(&self) -> impl Iterator<Item = (ImportId, ExportId)> + '_ {
+        self.exports.iter().copied()
+    }
+}
+
+impl Module {
+    /// Resolve this module's imports from the module `name` to the exports of
+    /// `provider`.
+    ///
+    /// Each import from `name` must resolve to the export of `provider` with
+    /// the same name, which must be of the same kind and of a compatible type:
+    /// functions and globals must have the same type, and memories and tables
+    /// must be at least as large as imported, and have a maximum size if the
+    /// import does, which is no larger than the imported one. Imports from
+    /// other modules are ignored.
+    pub fn resolve_imports(&self, name: &str, provider: &Module) -> Result<Resolution> {
+        let mut resolution = Resolution::default();
+        for import in self.imports.iter().filter(|i| i.module == name) {
+            let export = provider
+                .exports
+                .by_name(&import.name)
+                .with_context(|| format!("`{}` has no export `{}`", name, import.name))?;
+            self.check_import(import, provider, provider.exports.get(export).item)
+                .with_context(|| {
+                    format!("failed to resolve import `{}`.`{}`", name, import.name)
+                })?;
+            resolution.exports.push((import.id(), export));
+        }
+        Ok(resolution)
+    }
+
+    fn check_import(&self, import: &Import, provider: &Module, item: ExportItem) -> Result<()> {
+        match (import.kind.clone(), item) {
+            (ImportKind::Function(imported), ExportItem::Function(exported)) => {
+                let imported = self.types.params_results(self.funcs.get(imported).ty());
+                let exported = provider
+                    .types
+                    .params_results(provider.funcs.get(exported).ty());
+                if imported != exported {
+                    bail!("the exported function has a different type");
+                }
+            }
+            (ImportKind::Global(imported), ExportItem::Global(exported)) => {
+                let imported = self.globals.get(imported);
+                let exported = provider.globals.get(exported);
+                if imported.ty != exported.ty || imported.mutable != exported.mutable {
+                    bail!("the exported global has a different type");
+                }
+            }
+            (ImportKind::Memory(imported), ExportItem::Memory(exported)) => {
+                let imported = self.memories.get(imported);
+                let exported = provider.memories.get(exported);
+                if imported.shared != exported.shared {
+                    bail!("the exported memory has a different type");
+                }
+                check_limits(
+                    (imported.initial, imported.maximum),
+                    (exported.initial, exported.maximum),
+                )?;
+            }
+            (ImportKind::Table(imported), ExportItem::Table(exported)) => {
+                let imported = self.tables.get(imported);
+                let exported = provider.tables.get(exported);
+                let same_kind = matches!(
+                    (&imported.kind, &exported.kind),
+                    (TableKind::Function(_), TableKind::Function(_))
+                        | (TableKind::Anyref(_), TableKind::Anyref(_))
+                );
+                if !same_kind || imported.index_type != exported.index_type {
+                    bail!("the exported table has a different type");
+                }
+                check_limits(
+                    (imported.initial, imported.maximum),
+                    (exported.initial, exported.maximum),
+                )?;
+            }
+            _ => bail!("the export is of a different kind"),
+        }
+        Ok(())
+    }
+
+    /// Link `provider` into this module, defining this module's imports from
+    /// the module `name` with `provider`'s exports.
+    ///
+    /// The imports are resolved as by `resolve_imports`, and then everything
+    /// in `provider` is copied into this module: its types, functions,
+    /// globals, memories, tables, data and element segments, and its imports,
+    /// which become imports of this module. The resolved imports are replaced
+    /// by the copies of the exports they resolve to. `provider`'s exports and
+    /// custom sections aren't copied. If `provider` has a start function, it
+    /// runs before this module's own, like it would if `provider` was
+    /// instantiated first.
+    ///
+    /// Everything in `provider` is copied, whether this module uses it or not,
+    /// so run `passes::gc` afterwards to remove what isn't used.
+    pub fn link(&mut self, name: &str, provider: &Module) -> Result<()> {
+        let resolution = self.resolve_imports(name, provider)?;
+        let copied = Copied::run(self, provider)?;
+
+        let mut redirect = Redirect::default();
+        for (import, export) in resolution.iter() {
+            match (
+                self.imports.get(import).kind.clone(),
+                provider.exports.get(export).item,
+            ) {
+                (ImportKind::Function(from), ExportItem::Function(to)) => {
+                    redirect.funcs.insert(from, copied.funcs[&to]);
+                }
+                (ImportKind::Global(from), ExportItem::Global(to)) => {
+                    redirect.globals.insert(from, copied.globals[&to]);
+                }
+                (ImportKind::Memory(from), ExportItem::Memory(to)) => {
+                    redirect.memories.insert(from, copied.memories[&to]);
+                }
+                (ImportKind::Table(from), ExportItem::Table(to)) => {
+                    redirect.tables.insert(from, copied.tables[&to]);
+                }
+                _ => unreachable!(),
+            }
+        }
+        redirect.run(self);
+        for (import, _) in resolution.iter() {
+            match self.imports.get(import).kind.clone() {
+                ImportKind::Function(id) => self.funcs.delete(id),
+                ImportKind::Global(id) => self.globals.delete(id),
+                ImportKind::Memory(id) => self.memories.delete(id),
+                ImportKind::Table(id) => self.tables.delete(id),
+            }
+            self.imports.delete(import);
+        }
+
+        if let Some(start) = provider.start {
+            let start = copied.funcs[&start];
+            self.start = Some(match self.start {
+                Some(own) => {
+                    let mut builder = FunctionBuilder::new(&mut self.types, &[], &[]);
+                    builder.func_body().call(start).call(own);
+                    builder.finish(vec![], &mut self.funcs)
+                }
+                None => start,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Check that a memory or table with the `provided` limits can be imported
+/// with the `imported` limits.
+fn check_limits(imported: (u32, Option<u32>), provided: (u32, Option<u32>)) -> Result<()> {
+    if provided.0 < imported.0 {
+        bail!("the export is smaller than imported");
+    }
+    match (imported.1, provided.1) {
+        (None, _) => Ok(()),
+        (Some(imported), Some(provided)) if provided <= imported => Ok(()),
+        _ => bail!("the export can grow larger than imported"),
+    }
+}
+
+/// The ids of the copies of another module's items in a module.
+#[derive(Default)]
+struct Copied {
+    types: IdHashMap<Type, TypeId>,
+    funcs: IdHashMap<crate::Function, FunctionId>,
+    globals: IdHashMap<Global, GlobalId>,
+    memories: IdHashMap<Memory, MemoryId>,
+    tables: IdHashMap<Table, TableId>,
+    data: IdHashMap<Data, DataId>,
+    elements: IdHashMap<Element, ElementId>,
+}
+
+impl Copied {
+    /// Copy everything in `provider` into `module`, except for its exports,
+    /// start function and custom sections.
+    fn run(module: &mut Module, provider: &Module) -> Result<Copied> {
+        #[cfg(feature = "unstable")]
+        {
+            if provider.tags.iter().next().is_some() {
+                return Err(ErrorKind::Unsupported {
+                    feature: "linking tags",
+                })
+                .context("linking modules with tags is not supported yet");
+            }
+        }
+        let mut copied = Copied::default();
+        let import_of = |id: ImportId| {
+            let import = provider.imports.get(id);
+            (import.module.as_str(), import.name.as_str())
+        };
+
+        for ty in provider.types.iter() {
+            let id = if ty.is_for_function_entry() {
+                match module.types.find_for_function_entry(ty.results()) {
+                    Some(id) => id,
+                    None => module.types.add_entry_ty(ty.results()),
+                }
+            } else if ty.is_shared() || ty.cont_of().is_some() {
+                return Err(ErrorKind::Unsupported {
+                    feature: "linking shared or continuation types",
+                })
+                .context("linking modules with shared or continuation types is not supported yet");
+            } else {
+                module.types.add(ty.params(), ty.results())
+            };
+            copied.types.insert(ty.id(), id);
+        }
+
+        // Copy the functions as they are first, so that there are ids for all
+        // of them, and then fix up the ids used by the local ones.
+        for func in provider.funcs.iter() {
+            let id = match &func.kind {
+                FunctionKind::Import(imported) => {
+                    let (import_module, import_name) = import_of(imported.import);
+                    let ty = copied.types[&imported.ty];
+                    module.add_import_func(import_module, import_name, ty).0
+                }
+                FunctionKind::Local(local) => module.funcs.add_local(local.clone()),
+                FunctionKind::Uninitialized(_) => unreachable!(),
+            };
+            module.funcs.get_mut(id).name = func.name.clone();
+            copied.funcs.insert(func.id(), id);
+        }
+
+        for global in provider.globals.iter() {
+            let id = match global.kind {
+                GlobalKind::Import(import) => {
+                    let (import_module, import_name) = import_of(import);
+                    module
+                        .add_import_global(import_module, import_name, global.ty, global.mutable)
+                        .0
+                }
+                GlobalKind::Local(init) => {
+                    module.globals.add_local(global.ty, global.mutable, init)
+                }
+            };
+            module.globals.get_mut(id).name = global.name.clone();
+            copied.globals.insert(global.id(), id);
+        }
+        for global in provider.globals.iter() {
+            if let GlobalKind::Local(InitExpr::Global(init)) = global.kind {
+                let id = copied.globals[&global.id()];
+                module.globals.get_mut(id).kind =
+                    GlobalKind::Local(InitExpr::Global(copied.globals[&init]));
+            }
+        }
+
+        for memory in provider.memories.iter() {
+            let id = match memory.import {
+                Some(import) => {
+                    let (import_module, import_name) = import_of(import);
+                    module
+                        .add_import_memory(
+                            import_module,
+                            import_name,
+                            memory.shared,
+                            memory.initial,
+                            memory.maximum,
+                        )
+                        .0
+                }
+                None => module
+                    .memories
+                    .add_local(memory.shared, memory.initial, memory.maximum),
+            };
+            copied.memories.insert(memory.id(), id);
+        }
+
+        for table in provider.tables.iter() {
+            let mut kind = table.kind.clone();
+            if let TableKind::Function(table) = &mut kind {
+                for func in table.elements.iter_mut().flatten() {
+                    *func = copied.funcs[func];
+                }
+                for (global, elements) in table.relative_elements.iter_mut() {
+                    *global = copied.globals[global];
+                    for func in elements.iter_mut().flatten() {
+                        *func = copied.funcs[func];
+                    }
+                }
+            }
+            let id = match table.import {
+                Some(import) => {
+                    let (import_module, import_name) = import_of(import);
+                    module
+                        .add_import_table(
+                            import_module,
+                            import_name,
+                            table.initial,
+                            table.maximum,
+                            kind,
+                        )
+                        .0
+                }
+                None => module.tables.add_local(table.initial, table.maximum, kind),
+            };
+            module.tables.get_mut(id).index_type = table.index_type;
+            copied.tables.insert(table.id(), id);
+        }
+
+        for data in provider.data.iter() {
+            let id = module.data.add_passive(data.value.clone());
+            if let DataKind::Active(active) = &data.kind {
+                let memory = copied.memories[&active.memory];
+                let location = match active.location {
+                    ActiveDataLocation::Absolute(offset) => ActiveDataLocation::Absolute(offset),
+                    ActiveDataLocation::Relative(global) => {
+                        ActiveDataLocation::Relative(copied.globals[&global])
+                    }
+                };
+                module.data.get_mut(id).kind = DataKind::Active(ActiveData { memory, location });
+                module.memories.get_mut(memory).data_segments.insert(id);
+            }
+            copied.data.insert(data.id(), id);
+        }
+
+        for element in provider.elements.iter() {
+            let members = element
+                .members
+                .iter()
+                .map(|member| member.map(|func| copied.funcs[&func]))
+                .collect();
+            let id = module.elements.add(element.kind, members);
+            module.elements.get_mut(id).uses_exprs = element.uses_exprs;
+            copied.elements.insert(element.id(), id);
+        }
+
+        for (func, _) in provider.funcs.iter_local() {
+            let id = copied.funcs[&func];
+            let local = module.funcs.get_mut(id).kind.unwrap_local_mut();
+            let mut remap = Remap {
+                copied: &copied,
+                locals: &mut module.locals,
+                provider_locals: &provider.locals,
+                copied_locals: IdHashMap::default(),
+            };
+            for arg in local.args.iter_mut() {
+                remap.visit_local_id_mut(arg);
+            }
+            let builder = local.builder_mut();
+            builder.ty = copied.types[&builder.ty];
+            let entry = local.entry_block();
+            dfs_pre_order_mut(&mut remap, local, entry);
+        }
+
+        Ok(copied)
+    }
+}
+
+/// Replaces the ids of another module's items in a copied function with the
+/// ids of their copies.
+struct Remap<'a> {
+    copied: &'a Copied,
+    locals: &'a mut ModuleLocals,
+    provider_locals: &'a ModuleLocals,
+    // Locals are copied as they are found.
+    copied_locals: IdHashMap<Local, LocalId>,
+}
+
+impl VisitorMut for Remap<'_> {
+    fn visit_local_id_mut(&mut self, local: &mut LocalId) {
+        let locals = &mut self.locals;
+        let provider_local = self.provider_locals.get(*local);
+        *local = *self.copied_locals.entry(*local).or_insert_with(|| {
+            let id = locals.add(provider_local.ty());
+            locals.get_mut(id).name = provider_local.name.clone();
+            id
+        });
+    }
+
+    fn visit_function_id_mut(&mut self, func: &mut FunctionId) {
+        *func = self.copied.funcs[func];
+    }
+
+    fn visit_global_id_mut(&mut self, global: &mut GlobalId) {
+        *global = self.copied.globals[global];
+    }
+
+    fn visit_memory_id_mut(&mut self, memory: &mut MemoryId) {
+        *memory = self.copied.memories[memory];
+    }
+
+    fn visit_table_id_mut(&mut self, table: &mut TableId) {
+        *table = self.copied.tables[table];
+    }
+
+    fn visit_data_id_mut(&mut self, data: &mut DataId) {
+        *data = self.copied.data[data];
+    }
+
+    fn visit_element_id_mut(&mut self, element: &mut ElementId) {
+        *element = self.copied.elements[element];
+    }
+
+    fn visit_type_id_mut(&mut self, ty: &mut TypeId) {
+        *ty = self.copied.types[ty];
+    }
+}
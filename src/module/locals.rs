@@ -5,7 +5,7 @@ use crate::ty::ValType;
 use id_arena::Arena;
 
 /// The set of locals in each function in this module.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct ModuleLocals {
     arena: Arena<Local>,
 }
@@ -25,6 +25,12 @@ impl ModuleLocals {
         &self.arena[id]
     }
 
+    /// Get the local with the given id, if it is one of this module's and
+    /// hasn't been deleted.
+    pub(crate) fn try_get(&self, id: LocalId) -> Option<&Local> {
+        self.arena.get(id)
+    }
+
     /// Get the set of locals for this module.
     pub fn get_mut(&mut self, id: LocalId) -> &mut Local {
         &mut self.arena[id]
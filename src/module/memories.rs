@@ -4,13 +4,20 @@ use crate::emit::{Emit, EmitContext, Section};
 use crate::map::IdHashSet;
 use crate::parse::IndicesToIds;
 use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
-use crate::{Data, ImportId, Module, Result};
+use crate::{ActiveDataLocation, Data, DataKind, ImportId, Module, Result};
+use anyhow::bail;
+
+/// The maximum number of pages of a memory.
+const MAX_PAGES: u32 = 1 << 16;
+
+/// The size of a page of memory, in bytes.
+const PAGE_SIZE: u64 = 1 << 16;
 
 /// The id of a memory.
 pub type MemoryId = Id<Memory>;
 
 /// A memory in the wasm.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Memory {
     id: MemoryId,
     /// Is this memory shared?
@@ -36,6 +43,63 @@ impl Memory {
     pub fn id(&self) -> MemoryId {
         self.id
     }
+
+    /// Set the initial number of pages of this memory.
+    ///
+    /// Fails, leaving the memory untouched, if `initial` exceeds this memory's
+    /// maximum or the largest possible memory.
+    pub fn set_initial(&mut self, initial: u32) -> Result<()> {
+        check_limits(initial, self.maximum, self.shared)?;
+        self.initial = initial;
+        Ok(())
+    }
+
+    /// Set the maximum number of pages of this memory.
+    ///
+    /// Fails, leaving the memory untouched, if `maximum` is below this
+    /// memory's initial size or exceeds the largest possible memory.
+    pub fn set_maximum(&mut self, maximum: u32) -> Result<()> {
+        check_limits(self.initial, Some(maximum), self.shared)?;
+        self.maximum = Some(maximum);
+        Ok(())
+    }
+
+    /// Remove the maximum size of this memory, so that it can grow as large as
+    /// possible.
+    ///
+    /// Fails, leaving the memory untouched, if this memory is shared, since
+    /// shared memories require a maximum.
+    pub fn clear_maximum(&mut self) -> Result<()> {
+        check_limits(self.initial, None, self.shared)?;
+        self.maximum = None;
+        Ok(())
+    }
+
+    /// Set whether this memory is shared.
+    ///
+    /// Fails, leaving the memory untouched, when making a memory without a
+    /// maximum shared; set its maximum first.
+    pub fn set_shared(&mut self, shared: bool) -> Result<()> {
+        check_limits(self.initial, self.maximum, shared)?;
+        self.shared = shared;
+        Ok(())
+    }
+}
+
+fn check_limits(initial: u32, maximum: Option<u32>, shared: bool) -> Result<()> {
+    if initial > MAX_PAGES {
+        bail!("memory size must be at most {} pages", MAX_PAGES);
+    }
+    match maximum {
+        Some(maximum) if maximum > MAX_PAGES => {
+            bail!("maximum memory size must be at most {} pages", MAX_PAGES)
+        }
+        Some(maximum) if maximum < initial => {
+            bail!("maximum memory size must be at least the initial size")
+        }
+        None if shared => bail!("shared memories must have a maximum size"),
+        _ => Ok(()),
+    }
 }
 
 impl Emit for Memory {
@@ -52,7 +116,7 @@ impl Emit for Memory {
 }
 
 /// The set of memories in this module.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct ModuleMemories {
     arena: TombstoneArena<Memory>,
 }
@@ -100,6 +164,12 @@ impl ModuleMemories {
         &self.arena[id]
     }
 
+    /// Get the memory with the given id, if it is one of this module's and
+    /// hasn't been deleted.
+    pub(crate) fn try_get(&self, id: MemoryId) -> Option<&Memory> {
+        self.arena.get(id)
+    }
+
     /// Gets a reference to a memory given its id
     pub fn get_mut(&mut self, id: MemoryId) -> &mut Memory {
         &mut self.arena[id]
@@ -141,6 +211,35 @@ impl Module {
         }
         Ok(())
     }
+
+    /// Grow the initial size of `memory` so that all of its active data
+    /// segments at absolute addresses fit, and return its new initial size.
+    ///
+    /// Segments at addresses relative to a global are not taken into account,
+    /// since their addresses are not known until instantiation. Fails,
+    /// leaving the memory untouched, if the segments don't fit within the
+    /// memory's maximum size.
+    pub fn fit_memory_to_data(&mut self, memory: MemoryId) -> Result<u32> {
+        let mut end = 0;
+        for data in self.memories.get(memory).data_segments.iter() {
+            let data = self.data.get(*data);
+            if let DataKind::Active(active) = &data.kind {
+                if let ActiveDataLocation::Absolute(offset) = active.location {
+                    end = end.max(u64::from(offset) + data.value.len() as u64);
+                }
+            }
+        }
+
+        let pages = end / PAGE_SIZE + u64::from(end % PAGE_SIZE != 0);
+        let memory = self.memories.get_mut(memory);
+        if pages > u64::from(memory.initial) {
+            if pages > u64::from(MAX_PAGES) {
+                bail!("data segments don't fit in the largest possible memory");
+            }
+            memory.set_initial(pages as u32)?;
+        }
+        Ok(memory.initial)
+    }
 }
 
 impl Emit for ModuleMemories {
@@ -0,0 +1,106 @@
+//! Handling of the `walrus.metadata` custom section.
+//!
+//! This section holds arbitrary key/value pairs that toolchains can use to
+//! stamp the modules they produce, for example to record which version of a
+//! pipeline processed a module and detect it being processed twice. Its
+//! payload is a vector of entries, each of which is a name followed by a
+//! vector of bytes.
+
+use crate::emit::{Emit, EmitContext};
+use crate::error::Result;
+use crate::module::Module;
+use std::collections::BTreeMap;
+
+/// The name of the custom section holding a module's metadata.
+pub const METADATA_SECTION: &str = "walrus.metadata";
+
+/// Key/value metadata attached to a module, emitted in a `walrus.metadata`
+/// custom section.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ModuleMetadata {
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl ModuleMetadata {
+    /// Get the value of the `key` entry, if there is one.
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.entries.get(key).map(|value| &value[..])
+    }
+
+    /// Set the value of the `key` entry, returning its previous value, if
+    /// any.
+    pub fn set(&mut self, key: &str, value: impl Into<Vec<u8>>) -> Option<Vec<u8>> {
+        self.entries.insert(key.to_string(), value.into())
+    }
+
+    /// Remove the `key` entry, returning its value, if any.
+    pub fn remove(&mut self, key: &str) -> Option<Vec<u8>> {
+        self.entries.remove(key)
+    }
+
+    /// Does this metadata have a `key` entry?
+    pub fn contains(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Iterate over all entries, sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (&key[..], &value[..]))
+    }
+
+    /// Get the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Is there no entry at all?
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Remove all entries, so that no `walrus.metadata` section is emitted.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl Module {
+    /// Parse a `walrus.metadata` section from the custom section payload
+    /// specified.
+    pub(crate) fn parse_metadata_section(
+        &mut self,
+        mut data: wasmparser::BinaryReader,
+    ) -> Result<()> {
+        log::debug!("parse walrus.metadata section");
+        let count = data.read_var_u32()?;
+        for _ in 0..count {
+            let key = data.read_string()?;
+            let len = data.read_var_u32()?;
+            let value = data.read_bytes(len as usize)?;
+            self.metadata.set(key, value);
+        }
+        if !data.eof() {
+            anyhow::bail!("trailing bytes at the end of the walrus.metadata section");
+        }
+        Ok(())
+    }
+}
+
+impl Emit for ModuleMetadata {
+    fn emit(&self, cx: &mut EmitContext) {
+        if self.is_empty() {
+            return;
+        }
+        log::debug!("emit walrus.metadata section");
+        cx.custom_section(METADATA_SECTION).list(&self.entries);
+    }
+}
+
+impl Emit for (&String, &Vec<u8>) {
+    fn emit(&self, cx: &mut EmitContext) {
+        cx.encoder.str(self.0);
+        cx.encoder.bytes(self.1);
+    }
+}
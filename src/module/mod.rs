@@ -1,50 +1,106 @@
 //! A high-level API for manipulating wasm modules.
 
+mod address_map;
+mod annotations;
+mod build_id;
+mod component_type;
 mod config;
+mod coredump;
 mod custom;
 mod data;
+mod display;
 mod elements;
 mod exports;
+mod extract;
+mod features;
 mod functions;
 mod globals;
 mod imports;
+mod journal;
+mod layout;
+mod link;
 mod locals;
 mod memories;
+mod metadata;
+mod patch;
 mod producers;
+mod repair;
+mod summary;
 mod tables;
+#[cfg(feature = "unstable")]
+mod tags;
+mod trampoline;
 mod types;
+mod view;
 
+use crate::cancel::CancellationToken;
 use crate::emit::{Emit, EmitContext, IdsToIndices, Section};
 use crate::encode::Encoder;
-use crate::error::Result;
+use crate::error::{ErrorKind, Result};
 pub use crate::ir::InstrLocId;
+use crate::ir::{InstrSeqId, LocalId};
+pub use crate::module::address_map::{AddressMap, AddressMapEntry};
+pub use crate::module::annotations::ModuleAnnotations;
+pub use crate::module::component_type::{InterfaceExport, InterfaceFunc, InterfaceType};
+pub use crate::module::coredump::{
+    CoreInstance, CoreInstances, CoreModules, CoreStack, CoreStackFrame, CoreValue, Coredump,
+};
 pub use crate::module::custom::{
     CustomSection, CustomSectionId, ModuleCustomSections, RawCustomSection, TypedCustomSectionId,
     UntypedCustomSectionId,
 };
 pub use crate::module::data::{ActiveData, ActiveDataLocation, Data, DataId, DataKind, ModuleData};
-pub use crate::module::elements::{Element, ElementId, ModuleElements};
+pub use crate::module::display::{DisplayId, IdDisplay};
+pub use crate::module::elements::{Element, ElementId, ElementKind, ModuleElements};
 pub use crate::module::exports::{Export, ExportId, ExportItem, ModuleExports};
+pub use crate::module::extract::EXTRACTED_IMPORTS_MODULE;
+pub use crate::module::features::WasmFeatures;
 pub use crate::module::functions::{Function, FunctionId, ModuleFunctions};
-pub use crate::module::functions::{FunctionKind, ImportedFunction, LocalFunction};
+pub use crate::module::functions::{FunctionKind, ImportedFunction, InvalidBody, LocalFunction};
 pub use crate::module::globals::{Global, GlobalId, GlobalKind, ModuleGlobals};
 pub use crate::module::imports::{Import, ImportId, ImportKind, ModuleImports};
+pub use crate::module::journal::Change;
+use crate::module::journal::Journal;
+pub use crate::module::layout::SectionLayout;
+use crate::module::layout::{BinarySection, SectionCode};
+pub use crate::module::link::Resolution;
 pub use crate::module::locals::ModuleLocals;
 pub use crate::module::memories::{Memory, MemoryId, ModuleMemories};
+pub use crate::module::metadata::{ModuleMetadata, METADATA_SECTION};
+pub use crate::module::patch::apply_patch;
 pub use crate::module::producers::ModuleProducers;
-pub use crate::module::tables::FunctionTable;
-pub use crate::module::tables::{ModuleTables, Table, TableId, TableKind};
+pub use crate::module::repair::Repair;
+pub use crate::module::tables::{AnyrefTable, FunctionTable};
+pub use crate::module::tables::{IndexType, ModuleTables, Table, TableId, TableKind};
+#[cfg(feature = "unstable")]
+pub use crate::module::tags::{ModuleTags, Tag, TagId};
+pub use crate::module::trampoline::{AdaptSpec, AdaptValue};
 pub use crate::module::types::ModuleTypes;
-use crate::parse::IndicesToIds;
+pub use crate::module::view::ModuleView;
+use crate::parse::{IndexSpaces, IndicesToIds};
 use anyhow::{bail, Context};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs;
+use std::io::Read;
 use std::mem;
 use std::path::Path;
+use std::sync::Arc;
 
-pub use self::config::ModuleConfig;
+pub use self::config::{CustomSectionPolicy, ModuleConfig, SyntheticName};
 
 /// A wasm module.
-#[derive(Debug, Default)]
+///
+/// Cloning a module is cheap compared to parsing or building it again, since
+/// clones share the bodies of local functions until they are mutated. This
+/// lets modules be forked to try out transformations and discarded
+/// afterwards.
+///
+/// Clones only contain the custom sections in `customs` that implement
+/// `CustomSection::clone_section`, such as `RawCustomSection` and the
+/// coredump sections. Other custom sections are left out of clones, with a
+/// warning logged for each, and aren't emitted with them.
+#[derive(Debug, Clone, Default)]
 #[allow(missing_docs)]
 pub struct Module {
     pub imports: ModuleImports,
@@ -59,18 +115,97 @@ pub struct Module {
     pub data: ModuleData,
     /// Registration of passive element segments, if any
     pub elements: ModuleElements,
+    /// Tags defined in this module, from the stack-switching proposal.
+    #[cfg(feature = "unstable")]
+    pub tags: ModuleTags,
     /// The `start` function, if any
     pub start: Option<FunctionId>,
     /// Representation of the eventual custom section, `producers`
     pub producers: ModuleProducers,
+    /// Key/value metadata, in the `walrus.metadata` custom section.
+    pub metadata: ModuleMetadata,
+    /// Metadata for registries, in the `authors`, `licenses` and other
+    /// annotation custom sections.
+    pub annotations: ModuleAnnotations,
     /// Custom sections found in this module.
     pub customs: ModuleCustomSections,
     /// The name of this module, used for debugging purposes in the `name`
     /// custom section.
     pub name: Option<String>,
+    name_section_error: Option<String>,
+    raw_name_subsections: Vec<(u8, Vec<u8>)>,
+    /// An estimate of the memory taken up by the IR parsed so far, for
+    /// `ModuleConfig::max_ir_memory`.
+    parsed_ir_memory: usize,
+    build_id: Option<Vec<u8>>,
+    original: Option<Arc<OriginalEncoding>>,
+    customs_basis: Arc<CustomsBasis>,
+    journal: Option<Journal>,
+    sections_layout: Vec<SectionLayout>,
     pub(crate) config: ModuleConfig,
 }
 
+/// The binary a module was parsed from with `ModuleConfig::preserve_encoding`,
+/// along with walrus's own encoding of the module as parsed.
+///
+/// As long as the module still encodes to `canonical`, it wasn't modified,
+/// and the original binary can be emitted instead. There is no `canonical`
+/// encoding when build ids are generated, since they are recomputed.
+struct OriginalEncoding {
+    wasm: Vec<u8>,
+    canonical: Option<Vec<u8>>,
+}
+
+/// The encoding of a module that the indices and code offsets in its custom
+/// sections refer to: the binary it was parsed from, until walrus's own
+/// encoding of the module is emitted, and the last such emit afterwards.
+#[derive(Debug, Default)]
+struct CustomsBasis {
+    /// The functions, globals, tables and memories at each index of that
+    /// encoding, unless the module wasn't parsed from a binary.
+    indices: Option<IndexSpaces>,
+    /// Where that encoding has the instructions of the parsed binary, unless
+    /// it is the parsed binary itself.
+    code: Option<CodeTransform>,
+}
+
+impl CustomsBasis {
+    /// Map the code offsets of this encoding to those in `transform`, which
+    /// maps the offsets in the parsed binary.
+    fn code_transform(&self, transform: &CodeTransform) -> CodeTransform {
+        let code = match &self.code {
+            Some(code) => code,
+            None => return transform.clone(),
+        };
+        let mut offsets = HashMap::<u32, Vec<usize>>::new();
+        for (loc, offset) in code {
+            offsets.entry(loc.data()).or_default().push(*offset);
+        }
+        let mut composed = Vec::new();
+        for (loc, offset) in transform {
+            for from in offsets.get(&loc.data()).into_iter().flatten() {
+                composed.push((InstrLocId::new(*from as u32), *offset));
+            }
+        }
+        composed
+    }
+}
+
+impl fmt::Debug for OriginalEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OriginalEncoding")
+            .field("wasm", &format_args!("{} bytes", self.wasm.len()))
+            .field(
+                "canonical",
+                &self
+                    .canonical
+                    .as_ref()
+                    .map(|c| format!("{} bytes", c.len())),
+            )
+            .finish()
+    }
+}
+
 /// Maps from an offset of an instruction in the input Wasm to its offset in the
 /// output Wasm.
 ///
@@ -107,14 +242,59 @@ impl Module {
 
     /// Construct a new module from the in-memory wasm buffer with the default
     /// configuration.
+    ///
+    /// The buffer is only borrowed for the duration of parsing: the resulting
+    /// `Module` owns all of its data and does not hold on to `wasm`. This
+    /// means that any `&[u8]` will do, such as a memory-mapped file, and there
+    /// is no need to first copy the input into a `Vec<u8>`.
     pub fn from_buffer(wasm: &[u8]) -> Result<Module> {
         ModuleConfig::new().parse(wasm)
     }
 
+    /// Construct a new module by reading wasm from the given reader with the
+    /// default configuration.
+    pub fn from_reader<R>(reader: R) -> Result<Module>
+    where
+        R: Read,
+    {
+        ModuleConfig::new().parse_reader(reader)
+    }
+
+    /// Construct a new module by reading wasm from the given reader with the
+    /// given configuration.
+    pub fn from_reader_with_config<R>(mut reader: R, config: &ModuleConfig) -> Result<Module>
+    where
+        R: Read,
+    {
+        let mut wasm = Vec::new();
+        reader
+            .read_to_end(&mut wasm)
+            .context("failed to read wasm from reader")?;
+        config.parse(&wasm)
+    }
+
     fn parse(wasm: &[u8], config: &ModuleConfig) -> Result<Module> {
-        let mut parser = wasmparser::ModuleReader::new(wasm)?;
-        if parser.get_version() != 1 {
-            bail!("only support version 1 of wasm");
+        // Errors that weren't given a kind where they came from are from
+        // decoding the binary.
+        Module::decode(wasm, config).map_err(|e| ErrorKind::Parse.classify(e))
+    }
+
+    fn decode(wasm: &[u8], config: &ModuleConfig) -> Result<Module> {
+        let cancel = config.cancellation_token.as_ref();
+        CancellationToken::check(cancel)?;
+        if !config.skip_strict_validate && !config.best_effort_parse {
+            crate::passes::validate::binary(wasm, config)?;
+        }
+
+        let mut reader = wasmparser::BinaryReader::new(wasm);
+        if reader.read_bytes(4)? != b"\0asm" {
+            bail!("bad magic number");
+        }
+        if reader.read_u32()? != 1 {
+            return Err(ErrorKind::Unsupported {
+                feature: "versions of wasm other than 1",
+            })
+            .context("only support version 1 of wasm");
         }
 
         let mut ret = Module::default();
@@ -123,68 +303,70 @@ impl Module {
         let mut function_section_size = None;
         let mut data_count = None;
 
-        while !parser.eof() {
-            let section = parser.read()?;
+        while !reader.eof() {
+            CancellationToken::check(cancel)?;
+            let section = BinarySection::read(&mut reader)?;
+            ret.sections_layout.push(SectionLayout::new(&section));
+            let (data, offset) = (section.data, section.offset);
             match section.code {
-                wasmparser::SectionCode::Data => {
-                    let reader = section.get_data_section_reader()?;
+                SectionCode::Data => {
+                    let reader = wasmparser::DataSectionReader::new(data, offset)?;
                     ret.parse_data(reader, &mut indices, data_count)
                         .context("failed to parse data section")?;
                 }
-                wasmparser::SectionCode::Type => {
-                    let reader = section.get_type_section_reader()?;
-                    ret.parse_types(reader, &mut indices)
+                SectionCode::Type => {
+                    ret.parse_types(section.reader(), &mut indices)
                         .context("failed to parse type section")?;
                 }
-                wasmparser::SectionCode::Import => {
-                    let reader = section.get_import_section_reader()?;
+                SectionCode::Import => {
+                    let reader = wasmparser::ImportSectionReader::new(data, offset)?;
                     ret.parse_imports(reader, &mut indices)
                         .context("failed to parse import section")?;
                 }
-                wasmparser::SectionCode::Table => {
-                    let reader = section.get_table_section_reader()?;
+                SectionCode::Table => {
+                    let reader = wasmparser::TableSectionReader::new(data, offset)?;
                     ret.parse_tables(reader, &mut indices)
                         .context("failed to parse table section")?;
                 }
-                wasmparser::SectionCode::Memory => {
-                    let reader = section.get_memory_section_reader()?;
+                SectionCode::Memory => {
+                    let reader = wasmparser::MemorySectionReader::new(data, offset)?;
                     ret.parse_memories(reader, &mut indices)
                         .context("failed to parse memory section")?;
                 }
-                wasmparser::SectionCode::Global => {
-                    let reader = section.get_global_section_reader()?;
+                SectionCode::Global => {
+                    let reader = wasmparser::GlobalSectionReader::new(data, offset)?;
                     ret.parse_globals(reader, &mut indices)
                         .context("failed to parse global section")?;
                 }
-                wasmparser::SectionCode::Export => {
-                    let reader = section.get_export_section_reader()?;
+                SectionCode::Export => {
+                    let reader = wasmparser::ExportSectionReader::new(data, offset)?;
                     ret.parse_exports(reader, &mut indices)
                         .context("failed to parse export section")?;
                 }
-                wasmparser::SectionCode::Element => {
-                    let reader = section.get_element_section_reader()?;
+                SectionCode::Element => {
+                    let reader = wasmparser::ElementSectionReader::new(data, offset)?;
                     ret.parse_elements(reader, &mut indices)
                         .context("failed to parse element section")?;
                 }
-                wasmparser::SectionCode::Start => {
-                    let idx = section.get_start_section_content()?;
+                SectionCode::Start => {
+                    let idx = section.index()?;
                     if ret.start.is_some() {
                         bail!("multiple start sections found");
                     }
                     ret.start = Some(indices.get_func(idx)?);
                 }
-                wasmparser::SectionCode::Function => {
-                    let reader = section.get_function_section_reader()?;
+                SectionCode::Function => {
+                    let reader = wasmparser::FunctionSectionReader::new(data, offset)?;
                     function_section_size = Some(reader.get_count());
                     ret.declare_local_functions(reader, &mut indices)
                         .context("failed to parse function section")?;
                 }
-                wasmparser::SectionCode::Code => {
+                SectionCode::Code => {
                     let function_section_size = match function_section_size.take() {
                         Some(i) => i,
                         None => bail!("cannot have a code section without function section"),
                     };
-                    let reader = section.get_code_section_reader()?;
+                    let reader = wasmparser::CodeSectionReader::new(data, offset)?;
                     let on_instr_loc = config.on_instr_loc.as_ref().map(|f| f.as_ref());
                     ret.parse_local_functions(
                         reader,
@@ -194,26 +376,47 @@ impl Module {
                     )
                     .context("failed to parse code section")?;
                 }
-                wasmparser::SectionCode::DataCount => {
-                    let count = section.get_data_count_section_content()?;
+                #[cfg(feature = "unstable")]
+                SectionCode::Tag => {
+                    ret.parse_tags(section.reader(), &mut indices)
+                        .context("failed to parse tag section")?;
+                }
+                SectionCode::DataCount => {
+                    let count = section.index()?;
                     data_count = Some(count);
                     ret.reserve_data(count, &mut indices);
                 }
-                wasmparser::SectionCode::Custom { name, kind: _ } => {
+                SectionCode::Custom(name) => {
+                    let payload = data;
+                    let policy = config.custom_section_policy;
+                    // Parsing the sections below may fail partway through, so
+                    // hang onto what they parse into to put it back if they
+                    // are kept raw instead.
+                    let parsed = if policy == CustomSectionPolicy::KeepRaw {
+                        Some((
+                            ret.producers.clone(),
+                            ret.metadata.clone(),
+                            ret.annotations.clone(),
+                        ))
+                    } else {
+                        None
+                    };
                     let result = match name {
                         "producers" => {
-                            let reader = section.get_producers_section_reader()?;
+                            let reader = wasmparser::ProducersSectionReader::new(data, offset)?;
                             ret.parse_producers_section(reader)
                         }
-                        "name" => section
-                            .get_name_section_reader()
-                            .map_err(anyhow::Error::from)
-                            .and_then(|r| ret.parse_name_section(r, &indices)),
+                        "build_id" => ret.parse_build_id_section(reader_at(payload, offset)),
+                        METADATA_SECTION => ret.parse_metadata_section(reader_at(payload, offset)),
+                        "name" => ret.parse_name_section(payload, offset, &indices),
+                        _ if annotations::is_annotation_section(name) => {
+                            ret.parse_annotation_section(name, payload)
+                        }
                         _ => {
                             log::debug!("parsing custom section `{}`", name);
-                            let mut reader = section.get_binary_reader();
-                            let len = reader.bytes_remaining();
-                            let payload = reader.read_bytes(len)?;
+                            if coredump::add_coredump_section(&mut ret.customs, name, payload) {
+                                continue;
+                            }
                             ret.customs.add(RawCustomSection {
                                 name: name.to_string(),
                                 data: payload.to_vec(),
@@ -222,7 +425,28 @@ impl Module {
                         }
                     };
                     if let Err(e) = result {
-                        log::warn!("failed to parse `{}` custom section {}", name, e);
+                        let e = e.context(format!("failed to parse `{}` custom section", name));
+                        match policy {
+                            CustomSectionPolicy::Error => return Err(e),
+                            CustomSectionPolicy::WarnAndSkip => {
+                                log::warn!("{:#}", e);
+                                if name == "name" {
+                                    ret.name_section_error = Some(format!("{:#}", e.root_cause()));
+                                }
+                            }
+                            CustomSectionPolicy::KeepRaw => {
+                                log::debug!("keeping raw: {:#}", e);
+                                if let Some((producers, metadata, annotations)) = parsed {
+                                    ret.producers = producers;
+                                    ret.metadata = metadata;
+                                    ret.annotations = annotations;
+                                }
+                                ret.customs.add(RawCustomSection {
+                                    name: name.to_string(),
+                                    data: payload.to_vec(),
+                                });
+                            }
+                        }
                     }
                 }
             }
@@ -231,18 +455,42 @@ impl Module {
         if function_section_size.is_some() {
             bail!("cannot define a function section without a code section");
         }
+        for id in indices.unused_active_elements() {
+            ret.elements.delete(id);
+        }
 
         ret.producers
             .add_processed_by("walrus", env!("CARGO_PKG_VERSION"));
 
+        CancellationToken::check(cancel)?;
+
         // TODO: probably run this in a different location
         if !ret.config.skip_strict_validate {
             crate::passes::validate::run(&ret)?;
         }
 
+        if config.preserve_encoding {
+            let canonical = if config.generate_build_id {
+                None
+            } else {
+                Some(ret.encode(false, cancel, None))
+            };
+            CancellationToken::check(cancel)?;
+            ret.original = Some(Arc::new(OriginalEncoding {
+                wasm: wasm.to_vec(),
+                canonical,
+            }));
+        }
+
+        ret.funcs.set_original_ids(indices.funcs());
+
         if let Some(ref on_parse) = config.on_parse {
             on_parse(&mut ret, &indices)?;
         }
+        ret.customs_basis = Arc::new(CustomsBasis {
+            indices: Some(indices.index_spaces()),
+            code: None,
+        });
 
         log::debug!("parse complete");
         Ok(ret)
@@ -254,12 +502,63 @@ impl Module {
         P: AsRef<Path>,
     {
         let buffer = self.emit_wasm();
-        fs::write(path, buffer).context("failed to write wasm module")?;
+        fs::write(path, buffer)
+            .context("failed to write wasm module")
+            .map_err(|e| ErrorKind::Emit.classify(e))?;
         Ok(())
     }
 
     /// Emit this module into an in-memory wasm buffer.
+    ///
+    /// This ignores `ModuleConfig::cancellation_token`; see `try_emit_wasm`.
     pub fn emit_wasm(&mut self) -> Vec<u8> {
+        self.emit_wasm_checking(None)
+            .expect("emitting can only fail when cancelled")
+    }
+
+    /// Emit this module into an in-memory wasm buffer, unless the module's
+    /// `ModuleConfig::cancellation_token` is cancelled first.
+    ///
+    /// Fails with `ErrorKind::Cancelled` if the token is cancelled before the
+    /// module is fully emitted.
+    pub fn try_emit_wasm(&mut self) -> Result<Vec<u8>> {
+        let cancel = self.config.cancellation_token.clone();
+        self.emit_wasm_checking(cancel.as_ref())
+    }
+
+    fn emit_wasm_checking(&mut self, cancel: Option<&CancellationToken>) -> Result<Vec<u8>> {
+        CancellationToken::check(cancel)?;
+        // Custom sections are only brought up to date with walrus's encoding
+        // when it is what gets emitted, so whether the module is unmodified is
+        // checked without touching them.
+        if let Some(original) = self.original.clone() {
+            if let Some(canonical) = &original.canonical {
+                let wasm = self.encode(false, cancel, None);
+                CancellationToken::check(cancel)?;
+                if wasm == *canonical {
+                    log::debug!("module is unmodified, emitting the original binary");
+                    return Ok(original.wasm.clone());
+                }
+            }
+        }
+        let wasm = self.encode(true, cancel, None);
+        CancellationToken::check(cancel)?;
+        Ok(wasm)
+    }
+
+    /// Encode this module, bringing the indices and code offsets of custom
+    /// sections up to date with the encoding if `transform` is set, which it
+    /// is whenever the encoding is emitted.
+    ///
+    /// Once `cancel` is cancelled, the rest of the functions are skipped, and
+    /// the encoding is incomplete. The emitted instructions are recorded in
+    /// `address_map`, if given.
+    fn encode(
+        &mut self,
+        transform: bool,
+        cancel: Option<&CancellationToken>,
+        address_map: Option<&mut AddressMap>,
+    ) -> Vec<u8> {
         log::debug!("start emit");
 
         let indices = &mut IdsToIndices::default();
@@ -275,12 +574,16 @@ impl Module {
             encoder: Encoder::new(&mut wasm),
             locals: Default::default(),
             code_transform: Vec::new(),
+            address_map,
+            cancel,
         };
         self.types.emit(&mut cx);
         self.imports.emit(&mut cx);
         self.funcs.emit_func_section(&mut cx);
         self.tables.emit(&mut cx);
         self.memories.emit(&mut cx);
+        #[cfg(feature = "unstable")]
+        self.tags.emit(&mut cx);
         self.globals.emit(&mut cx);
         self.exports.emit(&mut cx);
         if let Some(start) = self.start {
@@ -298,8 +601,24 @@ impl Module {
         if !self.config.skip_producers_section {
             self.producers.emit(&mut cx);
         }
+        self.metadata.emit(&mut cx);
+        self.annotations.emit(&mut cx);
+        let build_id_pos = build_id::emit_build_id_section(&mut cx);
 
         let indices = mem::replace(cx.indices, Default::default());
+        let emitted_code = mem::take(&mut cx.code_transform);
+        let (remap, code_transform) = if transform {
+            let basis = &self.customs_basis;
+            let remap = basis.indices.as_ref().map(|spaces| spaces.remap(&indices));
+            let code_transform = if self.config.preserve_code_transform {
+                Some(basis.code_transform(&emitted_code))
+            } else {
+                None
+            };
+            (remap, code_transform)
+        } else {
+            (None, None)
+        };
 
         for (_id, section) in customs.iter_mut() {
             if !self.config.generate_dwarf && section.name().starts_with(".debug") {
@@ -309,8 +628,11 @@ impl Module {
 
             log::debug!("emitting custom section {}", section.name());
 
-            if self.config.preserve_code_transform {
-                section.apply_code_transform(&cx.code_transform);
+            if let Some(remap) = &remap {
+                section.apply_index_remap(remap);
+            }
+            if let Some(code_transform) = &code_transform {
+                section.apply_code_transform(code_transform);
             }
 
             cx.custom_section(&section.name())
@@ -318,32 +640,145 @@ impl Module {
                 .raw(&section.data(&indices));
         }
 
+        // Now that the whole module has been emitted, fill in its build id.
+        if let Some(pos) = build_id_pos {
+            let id = build_id::compute_build_id(&wasm);
+            wasm[pos..pos + id.len()].copy_from_slice(&id);
+            self.build_id = Some(id.to_vec());
+        }
+        self.customs = customs;
+        if transform {
+            // From now on, the custom sections refer to this encoding.
+            let basis = CustomsBasis {
+                indices: remap.map(|_| indices.index_spaces()),
+                code: if self.config.preserve_code_transform {
+                    Some(emitted_code)
+                } else {
+                    self.customs_basis.code.clone()
+                },
+            };
+            self.customs_basis = Arc::new(basis);
+        }
+
         log::debug!("emission finished");
         wasm
     }
 
+    /// Account for `count` more parsed items of `size` bytes each in the IR,
+    /// checking them against `ModuleConfig::max_ir_memory`.
+    pub(crate) fn charge_ir_memory(&mut self, count: usize, size: usize) -> Result<()> {
+        let bytes = count.saturating_mul(size);
+        self.parsed_ir_memory = self.parsed_ir_memory.saturating_add(bytes);
+        if let Some(max) = self.config.max_ir_memory {
+            if self.parsed_ir_memory > max {
+                return Err(ErrorKind::LimitExceeded).context(format!(
+                    "module needs more than the maximum of {} bytes of IR",
+                    max
+                ));
+            }
+        }
+        Ok(())
+    }
+
     /// Returns an iterator over all functions in this module
     pub fn functions(&self) -> impl Iterator<Item = &Function> {
         self.funcs.iter()
     }
 
+    /// Parse the `name` section from the custom section payload specified,
+    /// which starts at `offset` in the binary.
+    ///
+    /// The names are only assigned once the whole section has been parsed,
+    /// unless odd sections are skipped, in which case the names parsed before
+    /// an error are assigned too.
     fn parse_name_section(
         &mut self,
-        names: wasmparser::NameSectionReader,
+        payload: &[u8],
+        offset: usize,
         indices: &IndicesToIds,
     ) -> Result<()> {
         log::debug!("parse name section");
+        let mut names = Vec::new();
+        let result = self.read_names(payload, offset, indices, &mut names);
+        if result.is_err() && self.config.custom_section_policy != CustomSectionPolicy::WarnAndSkip
+        {
+            return result;
+        }
         for name in names {
-            match name? {
+            match name {
+                NameEntry::Module(name) => self.name = Some(name),
+                NameEntry::Function(id, name) => self.funcs.get_mut(id).name = Some(name),
+                NameEntry::Local(id, name) => self.locals.get_mut(id).name = Some(name),
+                NameEntry::Label(func, seq, name) => {
+                    if let FunctionKind::Local(f) = &mut self.funcs.get_mut(func).kind {
+                        f.block_mut(seq).name = Some(name);
+                    }
+                }
+                NameEntry::Raw(id, data) => self.raw_name_subsections.push((id, data)),
+            }
+        }
+        result
+    }
+
+    fn read_names(
+        &self,
+        payload: &[u8],
+        offset: usize,
+        indices: &IndicesToIds,
+        names: &mut Vec<NameEntry>,
+    ) -> Result<()> {
+        let mut reader = reader_at(payload, offset);
+        let mut seen = HashSet::new();
+        while !reader.eof() {
+            // Find where the subsection ends first, so that subsections which
+            // `wasmparser` doesn't know can be skipped.
+            let start = reader.current_position();
+            let id = reader.read_u8()? as u8;
+            let len = reader.read_var_u32()?;
+            let data_start = reader.current_position();
+            reader.skip_bytes(len as usize)?;
+            let end = reader.current_position();
+
+            let odd = if id > 3 {
+                "unknown"
+            } else if !seen.insert(id) {
+                "duplicated"
+            } else {
+                ""
+            };
+            if !odd.is_empty() {
+                match self.config.custom_section_policy {
+                    CustomSectionPolicy::Error => bail!("{} name subsection {}", odd, id),
+                    CustomSectionPolicy::WarnAndSkip => {
+                        log::warn!("skipping {} name subsection {}", odd, id)
+                    }
+                    CustomSectionPolicy::KeepRaw => {
+                        names.push(NameEntry::Raw(id, payload[data_start..end].to_vec()))
+                    }
+                }
+                continue;
+            }
+            if id == 3 {
+                self.read_label_names(
+                    reader_at(&payload[data_start..end], offset + data_start),
+                    indices,
+                    names,
+                )?;
+                continue;
+            }
+
+            let mut subsection =
+                wasmparser::NameSectionReader::new(&payload[start..end], offset + start)?;
+            match subsection.read()? {
                 wasmparser::Name::Module(m) => {
-                    self.name = Some(m.get_name()?.to_string());
+                    names.push(NameEntry::Module(m.get_name()?.to_string()));
                 }
                 wasmparser::Name::Function(f) => {
                     let mut map = f.get_map()?;
                     for _ in 0..map.get_count() {
                         let naming = map.read()?;
                         let id = indices.get_func(naming.index)?;
-                        self.funcs.get_mut(id).name = Some(naming.name.to_string());
+                        names.push(NameEntry::Function(id, naming.name.to_string()));
                     }
                 }
                 wasmparser::Name::Local(l) => {
@@ -364,7 +799,7 @@ impl Module {
                                 continue;
                             }
                             let id = indices.get_local(func_id, naming.index)?;
-                            self.locals.get_mut(id).name = Some(naming.name.to_string());
+                            names.push(NameEntry::Local(id, naming.name.to_string()));
                         }
                     }
                 }
@@ -372,15 +807,65 @@ impl Module {
         }
         Ok(())
     }
+
+    /// Read the label names subsection from the extended name section
+    /// proposal, which `wasmparser` doesn't know.
+    fn read_label_names(
+        &self,
+        mut reader: wasmparser::BinaryReader<'_>,
+        indices: &IndicesToIds,
+        names: &mut Vec<NameEntry>,
+    ) -> Result<()> {
+        for _ in 0..reader.read_var_u32()? {
+            let index = reader.read_var_u32()?;
+            let func = indices.get_func(index)?;
+            let labels = match &self.funcs.get(func).kind {
+                FunctionKind::Local(f) => f.labels(),
+                _ => Vec::new(),
+            };
+            for _ in 0..reader.read_var_u32()? {
+                let label = reader.read_var_u32()?;
+                let name = reader.read_string()?;
+                let seq = match labels.get(label as usize) {
+                    Some(seq) => *seq,
+                    None => bail!("function {} has no label {}", index, label),
+                };
+                names.push(NameEntry::Label(func, seq, name.to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A name read from the `name` section.
+enum NameEntry {
+    Module(String),
+    Function(FunctionId, String),
+    Local(LocalId, String),
+    Label(FunctionId, InstrSeqId, String),
+    /// An odd subsection kept raw, by its id.
+    Raw(u8, Vec<u8>),
+}
+
+fn reader_at(payload: &[u8], offset: usize) -> wasmparser::BinaryReader<'_> {
+    wasmparser::BinaryReader::new_with_offset(payload, offset)
 }
 
 fn emit_name_section(cx: &mut EmitContext) {
     log::debug!("emit name section");
+    let demangle = cx.module.config.demangle_names;
     let mut funcs = cx
         .module
         .funcs
         .iter()
-        .filter_map(|func| func.name.as_ref().map(|name| (func, name)))
+        .filter_map(|func| {
+            let name = if demangle {
+                func.demangled_name()?
+            } else {
+                func.name.as_deref()?.into()
+            };
+            Some((func, name))
+        })
         .map(|(func, name)| (cx.indices.get_func_index(func.id()), name))
         .collect::<Vec<_>>();
     funcs.sort_by_key(|p| p.0); // sort by index
@@ -408,7 +893,33 @@ fn emit_name_section(cx: &mut EmitContext) {
         .collect::<Vec<_>>();
     locals.sort_by_key(|p| p.0); // sort by index
 
-    if cx.module.name.is_none() && funcs.len() == 0 && locals.len() == 0 {
+    let mut labels = cx
+        .module
+        .funcs
+        .iter_local()
+        .filter(|(id, _)| cx.locals.contains_key(id))
+        .filter_map(|(id, func)| {
+            let label_names = func
+                .labels()
+                .into_iter()
+                .enumerate()
+                .filter_map(|(index, seq)| Some((index as u32, func.block(seq).name.as_ref()?)))
+                .collect::<Vec<_>>();
+            if label_names.is_empty() {
+                None
+            } else {
+                Some((cx.indices.get_func_index(id), label_names))
+            }
+        })
+        .collect::<Vec<_>>();
+    labels.sort_by_key(|p| p.0); // sort by index
+
+    if cx.module.name.is_none()
+        && funcs.is_empty()
+        && locals.is_empty()
+        && labels.is_empty()
+        && cx.module.raw_name_subsections.is_empty()
+    {
         return;
     }
 
@@ -422,7 +933,7 @@ fn emit_name_section(cx: &mut EmitContext) {
         cx.encoder.usize(funcs.len());
         for (index, name) in funcs {
             cx.encoder.u32(index);
-            cx.encoder.str(name);
+            cx.encoder.str(&name);
         }
     }
 
@@ -439,4 +950,19 @@ fn emit_name_section(cx: &mut EmitContext) {
             }
         }
     }
+    if !labels.is_empty() {
+        let mut cx = cx.subsection(3);
+        cx.encoder.usize(labels.len());
+        for (index, map) in labels {
+            cx.encoder.u32(index);
+            cx.encoder.usize(map.len());
+            for (index, name) in map {
+                cx.encoder.u32(index);
+                cx.encoder.str(name);
+            }
+        }
+    }
+    for (id, data) in &cx.module.raw_name_subsections {
+        cx.subsection(*id).encoder.raw(data);
+    }
 }
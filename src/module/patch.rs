@@ -0,0 +1,150 @@
+//! Binary patches from the original binary of a module to its new encoding.
+//!
+//! For updates where shipping the whole re-emitted module is too large, a
+//! patch only holds the bytes that aren't already in the original binary. A
+//! patch starts with `PATCH_MAGIC`, its version, and a hash of the original
+//! binary it applies to, followed by a sequence of operations that build up
+//! the new binary:
+//!
+//! * `0x00 offset:u32 len:u32` copies `len` bytes from `offset` in the
+//!   original binary, and
+//! * `0x01 len:u32 bytes` inserts `len` new bytes,
+//!
+//! with all numbers encoded as unsigned LEB128.
+
+use crate::encode::Encoder;
+use crate::error::Result;
+use crate::module::build_id::compute_build_id;
+use crate::module::Module;
+use anyhow::{bail, Context};
+use std::collections::HashMap;
+use wasmparser::BinaryReader;
+
+/// The magic bytes that patches start with.
+const PATCH_MAGIC: &[u8] = b"\0wpt";
+
+/// The version of the patch format.
+const PATCH_VERSION: u8 = 1;
+
+/// The size of the blocks of the original binary that are looked for in the
+/// new one. Shorter runs of identical bytes aren't copied.
+const BLOCK_LEN: usize = 16;
+
+const COPY: u8 = 0x00;
+const INSERT: u8 = 0x01;
+
+impl Module {
+    /// Emit this module as a patch against the binary it was parsed from.
+    ///
+    /// Applying the patch to the original binary with `apply_patch` gives the
+    /// same bytes as `emit_wasm` does. The original binary is only kept when
+    /// the module is parsed with `ModuleConfig::preserve_encoding` enabled, so
+    /// that must be enabled for this to work.
+    ///
+    /// Patches are small when the original encoding is preserved for most of
+    /// the module, which is what `preserve_encoding` itself does for modules
+    /// that weren't modified.
+    pub fn emit_patch(&mut self) -> Result<Vec<u8>> {
+        let original = match &self.original {
+            Some(original) => original.clone(),
+            None => bail!("the module doesn't have its original binary, see `preserve_encoding`"),
+        };
+        let wasm = self.emit_wasm();
+        Ok(diff(&original.wasm, &wasm))
+    }
+}
+
+/// Apply a patch made by `Module::emit_patch` to the `original` binary that
+/// it was made against, returning the patched binary.
+pub fn apply_patch(original: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = BinaryReader::new(patch);
+    if reader.read_bytes(PATCH_MAGIC.len()).ok() != Some(PATCH_MAGIC) {
+        bail!("not a walrus patch");
+    }
+    let version = reader.read_u8()?;
+    if version != u32::from(PATCH_VERSION) {
+        bail!("unsupported patch version {}", version);
+    }
+    let hash = compute_build_id(original);
+    if reader.read_bytes(hash.len())? != hash {
+        bail!("the patch was made against a different original binary");
+    }
+
+    let mut wasm = Vec::new();
+    while !reader.eof() {
+        match reader.read_u8()? as u8 {
+            COPY => {
+                let offset = reader.read_var_u32()? as usize;
+                let len = reader.read_var_u32()? as usize;
+                let bytes = offset
+                    .checked_add(len)
+                    .and_then(|end| original.get(offset..end))
+                    .context("patch copies from past the end of the original binary")?;
+                wasm.extend_from_slice(bytes);
+            }
+            INSERT => {
+                let len = reader.read_var_u32()? as usize;
+                wasm.extend_from_slice(reader.read_bytes(len)?);
+            }
+            op => bail!("invalid patch operation {:#x}", op),
+        }
+    }
+    Ok(wasm)
+}
+
+/// Make a patch that turns `original` into `new`.
+///
+/// Every aligned block of `original` is indexed, and `new` is scanned for
+/// them. Matches are extended in both directions and copied, and everything
+/// in between is inserted.
+fn diff(original: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut blocks = HashMap::new();
+    for (i, block) in original.chunks_exact(BLOCK_LEN).enumerate() {
+        blocks.entry(block).or_insert(i * BLOCK_LEN);
+    }
+
+    let mut patch = Vec::new();
+    let mut encoder = Encoder::new(&mut patch);
+    encoder.raw(PATCH_MAGIC);
+    encoder.byte(PATCH_VERSION);
+    encoder.raw(&compute_build_id(original));
+
+    let mut insert_from = 0;
+    let mut i = 0;
+    while i + BLOCK_LEN <= new.len() {
+        let from = match blocks.get(&new[i..i + BLOCK_LEN]) {
+            Some(from) => *from,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        let (mut start, mut from_start) = (i, from);
+        while start > insert_from && from_start > 0 && new[start - 1] == original[from_start - 1] {
+            start -= 1;
+            from_start -= 1;
+        }
+        let mut end = i + BLOCK_LEN;
+        let mut from_end = from + BLOCK_LEN;
+        while end < new.len() && from_end < original.len() && new[end] == original[from_end] {
+            end += 1;
+            from_end += 1;
+        }
+
+        insert(&mut encoder, &new[insert_from..start]);
+        encoder.byte(COPY);
+        encoder.usize(from_start);
+        encoder.usize(end - start);
+        insert_from = end;
+        i = end;
+    }
+    insert(&mut encoder, &new[insert_from..]);
+    patch
+}
+
+fn insert(encoder: &mut Encoder, bytes: &[u8]) {
+    if !bytes.is_empty() {
+        encoder.byte(INSERT);
+        encoder.bytes(bytes);
+    }
+}
@@ -8,18 +8,18 @@ use crate::error::Result;
 use crate::module::Module;
 
 /// Representation of the wasm custom section `producers`
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct ModuleProducers {
     fields: Vec<Field>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Field {
     name: String,
     values: Vec<Value>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Value {
     name: String,
     version: String,
@@ -0,0 +1,124 @@
+//! Repairing common corruption in modules ingested from the wild.
+
+use crate::ir::{dfs_pre_order_mut, Instr, InstrLocId, LoadSimdKind, MemArg, VisitorMut};
+use crate::{ExportItem, FunctionId, Module};
+use std::collections::HashSet;
+
+/// A repair made by `Module::repair`.
+#[derive(Debug, Clone)]
+pub enum Repair {
+    /// The `name` custom section was truncated or otherwise malformed.
+    ///
+    /// The names read before the error are kept, and the whole section is
+    /// written out again when the module is emitted.
+    NameSection {
+        /// The error that parsing the section failed with.
+        error: String,
+    },
+
+    /// Memory accesses of the local function `func` had alignment hints
+    /// greater than the number of bytes they access, and were given their
+    /// natural alignment instead.
+    Alignment {
+        /// The function containing the accesses.
+        func: FunctionId,
+        /// How many accesses were realigned.
+        count: usize,
+    },
+
+    /// An export had the same name as an earlier one, and was dropped.
+    DuplicateExport {
+        /// The name of the export.
+        name: String,
+        /// The item that the dropped export exported.
+        item: ExportItem,
+    },
+}
+
+impl Module {
+    /// Repair common corruption in this module, returning a report of what
+    /// was repaired.
+    ///
+    /// This is for tooling that ingests wasm from the wild, which may have
+    /// been produced by buggy tools, and is meant to be used with a module
+    /// parsed without `ModuleConfig::strict_validate`. The repairs are:
+    ///
+    /// * A truncated or malformed `name` section is rewritten from the names
+    ///   that could be read from it.
+    /// * Alignment hints of memory accesses that are greater than the number
+    ///   of bytes accessed are reduced to it.
+    /// * Exports with the same name as an earlier export are dropped.
+    pub fn repair(&mut self) -> Vec<Repair> {
+        let mut report = Vec::new();
+
+        if let Some(error) = self.name_section_error.take() {
+            report.push(Repair::NameSection { error });
+        }
+
+        for (id, func) in self.funcs.iter_local_mut() {
+            let mut realign = Realign(0);
+            let entry = func.entry_block();
+            dfs_pre_order_mut(&mut realign, func, entry);
+            if realign.0 > 0 {
+                report.push(Repair::Alignment {
+                    func: id,
+                    count: realign.0,
+                });
+            }
+        }
+
+        let mut names = HashSet::new();
+        let duplicates = self
+            .exports
+            .iter()
+            .filter(|e| !names.insert(e.name.as_str()))
+            .map(|e| e.id())
+            .collect::<Vec<_>>();
+        for id in duplicates {
+            let export = self.exports.get(id);
+            report.push(Repair::DuplicateExport {
+                name: export.name.clone(),
+                item: export.item,
+            });
+            self.exports.delete(id);
+        }
+
+        report
+    }
+}
+
+/// Gives memory accesses with alignment hints greater than their widths
+/// their natural alignment, counting them.
+struct Realign(usize);
+
+impl Realign {
+    fn clamp(&mut self, arg: &mut MemArg, width: u32) {
+        if arg.align > width {
+            arg.align = width;
+            self.0 += 1;
+        }
+    }
+}
+
+impl VisitorMut for Realign {
+    fn visit_instr_mut(&mut self, instr: &mut Instr, _: &mut InstrLocId) {
+        match instr {
+            Instr::Load(e) => self.clamp(&mut e.arg, e.kind.width()),
+            Instr::Store(e) => self.clamp(&mut e.arg, e.kind.width()),
+            Instr::AtomicRmw(e) => self.clamp(&mut e.arg, e.width.bytes()),
+            Instr::Cmpxchg(e) => self.clamp(&mut e.arg, e.width.bytes()),
+            Instr::AtomicNotify(e) => self.clamp(&mut e.arg, 4),
+            Instr::AtomicWait(e) => self.clamp(&mut e.arg, if e.sixty_four { 8 } else { 4 }),
+            Instr::LoadSimd(e) => {
+                let width = match e.kind {
+                    LoadSimdKind::Splat8 => 1,
+                    LoadSimdKind::Splat16 => 2,
+                    LoadSimdKind::Splat32 => 4,
+                    _ => 8,
+                };
+                self.clamp(&mut e.arg, width)
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! A summary of a module's interface, as JSON.
+
+use crate::ValType;
+use crate::{ExportItem, FunctionKind, ImportId, ImportKind, IndexType, Module, TableKind};
+use std::fmt::Write;
+
+impl Module {
+    /// Summarize this module's interface as JSON, for packaging and registry
+    /// tools that record what a module imports and exports.
+    ///
+    /// The JSON is an object with these fields, each of which is a list:
+    ///
+    /// * `imports`: objects with the `module` and `name` of each import, and
+    ///   its `kind`, one of `"func"`, `"table"`, `"memory"` or `"global"`.
+    ///
+    /// * `exports`: objects with the `name` and `kind` of each export.
+    ///
+    /// * `memories`: objects with the `initial` and `maximum` pages of each
+    ///   memory, whether it is `shared`, and its `import`, if any, as an object
+    ///   with its `module` and `name`.
+    ///
+    /// * `tables`: objects with the `element` type of each table, its
+    ///   `initial` and `maximum` size, its `index` type and its `import`.
+    ///
+    /// * `functions`: objects with the `name`, if any, of each function, the
+    ///   `params` and `results` of its signature, its `import` and the names
+    ///   of its `exports`.
+    ///
+    /// * `customs`: the names of the custom sections in `Module::customs`.
+    ///   The `name` and `producers` sections are parsed into the module
+    ///   rather than kept as custom sections, so they aren't listed.
+    ///
+    /// Items are listed in the order that their collections' `iter` methods
+    /// yield them, and missing values are `null`. The JSON is compact, with
+    /// the fields of objects in the order above.
+    pub fn summary_json(&self) -> String {
+        let mut out = String::new();
+        out.push_str("{\"imports\":");
+        list(&mut out, self.imports.iter(), |out, import| {
+            out.push_str("{\"module\":");
+            string(out, &import.module);
+            out.push_str(",\"name\":");
+            string(out, &import.name);
+            let kind = match import.kind {
+                ImportKind::Function(_) => "func",
+                ImportKind::Table(_) => "table",
+                ImportKind::Memory(_) => "memory",
+                ImportKind::Global(_) => "global",
+            };
+            write!(out, ",\"kind\":\"{}\"}}", kind).unwrap();
+        });
+
+        out.push_str(",\"exports\":");
+        list(&mut out, self.exports.iter(), |out, export| {
+            out.push_str("{\"name\":");
+            string(out, &export.name);
+            let kind = match export.item {
+                ExportItem::Function(_) => "func",
+                ExportItem::Table(_) => "table",
+                ExportItem::Memory(_) => "memory",
+                ExportItem::Global(_) => "global",
+            };
+            write!(out, ",\"kind\":\"{}\"}}", kind).unwrap();
+        });
+
+        out.push_str(",\"memories\":");
+        list(&mut out, self.memories.iter(), |out, memory| {
+            write!(out, "{{\"initial\":{},\"maximum\":", memory.initial).unwrap();
+            optional(out, memory.maximum);
+            write!(out, ",\"shared\":{},\"import\":", memory.shared).unwrap();
+            self.import_json(out, memory.import);
+            out.push('}');
+        });
+
+        out.push_str(",\"tables\":");
+        list(&mut out, self.tables.iter(), |out, table| {
+            let element = match table.kind {
+                TableKind::Function(_) => "funcref",
+                TableKind::Anyref(_) => "anyref",
+            };
+            write!(
+                out,
+                "{{\"element\":\"{}\",\"initial\":{},\"maximum\":",
+                element, table.initial
+            )
+            .unwrap();
+            optional(out, table.maximum);
+            let index = match table.index_type {
+                IndexType::I32 => "i32",
+                IndexType::I64 => "i64",
+            };
+            write!(out, ",\"index\":\"{}\",\"import\":", index).unwrap();
+            self.import_json(out, table.import);
+            out.push('}');
+        });
+
+        out.push_str(",\"functions\":");
+        list(&mut out, self.funcs.iter(), |out, func| {
+            out.push_str("{\"name\":");
+            match &func.name {
+                Some(name) => string(out, name),
+                None => out.push_str("null"),
+            }
+            let ty = self.types.get(func.ty());
+            out.push_str(",\"params\":");
+            types(out, ty.params());
+            out.push_str(",\"results\":");
+            types(out, ty.results());
+            out.push_str(",\"import\":");
+            let import = match &func.kind {
+                FunctionKind::Import(import) => Some(import.import),
+                _ => None,
+            };
+            self.import_json(out, import);
+            out.push_str(",\"exports\":");
+            let exports = self.exports.iter().filter(|export| match export.item {
+                ExportItem::Function(f) => f == func.id(),
+                _ => false,
+            });
+            list(out, exports, |out, export| string(out, &export.name));
+            out.push('}');
+        });
+
+        out.push_str(",\"customs\":");
+        list(&mut out, self.customs.iter(), |out, (_, section)| {
+            string(out, section.name())
+        });
+        out.push('}');
+        out
+    }
+
+    fn import_json(&self, out: &mut String, import: Option<ImportId>) {
+        match import.and_then(|id| self.imports.try_get(id)) {
+            Some(import) => {
+                out.push_str("{\"module\":");
+                string(out, &import.module);
+                out.push_str(",\"name\":");
+                string(out, &import.name);
+                out.push('}');
+            }
+            None => out.push_str("null"),
+        }
+    }
+}
+
+fn list<T>(out: &mut String, items: impl Iterator<Item = T>, mut f: impl FnMut(&mut String, T)) {
+    out.push('[');
+    for (i, item) in items.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        f(out, item);
+    }
+    out.push(']');
+}
+
+fn types(out: &mut String, types: &[ValType]) {
+    list(out, types.iter(), |out, ty| {
+        write!(out, "\"{}\"", ty).unwrap()
+    });
+}
+
+fn optional(out: &mut String, value: Option<u32>) {
+    match value {
+        Some(value) => write!(out, "{}", value).unwrap(),
+        None => out.push_str("null"),
+    }
+}
+
+/// Write `s` as a JSON string.
+fn string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
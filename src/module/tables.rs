@@ -10,13 +10,15 @@ use anyhow::bail;
 pub type TableId = Id<Table>;
 
 /// A table in the wasm.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Table {
     id: TableId,
     /// The initial size of this table
     pub initial: u32,
     /// The maximum size of this table
     pub maximum: Option<u32>,
+    /// The type of the indices into this table
+    pub index_type: IndexType,
     /// Which kind of table this is
     pub kind: TableKind,
     /// Whether or not this table is imported, and if so what imports it.
@@ -25,8 +27,32 @@ pub struct Table {
 
 impl Tombstone for Table {}
 
+/// The type of the indices into a table.
+///
+/// Tables are indexed by `i32`s unless they are 64-bit tables, as allowed by
+/// the memory64 proposal. The index type of a table is the type of the index
+/// operands of `call_indirect` and the `table.*` instructions that access it,
+/// and of the offsets of its active element segments.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum IndexType {
+    /// Indices are `i32`s.
+    I32,
+    /// Indices are `i64`s.
+    I64,
+}
+
+impl IndexType {
+    /// The value type of indices of this type.
+    pub fn value_type(self) -> ValType {
+        match self {
+            IndexType::I32 => ValType::I32,
+            IndexType::I64 => ValType::I64,
+        }
+    }
+}
+
 /// The kinds of tables that can exist
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum TableKind {
     /// A table of `anyfunc` functions.
     ///
@@ -56,7 +82,7 @@ impl TableKind {
 }
 
 /// Components of a table of functions (`anyfunc` table)
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct FunctionTable {
     /// Layout of this function table that we know of, or those elements which
     /// have constant initializers.
@@ -68,7 +94,7 @@ pub struct FunctionTable {
 }
 
 /// Components of a table of `anyref`
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct AnyrefTable {
     // currently intentionally empty
 }
@@ -78,6 +104,49 @@ impl Table {
     pub fn id(&self) -> TableId {
         self.id
     }
+
+    /// Reserve `count` contiguous slots in this function table, for functions
+    /// placed there later, with `place_function` or at runtime, and return
+    /// the index of the first one.
+    ///
+    /// The slots are added at the end of the table, which grows to make room
+    /// for them, and are null to begin with.
+    pub fn reserve_function_slots(&mut self, count: u32) -> Result<u32> {
+        let funcs = match &self.kind {
+            TableKind::Function(funcs) => funcs,
+            TableKind::Anyref(_) => bail!("cannot reserve function slots in an `anyref` table"),
+        };
+        // Elements at relative offsets may be placed anywhere.
+        if !funcs.relative_elements.is_empty() {
+            bail!("cannot reserve slots in a table with elements at relative offsets");
+        }
+        let start = self.initial.max(funcs.elements.len() as u32);
+        let end = match start.checked_add(count) {
+            Some(end) if end <= self.maximum.unwrap_or(u32::MAX) => end,
+            _ => bail!("the table is too small to reserve {} more slots", count),
+        };
+        self.initial = end;
+        Ok(start)
+    }
+
+    /// Place `func` in the slot at `index` of this function table, so that it
+    /// is initialized there by an active element segment.
+    ///
+    /// Returns the function that was placed in the slot before, if any.
+    pub fn place_function(&mut self, index: u32, func: FunctionId) -> Result<Option<FunctionId>> {
+        if index >= self.initial {
+            bail!("slot {} is out of the table's bounds", index);
+        }
+        let funcs = match &mut self.kind {
+            TableKind::Function(funcs) => funcs,
+            TableKind::Anyref(_) => bail!("cannot place functions in an `anyref` table"),
+        };
+        let index = index as usize;
+        if funcs.elements.len() <= index {
+            funcs.elements.resize(index + 1, None);
+        }
+        Ok(funcs.elements[index].replace(func))
+    }
 }
 
 impl Emit for Table {
@@ -86,9 +155,13 @@ impl Emit for Table {
             TableKind::Function(_) => {
                 cx.encoder.byte(0x70); // the `anyfunc` type
             }
-            TableKind::Anyref(_) => ValType::Anyref.emit(&mut cx.encoder),
+            TableKind::Anyref(_) => ValType::Anyref.emit(&mut cx.encoder, cx.indices),
+        }
+        let mut flags = self.maximum.is_some() as u8;
+        if self.index_type == IndexType::I64 {
+            flags |= 0x04;
         }
-        cx.encoder.byte(self.maximum.is_some() as u8);
+        cx.encoder.byte(flags);
         cx.encoder.u32(self.initial);
         if let Some(m) = self.maximum {
             cx.encoder.u32(m);
@@ -97,7 +170,7 @@ impl Emit for Table {
 }
 
 /// The set of tables in this module.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct ModuleTables {
     /// The arena containing this module's tables.
     arena: TombstoneArena<Table>,
@@ -117,6 +190,7 @@ impl ModuleTables {
             id,
             initial,
             maximum: max,
+            index_type: IndexType::I32,
             kind,
             import: Some(import),
         })
@@ -124,12 +198,16 @@ impl ModuleTables {
 
     /// Construct a new table, that does not originate from any of the input
     /// wasm tables.
+    ///
+    /// The new table is indexed by `i32`s; set its `index_type` to make it a
+    /// 64-bit table instead.
     pub fn add_local(&mut self, initial: u32, max: Option<u32>, kind: TableKind) -> TableId {
         let id = self.arena.next_id();
         let id2 = self.arena.alloc(Table {
             id,
             initial,
             maximum: max,
+            index_type: IndexType::I32,
             kind,
             import: None,
         });
@@ -142,6 +220,12 @@ impl ModuleTables {
         &self.arena[table]
     }
 
+    /// Get the table with the given id, if it is one of this module's and
+    /// hasn't been deleted.
+    pub(crate) fn try_get(&self, id: TableId) -> Option<&Table> {
+        self.arena.get(id)
+    }
+
     /// Returns the actual table associated with an ID
     pub fn get_mut(&mut self, table: TableId) -> &mut Table {
         &mut self.arena[table]
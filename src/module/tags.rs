@@ -0,0 +1,130 @@
+//! Exception and control tags used in a wasm module.
+//!
+//! Tags are currently only used by `suspend` and `resume` from the
+//! stack-switching proposal, and are only available with the `unstable`
+//! feature.
+
+use crate::emit::{Emit, EmitContext, Section};
+use crate::error::Result;
+use crate::parse::IndicesToIds;
+use crate::tombstone_arena::{Id, Tombstone, TombstoneArena};
+use crate::{Module, TypeId};
+use anyhow::{bail, Context};
+
+/// The id of a tag.
+pub type TagId = Id<Tag>;
+
+/// A tag in the wasm.
+#[derive(Debug, Clone)]
+pub struct Tag {
+    id: TagId,
+    /// The function type of this tag.
+    ///
+    /// Its parameters are the values passed along when suspending with this
+    /// tag, and its results are the values received on resumption.
+    pub ty: TypeId,
+}
+
+impl Tombstone for Tag {}
+
+impl Tag {
+    /// Return the id of this tag
+    pub fn id(&self) -> TagId {
+        self.id
+    }
+}
+
+impl Emit for Tag {
+    fn emit(&self, cx: &mut EmitContext) {
+        // The attribute byte, which must be zero.
+        cx.encoder.byte(0x00);
+        cx.encoder.u32(cx.indices.get_type_index(self.ty));
+    }
+}
+
+/// The set of tags in this module.
+#[derive(Debug, Clone, Default)]
+pub struct ModuleTags {
+    arena: TombstoneArena<Tag>,
+}
+
+impl ModuleTags {
+    /// Construct a new tag with the given function type.
+    pub fn add(&mut self, ty: TypeId) -> TagId {
+        let id = self.arena.next_id();
+        let id2 = self.arena.alloc(Tag { id, ty });
+        debug_assert_eq!(id, id2);
+        id
+    }
+
+    /// Gets a reference to a tag given its id
+    pub fn get(&self, id: TagId) -> &Tag {
+        &self.arena[id]
+    }
+
+    /// Gets a reference to a tag given its id
+    pub fn get_mut(&mut self, id: TagId) -> &mut Tag {
+        &mut self.arena[id]
+    }
+
+    /// Removes a tag from this module.
+    ///
+    /// It is up to you to ensure that any potential references to the deleted
+    /// tag are also removed, eg `suspend` instructions.
+    pub fn delete(&mut self, id: TagId) {
+        self.arena.delete(id);
+    }
+
+    /// Get a shared reference to this module's tags.
+    pub fn iter(&self) -> impl Iterator<Item = &Tag> {
+        self.arena.iter().map(|(_, t)| t)
+    }
+
+    /// Get a mutable reference to this module's tags.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Tag> {
+        self.arena.iter_mut().map(|(_, t)| t)
+    }
+}
+
+impl Module {
+    /// Construct the set of tags within a module.
+    pub(crate) fn parse_tags(
+        &mut self,
+        mut reader: wasmparser::BinaryReader,
+        ids: &mut IndicesToIds,
+    ) -> Result<()> {
+        log::debug!("parse tag section");
+        let count = reader.read_var_u32()?;
+        for _ in 0..count {
+            if reader.read_u8()? != 0x00 {
+                bail!("invalid tag attribute");
+            }
+            let ty = ids
+                .get_type(reader.read_var_u32()?)
+                .context("invalid tag type")?;
+            let id = self.tags.add(ty);
+            ids.push_tag(id);
+        }
+        if !reader.eof() {
+            bail!("unexpected data at the end of the tag section");
+        }
+        Ok(())
+    }
+}
+
+impl Emit for ModuleTags {
+    fn emit(&self, cx: &mut EmitContext) {
+        log::debug!("emit tag section");
+        let tags = self.iter().count();
+        if tags == 0 {
+            return;
+        }
+
+        let mut cx = cx.start_section(Section::Tag);
+        cx.encoder.usize(tags);
+        for tag in self.iter() {
+            cx.indices.push_tag(tag.id());
+            tag.emit(&mut cx);
+        }
+    }
+}
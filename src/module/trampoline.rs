@@ -0,0 +1,211 @@
+//! Generating trampolines that adapt calls between function signatures.
+
+use crate::ir::{UnaryOp, Value};
+use crate::{FunctionBuilder, FunctionId, InstrSeqBuilder, LocalId, Module, TypeId, ValType};
+
+/// How a trampoline calls its target, and adapts its own parameters to the
+/// target's parameters and the target's results to its own results.
+#[derive(Clone, Debug)]
+pub struct AdaptSpec {
+    /// The function that the trampoline calls.
+    pub target: FunctionId,
+    /// The arguments to pass to the target, one for each of its parameters,
+    /// made from the trampoline's parameters.
+    pub params: Vec<AdaptValue>,
+    /// The values that the trampoline returns, one for each of its results,
+    /// made from the target's results.
+    pub results: Vec<AdaptValue>,
+}
+
+/// A value passed on by a trampoline, made from the values that it was given:
+/// its parameters when calling its target, or the target's results when
+/// returning.
+#[derive(Clone, Copy, Debug)]
+pub enum AdaptValue {
+    /// The value at this index, as it is.
+    Index(usize),
+    /// A constant, such as a default for a parameter that the caller doesn't
+    /// pass.
+    Const(Value),
+    /// The `i32` at this index, sign-extended to an `i64`.
+    ExtendS(usize),
+    /// The `i32` at this index, zero-extended to an `i64`.
+    ExtendU(usize),
+    /// The `i64` at this index, wrapped to an `i32`.
+    Wrap(usize),
+}
+
+impl AdaptSpec {
+    /// An adaptation calling `target` that passes the trampoline's
+    /// parameters and the target's results on as they are.
+    ///
+    /// Change `params` and `results` to reorder, drop, extend or add values.
+    pub fn new(module: &Module, target: FunctionId) -> AdaptSpec {
+        let ty = module.funcs.get(target).ty();
+        let (params, results) = module.types.params_results(ty);
+        AdaptSpec {
+            target,
+            params: (0..params.len()).map(AdaptValue::Index).collect(),
+            results: (0..results.len()).map(AdaptValue::Index).collect(),
+        }
+    }
+}
+
+impl AdaptValue {
+    /// The type of this value, when made from values of the types `from`.
+    fn ty(&self, from: &[ValType]) -> ValType {
+        let at = |index: usize| {
+            *from
+                .get(index)
+                .unwrap_or_else(|| panic!("adapted value index {} is out of bounds", index))
+        };
+        let converted = |index: usize, expected: ValType, ty: ValType| {
+            assert_eq!(
+                at(index),
+                expected,
+                "adapted value {} has the wrong type",
+                index
+            );
+            ty
+        };
+        match *self {
+            AdaptValue::Index(index) => at(index),
+            AdaptValue::Const(Value::I32(_)) => ValType::I32,
+            AdaptValue::Const(Value::I64(_)) => ValType::I64,
+            AdaptValue::Const(Value::F32(_)) => ValType::F32,
+            AdaptValue::Const(Value::F64(_)) => ValType::F64,
+            AdaptValue::Const(Value::V128(_)) => ValType::V128,
+            AdaptValue::ExtendS(index) | AdaptValue::ExtendU(index) => {
+                converted(index, ValType::I32, ValType::I64)
+            }
+            AdaptValue::Wrap(index) => converted(index, ValType::I64, ValType::I32),
+        }
+    }
+}
+
+impl Module {
+    /// Add a trampoline of type `from_ty` that calls `adapt.target`, which is
+    /// of type `to_ty`, adapting the arguments and results between the two
+    /// types as `adapt` describes.
+    ///
+    /// This is useful when a function has to be called with a different
+    /// signature than its own, like when merging modules whose imports and
+    /// exports don't quite match, or retargeting a function to another ABI.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `adapt.target` isn't of type `to_ty`, or if `adapt` doesn't
+    /// make values of the right types for `to_ty`'s parameters and
+    /// `from_ty`'s results.
+    pub fn add_trampoline(
+        &mut self,
+        from_ty: TypeId,
+        to_ty: TypeId,
+        adapt: AdaptSpec,
+    ) -> FunctionId {
+        assert_eq!(
+            self.funcs.get(adapt.target).ty(),
+            to_ty,
+            "the trampoline target isn't of type `to_ty`"
+        );
+        let (from_params, from_results) = self.types.params_results(from_ty);
+        let (from_params, from_results) = (from_params.to_vec(), from_results.to_vec());
+        let (to_params, to_results) = self.types.params_results(to_ty);
+        let (to_params, to_results) = (to_params.to_vec(), to_results.to_vec());
+        assert_eq!(
+            adapt.params.len(),
+            to_params.len(),
+            "the trampoline target takes {} parameters",
+            to_params.len()
+        );
+        assert_eq!(
+            adapt.results.len(),
+            from_results.len(),
+            "the trampoline returns {} results",
+            from_results.len()
+        );
+
+        let args = from_params
+            .iter()
+            .map(|ty| self.locals.add(*ty))
+            .collect::<Vec<_>>();
+        let mut builder = FunctionBuilder::new(&mut self.types, &from_params, &from_results);
+        let mut body = builder.func_body();
+
+        push_adapted(&mut body, &adapt.params, &from_params, &to_params, &args);
+        body.call(adapt.target);
+
+        let identity = adapt.results.len() == to_results.len()
+            && adapt
+                .results
+                .iter()
+                .enumerate()
+                .all(|(i, value)| matches!(value, AdaptValue::Index(index) if *index == i));
+        if identity {
+            types_match(&adapt.results, &to_results, &from_results);
+        } else {
+            // The target's results are on the stack, the last one on top, so
+            // pop them into locals to pick from.
+            let results = to_results
+                .iter()
+                .map(|ty| self.locals.add(*ty))
+                .collect::<Vec<_>>();
+            for local in results.iter().rev() {
+                body.local_set(*local);
+            }
+            push_adapted(
+                &mut body,
+                &adapt.results,
+                &to_results,
+                &from_results,
+                &results,
+            );
+        }
+
+        builder.finish(args, &mut self.funcs)
+    }
+}
+
+/// Check that `values`, made from values of the types `from`, have the types
+/// `to`.
+fn types_match(values: &[AdaptValue], from: &[ValType], to: &[ValType]) {
+    for (i, (value, ty)) in values.iter().zip(to).enumerate() {
+        assert_eq!(
+            value.ty(from),
+            *ty,
+            "adapted value {} has the wrong type",
+            i
+        );
+    }
+}
+
+/// Push `values`, made from the values of the types `from` in `locals`,
+/// which have to have the types `to`.
+fn push_adapted(
+    body: &mut InstrSeqBuilder,
+    values: &[AdaptValue],
+    from: &[ValType],
+    to: &[ValType],
+    locals: &[LocalId],
+) {
+    types_match(values, from, to);
+    for value in values {
+        match *value {
+            AdaptValue::Index(index) => {
+                body.local_get(locals[index]);
+            }
+            AdaptValue::Const(value) => {
+                body.const_(value);
+            }
+            AdaptValue::ExtendS(index) => {
+                body.local_get(locals[index]).unop(UnaryOp::I64ExtendSI32);
+            }
+            AdaptValue::ExtendU(index) => {
+                body.local_get(locals[index]).unop(UnaryOp::I64ExtendUI32);
+            }
+            AdaptValue::Wrap(index) => {
+                body.local_get(locals[index]).unop(UnaryOp::I32WrapI64);
+            }
+        }
+    }
+}
@@ -3,12 +3,18 @@
 use crate::arena_set::ArenaSet;
 use crate::emit::{Emit, EmitContext, Section};
 use crate::error::Result;
+use crate::ir::{dfs_in_order, Visitor};
 use crate::module::Module;
 use crate::parse::IndicesToIds;
 use crate::ty::{Type, TypeId, ValType};
+use anyhow::bail;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+#[cfg(feature = "unstable")]
+use std::collections::HashSet;
 
 /// The set of de-duplicated types within a module.
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct ModuleTypes {
     arena: ArenaSet<Type>,
 }
@@ -19,6 +25,12 @@ impl ModuleTypes {
         &self.arena[id]
     }
 
+    /// Get the type with the given id, if it is one of this module's and
+    /// hasn't been deleted.
+    pub(crate) fn try_get(&self, id: TypeId) -> Option<&Type> {
+        self.arena.get(id)
+    }
+
     /// Get a type associated with an ID
     pub fn get_mut(&mut self, id: TypeId) -> &mut Type {
         &mut self.arena[id]
@@ -80,6 +92,31 @@ impl ModuleTypes {
         ))
     }
 
+    /// Add a new `shared` function type to this module, and return its `Id`.
+    ///
+    /// Shared types are part of the in-progress shared-everything-threads
+    /// proposal.
+    #[cfg(feature = "unstable")]
+    pub fn add_shared(&mut self, params: &[ValType], results: &[ValType]) -> TypeId {
+        let id = self.arena.next_id();
+        self.arena.insert(Type::new_shared(
+            id,
+            params.to_vec().into_boxed_slice(),
+            results.to_vec().into_boxed_slice(),
+        ))
+    }
+
+    /// Add a new continuation type of the given function type to this module,
+    /// and return its `Id`.
+    ///
+    /// Continuation types are part of the in-progress stack-switching
+    /// proposal.
+    #[cfg(feature = "unstable")]
+    pub fn add_cont(&mut self, func_ty: TypeId) -> TypeId {
+        let id = self.arena.next_id();
+        self.arena.insert(Type::new_cont(id, func_ty))
+    }
+
     pub(crate) fn add_entry_ty(&mut self, results: &[ValType]) -> TypeId {
         let id = self.arena.next_id();
         self.arena.insert(Type::for_function_entry(
@@ -91,7 +128,12 @@ impl ModuleTypes {
     /// Find the existing type for the given parameters and results.
     pub fn find(&self, params: &[ValType], results: &[ValType]) -> Option<TypeId> {
         self.arena.iter().find_map(|(id, ty)| {
-            if !ty.is_for_function_entry() && ty.params() == params && ty.results() == results {
+            if !ty.is_for_function_entry()
+                && !ty.is_shared()
+                && ty.cont_of().is_none()
+                && ty.params() == params
+                && ty.results() == results
+            {
                 Some(id)
             } else {
                 None
@@ -112,35 +154,65 @@ impl ModuleTypes {
 
 impl Module {
     /// Construct the set of types within a module.
+    ///
+    /// The section is decoded by hand to also parse the kinds of types that
+    /// `wasmparser` doesn't know about, from the proposals behind the
+    /// `unstable` feature.
     pub(crate) fn parse_types(
         &mut self,
-        section: wasmparser::TypeSectionReader,
+        mut reader: wasmparser::BinaryReader,
         ids: &mut IndicesToIds,
     ) -> Result<()> {
         log::debug!("parsing type section");
-        for ty in section {
-            let fun_ty = ty?;
+        let count = reader.read_var_u32()?;
+        for _ in 0..count {
             let id = self.types.arena.next_id();
-            let params = fun_ty
-                .params
-                .iter()
-                .map(ValType::parse)
-                .collect::<Result<Vec<_>>>()?
-                .into_boxed_slice();
-            let results = fun_ty
-                .returns
-                .iter()
-                .map(ValType::parse)
-                .collect::<Result<Vec<_>>>()?
-                .into_boxed_slice();
-            let id = self.types.arena.insert(Type::new(id, params, results));
+            let ty = match reader.read_u8()? {
+                0x60 => {
+                    let params = parse_val_types(&mut reader, ids)?;
+                    let results = parse_val_types(&mut reader, ids)?;
+                    Type::new(id, params, results)
+                }
+                #[cfg(feature = "unstable")]
+                0x65 => {
+                    if reader.read_u8()? != 0x60 {
+                        bail!("only function types can be `shared`");
+                    }
+                    let params = parse_val_types(&mut reader, ids)?;
+                    let results = parse_val_types(&mut reader, ids)?;
+                    Type::new_shared(id, params, results)
+                }
+                #[cfg(feature = "unstable")]
+                0x5d => {
+                    let func_ty = reader.read_var_s33()?;
+                    if func_ty < 0 {
+                        bail!("continuation types must be of a function type index");
+                    }
+                    Type::new_cont(id, ids.get_type(func_ty as u32)?)
+                }
+                form => bail!("unsupported type form {:#x}", form),
+            };
+            let id = self.types.arena.insert(ty);
             ids.push_type(id);
         }
+        if !reader.eof() {
+            bail!("unexpected data at the end of the type section");
+        }
 
         Ok(())
     }
 }
 
+/// Parse a vector of value types, the parameters or results of a function
+/// type.
+fn parse_val_types(
+    reader: &mut wasmparser::BinaryReader,
+    ids: &IndicesToIds,
+) -> Result<Box<[ValType]>> {
+    let count = reader.read_var_u32()?;
+    (0..count).map(|_| ValType::read(reader, ids)).collect()
+}
+
 impl Emit for ModuleTypes {
     fn emit(&self, cx: &mut EmitContext) {
         log::debug!("emitting type section");
@@ -161,9 +233,77 @@ impl Emit for ModuleTypes {
         // Sort for deterministic ordering.
         tys.sort_by_key(|&(_, ty)| ty);
 
+        if cx.module.config.compression_friendly {
+            // Put the most used types first, so that they get the shortest
+            // indices, and the same indices in every module. Continuation
+            // types still come after the function types they refer to.
+            let uses = type_uses(cx.module);
+            tys.sort_by_key(|&(id, ty)| {
+                let uses = uses.get(&id).copied().unwrap_or(0);
+                (ty.cont_of().is_some(), Reverse(uses))
+            });
+        }
+
+        // Types can only refer to the types before them.
+        #[cfg(feature = "unstable")]
+        let tys = referenced_first(tys);
+
         for (id, ty) in tys {
             cx.indices.push_type(id);
             ty.emit(&mut cx);
         }
     }
 }
+
+/// Reorder `tys` so that the types each of them refers to come before it, and
+/// otherwise keep them in the same order.
+#[cfg(feature = "unstable")]
+fn referenced_first(tys: Vec<(TypeId, &Type)>) -> Vec<(TypeId, &Type)> {
+    fn visit<'a>(
+        id: TypeId,
+        tys: &HashMap<TypeId, &'a Type>,
+        seen: &mut HashSet<TypeId>,
+        ordered: &mut Vec<(TypeId, &'a Type)>,
+    ) {
+        let ty = match tys.get(&id) {
+            Some(ty) if seen.insert(id) => *ty,
+            _ => return,
+        };
+        for referenced in ty.referenced_types() {
+            visit(referenced, tys, seen, ordered);
+        }
+        ordered.push((id, ty));
+    }
+
+    let mut ordered = Vec::with_capacity(tys.len());
+    let mut seen = HashSet::new();
+    let by_id = tys.iter().copied().collect();
+    for (id, _) in tys {
+        visit(id, &by_id, &mut seen, &mut ordered);
+    }
+    ordered
+}
+
+/// Count how many times each type is referred to in `module`.
+fn type_uses(module: &Module) -> HashMap<TypeId, usize> {
+    struct CountUses<'a>(&'a mut HashMap<TypeId, usize>);
+
+    impl<'instr> Visitor<'instr> for CountUses<'_> {
+        fn visit_type_id(&mut self, &ty: &TypeId) {
+            *self.0.entry(ty).or_insert(0) += 1;
+        }
+    }
+
+    let mut uses = HashMap::new();
+    for func in module.funcs.iter() {
+        *uses.entry(func.ty()).or_insert(0) += 1;
+    }
+    for (_, func) in module.funcs.iter_local() {
+        dfs_in_order(&mut CountUses(&mut uses), func, func.entry_block());
+    }
+    #[cfg(feature = "unstable")]
+    for tag in module.tags.iter() {
+        *uses.entry(tag.ty).or_insert(0) += 1;
+    }
+    uses
+}
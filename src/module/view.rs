@@ -0,0 +1,60 @@
+//! Frozen modules, for sharing between threads.
+
+use crate::Module;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// An immutable view of a `Module`, created with `Module::freeze`.
+///
+/// Cloning a view is cheap and doesn't copy the module, so views can be
+/// handed to many threads that each run read-only analyses of the same
+/// module in parallel. The module is accessed through `Deref`.
+#[derive(Clone, Debug)]
+pub struct ModuleView {
+    module: Arc<Module>,
+}
+
+impl Module {
+    /// Freeze this module into an immutable view that can be cheaply cloned
+    /// and shared between threads.
+    pub fn freeze(self) -> ModuleView {
+        ModuleView {
+            module: Arc::new(self),
+        }
+    }
+}
+
+impl ModuleView {
+    /// Get the module back to modify it again, if this is its only view.
+    ///
+    /// Otherwise the view is returned as is.
+    pub fn try_into_module(self) -> Result<Module, ModuleView> {
+        Arc::try_unwrap(self.module).map_err(|module| ModuleView { module })
+    }
+
+    /// Get how many views of the module there are, including this one.
+    pub fn view_count(&self) -> usize {
+        Arc::strong_count(&self.module)
+    }
+}
+
+impl Deref for ModuleView {
+    type Target = Module;
+
+    fn deref(&self) -> &Module {
+        &self.module
+    }
+}
+
+impl From<Module> for ModuleView {
+    fn from(module: Module) -> ModuleView {
+        module.freeze()
+    }
+}
+
+// Views are only useful as long as modules can be shared between threads.
+fn _assert_module_is_send_and_sync() {
+    fn assert<T: Send + Sync>() {}
+    assert::<Module>();
+    assert::<ModuleView>();
+}
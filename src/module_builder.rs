@@ -0,0 +1,139 @@
+//! Building whole modules at once.
+
+use crate::{ExportItem, FunctionBuilder, FunctionId, GlobalId, InitExpr, InstrSeqBuilder};
+use crate::{LocalId, MemoryId, Module, ModuleConfig, ModuleLocals, ValType};
+
+/// Build a whole `Module` from descriptions of its items.
+///
+/// This takes care of adding types, imports and locals to the right
+/// collections of the module, so that building a module only needs a call
+/// per item.
+///
+/// # Example
+///
+/// ```
+/// use walrus::{ModuleBuilder, ValType};
+///
+/// let mut builder = ModuleBuilder::new();
+/// let log = builder.import_func("env", "log", &[ValType::I32], &[]);
+/// builder.memory(1, None);
+/// let main = builder.func("main", &[ValType::I32], &[], |body, args, _| {
+///     body.local_get(args[0]).call(log);
+/// });
+/// builder.export("main", main);
+/// let wasm = builder.finish().emit_wasm();
+/// ```
+#[derive(Debug, Default)]
+pub struct ModuleBuilder {
+    module: Module,
+}
+
+impl ModuleBuilder {
+    /// Create a builder for an empty module.
+    pub fn new() -> ModuleBuilder {
+        ModuleBuilder::default()
+    }
+
+    /// Create a builder for an empty module with the given configuration.
+    pub fn with_config(config: ModuleConfig) -> ModuleBuilder {
+        ModuleBuilder {
+            module: Module::with_config(config),
+        }
+    }
+
+    /// Import a function of the given type, named after its import.
+    pub fn import_func(
+        &mut self,
+        module: &str,
+        name: &str,
+        params: &[ValType],
+        results: &[ValType],
+    ) -> FunctionId {
+        let ty = self.module.types.add(params, results);
+        let (func, _) = self.module.add_import_func(module, name, ty);
+        self.module.funcs.get_mut(func).name = Some(name.to_string());
+        func
+    }
+
+    /// Import a memory of `initial` pages, growable up to `maximum` pages.
+    pub fn import_memory(
+        &mut self,
+        module: &str,
+        name: &str,
+        initial: u32,
+        maximum: Option<u32>,
+    ) -> MemoryId {
+        let (memory, _) = self
+            .module
+            .add_import_memory(module, name, false, initial, maximum);
+        memory
+    }
+
+    /// Import a global of the given type.
+    pub fn import_global(
+        &mut self,
+        module: &str,
+        name: &str,
+        ty: ValType,
+        mutable: bool,
+    ) -> GlobalId {
+        self.module.add_import_global(module, name, ty, mutable).0
+    }
+
+    /// Define a memory of `initial` pages, growable up to `maximum` pages.
+    pub fn memory(&mut self, initial: u32, maximum: Option<u32>) -> MemoryId {
+        self.module.memories.add_local(false, initial, maximum)
+    }
+
+    /// Define a global of the given type, initialized with `init`.
+    pub fn global(&mut self, ty: ValType, mutable: bool, init: InitExpr) -> GlobalId {
+        self.module.globals.add_local(ty, mutable, init)
+    }
+
+    /// Define a function named `name` of the given type.
+    ///
+    /// `body` builds the function's body. It is given the locals of the
+    /// function's arguments, and the module's locals to add any others to.
+    pub fn func<F>(
+        &mut self,
+        name: &str,
+        params: &[ValType],
+        results: &[ValType],
+        body: F,
+    ) -> FunctionId
+    where
+        F: FnOnce(&mut InstrSeqBuilder, &[LocalId], &mut ModuleLocals),
+    {
+        let args = params
+            .iter()
+            .map(|ty| self.module.locals.add(*ty))
+            .collect::<Vec<_>>();
+        let mut builder = FunctionBuilder::new(&mut self.module.types, params, results);
+        builder.name(name.to_string());
+        body(&mut builder.func_body(), &args, &mut self.module.locals);
+        builder.finish(args, &mut self.module.funcs)
+    }
+
+    /// Export `item` under `name`.
+    pub fn export(&mut self, name: &str, item: impl Into<ExportItem>) -> &mut ModuleBuilder {
+        self.module.exports.add(name, item);
+        self
+    }
+
+    /// Run `func` when the module is instantiated.
+    pub fn start(&mut self, func: FunctionId) -> &mut ModuleBuilder {
+        self.module.start = Some(func);
+        self
+    }
+
+    /// Get the module being built, for anything that this builder doesn't
+    /// cover.
+    pub fn module_mut(&mut self) -> &mut Module {
+        &mut self.module
+    }
+
+    /// Finish building, and get the built module.
+    pub fn finish(self) -> Module {
+        self.module
+    }
+}
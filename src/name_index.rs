@@ -0,0 +1,62 @@
+//! A lazily-built index from the names of items to their ids.
+
+use id_arena::Id;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+/// A lazily-built index from the names of items to their ids.
+///
+/// The index is built on the first lookup after it was last invalidated. Its
+/// owner must invalidate it whenever the set of items or their names might
+/// change, which is whenever mutable access to the items is handed out.
+pub(crate) struct NameIndex<T> {
+    // A `Mutex` rather than a `RefCell` so that the owner stays `Sync`.
+    index: Mutex<Option<HashMap<String, Id<T>>>>,
+}
+
+impl<T> NameIndex<T> {
+    /// Look up the id of the first item named `name`, building the index from
+    /// `items` first if necessary.
+    pub(crate) fn get<'a, I>(&self, name: &str, items: impl FnOnce() -> I) -> Option<Id<T>>
+    where
+        I: Iterator<Item = (Id<T>, &'a str)>,
+    {
+        let mut index = self.index.lock().unwrap();
+        let index = index.get_or_insert_with(|| {
+            let mut index = HashMap::new();
+            for (id, name) in items() {
+                index.entry(name.to_string()).or_insert(id);
+            }
+            index
+        });
+        index.get(name).cloned()
+    }
+
+    /// Throw away the index, so that it is rebuilt on the next lookup.
+    pub(crate) fn invalidate(&mut self) {
+        *self.index.get_mut().unwrap() = None;
+    }
+}
+
+impl<T> Default for NameIndex<T> {
+    fn default() -> NameIndex<T> {
+        NameIndex {
+            index: Mutex::new(None),
+        }
+    }
+}
+
+// Clones start out without an index, and build their own on the first lookup.
+impl<T> Clone for NameIndex<T> {
+    fn clone(&self) -> NameIndex<T> {
+        NameIndex::default()
+    }
+}
+
+impl<T> fmt::Debug for NameIndex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let built = self.index.lock().map(|i| i.is_some()).unwrap_or(false);
+        f.debug_struct("NameIndex").field("built", &built).finish()
+    }
+}
@@ -1,7 +1,9 @@
 use crate::map::IdHashMap;
+use crate::IdsToIndices;
 use crate::{DataId, ElementId, Function, FunctionId, GlobalId, Result};
 use crate::{LocalId, MemoryId, TableId, TypeId};
 use anyhow::bail;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 /// Maps from old indices in the original Wasm binary to `walrus` IDs.
 ///
@@ -23,9 +25,25 @@ pub struct IndicesToIds {
     funcs: Vec<FunctionId>,
     globals: Vec<GlobalId>,
     memories: Vec<MemoryId>,
-    elements: Vec<ElementId>,
+    elements: Vec<ElementIndex>,
     data: Vec<DataId>,
     locals: IdHashMap<Function, Vec<LocalId>>,
+    #[cfg(feature = "unstable")]
+    tags: Vec<crate::TagId>,
+}
+
+/// What an element segment index of the original Wasm binary refers to.
+#[derive(Debug)]
+enum ElementIndex {
+    Segment(ElementId),
+    /// An active segment, which is folded into its table's initializers.
+    /// It's dropped once the module is instantiated, so `table.init` and
+    /// `elem.drop` refer to the empty passive segment `stand_in` instead,
+    /// which is removed after parsing unless `used`.
+    Active {
+        stand_in: ElementId,
+        used: AtomicBool,
+    },
 }
 
 macro_rules! define_push_get {
@@ -60,10 +78,54 @@ define_push_get!(push_type, get_type, TypeId, types);
 define_push_get!(push_func, get_func, FunctionId, funcs);
 define_push_get!(push_global, get_global, GlobalId, globals);
 define_push_get!(push_memory, get_memory, MemoryId, memories);
-//define_push_get!(push_element, get_element, ElementId, elements);
 define_push_get!(push_data, get_data, DataId, data);
+#[cfg(feature = "unstable")]
+define_push_get!(push_tag, get_tag, crate::TagId, tags);
 
 impl IndicesToIds {
+    /// Pushes a new element segment ID to map it to the next index internally.
+    pub(crate) fn push_element(&mut self, id: ElementId) -> u32 {
+        self.elements.push(ElementIndex::Segment(id));
+        (self.elements.len() - 1) as u32
+    }
+
+    /// Pushes an active element segment, which is folded into its table's
+    /// initializers, with the empty passive segment `stand_in` that
+    /// `table.init` and `elem.drop` refer to instead.
+    pub(crate) fn push_active_element(&mut self, stand_in: ElementId) -> u32 {
+        self.elements.push(ElementIndex::Active {
+            stand_in,
+            used: AtomicBool::new(false),
+        });
+        (self.elements.len() - 1) as u32
+    }
+
+    /// Gets the ID for a particular element segment index.
+    ///
+    /// If the index did not exist in the original Wasm binary, an `Err` is
+    /// returned. Active element segments get the ID of an empty passive
+    /// segment, since they are dropped once the module is instantiated.
+    pub fn get_element(&self, index: u32) -> Result<ElementId> {
+        match self.elements.get(index as usize) {
+            Some(ElementIndex::Segment(id)) => Ok(*id),
+            Some(ElementIndex::Active { stand_in, used }) => {
+                used.store(true, Ordering::Relaxed);
+                Ok(*stand_in)
+            }
+            None => bail!("index `{}` is out of bounds for elements", index),
+        }
+    }
+
+    /// The stand-ins of active element segments that nothing refers to.
+    pub(crate) fn unused_active_elements(&self) -> impl Iterator<Item = ElementId> + '_ {
+        self.elements.iter().filter_map(|index| match index {
+            ElementIndex::Active { stand_in, used } if !used.load(Ordering::Relaxed) => {
+                Some(*stand_in)
+            }
+            _ => None,
+        })
+    }
+
     /// Pushes a new local ID to map it to the next index internally
     pub(crate) fn push_local(&mut self, function: FunctionId, id: LocalId) -> u32 {
         let list = self.locals.entry(function).or_insert(Vec::new());
@@ -82,4 +144,101 @@ impl IndicesToIds {
             None => bail!("index `{}` is out of bounds for local", index,),
         }
     }
+
+    /// The functions of the original Wasm binary, by their index.
+    pub(crate) fn funcs(&self) -> &[FunctionId] {
+        &self.funcs
+    }
+
+    /// The functions, globals, tables and memories of the original Wasm
+    /// binary, by their index.
+    pub(crate) fn index_spaces(&self) -> IndexSpaces {
+        IndexSpaces {
+            funcs: self.funcs.clone(),
+            globals: self.globals.clone(),
+            tables: self.tables.clone(),
+            memories: self.memories.clone(),
+        }
+    }
+}
+
+/// The functions, globals, tables and memories at each index of an encoding of
+/// a module, either the binary it was parsed from or one of its emits.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IndexSpaces {
+    pub(crate) funcs: Vec<FunctionId>,
+    pub(crate) globals: Vec<GlobalId>,
+    pub(crate) tables: Vec<TableId>,
+    pub(crate) memories: Vec<MemoryId>,
+}
+
+impl IndexSpaces {
+    /// Map these indices to the indices that `emitted` assigned to the same
+    /// IDs.
+    pub(crate) fn remap(&self, emitted: &IdsToIndices) -> IndexRemap {
+        IndexRemap {
+            funcs: self
+                .funcs
+                .iter()
+                .map(|id| emitted.find_func_index(*id))
+                .collect(),
+            globals: self
+                .globals
+                .iter()
+                .map(|id| emitted.find_global_index(*id))
+                .collect(),
+            tables: self
+                .tables
+                .iter()
+                .map(|id| emitted.find_table_index(*id))
+                .collect(),
+            memories: self
+                .memories
+                .iter()
+                .map(|id| emitted.find_memory_index(*id))
+                .collect(),
+        }
+    }
+}
+
+/// Maps from the indices that custom sections hold to the new indices that the
+/// same functions, globals, tables and memories are emitted at.
+///
+/// The old indices are those of the original Wasm binary on the first emit of
+/// a module, and those of the last emit that wrote walrus's own encoding of the
+/// module afterwards, which the custom sections were brought up to date with.
+///
+/// This is passed to `CustomSection::apply_index_remap`, for custom sections
+/// that hold raw indices rather than `walrus` IDs.
+#[derive(Debug, Default)]
+pub struct IndexRemap {
+    funcs: Vec<Option<u32>>,
+    globals: Vec<Option<u32>>,
+    tables: Vec<Option<u32>>,
+    memories: Vec<Option<u32>>,
+}
+
+impl IndexRemap {
+    /// Gets the new index of the function at the old `index`.
+    ///
+    /// Returns `None` if there was no function at the old index, or if the
+    /// function was deleted since.
+    pub fn func(&self, index: u32) -> Option<u32> {
+        self.funcs.get(index as usize).copied().flatten()
+    }
+
+    /// Gets the new index of the global at the old `index`, like `func`.
+    pub fn global(&self, index: u32) -> Option<u32> {
+        self.globals.get(index as usize).copied().flatten()
+    }
+
+    /// Gets the new index of the table at the old `index`, like `func`.
+    pub fn table(&self, index: u32) -> Option<u32> {
+        self.tables.get(index as usize).copied().flatten()
+    }
+
+    /// Gets the new index of the memory at the old `index`, like `func`.
+    pub fn memory(&self, index: u32) -> Option<u32> {
+        self.memories.get(index as usize).copied().flatten()
+    }
 }
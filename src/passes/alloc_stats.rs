@@ -0,0 +1,434 @@
+//! Track heap allocations in linear memory.
+//!
+//! This pass interposes on a module's allocator, to debug heap growth
+//! without modifying the program's source. Every use of the allocator's
+//! functions, other than by the allocator itself, is redirected to a wrapper
+//! that calls the allocator and updates allocation statistics in linear
+//! memory. The statistics are `STATS_SIZE` bytes of `i64`s:
+//!
+//! * `ALLOCATIONS`: the number of successful allocations,
+//! * `FREES`: the number of frees of non-null pointers,
+//! * `REALLOCATIONS`: the number of successful reallocations,
+//! * `LIVE_BYTES`: the number of bytes currently allocated,
+//! * `PEAK_BYTES`: the largest that `LIVE_BYTES` has been, and
+//! * `TOTAL_BYTES`: the number of bytes requested by all allocations and
+//!   reallocations.
+//!
+//! Rust's allocator functions are told the size of the blocks that they free,
+//! but C's `free` isn't. For C allocators, live and peak bytes are only
+//! tracked when a function like `malloc_usable_size` tells them, and they are
+//! in terms of the usable sizes of blocks, which may be larger than the
+//! requested sizes.
+
+use crate::ir::*;
+use crate::passes::record_replay::redirect;
+use crate::{ExportItem, FunctionBuilder, FunctionId, FunctionKind, InstrSeqBuilder, LocalId};
+use crate::{MemoryId, Module, Result, ValType};
+use anyhow::bail;
+use std::collections::{HashMap, HashSet};
+
+/// The offset of the number of allocations in the statistics.
+pub const ALLOCATIONS: u32 = 0;
+/// The offset of the number of frees in the statistics.
+pub const FREES: u32 = 8;
+/// The offset of the number of reallocations in the statistics.
+pub const REALLOCATIONS: u32 = 16;
+/// The offset of the number of live bytes in the statistics.
+pub const LIVE_BYTES: u32 = 24;
+/// The offset of the peak number of live bytes in the statistics.
+pub const PEAK_BYTES: u32 = 32;
+/// The offset of the total number of requested bytes in the statistics.
+pub const TOTAL_BYTES: u32 = 40;
+/// The size of the statistics, in bytes.
+pub const STATS_SIZE: u32 = 48;
+
+/// The functions of an allocator.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Allocator {
+    /// A C allocator.
+    C {
+        /// `malloc(size) -> ptr`
+        malloc: FunctionId,
+        /// `free(ptr)`
+        free: FunctionId,
+        /// `realloc(ptr, size) -> ptr`
+        realloc: Option<FunctionId>,
+        /// `calloc(count, size) -> ptr`
+        calloc: Option<FunctionId>,
+        /// `malloc_usable_size(ptr) -> size`, for tracking live bytes.
+        usable_size: Option<FunctionId>,
+    },
+    /// Rust's allocator functions.
+    Rust {
+        /// `__rust_alloc(size, align) -> ptr`
+        alloc: FunctionId,
+        /// `__rust_dealloc(ptr, size, align)`
+        dealloc: FunctionId,
+        /// `__rust_realloc(ptr, old_size, align, new_size) -> ptr`
+        realloc: Option<FunctionId>,
+        /// `__rust_alloc_zeroed(size, align) -> ptr`
+        alloc_zeroed: Option<FunctionId>,
+    },
+}
+
+impl Allocator {
+    /// Find the C allocator functions in `module`, by their usual names.
+    ///
+    /// Functions are looked up by their names in the "name" section, and
+    /// then by the names that they are exported or imported under.
+    pub fn find_c(module: &Module) -> Option<Allocator> {
+        Some(Allocator::C {
+            malloc: find(module, "malloc")?,
+            free: find(module, "free")?,
+            realloc: find(module, "realloc"),
+            calloc: find(module, "calloc"),
+            usable_size: find(module, "malloc_usable_size"),
+        })
+    }
+
+    /// Find Rust's allocator functions in `module`, by their names.
+    ///
+    /// Functions are looked up the same way as in `find_c`.
+    pub fn find_rust(module: &Module) -> Option<Allocator> {
+        Some(Allocator::Rust {
+            alloc: find(module, "__rust_alloc")?,
+            dealloc: find(module, "__rust_dealloc")?,
+            realloc: find(module, "__rust_realloc"),
+            alloc_zeroed: find(module, "__rust_alloc_zeroed"),
+        })
+    }
+
+    fn functions(&self) -> Vec<FunctionId> {
+        let (required, optional) = match *self {
+            Allocator::C {
+                malloc,
+                free,
+                realloc,
+                calloc,
+                usable_size,
+            } => (vec![malloc, free], vec![realloc, calloc, usable_size]),
+            Allocator::Rust {
+                alloc,
+                dealloc,
+                realloc,
+                alloc_zeroed,
+            } => (vec![alloc, dealloc], vec![realloc, alloc_zeroed]),
+        };
+        required
+            .into_iter()
+            .chain(optional.into_iter().flatten())
+            .collect()
+    }
+}
+
+fn find(module: &Module, name: &str) -> Option<FunctionId> {
+    if let Some(func) = module.funcs.by_name(name) {
+        return Some(func);
+    }
+    let exported = module.exports.iter().find_map(|e| match e.item {
+        ExportItem::Function(func) if e.name == name => Some(func),
+        _ => None,
+    });
+    exported.or_else(|| {
+        module.funcs.iter().find_map(|f| match &f.kind {
+            FunctionKind::Import(i) if module.imports.get(i.import).name == name => Some(f.id()),
+            _ => None,
+        })
+    })
+}
+
+/// Where to keep the statistics, and which allocator to track.
+#[derive(Debug, Copy, Clone)]
+pub struct Config {
+    /// The memory holding the statistics, and the allocator's heap.
+    pub memory: MemoryId,
+    /// The address of the statistics, which must be 8-byte aligned, have room
+    /// for `STATS_SIZE` bytes, and start out zeroed.
+    pub base: u32,
+    /// The allocator to track.
+    pub allocator: Allocator,
+}
+
+/// Track allocations by the allocator in `config` throughout `module`.
+///
+/// Calls, element segment members and exports of the allocator's functions
+/// are redirected to the tracking wrappers, except within the allocator's own
+/// functions. Fails if the allocator's functions don't have the expected
+/// types, with `i32` pointers and sizes.
+pub fn run(module: &mut Module, config: &Config) -> Result<()> {
+    if config.base & 7 != 0 {
+        bail!("the statistics must be 8-byte aligned");
+    }
+    let mut wrappers = HashMap::new();
+    let mut wrap = |module: &mut Module, func: Option<FunctionId>, kind: Kind| -> Result<()> {
+        if let Some(func) = func {
+            let wrapper = config.wrap(module, func, kind)?;
+            wrappers.insert(func, wrapper);
+        }
+        Ok(())
+    };
+    match config.allocator {
+        Allocator::C {
+            malloc,
+            free,
+            realloc,
+            calloc,
+            usable_size,
+        } => {
+            wrap(module, Some(malloc), Kind::Malloc(usable_size))?;
+            wrap(module, Some(free), Kind::Free(usable_size))?;
+            wrap(module, realloc, Kind::Realloc(usable_size))?;
+            wrap(module, calloc, Kind::Calloc(usable_size))?;
+        }
+        Allocator::Rust {
+            alloc,
+            dealloc,
+            realloc,
+            alloc_zeroed,
+        } => {
+            wrap(module, Some(alloc), Kind::RustAlloc)?;
+            wrap(module, Some(dealloc), Kind::RustDealloc)?;
+            wrap(module, realloc, Kind::RustRealloc)?;
+            wrap(module, alloc_zeroed, Kind::RustAllocZeroed)?;
+        }
+    }
+
+    let allocator = config
+        .allocator
+        .functions()
+        .into_iter()
+        .collect::<HashSet<_>>();
+    redirect(module, &wrappers, &allocator);
+    for export in module.exports.iter_mut() {
+        if let ExportItem::Function(func) = &mut export.item {
+            if let Some(wrapper) = wrappers.get(func) {
+                *func = *wrapper;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Which allocator function a wrapper wraps, along with the function that
+/// gives the usable sizes of blocks for C allocators.
+#[derive(Copy, Clone)]
+enum Kind {
+    Malloc(Option<FunctionId>),
+    Free(Option<FunctionId>),
+    Realloc(Option<FunctionId>),
+    Calloc(Option<FunctionId>),
+    RustAlloc,
+    RustAllocZeroed,
+    RustDealloc,
+    RustRealloc,
+}
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::Malloc(_) => "malloc",
+            Kind::Free(_) => "free",
+            Kind::Realloc(_) => "realloc",
+            Kind::Calloc(_) => "calloc",
+            Kind::RustAlloc => "__rust_alloc",
+            Kind::RustAllocZeroed => "__rust_alloc_zeroed",
+            Kind::RustDealloc => "__rust_dealloc",
+            Kind::RustRealloc => "__rust_realloc",
+        }
+    }
+
+    fn ty(&self) -> (&'static [ValType], &'static [ValType]) {
+        use ValType::I32;
+        match self {
+            Kind::Malloc(_) => (&[I32], &[I32]),
+            Kind::Free(_) => (&[I32], &[]),
+            Kind::Realloc(_) | Kind::Calloc(_) | Kind::RustAlloc | Kind::RustAllocZeroed => {
+                (&[I32, I32], &[I32])
+            }
+            Kind::RustDealloc => (&[I32, I32, I32], &[]),
+            Kind::RustRealloc => (&[I32, I32, I32, I32], &[I32]),
+        }
+    }
+}
+
+impl Config {
+    fn memarg(&self, field: u32) -> MemArg {
+        MemArg {
+            align: 8,
+            offset: self.base + field,
+            encoding: None,
+        }
+    }
+
+    /// Add the `i64` that `push` pushes to `field`.
+    fn add(&self, body: &mut InstrSeqBuilder, field: u32, push: impl FnOnce(&mut InstrSeqBuilder)) {
+        body.i32_const(0).i32_const(0).load(
+            self.memory,
+            LoadKind::I64 { atomic: false },
+            self.memarg(field),
+        );
+        push(body);
+        body.binop(BinaryOp::I64Add).store(
+            self.memory,
+            StoreKind::I64 { atomic: false },
+            self.memarg(field),
+        );
+    }
+
+    fn count(&self, body: &mut InstrSeqBuilder, field: u32) {
+        self.add(body, field, |body| {
+            body.i64_const(1);
+        });
+    }
+
+    /// Add the size in `size` to the live bytes, and update the peak.
+    fn allocated(&self, body: &mut InstrSeqBuilder, size: impl FnOnce(&mut InstrSeqBuilder)) {
+        self.add(body, LIVE_BYTES, size);
+        let load = |body: &mut InstrSeqBuilder, field| {
+            let kind = LoadKind::I64 { atomic: false };
+            body.i32_const(0)
+                .load(self.memory, kind, self.memarg(field));
+        };
+        body.i32_const(0);
+        load(body, LIVE_BYTES);
+        load(body, PEAK_BYTES);
+        load(body, LIVE_BYTES);
+        load(body, PEAK_BYTES);
+        body.binop(BinaryOp::I64GtS).select(None).store(
+            self.memory,
+            StoreKind::I64 { atomic: false },
+            self.memarg(PEAK_BYTES),
+        );
+    }
+
+    /// Make a function that tracks calls to the allocator function `func`.
+    fn wrap(&self, module: &mut Module, func: FunctionId, kind: Kind) -> Result<FunctionId> {
+        let (params, results) = kind.ty();
+        if module.types.params_results(module.funcs.get(func).ty()) != (params, results) {
+            bail!("`{}` doesn't have the expected type", kind.name());
+        }
+        if let Kind::Malloc(Some(usable_size)) = kind {
+            let ty = module.funcs.get(usable_size).ty();
+            if module.types.params_results(ty) != (&[ValType::I32][..], &[ValType::I32][..]) {
+                bail!("`malloc_usable_size` doesn't have the expected type");
+            }
+        }
+
+        let args = params
+            .iter()
+            .map(|ty| module.locals.add(*ty))
+            .collect::<Vec<_>>();
+        let ret = module.locals.add(ValType::I32);
+        let old = module.locals.add(ValType::I64);
+        let extend = |body: &mut InstrSeqBuilder, local: LocalId| {
+            body.local_get(local).unop(UnaryOp::I64ExtendUI32);
+        };
+        let usable = |body: &mut InstrSeqBuilder, usable_size: FunctionId, ptr: LocalId| {
+            body.local_get(ptr)
+                .call(usable_size)
+                .unop(UnaryOp::I64ExtendUI32);
+        };
+
+        let mut builder = FunctionBuilder::new(&mut module.types, params, results);
+        builder.name(format!("alloc_stats:{}", kind.name()));
+        let mut body = builder.func_body();
+
+        // Before the call, measure what is about to be freed.
+        match kind {
+            Kind::Free(usable_size) | Kind::Realloc(usable_size) => {
+                body.local_get(args[0]).if_else(
+                    None,
+                    |then| match usable_size {
+                        Some(usable_size) => {
+                            usable(then, usable_size, args[0]);
+                            then.local_set(old);
+                        }
+                        None => {
+                            then.i64_const(0).local_set(old);
+                        }
+                    },
+                    |else_| {
+                        else_.i64_const(0).local_set(old);
+                    },
+                );
+                if let Kind::Free(_) = kind {
+                    body.local_get(args[0]).if_else(
+                        None,
+                        |then| {
+                            self.count(then, FREES);
+                            if usable_size.is_some() {
+                                self.add(then, LIVE_BYTES, |body| {
+                                    body.i64_const(0).local_get(old).binop(BinaryOp::I64Sub);
+                                });
+                            }
+                        },
+                        |_| {},
+                    );
+                }
+            }
+            _ => {}
+        }
+
+        for &arg in args.iter() {
+            body.local_get(arg);
+        }
+        body.call(func);
+        if results.is_empty() {
+            if let Kind::RustDealloc = kind {
+                self.count(&mut body, FREES);
+                self.add(&mut body, LIVE_BYTES, |body| {
+                    body.i64_const(0);
+                    extend(body, args[1]);
+                    body.binop(BinaryOp::I64Sub);
+                });
+            }
+            return Ok(builder.finish(args, &mut module.funcs));
+        }
+
+        // After the call, account for what was allocated, if anything.
+        body.local_tee(ret).if_else(
+            None,
+            |then| {
+                let field = match kind {
+                    Kind::Realloc(_) | Kind::RustRealloc => REALLOCATIONS,
+                    _ => ALLOCATIONS,
+                };
+                self.count(then, field);
+                let requested = |body: &mut InstrSeqBuilder| match kind {
+                    Kind::Calloc(_) => {
+                        extend(body, args[0]);
+                        extend(body, args[1]);
+                        body.binop(BinaryOp::I64Mul);
+                    }
+                    Kind::RustRealloc => extend(body, args[3]),
+                    Kind::Realloc(_) => extend(body, args[1]),
+                    _ => extend(body, args[0]),
+                };
+                self.add(then, TOTAL_BYTES, requested);
+                match kind {
+                    Kind::Malloc(Some(usable_size))
+                    | Kind::Calloc(Some(usable_size))
+                    | Kind::Realloc(Some(usable_size)) => {
+                        self.allocated(then, |body| {
+                            usable(body, usable_size, ret);
+                            body.local_get(old).binop(BinaryOp::I64Sub);
+                        });
+                    }
+                    Kind::Malloc(None) | Kind::Calloc(None) | Kind::Realloc(None) => {}
+                    Kind::RustAlloc | Kind::RustAllocZeroed => {
+                        self.allocated(then, |body| extend(body, args[0]))
+                    }
+                    Kind::RustRealloc => self.allocated(then, |body| {
+                        extend(body, args[3]);
+                        extend(body, args[1]);
+                        body.binop(BinaryOp::I64Sub);
+                    }),
+                    Kind::Free(_) | Kind::RustDealloc => unreachable!(),
+                }
+            },
+            |_| {},
+        );
+        body.local_get(ret);
+        Ok(builder.finish(args, &mut module.funcs))
+    }
+}
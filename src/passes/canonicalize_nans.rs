@@ -0,0 +1,168 @@
+//! Canonicalize the NaNs that float instructions produce.
+//!
+//! The sign and payload bits of the NaNs that float arithmetic produces
+//! aren't fully specified, so they may differ between engines, or even
+//! between runs, and a module that stores or reinterprets them can tell.
+//! Deterministic execution environments, such as blockchains, need every run
+//! to agree. This pass makes every float instruction that may produce a NaN
+//! produce the positive canonical NaN instead of any other NaN, which is the
+//! NaN with only the most significant payload bit set.
+//!
+//! Each such instruction is followed by a call to a generated function that
+//! replaces NaNs with the canonical one, and leaves other values unchanged.
+//! The instructions are the ones that `passes::nondeterminism` lists as
+//! `Cause::NanBits`. Float instructions that only operate on the sign bit,
+//! like `f32.neg`, keep NaNs the way they were, so they stay canonical.
+
+use crate::ir::*;
+use crate::passes::nondeterminism::{nan_type, NanType};
+use crate::{FunctionBuilder, FunctionId, Module, ValType};
+use std::collections::HashMap;
+
+/// The bits of the canonical `f32` NaN.
+pub const CANONICAL_NAN_F32: u32 = 0x7fc0_0000;
+
+/// The bits of the canonical `f64` NaN.
+pub const CANONICAL_NAN_F64: u64 = 0x7ff8_0000_0000_0000;
+
+/// How to canonicalize NaNs.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Canonicalize the results of SIMD instructions as well as scalar ones,
+    /// lane by lane.
+    ///
+    /// Environments that don't allow SIMD at all, or whose engines already
+    /// canonicalize SIMD results, can turn this off to save the checks.
+    pub simd: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { simd: true }
+    }
+}
+
+/// Canonicalize the NaNs that the float instructions of `module` produce.
+///
+/// Returns the number of instructions whose results are now canonicalized.
+pub fn run(module: &mut Module, options: &Options) -> usize {
+    let mut canonicalize = Canonicalize {
+        simd: options.simd,
+        types: Vec::new(),
+        funcs: HashMap::new(),
+        count: 0,
+    };
+
+    // Find which types need canonicalizing first, so that only the functions
+    // that are called get generated.
+    for (_, func) in module.funcs.iter_local() {
+        dfs_in_order(&mut canonicalize, func, func.entry_block());
+    }
+    for ty in canonicalize.types.clone() {
+        let func = canonicalize_function(module, ty);
+        canonicalize.funcs.insert(ty, func);
+    }
+
+    for (_, func) in module.funcs.iter_local_mut() {
+        let entry = func.entry_block();
+        dfs_pre_order_mut(&mut canonicalize, func, entry);
+    }
+    canonicalize.count
+}
+
+struct Canonicalize {
+    simd: bool,
+    // The types that need canonicalizing, in the order they first appear.
+    types: Vec<NanType>,
+    funcs: HashMap<NanType, FunctionId>,
+    count: usize,
+}
+
+impl Canonicalize {
+    fn nan_type(&self, instr: &Instr) -> Option<NanType> {
+        match nan_type(instr)? {
+            NanType::F32x4 | NanType::F64x2 if !self.simd => None,
+            ty => Some(ty),
+        }
+    }
+}
+
+impl<'instr> Visitor<'instr> for Canonicalize {
+    fn visit_instr(&mut self, instr: &'instr Instr, _: &'instr InstrLocId) {
+        if let Some(ty) = self.nan_type(instr) {
+            if !self.types.contains(&ty) {
+                self.types.push(ty);
+            }
+        }
+    }
+}
+
+impl VisitorMut for Canonicalize {
+    fn start_instr_seq_mut(&mut self, seq: &mut InstrSeq) {
+        if !seq.instrs.iter().any(|(i, _)| self.nan_type(i).is_some()) {
+            return;
+        }
+        let mut instrs = Vec::with_capacity(seq.instrs.len());
+        for (instr, loc) in seq.instrs.drain(..) {
+            let ty = self.nan_type(&instr);
+            instrs.push((instr, loc));
+            if let Some(ty) = ty {
+                let func = self.funcs[&ty];
+                instrs.push((Call { func }.into(), loc));
+                self.count += 1;
+            }
+        }
+        seq.instrs = instrs;
+    }
+}
+
+/// Create a function that replaces NaNs of type `ty` with the canonical NaN,
+/// lane by lane for SIMD types.
+fn canonicalize_function(module: &mut Module, ty: NanType) -> FunctionId {
+    let f32x4 = u128::from(CANONICAL_NAN_F32) * 0x0000_0001_0000_0001_0000_0001_0000_0001;
+    let f64x2 = u128::from(CANONICAL_NAN_F64) * 0x0000_0000_0000_0001_0000_0000_0000_0001;
+    let (name, val_ty, nan, ne) = match ty {
+        NanType::F32 => (
+            "canonicalize_nan_f32",
+            ValType::F32,
+            Value::F32(f32::from_bits(CANONICAL_NAN_F32)),
+            BinaryOp::F32Ne,
+        ),
+        NanType::F64 => (
+            "canonicalize_nan_f64",
+            ValType::F64,
+            Value::F64(f64::from_bits(CANONICAL_NAN_F64)),
+            BinaryOp::F64Ne,
+        ),
+        NanType::F32x4 => (
+            "canonicalize_nan_f32x4",
+            ValType::V128,
+            Value::V128(f32x4),
+            BinaryOp::F32x4Ne,
+        ),
+        NanType::F64x2 => (
+            "canonicalize_nan_f64x2",
+            ValType::V128,
+            Value::V128(f64x2),
+            BinaryOp::F64x2Ne,
+        ),
+    };
+
+    // Only NaNs aren't equal to themselves, so `x != x` is true for NaNs, or
+    // all ones in the lanes that hold NaNs.
+    let x = module.locals.add(val_ty);
+    let mut builder = FunctionBuilder::new(&mut module.types, &[val_ty], &[val_ty]);
+    builder.name(name.to_string());
+    let mut body = builder.func_body();
+    body.const_(nan)
+        .local_get(x)
+        .local_get(x)
+        .local_get(x)
+        .binop(ne);
+    if val_ty == ValType::V128 {
+        body.v128_bitselect();
+    } else {
+        body.select(None);
+    }
+    builder.finish(vec![x], &mut module.funcs)
+}
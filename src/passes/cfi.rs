@@ -0,0 +1,254 @@
+//! Harden indirect calls against corrupted tables.
+//!
+//! Engines only check that the target of a `call_indirect` has the expected
+//! signature. When an attacker can write to a table, for example through
+//! `table.set` with a corrupted operand, or through an embedder that puts
+//! host functions in exported tables, any function of the right signature
+//! can be called, including ones that were never meant to be called
+//! indirectly. This pass adds checks of its own:
+//!
+//! * Every function that can legitimately end up in a table, because it is
+//!   an element segment member, the target of a `ref.func`, or exported,
+//!   gets a type tag, and checks it in a prologue.
+//! * Every `call_indirect` stores the tag it expects in a global before the
+//!   call, and the prologue of the target compares it with its own tag and
+//!   clears it.
+//! * After the call, the global must have been cleared, so calls to targets
+//!   without a prologue trap as well.
+//!
+//! Imported functions that can end up in tables get local wrappers with a
+//! prologue, which replace them in element segments, `ref.func`s and
+//! exports. Tags are derived from types, so that any function can be called
+//! through a `call_indirect` of its own type, as before.
+
+use crate::ir::*;
+use crate::{
+    ExportItem, FunctionBuilder, FunctionId, FunctionKind, GlobalId, InitExpr, Module, TableKind,
+    TypeId, ValType,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Add tag checks to all indirect calls in `module`, and to the functions
+/// that they can call.
+///
+/// Returns the global that holds the tag expected by the indirect call in
+/// progress, which is zero between calls.
+pub fn run(module: &mut Module) -> GlobalId {
+    let targets = targets(module);
+    let expected = module
+        .globals
+        .add_local(ValType::I32, true, InitExpr::Value(Value::I32(0)));
+    let check_tag = check_tag(module, expected);
+    let check_cleared = check_cleared(module, expected);
+
+    let mut wrappers = HashMap::new();
+    for func in targets {
+        let ty = module.funcs.get(func).ty();
+        match &mut module.funcs.get_mut(func).kind {
+            FunctionKind::Local(local) => {
+                let entry = local.entry_block();
+                local
+                    .builder_mut()
+                    .instr_seq(entry)
+                    .instr_at(0, Const { value: tag(ty) })
+                    .instr_at(1, Call { func: check_tag });
+            }
+            _ => {
+                let wrapper = wrap(module, func, ty, check_tag);
+                wrappers.insert(func, wrapper);
+            }
+        }
+    }
+
+    for member in members_mut(module) {
+        if let Some(wrapper) = wrappers.get(member) {
+            *member = *wrapper;
+        }
+    }
+    for export in module.exports.iter_mut() {
+        if let ExportItem::Function(func) = &mut export.item {
+            if let Some(wrapper) = wrappers.get(func) {
+                *func = *wrapper;
+            }
+        }
+    }
+    let helpers = [check_tag, check_cleared];
+    let wrapper_ids = wrappers.values().copied().collect::<HashSet<_>>();
+    for (id, func) in module.funcs.iter_local_mut() {
+        if helpers.contains(&id) || wrapper_ids.contains(&id) {
+            continue;
+        }
+        let entry = func.entry_block();
+        dfs_pre_order_mut(
+            &mut Harden {
+                expected,
+                check_cleared,
+                wrappers: &wrappers,
+            },
+            func,
+            entry,
+        );
+    }
+    expected
+}
+
+/// The tag of functions of type `ty`, which is never zero.
+fn tag(ty: TypeId) -> Value {
+    Value::I32(ty.index() as i32 + 1)
+}
+
+/// The functions that can legitimately end up in tables.
+fn targets(module: &Module) -> Vec<FunctionId> {
+    #[derive(Default)]
+    struct Collect(HashSet<FunctionId>);
+
+    impl<'instr> Visitor<'instr> for Collect {
+        fn visit_ref_func(&mut self, instr: &RefFunc) {
+            self.0.insert(instr.func);
+        }
+    }
+
+    let mut collect = Collect::default();
+    for (_, func) in module.funcs.iter_local() {
+        dfs_in_order(&mut collect, func, func.entry_block());
+    }
+    for table in module.tables.iter() {
+        if let TableKind::Function(table) = &table.kind {
+            collect.0.extend(table.elements.iter().flatten());
+            for (_, members) in &table.relative_elements {
+                collect.0.extend(members.iter().flatten());
+            }
+        }
+    }
+    for elem in module.elements.iter() {
+        collect.0.extend(elem.members.iter().flatten());
+    }
+    for export in module.exports.iter() {
+        if let ExportItem::Function(func) = export.item {
+            collect.0.insert(func);
+        }
+    }
+    let mut targets = collect.0.into_iter().collect::<Vec<_>>();
+    targets.sort();
+    targets
+}
+
+/// The members of all active, passive and declared element segments.
+fn members_mut(module: &mut Module) -> Vec<&mut FunctionId> {
+    let mut members = Vec::new();
+    for table in module.tables.iter_mut() {
+        if let TableKind::Function(table) = &mut table.kind {
+            members.extend(table.elements.iter_mut().flatten());
+            for (_, elements) in &mut table.relative_elements {
+                members.extend(elements.iter_mut().flatten());
+            }
+        }
+    }
+    for elem in module.elements.iter_mut() {
+        members.extend(elem.members.iter_mut().flatten());
+    }
+    members
+}
+
+/// `cfi_check_tag(tag)`: trap if an indirect call is in progress that expects
+/// a different tag, and clear the expected tag otherwise.
+fn check_tag(module: &mut Module, expected: GlobalId) -> FunctionId {
+    let tag = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
+    builder.name("cfi_check_tag".to_string());
+    builder.func_body().global_get(expected).if_else(
+        None,
+        |then| {
+            then.global_get(expected)
+                .local_get(tag)
+                .binop(BinaryOp::I32Ne)
+                .if_else(
+                    None,
+                    |then| {
+                        then.unreachable();
+                    },
+                    |_| {},
+                )
+                .i32_const(0)
+                .global_set(expected);
+        },
+        |_| {},
+    );
+    builder.finish(vec![tag], &mut module.funcs)
+}
+
+/// `cfi_check_cleared()`: trap if the target of an indirect call didn't clear
+/// the expected tag.
+fn check_cleared(module: &mut Module, expected: GlobalId) -> FunctionId {
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.name("cfi_check_cleared".to_string());
+    builder.func_body().global_get(expected).if_else(
+        None,
+        |then| {
+            then.unreachable();
+        },
+        |_| {},
+    );
+    builder.finish(vec![], &mut module.funcs)
+}
+
+/// A local function checking the tag of, and then calling, the imported
+/// `func`.
+fn wrap(module: &mut Module, func: FunctionId, ty: TypeId, check_tag: FunctionId) -> FunctionId {
+    let (params, results) = module.types.params_results(ty);
+    let (params, results) = (params.to_vec(), results.to_vec());
+    let args = params
+        .iter()
+        .map(|ty| module.locals.add(*ty))
+        .collect::<Vec<_>>();
+    let mut builder = FunctionBuilder::new(&mut module.types, &params, &results);
+    if let Some(name) = &module.funcs.get(func).name {
+        builder.name(format!("cfi_wrapper_{}", name));
+    }
+    let mut body = builder.func_body();
+    body.instr(Const { value: tag(ty) }).call(check_tag);
+    for arg in &args {
+        body.local_get(*arg);
+    }
+    body.call(func);
+    builder.finish(args, &mut module.funcs)
+}
+
+struct Harden<'a> {
+    expected: GlobalId,
+    check_cleared: FunctionId,
+    wrappers: &'a HashMap<FunctionId, FunctionId>,
+}
+
+impl VisitorMut for Harden<'_> {
+    fn start_instr_seq_mut(&mut self, seq: &mut InstrSeq) {
+        if !seq
+            .instrs
+            .iter()
+            .any(|(instr, _)| matches!(instr, Instr::CallIndirect(_)))
+        {
+            return;
+        }
+        let mut instrs = Vec::with_capacity(seq.instrs.len());
+        for (instr, loc) in seq.instrs.drain(..) {
+            if let Instr::CallIndirect(call) = &instr {
+                let value = tag(call.ty);
+                instrs.push((Const { value }.into(), loc));
+                let global = self.expected;
+                instrs.push((GlobalSet { global }.into(), loc));
+                instrs.push((instr, loc));
+                let func = self.check_cleared;
+                instrs.push((Call { func }.into(), loc));
+            } else {
+                instrs.push((instr, loc));
+            }
+        }
+        seq.instrs = instrs;
+    }
+
+    fn visit_ref_func_mut(&mut self, instr: &mut RefFunc) {
+        if let Some(wrapper) = self.wrappers.get(&instr.func) {
+            instr.func = *wrapper;
+        }
+    }
+}
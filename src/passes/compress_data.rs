@@ -0,0 +1,374 @@
+//! Compress data segments, and decompress them when the module starts.
+//!
+//! Big data segments, like tables and embedded assets, often make up much of
+//! a module's size, and they compress well. This pass compresses the large
+//! active data segments of a memory with a small LZ77 codec in the style of
+//! LZ4, and makes them passive. A new start function copies each compressed
+//! segment to a scratch area with `memory.init`, decompresses it to where
+//! the segment used to be, and then clears the scratch area again, before
+//! calling the original start function. This trades some startup time for a
+//! smaller download, and requires the bulk memory proposal.
+//!
+//! The compressed format is a series of sequences, each of which is
+//!
+//! * a token byte, whose high nibble is the number of literal bytes, and
+//!   whose low nibble is the length of the match minus four,
+//! * more bytes of the number of literal bytes, if the high nibble is 15,
+//!   which are added to it up to and including the first byte that isn't
+//!   255,
+//! * the literal bytes, which are copied to the output,
+//! * and unless this is the last sequence, a little-endian `u16` offset and
+//!   more bytes of the match length, like for the literal bytes. The match
+//!   is copied byte by byte from as far back in the output as the offset.
+
+use crate::ir::*;
+use crate::{ActiveData, ActiveDataLocation, DataKind, FunctionBuilder, FunctionId, LocalId};
+use crate::{InstrSeqBuilder, MemoryId, Module, Result, ValType};
+use anyhow::bail;
+use std::collections::HashMap;
+
+const MIN_MATCH: usize = 4;
+const MAX_OFFSET: usize = 0xffff;
+const PAGE_SIZE: u32 = 1 << 16;
+
+/// Which data segments to compress.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Only compress segments of at least this many bytes.
+    pub min_size: usize,
+
+    /// The address of the scratch area that compressed segments are copied to
+    /// before decompressing them. It must not be in use when the module
+    /// starts, and must have room for the largest compressed segment.
+    ///
+    /// Defaults to right after the last data segment, which is where the
+    /// stack or the heap is with the usual memory layouts.
+    pub scratch: Option<u32>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            min_size: 1024,
+            scratch: None,
+        }
+    }
+}
+
+/// Compress the active data segments of `memory` that `options` selects,
+/// and decompress them in a new start function.
+///
+/// Segments are only compressed when that makes them smaller, and when they
+/// don't overlap other segments, since decompressing them later would
+/// overwrite the other segments. Returns the number of compressed segments.
+///
+/// Fails if any data segment of `memory` isn't at a constant address, or if
+/// `memory` is shared, since every thread would decompress the segments
+/// again.
+pub fn run(module: &mut Module, memory: MemoryId, options: &Options) -> Result<usize> {
+    if module.memories.get(memory).shared {
+        bail!("can't compress the data segments of a shared memory");
+    }
+    let mut segments = Vec::new();
+    for data in module.memories.get(memory).data_segments.iter() {
+        let data = module.data.get(*data);
+        let address = match &data.kind {
+            DataKind::Active(ActiveData {
+                location: ActiveDataLocation::Absolute(address),
+                ..
+            }) => *address,
+            _ => bail!("data segments must be at constant addresses"),
+        };
+        segments.push((data.id(), address, address + data.value.len() as u32));
+    }
+    segments.sort();
+    let data_end = segments.iter().map(|(_, _, end)| *end).max().unwrap_or(0);
+
+    let mut compressed = Vec::new();
+    for &(id, start, end) in segments.iter() {
+        let overlaps = segments
+            .iter()
+            .any(|&(other, s, e)| other != id && s < end && start < e);
+        let value = &module.data.get(id).value;
+        if overlaps || value.len() < options.min_size {
+            continue;
+        }
+        let packed = compress(value);
+        if packed.len() < value.len() {
+            compressed.push((id, start, packed));
+        }
+    }
+    if compressed.is_empty() {
+        return Ok(0);
+    }
+
+    let scratch = options.scratch.unwrap_or(data_end);
+    let scratch_len = compressed.iter().map(|(_, _, p)| p.len()).max().unwrap() as u32;
+    let end = match scratch.checked_add(scratch_len) {
+        Some(end) => end,
+        None => bail!("the scratch area doesn't fit in a 32-bit memory"),
+    };
+    let pages = end / PAGE_SIZE + u32::from(end % PAGE_SIZE != 0);
+    if module.memories.get(memory).initial < pages {
+        module.memories.get_mut(memory).set_initial(pages)?;
+    }
+
+    let decompress = decompressor(module, memory);
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.name("compress_data_init".to_string());
+    let mut body = builder.func_body();
+    for (id, address, packed) in compressed.iter() {
+        let len = packed.len() as u32;
+        body.i32_const(scratch as i32)
+            .memory_init_and_drop(memory, *id, len)
+            .i32_const(scratch as i32)
+            .i32_const(len as i32)
+            .i32_const(*address as i32)
+            .call(decompress);
+    }
+    body.i32_const(scratch as i32)
+        .i32_const(0)
+        .i32_const(scratch_len as i32)
+        .memory_fill(memory);
+    if let Some(start) = module.start {
+        body.call(start);
+    }
+    let init = builder.finish(vec![], &mut module.funcs);
+    module.start = Some(init);
+
+    let count = compressed.len();
+    for (id, _, packed) in compressed {
+        let data = module.data.get_mut(id);
+        data.value = packed;
+        data.kind = DataKind::Passive;
+        module.memories.get_mut(memory).data_segments.remove(&id);
+    }
+    Ok(count)
+}
+
+/// Compress `data` into the format described in the module documentation.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2);
+    // The last position of every 4-byte string seen so far.
+    let mut seen = HashMap::new();
+    let key = |i: usize| [data[i], data[i + 1], data[i + 2], data[i + 3]];
+    let mut literals = 0;
+    let mut i = 0;
+    while i + MIN_MATCH <= data.len() {
+        let candidate = seen.insert(key(i), i);
+        let candidate = match candidate {
+            Some(candidate) if i - candidate <= MAX_OFFSET => candidate,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let mut len = MIN_MATCH;
+        while i + len < data.len() && data[candidate + len] == data[i + len] {
+            len += 1;
+        }
+        sequence(&mut out, &data[literals..i], Some((i - candidate, len)));
+        for j in i + 1..(i + len).min(data.len() + 1 - MIN_MATCH) {
+            seen.insert(key(j), j);
+        }
+        i += len;
+        literals = i;
+    }
+    sequence(&mut out, &data[literals..], None);
+    out
+}
+
+fn sequence(out: &mut Vec<u8>, literals: &[u8], found: Option<(usize, usize)>) {
+    let extra = found.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push((literals.len().min(15) as u8) << 4 | extra.min(15) as u8);
+    if literals.len() >= 15 {
+        length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = found {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if extra >= 15 {
+            length(out, extra - 15);
+        }
+    }
+}
+
+fn length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+/// Decompress `data` compressed by `compress`, or return `None` if it is
+/// malformed.
+pub fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut bytes = data.iter().copied();
+    let length = |bytes: &mut dyn Iterator<Item = u8>, mut len: usize| -> Option<usize> {
+        loop {
+            let byte = bytes.next()?;
+            len += usize::from(byte);
+            if byte != 255 {
+                return Some(len);
+            }
+        }
+    };
+    while let Some(token) = bytes.next() {
+        let mut literals = usize::from(token >> 4);
+        if literals == 15 {
+            literals = length(&mut bytes, literals)?;
+        }
+        for _ in 0..literals {
+            out.push(bytes.next()?);
+        }
+        let offset = match bytes.next() {
+            Some(low) => usize::from(u16::from_le_bytes([low, bytes.next()?])),
+            None => break,
+        };
+        let mut len = usize::from(token & 15) + MIN_MATCH;
+        if len == 15 + MIN_MATCH {
+            len = length(&mut bytes, len)?;
+        }
+        let start = out.len().checked_sub(offset).filter(|_| offset > 0)?;
+        for i in start..start + len {
+            out.push(out[i]);
+        }
+    }
+    Some(out)
+}
+
+/// `compress_data_decompress(src, len, dst)`: decompress the `len` bytes at
+/// `src` to `dst`.
+fn decompressor(module: &mut Module, memory: MemoryId) -> FunctionId {
+    let mut local = || module.locals.add(ValType::I32);
+    let (src, len, dst) = (local(), local(), local());
+    let (end, token, n, offset, byte) = (local(), local(), local(), local(), local());
+    let byte_arg = MemArg {
+        align: 1,
+        offset: 0,
+        encoding: None,
+    };
+    let load8 = LoadKind::I32_8 {
+        kind: ExtendedLoad::ZeroExtend,
+    };
+    let bump = |body: &mut InstrSeqBuilder, local: LocalId, by: i32| {
+        body.local_get(local)
+            .i32_const(by)
+            .binop(BinaryOp::I32Add)
+            .local_set(local);
+    };
+    // Add more bytes of a length to `n`, if it is `limit`.
+    let more = |body: &mut InstrSeqBuilder, limit: i32| {
+        body.local_get(n)
+            .i32_const(limit)
+            .binop(BinaryOp::I32Eq)
+            .if_else(
+                None,
+                |then| {
+                    then.loop_(None, |more| {
+                        let looping = more.id();
+                        more.local_get(src)
+                            .load(memory, load8, byte_arg)
+                            .local_tee(byte)
+                            .local_get(n)
+                            .binop(BinaryOp::I32Add)
+                            .local_set(n);
+                        bump(more, src, 1);
+                        more.local_get(byte)
+                            .i32_const(255)
+                            .binop(BinaryOp::I32Eq)
+                            .br_if(looping);
+                    });
+                },
+                |_| {},
+            );
+    };
+
+    let mut builder = FunctionBuilder::new(
+        &mut module.types,
+        &[ValType::I32, ValType::I32, ValType::I32],
+        &[],
+    );
+    builder.name("compress_data_decompress".to_string());
+    builder
+        .func_body()
+        .local_get(src)
+        .local_get(len)
+        .binop(BinaryOp::I32Add)
+        .local_set(end)
+        .block(None, |done| {
+            let done_id = done.id();
+            done.loop_(None, |seq| {
+                let seq_id = seq.id();
+                seq.local_get(src)
+                    .local_get(end)
+                    .binop(BinaryOp::I32GeU)
+                    .br_if(done_id)
+                    .local_get(src)
+                    .load(memory, load8, byte_arg)
+                    .local_set(token);
+                bump(seq, src, 1);
+
+                // Copy the literals.
+                seq.local_get(token)
+                    .i32_const(4)
+                    .binop(BinaryOp::I32ShrU)
+                    .local_set(n);
+                more(seq, 15);
+                seq.local_get(dst)
+                    .local_get(src)
+                    .local_get(n)
+                    .memory_copy(memory, memory);
+                seq.local_get(dst)
+                    .local_get(n)
+                    .binop(BinaryOp::I32Add)
+                    .local_set(dst)
+                    .local_get(src)
+                    .local_get(n)
+                    .binop(BinaryOp::I32Add)
+                    .local_tee(src)
+                    .local_get(end)
+                    .binop(BinaryOp::I32GeU)
+                    .br_if(done_id);
+
+                // Copy the match, byte by byte since it may overlap the
+                // bytes it produces.
+                seq.local_get(src)
+                    .load(
+                        memory,
+                        LoadKind::I32_16 {
+                            kind: ExtendedLoad::ZeroExtend,
+                        },
+                        byte_arg,
+                    )
+                    .local_set(offset);
+                bump(seq, src, 2);
+                seq.local_get(token)
+                    .i32_const(15)
+                    .binop(BinaryOp::I32And)
+                    .i32_const(MIN_MATCH as i32)
+                    .binop(BinaryOp::I32Add)
+                    .local_set(n);
+                more(seq, 15 + MIN_MATCH as i32);
+                seq.loop_(None, |copy| {
+                    let copy_id = copy.id();
+                    copy.local_get(dst)
+                        .local_get(dst)
+                        .local_get(offset)
+                        .binop(BinaryOp::I32Sub)
+                        .load(memory, load8, byte_arg)
+                        .store(memory, StoreKind::I32_8 { atomic: false }, byte_arg);
+                    bump(copy, dst, 1);
+                    copy.local_get(n)
+                        .i32_const(1)
+                        .binop(BinaryOp::I32Sub)
+                        .local_tee(n)
+                        .br_if(copy_id);
+                });
+                seq.br(seq_id);
+            });
+        });
+    builder.finish(vec![src, len, dst], &mut module.funcs)
+}
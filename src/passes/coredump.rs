@@ -0,0 +1,487 @@
+//! Instrument a module to capture its stack when it traps, for a coredump.
+//!
+//! Engines don't give a trapping module a chance to look at its own stack, so
+//! this pass makes it unwind itself instead. Every `unreachable` in a local
+//! function, which is what panics and aborts compile to, records the
+//! function's frame into a buffer in linear memory and returns, and so does
+//! every call to a local function that returns while the stack is being
+//! unwound. Exported functions and the start function are wrapped so that
+//! the trap still happens once the whole stack has been recorded, and the
+//! host can then turn the buffer into the frames of a `CoreStack` with
+//! `frames`.
+//!
+//! Only `unreachable`s are caught: other traps, like out of bounds memory
+//! accesses, still happen right away without recording anything. Frames
+//! record the values of the locals that are integers or floats, and leave out
+//! the operand stack.
+//!
+//! Each frame takes up eight bytes for the function and code offset, and then
+//! eight bytes for each recorded local. The frames are written youngest first,
+//! and the ones that don't fit in the buffer once it fills up are skipped.
+
+use crate::emit::IdsToIndices;
+use crate::encode::Encoder;
+use crate::ir::*;
+use crate::passes::Roots;
+use crate::{
+    CoreStackFrame, CoreValue, CustomSection, ExportItem, FunctionBuilder, FunctionId, FunctionKind,
+};
+use crate::{GlobalId, LocalFunction, MemoryId, Module, Result, ValType};
+use anyhow::bail;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::mem;
+use wasmparser::BinaryReader;
+
+/// The name of the custom section that `run` adds to describe the recorded
+/// frames, and that `frames` needs to make sense of them.
+pub const FUNCS_SECTION: &str = "coredump-funcs";
+
+/// Where to record the frames of the stack when it is unwound.
+#[derive(Debug, Copy, Clone)]
+pub struct Config {
+    /// The memory holding the frame buffer.
+    pub memory: MemoryId,
+    /// The address of the start of the frame buffer.
+    pub base: u32,
+    /// The size of the frame buffer, in bytes.
+    pub capacity: u32,
+    /// A mutable `i32` global holding the number of bytes of frames recorded
+    /// so far.
+    ///
+    /// It should start out as zero, since the stack is being unwound whenever
+    /// it isn't. After a trap, the frames are in the `cursor` bytes at `base`,
+    /// and it should be set back to zero before the module is used again.
+    pub cursor: GlobalId,
+}
+
+/// Instrument `module` to record its stack into linear memory when it traps,
+/// as described by `config`.
+///
+/// Fails if the largest frame doesn't fit in the frame buffer, or, with the
+/// `unstable` feature, if a function returns a continuation reference, since
+/// there is no value to return when unwinding out of it.
+pub fn run(module: &mut Module, config: &Config) -> Result<()> {
+    let cursor = module.globals.get(config.cursor);
+    if cursor.ty != ValType::I32 || !cursor.mutable {
+        bail!("the frame buffer cursor must be a mutable `i32` global");
+    }
+    if config.base.checked_add(config.capacity).is_none() {
+        bail!("the frame buffer must fit in the first 4 GiB of memory");
+    }
+
+    let mut funcs = Vec::new();
+    for (id, func) in module.funcs.iter_local() {
+        #[cfg(feature = "unstable")]
+        {
+            let results = module.types.results(func.ty());
+            if results.iter().any(|ty| matches!(ty, ValType::Cont { .. })) {
+                bail!(
+                    "cannot unwind out of {:?}, which returns a continuation",
+                    id
+                );
+            }
+        }
+        let locals = recorded_locals(module, func);
+        let size = frame_size(&locals);
+        if size > config.capacity {
+            bail!(
+                "the frame buffer must have room for the largest frame, of {} bytes",
+                size
+            );
+        }
+        funcs.push((id, locals));
+    }
+
+    let instrumented = funcs.iter().map(|(id, _)| *id).collect::<HashSet<_>>();
+    for (number, (id, locals)) in funcs.iter().enumerate() {
+        let results = module.types.results(module.funcs.get(*id).ty()).to_vec();
+        let unwind = Unwind {
+            config,
+            number: number as u32,
+            locals,
+            results: &results,
+            instrumented: &instrumented,
+        };
+        unwind.rewrite(module.funcs.get_mut(*id).kind.unwrap_local_mut());
+    }
+
+    wrap_entry_points(module, config);
+    module.customs.add(FrameFunctions { funcs });
+    Ok(())
+}
+
+/// The parameters of `func`, and then the other locals that it uses, that are
+/// integers or floats.
+fn recorded_locals(module: &Module, func: &LocalFunction) -> Vec<(LocalId, ValType)> {
+    struct CollectLocals<'a> {
+        locals: &'a mut Vec<LocalId>,
+    }
+
+    impl<'instr> Visitor<'instr> for CollectLocals<'_> {
+        fn visit_local_id(&mut self, &local: &LocalId) {
+            if !self.locals.contains(&local) {
+                self.locals.push(local);
+            }
+        }
+    }
+
+    let mut ids = func.args.clone();
+    dfs_in_order(
+        &mut CollectLocals { locals: &mut ids },
+        func,
+        func.entry_block(),
+    );
+    ids.into_iter()
+        .map(|id| (id, module.locals.get(id).ty()))
+        .filter(|(_, ty)| {
+            matches!(
+                ty,
+                ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64
+            )
+        })
+        .collect()
+}
+
+fn frame_size(locals: &[(LocalId, ValType)]) -> u32 {
+    8 + 8 * locals.len() as u32
+}
+
+struct Unwind<'a> {
+    config: &'a Config,
+    number: u32,
+    locals: &'a [(LocalId, ValType)],
+    results: &'a [ValType],
+    instrumented: &'a HashSet<FunctionId>,
+}
+
+impl Unwind<'_> {
+    fn rewrite(&self, func: &mut LocalFunction) {
+        let mut seqs = Vec::new();
+        dfs_in_order(&mut CollectSeqs(&mut seqs), func, func.entry_block());
+        for seq in seqs {
+            let instrs = mem::take(&mut func.block_mut(seq).instrs);
+            let mut new_instrs = Vec::with_capacity(instrs.len());
+            for (instr, loc) in instrs {
+                let offset = if loc.is_default() { 0 } else { loc.data() };
+                match instr {
+                    Instr::Unreachable(_) => {
+                        new_instrs.extend(self.unwind(func, offset));
+                    }
+                    Instr::Call(Call { func: callee }) if !self.instrumented.contains(&callee) => {
+                        new_instrs.push((instr, loc));
+                    }
+                    Instr::Call(_) | Instr::CallIndirect(_) => {
+                        new_instrs.push((instr, loc));
+                        let unwind = self.unwind(func, offset);
+                        let consequent = self.seq(func, unwind);
+                        let alternative = self.seq(func, Vec::new());
+                        new_instrs.push((self.cursor_get(), Default::default()));
+                        new_instrs.push((
+                            IfElse {
+                                consequent,
+                                alternative,
+                            }
+                            .into(),
+                            Default::default(),
+                        ));
+                    }
+                    instr => new_instrs.push((instr, loc)),
+                }
+            }
+            func.block_mut(seq).instrs = new_instrs;
+        }
+    }
+
+    /// The instructions that record this function's frame, at `offset`, and
+    /// return from it.
+    fn unwind(&self, func: &mut LocalFunction, offset: u32) -> Vec<(Instr, InstrLocId)> {
+        let size = frame_size(self.locals);
+        let mut record = Vec::new();
+        self.store(&mut record, i32_const(self.number), ValType::I32, 0);
+        self.store(&mut record, i32_const(offset), ValType::I32, 4);
+        for (i, &(local, ty)) in self.locals.iter().enumerate() {
+            self.store(&mut record, LocalGet { local }.into(), ty, 8 + 8 * i as u32);
+        }
+        record.push((self.cursor_get(), Default::default()));
+        record.push((i32_const(size), Default::default()));
+        record.push((binop(BinaryOp::I32Add), Default::default()));
+        record.push((
+            GlobalSet {
+                global: self.config.cursor,
+            }
+            .into(),
+            Default::default(),
+        ));
+
+        // Frames that don't fit in what is left of the buffer are skipped,
+        // but the stack is still unwound.
+        let consequent = self.seq(func, record);
+        let alternative = self.seq(func, Vec::new());
+        let mut instrs = vec![
+            self.cursor_get(),
+            i32_const(size),
+            binop(BinaryOp::I32Add),
+            i32_const(self.config.capacity),
+            binop(BinaryOp::I32LeU),
+            IfElse {
+                consequent,
+                alternative,
+            }
+            .into(),
+        ];
+        for ty in self.results {
+            instrs.push(match ty {
+                ValType::I32 => i32_const(0),
+                ValType::I64 => Const {
+                    value: Value::I64(0),
+                }
+                .into(),
+                ValType::F32 => Const {
+                    value: Value::F32(0.0),
+                }
+                .into(),
+                ValType::F64 => Const {
+                    value: Value::F64(0.0),
+                }
+                .into(),
+                ValType::V128 => Const {
+                    value: Value::V128(0),
+                }
+                .into(),
+                ValType::Anyref => RefNull {}.into(),
+                #[cfg(feature = "unstable")]
+                ValType::Cont { .. } => unreachable!(),
+            });
+        }
+        instrs.push(Return {}.into());
+        instrs
+            .into_iter()
+            .map(|instr| (instr, Default::default()))
+            .collect()
+    }
+
+    /// Store the value that `value` pushes, of type `ty`, at `offset` in the
+    /// current frame.
+    fn store(&self, instrs: &mut Vec<(Instr, InstrLocId)>, value: Instr, ty: ValType, offset: u32) {
+        let (kind, align) = match ty {
+            ValType::I32 => (StoreKind::I32 { atomic: false }, 4),
+            ValType::I64 => (StoreKind::I64 { atomic: false }, 8),
+            ValType::F32 => (StoreKind::F32, 4),
+            ValType::F64 => (StoreKind::F64, 8),
+            _ => unreachable!(),
+        };
+        let store = Store {
+            memory: self.config.memory,
+            kind,
+            arg: MemArg {
+                align,
+                offset: self.config.base + offset,
+                encoding: None,
+            },
+        };
+        instrs.push((self.cursor_get(), Default::default()));
+        instrs.push((value, Default::default()));
+        instrs.push((store.into(), Default::default()));
+    }
+
+    fn seq(&self, func: &mut LocalFunction, instrs: Vec<(Instr, InstrLocId)>) -> InstrSeqId {
+        let seq = func.builder_mut().dangling_instr_seq(None).id();
+        func.block_mut(seq).instrs = instrs;
+        seq
+    }
+
+    fn cursor_get(&self) -> Instr {
+        GlobalGet {
+            global: self.config.cursor,
+        }
+        .into()
+    }
+}
+
+fn i32_const(value: u32) -> Instr {
+    Const {
+        value: Value::I32(value as i32),
+    }
+    .into()
+}
+
+fn binop(op: BinaryOp) -> Instr {
+    Binop { op }.into()
+}
+
+struct CollectSeqs<'a>(&'a mut Vec<InstrSeqId>);
+
+impl<'instr> Visitor<'instr> for CollectSeqs<'_> {
+    fn start_instr_seq(&mut self, seq: &'instr InstrSeq) {
+        self.0.push(seq.id());
+    }
+}
+
+/// Point the exports of local functions, and the start function, at wrappers
+/// that trap if the stack was unwound.
+fn wrap_entry_points(module: &mut Module, config: &Config) {
+    let mut entry_points = module
+        .exports
+        .iter()
+        .filter_map(|export| match export.item {
+            ExportItem::Function(func) => Some(func),
+            _ => None,
+        })
+        .chain(module.start)
+        .filter(|func| matches!(module.funcs.get(*func).kind, FunctionKind::Local(_)))
+        .collect::<Vec<_>>();
+    entry_points.sort();
+    entry_points.dedup();
+
+    let mut wrappers = HashMap::new();
+    for func in entry_points {
+        let (params, results) = module.types.params_results(module.funcs.get(func).ty());
+        let (params, results) = (params.to_vec(), results.to_vec());
+        let args = params
+            .iter()
+            .map(|ty| module.locals.add(*ty))
+            .collect::<Vec<_>>();
+        let mut builder = FunctionBuilder::new(&mut module.types, &params, &results);
+        let mut body = builder.func_body();
+        for arg in args.iter() {
+            body.local_get(*arg);
+        }
+        body.call(func).global_get(config.cursor).if_else(
+            None,
+            |then| {
+                then.unreachable();
+            },
+            |_| {},
+        );
+        let wrapper = builder.finish(args, &mut module.funcs);
+        module.funcs.get_mut(wrapper).name = module
+            .funcs
+            .get(func)
+            .name
+            .as_ref()
+            .map(|name| format!("coredump:{}", name));
+        wrappers.insert(func, wrapper);
+    }
+
+    for export in module.exports.iter_mut() {
+        if let ExportItem::Function(func) = &mut export.item {
+            if let Some(wrapper) = wrappers.get(func) {
+                *func = *wrapper;
+            }
+        }
+    }
+    if let Some(start) = module.start {
+        module.start = wrappers.get(&start).copied().or(Some(start));
+    }
+}
+
+/// The custom section describing the frames of each instrumented function,
+/// by the number that the instrumentation records for it.
+#[derive(Debug, Clone)]
+struct FrameFunctions {
+    funcs: Vec<(FunctionId, Vec<(LocalId, ValType)>)>,
+}
+
+impl CustomSection for FrameFunctions {
+    fn name(&self) -> &str {
+        FUNCS_SECTION
+    }
+
+    fn data(&self, indices: &IdsToIndices) -> Cow<'_, [u8]> {
+        let mut data = Vec::new();
+        let mut encoder = Encoder::new(&mut data);
+        encoder.usize(self.funcs.len());
+        for (func, locals) in self.funcs.iter() {
+            let local_indices = &indices.locals[func];
+            encoder.u32(indices.get_func_index(*func));
+            encoder.usize(local_indices.len());
+            encoder.usize(locals.len());
+            for (local, ty) in locals.iter() {
+                encoder.u32(local_indices[local]);
+                ty.emit(&mut encoder, indices);
+            }
+        }
+        data.into()
+    }
+
+    fn add_gc_roots(&self, roots: &mut Roots) {
+        for (func, _) in self.funcs.iter() {
+            roots.push_func(*func);
+        }
+    }
+
+    fn clone_section(&self) -> Option<Box<dyn CustomSection>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+/// Read the frames recorded in `buffer`, which is the part of the frame
+/// buffer that the cursor says was written, using `section`, the data of the
+/// `FUNCS_SECTION` custom section of the instrumented module, which parsing
+/// it keeps as a `RawCustomSection`.
+///
+/// The frames refer to the functions and locals of the instrumented module,
+/// as emitted, and are all of its first instance. Their code offsets are
+/// those of the trapping instructions and calls in the binary that the module
+/// was parsed from, or what `ModuleConfig::on_instr_loc` gave for them, since
+/// where they end up in the instrumented binary isn't known until it is
+/// emitted, and are zero for instructions without one.
+pub fn frames(section: &[u8], buffer: &[u8]) -> Result<Vec<CoreStackFrame>> {
+    let mut reader = BinaryReader::new(section);
+    let mut funcs = Vec::new();
+    for _ in 0..reader.read_var_u32()? {
+        let func = reader.read_var_u32()?;
+        let num_locals = reader.read_var_u32()?;
+        let mut locals = Vec::new();
+        for _ in 0..reader.read_var_u32()? {
+            let index = reader.read_var_u32()?;
+            if index >= num_locals {
+                bail!("invalid coredump local index: {}", index);
+            }
+            locals.push((index, reader.read_type()?));
+        }
+        funcs.push((func, num_locals, locals));
+    }
+
+    // Frames are made of eight byte words: one for the function and code
+    // offset, and then one for each recorded local.
+    let mut words = buffer.chunks_exact(8);
+    if !words.remainder().is_empty() {
+        bail!("truncated coredump frame");
+    }
+    let read_u32 = |word: &[u8]| u32::from_le_bytes(word[..4].try_into().unwrap());
+    let read_u64 = |word: &[u8]| u64::from_le_bytes(word.try_into().unwrap());
+    let mut frames = Vec::new();
+    while let Some(header) = words.next() {
+        let number = read_u32(header) as usize;
+        let code_offset = read_u32(&header[4..]);
+        let (func, num_locals, locals) = match funcs.get(number) {
+            Some(func) => func,
+            None => bail!("invalid coredump function number: {}", number),
+        };
+        let mut values = vec![CoreValue::Missing; *num_locals as usize];
+        for (index, ty) in locals.iter() {
+            let bytes = match words.next() {
+                Some(word) => word,
+                None => bail!("truncated coredump frame"),
+            };
+            values[*index as usize] = match ty {
+                wasmparser::Type::I32 => CoreValue::I32(read_u32(bytes) as i32),
+                wasmparser::Type::I64 => CoreValue::I64(read_u64(bytes) as i64),
+                wasmparser::Type::F32 => CoreValue::F32(f32::from_bits(read_u32(bytes))),
+                wasmparser::Type::F64 => CoreValue::F64(f64::from_bits(read_u64(bytes))),
+                ty => bail!("invalid coredump local type: {:?}", ty),
+            };
+        }
+        frames.push(CoreStackFrame {
+            instance: 0,
+            func: *func,
+            code_offset,
+            locals: values,
+            stack: Vec::new(),
+        });
+    }
+    Ok(frames)
+}
@@ -0,0 +1,302 @@
+//! Common subexpression elimination.
+//!
+//! Naive code generators often compute the same value several times, for
+//! example an address that is used by many loads and stores. This pass finds
+//! pure expressions, built from constants, locals, immutable globals, and
+//! non-trapping unary and binary operators, that are computed more than once
+//! within the same instruction sequence. The first computation is saved to a
+//! fresh local with `local.tee`, and later ones are replaced with a
+//! `local.get` of it.
+//!
+//! The pass is deliberately conservative. Loads, calls, and anything else
+//! with side effects or that might trap are never eliminated, and nothing is
+//! reused across nested blocks, or across a `local.set` or `local.tee` of any
+//! local an expression reads.
+
+use crate::ir::*;
+use crate::{GlobalId, Module, ModuleGlobals, ModuleLocals, ValType};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Run common subexpression elimination over every local function in
+/// `module`.
+pub fn run(module: &mut Module) {
+    for (_, func) in module.funcs.iter_local_mut() {
+        let mut cse = Cse {
+            locals: &mut module.locals,
+            globals: &module.globals,
+        };
+        let entry = func.entry_block();
+        dfs_pre_order_mut(&mut cse, func, entry);
+    }
+}
+
+struct Cse<'a> {
+    locals: &'a mut ModuleLocals,
+    globals: &'a ModuleGlobals,
+}
+
+impl VisitorMut for Cse<'_> {
+    fn start_instr_seq_mut(&mut self, seq: &mut InstrSeq) {
+        while let Some(redundant) = self.find_redundant(&seq.instrs) {
+            let tmp = self.locals.add(redundant.ty);
+            let get_loc = seq.instrs[redundant.later.start].1;
+            let tee_loc = seq.instrs[redundant.first.end - 1].1;
+            seq.instrs.splice(
+                redundant.later,
+                Some((LocalGet { local: tmp }.into(), get_loc)),
+            );
+            let tee = (LocalTee { local: tmp }.into(), tee_loc);
+            seq.instrs.insert(redundant.first.end, tee);
+        }
+    }
+}
+
+/// An expression computed twice, at the ranges of instructions `first` and
+/// `later`.
+struct Redundant {
+    first: Range<usize>,
+    later: Range<usize>,
+    ty: ValType,
+}
+
+/// A pure expression, whose operands are other interned expressions.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub(super) enum Expr {
+    // The type of the constant and its bits.
+    Const(ValType, u128),
+    Local(LocalId),
+    Global(GlobalId),
+    Unop(UnaryOp, usize),
+    Binop(BinaryOp, usize, usize),
+}
+
+/// A value on the operand stack computed by the pure expression `expr`, using
+/// the instructions since `start`.
+#[derive(Copy, Clone)]
+struct Operand {
+    expr: usize,
+    start: usize,
+    // Whether those instructions are free of side effects, so that they can
+    // be replaced. Ones that contain a `local.tee` can still be reused.
+    pure: bool,
+}
+
+/// Interned pure expressions, along with their types.
+#[derive(Default)]
+pub(super) struct Exprs {
+    ids: HashMap<Expr, usize>,
+    exprs: Vec<(Expr, ValType)>,
+}
+
+impl Exprs {
+    pub(super) fn intern(&mut self, expr: Expr, ty: ValType) -> usize {
+        let exprs = &mut self.exprs;
+        *self.ids.entry(expr).or_insert_with(|| {
+            exprs.push((expr, ty));
+            exprs.len() - 1
+        })
+    }
+
+    pub(super) fn ty(&self, expr: usize) -> ValType {
+        self.exprs[expr].1
+    }
+
+    pub(super) fn constant(&mut self, value: Value) -> usize {
+        let (ty, bits) = match value {
+            Value::I32(x) => (ValType::I32, u128::from(x as u32)),
+            Value::I64(x) => (ValType::I64, u128::from(x as u64)),
+            Value::F32(x) => (ValType::F32, u128::from(x.to_bits())),
+            Value::F64(x) => (ValType::F64, u128::from(x.to_bits())),
+            Value::V128(x) => (ValType::V128, x),
+        };
+        self.intern(Expr::Const(ty, bits), ty)
+    }
+
+    fn reads(&self, expr: usize, local: LocalId) -> bool {
+        match self.exprs[expr].0 {
+            Expr::Local(l) => l == local,
+            Expr::Const(..) | Expr::Global(_) => false,
+            Expr::Unop(_, a) => self.reads(a, local),
+            Expr::Binop(_, a, b) => self.reads(a, local) || self.reads(b, local),
+        }
+    }
+}
+
+impl Cse<'_> {
+    /// Simulate the operand stack of `instrs` to find the first pure
+    /// expression that was already computed earlier on.
+    fn find_redundant(&self, instrs: &[(Instr, InstrLocId)]) -> Option<Redundant> {
+        let mut exprs = Exprs::default();
+        // `None` for values that don't come from a pure expression.
+        let mut stack: Vec<Option<Operand>> = Vec::new();
+        // The pure expressions computed so far, and where.
+        let mut available: HashMap<usize, Range<usize>> = HashMap::new();
+
+        for (i, (instr, _)) in instrs.iter().enumerate() {
+            let leaf = |exprs: &mut Exprs, expr, ty| {
+                Some(Operand {
+                    expr: exprs.intern(expr, ty),
+                    start: i,
+                    pure: true,
+                })
+            };
+            let operand = match instr {
+                Instr::Const(c) => Some(Operand {
+                    expr: exprs.constant(c.value),
+                    start: i,
+                    pure: true,
+                }),
+                Instr::LocalGet(l) => {
+                    let ty = self.locals.get(l.local).ty();
+                    leaf(&mut exprs, Expr::Local(l.local), ty)
+                }
+                Instr::GlobalGet(g) => {
+                    let global = self.globals.get(g.global);
+                    if global.mutable {
+                        None
+                    } else {
+                        leaf(&mut exprs, Expr::Global(g.global), global.ty)
+                    }
+                }
+                // An expression must be a contiguous range of instructions,
+                // so none of the operands below a `local.set`, `local.tee` or
+                // `drop` may be combined with the ones after it, or the range
+                // of the result would contain it.
+                Instr::LocalSet(l) => {
+                    stack.clear();
+                    available.retain(|expr, _| !exprs.reads(*expr, l.local));
+                    continue;
+                }
+                Instr::LocalTee(l) => {
+                    // The value is now also the value of the local, and it is
+                    // still computed by all of the instructions since the
+                    // start of the operand, but replacing them would remove
+                    // the `local.tee`.
+                    let operand = stack.pop().flatten();
+                    stack.clear();
+                    available.retain(|expr, _| !exprs.reads(*expr, l.local));
+                    operand.map(|o| {
+                        let ty = self.locals.get(l.local).ty();
+                        Operand {
+                            expr: exprs.intern(Expr::Local(l.local), ty),
+                            start: o.start,
+                            pure: false,
+                        }
+                    })
+                }
+                Instr::Unop(u) => {
+                    let a = stack.pop().flatten();
+                    match (a, unop_result(u.op)) {
+                        (Some(a), Some(ty)) => Some(Operand {
+                            expr: exprs.intern(Expr::Unop(u.op, a.expr), ty),
+                            start: a.start,
+                            pure: a.pure,
+                        }),
+                        _ => None,
+                    }
+                }
+                Instr::Binop(b) => {
+                    let rhs = stack.pop().flatten();
+                    let lhs = stack.pop().flatten();
+                    match (lhs, rhs, binop_result(b.op)) {
+                        (Some(lhs), Some(rhs), Some(ty)) => Some(Operand {
+                            expr: exprs.intern(Expr::Binop(b.op, lhs.expr, rhs.expr), ty),
+                            start: lhs.start,
+                            pure: lhs.pure && rhs.pure,
+                        }),
+                        _ => None,
+                    }
+                }
+                Instr::Drop(_) => {
+                    stack.clear();
+                    continue;
+                }
+                Instr::Block(_) | Instr::Loop(_) | Instr::IfElse(_) => {
+                    // Nested blocks may set any local, and may consume any
+                    // number of operands.
+                    stack.clear();
+                    available.clear();
+                    continue;
+                }
+                _ => {
+                    // We don't know how many operands other instructions
+                    // consume, so forget about all of them.
+                    stack.clear();
+                    continue;
+                }
+            };
+
+            if let Some(operand) = operand {
+                let range = operand.start..i + 1;
+                let is_leaf = !matches!(instr, Instr::Unop(_) | Instr::Binop(_));
+                if !is_leaf {
+                    if let Some(first) = available.get(&operand.expr) {
+                        // Replacing fewer than three instructions with a
+                        // `local.get`, and a `local.tee` elsewhere, is not a
+                        // win.
+                        if range.len() >= 3 && operand.pure {
+                            return Some(Redundant {
+                                first: first.clone(),
+                                later: range,
+                                ty: exprs.ty(operand.expr),
+                            });
+                        }
+                    } else {
+                        available.insert(operand.expr, range);
+                    }
+                }
+            }
+            stack.push(operand);
+        }
+        None
+    }
+}
+
+/// The result type of pure, non-trapping binary operators.
+pub(super) fn binop_result(op: BinaryOp) -> Option<ValType> {
+    use BinaryOp::*;
+    Some(match op {
+        I32Eq | I32Ne | I32LtS | I32LtU | I32GtS | I32GtU | I32LeS | I32LeU | I32GeS | I32GeU
+        | I64Eq | I64Ne | I64LtS | I64LtU | I64GtS | I64GtU | I64LeS | I64LeU | I64GeS | I64GeU
+        | F32Eq | F32Ne | F32Lt | F32Gt | F32Le | F32Ge | F64Eq | F64Ne | F64Lt | F64Gt | F64Le
+        | F64Ge => ValType::I32,
+
+        I32Add | I32Sub | I32Mul | I32And | I32Or | I32Xor | I32Shl | I32ShrS | I32ShrU
+        | I32Rotl | I32Rotr => ValType::I32,
+        I64Add | I64Sub | I64Mul | I64And | I64Or | I64Xor | I64Shl | I64ShrS | I64ShrU
+        | I64Rotl | I64Rotr => ValType::I64,
+
+        F32Add | F32Sub | F32Mul | F32Div | F32Min | F32Max | F32Copysign => ValType::F32,
+        F64Add | F64Sub | F64Mul | F64Div | F64Min | F64Max | F64Copysign => ValType::F64,
+
+        // Integer division traps, and we leave SIMD alone.
+        _ => return None,
+    })
+}
+
+/// The result type of pure, non-trapping unary operators.
+pub(super) fn unop_result(op: UnaryOp) -> Option<ValType> {
+    use UnaryOp::*;
+    Some(match op {
+        I32Eqz | I32Clz | I32Ctz | I32Popcnt | I64Eqz => ValType::I32,
+        I64Clz | I64Ctz | I64Popcnt => ValType::I64,
+
+        F32Abs | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt => ValType::F32,
+        F64Abs | F64Neg | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt => ValType::F64,
+
+        I32WrapI64 | I32ReinterpretF32 | I32Extend8S | I32Extend16S | I32TruncSSatF32
+        | I32TruncUSatF32 | I32TruncSSatF64 | I32TruncUSatF64 => ValType::I32,
+        I64ExtendSI32 | I64ExtendUI32 | I64ReinterpretF64 | I64Extend8S | I64Extend16S
+        | I64Extend32S | I64TruncSSatF32 | I64TruncUSatF32 | I64TruncSSatF64 | I64TruncUSatF64 => {
+            ValType::I64
+        }
+        F32ConvertSI32 | F32ConvertUI32 | F32ConvertSI64 | F32ConvertUI64 | F32DemoteF64
+        | F32ReinterpretI32 => ValType::F32,
+        F64ConvertSI32 | F64ConvertUI32 | F64ConvertSI64 | F64ConvertUI64 | F64PromoteF32
+        | F64ReinterpretI64 => ValType::F64,
+
+        // Non-saturating truncations trap, and we leave SIMD alone.
+        _ => return None,
+    })
+}
@@ -0,0 +1,132 @@
+//! Run LLVM's static constructors before the rest of a module.
+//!
+//! `wasm-ld` collects the static constructors of a module into a function
+//! named `__wasm_call_ctors`. Commands call it from `_start` and reactors from
+//! `_initialize`, but loaders that call other exports directly have to run it
+//! first themselves. This pass does that within the module instead, either
+//! from the start section or from wrappers around the exports.
+//!
+//! Either way, the constructors function is made to only run once, so that
+//! calling it again, eg from `_initialize`, does nothing.
+
+use crate::ir::*;
+use crate::{ExportItem, FunctionBuilder, FunctionId, FunctionKind, InitExpr};
+use crate::{Module, Result, ValType};
+use anyhow::bail;
+use std::collections::HashMap;
+
+/// The name of the function that runs the static constructors.
+pub const CALL_CTORS: &str = "__wasm_call_ctors";
+
+/// Find the function of `module` that runs the static constructors, either by
+/// its export or by its name.
+pub fn find(module: &Module) -> Option<FunctionId> {
+    let export = module.exports.iter().find(|e| e.name == CALL_CTORS);
+    match export.map(|e| &e.item) {
+        Some(ExportItem::Function(f)) => Some(*f),
+        _ => module.funcs.by_name(CALL_CTORS),
+    }
+}
+
+/// Run the static constructors of `module` from its start section.
+///
+/// If `module` already has a start function, the new start function runs the
+/// constructors before it. Returns the new start function.
+pub fn promote_to_start(module: &mut Module) -> Result<FunctionId> {
+    let ctors = run_once(module)?;
+    let start = match module.start {
+        None => ctors,
+        Some(start) => {
+            let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+            builder.name(format!("{}_and_start", CALL_CTORS));
+            builder.func_body().call(ctors).call(start);
+            builder.finish(vec![], &mut module.funcs)
+        }
+    };
+    module.start = Some(start);
+    Ok(start)
+}
+
+/// Run the static constructors of `module` before any of its exported
+/// functions, by exporting wrappers that call the constructors first.
+///
+/// Functions exported under several names share a wrapper, and the export of
+/// the constructors function itself is left as is. Returns how many functions
+/// were wrapped.
+pub fn wrap_exports(module: &mut Module) -> Result<usize> {
+    let ctors = run_once(module)?;
+    let mut wrappers = HashMap::new();
+    let exports = module
+        .exports
+        .iter()
+        .filter_map(|e| match e.item {
+            ExportItem::Function(f) if f != ctors => Some((e.id(), f)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    for (export, func) in exports {
+        let wrapper = *wrappers
+            .entry(func)
+            .or_insert_with(|| wrap(module, ctors, func));
+        module.exports.get_mut(export).item = ExportItem::Function(wrapper);
+    }
+    Ok(wrappers.len())
+}
+
+/// Make the static constructors function of `module` return early once it
+/// has already run.
+fn run_once(module: &mut Module) -> Result<FunctionId> {
+    let ctors = match find(module) {
+        Some(f) => f,
+        None => bail!("the module has no `{}` function", CALL_CTORS),
+    };
+    let ty = module.funcs.get(ctors).ty();
+    if !module.types.params(ty).is_empty() || !module.types.results(ty).is_empty() {
+        bail!("`{}` must not have parameters or results", CALL_CTORS);
+    }
+
+    if !matches!(module.funcs.get(ctors).kind, FunctionKind::Local(_)) {
+        bail!("`{}` must be a local function", CALL_CTORS);
+    }
+
+    let ran = module
+        .globals
+        .add_local(ValType::I32, true, InitExpr::Value(Value::I32(0)));
+    let func = module.funcs.get_mut(ctors).kind.unwrap_local_mut();
+    let entry = func.entry_block();
+    let guard: Vec<Instr> = vec![
+        GlobalGet { global: ran }.into(),
+        BrIf { block: entry }.into(),
+        Const {
+            value: Value::I32(1),
+        }
+        .into(),
+        GlobalSet { global: ran }.into(),
+    ];
+    let guard = guard.into_iter().map(|i| (i, InstrLocId::default()));
+    func.block_mut(entry).instrs.splice(0..0, guard);
+    Ok(ctors)
+}
+
+/// Create a function that calls `ctors` and then forwards to `func`.
+fn wrap(module: &mut Module, ctors: FunctionId, func: FunctionId) -> FunctionId {
+    let ty = module.funcs.get(func).ty();
+    let (params, results) = module.types.params_results(ty);
+    let (params, results) = (params.to_vec(), results.to_vec());
+    let args = params
+        .iter()
+        .map(|ty| module.locals.add(*ty))
+        .collect::<Vec<_>>();
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &params, &results);
+    if let Some(name) = &module.funcs.get(func).name {
+        builder.name(format!("{}_with_ctors", name));
+    }
+    let mut body = builder.func_body();
+    body.call(ctors);
+    for arg in args.iter() {
+        body.local_get(*arg);
+    }
+    body.call(func);
+    builder.finish(args, &mut module.funcs)
+}
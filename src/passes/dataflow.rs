@@ -0,0 +1,744 @@
+//! A forward dataflow framework over the values of a function.
+//!
+//! The analysis simulates a function's operand stack and locals with abstract
+//! values from a `Lattice`, which describe what is known about the concrete
+//! values there. Wasm's control flow is structured, so the states that reach
+//! the end of a `block` or `if`, or the start of a `loop`, from falling
+//! through and from branches are joined, and loop bodies are analyzed until
+//! the state at their start stops changing.
+//!
+//! Two lattices are provided: `Constant`, which finds the values that are
+//! always the same constant, and `KnownBits`, which finds the bits of integers
+//! that are always zero or always one. For example, the index of a
+//! `call_indirect` whose known bits show that it is less than the size of the
+//! table never traps on a bounds check.
+
+use crate::ir::*;
+use crate::{GlobalKind, InitExpr, LocalFunction, Module, ValType};
+use std::collections::{HashMap, HashSet};
+
+/// The abstract values of a dataflow analysis.
+///
+/// The values form a lattice, where joining two values gives a value that
+/// describes everything that either of them does. The lattice must have a
+/// finite height, so that joining values again and again eventually stops
+/// changing them, which is what ends the analysis of loops.
+pub trait Lattice: Clone + PartialEq {
+    /// The value that nothing is known about.
+    fn unknown() -> Self;
+
+    /// The value of the constant `value`.
+    fn constant(value: Value) -> Self;
+
+    /// Join two values, giving one that describes both.
+    fn join(&self, other: &Self) -> Self;
+
+    /// The result of applying `op` to `operand`.
+    ///
+    /// Defaults to an unknown value.
+    fn unop(op: UnaryOp, operand: &Self) -> Self {
+        let _ = (op, operand);
+        Self::unknown()
+    }
+
+    /// The result of applying `op` to `lhs` and `rhs`.
+    ///
+    /// Defaults to an unknown value.
+    fn binop(op: BinaryOp, lhs: &Self, rhs: &Self) -> Self {
+        let _ = (op, lhs, rhs);
+        Self::unknown()
+    }
+}
+
+/// The abstract values of the locals and the operand stack at a point in a
+/// function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct State<L> {
+    /// The values of the function's locals.
+    pub locals: HashMap<LocalId, L>,
+    /// The values on the operand stack of the innermost sequence, with the
+    /// top of the stack last.
+    pub stack: Vec<L>,
+}
+
+impl<L: Lattice> State<L> {
+    fn join(&self, other: &State<L>) -> State<L> {
+        let mut locals = self.locals.clone();
+        for (local, value) in other.locals.iter() {
+            let joined = match locals.get(local) {
+                Some(ours) => ours.join(value),
+                None => value.clone(),
+            };
+            locals.insert(*local, joined);
+        }
+        let stack = self
+            .stack
+            .iter()
+            .zip(&other.stack)
+            .map(|(a, b)| a.join(b))
+            .collect();
+        State { locals, stack }
+    }
+
+    fn pop(&mut self) -> L {
+        self.stack.pop().unwrap_or_else(L::unknown)
+    }
+
+    fn split_off(&mut self, n: usize) -> Vec<L> {
+        let at = self.stack.len().saturating_sub(n);
+        self.stack.split_off(at)
+    }
+}
+
+fn join<L: Lattice>(a: Option<State<L>>, b: Option<State<L>>) -> Option<State<L>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.join(&b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The abstract states at each instruction of a function, found with `run`.
+#[derive(Debug, Clone)]
+pub struct Dataflow<L> {
+    // The state before each instruction of each sequence, or `None` where the
+    // instruction is unreachable.
+    before: HashMap<InstrSeqId, Vec<Option<State<L>>>>,
+}
+
+impl<L> Dataflow<L> {
+    /// Get the state just before the instruction at `index` in `seq`.
+    ///
+    /// The operands of the instruction are at the top of the state's stack.
+    /// Returns `None` if the instruction is unreachable. Panics if there is no
+    /// such instruction.
+    pub fn before(&self, seq: InstrSeqId, index: usize) -> Option<&State<L>> {
+        self.before[&seq][index].as_ref()
+    }
+}
+
+/// Find the abstract states at each instruction of `func`, a function of
+/// `module`.
+///
+/// The function's arguments start out unknown, and its other locals start
+/// out as the constant zero of their type.
+pub fn run<L: Lattice>(module: &Module, func: &LocalFunction) -> Dataflow<L> {
+    let mut locals = Locals::default();
+    dfs_in_order(&mut locals, func, func.entry_block());
+
+    let mut entry = State {
+        locals: HashMap::new(),
+        stack: Vec::new(),
+    };
+    for local in locals.0 {
+        let zero = match module.locals.get(local).ty() {
+            ValType::I32 => Some(Value::I32(0)),
+            ValType::I64 => Some(Value::I64(0)),
+            ValType::F32 => Some(Value::F32(0.0)),
+            ValType::F64 => Some(Value::F64(0.0)),
+            ValType::V128 => Some(Value::V128(0)),
+            ValType::Anyref => None,
+            #[cfg(feature = "unstable")]
+            ValType::Cont { .. } => None,
+        };
+        let value = match zero {
+            Some(zero) if !func.args.contains(&local) => L::constant(zero),
+            _ => L::unknown(),
+        };
+        entry.locals.insert(local, value);
+    }
+
+    let mut analysis = Analysis {
+        module,
+        func,
+        dataflow: Dataflow {
+            before: HashMap::new(),
+        },
+        targets: HashMap::new(),
+    };
+    analysis.seq(func.entry_block(), false, entry);
+    analysis.dataflow
+}
+
+/// Finds the locals that a function uses.
+#[derive(Default)]
+struct Locals(HashSet<LocalId>);
+
+impl<'instr> Visitor<'instr> for Locals {
+    fn visit_local_id(&mut self, local: &LocalId) {
+        self.0.insert(*local);
+    }
+}
+
+/// The states that branches to a sequence being analyzed carry.
+struct Target<L> {
+    // How many values the branches carry.
+    arity: usize,
+    // The join of the states that the branches carry so far.
+    incoming: Option<State<L>>,
+}
+
+struct Analysis<'a, L> {
+    module: &'a Module,
+    func: &'a LocalFunction,
+    dataflow: Dataflow<L>,
+    targets: HashMap<InstrSeqId, Target<L>>,
+}
+
+impl<L: Lattice> Analysis<'_, L> {
+    /// Get how many parameters and results a sequence has.
+    fn seq_arity(&self, seq: InstrSeqId) -> (usize, usize) {
+        match self.func.block(seq).ty {
+            InstrSeqType::Simple(ty) => (0, ty.is_some() as usize),
+            InstrSeqType::MultiValue(ty) => {
+                let (params, results) = self.module.types.params_results(ty);
+                (params.len(), results.len())
+            }
+        }
+    }
+
+    /// Analyze `seq`, given the state at its start, and return the state
+    /// after it, or `None` if that is unreachable.
+    fn seq(&mut self, seq: InstrSeqId, is_loop: bool, entry: State<L>) -> Option<State<L>> {
+        let (params, results) = self.seq_arity(seq);
+        if !is_loop {
+            self.targets.insert(
+                seq,
+                Target {
+                    arity: results,
+                    incoming: None,
+                },
+            );
+            let end = self.instrs(seq, results, Some(entry));
+            let incoming = self.targets.remove(&seq).and_then(|t| t.incoming);
+            return join(end, incoming);
+        }
+
+        let mut head = entry;
+        loop {
+            self.targets.insert(
+                seq,
+                Target {
+                    arity: params,
+                    incoming: None,
+                },
+            );
+            let end = self.instrs(seq, results, Some(head.clone()));
+            let next = match self.targets.remove(&seq).and_then(|t| t.incoming) {
+                Some(incoming) => head.join(&incoming),
+                None => head.clone(),
+            };
+            if next == head {
+                return end;
+            }
+            head = next;
+        }
+    }
+
+    /// Analyze the instructions of `seq`, and return the state at its end,
+    /// with just its `results` on the stack.
+    fn instrs(
+        &mut self,
+        seq: InstrSeqId,
+        results: usize,
+        mut state: Option<State<L>>,
+    ) -> Option<State<L>> {
+        let func = self.func;
+        let instrs = &func.block(seq).instrs;
+        let mut before = Vec::with_capacity(instrs.len());
+        for (instr, _) in instrs.iter() {
+            before.push(state.clone());
+            state = match state {
+                Some(state) => self.instr(instr, state),
+                None => None,
+            };
+        }
+        self.dataflow.before.insert(seq, before);
+        let mut state = state?;
+        state.stack = state.split_off(results);
+        Some(state)
+    }
+
+    /// Join `state` into the states that branches to `block` carry.
+    fn branch(&mut self, block: InstrSeqId, state: &State<L>) {
+        let target = self.targets.get_mut(&block).unwrap();
+        let at = state.stack.len().saturating_sub(target.arity);
+        let carried = State {
+            locals: state.locals.clone(),
+            stack: state.stack[at..].to_vec(),
+        };
+        target.incoming = join(target.incoming.take(), Some(carried));
+    }
+
+    /// Get the state after `instr`, given the state before it, or `None` if
+    /// the state after it is unreachable.
+    fn instr(&mut self, instr: &Instr, mut state: State<L>) -> Option<State<L>> {
+        match instr {
+            Instr::LocalGet(LocalGet { local }) => {
+                let value = state.locals.get(local).cloned();
+                state.stack.push(value.unwrap_or_else(L::unknown));
+            }
+            Instr::LocalSet(LocalSet { local }) => {
+                let value = state.pop();
+                state.locals.insert(*local, value);
+            }
+            Instr::LocalTee(LocalTee { local }) => {
+                let value = state.stack.last().cloned().unwrap_or_else(L::unknown);
+                state.locals.insert(*local, value);
+            }
+            Instr::GlobalGet(GlobalGet { global }) => {
+                let global = self.module.globals.get(*global);
+                let value = match global.kind {
+                    GlobalKind::Local(InitExpr::Value(value)) if !global.mutable => {
+                        L::constant(value)
+                    }
+                    _ => L::unknown(),
+                };
+                state.stack.push(value);
+            }
+            Instr::Const(Const { value }) => state.stack.push(L::constant(*value)),
+            Instr::Unop(Unop { op }) => {
+                let operand = state.pop();
+                state.stack.push(L::unop(*op, &operand));
+            }
+            Instr::Binop(Binop { op }) => {
+                let rhs = state.pop();
+                let lhs = state.pop();
+                state.stack.push(L::binop(*op, &lhs, &rhs));
+            }
+            Instr::Select(_) => {
+                state.pop();
+                let b = state.pop();
+                let a = state.pop();
+                state.stack.push(a.join(&b));
+            }
+            Instr::Return(_) | Instr::Unreachable(_) => return None,
+            Instr::Br(Br { block }) => {
+                self.branch(*block, &state);
+                return None;
+            }
+            Instr::BrIf(BrIf { block }) => {
+                state.pop();
+                self.branch(*block, &state);
+            }
+            Instr::BrTable(BrTable { blocks, default }) => {
+                state.pop();
+                for block in blocks.iter().chain(Some(default)) {
+                    self.branch(*block, &state);
+                }
+                return None;
+            }
+            Instr::Block(Block { seq }) | Instr::Loop(Loop { seq }) => {
+                let (params, _) = self.seq_arity(*seq);
+                let entry = State {
+                    locals: state.locals.clone(),
+                    stack: state.split_off(params),
+                };
+                let is_loop = matches!(instr, Instr::Loop(_));
+                let end = self.seq(*seq, is_loop, entry)?;
+                state.locals = end.locals;
+                state.stack.extend(end.stack);
+            }
+            Instr::IfElse(IfElse {
+                consequent,
+                alternative,
+            }) => {
+                state.pop();
+                let (params, _) = self.seq_arity(*consequent);
+                let entry = State {
+                    locals: state.locals.clone(),
+                    stack: state.split_off(params),
+                };
+                let consequent = self.seq(*consequent, false, entry.clone());
+                let alternative = self.seq(*alternative, false, entry);
+                let end = join(consequent, alternative)?;
+                state.locals = end.locals;
+                state.stack.extend(end.stack);
+            }
+            #[cfg(feature = "unstable")]
+            Instr::Resume(Resume { handlers, .. }) => {
+                let (pops, pushes) = self.arity(instr);
+                state.split_off(pops);
+                // Suspending the resumed continuation branches to a handler
+                // with whatever values were passed to `suspend`.
+                for handler in handlers.iter() {
+                    let arity = self.targets[&handler.block].arity;
+                    let suspended = State {
+                        locals: state.locals.clone(),
+                        stack: (0..arity).map(|_| L::unknown()).collect(),
+                    };
+                    self.branch(handler.block, &suspended);
+                }
+                state.stack.extend((0..pushes).map(|_| L::unknown()));
+            }
+            _ => {
+                let (pops, pushes) = self.arity(instr);
+                state.split_off(pops);
+                state.stack.extend((0..pushes).map(|_| L::unknown()));
+            }
+        }
+        Some(state)
+    }
+
+    /// Get how many operands `instr` pops and how many results it pushes, for
+    /// the instructions whose results are unknown.
+    fn arity(&self, instr: &Instr) -> (usize, usize) {
+        let types = &self.module.types;
+        match instr {
+            Instr::Call(Call { func }) => {
+                let ty = self.module.funcs.get(*func).ty();
+                (types.params(ty).len(), types.results(ty).len())
+            }
+            Instr::CallIndirect(CallIndirect { ty, .. }) => {
+                (types.params(*ty).len() + 1, types.results(*ty).len())
+            }
+            #[cfg(feature = "unstable")]
+            Instr::Suspend(Suspend { tag }) => {
+                let ty = self.module.tags.get(*tag).ty;
+                (types.params(ty).len(), types.results(ty).len())
+            }
+            #[cfg(feature = "unstable")]
+            Instr::Resume(Resume { ty, .. }) => match types.get(*ty).cont_of() {
+                Some(ty) => (types.params(ty).len() + 1, types.results(ty).len()),
+                None => (1, 0),
+            },
+            Instr::Drop(_) | Instr::GlobalSet(_) => (1, 0),
+            Instr::MemorySize(_) | Instr::TableSize(_) | Instr::RefNull(_) | Instr::RefFunc(_) => {
+                (0, 1)
+            }
+            Instr::MemoryGrow(_)
+            | Instr::Load(_)
+            | Instr::LoadSimd(_)
+            | Instr::TableGet(_)
+            | Instr::RefIsNull(_) => (1, 1),
+            Instr::Store(_) | Instr::TableSet(_) => (2, 0),
+            Instr::AtomicRmw(_)
+            | Instr::AtomicNotify(_)
+            | Instr::TableGrow(_)
+            | Instr::V128Swizzle(_)
+            | Instr::V128Shuffle(_) => (2, 1),
+            Instr::Cmpxchg(_) | Instr::AtomicWait(_) | Instr::V128Bitselect(_) => (3, 1),
+            Instr::MemoryInit(_)
+            | Instr::MemoryCopy(_)
+            | Instr::MemoryFill(_)
+            | Instr::TableFill(_)
+            | Instr::TableInit(_)
+            | Instr::TableCopy(_) => (3, 0),
+            #[cfg(feature = "unstable")]
+            Instr::GlobalAtomicGet(_) => (0, 1),
+            #[cfg(feature = "unstable")]
+            Instr::GlobalAtomicSet(_) => (1, 0),
+            #[cfg(feature = "unstable")]
+            Instr::ContNew(_) => (1, 1),
+            _ => (0, 0),
+        }
+    }
+}
+
+/// A lattice of constants: values are either always the same constant, or
+/// unknown.
+#[derive(Debug, Copy, Clone)]
+pub enum Constant {
+    /// The value is always this constant.
+    Known(Value),
+    /// The value may differ between executions.
+    Unknown,
+}
+
+impl PartialEq for Constant {
+    fn eq(&self, other: &Constant) -> bool {
+        // Floats are compared by their bits, so that NaNs equal themselves
+        // and the analysis of loops ends.
+        match (self, other) {
+            (Constant::Known(a), Constant::Known(b)) => bits(a) == bits(b),
+            (Constant::Unknown, Constant::Unknown) => true,
+            _ => false,
+        }
+    }
+}
+
+fn bits(value: &Value) -> (u8, u128) {
+    match *value {
+        Value::I32(n) => (0, n as u32 as u128),
+        Value::I64(n) => (1, n as u64 as u128),
+        Value::F32(n) => (2, n.to_bits() as u128),
+        Value::F64(n) => (3, n.to_bits() as u128),
+        Value::V128(n) => (4, n),
+    }
+}
+
+impl Lattice for Constant {
+    fn unknown() -> Constant {
+        Constant::Unknown
+    }
+
+    fn constant(value: Value) -> Constant {
+        Constant::Known(value)
+    }
+
+    fn join(&self, other: &Constant) -> Constant {
+        if self == other {
+            *self
+        } else {
+            Constant::Unknown
+        }
+    }
+
+    fn unop(op: UnaryOp, operand: &Constant) -> Constant {
+        use UnaryOp::*;
+        let value = match (op, operand) {
+            (I32Eqz, Constant::Known(Value::I32(n))) => Value::I32((*n == 0) as i32),
+            (I32Clz, Constant::Known(Value::I32(n))) => Value::I32(n.leading_zeros() as i32),
+            (I32Ctz, Constant::Known(Value::I32(n))) => Value::I32(n.trailing_zeros() as i32),
+            (I32Popcnt, Constant::Known(Value::I32(n))) => Value::I32(n.count_ones() as i32),
+            (I32Extend8S, Constant::Known(Value::I32(n))) => Value::I32(*n as i8 as i32),
+            (I32Extend16S, Constant::Known(Value::I32(n))) => Value::I32(*n as i16 as i32),
+            (I32WrapI64, Constant::Known(Value::I64(n))) => Value::I32(*n as i32),
+            (I64Eqz, Constant::Known(Value::I64(n))) => Value::I32((*n == 0) as i32),
+            (I64Clz, Constant::Known(Value::I64(n))) => Value::I64(n.leading_zeros() as i64),
+            (I64Ctz, Constant::Known(Value::I64(n))) => Value::I64(n.trailing_zeros() as i64),
+            (I64Popcnt, Constant::Known(Value::I64(n))) => Value::I64(n.count_ones() as i64),
+            (I64Extend8S, Constant::Known(Value::I64(n))) => Value::I64(*n as i8 as i64),
+            (I64Extend16S, Constant::Known(Value::I64(n))) => Value::I64(*n as i16 as i64),
+            (I64Extend32S, Constant::Known(Value::I64(n))) => Value::I64(*n as i32 as i64),
+            (I64ExtendSI32, Constant::Known(Value::I32(n))) => Value::I64(*n as i64),
+            (I64ExtendUI32, Constant::Known(Value::I32(n))) => Value::I64(*n as u32 as i64),
+            _ => return Constant::Unknown,
+        };
+        Constant::Known(value)
+    }
+
+    fn binop(op: BinaryOp, lhs: &Constant, rhs: &Constant) -> Constant {
+        let value = match (lhs, rhs) {
+            (Constant::Known(Value::I32(a)), Constant::Known(Value::I32(b))) => {
+                i32_binop(op, *a, *b)
+            }
+            (Constant::Known(Value::I64(a)), Constant::Known(Value::I64(b))) => {
+                i64_binop(op, *a, *b)
+            }
+            _ => None,
+        };
+        value.map_or(Constant::Unknown, Constant::Known)
+    }
+}
+
+/// Evaluate an `i32` binary operator, or return `None` if it traps or isn't
+/// an `i32` operator.
+fn i32_binop(op: BinaryOp, a: i32, b: i32) -> Option<Value> {
+    use BinaryOp::*;
+    let (ua, ub) = (a as u32, b as u32);
+    let n = match op {
+        I32Eq => (a == b) as i32,
+        I32Ne => (a != b) as i32,
+        I32LtS => (a < b) as i32,
+        I32LtU => (ua < ub) as i32,
+        I32GtS => (a > b) as i32,
+        I32GtU => (ua > ub) as i32,
+        I32LeS => (a <= b) as i32,
+        I32LeU => (ua <= ub) as i32,
+        I32GeS => (a >= b) as i32,
+        I32GeU => (ua >= ub) as i32,
+        I32Add => a.wrapping_add(b),
+        I32Sub => a.wrapping_sub(b),
+        I32Mul => a.wrapping_mul(b),
+        I32DivS if b != 0 && !(a == i32::MIN && b == -1) => a / b,
+        I32DivU if b != 0 => (ua / ub) as i32,
+        I32RemS if b != 0 => a.wrapping_rem(b),
+        I32RemU if b != 0 => (ua % ub) as i32,
+        I32And => a & b,
+        I32Or => a | b,
+        I32Xor => a ^ b,
+        I32Shl => a.wrapping_shl(ub),
+        I32ShrS => a.wrapping_shr(ub),
+        I32ShrU => ua.wrapping_shr(ub) as i32,
+        I32Rotl => ua.rotate_left(ub % 32) as i32,
+        I32Rotr => ua.rotate_right(ub % 32) as i32,
+        _ => return None,
+    };
+    Some(Value::I32(n))
+}
+
+/// Evaluate an `i64` binary operator, or return `None` if it traps or isn't
+/// an `i64` operator.
+fn i64_binop(op: BinaryOp, a: i64, b: i64) -> Option<Value> {
+    use BinaryOp::*;
+    let (ua, ub) = (a as u64, b as u64);
+    let compare = |result: bool| Some(Value::I32(result as i32));
+    let n = match op {
+        I64Eq => return compare(a == b),
+        I64Ne => return compare(a != b),
+        I64LtS => return compare(a < b),
+        I64LtU => return compare(ua < ub),
+        I64GtS => return compare(a > b),
+        I64GtU => return compare(ua > ub),
+        I64LeS => return compare(a <= b),
+        I64LeU => return compare(ua <= ub),
+        I64GeS => return compare(a >= b),
+        I64GeU => return compare(ua >= ub),
+        I64Add => a.wrapping_add(b),
+        I64Sub => a.wrapping_sub(b),
+        I64Mul => a.wrapping_mul(b),
+        I64DivS if b != 0 && !(a == i64::MIN && b == -1) => a / b,
+        I64DivU if b != 0 => (ua / ub) as i64,
+        I64RemS if b != 0 => a.wrapping_rem(b),
+        I64RemU if b != 0 => (ua % ub) as i64,
+        I64And => a & b,
+        I64Or => a | b,
+        I64Xor => a ^ b,
+        I64Shl => a.wrapping_shl(ub as u32),
+        I64ShrS => a.wrapping_shr(ub as u32),
+        I64ShrU => ua.wrapping_shr(ub as u32) as i64,
+        I64Rotl => ua.rotate_left((ub % 64) as u32) as i64,
+        I64Rotr => ua.rotate_right((ub % 64) as u32) as i64,
+        _ => return None,
+    };
+    Some(Value::I64(n))
+}
+
+/// A lattice of the bits of integers that are known to always be zero, or
+/// always be one.
+///
+/// The bits of `i32` values are the low 32 bits of the masks, and nothing is
+/// known about the bits of other values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct KnownBits {
+    /// The bits that are always zero.
+    pub zeros: u64,
+    /// The bits that are always one.
+    pub ones: u64,
+}
+
+impl KnownBits {
+    /// Get the value if all of its `width` low bits are known.
+    pub fn value(&self, width: u32) -> Option<u64> {
+        let mask = width_mask(width);
+        if (self.zeros | self.ones) & mask == mask {
+            Some(self.ones & mask)
+        } else {
+            None
+        }
+    }
+
+    /// Get the largest unsigned value of `width` bits that this may be.
+    pub fn max_unsigned(&self, width: u32) -> u64 {
+        !self.zeros & width_mask(width)
+    }
+
+    fn masked(self, width: u32) -> KnownBits {
+        let mask = width_mask(width);
+        KnownBits {
+            zeros: self.zeros & mask,
+            ones: self.ones & mask,
+        }
+    }
+
+    fn boolean() -> KnownBits {
+        KnownBits {
+            zeros: width_mask(32) & !1,
+            ones: 0,
+        }
+    }
+}
+
+fn width_mask(width: u32) -> u64 {
+    if width >= 64 {
+        u64::MAX
+    } else {
+        (1 << width) - 1
+    }
+}
+
+impl Lattice for KnownBits {
+    fn unknown() -> KnownBits {
+        KnownBits::default()
+    }
+
+    fn constant(value: Value) -> KnownBits {
+        let (n, width) = match value {
+            Value::I32(n) => (n as u32 as u64, 32),
+            Value::I64(n) => (n as u64, 64),
+            _ => return KnownBits::unknown(),
+        };
+        KnownBits {
+            zeros: !n & width_mask(width),
+            ones: n,
+        }
+    }
+
+    fn join(&self, other: &KnownBits) -> KnownBits {
+        KnownBits {
+            zeros: self.zeros & other.zeros,
+            ones: self.ones & other.ones,
+        }
+    }
+
+    fn unop(op: UnaryOp, operand: &KnownBits) -> KnownBits {
+        use UnaryOp::*;
+        match op {
+            I32Eqz | I64Eqz => KnownBits::boolean(),
+            I32WrapI64 => operand.masked(32),
+            I64ExtendUI32 => KnownBits {
+                zeros: operand.zeros | !width_mask(32),
+                ones: operand.ones & width_mask(32),
+            },
+            _ => KnownBits::unknown(),
+        }
+    }
+
+    fn binop(op: BinaryOp, lhs: &KnownBits, rhs: &KnownBits) -> KnownBits {
+        use BinaryOp::*;
+        let width = match op {
+            I32And | I32Or | I32Xor | I32Shl | I32ShrU => 32,
+            I64And | I64Or | I64Xor | I64Shl | I64ShrU => 64,
+            I32Eq | I32Ne | I32LtS | I32LtU | I32GtS | I32GtU | I32LeS | I32LeU | I32GeS
+            | I32GeU | I64Eq | I64Ne | I64LtS | I64LtU | I64GtS | I64GtU | I64LeS | I64LeU
+            | I64GeS | I64GeU | F32Eq | F32Ne | F32Lt | F32Gt | F32Le | F32Ge | F64Eq | F64Ne
+            | F64Lt | F64Gt | F64Le | F64Ge => return KnownBits::boolean(),
+            _ => {
+                // Other operators are only understood on constants.
+                let value = match (lhs.value(32), rhs.value(32)) {
+                    (Some(a), Some(b)) => i32_binop(op, a as i32, b as i32),
+                    _ => None,
+                };
+                let value = value.or_else(|| match (lhs.value(64), rhs.value(64)) {
+                    (Some(a), Some(b)) => i64_binop(op, a as i64, b as i64),
+                    _ => None,
+                });
+                return value.map_or_else(KnownBits::unknown, KnownBits::constant);
+            }
+        };
+        let known = match op {
+            I32And | I64And => KnownBits {
+                zeros: lhs.zeros | rhs.zeros,
+                ones: lhs.ones & rhs.ones,
+            },
+            I32Or | I64Or => KnownBits {
+                zeros: lhs.zeros & rhs.zeros,
+                ones: lhs.ones | rhs.ones,
+            },
+            I32Xor | I64Xor => KnownBits {
+                zeros: (lhs.zeros & rhs.zeros) | (lhs.ones & rhs.ones),
+                ones: (lhs.zeros & rhs.ones) | (lhs.ones & rhs.zeros),
+            },
+            _ => {
+                // Shifts are only understood by known amounts.
+                let amount = match rhs.value(width) {
+                    Some(amount) => (amount % width as u64) as u32,
+                    None => return KnownBits::unknown(),
+                };
+                let lhs = lhs.masked(width);
+                let shifted = width_mask(width) & !(width_mask(width) >> amount);
+                match op {
+                    I32Shl | I64Shl => KnownBits {
+                        zeros: (lhs.zeros << amount) | width_mask(amount),
+                        ones: lhs.ones << amount,
+                    },
+                    _ => KnownBits {
+                        zeros: (lhs.zeros >> amount) | shifted,
+                        ones: lhs.ones >> amount,
+                    },
+                }
+            }
+        };
+        known.masked(width)
+    }
+}
@@ -0,0 +1,142 @@
+//! Remove unreachable instructions from functions.
+//!
+//! Transforms that insert branches, returns or traps, and functions built
+//! with `FunctionBuilder`, often leave instructions behind them that can never
+//! run. The parser never creates such code, and the emitter and other passes
+//! don't expect it, so this pass removes it again.
+//!
+//! Within each instruction sequence, everything after a `br`, `br_table`,
+//! `return` or `unreachable` is removed, along with the blocks in it. So is
+//! everything after a `block`, `loop` or `if` that never reaches its end,
+//! because all of its paths branch elsewhere, return or trap. Such code is
+//! replaced with an `unreachable`, since the instructions after the `block`,
+//! `loop` or `if` are still type checked as if its results were on the stack.
+
+use crate::ir::*;
+use crate::{LocalFunction, Module};
+use std::collections::HashSet;
+
+/// Remove the unreachable instructions of every local function in `module`.
+///
+/// Returns how many instructions were removed, including the ones nested in
+/// removed blocks.
+pub fn run(module: &mut Module) -> usize {
+    module
+        .funcs
+        .iter_local_mut()
+        .map(|(_, func)| run_function(func))
+        .sum()
+}
+
+/// Remove the unreachable instructions of `func`.
+///
+/// Returns how many instructions were removed, including the ones nested in
+/// removed blocks.
+pub fn run_function(func: &mut LocalFunction) -> usize {
+    let mut dce = Dce {
+        func,
+        branched: HashSet::new(),
+        removed: 0,
+    };
+    let entry = dce.func.entry_block();
+    dce.seq(entry);
+    dce.removed
+}
+
+struct Dce<'a> {
+    func: &'a mut LocalFunction,
+    // The sequences that reachable branches target.
+    branched: HashSet<InstrSeqId>,
+    removed: usize,
+}
+
+impl Dce<'_> {
+    /// Remove the unreachable instructions of `seq`, and return whether its
+    /// end is reached by falling through.
+    fn seq(&mut self, seq: InstrSeqId) -> bool {
+        let mut i = 0;
+        while i < self.func.block(seq).instrs.len() {
+            let instr = self.func.block(seq).instrs[i].0.clone();
+            let falls_through = match &instr {
+                Instr::Block(Block { seq }) => self.seq(*seq) || self.branched.contains(seq),
+                // Branches to a loop go back to its start.
+                Instr::Loop(Loop { seq }) => self.seq(*seq),
+                Instr::IfElse(IfElse {
+                    consequent,
+                    alternative,
+                }) => {
+                    let consequent_end = self.seq(*consequent);
+                    let alternative_end = self.seq(*alternative);
+                    consequent_end
+                        || alternative_end
+                        || self.branched.contains(consequent)
+                        || self.branched.contains(alternative)
+                }
+                Instr::Br(Br { block }) => {
+                    self.branched.insert(*block);
+                    false
+                }
+                Instr::BrIf(BrIf { block }) => {
+                    self.branched.insert(*block);
+                    true
+                }
+                Instr::BrTable(BrTable { blocks, default }) => {
+                    self.branched.extend(blocks.iter().chain(Some(default)));
+                    false
+                }
+                #[cfg(feature = "unstable")]
+                Instr::Resume(Resume { handlers, .. }) => {
+                    self.branched.extend(handlers.iter().map(|h| h.block));
+                    true
+                }
+                Instr::Return(_) | Instr::Unreachable(_) => false,
+                _ => true,
+            };
+            if !falls_through {
+                let nested = matches!(instr, Instr::Block(_) | Instr::Loop(_) | Instr::IfElse(_));
+                self.truncate(seq, i + 1, nested);
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
+    /// Remove the instructions of `seq` from `at` on, replacing them with an
+    /// `unreachable` if `trap`.
+    fn truncate(&mut self, seq: InstrSeqId, at: usize, trap: bool) {
+        let instrs = &mut self.func.block_mut(seq).instrs;
+        let rest = instrs.split_off(at);
+        if rest.is_empty() {
+            return;
+        }
+        if trap {
+            if let [(Instr::Unreachable(_), _)] = rest[..] {
+                instrs.extend(rest);
+                return;
+            }
+            instrs.push((Unreachable {}.into(), rest[0].1));
+        }
+        self.delete(&rest);
+    }
+
+    /// Delete `instrs` and the sequences nested in them.
+    fn delete(&mut self, instrs: &[(Instr, InstrLocId)]) {
+        for (instr, _) in instrs {
+            self.removed += 1;
+            let nested = match instr {
+                Instr::Block(Block { seq }) | Instr::Loop(Loop { seq }) => vec![*seq],
+                Instr::IfElse(IfElse {
+                    consequent,
+                    alternative,
+                }) => vec![*consequent, *alternative],
+                _ => continue,
+            };
+            for seq in nested {
+                let instrs = std::mem::take(&mut self.func.block_mut(seq).instrs);
+                self.delete(&instrs);
+                self.func.builder_mut().arena.delete(seq);
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Find what the functions of a module may do besides computing results.
+//!
+//! Removing a call whose result is unused, reusing the result of an earlier
+//! call, or evaluating a call at compile time all depend on what the called
+//! function does, including everything that it calls in turn. This analysis
+//! classifies each function by the strongest effect that it, or any function
+//! it transitively calls, may have.
+
+use crate::ir::*;
+use crate::{FunctionId, FunctionKind, Module};
+use std::collections::HashMap;
+
+/// The effects that a function may have, from weakest to strongest.
+///
+/// Each effect includes the weaker ones, so for example a function that may
+/// write memory may read it as well. Functions with any of these effects may
+/// still trap, such as on a division by zero or an out of bounds access.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Effect {
+    /// The function's results only depend on its arguments and on immutable
+    /// globals, and it changes nothing.
+    Pure,
+    /// The function may read state, such as memories, tables and mutable
+    /// globals.
+    ReadsMemory,
+    /// The function may write state, such as memories, tables and globals,
+    /// or drop data and element segments.
+    WritesMemory,
+    /// The function may call imported functions, whose effects are unknown,
+    /// or call functions indirectly, or switch stacks.
+    CallsImports,
+}
+
+/// Find the effects of all the functions of `module`.
+///
+/// Imported functions are `Effect::CallsImports`.
+pub fn run(module: &Module) -> HashMap<FunctionId, Effect> {
+    let mut effects = HashMap::new();
+    let mut callees = HashMap::new();
+    for func in module.funcs.iter() {
+        let (effect, calls) = match &func.kind {
+            FunctionKind::Local(local) => {
+                let mut own = Own {
+                    module,
+                    effect: Effect::Pure,
+                    calls: Vec::new(),
+                };
+                dfs_in_order(&mut own, local, local.entry_block());
+                (own.effect, own.calls)
+            }
+            _ => (Effect::CallsImports, Vec::new()),
+        };
+        effects.insert(func.id(), effect);
+        callees.insert(func.id(), calls);
+    }
+
+    // Functions have the effects of the functions they call, which is found
+    // by iterating until nothing changes, since calls may be recursive.
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (func, calls) in callees.iter() {
+            let effect = calls
+                .iter()
+                .map(|callee| effects[callee])
+                .fold(effects[func], Effect::max);
+            if effect != effects[func] {
+                effects.insert(*func, effect);
+                changed = true;
+            }
+        }
+    }
+    effects
+}
+
+/// Finds the effects of a function's own instructions, and the functions it
+/// calls directly.
+struct Own<'a> {
+    module: &'a Module,
+    effect: Effect,
+    calls: Vec<FunctionId>,
+}
+
+impl<'instr> Visitor<'instr> for Own<'_> {
+    fn visit_instr(&mut self, instr: &'instr Instr, _: &'instr InstrLocId) {
+        let effect = match instr {
+            Instr::Call(Call { func }) => {
+                self.calls.push(*func);
+                Effect::Pure
+            }
+            Instr::GlobalGet(GlobalGet { global }) => {
+                if self.module.globals.get(*global).mutable {
+                    Effect::ReadsMemory
+                } else {
+                    Effect::Pure
+                }
+            }
+            Instr::Load(_)
+            | Instr::LoadSimd(_)
+            | Instr::MemorySize(_)
+            | Instr::TableGet(_)
+            | Instr::TableSize(_) => Effect::ReadsMemory,
+            Instr::GlobalSet(_)
+            | Instr::Store(_)
+            | Instr::AtomicRmw(_)
+            | Instr::Cmpxchg(_)
+            | Instr::AtomicNotify(_)
+            | Instr::AtomicWait(_)
+            | Instr::AtomicFence(_)
+            | Instr::MemoryGrow(_)
+            | Instr::MemoryInit(_)
+            | Instr::DataDrop(_)
+            | Instr::MemoryCopy(_)
+            | Instr::MemoryFill(_)
+            | Instr::TableSet(_)
+            | Instr::TableGrow(_)
+            | Instr::TableFill(_)
+            | Instr::TableInit(_)
+            | Instr::ElemDrop(_)
+            | Instr::TableCopy(_) => Effect::WritesMemory,
+            #[cfg(feature = "unstable")]
+            Instr::GlobalAtomicGet(_) => Effect::ReadsMemory,
+            #[cfg(feature = "unstable")]
+            Instr::GlobalAtomicSet(_) => Effect::WritesMemory,
+            #[cfg(feature = "unstable")]
+            Instr::Suspend(_) | Instr::Resume(_) => Effect::CallsImports,
+            Instr::CallIndirect(_) => Effect::CallsImports,
+            _ => Effect::Pure,
+        };
+        self.effect = self.effect.max(effect);
+    }
+}
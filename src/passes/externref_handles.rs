@@ -0,0 +1,326 @@
+//! Shim `i32` handles to host objects into `externref`s.
+//!
+//! APIs designed before reference types pass host objects around as `i32`
+//! handles, that the host maps to its objects in a table of its own. This
+//! module helps migrate such modules to `externref`s, one function at a time,
+//! without touching the code that uses the handles: `add_table` adds a table
+//! of `externref`s to a module, along with functions to allocate handles in
+//! it, free them and get the references back, and `shim_import` and
+//! `shim_export` change the signatures of imported and exported functions to
+//! take and return `externref`s in place of chosen handles, converting
+//! between the two at the boundary.
+//!
+//! Handle `0` always stands for the null reference, so that allocating a
+//! handle for null doesn't use up a slot in the table. Nulls are never stored
+//! in the table otherwise, since null slots are the free ones.
+
+use crate::ir::*;
+use crate::passes::record_replay;
+use crate::{
+    AnyrefTable, ExportItem, FunctionBuilder, FunctionId, FunctionKind, GlobalId, InitExpr,
+};
+use crate::{Module, Result, TableId, TableKind, ValType};
+use anyhow::bail;
+use std::collections::{HashMap, HashSet};
+
+/// A table of `externref`s, and the functions that manage the handles into
+/// it.
+#[derive(Debug, Copy, Clone)]
+pub struct HandleTable {
+    /// The table of `externref`s.
+    pub table: TableId,
+    /// A function of type `[externref] -> [i32]`, that stores a reference in
+    /// a free slot of the table, growing it if there are none, and returns
+    /// the slot's handle.
+    pub alloc: FunctionId,
+    /// A function of type `[i32] -> []`, that frees the slot of a handle.
+    pub free: FunctionId,
+    /// A function of type `[i32] -> [externref]`, that gets the reference of
+    /// a handle.
+    pub get: FunctionId,
+}
+
+/// Add a table of `externref`s to `module`, along with the functions
+/// managing the handles into it.
+///
+/// Slots are reused once they are freed. `alloc` scans for a free slot,
+/// starting from the lowest one that may be free, before growing the table.
+pub fn add_table(module: &mut Module) -> HandleTable {
+    let table = module
+        .tables
+        .add_local(1, None, TableKind::Anyref(AnyrefTable::default()));
+    // The lowest slot that may be free.
+    let hint = module
+        .globals
+        .add_local(ValType::I32, true, InitExpr::Value(Value::I32(1)));
+
+    let alloc = add_alloc(module, table, hint);
+    let free = add_free(module, table, hint);
+
+    let handle = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[ValType::Anyref]);
+    builder.func_body().local_get(handle).table_get(table);
+    builder.name("externref_get".to_string());
+    let get = builder.finish(vec![handle], &mut module.funcs);
+
+    HandleTable {
+        table,
+        alloc,
+        free,
+        get,
+    }
+}
+
+fn add_alloc(module: &mut Module, table: TableId, hint: GlobalId) -> FunctionId {
+    let reference = module.locals.add(ValType::Anyref);
+    let slot = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::Anyref], &[ValType::I32]);
+    let mut body = builder.func_body();
+
+    body.local_get(reference)
+        .ref_is_null()
+        .if_else(
+            None,
+            |then| {
+                then.i32_const(0).return_();
+            },
+            |_| {},
+        )
+        .global_get(hint)
+        .local_set(slot)
+        .block(None, |full| {
+            let full_id = full.id();
+            full.loop_(None, |scan| {
+                let scan_id = scan.id();
+                scan.local_get(slot)
+                    .table_size(table)
+                    .binop(BinaryOp::I32GeU)
+                    .br_if(full_id)
+                    .local_get(slot)
+                    .table_get(table)
+                    .ref_is_null()
+                    .if_else(
+                        None,
+                        |then| {
+                            then.local_get(slot)
+                                .local_get(reference)
+                                .table_set(table)
+                                .local_get(slot)
+                                .i32_const(1)
+                                .binop(BinaryOp::I32Add)
+                                .global_set(hint)
+                                .local_get(slot)
+                                .return_();
+                        },
+                        |_| {},
+                    )
+                    .local_get(slot)
+                    .i32_const(1)
+                    .binop(BinaryOp::I32Add)
+                    .local_set(slot)
+                    .br(scan_id);
+            });
+        })
+        // Every slot is taken, so grow the table by one.
+        .local_get(reference)
+        .i32_const(1)
+        .table_grow(table)
+        .local_tee(slot)
+        .i32_const(-1)
+        .binop(BinaryOp::I32Eq)
+        .if_else(
+            None,
+            |then| {
+                then.unreachable();
+            },
+            |_| {},
+        )
+        .local_get(slot)
+        .i32_const(1)
+        .binop(BinaryOp::I32Add)
+        .global_set(hint)
+        .local_get(slot);
+
+    builder.name("externref_alloc".to_string());
+    builder.finish(vec![reference], &mut module.funcs)
+}
+
+fn add_free(module: &mut Module, table: TableId, hint: GlobalId) -> FunctionId {
+    let handle = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32], &[]);
+    builder.func_body().block(None, |done| {
+        let done_id = done.id();
+        done.local_get(handle)
+            .unop(UnaryOp::I32Eqz)
+            .br_if(done_id)
+            .local_get(handle)
+            .ref_null()
+            .table_set(table)
+            .local_get(handle)
+            .global_get(hint)
+            .binop(BinaryOp::I32GeU)
+            .br_if(done_id)
+            .local_get(handle)
+            .global_set(hint);
+    });
+    builder.name("externref_free".to_string());
+    builder.finish(vec![handle], &mut module.funcs)
+}
+
+/// Change the imported function `func` to take `externref`s instead of the
+/// `i32` handles at the indices `params` of its parameters, and to return
+/// `externref`s instead of the handles at the indices `results` of its
+/// results.
+///
+/// Every use of `func` within the module goes through a generated thunk with
+/// its original signature instead, which gets the references of the handles
+/// that it is passed, and allocates handles for the references that the
+/// import returns. The module owns these handles, and has to free them.
+///
+/// Returns the thunk.
+pub fn shim_import(
+    module: &mut Module,
+    handles: &HandleTable,
+    func: FunctionId,
+    params: &[usize],
+    results: &[usize],
+) -> Result<FunctionId> {
+    let ty = match &module.funcs.get(func).kind {
+        FunctionKind::Import(import) => import.ty,
+        _ => bail!("`shim_import` can only shim imported functions"),
+    };
+    let (old_params, old_results) = module.types.params_results(ty);
+    let (old_params, old_results) = (old_params.to_vec(), old_results.to_vec());
+    let new_params = with_externrefs(&old_params, params)?;
+    let new_results = with_externrefs(&old_results, results)?;
+    let new_ty = module.types.add(&new_params, &new_results);
+    if let FunctionKind::Import(import) = &mut module.funcs.get_mut(func).kind {
+        import.ty = new_ty;
+    }
+
+    let thunk = convert(
+        module,
+        handles,
+        func,
+        (&old_params, &old_results),
+        params,
+        results,
+    );
+    let name = module.funcs.get(func).name.clone().unwrap_or_default();
+    module.funcs.get_mut(thunk).name = Some(format!("externref_shim${}", name));
+
+    let mut thunks = HashMap::new();
+    thunks.insert(func, thunk);
+    record_replay::redirect(module, &thunks, &HashSet::new());
+    Ok(thunk)
+}
+
+/// Export the exported function `func` through a generated wrapper, which
+/// takes `externref`s instead of the `i32` handles at the indices `params` of
+/// its parameters, and returns `externref`s instead of the handles at the
+/// indices `results` of its results.
+///
+/// The wrapper allocates handles for the references it is passed, which the
+/// module owns and has to free, and gets the references of the handles that
+/// `func` returns.
+///
+/// Returns the wrapper.
+pub fn shim_export(
+    module: &mut Module,
+    handles: &HandleTable,
+    func: FunctionId,
+    params: &[usize],
+    results: &[usize],
+) -> Result<FunctionId> {
+    let exports = module
+        .exports
+        .iter()
+        .filter(|e| matches!(e.item, ExportItem::Function(f) if f == func))
+        .map(|e| e.id())
+        .collect::<Vec<_>>();
+    if exports.is_empty() {
+        bail!("`shim_export` can only shim exported functions");
+    }
+    let ty = module.funcs.get(func).ty();
+    let (old_params, old_results) = module.types.params_results(ty);
+    let (old_params, old_results) = (old_params.to_vec(), old_results.to_vec());
+    let new_params = with_externrefs(&old_params, params)?;
+    let new_results = with_externrefs(&old_results, results)?;
+
+    let wrapper = convert(
+        module,
+        handles,
+        func,
+        (&new_params, &new_results),
+        params,
+        results,
+    );
+    let name = module.funcs.get(func).name.clone().unwrap_or_default();
+    module.funcs.get_mut(wrapper).name = Some(format!("externref_shim${}", name));
+    for export in exports {
+        module.exports.get_mut(export).item = ExportItem::Function(wrapper);
+    }
+    Ok(wrapper)
+}
+
+/// `types` with the `i32`s at `indices` replaced by `externref`s.
+fn with_externrefs(types: &[ValType], indices: &[usize]) -> Result<Vec<ValType>> {
+    let mut types = types.to_vec();
+    for &index in indices {
+        match types.get_mut(index) {
+            Some(ty @ ValType::I32) => *ty = ValType::Anyref,
+            Some(_) => bail!("the handle at index {} isn't an `i32`", index),
+            None => bail!("the handle index {} is out of bounds", index),
+        }
+    }
+    Ok(types)
+}
+
+/// Generate a function of the type `ty` that calls `func`, converting the
+/// values at the indices `params` of its parameters and `results` of its
+/// results between handles and references.
+fn convert(
+    module: &mut Module,
+    handles: &HandleTable,
+    func: FunctionId,
+    ty: (&[ValType], &[ValType]),
+    params: &[usize],
+    results: &[usize],
+) -> FunctionId {
+    let (ty_params, ty_results) = ty;
+    let callee_ty = module.funcs.get(func).ty();
+    let callee_results = module.types.results(callee_ty).to_vec();
+    // References are converted to handles, and handles to references.
+    let conversion = |ty: ValType| match ty {
+        ValType::Anyref => handles.alloc,
+        _ => handles.get,
+    };
+
+    let args = ty_params
+        .iter()
+        .map(|ty| module.locals.add(*ty))
+        .collect::<Vec<_>>();
+    let rets = callee_results
+        .iter()
+        .map(|ty| module.locals.add(*ty))
+        .collect::<Vec<_>>();
+    let mut builder = FunctionBuilder::new(&mut module.types, ty_params, ty_results);
+    let mut body = builder.func_body();
+    for (i, (&arg, &ty)) in args.iter().zip(ty_params).enumerate() {
+        body.local_get(arg);
+        if params.contains(&i) {
+            body.call(conversion(ty));
+        }
+    }
+    body.call(func);
+    for &ret in rets.iter().rev() {
+        body.local_set(ret);
+    }
+    for (i, (&ret, &ty)) in rets.iter().zip(&callee_results).enumerate() {
+        body.local_get(ret);
+        if results.contains(&i) {
+            body.call(conversion(ty));
+        }
+    }
+    builder.finish(args, &mut module.funcs)
+}
@@ -1,16 +1,66 @@
 //! Removes any non-referenced items from a module
 //!
-//! This commit will remove functions, data, etc, that are not referenced
-//! internally and can be safely removed.
+//! This commit will remove functions, data, types, tags, etc, that are not
+//! referenced internally and can be safely removed. Types are only kept if a
+//! used function, instruction, tag or continuation type refers to them, so
+//! the signatures of removed functions are removed along with them.
 
 use crate::map::IdHashSet;
 use crate::passes::used::Used;
-use crate::{ImportKind, Module};
+use crate::passes::Roots;
+use crate::{ElementKind, Export, ExportItem, ImportKind, Module, Result};
 use id_arena::Id;
 
 /// Run GC passes over the module specified.
 pub fn run(m: &mut Module) {
-    let used = Used::new(m);
+    run_with_roots(m, Roots::new())
+}
+
+/// Run GC passes over the module specified, with `roots` as roots in addition
+/// to the exports, the start function and the roots of custom sections.
+///
+/// Exports that `roots` ignores aren't roots, and are removed if nothing else
+/// uses what they export.
+pub fn run_with_roots(m: &mut Module, roots: Roots) {
+    let ignored_exports = roots.ignored_exports.clone();
+    let used = Used::new(m, roots);
+    remove_unused(m, &ignored_exports, used);
+}
+
+/// Like `run`, but fails with `ErrorKind::Cancelled`, without modifying the
+/// module, if the module's `ModuleConfig::cancellation_token` is cancelled
+/// before it is known what to remove.
+pub fn try_run(m: &mut Module) -> Result<()> {
+    try_run_with_roots(m, Roots::new())
+}
+
+/// Like `run_with_roots`, but cancellable like `try_run`.
+pub fn try_run_with_roots(m: &mut Module, roots: Roots) -> Result<()> {
+    let ignored_exports = roots.ignored_exports.clone();
+    let used = Used::compute(m, roots, m.config.cancellation_token.as_ref())?;
+    remove_unused(m, &ignored_exports, used);
+    Ok(())
+}
+
+fn remove_unused(m: &mut Module, ignored_exports: &IdHashSet<Export>, used: Used) {
+    let mut unused_exports = Vec::new();
+    for export in m.exports.iter() {
+        if !ignored_exports.contains(&export.id()) {
+            continue;
+        }
+        let used = match export.item {
+            ExportItem::Function(f) => used.funcs.contains(&f),
+            ExportItem::Table(t) => used.tables.contains(&t),
+            ExportItem::Memory(m) => used.memories.contains(&m),
+            ExportItem::Global(g) => used.globals.contains(&g),
+        };
+        if !used {
+            unused_exports.push(export.id());
+        }
+    }
+    for id in unused_exports {
+        m.exports.delete(id);
+    }
 
     let mut unused_imports = Vec::new();
     for import in m.imports.iter() {
@@ -40,9 +90,29 @@ pub fn run(m: &mut Module) {
     for id in unused(&used.data, m.data.iter().map(|t| t.id())) {
         m.data.delete(id);
     }
-    for id in unused(&used.elements, m.elements.iter().map(|t| t.id())) {
+    let non_declared = m
+        .elements
+        .iter()
+        .filter(|e| e.kind != ElementKind::Declared)
+        .map(|t| t.id());
+    for id in unused(&used.elements, non_declared) {
         m.elements.delete(id);
     }
+    // Declared element segments are never referenced by instructions, they
+    // only declare which functions may be used with `ref.func`. Keep them, but
+    // only with the functions that remain.
+    for elem in m.elements.iter_mut() {
+        if elem.kind == ElementKind::Declared {
+            elem.members.retain(|f| match f {
+                Some(f) => used.funcs.contains(f),
+                None => true,
+            });
+        }
+    }
+    #[cfg(feature = "unstable")]
+    for id in unused(&used.tags, m.tags.iter().map(|t| t.id())) {
+        m.tags.delete(id);
+    }
     for id in unused(&used.types, m.types.iter().map(|t| t.id())) {
         m.types.delete(id);
     }
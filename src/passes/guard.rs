@@ -0,0 +1,242 @@
+//! Guard regions with canaries around the stack, for detecting memory
+//! corruption cheaply.
+//!
+//! This is a lightweight alternative to a full address sanitizer with shadow
+//! memory. It expects the layout that `wasm-ld` produces by default, with the
+//! static data first, followed by the stack, which grows downwards from the
+//! initial value of `__stack_pointer`, followed by the heap, which starts at
+//! `__heap_base`:
+//!
+//! ```text
+//! | data | stack <- __stack_pointer | heap from __heap_base ...
+//! ```
+//!
+//! The pass moves the stack and the heap up to make room for two guard
+//! regions, one between the data and the stack, and one between the stack
+//! and the heap:
+//!
+//! ```text
+//! | data | guard | stack <- __stack_pointer | guard | heap from __heap_base ...
+//! ```
+//!
+//! Stack overflows and write overflows at the end of the data run into the
+//! lower guard, and stack underflows and heap underflows into the upper one.
+//! Both guards are filled with a canary value when the module is
+//! instantiated, and a generated check function traps when either of them
+//! doesn't hold the canary anymore. Every function calls the check function
+//! periodically, and it can be exported for the embedder to call as well.
+
+use crate::ir::*;
+use crate::{ActiveData, ActiveDataLocation, DataKind, ExportItem, FunctionBuilder, FunctionId};
+use crate::{GlobalId, GlobalKind, InitExpr, MemoryId, Module, Result, ValType};
+use anyhow::bail;
+
+/// The value that guard regions are filled with.
+pub const CANARY: u64 = 0xfeed_face_dead_beef;
+
+const PAGE_SIZE: u32 = 1 << 16;
+
+/// How to guard the stack.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The size of each guard region in bytes, which must be a multiple of
+    /// 16 to keep the stack aligned.
+    pub guard_size: u32,
+
+    /// Check the guard regions every time this many functions have been
+    /// called, or never automatically if this is zero.
+    pub interval: u32,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            guard_size: 1024,
+            interval: 1024,
+        }
+    }
+}
+
+/// Add guard regions around the stack in `memory`, whose pointer is
+/// `stack_pointer`, and periodic checks of their canaries.
+///
+/// The initial values of `stack_pointer` and of the global exported as
+/// `__heap_base` are moved up past the guard regions, and the initial size of
+/// `memory` grows if needed to fit them. Returns the check function.
+///
+/// Fails if the memory doesn't have the layout described in the module
+/// documentation, for example because it was linked with the stack first.
+pub fn run(
+    module: &mut Module,
+    memory: MemoryId,
+    stack_pointer: GlobalId,
+    options: &Options,
+) -> Result<FunctionId> {
+    let guard = options.guard_size;
+    if guard == 0 || guard & 15 != 0 {
+        bail!("the guard size must be a non-zero multiple of 16");
+    }
+    let heap_base = match module.exports.iter().find(|e| e.name == "__heap_base") {
+        Some(export) => match export.item {
+            ExportItem::Global(global) => global,
+            _ => bail!("`__heap_base` isn't a global"),
+        },
+        None => bail!("the module doesn't export `__heap_base`"),
+    };
+    let sp = initial_value(module, stack_pointer, "__stack_pointer")?;
+    let heap = initial_value(module, heap_base, "__heap_base")?;
+    let data_end = data_end(module, memory)?;
+    if data_end > sp || sp > heap {
+        bail!(
+            "expected the data to end before the stack, and the stack before the heap, \
+             but the data ends at {}, the stack at {} and the heap starts at {}",
+            data_end,
+            sp,
+            heap
+        );
+    }
+
+    let lower = data_end;
+    let upper = checked_add(sp, guard)?;
+    let end = checked_add(upper, guard)?;
+    set_initial_value(module, stack_pointer, upper);
+    set_initial_value(module, heap_base, checked_add(heap, 2 * guard)?);
+    let pages = end / PAGE_SIZE + u32::from(end % PAGE_SIZE != 0);
+    if module.memories.get(memory).initial < pages {
+        module.memories.get_mut(memory).set_initial(pages)?;
+    }
+
+    let canaries = CANARY
+        .to_le_bytes()
+        .iter()
+        .copied()
+        .cycle()
+        .take(guard as usize)
+        .collect::<Vec<_>>();
+    for start in [lower, upper].iter() {
+        let data = module.data.add_passive(canaries.clone());
+        module.make_data_active(data, memory, ActiveDataLocation::Absolute(*start))?;
+    }
+
+    let funcs = module
+        .funcs
+        .iter_local()
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+    let check = check(module, memory, lower, upper, guard);
+    if options.interval != 0 {
+        let tick = tick(module, check, options.interval);
+        for func in funcs {
+            let func = module.funcs.get_mut(func).kind.unwrap_local_mut();
+            let entry = func.entry_block();
+            func.builder_mut()
+                .instr_seq(entry)
+                .instr_at(0, Call { func: tick });
+        }
+    }
+    Ok(check)
+}
+
+fn initial_value(module: &Module, global: GlobalId, name: &str) -> Result<u32> {
+    let global = module.globals.get(global);
+    match global.kind {
+        GlobalKind::Local(InitExpr::Value(Value::I32(value))) => Ok(value as u32),
+        _ => bail!("`{}` must be initialized with an `i32` constant", name),
+    }
+}
+
+fn set_initial_value(module: &mut Module, global: GlobalId, value: u32) {
+    module.globals.get_mut(global).kind =
+        GlobalKind::Local(InitExpr::Value(Value::I32(value as i32)));
+}
+
+fn checked_add(a: u32, b: u32) -> Result<u32> {
+    match a.checked_add(b) {
+        Some(sum) => Ok(sum),
+        None => bail!("the guard regions don't fit in a 32-bit memory"),
+    }
+}
+
+/// Where the last active data segment in `memory` ends.
+fn data_end(module: &Module, memory: MemoryId) -> Result<u32> {
+    let mut end = 0;
+    for data in module.memories.get(memory).data_segments.iter() {
+        let data = module.data.get(*data);
+        let address = match &data.kind {
+            DataKind::Active(ActiveData {
+                location: ActiveDataLocation::Absolute(address),
+                ..
+            }) => *address,
+            _ => bail!("data segments must be at constant addresses"),
+        };
+        end = end.max(checked_add(address, data.value.len() as u32)?);
+    }
+    Ok(end)
+}
+
+/// `guard_check()`: trap if either guard region doesn't hold the canary.
+fn check(module: &mut Module, memory: MemoryId, lower: u32, upper: u32, guard: u32) -> FunctionId {
+    let i = module.locals.add(ValType::I32);
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.name("guard_check".to_string());
+    builder
+        .func_body()
+        .i32_const(0)
+        .local_set(i)
+        .loop_(None, |body| {
+            let looping = body.id();
+            for start in [lower, upper].iter() {
+                let arg = MemArg {
+                    align: 1,
+                    offset: *start,
+                    encoding: None,
+                };
+                body.local_get(i)
+                    .load(memory, LoadKind::I64 { atomic: false }, arg)
+                    .i64_const(CANARY as i64)
+                    .binop(BinaryOp::I64Ne)
+                    .if_else(
+                        None,
+                        |then| {
+                            then.unreachable();
+                        },
+                        |_| {},
+                    );
+            }
+            body.local_get(i)
+                .i32_const(8)
+                .binop(BinaryOp::I32Add)
+                .local_tee(i)
+                .i32_const(guard as i32)
+                .binop(BinaryOp::I32LtU)
+                .br_if(looping);
+        });
+    builder.finish(vec![], &mut module.funcs)
+}
+
+/// `guard_tick()`: call `check` every `interval` calls.
+fn tick(module: &mut Module, check: FunctionId, interval: u32) -> FunctionId {
+    let interval = interval as i32;
+    let countdown =
+        module
+            .globals
+            .add_local(ValType::I32, true, InitExpr::Value(Value::I32(interval)));
+    let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.name("guard_tick".to_string());
+    builder
+        .func_body()
+        .global_get(countdown)
+        .i32_const(1)
+        .binop(BinaryOp::I32Sub)
+        .global_set(countdown)
+        .global_get(countdown)
+        .unop(UnaryOp::I32Eqz)
+        .if_else(
+            None,
+            |then| {
+                then.i32_const(interval).global_set(countdown).call(check);
+            },
+            |_| {},
+        );
+    builder.finish(vec![], &mut module.funcs)
+}
@@ -0,0 +1,175 @@
+//! Hoist loop-invariant constants and globals out of loops.
+//!
+//! Instrumentation, such as gas metering, often injects the same constants and
+//! reads of immutable globals into every iteration of a hot loop. This pass
+//! computes each such loop-invariant expression once, into a fresh local,
+//! before the outermost loop containing it, and replaces it inside the loop
+//! with a `local.get` of that local.
+//!
+//! Loop-invariant expressions are reads of immutable globals, and trees of
+//! non-trapping unary and binary operators over constants and immutable
+//! globals. Since these can neither trap nor have side effects, hoisting them
+//! is sound even for loops that branch away before reaching them.
+
+use crate::ir::*;
+use crate::passes::cse::{binop_result, unop_result, Expr, Exprs};
+use crate::{LocalFunction, Module, ModuleGlobals, ModuleLocals};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Hoist loop-invariant expressions out of the loops of every local function
+/// in `module`.
+pub fn run(module: &mut Module) {
+    for (_, func) in module.funcs.iter_local_mut() {
+        hoist_function(func, &mut module.locals, &module.globals);
+    }
+}
+
+fn hoist_function(func: &mut LocalFunction, locals: &mut ModuleLocals, globals: &ModuleGlobals) {
+    // Instruction sequences that are not inside of any loop.
+    let mut outside = vec![func.entry_block()];
+    while let Some(seq) = outside.pop() {
+        let mut i = 0;
+        while i < func.block(seq).instrs.len() {
+            match &func.block(seq).instrs[i].0 {
+                Instr::Block(b) => outside.push(b.seq),
+                Instr::IfElse(e) => {
+                    outside.push(e.consequent);
+                    outside.push(e.alternative);
+                }
+                Instr::Loop(l) => {
+                    let hoisted = hoist_loop(func, l.seq, locals, globals);
+                    let n = hoisted.len();
+                    let instrs = &mut func.block_mut(seq).instrs;
+                    instrs.splice(i..i, hoisted);
+                    i += n;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+}
+
+/// Replace the loop-invariant expressions in the loop with body `body`, and
+/// return the instructions computing them, to be placed before the loop.
+fn hoist_loop(
+    func: &mut LocalFunction,
+    body: InstrSeqId,
+    locals: &mut ModuleLocals,
+    globals: &ModuleGlobals,
+) -> Vec<(Instr, InstrLocId)> {
+    let mut exprs = Exprs::default();
+    let mut hoisted_locals = HashMap::new();
+    let mut hoisted = Vec::new();
+
+    let mut seqs = vec![body];
+    while let Some(seq) = seqs.pop() {
+        let instrs = &mut func.block_mut(seq).instrs;
+        for (instr, _) in instrs.iter() {
+            match instr {
+                Instr::Block(Block { seq }) | Instr::Loop(Loop { seq }) => seqs.push(*seq),
+                Instr::IfElse(e) => {
+                    seqs.push(e.consequent);
+                    seqs.push(e.alternative);
+                }
+                _ => {}
+            }
+        }
+
+        // Replace the invariant expressions from last to first, so that the
+        // ranges of earlier ones stay valid.
+        for (range, expr) in invariants(instrs, &mut exprs, globals).into_iter().rev() {
+            let local = *hoisted_locals.entry(expr).or_insert_with(|| {
+                let local = locals.add(exprs.ty(expr));
+                hoisted.extend(instrs[range.clone()].iter().cloned());
+                let loc = instrs[range.end - 1].1;
+                hoisted.push((LocalSet { local }.into(), loc));
+                local
+            });
+            let loc = instrs[range.start].1;
+            instrs.splice(range, Some((LocalGet { local }.into(), loc)));
+        }
+    }
+    hoisted
+}
+
+/// Find the maximal loop-invariant expressions in `instrs` worth hoisting, in
+/// order.
+fn invariants(
+    instrs: &[(Instr, InstrLocId)],
+    exprs: &mut Exprs,
+    globals: &ModuleGlobals,
+) -> Vec<(Range<usize>, usize)> {
+    // The invariant operands on the stack, or `None` for other values.
+    let mut stack: Vec<Option<(usize, usize)>> = Vec::new();
+    let mut found: Vec<(Range<usize>, usize)> = Vec::new();
+
+    for (i, (instr, _)) in instrs.iter().enumerate() {
+        let operand = match instr {
+            // A constant on its own is as cheap as a `local.get`, so it is
+            // only worth hoisting as part of a larger expression.
+            Instr::Const(c) => {
+                stack.push(Some((i, exprs.constant(c.value))));
+                continue;
+            }
+            Instr::GlobalGet(g) => {
+                let global = globals.get(g.global);
+                if global.mutable {
+                    None
+                } else {
+                    Some((i, exprs.intern(Expr::Global(g.global), global.ty)))
+                }
+            }
+            Instr::Unop(u) => match (stack.pop().flatten(), unop_result(u.op)) {
+                (Some((start, a)), Some(ty)) => {
+                    Some((start, exprs.intern(Expr::Unop(u.op, a), ty)))
+                }
+                _ => None,
+            },
+            Instr::Binop(b) => {
+                let rhs = stack.pop().flatten();
+                let lhs = stack.pop().flatten();
+                match (lhs, rhs, binop_result(b.op)) {
+                    (Some((start, a)), Some((_, b2)), Some(ty)) => {
+                        Some((start, exprs.intern(Expr::Binop(b.op, a, b2), ty)))
+                    }
+                    _ => None,
+                }
+            }
+            Instr::LocalGet(_) => None,
+            // An expression must be a contiguous range of instructions, so
+            // none of the operands below a `local.set`, `local.tee` or `drop`
+            // may be combined with the ones after it, or hoisting the result
+            // would hoist the statement too.
+            Instr::LocalSet(_) | Instr::Drop(_) => {
+                stack.clear();
+                continue;
+            }
+            Instr::LocalTee(_) => {
+                stack.clear();
+                None
+            }
+            _ => {
+                // We don't know how many operands other instructions consume,
+                // so forget about all of them.
+                stack.clear();
+                continue;
+            }
+        };
+
+        if let Some((start, expr)) = operand {
+            // Drop the subexpressions of this expression, which are no longer
+            // maximal.
+            while let Some((range, _)) = found.last() {
+                if range.start < start {
+                    break;
+                }
+                found.pop();
+            }
+            found.push((start..i + 1, expr));
+        }
+        stack.push(operand);
+    }
+    found
+}
@@ -0,0 +1,168 @@
+//! Find which locals are live at each instruction of a function.
+//!
+//! A local is live at a point in a function if its value there may be read
+//! later on, before it is written again. Passes that reuse locals, or that
+//! save locals around calls, only need to care about the live ones.
+//!
+//! Wasm's control flow is structured, so this is a backwards dataflow
+//! analysis over the instruction sequences: the locals live at the end of a
+//! `block` or `if` are the ones live after it, branches to a `block` or `if`
+//! join in the locals live after it, and branches to a `loop` join in the
+//! locals live at the start of its body, which is found by iterating until
+//! nothing changes.
+
+use crate::ir::*;
+use crate::LocalFunction;
+use std::collections::{HashMap, HashSet};
+
+/// The live locals at each instruction of a function, found with `run`.
+#[derive(Debug, Clone, Default)]
+pub struct Liveness {
+    // The locals live before each instruction of each sequence.
+    before: HashMap<InstrSeqId, Vec<HashSet<LocalId>>>,
+    // The locals live at the end of each sequence.
+    end: HashMap<InstrSeqId, HashSet<LocalId>>,
+    // The locals live at the start of the function.
+    entry: HashSet<LocalId>,
+}
+
+impl Liveness {
+    /// Get the locals live just before the instruction at `index` in `seq`.
+    ///
+    /// Panics if there is no such instruction.
+    pub fn live_before(&self, seq: InstrSeqId, index: usize) -> &HashSet<LocalId> {
+        &self.before[&seq][index]
+    }
+
+    /// Get the locals live just after the instruction at `index` in `seq`.
+    ///
+    /// After the last instruction of a sequence this is the locals live at
+    /// the end of the sequence. Panics if there is no such instruction.
+    pub fn live_after(&self, seq: InstrSeqId, index: usize) -> &HashSet<LocalId> {
+        let before = &self.before[&seq];
+        assert!(index < before.len(), "no instruction at {}", index);
+        match before.get(index + 1) {
+            Some(live) => live,
+            None => &self.end[&seq],
+        }
+    }
+
+    /// Get the locals live at the start of the function.
+    ///
+    /// These are the arguments that the function reads, and the other locals
+    /// that it reads before writing them, which means reading their default
+    /// value.
+    pub fn live_at_entry(&self) -> &HashSet<LocalId> {
+        &self.entry
+    }
+}
+
+/// Find the live locals at each instruction of `func`.
+pub fn run(func: &LocalFunction) -> Liveness {
+    let mut analysis = Analysis {
+        func,
+        liveness: Liveness::default(),
+        targets: HashMap::new(),
+        loop_heads: HashMap::new(),
+        changed: true,
+    };
+    let mut entry = HashSet::new();
+    while analysis.changed {
+        analysis.changed = false;
+        entry = analysis.seq(func.entry_block(), Target::Block, HashSet::new());
+    }
+    analysis.liveness.entry = entry;
+    analysis.liveness
+}
+
+/// What branches to a sequence jump to.
+#[derive(Copy, Clone)]
+enum Target {
+    /// The end of the sequence, as for `block` and `if`.
+    Block,
+    /// The start of the sequence, as for `loop`.
+    Loop,
+}
+
+struct Analysis<'a> {
+    func: &'a LocalFunction,
+    liveness: Liveness,
+    // The locals live at the targets of branches to the sequences that are
+    // being analyzed.
+    targets: HashMap<InstrSeqId, HashSet<LocalId>>,
+    // The current guess at the locals live at the start of each loop body.
+    loop_heads: HashMap<InstrSeqId, HashSet<LocalId>>,
+    // Whether any guess changed during this iteration.
+    changed: bool,
+}
+
+impl Analysis<'_> {
+    /// Analyze `seq`, given the locals live at its end, and return the locals
+    /// live at its start.
+    fn seq(&mut self, seq: InstrSeqId, target: Target, end: HashSet<LocalId>) -> HashSet<LocalId> {
+        let branch_target = match target {
+            Target::Block => end.clone(),
+            Target::Loop => self.loop_heads.get(&seq).cloned().unwrap_or_default(),
+        };
+        self.targets.insert(seq, branch_target);
+
+        let func = self.func;
+        let instrs = &func.block(seq).instrs;
+        let mut before = vec![HashSet::new(); instrs.len()];
+        let mut live = end.clone();
+        for (index, (instr, _)) in instrs.iter().enumerate().rev() {
+            live = self.instr(instr, live);
+            before[index] = live.clone();
+        }
+
+        if let Target::Loop = target {
+            if self.loop_heads.get(&seq) != Some(&live) {
+                self.loop_heads.insert(seq, live.clone());
+                self.changed = true;
+            }
+        }
+        self.targets.remove(&seq);
+        self.liveness.before.insert(seq, before);
+        self.liveness.end.insert(seq, end);
+        live
+    }
+
+    /// Get the locals live before `instr`, given the ones live after it.
+    fn instr(&mut self, instr: &Instr, mut live: HashSet<LocalId>) -> HashSet<LocalId> {
+        match instr {
+            Instr::LocalGet(LocalGet { local }) => {
+                live.insert(*local);
+            }
+            Instr::LocalSet(LocalSet { local }) | Instr::LocalTee(LocalTee { local }) => {
+                live.remove(local);
+            }
+            Instr::Return(_) | Instr::Unreachable(_) => live.clear(),
+            Instr::Br(Br { block }) => live = self.targets[block].clone(),
+            Instr::BrIf(BrIf { block }) => live.extend(self.targets[block].iter().copied()),
+            Instr::BrTable(BrTable { blocks, default }) => {
+                live.clear();
+                for block in blocks.iter().chain(Some(default)) {
+                    live.extend(self.targets[block].iter().copied());
+                }
+            }
+            #[cfg(feature = "unstable")]
+            Instr::Resume(Resume { handlers, .. }) => {
+                for handler in handlers.iter() {
+                    live.extend(self.targets[&handler.block].iter().copied());
+                }
+            }
+            Instr::Block(Block { seq }) => live = self.seq(*seq, Target::Block, live),
+            Instr::Loop(Loop { seq }) => live = self.seq(*seq, Target::Loop, live),
+            Instr::IfElse(IfElse {
+                consequent,
+                alternative,
+            }) => {
+                let mut consequent = self.seq(*consequent, Target::Block, live.clone());
+                consequent.extend(self.seq(*alternative, Target::Block, live));
+                live = consequent;
+            }
+            _ => {}
+        }
+        live
+    }
+}
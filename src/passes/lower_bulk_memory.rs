@@ -0,0 +1,430 @@
+//! Lower the memory instructions of the bulk memory proposal to MVP code.
+//!
+//! This pass targets engines that predate the bulk memory proposal. Every
+//! `memory.copy`, `memory.fill`, `memory.init` and `data.drop` is replaced
+//! with a call to a generated function that does the same thing with byte by
+//! byte loops, and checks bounds up front so that it traps in the same cases,
+//! before writing anything.
+//!
+//! Passive data segments can't be expressed in the MVP, so they are turned
+//! into active segments placed right after the initial size of the memory
+//! they are copied into, which grows to make room for them. The `memory.init`
+//! replacement copies from there, and a mutable global per segment tracks how
+//! many of its bytes are left, which `data.drop` sets to zero. Note that this
+//! memory is not reserved in any way: code that assumes that all memory past
+//! its static data is free for the taking, such as allocators that are handed
+//! everything above a `__heap_base`, may clobber the placed segments.
+//!
+//! The table instructions of the proposal are left alone.
+
+use crate::ir::*;
+use crate::{ActiveData, ActiveDataLocation, DataId, DataKind, FunctionBuilder, FunctionId};
+use crate::{GlobalId, InitExpr, InstrSeqBuilder, MemoryId, Module, Result, ValType};
+use anyhow::bail;
+use std::collections::{HashMap, HashSet};
+
+const PAGE_SIZE: u32 = 1 << 16;
+const MAX_PAGES: u32 = 1 << 16;
+
+/// Replace all bulk memory instructions in `module` with calls to equivalent
+/// MVP functions, and make all passive data segments active.
+pub fn run(module: &mut Module) -> Result<()> {
+    let keys = bulk_memory_instrs(module)?;
+    let inits = memory_inits(module);
+
+    // Place each passive segment in the memory it is copied into, right after
+    // its initial pages.
+    let mut next = HashMap::new();
+    let mut segments = HashMap::new();
+    let passive = module
+        .data
+        .iter()
+        .filter(|d| d.is_passive())
+        .map(|d| (d.id(), d.value.len() as u64))
+        .collect::<Vec<_>>();
+    for (data, len) in passive {
+        let memory = match inits.get(&data).map(|m| m.iter().collect::<Vec<_>>()) {
+            Some(ref memories) if memories.len() > 1 => {
+                bail!("passive data segment is copied into more than one memory")
+            }
+            Some(memories) => *memories[0],
+            None => match module.memories.iter().next() {
+                Some(memory) => memory.id(),
+                None => bail!("passive data segment without a memory to place it in"),
+            },
+        };
+        let initial = module.memories.get(memory).initial;
+        let address = next
+            .entry(memory)
+            .or_insert_with(|| u64::from(initial) * u64::from(PAGE_SIZE));
+        segments.insert(data, (memory, *address));
+        *address += len;
+    }
+    let mut grown = Vec::new();
+    for (&memory, &end) in next.iter() {
+        let page = u64::from(PAGE_SIZE);
+        let pages = end / page + u64::from(end % page != 0);
+        let maximum = module.memories.get(memory).maximum.unwrap_or(MAX_PAGES);
+        if pages > u64::from(maximum) {
+            bail!("memory is too small to make room for its passive data segments");
+        }
+        grown.push((memory, pages as u32));
+    }
+    for (memory, pages) in grown {
+        module.memories.get_mut(memory).initial = pages;
+    }
+    for (&data, &(memory, address)) in segments.iter() {
+        module.data.get_mut(data).kind = DataKind::Active(ActiveData {
+            memory,
+            location: ActiveDataLocation::Absolute(address as u32),
+        });
+        module.memories.get_mut(memory).data_segments.insert(data);
+    }
+
+    let mut lower = Lower {
+        segments,
+        copies: HashMap::new(),
+        fills: HashMap::new(),
+        inits: HashMap::new(),
+        drops: HashMap::new(),
+        lengths: HashMap::new(),
+    };
+    let mut replacements = HashMap::new();
+    for key in keys {
+        let func = match key {
+            Key::Copy(memory) => lower.copy(module, memory),
+            Key::Fill(memory) => lower.fill(module, memory),
+            Key::Init(memory, data) => lower.init(module, memory, data),
+            Key::Drop(data) => lower.drop(module, data),
+        };
+        replacements.insert(key, func);
+    }
+
+    for (_, func) in module.funcs.iter_local_mut() {
+        let entry = func.entry_block();
+        dfs_pre_order_mut(&mut Replace(&replacements), func, entry);
+    }
+    Ok(())
+}
+
+/// Find out which memories each data segment is copied into.
+fn memory_inits(module: &Module) -> HashMap<DataId, HashSet<MemoryId>> {
+    #[derive(Default)]
+    struct Inits(HashMap<DataId, HashSet<MemoryId>>);
+
+    impl<'instr> Visitor<'instr> for Inits {
+        fn visit_memory_init(&mut self, e: &MemoryInit) {
+            self.0.entry(e.data).or_default().insert(e.memory);
+        }
+    }
+
+    let mut inits = Inits::default();
+    for (_, func) in module.funcs.iter_local() {
+        dfs_in_order(&mut inits, func, func.entry_block());
+    }
+    inits.0
+}
+
+/// All the distinct memory instructions of the bulk memory proposal in
+/// `module`, in the order they first appear.
+fn bulk_memory_instrs(module: &Module) -> Result<Vec<Key>> {
+    #[derive(Default)]
+    struct Collect {
+        keys: Vec<Key>,
+        seen: HashSet<Key>,
+        copies_between_memories: bool,
+    }
+
+    impl<'instr> Visitor<'instr> for Collect {
+        fn visit_instr(&mut self, instr: &'instr Instr, _: &'instr InstrLocId) {
+            if let Instr::MemoryCopy(e) = instr {
+                self.copies_between_memories |= e.src != e.dst;
+            }
+            if let Some(key) = Key::of(instr) {
+                if self.seen.insert(key) {
+                    self.keys.push(key);
+                }
+            }
+        }
+    }
+
+    let mut collect = Collect::default();
+    for (_, func) in module.funcs.iter_local() {
+        dfs_in_order(&mut collect, func, func.entry_block());
+    }
+    if collect.copies_between_memories {
+        bail!("cannot lower `memory.copy` between different memories");
+    }
+    Ok(collect.keys)
+}
+
+/// Identifies a bulk memory instruction along with its immediates.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Copy(MemoryId),
+    Fill(MemoryId),
+    Init(MemoryId, DataId),
+    Drop(DataId),
+}
+
+impl Key {
+    fn of(instr: &Instr) -> Option<Key> {
+        Some(match instr {
+            Instr::MemoryCopy(e) => Key::Copy(e.src),
+            Instr::MemoryFill(e) => Key::Fill(e.memory),
+            Instr::MemoryInit(e) => Key::Init(e.memory, e.data),
+            Instr::DataDrop(e) => Key::Drop(e.data),
+            _ => return None,
+        })
+    }
+}
+
+/// Replaces bulk memory instructions with calls to their replacements.
+struct Replace<'a>(&'a HashMap<Key, FunctionId>);
+
+impl VisitorMut for Replace<'_> {
+    fn visit_instr_mut(&mut self, instr: &mut Instr, _: &mut InstrLocId) {
+        if let Some(key) = Key::of(instr) {
+            *instr = Call { func: self.0[&key] }.into();
+        }
+    }
+}
+
+/// The generated replacements, created on demand.
+struct Lower {
+    segments: HashMap<DataId, (MemoryId, u64)>,
+    copies: HashMap<MemoryId, FunctionId>,
+    fills: HashMap<MemoryId, FunctionId>,
+    inits: HashMap<(MemoryId, DataId), FunctionId>,
+    drops: HashMap<DataId, FunctionId>,
+    lengths: HashMap<DataId, GlobalId>,
+}
+
+impl Lower {
+    /// `memory.copy`, which copies forwards or backwards, depending on which
+    /// way the source and destination overlap.
+    fn copy(&mut self, module: &mut Module, memory: MemoryId) -> FunctionId {
+        if let Some(func) = self.copies.get(&memory) {
+            return *func;
+        }
+        let dst = module.locals.add(ValType::I32);
+        let src = module.locals.add(ValType::I32);
+        let n = module.locals.add(ValType::I32);
+        let i = module.locals.add(ValType::I32);
+
+        let params = [ValType::I32; 3];
+        let mut builder = FunctionBuilder::new(&mut module.types, &params, &[]);
+        builder.name("memory.copy".to_string());
+        let mut body = builder.func_body();
+        trap_if_past(&mut body, dst, n, |b| memory_end(b, memory));
+        trap_if_past(&mut body, src, n, |b| memory_end(b, memory));
+        body.local_get(dst)
+            .local_get(src)
+            .binop(BinaryOp::I32LeU)
+            .if_else(
+                None,
+                |forward| {
+                    forward.block(None, |done| {
+                        let done_id = done.id();
+                        done.loop_(None, |loop_| {
+                            let loop_id = loop_.id();
+                            loop_
+                                .local_get(i)
+                                .local_get(n)
+                                .binop(BinaryOp::I32GeU)
+                                .br_if(done_id);
+                            copy_byte(loop_, memory, dst, src, i);
+                            loop_
+                                .local_get(i)
+                                .i32_const(1)
+                                .binop(BinaryOp::I32Add)
+                                .local_set(i)
+                                .br(loop_id);
+                        });
+                    });
+                },
+                |backward| {
+                    backward.block(None, |done| {
+                        let done_id = done.id();
+                        done.loop_(None, |loop_| {
+                            let loop_id = loop_.id();
+                            loop_
+                                .local_get(n)
+                                .unop(UnaryOp::I32Eqz)
+                                .br_if(done_id)
+                                .local_get(n)
+                                .i32_const(1)
+                                .binop(BinaryOp::I32Sub)
+                                .local_set(n);
+                            copy_byte(loop_, memory, dst, src, n);
+                            loop_.br(loop_id);
+                        });
+                    });
+                },
+            );
+        let func = builder.finish(vec![dst, src, n], &mut module.funcs);
+        self.copies.insert(memory, func);
+        func
+    }
+
+    /// `memory.fill`.
+    fn fill(&mut self, module: &mut Module, memory: MemoryId) -> FunctionId {
+        if let Some(func) = self.fills.get(&memory) {
+            return *func;
+        }
+        let dst = module.locals.add(ValType::I32);
+        let val = module.locals.add(ValType::I32);
+        let n = module.locals.add(ValType::I32);
+        let i = module.locals.add(ValType::I32);
+
+        let params = [ValType::I32; 3];
+        let mut builder = FunctionBuilder::new(&mut module.types, &params, &[]);
+        builder.name("memory.fill".to_string());
+        let mut body = builder.func_body();
+        trap_if_past(&mut body, dst, n, |b| memory_end(b, memory));
+        body.block(None, |done| {
+            let done_id = done.id();
+            done.loop_(None, |loop_| {
+                let loop_id = loop_.id();
+                loop_
+                    .local_get(i)
+                    .local_get(n)
+                    .binop(BinaryOp::I32GeU)
+                    .br_if(done_id)
+                    .local_get(dst)
+                    .local_get(i)
+                    .binop(BinaryOp::I32Add)
+                    .local_get(val)
+                    .store(memory, StoreKind::I32_8 { atomic: false }, byte())
+                    .local_get(i)
+                    .i32_const(1)
+                    .binop(BinaryOp::I32Add)
+                    .local_set(i)
+                    .br(loop_id);
+            });
+        });
+        let func = builder.finish(vec![dst, val, n], &mut module.funcs);
+        self.fills.insert(memory, func);
+        func
+    }
+
+    /// `memory.init`, copying from where the segment was placed.
+    ///
+    /// Active segments behave as if they are dropped, since they are after
+    /// instantiation.
+    fn init(&mut self, module: &mut Module, memory: MemoryId, data: DataId) -> FunctionId {
+        if let Some(func) = self.inits.get(&(memory, data)) {
+            return *func;
+        }
+        let copy = self.copy(module, memory);
+        let len = self.length(module, data);
+        let base = match self.segments.get(&data) {
+            Some((_, address)) => *address as u32 as i32,
+            None => 0,
+        };
+        let dst = module.locals.add(ValType::I32);
+        let src = module.locals.add(ValType::I32);
+        let n = module.locals.add(ValType::I32);
+
+        let params = [ValType::I32; 3];
+        let mut builder = FunctionBuilder::new(&mut module.types, &params, &[]);
+        builder.name(format!("memory.init:{}", data.index()));
+        let mut body = builder.func_body();
+        trap_if_past(&mut body, src, n, |b| {
+            b.global_get(len).unop(UnaryOp::I64ExtendUI32);
+        });
+        body.local_get(dst)
+            .local_get(src)
+            .i32_const(base)
+            .binop(BinaryOp::I32Add)
+            .local_get(n)
+            .call(copy);
+        let func = builder.finish(vec![dst, src, n], &mut module.funcs);
+        self.inits.insert((memory, data), func);
+        func
+    }
+
+    /// `data.drop`, which empties the segment.
+    fn drop(&mut self, module: &mut Module, data: DataId) -> FunctionId {
+        if let Some(func) = self.drops.get(&data) {
+            return *func;
+        }
+        let len = self.length(module, data);
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+        builder.name(format!("data.drop:{}", data.index()));
+        builder.func_body().i32_const(0).global_set(len);
+        let func = builder.finish(vec![], &mut module.funcs);
+        self.drops.insert(data, func);
+        func
+    }
+
+    /// The global holding the number of bytes left in a segment.
+    fn length(&mut self, module: &mut Module, data: DataId) -> GlobalId {
+        if let Some(global) = self.lengths.get(&data) {
+            return *global;
+        }
+        let len = match self.segments.get(&data) {
+            Some(_) => module.data.get(data).value.len() as i32,
+            None => 0,
+        };
+        let global = module
+            .globals
+            .add_local(ValType::I32, true, InitExpr::Value(Value::I32(len)));
+        self.lengths.insert(data, global);
+        global
+    }
+}
+
+fn byte() -> MemArg {
+    MemArg {
+        align: 1,
+        offset: 0,
+        encoding: None,
+    }
+}
+
+/// Copy the byte at `src + i` to `dst + i`.
+fn copy_byte(body: &mut InstrSeqBuilder, memory: MemoryId, dst: LocalId, src: LocalId, i: LocalId) {
+    let load = LoadKind::I32_8 {
+        kind: ExtendedLoad::ZeroExtend,
+    };
+    body.local_get(dst)
+        .local_get(i)
+        .binop(BinaryOp::I32Add)
+        .local_get(src)
+        .local_get(i)
+        .binop(BinaryOp::I32Add)
+        .load(memory, load, byte())
+        .store(memory, StoreKind::I32_8 { atomic: false }, byte());
+}
+
+/// Push the size of `memory` in bytes, as an `i64`.
+fn memory_end(body: &mut InstrSeqBuilder, memory: MemoryId) {
+    body.memory_size(memory)
+        .unop(UnaryOp::I64ExtendUI32)
+        .i64_const(16)
+        .binop(BinaryOp::I64Shl);
+}
+
+/// Trap unless the range of `len` bytes from `start` ends at or before the
+/// `i64` pushed by `end`.
+fn trap_if_past(
+    body: &mut InstrSeqBuilder,
+    start: LocalId,
+    len: LocalId,
+    end: impl FnOnce(&mut InstrSeqBuilder),
+) {
+    body.local_get(start)
+        .unop(UnaryOp::I64ExtendUI32)
+        .local_get(len)
+        .unop(UnaryOp::I64ExtendUI32)
+        .binop(BinaryOp::I64Add);
+    end(body);
+    body.binop(BinaryOp::I64GtU).if_else(
+        None,
+        |then| {
+            then.unreachable();
+        },
+        |_| {},
+    );
+}
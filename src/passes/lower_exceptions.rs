@@ -0,0 +1,277 @@
+//! Lower exception handling to calls to an imported abort.
+//!
+//! Modules built with exceptions enabled, such as C++ or Rust with
+//! `panic=unwind`, fail to instantiate on engines without the exception
+//! handling proposal, even when they never throw in practice. This pass makes
+//! them run there by giving up on catching exceptions: every `throw`,
+//! `rethrow` and `throw_ref` becomes a call to an imported function that
+//! aborts, and every `try` and `try_table` becomes a `block` of its body,
+//! with its handlers removed. Tags, and their imports and exports, are
+//! removed as well.
+//!
+//! walrus can't parse modules that use exceptions, so unlike other passes
+//! this one works on binaries, and should run before parsing them with
+//! `Module::from_buffer`. Function indices are shifted by the new import,
+//! including in the `name` section, but other custom sections that refer to
+//! functions or code offsets, such as DWARF, aren't fixed up. `exnref`
+//! locals, parameters and globals are left as they are, since there is
+//! nothing to replace them with, so modules that have them still need the
+//! proposal.
+
+use crate::Result;
+use std::convert::Infallible;
+use wasm_encoder::reencode::{utils, Error, Reencode};
+use wasm_encoder::{
+    CodeSection, EntityType, ExportSection, ImportSection, Instruction, RawSection, SectionId,
+    TypeSection,
+};
+use wasmparser_next::{
+    Export, ExternalKind, FunctionBody, ImportSectionReader, Operator, Parser, Payload, TypeRef,
+    TypeSectionReader,
+};
+
+/// The function that thrown exceptions abort with.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The module that the abort function is imported from.
+    pub abort_module: String,
+
+    /// The name that the abort function is imported under. It takes no
+    /// parameters and returns nothing, and isn't expected to return at all,
+    /// although if it does, the throw traps instead.
+    pub abort_name: String,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            abort_module: "env".to_string(),
+            abort_name: "abort".to_string(),
+        }
+    }
+}
+
+/// Lower the exception handling in the binary module `wasm` as described by
+/// `options`, returning the lowered binary.
+///
+/// The abort function is only imported if the module throws at all. Fails if
+/// `wasm` can't be parsed.
+pub fn run(wasm: &[u8], options: &Options) -> Result<Vec<u8>> {
+    let mut lower = Lower {
+        options,
+        func_imports: 0,
+        types: 0,
+        throws: false,
+        imported: false,
+    };
+    for payload in Parser::new(0).parse_all(wasm) {
+        match payload? {
+            Payload::TypeSection(section) => {
+                for group in section {
+                    lower.types += group?.types().len() as u32;
+                }
+            }
+            Payload::ImportSection(section) => {
+                for import in section.into_imports() {
+                    if let TypeRef::Func(_) = import?.ty {
+                        lower.func_imports += 1;
+                    }
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                let mut reader = body.get_operators_reader()?;
+                while !reader.eof() {
+                    match reader.read()? {
+                        Operator::Throw { .. } | Operator::Rethrow { .. } | Operator::ThrowRef => {
+                            lower.throws = true;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut module = wasm_encoder::Module::new();
+    lower.parse_core_module(&mut module, Parser::new(0), wasm)?;
+    Ok(module.finish())
+}
+
+struct Lower<'a> {
+    options: &'a Options,
+    // The number of imported functions, which is the index of the abort
+    // function once it is imported.
+    func_imports: u32,
+    // The number of types, which is the index of the abort function's type
+    // once it is added.
+    types: u32,
+    // Whether anything throws, and so the abort function is needed.
+    throws: bool,
+    // Whether the abort function was imported already.
+    imported: bool,
+}
+
+impl Lower<'_> {
+    fn import_abort(&mut self, imports: &mut ImportSection) {
+        imports.import(
+            &self.options.abort_module,
+            &self.options.abort_name,
+            EntityType::Function(self.types),
+        );
+        self.imported = true;
+    }
+}
+
+impl Reencode for Lower<'_> {
+    type Error = Infallible;
+
+    fn function_index(&mut self, func: u32) -> Result<u32, Error> {
+        Ok(if self.throws && func >= self.func_imports {
+            func + 1
+        } else {
+            func
+        })
+    }
+
+    fn parse_type_section(
+        &mut self,
+        types: &mut TypeSection,
+        section: TypeSectionReader<'_>,
+    ) -> Result<(), Error> {
+        utils::parse_type_section(self, types, section)?;
+        if self.throws {
+            types.ty().function([], []);
+        }
+        Ok(())
+    }
+
+    fn parse_import_section(
+        &mut self,
+        imports: &mut ImportSection,
+        section: ImportSectionReader<'_>,
+    ) -> Result<(), Error> {
+        for import in section.into_imports() {
+            let import = import?;
+            if let TypeRef::Tag(_) = import.ty {
+                continue;
+            }
+            self.parse_import(imports, import)?;
+        }
+        if self.throws {
+            self.import_abort(imports);
+        }
+        Ok(())
+    }
+
+    fn parse_export(
+        &mut self,
+        exports: &mut ExportSection,
+        export: Export<'_>,
+    ) -> Result<(), Error> {
+        if export.kind == ExternalKind::Tag {
+            return Ok(());
+        }
+        utils::parse_export(self, exports, export)
+    }
+
+    fn intersperse_section_hook(
+        &mut self,
+        module: &mut wasm_encoder::Module,
+        _after: Option<SectionId>,
+        before: Option<SectionId>,
+    ) -> Result<(), Error> {
+        // The import section comes right after the type section, so if the
+        // next section is neither, the module has no imports to add the abort
+        // function to.
+        match before {
+            Some(SectionId::Type) | Some(SectionId::Import) => {}
+            _ if self.throws && !self.imported => {
+                let mut imports = ImportSection::new();
+                self.import_abort(&mut imports);
+                module.section(&imports);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn parse_core_module(
+        &mut self,
+        module: &mut wasm_encoder::Module,
+        parser: Parser,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        // Reencoding emits every section that it parses, even one that nothing
+        // is added to, so the tag section is left out of what gets parsed.
+        let offset = parser.offset();
+        let mut without_tags = wasm_encoder::Module::new();
+        for payload in parser.parse_all(data) {
+            let payload = payload?;
+            if let Payload::TagSection(_) = payload {
+                continue;
+            }
+            if let Some((id, range)) = payload.as_section() {
+                let range = (range.start - offset) as usize..(range.end - offset) as usize;
+                without_tags.section(&RawSection {
+                    id,
+                    data: &data[range],
+                });
+            }
+        }
+        utils::parse_core_module(self, module, Parser::new(0), &without_tags.finish())
+    }
+
+    fn parse_function_body(
+        &mut self,
+        code: &mut CodeSection,
+        body: FunctionBody<'_>,
+    ) -> Result<(), Error> {
+        let abort = self.func_imports;
+        let mut func = self.new_function_with_parsed_locals(&body)?;
+        // While removing the handlers of a `try`, the depth of the blocks
+        // within the handler being removed.
+        let mut handler = None;
+        let mut reader = body.get_operators_reader()?;
+        while !reader.eof() {
+            let op = reader.read()?;
+            if let Some(depth) = handler {
+                handler = match op {
+                    Operator::Block { .. }
+                    | Operator::Loop { .. }
+                    | Operator::If { .. }
+                    | Operator::Try { .. }
+                    | Operator::TryTable { .. } => Some(depth + 1),
+                    Operator::End if depth == 0 => {
+                        func.instruction(&Instruction::End);
+                        None
+                    }
+                    Operator::End | Operator::Delegate { .. } => Some(depth - 1),
+                    _ => Some(depth),
+                };
+                continue;
+            }
+            match op {
+                Operator::Try { blockty } => {
+                    func.instruction(&Instruction::Block(self.block_type(blockty)?));
+                }
+                Operator::TryTable { try_table } => {
+                    func.instruction(&Instruction::Block(self.block_type(try_table.ty)?));
+                }
+                Operator::Catch { .. } | Operator::CatchAll => handler = Some(0),
+                Operator::Delegate { .. } => {
+                    func.instruction(&Instruction::End);
+                }
+                Operator::Throw { .. } | Operator::Rethrow { .. } | Operator::ThrowRef => {
+                    func.instruction(&Instruction::Call(abort));
+                    func.instruction(&Instruction::Unreachable);
+                }
+                op => {
+                    func.instruction(&self.instruction(op)?);
+                }
+            }
+        }
+        code.function(&func);
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+//! Lower float instructions to calls to softfloat implementations.
+//!
+//! Some targets forbid hardware floats, for example because their engines
+//! must be deterministic down to the last bit, or because they run on
+//! hardware without an FPU. This pass replaces every `f32` and `f64`
+//! instruction that computes something, that is arithmetic, comparisons,
+//! rounding, and conversions to, from and between floats, with a call to a
+//! function that does the same in software.
+//!
+//! Each instruction is implemented by a function with the same signature,
+//! identified by the instruction's name in the text format, such as `f32.add`
+//! for a function taking two `f32`s and returning an `f32`, or
+//! `i32.trunc_f64_s` for one taking an `f64` and returning an `i32`. These
+//! functions are either functions of the module already, such as ones linked
+//! in from a softfloat library, or otherwise imported. Implementations of
+//! trapping instructions like `i32.trunc_f64_s` should trap like they do.
+//!
+//! Float values themselves are kept, so floats can still be loaded, stored,
+//! passed around and reinterpreted as integers, which is how softfloat
+//! implementations get at their bits. SIMD instructions are left alone.
+
+use crate::ir::*;
+use crate::{FunctionId, ImportKind, Module, Result, ValType};
+use anyhow::bail;
+use std::collections::HashMap;
+
+/// The float unary operators that are lowered, with their names, operand
+/// types and result types.
+const UNARY: &[(UnaryOp, &str, ValType, ValType)] = {
+    use crate::ir::UnaryOp::*;
+    use crate::ValType::*;
+    &[
+        (F32Abs, "f32.abs", F32, F32),
+        (F32Neg, "f32.neg", F32, F32),
+        (F32Ceil, "f32.ceil", F32, F32),
+        (F32Floor, "f32.floor", F32, F32),
+        (F32Trunc, "f32.trunc", F32, F32),
+        (F32Nearest, "f32.nearest", F32, F32),
+        (F32Sqrt, "f32.sqrt", F32, F32),
+        (F64Abs, "f64.abs", F64, F64),
+        (F64Neg, "f64.neg", F64, F64),
+        (F64Ceil, "f64.ceil", F64, F64),
+        (F64Floor, "f64.floor", F64, F64),
+        (F64Trunc, "f64.trunc", F64, F64),
+        (F64Nearest, "f64.nearest", F64, F64),
+        (F64Sqrt, "f64.sqrt", F64, F64),
+        (I32TruncSF32, "i32.trunc_f32_s", F32, I32),
+        (I32TruncUF32, "i32.trunc_f32_u", F32, I32),
+        (I32TruncSF64, "i32.trunc_f64_s", F64, I32),
+        (I32TruncUF64, "i32.trunc_f64_u", F64, I32),
+        (I64TruncSF32, "i64.trunc_f32_s", F32, I64),
+        (I64TruncUF32, "i64.trunc_f32_u", F32, I64),
+        (I64TruncSF64, "i64.trunc_f64_s", F64, I64),
+        (I64TruncUF64, "i64.trunc_f64_u", F64, I64),
+        (I32TruncSSatF32, "i32.trunc_sat_f32_s", F32, I32),
+        (I32TruncUSatF32, "i32.trunc_sat_f32_u", F32, I32),
+        (I32TruncSSatF64, "i32.trunc_sat_f64_s", F64, I32),
+        (I32TruncUSatF64, "i32.trunc_sat_f64_u", F64, I32),
+        (I64TruncSSatF32, "i64.trunc_sat_f32_s", F32, I64),
+        (I64TruncUSatF32, "i64.trunc_sat_f32_u", F32, I64),
+        (I64TruncSSatF64, "i64.trunc_sat_f64_s", F64, I64),
+        (I64TruncUSatF64, "i64.trunc_sat_f64_u", F64, I64),
+        (F32ConvertSI32, "f32.convert_i32_s", I32, F32),
+        (F32ConvertUI32, "f32.convert_i32_u", I32, F32),
+        (F32ConvertSI64, "f32.convert_i64_s", I64, F32),
+        (F32ConvertUI64, "f32.convert_i64_u", I64, F32),
+        (F64ConvertSI32, "f64.convert_i32_s", I32, F64),
+        (F64ConvertUI32, "f64.convert_i32_u", I32, F64),
+        (F64ConvertSI64, "f64.convert_i64_s", I64, F64),
+        (F64ConvertUI64, "f64.convert_i64_u", I64, F64),
+        (F32DemoteF64, "f32.demote_f64", F64, F32),
+        (F64PromoteF32, "f64.promote_f32", F32, F64),
+    ]
+};
+
+/// The float binary operators that are lowered, with their names, operand
+/// types and result types.
+const BINARY: &[(BinaryOp, &str, ValType, ValType)] = {
+    use crate::ir::BinaryOp::*;
+    use crate::ValType::*;
+    &[
+        (F32Add, "f32.add", F32, F32),
+        (F32Sub, "f32.sub", F32, F32),
+        (F32Mul, "f32.mul", F32, F32),
+        (F32Div, "f32.div", F32, F32),
+        (F32Min, "f32.min", F32, F32),
+        (F32Max, "f32.max", F32, F32),
+        (F32Copysign, "f32.copysign", F32, F32),
+        (F32Eq, "f32.eq", F32, I32),
+        (F32Ne, "f32.ne", F32, I32),
+        (F32Lt, "f32.lt", F32, I32),
+        (F32Gt, "f32.gt", F32, I32),
+        (F32Le, "f32.le", F32, I32),
+        (F32Ge, "f32.ge", F32, I32),
+        (F64Add, "f64.add", F64, F64),
+        (F64Sub, "f64.sub", F64, F64),
+        (F64Mul, "f64.mul", F64, F64),
+        (F64Div, "f64.div", F64, F64),
+        (F64Min, "f64.min", F64, F64),
+        (F64Max, "f64.max", F64, F64),
+        (F64Copysign, "f64.copysign", F64, F64),
+        (F64Eq, "f64.eq", F64, I32),
+        (F64Ne, "f64.ne", F64, I32),
+        (F64Lt, "f64.lt", F64, I32),
+        (F64Gt, "f64.gt", F64, I32),
+        (F64Le, "f64.le", F64, I32),
+        (F64Ge, "f64.ge", F64, I32),
+    ]
+};
+
+/// Where to find the implementations of float instructions.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The module that implementations not in `functions` are imported
+    /// from, under the names of the instructions they implement.
+    pub import_module: String,
+
+    /// Functions of the module that implement float instructions, by the
+    /// names of the instructions they implement, such as `f64.mul`.
+    pub functions: HashMap<String, FunctionId>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            import_module: "softfloat".to_string(),
+            functions: HashMap::new(),
+        }
+    }
+}
+
+/// The signature of the function implementing the float instruction `name`,
+/// as its parameters and its result, if there is such an instruction.
+///
+/// This is handy for writing or checking implementations.
+pub fn signature(name: &str) -> Option<(Vec<ValType>, ValType)> {
+    if let Some((_, _, param, result)) = UNARY.iter().find(|(_, n, _, _)| *n == name) {
+        return Some((vec![*param], *result));
+    }
+    let (_, _, param, result) = BINARY.iter().find(|(_, n, _, _)| *n == name)?;
+    Some((vec![*param, *param], *result))
+}
+
+/// Replace the float instructions of `module` with calls to their
+/// implementations, as described by `options`.
+///
+/// Imports are only added for instructions that the module uses, and
+/// existing imports with the right names and signatures are reused. The
+/// functions in `options.functions` aren't lowered themselves. Fails,
+/// leaving the module untouched, if one of `options.functions` isn't named
+/// after a float instruction or doesn't have its signature.
+pub fn run(module: &mut Module, options: &Options) -> Result<()> {
+    for (name, func) in options.functions.iter() {
+        let (params, result) = match signature(name) {
+            Some(signature) => signature,
+            None => bail!("`{}` isn't a float instruction that can be lowered", name),
+        };
+        let ty = module.funcs.get(*func).ty();
+        if module.types.params_results(ty) != (&params[..], &[result][..]) {
+            bail!(
+                "the implementation of `{}` must take {} and return an `{}`",
+                name,
+                params
+                    .iter()
+                    .map(|ty| format!("an `{}`", ty))
+                    .collect::<Vec<_>>()
+                    .join(" and "),
+                result
+            );
+        }
+    }
+
+    let mut lower = Lower {
+        unary: HashMap::new(),
+        binary: HashMap::new(),
+    };
+    let implementations = options.functions.values().copied().collect::<Vec<_>>();
+    for name in float_instrs(module, &implementations) {
+        let func = match options.functions.get(name) {
+            Some(func) => *func,
+            None => import(module, &options.import_module, name),
+        };
+        if let Some((op, ..)) = UNARY.iter().find(|(_, n, _, _)| *n == name) {
+            lower.unary.insert(*op, func);
+        }
+        if let Some((op, ..)) = BINARY.iter().find(|(_, n, _, _)| *n == name) {
+            lower.binary.insert(*op, func);
+        }
+    }
+
+    // The implementations are left alone, in case they use the instructions
+    // that they implement, such as for a fast path.
+    for (id, func) in module.funcs.iter_local_mut() {
+        if implementations.contains(&id) {
+            continue;
+        }
+        let entry = func.entry_block();
+        dfs_pre_order_mut(&mut lower, func, entry);
+    }
+    Ok(())
+}
+
+/// The names of all the distinct float instructions in `module` that are
+/// lowered, in the order they first appear, outside of the `implementations`.
+fn float_instrs(module: &Module, implementations: &[FunctionId]) -> Vec<&'static str> {
+    #[derive(Default)]
+    struct Collect(Vec<&'static str>);
+
+    impl Collect {
+        fn add(&mut self, name: Option<&'static str>) {
+            if let Some(name) = name {
+                if !self.0.contains(&name) {
+                    self.0.push(name);
+                }
+            }
+        }
+    }
+
+    impl<'instr> Visitor<'instr> for Collect {
+        fn visit_unop(&mut self, e: &Unop) {
+            let name = UNARY.iter().find(|(op, ..)| *op == e.op);
+            self.add(name.map(|(_, name, ..)| *name));
+        }
+
+        fn visit_binop(&mut self, e: &Binop) {
+            let name = BINARY.iter().find(|(op, ..)| *op == e.op);
+            self.add(name.map(|(_, name, ..)| *name));
+        }
+    }
+
+    let mut collect = Collect::default();
+    for (id, func) in module.funcs.iter_local() {
+        if !implementations.contains(&id) {
+            dfs_in_order(&mut collect, func, func.entry_block());
+        }
+    }
+    collect.0
+}
+
+/// Import the implementation of the float instruction `name` from `from`,
+/// unless it is imported already.
+fn import(module: &mut Module, from: &str, name: &str) -> FunctionId {
+    let (params, result) = signature(name).unwrap();
+    if let Some(import) = module.imports.find(from, name) {
+        if let ImportKind::Function(func) = module.imports.get(import).kind {
+            let ty = module.funcs.get(func).ty();
+            if module.types.params_results(ty) == (&params[..], &[result][..]) {
+                return func;
+            }
+        }
+    }
+    let ty = match module.types.find(&params, &[result]) {
+        Some(ty) => ty,
+        None => module.types.add(&params, &[result]),
+    };
+    module.add_import_func(from, name, ty).0
+}
+
+struct Lower {
+    unary: HashMap<UnaryOp, FunctionId>,
+    binary: HashMap<BinaryOp, FunctionId>,
+}
+
+impl VisitorMut for Lower {
+    fn visit_instr_mut(&mut self, instr: &mut Instr, _: &mut InstrLocId) {
+        let func = match instr {
+            Instr::Unop(e) => self.unary.get(&e.op),
+            Instr::Binop(e) => self.binary.get(&e.op),
+            _ => None,
+        };
+        if let Some(func) = func {
+            *instr = Call { func: *func }.into();
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Lower `i64`s at a module's boundary, for JavaScript hosts without `BigInt`
+//! integration.
+//!
+//! JavaScript engines that predate the `BigInt` integration can't pass `i64`
+//! values to or from wasm at all. This pass changes the signatures that a
+//! host sees, those of imported and exported functions, to represent each
+//! `i64` in a way that such engines can handle, as chosen by `I64Repr`:
+//!
+//! * As a pair of `i32`s, the low half first, which is exact. An `i64` result
+//!   becomes two results, so exported functions returning `i64`s rely on
+//!   multi-value. Run `lower_multi_value` afterwards to avoid that.
+//! * As an `f64`, which loses precision for values larger in magnitude than
+//!   2^53. An `f64` that doesn't fit an `i64`, or is NaN, traps when it is
+//!   converted.
+//!
+//! Each exported function with `i64`s in its signature is exported through a
+//! generated `legalstub$` wrapper instead, which converts between the
+//! representations and calls it. Each such imported function is imported
+//! with the lowered signature instead, and every use of it within the module
+//! goes through a generated `legalfunc$` thunk with its original signature,
+//! which converts the other way around. Calls within the module keep passing
+//! `i64`s as they are.
+
+use crate::ir::*;
+use crate::passes::record_replay;
+use crate::{ExportItem, FunctionBuilder, FunctionId, FunctionKind, InstrSeqBuilder, LocalId};
+use crate::{Module, ModuleLocals, TypeId, ValType};
+use std::collections::{HashMap, HashSet};
+
+/// How `i64`s are represented at a module's boundary.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum I64Repr {
+    /// As two `i32`s, the low half first and then the high half.
+    I32Pair,
+    /// As an `f64`, converted to and from a signed integer.
+    F64,
+}
+
+/// Lower the `i64`s in the signatures of all imported and exported functions
+/// in `module` to `repr`.
+///
+/// Returns a map from each lowered exported function, and each lowered
+/// imported function, to the wrapper or thunk generated for it.
+pub fn run(module: &mut Module, repr: I64Repr) -> HashMap<FunctionId, FunctionId> {
+    let imports = module
+        .funcs
+        .iter()
+        .filter_map(|f| match &f.kind {
+            FunctionKind::Import(i) if has_i64(module, i.ty) => Some((f.id(), i.ty, i.import)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut thunks = HashMap::new();
+    for (import, ty, import_id) in imports {
+        let (params, results) = module.types.params_results(ty);
+        let (params, results) = (params.to_vec(), results.to_vec());
+        let lowered_results = lower_types(&results, repr);
+        let lowered_ty = module
+            .types
+            .add(&lower_types(&params, repr), &lowered_results);
+        if let FunctionKind::Import(i) = &mut module.funcs.get_mut(import).kind {
+            i.ty = lowered_ty;
+        }
+
+        let args = params
+            .iter()
+            .map(|ty| module.locals.add(*ty))
+            .collect::<Vec<_>>();
+        let mut builder = FunctionBuilder::new(&mut module.types, &params, &results);
+        let mut body = builder.func_body();
+        for (&arg, &ty) in args.iter().zip(&params) {
+            push_lowered(&mut body, repr, arg, ty);
+        }
+        body.call(import);
+        let rets = pop_all(&mut body, &mut module.locals, &lowered_results);
+        let mut rets = rets.iter();
+        for &ty in results.iter() {
+            push_raised(&mut body, repr, &mut rets, ty);
+        }
+        builder.name(format!("legalfunc${}", module.imports.get(import_id).name));
+        let thunk = builder.finish(args, &mut module.funcs);
+        thunks.insert(import, thunk);
+    }
+    record_replay::redirect(module, &thunks, &HashSet::new());
+
+    let exports = module
+        .exports
+        .iter()
+        .filter_map(|e| match e.item {
+            ExportItem::Function(f) if has_i64(module, module.funcs.get(f).ty()) => {
+                Some((e.id(), e.name.clone(), f))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut wrappers = HashMap::new();
+    for (export, name, func) in exports {
+        let wrapper = match wrappers.get(&func) {
+            Some(wrapper) => *wrapper,
+            None => {
+                let wrapper = wrap_export(module, repr, func, &name);
+                wrappers.insert(func, wrapper);
+                wrapper
+            }
+        };
+        module.exports.get_mut(export).item = ExportItem::Function(wrapper);
+    }
+
+    thunks.extend(wrappers);
+    thunks
+}
+
+/// Generate a wrapper for `func` with the lowered signature.
+fn wrap_export(module: &mut Module, repr: I64Repr, func: FunctionId, name: &str) -> FunctionId {
+    let ty = module.funcs.get(func).ty();
+    let (params, results) = module.types.params_results(ty);
+    let (params, results) = (params.to_vec(), results.to_vec());
+    let lowered_params = lower_types(&params, repr);
+
+    let args = lowered_params
+        .iter()
+        .map(|ty| module.locals.add(*ty))
+        .collect::<Vec<_>>();
+    let mut builder = FunctionBuilder::new(
+        &mut module.types,
+        &lowered_params,
+        &lower_types(&results, repr),
+    );
+    let mut body = builder.func_body();
+    let mut lowered_args = args.iter();
+    for &ty in params.iter() {
+        push_raised(&mut body, repr, &mut lowered_args, ty);
+    }
+    body.call(func);
+    if results.contains(&ValType::I64) {
+        let rets = pop_all(&mut body, &mut module.locals, &results);
+        for (&ret, &ty) in rets.iter().zip(&results) {
+            push_lowered(&mut body, repr, ret, ty);
+        }
+    }
+    builder.name(format!("legalstub${}", name));
+    builder.finish(args, &mut module.funcs)
+}
+
+fn has_i64(module: &Module, ty: TypeId) -> bool {
+    let (params, results) = module.types.params_results(ty);
+    params.iter().chain(results).any(|t| *t == ValType::I64)
+}
+
+/// The types of `types` as the host sees them.
+fn lower_types(types: &[ValType], repr: I64Repr) -> Vec<ValType> {
+    let mut lowered = Vec::new();
+    for &ty in types {
+        match (ty, repr) {
+            (ValType::I64, I64Repr::I32Pair) => lowered.extend(&[ValType::I32, ValType::I32]),
+            (ValType::I64, I64Repr::F64) => lowered.push(ValType::F64),
+            (ty, _) => lowered.push(ty),
+        }
+    }
+    lowered
+}
+
+/// Pop values of the types `types` off the stack into new locals.
+fn pop_all(
+    body: &mut InstrSeqBuilder,
+    locals: &mut ModuleLocals,
+    types: &[ValType],
+) -> Vec<LocalId> {
+    let popped = types.iter().map(|ty| locals.add(*ty)).collect::<Vec<_>>();
+    for &local in popped.iter().rev() {
+        body.local_set(local);
+    }
+    popped
+}
+
+/// Push the value of type `ty` in `local` as the host sees it.
+fn push_lowered(body: &mut InstrSeqBuilder, repr: I64Repr, local: LocalId, ty: ValType) {
+    body.local_get(local);
+    if ty != ValType::I64 {
+        return;
+    }
+    match repr {
+        I64Repr::I32Pair => {
+            body.unop(UnaryOp::I32WrapI64)
+                .local_get(local)
+                .i64_const(32)
+                .binop(BinaryOp::I64ShrU)
+                .unop(UnaryOp::I32WrapI64);
+        }
+        I64Repr::F64 => {
+            body.unop(UnaryOp::F64ConvertSI64);
+        }
+    }
+}
+
+/// Push a value of type `ty` made from the values that the host sees it as,
+/// taken from `locals`.
+fn push_raised<'a>(
+    body: &mut InstrSeqBuilder,
+    repr: I64Repr,
+    locals: &mut impl Iterator<Item = &'a LocalId>,
+    ty: ValType,
+) {
+    let mut next = || *locals.next().unwrap();
+    body.local_get(next());
+    if ty != ValType::I64 {
+        return;
+    }
+    match repr {
+        I64Repr::I32Pair => {
+            body.unop(UnaryOp::I64ExtendUI32)
+                .local_get(next())
+                .unop(UnaryOp::I64ExtendUI32)
+                .i64_const(32)
+                .binop(BinaryOp::I64Shl)
+                .binop(BinaryOp::I64Or);
+        }
+        I64Repr::F64 => {
+            body.unop(UnaryOp::I64TruncSF64);
+        }
+    }
+}
@@ -0,0 +1,391 @@
+//! Lower multi-value function signatures to out-parameters in linear memory.
+//!
+//! This pass targets hosts and bindings generators that can't handle functions
+//! returning more than one value. Every such function returns only its first
+//! result instead, and takes an extra `i32` parameter, after its own ones,
+//! with the address to store the rest of its results at. They are stored one
+//! after the other, in order, each aligned to its size. Imported and exported
+//! functions change the same way, so the host has to implement and call them
+//! with this convention.
+//!
+//! Calls within the module pass the address of a scratch area, placed right
+//! after the initial size of the first memory, which grows to make room for
+//! it, and load the results from there right after the call returns. Like the
+//! data placed by `lower_bulk_memory`, this memory isn't reserved in any way,
+//! and since every call shares the area, the memory can't be shared between
+//! threads.
+//!
+//! Blocks, loops and ifs with multi-value types are left alone, along with
+//! their types.
+
+use crate::ir::*;
+use crate::{FunctionId, FunctionKind, IndexType, LocalFunction, MemoryId, Module};
+use crate::{ModuleLocals, ModuleTables, ModuleTypes, Result, TypeId, ValType};
+use anyhow::bail;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+const PAGE_SIZE: u32 = 1 << 16;
+const MAX_PAGES: u32 = 1 << 16;
+
+/// Lower all functions in `module` that return more than one value to return
+/// their first result, and store the others through an extra pointer
+/// parameter.
+pub fn run(module: &mut Module) -> Result<()> {
+    let lowered = lowered_types(module)?;
+    if lowered.is_empty() {
+        return Ok(());
+    }
+
+    let memory = match module.memories.iter().next() {
+        Some(memory) => memory,
+        None => bail!("cannot lower multi-value functions without a memory"),
+    };
+    if memory.shared {
+        bail!("cannot lower multi-value functions with a shared memory");
+    }
+    let size = lowered
+        .values()
+        .filter_map(|l| l.rest.last().map(|&(ty, offset)| offset + size_of(ty)))
+        .max()
+        .unwrap_or(0);
+    let pages = size / PAGE_SIZE + u32::from(size % PAGE_SIZE != 0);
+    if memory.initial + pages > memory.maximum.unwrap_or(MAX_PAGES) {
+        bail!("memory is too small to make room for the multi-value scratch area");
+    }
+    let memory = memory.id();
+    let scratch = module.memories.get(memory).initial * PAGE_SIZE;
+    module.memories.get_mut(memory).initial += pages;
+
+    let callees = module
+        .funcs
+        .iter()
+        .map(|f| (f.id(), f.ty()))
+        .collect::<HashMap<_, _>>();
+    let lower = Lower {
+        lowered: &lowered,
+        callees: &callees,
+        tables: &module.tables,
+        memory,
+        scratch,
+    };
+    for (id, func) in module.funcs.iter_local_mut() {
+        let own = lowered.get(&callees[&id]);
+        lower.rewrite(&mut module.types, &mut module.locals, func, own)?;
+    }
+    for func in module.funcs.iter_mut() {
+        if let FunctionKind::Import(import) = &mut func.kind {
+            if let Some(lowered) = lowered.get(&import.ty) {
+                import.ty = lowered.ty;
+            }
+        }
+    }
+
+    // Remove the multi-value types, unless blocks still use them.
+    let block_types = block_types(module);
+    for ty in lowered.keys() {
+        if !block_types.contains(ty) {
+            module.types.delete(*ty);
+        }
+    }
+    Ok(())
+}
+
+/// The multi-value types of the blocks, loops and ifs in `module`.
+fn block_types(module: &Module) -> HashSet<TypeId> {
+    struct CollectBlockTypes<'a>(&'a mut HashSet<TypeId>);
+
+    impl<'instr> Visitor<'instr> for CollectBlockTypes<'_> {
+        fn start_instr_seq(&mut self, seq: &'instr InstrSeq) {
+            if let InstrSeqType::MultiValue(ty) = seq.ty {
+                self.0.insert(ty);
+            }
+        }
+    }
+
+    let mut types = HashSet::new();
+    for (_, func) in module.funcs.iter_local() {
+        dfs_in_order(&mut CollectBlockTypes(&mut types), func, func.entry_block());
+    }
+    types
+}
+
+/// How a multi-value function type is lowered.
+struct Lowered {
+    /// The lowered type.
+    ty: TypeId,
+    /// The types of the results after the first, and their offsets from the
+    /// out-parameter.
+    rest: Vec<(ValType, u32)>,
+}
+
+/// Lower the multi-value types of functions, and of indirect calls, in
+/// `module`.
+fn lowered_types(module: &mut Module) -> Result<HashMap<TypeId, Lowered>> {
+    struct CollectTypes<'a>(&'a mut Vec<TypeId>);
+
+    impl<'instr> Visitor<'instr> for CollectTypes<'_> {
+        fn visit_call_indirect(&mut self, e: &CallIndirect) {
+            self.0.push(e.ty);
+        }
+    }
+
+    let mut types = module.funcs.iter().map(|f| f.ty()).collect::<Vec<_>>();
+    for (_, func) in module.funcs.iter_local() {
+        dfs_in_order(&mut CollectTypes(&mut types), func, func.entry_block());
+    }
+
+    let mut lowered = HashMap::new();
+    for ty in types {
+        let (params, results) = module.types.params_results(ty);
+        if results.len() < 2 || lowered.contains_key(&ty) {
+            continue;
+        }
+        let mut rest = Vec::new();
+        let mut end = 0;
+        for &result in &results[1..] {
+            if result.is_reference() {
+                bail!("cannot store a reference result to linear memory");
+            }
+            let size = size_of(result);
+            let offset = (end + size - 1) & !(size - 1);
+            rest.push((result, offset));
+            end = offset + size;
+        }
+        let mut lowered_params = params.to_vec();
+        lowered_params.push(ValType::I32);
+        let first = results[0];
+        let lowered_ty = module.types.add(&lowered_params, &[first]);
+        lowered.insert(
+            ty,
+            Lowered {
+                ty: lowered_ty,
+                rest,
+            },
+        );
+    }
+    Ok(lowered)
+}
+
+fn size_of(ty: ValType) -> u32 {
+    match ty {
+        ValType::I32 | ValType::F32 => 4,
+        ValType::I64 | ValType::F64 => 8,
+        ValType::V128 => 16,
+        ValType::Anyref => unreachable!(),
+        #[cfg(feature = "unstable")]
+        ValType::Cont { .. } => unreachable!(),
+    }
+}
+
+struct Lower<'a> {
+    lowered: &'a HashMap<TypeId, Lowered>,
+    callees: &'a HashMap<FunctionId, TypeId>,
+    tables: &'a ModuleTables,
+    memory: MemoryId,
+    scratch: u32,
+}
+
+impl Lower<'_> {
+    /// Rewrite the calls to lowered functions in `func`, and lower `func`
+    /// itself if it is lowered as `own`.
+    fn rewrite(
+        &self,
+        types: &mut ModuleTypes,
+        locals: &mut ModuleLocals,
+        func: &mut LocalFunction,
+        own: Option<&Lowered>,
+    ) -> Result<()> {
+        let entry = func.entry_block();
+        let out = match own {
+            Some(own) => {
+                let out = locals.add(ValType::I32);
+                let results = own
+                    .rest
+                    .iter()
+                    .map(|&(ty, _)| locals.add(ty))
+                    .collect::<Vec<_>>();
+                Some((own, out, results))
+            }
+            None => None,
+        };
+        let mut indices = HashMap::new();
+
+        let mut seqs = Vec::new();
+        dfs_in_order(&mut CollectSeqs(&mut seqs), func, entry);
+        for seq in seqs {
+            let instrs = mem::take(&mut func.block_mut(seq).instrs);
+            let mut new_instrs = Vec::with_capacity(instrs.len());
+            for (instr, loc) in instrs {
+                match instr {
+                    Instr::Call(Call { func: callee }) => {
+                        match self.lowered.get(&self.callees[&callee]) {
+                            Some(lowered) => {
+                                new_instrs.push((self.scratch_address(), loc));
+                                new_instrs.push((instr, loc));
+                                self.load_results(lowered, &mut new_instrs, loc);
+                            }
+                            None => new_instrs.push((instr, loc)),
+                        }
+                    }
+                    Instr::CallIndirect(CallIndirect { ty, table }) => {
+                        match self.lowered.get(&ty) {
+                            Some(lowered) => {
+                                // The out-parameter goes under the index of the
+                                // function to call.
+                                let index = *indices.entry(table).or_insert_with(|| {
+                                    locals.add(match self.tables.get(table).index_type {
+                                        IndexType::I32 => ValType::I32,
+                                        IndexType::I64 => ValType::I64,
+                                    })
+                                });
+                                new_instrs.push((LocalSet { local: index }.into(), loc));
+                                new_instrs.push((self.scratch_address(), loc));
+                                new_instrs.push((LocalGet { local: index }.into(), loc));
+                                let call = CallIndirect {
+                                    ty: lowered.ty,
+                                    table,
+                                };
+                                new_instrs.push((call.into(), loc));
+                                self.load_results(lowered, &mut new_instrs, loc);
+                            }
+                            None => new_instrs.push((instr, loc)),
+                        }
+                    }
+                    // Branching out of the function returns from it just the
+                    // same.
+                    Instr::Return(_) | Instr::Br(_) if returns(&instr, entry) && own.is_some() => {
+                        let (own, out, results) = out.as_ref().unwrap();
+                        self.store_results(own, *out, results, &mut new_instrs, loc);
+                        new_instrs.push((Return {}.into(), loc));
+                    }
+                    Instr::BrIf(BrIf { block }) if own.is_some() && block == entry => {
+                        bail!("cannot lower a conditional branch out of a multi-value function")
+                    }
+                    Instr::BrTable(BrTable {
+                        ref blocks,
+                        default,
+                    }) if own.is_some() && (default == entry || blocks.contains(&entry)) => {
+                        bail!("cannot lower a branch table out of a multi-value function")
+                    }
+                    #[cfg(feature = "unstable")]
+                    Instr::Resume(Resume { ref handlers, .. })
+                        if own.is_some() && handlers.iter().any(|h| h.block == entry) =>
+                    {
+                        bail!("cannot lower a `resume` handler out of a multi-value function")
+                    }
+                    instr => new_instrs.push((instr, loc)),
+                }
+            }
+            func.block_mut(seq).instrs = new_instrs;
+        }
+
+        if let Some((own, out, results)) = out {
+            let mut instrs = mem::take(&mut func.block_mut(entry).instrs);
+            self.store_results(own, out, &results, &mut instrs, InstrLocId::default());
+            let block = func.block_mut(entry);
+            block.instrs = instrs;
+            let first = types.results(own.ty)[0];
+            block.ty = InstrSeqType::MultiValue(types.add_entry_ty(&[first]));
+            func.args.push(out);
+            func.builder_mut().ty = own.ty;
+        }
+        Ok(())
+    }
+
+    fn scratch_address(&self) -> Instr {
+        Const {
+            value: Value::I32(self.scratch as i32),
+        }
+        .into()
+    }
+
+    /// Store the results after the first, on top of the stack, through
+    /// `out`, saving them in `results` to get them off the stack.
+    fn store_results(
+        &self,
+        lowered: &Lowered,
+        out: LocalId,
+        results: &[LocalId],
+        instrs: &mut Vec<(Instr, InstrLocId)>,
+        loc: InstrLocId,
+    ) {
+        for &local in results.iter().rev() {
+            instrs.push((LocalSet { local }.into(), loc));
+        }
+        for (&local, &(ty, offset)) in results.iter().zip(&lowered.rest) {
+            let kind = match ty {
+                ValType::I32 => StoreKind::I32 { atomic: false },
+                ValType::I64 => StoreKind::I64 { atomic: false },
+                ValType::F32 => StoreKind::F32,
+                ValType::F64 => StoreKind::F64,
+                ValType::V128 => StoreKind::V128,
+                ValType::Anyref => unreachable!(),
+                #[cfg(feature = "unstable")]
+                ValType::Cont { .. } => unreachable!(),
+            };
+            let store = Store {
+                memory: self.memory,
+                kind,
+                arg: memarg(ty, offset),
+            };
+            instrs.push((LocalGet { local: out }.into(), loc));
+            instrs.push((LocalGet { local }.into(), loc));
+            instrs.push((store.into(), loc));
+        }
+    }
+
+    /// Load the results after the first of a call to a lowered function from
+    /// the scratch area.
+    fn load_results(
+        &self,
+        lowered: &Lowered,
+        instrs: &mut Vec<(Instr, InstrLocId)>,
+        loc: InstrLocId,
+    ) {
+        for &(ty, offset) in &lowered.rest {
+            let kind = match ty {
+                ValType::I32 => LoadKind::I32 { atomic: false },
+                ValType::I64 => LoadKind::I64 { atomic: false },
+                ValType::F32 => LoadKind::F32,
+                ValType::F64 => LoadKind::F64,
+                ValType::V128 => LoadKind::V128,
+                ValType::Anyref => unreachable!(),
+                #[cfg(feature = "unstable")]
+                ValType::Cont { .. } => unreachable!(),
+            };
+            let load = Load {
+                memory: self.memory,
+                kind,
+                arg: memarg(ty, offset),
+            };
+            instrs.push((self.scratch_address(), loc));
+            instrs.push((load.into(), loc));
+        }
+    }
+}
+
+/// Whether `instr` returns from the function whose body is `entry`.
+fn returns(instr: &Instr, entry: InstrSeqId) -> bool {
+    match instr {
+        Instr::Return(_) => true,
+        Instr::Br(Br { block }) => *block == entry,
+        _ => false,
+    }
+}
+
+fn memarg(ty: ValType, offset: u32) -> MemArg {
+    MemArg {
+        align: size_of(ty),
+        offset,
+        encoding: None,
+    }
+}
+
+struct CollectSeqs<'a>(&'a mut Vec<InstrSeqId>);
+
+impl<'instr> Visitor<'instr> for CollectSeqs<'_> {
+    fn start_instr_seq(&mut self, seq: &'instr InstrSeq) {
+        self.0.push(seq.id());
+    }
+}
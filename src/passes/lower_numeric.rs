@@ -0,0 +1,223 @@
+//! Lower the sign extension and non-trapping float-to-int operators to MVP
+//! code.
+//!
+//! This pass targets engines that predate these proposals. Which of them are
+//! lowered depends on the `WasmFeatures` passed in: operators of disabled
+//! features are rewritten, while operators of enabled features are kept.
+//!
+//! * The `extend*_s` operators become a left shift followed by an arithmetic
+//!   right shift, inline.
+//!
+//! * The `trunc_sat` operators become calls to generated functions, which
+//!   only do a trapping truncation once they have checked that it can't trap,
+//!   and otherwise saturate: NaNs become zero, and out of range values become
+//!   the smallest or largest integer.
+
+use crate::ir::*;
+use crate::{FunctionBuilder, FunctionId, Module, ValType, WasmFeatures};
+use std::collections::HashMap;
+
+/// Lower the operators of the sign extension and non-trapping float-to-int
+/// conversion proposals in `module`, unless `features` enables them.
+pub fn run(module: &mut Module, features: WasmFeatures) {
+    let mut lower = Lower {
+        sign_extension: !features.sign_extension,
+        saturating: HashMap::new(),
+    };
+
+    if !features.saturating_float_to_int {
+        for op in saturating_ops(module) {
+            let func = saturating_trunc(module, op);
+            lower.saturating.insert(op, func);
+        }
+    }
+
+    for (_, func) in module.funcs.iter_local_mut() {
+        let entry = func.entry_block();
+        dfs_pre_order_mut(&mut lower, func, entry);
+    }
+}
+
+/// All the distinct `trunc_sat` operators in `module`, in the order they first
+/// appear.
+fn saturating_ops(module: &Module) -> Vec<UnaryOp> {
+    #[derive(Default)]
+    struct Collect(Vec<UnaryOp>);
+
+    impl<'instr> Visitor<'instr> for Collect {
+        fn visit_unop(&mut self, e: &Unop) {
+            if saturating_params(e.op).is_some() && !self.0.contains(&e.op) {
+                self.0.push(e.op);
+            }
+        }
+    }
+
+    let mut collect = Collect::default();
+    for (_, func) in module.funcs.iter_local() {
+        dfs_in_order(&mut collect, func, func.entry_block());
+    }
+    collect.0
+}
+
+struct Lower {
+    sign_extension: bool,
+    saturating: HashMap<UnaryOp, FunctionId>,
+}
+
+impl VisitorMut for Lower {
+    fn start_instr_seq_mut(&mut self, seq: &mut InstrSeq) {
+        if !self.sign_extension {
+            return;
+        }
+        let any = seq.instrs.iter().any(|(instr, _)| match instr {
+            Instr::Unop(e) => sign_extension_shift(e.op).is_some(),
+            _ => false,
+        });
+        if !any {
+            return;
+        }
+
+        let mut instrs = Vec::with_capacity(seq.instrs.len());
+        for (instr, loc) in seq.instrs.drain(..) {
+            let shift = match &instr {
+                Instr::Unop(e) => sign_extension_shift(e.op),
+                _ => None,
+            };
+            match shift {
+                Some((shift, shl, shr_s)) => {
+                    instrs.push((Const { value: shift }.into(), loc));
+                    instrs.push((Binop { op: shl }.into(), loc));
+                    instrs.push((Const { value: shift }.into(), loc));
+                    instrs.push((Binop { op: shr_s }.into(), loc));
+                }
+                None => instrs.push((instr, loc)),
+            }
+        }
+        seq.instrs = instrs;
+    }
+
+    fn visit_instr_mut(&mut self, instr: &mut Instr, _: &mut InstrLocId) {
+        if let Instr::Unop(e) = instr {
+            if let Some(func) = self.saturating.get(&e.op) {
+                *instr = Call { func: *func }.into();
+            }
+        }
+    }
+}
+
+/// The shift amount, and the shift operators, that sign extend like `op`.
+fn sign_extension_shift(op: UnaryOp) -> Option<(Value, BinaryOp, BinaryOp)> {
+    use BinaryOp::*;
+    Some(match op {
+        UnaryOp::I32Extend8S => (Value::I32(24), I32Shl, I32ShrS),
+        UnaryOp::I32Extend16S => (Value::I32(16), I32Shl, I32ShrS),
+        UnaryOp::I64Extend8S => (Value::I64(56), I64Shl, I64ShrS),
+        UnaryOp::I64Extend16S => (Value::I64(48), I64Shl, I64ShrS),
+        UnaryOp::I64Extend32S => (Value::I64(32), I64Shl, I64ShrS),
+        _ => return None,
+    })
+}
+
+/// How to lower a `trunc_sat` operator.
+struct Saturating {
+    name: &'static str,
+    from: ValType,
+    to: ValType,
+    signed: bool,
+    trunc: UnaryOp,
+}
+
+fn saturating_params(op: UnaryOp) -> Option<Saturating> {
+    use crate::ValType::*;
+    use UnaryOp::*;
+    let (name, from, to, signed, trunc) = match op {
+        I32TruncSSatF32 => ("i32.trunc_sat_f32_s", F32, I32, true, I32TruncSF32),
+        I32TruncUSatF32 => ("i32.trunc_sat_f32_u", F32, I32, false, I32TruncUF32),
+        I32TruncSSatF64 => ("i32.trunc_sat_f64_s", F64, I32, true, I32TruncSF64),
+        I32TruncUSatF64 => ("i32.trunc_sat_f64_u", F64, I32, false, I32TruncUF64),
+        I64TruncSSatF32 => ("i64.trunc_sat_f32_s", F32, I64, true, I64TruncSF32),
+        I64TruncUSatF32 => ("i64.trunc_sat_f32_u", F32, I64, false, I64TruncUF32),
+        I64TruncSSatF64 => ("i64.trunc_sat_f64_s", F64, I64, true, I64TruncSF64),
+        I64TruncUSatF64 => ("i64.trunc_sat_f64_u", F64, I64, false, I64TruncUF64),
+        _ => return None,
+    };
+    Some(Saturating {
+        name,
+        from,
+        to,
+        signed,
+        trunc,
+    })
+}
+
+/// Create a function doing what the `trunc_sat` operator `op` does.
+fn saturating_trunc(module: &mut Module, op: UnaryOp) -> FunctionId {
+    let Saturating {
+        name,
+        from,
+        to,
+        signed,
+        trunc,
+    } = saturating_params(op).unwrap();
+
+    // Inputs strictly between `above` and `below` can be truncated without
+    // trapping. Note that `above` may round up to the smallest integer
+    // itself, which then saturates to the right result anyway.
+    let bits = if to == ValType::I32 { 32 } else { 64 };
+    let below = 2f64.powi(if signed { bits - 1 } else { bits });
+    let above = if signed { -below - 1.0 } else { -1.0 };
+    let (zero, min, max) = match (to, signed) {
+        (ValType::I32, true) => (Value::I32(0), Value::I32(i32::MIN), Value::I32(i32::MAX)),
+        (ValType::I32, false) => (Value::I32(0), Value::I32(0), Value::I32(-1)),
+        (_, true) => (Value::I64(0), Value::I64(i64::MIN), Value::I64(i64::MAX)),
+        (_, false) => (Value::I64(0), Value::I64(0), Value::I64(-1)),
+    };
+    let float = |f: f64| match from {
+        ValType::F32 => Value::F32(f as f32),
+        _ => Value::F64(f),
+    };
+    let (lt, gt, ne) = match from {
+        ValType::F32 => (BinaryOp::F32Lt, BinaryOp::F32Gt, BinaryOp::F32Ne),
+        _ => (BinaryOp::F64Lt, BinaryOp::F64Gt, BinaryOp::F64Ne),
+    };
+
+    let x = module.locals.add(from);
+    let ty = Some(to);
+    let mut builder = FunctionBuilder::new(&mut module.types, &[from], &[to]);
+    builder.name(name.to_string());
+    builder
+        .func_body()
+        .local_get(x)
+        .const_(float(above))
+        .binop(gt)
+        .local_get(x)
+        .const_(float(below))
+        .binop(lt)
+        .binop(BinaryOp::I32And)
+        .if_else(
+            ty,
+            |in_range| {
+                in_range.local_get(x).unop(trunc);
+            },
+            |out_of_range| {
+                out_of_range.local_get(x).local_get(x).binop(ne).if_else(
+                    ty,
+                    |nan| {
+                        nan.const_(zero);
+                    },
+                    |not_nan| {
+                        not_nan.local_get(x).const_(float(0.0)).binop(lt).if_else(
+                            ty,
+                            |negative| {
+                                negative.const_(min);
+                            },
+                            |positive| {
+                                positive.const_(max);
+                            },
+                        );
+                    },
+                );
+            },
+        );
+    builder.finish(vec![x], &mut module.funcs)
+}
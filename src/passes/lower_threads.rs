@@ -0,0 +1,372 @@
+//! Lower a threaded module to a single-threaded one.
+//!
+//! This pass produces fallback binaries for engines without the threads
+//! proposal from modules built for threads. With only one thread around,
+//! atomicity comes for free, so
+//!
+//! * shared memories become unshared,
+//! * atomic loads and stores become plain loads and stores,
+//! * atomic read-modify-write and compare-exchange operators become calls to
+//!   generated functions doing plain loads and stores,
+//! * `atomic.fence` is removed,
+//! * `atomic.notify` returns zero, since there is nobody to wake up, and
+//! * `*.atomic.wait` returns "not-equal" when the value in memory differs from
+//!   the expected one, and "timed-out" right away otherwise, unless the wait
+//!   has no timeout, in which case it traps instead of waiting forever.
+//!
+//! Accesses keep trapping when they are out of bounds, but no longer trap
+//! when they are misaligned. Imported memories are demoted too, so the
+//! embedder must provide an unshared memory instead.
+
+use crate::ir::*;
+use crate::{FunctionBuilder, FunctionId, MemoryId, Module, ValType};
+use std::collections::{HashMap, HashSet};
+
+/// Make `module` single-threaded, replacing all thread-related instructions
+/// with plain equivalents.
+pub fn run(module: &mut Module) {
+    for memory in module.memories.iter_mut() {
+        memory.shared = false;
+    }
+
+    let mut replacements = HashMap::new();
+    for key in atomic_instrs(module) {
+        let func = match key {
+            Key::Rmw(memory, op, width, arg) => rmw(module, memory, op, width, arg),
+            Key::Cmpxchg(memory, width, arg) => cmpxchg(module, memory, width, arg),
+            Key::Notify(memory, arg) => notify(module, memory, arg),
+            Key::Wait(memory, arg, sixty_four) => wait(module, memory, arg, sixty_four),
+        };
+        replacements.insert(key, func);
+    }
+
+    for (_, func) in module.funcs.iter_local_mut() {
+        let entry = func.entry_block();
+        dfs_pre_order_mut(&mut Lower(&replacements), func, entry);
+    }
+}
+
+/// Identifies an atomic instruction that is replaced with a call, along with
+/// its immediates.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Rmw(MemoryId, AtomicOp, AtomicWidth, MemArg),
+    Cmpxchg(MemoryId, AtomicWidth, MemArg),
+    Notify(MemoryId, MemArg),
+    Wait(MemoryId, MemArg, bool),
+}
+
+impl Key {
+    fn of(instr: &Instr) -> Option<Key> {
+        Some(match instr {
+            Instr::AtomicRmw(e) => Key::Rmw(e.memory, e.op, e.width, e.arg),
+            Instr::Cmpxchg(e) => Key::Cmpxchg(e.memory, e.width, e.arg),
+            Instr::AtomicNotify(e) => Key::Notify(e.memory, e.arg),
+            Instr::AtomicWait(e) => Key::Wait(e.memory, e.arg, e.sixty_four),
+            _ => return None,
+        })
+    }
+}
+
+/// All the distinct atomic instructions in `module` that are replaced with
+/// calls, in the order they first appear.
+fn atomic_instrs(module: &Module) -> Vec<Key> {
+    #[derive(Default)]
+    struct Collect {
+        keys: Vec<Key>,
+        seen: HashSet<Key>,
+    }
+
+    impl<'instr> Visitor<'instr> for Collect {
+        fn visit_instr(&mut self, instr: &'instr Instr, _: &'instr InstrLocId) {
+            if let Some(key) = Key::of(instr) {
+                if self.seen.insert(key) {
+                    self.keys.push(key);
+                }
+            }
+        }
+    }
+
+    let mut collect = Collect::default();
+    for (_, func) in module.funcs.iter_local() {
+        dfs_in_order(&mut collect, func, func.entry_block());
+    }
+    collect.keys
+}
+
+struct Lower<'a>(&'a HashMap<Key, FunctionId>);
+
+impl VisitorMut for Lower<'_> {
+    fn start_instr_seq_mut(&mut self, seq: &mut InstrSeq) {
+        seq.instrs.retain(|(instr, _)| match instr {
+            Instr::AtomicFence(_) => false,
+            #[cfg(feature = "unstable")]
+            Instr::Pause(_) => false,
+            _ => true,
+        });
+    }
+
+    fn visit_instr_mut(&mut self, instr: &mut Instr, _: &mut InstrLocId) {
+        if let Some(key) = Key::of(instr) {
+            *instr = Call { func: self.0[&key] }.into();
+            return;
+        }
+        match instr {
+            Instr::Load(e) => e.kind = plain_load(e.kind),
+            Instr::Store(e) => e.kind = plain_store(e.kind),
+            #[cfg(feature = "unstable")]
+            Instr::GlobalAtomicGet(e) => *instr = GlobalGet { global: e.global }.into(),
+            #[cfg(feature = "unstable")]
+            Instr::GlobalAtomicSet(e) => *instr = GlobalSet { global: e.global }.into(),
+            _ => {}
+        }
+    }
+}
+
+fn plain_load(kind: LoadKind) -> LoadKind {
+    use LoadKind::*;
+    let plain = |kind: ExtendedLoad| match kind {
+        ExtendedLoad::ZeroExtendAtomic => ExtendedLoad::ZeroExtend,
+        other => other,
+    };
+    match kind {
+        I32 { .. } => I32 { atomic: false },
+        I64 { .. } => I64 { atomic: false },
+        I32_8 { kind } => I32_8 { kind: plain(kind) },
+        I32_16 { kind } => I32_16 { kind: plain(kind) },
+        I64_8 { kind } => I64_8 { kind: plain(kind) },
+        I64_16 { kind } => I64_16 { kind: plain(kind) },
+        I64_32 { kind } => I64_32 { kind: plain(kind) },
+        F32 | F64 | V128 => kind,
+    }
+}
+
+fn plain_store(kind: StoreKind) -> StoreKind {
+    use StoreKind::*;
+    match kind {
+        I32 { .. } => I32 { atomic: false },
+        I64 { .. } => I64 { atomic: false },
+        I32_8 { .. } => I32_8 { atomic: false },
+        I32_16 { .. } => I32_16 { atomic: false },
+        I64_8 { .. } => I64_8 { atomic: false },
+        I64_16 { .. } => I64_16 { atomic: false },
+        I64_32 { .. } => I64_32 { atomic: false },
+        F32 | F64 | V128 => kind,
+    }
+}
+
+/// The type of the values accessed by atomic operators of this width, and
+/// how to load and store them.
+fn access(width: AtomicWidth) -> (ValType, LoadKind, StoreKind) {
+    let zero = ExtendedLoad::ZeroExtend;
+    match width {
+        AtomicWidth::I32 => (
+            ValType::I32,
+            LoadKind::I32 { atomic: false },
+            StoreKind::I32 { atomic: false },
+        ),
+        AtomicWidth::I32_8 => (
+            ValType::I32,
+            LoadKind::I32_8 { kind: zero },
+            StoreKind::I32_8 { atomic: false },
+        ),
+        AtomicWidth::I32_16 => (
+            ValType::I32,
+            LoadKind::I32_16 { kind: zero },
+            StoreKind::I32_16 { atomic: false },
+        ),
+        AtomicWidth::I64 => (
+            ValType::I64,
+            LoadKind::I64 { atomic: false },
+            StoreKind::I64 { atomic: false },
+        ),
+        AtomicWidth::I64_8 => (
+            ValType::I64,
+            LoadKind::I64_8 { kind: zero },
+            StoreKind::I64_8 { atomic: false },
+        ),
+        AtomicWidth::I64_16 => (
+            ValType::I64,
+            LoadKind::I64_16 { kind: zero },
+            StoreKind::I64_16 { atomic: false },
+        ),
+        AtomicWidth::I64_32 => (
+            ValType::I64,
+            LoadKind::I64_32 { kind: zero },
+            StoreKind::I64_32 { atomic: false },
+        ),
+    }
+}
+
+/// The text format name of the read-modify-write operator `op` of this
+/// width.
+fn rmw_name(width: AtomicWidth, op: &str) -> String {
+    let (ty, bits) = match width {
+        AtomicWidth::I32 => ("i32", ""),
+        AtomicWidth::I32_8 => ("i32", "8"),
+        AtomicWidth::I32_16 => ("i32", "16"),
+        AtomicWidth::I64 => ("i64", ""),
+        AtomicWidth::I64_8 => ("i64", "8"),
+        AtomicWidth::I64_16 => ("i64", "16"),
+        AtomicWidth::I64_32 => ("i64", "32"),
+    };
+    let unsigned = if bits.is_empty() { "" } else { "_u" };
+    format!("{}.atomic.rmw{}.{}{}", ty, bits, op, unsigned)
+}
+
+/// A read-modify-write: store the result of `op` on the old value and the
+/// operand, and return the old value.
+fn rmw(
+    module: &mut Module,
+    memory: MemoryId,
+    op: AtomicOp,
+    width: AtomicWidth,
+    arg: MemArg,
+) -> FunctionId {
+    let (ty, load, store) = access(width);
+    let addr = module.locals.add(ValType::I32);
+    let val = module.locals.add(ty);
+    let old = module.locals.add(ty);
+    let (binop, op_name) = match (op, ty) {
+        (AtomicOp::Add, ValType::I32) => (Some(BinaryOp::I32Add), "add"),
+        (AtomicOp::Sub, ValType::I32) => (Some(BinaryOp::I32Sub), "sub"),
+        (AtomicOp::And, ValType::I32) => (Some(BinaryOp::I32And), "and"),
+        (AtomicOp::Or, ValType::I32) => (Some(BinaryOp::I32Or), "or"),
+        (AtomicOp::Xor, ValType::I32) => (Some(BinaryOp::I32Xor), "xor"),
+        (AtomicOp::Add, _) => (Some(BinaryOp::I64Add), "add"),
+        (AtomicOp::Sub, _) => (Some(BinaryOp::I64Sub), "sub"),
+        (AtomicOp::And, _) => (Some(BinaryOp::I64And), "and"),
+        (AtomicOp::Or, _) => (Some(BinaryOp::I64Or), "or"),
+        (AtomicOp::Xor, _) => (Some(BinaryOp::I64Xor), "xor"),
+        (AtomicOp::Xchg, _) => (None, "xchg"),
+    };
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &[ValType::I32, ty], &[ty]);
+    builder.name(rmw_name(width, op_name));
+    let mut body = builder.func_body();
+    body.local_get(addr)
+        .load(memory, load, arg)
+        .local_set(old)
+        .local_get(addr);
+    if let Some(binop) = binop {
+        body.local_get(old).local_get(val).binop(binop);
+    } else {
+        body.local_get(val);
+    }
+    body.store(memory, store, arg).local_get(old);
+    builder.finish(vec![addr, val], &mut module.funcs)
+}
+
+/// A compare-exchange: store the replacement if the old value equals the
+/// expected value, wrapped to the access width, and return the old value.
+fn cmpxchg(module: &mut Module, memory: MemoryId, width: AtomicWidth, arg: MemArg) -> FunctionId {
+    let (ty, load, store) = access(width);
+    let addr = module.locals.add(ValType::I32);
+    let expected = module.locals.add(ty);
+    let replacement = module.locals.add(ty);
+    let old = module.locals.add(ty);
+    let (mask, and, eq) = match (ty, width.bytes()) {
+        (ValType::I32, 4) => (None, BinaryOp::I32And, BinaryOp::I32Eq),
+        (ValType::I32, n) => (
+            Some(Value::I32((1 << (8 * n)) - 1)),
+            BinaryOp::I32And,
+            BinaryOp::I32Eq,
+        ),
+        (_, 8) => (None, BinaryOp::I64And, BinaryOp::I64Eq),
+        (_, n) => (
+            Some(Value::I64((1 << (8 * n)) - 1)),
+            BinaryOp::I64And,
+            BinaryOp::I64Eq,
+        ),
+    };
+
+    let params = [ValType::I32, ty, ty];
+    let mut builder = FunctionBuilder::new(&mut module.types, &params, &[ty]);
+    builder.name(rmw_name(width, "cmpxchg"));
+    let mut body = builder.func_body();
+    body.local_get(addr)
+        .load(memory, load, arg)
+        .local_tee(old)
+        .local_get(expected);
+    if let Some(mask) = mask {
+        body.const_(mask).binop(and);
+    }
+    body.binop(eq)
+        .if_else(
+            None,
+            |then| {
+                then.local_get(addr)
+                    .local_get(replacement)
+                    .store(memory, store, arg);
+            },
+            |_| {},
+        )
+        .local_get(old);
+    builder.finish(vec![addr, expected, replacement], &mut module.funcs)
+}
+
+/// `atomic.notify`, which wakes up nobody, but still traps when the address
+/// is out of bounds.
+fn notify(module: &mut Module, memory: MemoryId, arg: MemArg) -> FunctionId {
+    let addr = module.locals.add(ValType::I32);
+    let count = module.locals.add(ValType::I32);
+
+    let params = [ValType::I32, ValType::I32];
+    let mut builder = FunctionBuilder::new(&mut module.types, &params, &[ValType::I32]);
+    builder.name("memory.atomic.notify".to_string());
+    builder
+        .func_body()
+        .local_get(addr)
+        .load(memory, LoadKind::I32 { atomic: false }, arg)
+        .drop()
+        .i32_const(0);
+    builder.finish(vec![addr, count], &mut module.funcs)
+}
+
+/// `*.atomic.wait`, which returns 1 ("not-equal") if the value in memory
+/// isn't the expected one, and 2 ("timed-out") otherwise, as if the timeout
+/// elapsed right away. Waiting without a timeout traps, since nobody could
+/// ever wake this thread up again.
+fn wait(module: &mut Module, memory: MemoryId, arg: MemArg, sixty_four: bool) -> FunctionId {
+    let (ty, load, ne, name) = if sixty_four {
+        let load = LoadKind::I64 { atomic: false };
+        (ValType::I64, load, BinaryOp::I64Ne, "memory.atomic.wait64")
+    } else {
+        let load = LoadKind::I32 { atomic: false };
+        (ValType::I32, load, BinaryOp::I32Ne, "memory.atomic.wait32")
+    };
+    let addr = module.locals.add(ValType::I32);
+    let expected = module.locals.add(ty);
+    let timeout = module.locals.add(ValType::I64);
+
+    let params = [ValType::I32, ty, ValType::I64];
+    let mut builder = FunctionBuilder::new(&mut module.types, &params, &[ValType::I32]);
+    builder.name(name.to_string());
+    builder
+        .func_body()
+        .local_get(addr)
+        .load(memory, load, arg)
+        .local_get(expected)
+        .binop(ne)
+        .if_else(
+            Some(ValType::I32),
+            |not_equal| {
+                not_equal.i32_const(1);
+            },
+            |equal| {
+                equal
+                    .local_get(timeout)
+                    .i64_const(0)
+                    .binop(BinaryOp::I64LtS)
+                    .if_else(
+                        None,
+                        |forever| {
+                            forever.unreachable();
+                        },
+                        |_| {},
+                    )
+                    .i32_const(2);
+            },
+        );
+    builder.finish(vec![addr, expected, timeout], &mut module.funcs)
+}
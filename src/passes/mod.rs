@@ -1,6 +1,43 @@
 //! Passes over whole modules or individual functions.
 
+pub mod alloc_stats;
+pub mod canonicalize_nans;
+pub mod cfi;
+pub mod compress_data;
+pub mod coredump;
+pub mod cse;
+pub mod ctors;
+pub mod dataflow;
+pub mod dce_local;
+pub mod effects;
+pub mod externref_handles;
 pub mod gc;
+pub mod guard;
+pub mod licm;
+pub mod liveness;
+pub mod lower_bulk_memory;
+pub mod lower_exceptions;
+pub mod lower_floats;
+pub mod lower_i64_boundary;
+pub mod lower_multi_value;
+pub mod lower_numeric;
+pub mod lower_threads;
+pub mod nondeterminism;
+pub mod obfuscate;
+pub mod outline;
+pub mod promote_indirect;
+pub mod record_replay;
+pub mod reduce;
+pub mod reorder;
+pub mod retained;
+pub mod select;
+pub mod shadow_stack;
+pub mod snip;
+pub mod timing;
+pub mod traps;
+pub mod type_stack;
+pub mod unroll;
 mod used;
 pub mod validate;
+pub mod watermark;
 pub use self::used::Roots;
@@ -0,0 +1,204 @@
+//! Find what may make a module behave differently from one run to another.
+//!
+//! Wasm is deterministic for the most part, which blockchains and
+//! reproducible builds rely on, but a few instructions may give different
+//! results on different engines or runs, and imported functions may give
+//! anything at all. This lists the parts of a module that may be
+//! nondeterministic:
+//!
+//! * Float instructions that may produce a NaN, whose sign and payload bits
+//!   aren't fully specified. Engines that canonicalize NaNs make these
+//!   deterministic, as does a module that canonicalizes its NaNs before
+//!   storing or reinterpreting them, which this doesn't try to prove.
+//!
+//! * `memory.grow` and `table.grow`, which may fail depending on how many
+//!   resources the engine has to spare.
+//!
+//! * Shared memories, which other threads may change in between any two
+//!   instructions, and `memory.atomic.wait`, which may time out.
+//!
+//! * Functions imported from WASI that read clocks, get random bytes, or
+//!   wait for timeouts.
+//!
+//! Relaxed SIMD instructions are nondeterministic as well, but walrus doesn't
+//! parse them, so modules that use them fail to parse instead.
+
+use crate::ir::*;
+use crate::wasi::{self, Capability};
+use crate::{FunctionId, FunctionKind, ImportId, MemoryId, Module};
+
+/// Why an instruction may be nondeterministic.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Cause {
+    /// A float instruction that may produce a NaN, whose bits may differ.
+    NanBits,
+    /// A `memory.grow` or `table.grow`, which may fail.
+    Growth,
+    /// A `memory.atomic.wait`, which may time out.
+    AtomicWait,
+}
+
+/// An instruction that may be nondeterministic.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Site {
+    /// The function the instruction is in.
+    pub func: FunctionId,
+
+    /// The instruction sequence the instruction is in.
+    pub seq: InstrSeqId,
+
+    /// The position of the instruction in its sequence.
+    pub index: usize,
+
+    /// Why the instruction may be nondeterministic.
+    pub cause: Cause,
+
+    /// The offset of the instruction in the binary the module was parsed
+    /// from, or what `ModuleConfig::on_instr_loc` gave for it, if it was
+    /// parsed rather than added afterwards.
+    pub offset: Option<u32>,
+}
+
+/// The sources of nondeterminism in a module, found with `run`.
+#[derive(Debug, Clone, Default)]
+pub struct Nondeterminism {
+    /// The instructions that may be nondeterministic, function by function in
+    /// the order of `ModuleFunctions::iter`, and in the order of their
+    /// instructions within a function.
+    pub instrs: Vec<Site>,
+
+    /// The shared memories.
+    pub shared_memories: Vec<MemoryId>,
+
+    /// The functions imported from WASI that read clocks, get random bytes,
+    /// or wait for timeouts, along with which of these they do.
+    pub imports: Vec<(ImportId, Capability)>,
+}
+
+impl Nondeterminism {
+    /// Was nothing nondeterministic found at all?
+    pub fn is_empty(&self) -> bool {
+        self.instrs.is_empty() && self.shared_memories.is_empty() && self.imports.is_empty()
+    }
+}
+
+/// Find the sources of nondeterminism in `module`.
+pub fn run(module: &Module) -> Nondeterminism {
+    let mut found = Nondeterminism::default();
+    for func in module.funcs.iter() {
+        let local = match &func.kind {
+            FunctionKind::Local(local) => local,
+            _ => continue,
+        };
+        let mut finder = Finder {
+            func: func.id(),
+            seqs: Vec::new(),
+            sites: &mut found.instrs,
+        };
+        dfs_in_order(&mut finder, local, local.entry_block());
+    }
+    found.shared_memories = module
+        .memories
+        .iter()
+        .filter(|memory| memory.shared)
+        .map(|memory| memory.id())
+        .collect();
+    found.imports = wasi::imports(module)
+        .filter_map(|import| match Capability::of(&import.name)? {
+            capability @ Capability::Clock
+            | capability @ Capability::Random
+            | capability @ Capability::Poll => Some((import.id(), capability)),
+            _ => None,
+        })
+        .collect();
+    found
+}
+
+/// Why `instr` may be nondeterministic, if it may be.
+fn cause(instr: &Instr) -> Option<Cause> {
+    Some(match instr {
+        _ if nan_type(instr).is_some() => Cause::NanBits,
+        Instr::MemoryGrow(_) | Instr::TableGrow(_) => Cause::Growth,
+        Instr::AtomicWait(_) => Cause::AtomicWait,
+        _ => return None,
+    })
+}
+
+/// The type of the values that a float instruction that may produce a NaN
+/// produces.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum NanType {
+    F32,
+    F64,
+    F32x4,
+    F64x2,
+}
+
+/// The type of the values that `instr` produces, if it is a float
+/// instruction that may produce a NaN.
+///
+/// Other float instructions either only operate on the sign bit, like
+/// `f32.neg`, or produce a number, like `f32.convert_i32_s`.
+pub(crate) fn nan_type(instr: &Instr) -> Option<NanType> {
+    use BinaryOp::*;
+    use UnaryOp::*;
+    Some(match instr {
+        Instr::Binop(Binop {
+            op: F32Add | F32Sub | F32Mul | F32Div | F32Min | F32Max,
+        })
+        | Instr::Unop(Unop {
+            op: F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt | F32DemoteF64,
+        }) => NanType::F32,
+        Instr::Binop(Binop {
+            op: F64Add | F64Sub | F64Mul | F64Div | F64Min | F64Max,
+        })
+        | Instr::Unop(Unop {
+            op: F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt | F64PromoteF32,
+        }) => NanType::F64,
+        Instr::Binop(Binop {
+            op: F32x4Add | F32x4Sub | F32x4Mul | F32x4Div | F32x4Min | F32x4Max,
+        })
+        | Instr::Unop(Unop { op: F32x4Sqrt }) => NanType::F32x4,
+        Instr::Binop(Binop {
+            op: F64x2Add | F64x2Sub | F64x2Mul | F64x2Div | F64x2Min | F64x2Max,
+        })
+        | Instr::Unop(Unop { op: F64x2Sqrt }) => NanType::F64x2,
+        _ => return None,
+    })
+}
+
+struct Finder<'a> {
+    func: FunctionId,
+    // The sequences being visited, along with the position of the next
+    // instruction in each.
+    seqs: Vec<(InstrSeqId, usize)>,
+    sites: &'a mut Vec<Site>,
+}
+
+impl<'instr> Visitor<'instr> for Finder<'_> {
+    fn start_instr_seq(&mut self, seq: &'instr InstrSeq) {
+        self.seqs.push((seq.id(), 0));
+    }
+
+    fn end_instr_seq(&mut self, _: &'instr InstrSeq) {
+        self.seqs.pop();
+    }
+
+    fn visit_instr(&mut self, instr: &'instr Instr, loc: &'instr InstrLocId) {
+        let func = self.func;
+        let (seq, index) = self.seqs.last_mut().unwrap();
+        let site = cause(instr).map(|cause| Site {
+            func,
+            seq: *seq,
+            index: *index,
+            cause,
+            offset: if loc.is_default() {
+                None
+            } else {
+                Some(loc.data())
+            },
+        });
+        *index += 1;
+        self.sites.extend(site);
+    }
+}
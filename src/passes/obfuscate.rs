@@ -0,0 +1,323 @@
+//! Obfuscate modules before shipping them.
+//!
+//! This bundles the usual obfuscations for proprietary wasm into one pass:
+//!
+//! * stripping the "name" section, or scrambling the names in it,
+//! * shuffling the order of local functions, and with it their indices,
+//! * renaming exports, and
+//! * inserting opaque predicates, branches that are never taken but that
+//!   look like they could be, at the start of every function.
+//!
+//! Everything random is derived from `Options::seed`, so the same seed
+//! obfuscates the same module the same way. Renamed exports and scrambled
+//! function names are recorded in a `Mapping`, which can be written out next
+//! to the obfuscated module to make sense of stack traces and of its exports
+//! later.
+
+use crate::ir::{BinaryOp, Binop, Const, GlobalGet, Value};
+use crate::{FunctionId, FunctionKind, GlobalId, InitExpr, LocalId, Module, ValType};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+/// What to do with the names in the "name" section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Names {
+    /// Leave them as they are.
+    Keep,
+    /// Remove them.
+    Strip,
+    /// Replace them with meaningless ones.
+    Scramble,
+}
+
+/// Which obfuscations to apply.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The seed for all random decisions.
+    pub seed: u64,
+
+    /// What to do with the names of the module, its functions, locals,
+    /// globals and types.
+    pub names: Names,
+
+    /// Shuffle the order of local functions.
+    pub shuffle_functions: bool,
+
+    /// Rename exports, except for those listed in `keep_exports`.
+    pub rename_exports: bool,
+
+    /// Exports that keep their names, like the ones that the host looks up.
+    pub keep_exports: Vec<String>,
+
+    /// Insert opaque predicates at the start of every local function.
+    pub opaque_predicates: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            seed: 0,
+            names: Names::Strip,
+            shuffle_functions: true,
+            rename_exports: false,
+            keep_exports: Vec::new(),
+            opaque_predicates: false,
+        }
+    }
+}
+
+/// The original names of renamed exports and scrambled functions.
+///
+/// Its `Display` implementation writes it in a line-based format, one
+/// `export "new" "original"` or `function "new" "original"` line per name.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    /// The original names of exports, by their new names.
+    pub exports: BTreeMap<String, String>,
+
+    /// The original names of functions, by their scrambled names.
+    pub functions: BTreeMap<String, String>,
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (new, original) in &self.exports {
+            writeln!(f, "export {:?} {:?}", new, original)?;
+        }
+        for (new, original) in &self.functions {
+            writeln!(f, "function {:?} {:?}", new, original)?;
+        }
+        Ok(())
+    }
+}
+
+/// Obfuscate `module` according to `options`, returning the original names
+/// of everything that was renamed.
+///
+/// Renaming exports changes the interface of the module, so every export
+/// that something outside the module looks up by name must be listed in
+/// `Options::keep_exports`. Imports are never renamed.
+pub fn run(module: &mut Module, options: &Options) -> Mapping {
+    let mut rng = Rng(options.seed);
+    let mut mapping = Mapping::default();
+    let mut names = NameGenerator::default();
+
+    match options.names {
+        Names::Keep => {}
+        Names::Strip => strip_names(module),
+        Names::Scramble => scramble_names(module, &mut rng, &mut names, &mut mapping),
+    }
+
+    if options.rename_exports {
+        let mut exports = module
+            .exports
+            .iter()
+            .filter(|e| !options.keep_exports.contains(&e.name))
+            .map(|e| e.id())
+            .collect::<Vec<_>>();
+        // Don't let the new names give away the original order.
+        rng.shuffle(&mut exports);
+        for name in &options.keep_exports {
+            names.reserve(name);
+        }
+        for id in exports {
+            let export = module.exports.get_mut(id);
+            let name = names.next(&mut rng);
+            let original = std::mem::replace(&mut export.name, name.clone());
+            mapping.exports.insert(name, original);
+        }
+    }
+
+    if options.opaque_predicates {
+        insert_opaque_predicates(module, &mut rng);
+    }
+
+    if options.shuffle_functions {
+        let mut order = local_functions(module);
+        rng.shuffle(&mut order);
+        module.funcs.set_emit_order(order);
+    }
+
+    mapping
+}
+
+fn local_functions(module: &Module) -> Vec<FunctionId> {
+    module.funcs.iter_local().map(|(id, _)| id).collect()
+}
+
+fn locals(module: &Module) -> Vec<LocalId> {
+    module.locals.iter().map(|l| l.id()).collect()
+}
+
+fn globals(module: &Module) -> Vec<GlobalId> {
+    module.globals.iter().map(|g| g.id()).collect()
+}
+
+fn strip_names(module: &mut Module) {
+    strip_names_of_types_and_module(module);
+    for func in module.funcs.iter_mut() {
+        func.name = None;
+    }
+    for local in locals(module) {
+        module.locals.get_mut(local).name = None;
+    }
+    for global in globals(module) {
+        module.globals.get_mut(global).name = None;
+    }
+}
+
+fn scramble_names(
+    module: &mut Module,
+    rng: &mut Rng,
+    names: &mut NameGenerator,
+    mapping: &mut Mapping,
+) {
+    // Types don't have names in the "name" section, and the module's own
+    // name is of no use once scrambled.
+    strip_names_of_types_and_module(module);
+
+    let mut funcs = module
+        .funcs
+        .iter()
+        .filter(|f| f.name.is_some())
+        .map(|f| f.id())
+        .collect::<Vec<_>>();
+    rng.shuffle(&mut funcs);
+    for id in funcs {
+        let name = names.next(rng);
+        let func = module.funcs.get_mut(id);
+        if let Some(original) = func.name.replace(name.clone()) {
+            mapping.functions.insert(name, original);
+        }
+    }
+    for local in locals(module) {
+        let local = module.locals.get_mut(local);
+        if local.name.is_some() {
+            local.name = Some(names.next(rng));
+        }
+    }
+    for global in globals(module) {
+        let global = module.globals.get_mut(global);
+        if global.name.is_some() {
+            global.name = Some(names.next(rng));
+        }
+    }
+}
+
+fn strip_names_of_types_and_module(module: &mut Module) {
+    module.name = None;
+    let types = module.types.iter().map(|t| t.id()).collect::<Vec<_>>();
+    for ty in types {
+        module.types.get_mut(ty).name = None;
+    }
+}
+
+/// Insert `if (x * (x + 1)) & 1 then unreachable end` at the start of every
+/// local function, where `x` is a mutable global.
+///
+/// The product of two consecutive integers is always even, wrapping around
+/// or not, so the branch is never taken. Since the global is mutable, its
+/// value can't be assumed by looking at its initializer alone.
+fn insert_opaque_predicates(module: &mut Module, rng: &mut Rng) {
+    let x = module.globals.add_local(
+        ValType::I32,
+        true,
+        InitExpr::Value(Value::I32(rng.next() as i32)),
+    );
+    for id in local_functions(module) {
+        let func = match &mut module.funcs.get_mut(id).kind {
+            FunctionKind::Local(func) => func,
+            _ => unreachable!(),
+        };
+        let entry = func.entry_block();
+        let mut body = func.builder_mut().instr_seq(entry);
+        body.instr_at(0, GlobalGet { global: x })
+            .instr_at(1, GlobalGet { global: x })
+            .instr_at(
+                2,
+                Const {
+                    value: Value::I32(1),
+                },
+            )
+            .instr_at(
+                3,
+                Binop {
+                    op: BinaryOp::I32Add,
+                },
+            )
+            .instr_at(
+                4,
+                Binop {
+                    op: BinaryOp::I32Mul,
+                },
+            )
+            .instr_at(
+                5,
+                Const {
+                    value: Value::I32(1),
+                },
+            )
+            .instr_at(
+                6,
+                Binop {
+                    op: BinaryOp::I32And,
+                },
+            )
+            .if_else_at(
+                7,
+                None,
+                |then| {
+                    then.unreachable();
+                },
+                |_| {},
+            );
+    }
+}
+
+/// Generates short, unique names.
+#[derive(Default)]
+struct NameGenerator {
+    used: HashSet<String>,
+}
+
+impl NameGenerator {
+    fn reserve(&mut self, name: &str) {
+        self.used.insert(name.to_string());
+    }
+
+    fn next(&mut self, rng: &mut Rng) -> String {
+        // Grow the names as fewer of them are left, so that this always stops.
+        let mut len = 1;
+        loop {
+            for _ in 0..8 {
+                let name = (0..len)
+                    .map(|_| (b'a' + (rng.next() % 26) as u8) as char)
+                    .collect::<String>();
+                if self.used.insert(name.clone()) {
+                    return name;
+                }
+            }
+            len += 1;
+        }
+    }
+}
+
+/// A small splitmix64 generator; obfuscation doesn't need anything stronger.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
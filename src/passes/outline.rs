@@ -0,0 +1,349 @@
+//! Outline repeated instruction sequences into helper functions.
+//!
+//! This is the dual of inlining, and is particularly effective on generated
+//! or macro-heavy code, which tends to repeat the same few sequences many
+//! times. The pass looks for straight-line sequences of instructions that
+//! appear several times, in any functions, and replaces each of them with a
+//! call to a new helper function holding the sequence.
+//!
+//! Only sequences that don't consume operands pushed before them are
+//! outlined, and they may read locals but not write them: the locals that a
+//! sequence reads become parameters of the helper, and whatever the sequence
+//! leaves on the stack becomes its results. Control flow instructions, and
+//! instructions whose results this pass doesn't know the types of, are never
+//! part of an outlined sequence.
+//!
+//! Outlining makes code smaller, but calls aren't free, so this is best done
+//! for size-optimized builds, or on cold code.
+
+use crate::ir::*;
+use crate::passes::cse::{binop_result, unop_result};
+use crate::{FunctionBuilder, FunctionId, FunctionKind, LocalFunction, Module, ValType};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+
+/// How to outline sequences.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The longest sequences to consider, in instructions.
+    pub max_len: usize,
+
+    /// Stop after creating this many helper functions.
+    pub max_helpers: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            max_len: 32,
+            max_helpers: 1000,
+        }
+    }
+}
+
+/// Outline repeated instruction sequences in the local functions of
+/// `module`, as long as doing so reduces the total number of instructions.
+///
+/// Sequences that save the most instructions are outlined first. Returns the
+/// helper functions, which are named `outlined_<n>`, in the order they were
+/// created.
+pub fn run(module: &mut Module, options: &Options) -> Vec<FunctionId> {
+    let mut helpers = Vec::new();
+    while helpers.len() < options.max_helpers {
+        let candidates = candidates(module, options.max_len);
+        let best = candidates
+            .into_values()
+            .filter_map(|sites| {
+                let sites = non_overlapping(sites);
+                let savings = savings(&sites)?;
+                Some((savings, sites))
+            })
+            .max_by_key(|(savings, _)| *savings);
+        let sites = match best {
+            Some((_, sites)) => sites,
+            None => break,
+        };
+        let helper = outline(module, &sites, helpers.len());
+        helpers.push(helper);
+    }
+    helpers
+}
+
+/// An occurrence of a sequence: `len` instructions at `start` in `seq` of
+/// `func`, reading the locals in `reads` in the order of their first use.
+#[derive(Clone)]
+struct Site {
+    func: FunctionId,
+    seq: InstrSeqId,
+    start: usize,
+    len: usize,
+    reads: Vec<LocalId>,
+    results: Vec<ValType>,
+}
+
+/// All sequences that could be outlined and appear more than once, by a key
+/// that is the same for sequences that are the same up to which locals they
+/// read.
+fn candidates(module: &Module, max_len: usize) -> BTreeMap<Vec<u32>, Vec<Site>> {
+    // Keeping every sequence around takes too much memory for big modules,
+    // so first count them by the hashes of their keys.
+    let mut pieces = HashMap::new();
+    let mut counts = HashMap::<u64, usize>::new();
+    windows(module, max_len, &mut pieces, |key, _| {
+        *counts.entry(hash(key)).or_insert(0) += 1;
+    });
+    let mut candidates = BTreeMap::<Vec<u32>, Vec<Site>>::new();
+    windows(module, max_len, &mut pieces, |key, site| {
+        if counts[&hash(key)] > 1 {
+            candidates.entry(key.to_vec()).or_default().push(site());
+        }
+    });
+    candidates
+}
+
+fn hash(key: &[u32]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Call `visit` with the key of every sequence of at least two instructions
+/// that could be outlined, and a function making its `Site`. The parts of
+/// keys are interned in `pieces`.
+fn windows(
+    module: &Module,
+    max_len: usize,
+    pieces: &mut HashMap<String, u32>,
+    mut visit: impl FnMut(&[u32], &dyn Fn() -> Site),
+) {
+    for (id, func) in module.funcs.iter_local() {
+        for seq in seqs(func) {
+            let instrs = &func.block(seq).instrs;
+            for start in 0..instrs.len() {
+                let mut key = Vec::new();
+                let mut reads = Vec::new();
+                let mut stack = Vec::new();
+                for (len, (instr, _)) in instrs[start..].iter().take(max_len).enumerate() {
+                    let (pops, pushes) = match effect(module, &stack, instr) {
+                        Some(effect) => effect,
+                        None => break,
+                    };
+                    if pops > stack.len() {
+                        break;
+                    }
+                    stack.truncate(stack.len() - pops);
+                    stack.extend(pushes);
+                    let piece = piece(module, instr, &mut reads);
+                    let next = pieces.len() as u32;
+                    key.push(*pieces.entry(piece).or_insert(next));
+                    if len > 0 {
+                        visit(&key, &|| Site {
+                            func: id,
+                            seq,
+                            start,
+                            len: len + 1,
+                            reads: reads.clone(),
+                            results: stack.clone(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// All instruction sequences of `func`.
+fn seqs(func: &LocalFunction) -> Vec<InstrSeqId> {
+    let mut seqs = vec![func.entry_block()];
+    let mut i = 0;
+    while i < seqs.len() {
+        for (instr, _) in func.block(seqs[i]).instrs.iter() {
+            match instr {
+                Instr::Block(Block { seq }) | Instr::Loop(Loop { seq }) => seqs.push(*seq),
+                Instr::IfElse(IfElse {
+                    consequent,
+                    alternative,
+                }) => {
+                    seqs.push(*consequent);
+                    seqs.push(*alternative);
+                }
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    seqs
+}
+
+/// How many operands `instr` pops off of `stack`, and the types of the values
+/// that it pushes, or `None` if it can't be outlined.
+fn effect(module: &Module, stack: &[ValType], instr: &Instr) -> Option<(usize, Vec<ValType>)> {
+    Some(match instr {
+        Instr::Const(c) => (0, vec![value_type(c.value)]),
+        Instr::LocalGet(l) => (0, vec![module.locals.get(l.local).ty()]),
+        Instr::GlobalGet(g) => (0, vec![module.globals.get(g.global).ty]),
+        Instr::GlobalSet(_) | Instr::Drop(_) => (1, vec![]),
+        Instr::Unop(u) => (1, vec![unop_result(u.op)?]),
+        Instr::Binop(b) => (2, vec![binop_result(b.op).or_else(|| division(b.op))?]),
+        Instr::Select(_) => (3, vec![*stack.iter().rev().nth(1)?]),
+        Instr::Load(l) => (1, vec![load_result(l.kind)]),
+        Instr::Store(_) => (2, vec![]),
+        Instr::Call(c) => {
+            let ty = module.funcs.get(c.func).ty();
+            let (params, results) = module.types.params_results(ty);
+            (params.len(), results.to_vec())
+        }
+        _ => return None,
+    })
+}
+
+/// The result type of integer divisions, which trap, but that doesn't matter
+/// for outlining.
+fn division(op: BinaryOp) -> Option<ValType> {
+    use BinaryOp::*;
+    match op {
+        I32DivS | I32DivU | I32RemS | I32RemU => Some(ValType::I32),
+        I64DivS | I64DivU | I64RemS | I64RemU => Some(ValType::I64),
+        _ => None,
+    }
+}
+
+fn load_result(kind: LoadKind) -> ValType {
+    match kind {
+        LoadKind::I32 { .. } | LoadKind::I32_8 { .. } | LoadKind::I32_16 { .. } => ValType::I32,
+        LoadKind::I64 { .. }
+        | LoadKind::I64_8 { .. }
+        | LoadKind::I64_16 { .. }
+        | LoadKind::I64_32 { .. } => ValType::I64,
+        LoadKind::F32 => ValType::F32,
+        LoadKind::F64 => ValType::F64,
+        LoadKind::V128 => ValType::V128,
+    }
+}
+
+fn value_type(value: Value) -> ValType {
+    match value {
+        Value::I32(_) => ValType::I32,
+        Value::I64(_) => ValType::I64,
+        Value::F32(_) => ValType::F32,
+        Value::F64(_) => ValType::F64,
+        Value::V128(_) => ValType::V128,
+    }
+}
+
+/// The part of a sequence's key for `instr`. Locals are numbered in the order
+/// they are first read, and appended to `reads`.
+fn piece(module: &Module, instr: &Instr, reads: &mut Vec<LocalId>) -> String {
+    match instr {
+        Instr::LocalGet(l) => {
+            let param = match reads.iter().position(|local| *local == l.local) {
+                Some(param) => param,
+                None => {
+                    reads.push(l.local);
+                    reads.len() - 1
+                }
+            };
+            format!("local.get {} {}", param, module.locals.get(l.local).ty())
+        }
+        // Compare constants by their bits, since NaNs with different bits
+        // look the same.
+        Instr::Const(c) => match c.value {
+            Value::F32(x) => format!("f32.const {:#x}", x.to_bits()),
+            Value::F64(x) => format!("f64.const {:#x}", x.to_bits()),
+            value => format!("{:?}", value),
+        },
+        instr => format!("{:?}", instr),
+    }
+}
+
+/// The first of `sites` and the later ones that don't overlap earlier ones.
+fn non_overlapping(sites: Vec<Site>) -> Vec<Site> {
+    let mut kept: Vec<Site> = Vec::with_capacity(sites.len());
+    for site in sites {
+        let overlaps = kept.iter().any(|k| {
+            k.func == site.func
+                && k.seq == site.seq
+                && k.start < site.start + site.len
+                && site.start < k.start + k.len
+        });
+        if !overlaps {
+            kept.push(site);
+        }
+    }
+    kept
+}
+
+/// How many instructions outlining `sites` saves, if any.
+///
+/// Each site becomes a `local.get` for each local it reads and a `call`, and
+/// the helper holds the sequence and its `end`.
+fn savings(sites: &[Site]) -> Option<usize> {
+    let site = sites.first()?;
+    if sites.len() < 2 {
+        return None;
+    }
+    let before = sites.len() * site.len;
+    let after = sites.len() * (site.reads.len() + 1) + site.len + 1;
+    before.checked_sub(after).filter(|savings| *savings > 0)
+}
+
+/// Move the sequence at `sites` into a new helper, and call it from each of
+/// them instead.
+fn outline(module: &mut Module, sites: &[Site], n: usize) -> FunctionId {
+    let site = &sites[0];
+    let params = site
+        .reads
+        .iter()
+        .map(|local| module.locals.get(*local).ty())
+        .collect::<Vec<_>>();
+    let args = params
+        .iter()
+        .map(|ty| module.locals.add(*ty))
+        .collect::<Vec<_>>();
+    let renamed = site
+        .reads
+        .iter()
+        .copied()
+        .zip(args.iter().copied())
+        .collect::<HashMap<_, _>>();
+    let instrs = match &module.funcs.get(site.func).kind {
+        FunctionKind::Local(func) => {
+            func.block(site.seq).instrs[site.start..site.start + site.len].to_vec()
+        }
+        _ => unreachable!(),
+    };
+
+    let mut builder = FunctionBuilder::new(&mut module.types, &params, &site.results);
+    builder.name(format!("outlined_{}", n));
+    let mut body = builder.func_body();
+    for (mut instr, _) in instrs {
+        if let Instr::LocalGet(get) = &mut instr {
+            get.local = renamed[&get.local];
+        }
+        body.instr(instr);
+    }
+    let helper = builder.finish(args, &mut module.funcs);
+
+    // Replace later sites of each sequence first, so that the positions of
+    // earlier ones stay the same.
+    let mut sites = sites.to_vec();
+    sites.sort_by_key(|site| (site.func, site.seq, std::cmp::Reverse(site.start)));
+    for site in sites {
+        let func = match &mut module.funcs.get_mut(site.func).kind {
+            FunctionKind::Local(func) => func,
+            _ => unreachable!(),
+        };
+        let seq = func.block_mut(site.seq);
+        let loc = seq.instrs[site.start].1;
+        let call = site
+            .reads
+            .iter()
+            .map(|&local| LocalGet { local }.into())
+            .chain(Some(Call { func: helper }.into()))
+            .map(|instr: Instr| (instr, loc))
+            .collect::<Vec<_>>();
+        seq.instrs.splice(site.start..site.start + site.len, call);
+    }
+    helper
+}
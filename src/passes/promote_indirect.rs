@@ -0,0 +1,363 @@
+//! Promote hot indirect calls to direct calls, according to a profile.
+//!
+//! Engines can inline and specialize direct calls, but an indirect call has
+//! to load its target from a table and check its signature first. When a
+//! profile shows that a `call_indirect` site usually calls the same few
+//! functions, this pass guards direct calls to them with comparisons of the
+//! table index:
+//!
+//! ```text
+//! if (index == K) call $f else call_indirect
+//! ```
+//!
+//! This is only correct when table slot `K` always holds `$f`, so only calls
+//! through tables whose contents are fixed at instantiation are promoted:
+//! tables that are local and not exported, whose element segments are at
+//! constant offsets, and that no instruction writes to.
+
+use crate::ir::*;
+use crate::{ExportItem, FunctionId, FunctionKind, IndexType, InstrSeqBuilder, LocalFunction};
+use crate::{LocalId, Module, TableId, TableKind, ValType};
+use std::collections::{HashMap, HashSet};
+
+/// Which functions each `call_indirect` site of a module called, and how
+/// often.
+///
+/// Call sites are identified by their caller, and by the position of the
+/// `call_indirect` among the caller's `call_indirect`s, counting from zero in
+/// the order they appear in the code section. Callers are identified like in
+/// `reorder::Profile`: either by name, or by their index in the function
+/// index space of the profiled module. Targets are identified by the table
+/// index that the call site was called with.
+#[derive(Debug, Default, Clone)]
+pub struct Profile {
+    by_name: HashMap<String, Sites>,
+    by_index: HashMap<u32, Sites>,
+}
+
+/// The number of calls of each site with each table index.
+type Sites = HashMap<u32, HashMap<u64, u64>>;
+
+impl Profile {
+    /// Create a new, empty profile.
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    /// Record `count` calls of table index `index` by the `site`th
+    /// `call_indirect` of the function named `caller`.
+    pub fn add_name(
+        &mut self,
+        caller: impl Into<String>,
+        site: u32,
+        index: u64,
+        count: u64,
+    ) -> &mut Profile {
+        add(
+            self.by_name.entry(caller.into()).or_default(),
+            site,
+            index,
+            count,
+        );
+        self
+    }
+
+    /// Record `count` calls of table index `index` by the `site`th
+    /// `call_indirect` of the function at `caller` in the function index
+    /// space.
+    pub fn add_index(&mut self, caller: u32, site: u32, index: u64, count: u64) -> &mut Profile {
+        add(self.by_index.entry(caller).or_default(), site, index, count);
+        self
+    }
+
+    fn counts(&self, module: &Module, caller: FunctionId, site: u32) -> HashMap<u64, u64> {
+        let by_name = module
+            .funcs
+            .get(caller)
+            .name
+            .as_ref()
+            .and_then(|name| self.by_name.get(name));
+        let by_index = self.by_index.get(&(caller.index() as u32));
+        let mut counts = HashMap::new();
+        for sites in by_name.into_iter().chain(by_index) {
+            for (index, count) in sites.get(&site).into_iter().flatten() {
+                *counts.entry(*index).or_insert(0) += count;
+            }
+        }
+        counts
+    }
+}
+
+fn add(sites: &mut Sites, site: u32, index: u64, count: u64) {
+    *sites.entry(site).or_default().entry(index).or_insert(0) += count;
+}
+
+/// Which calls to promote.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Promote at most this many of the most frequent targets of each call
+    /// site.
+    pub max_targets: usize,
+
+    /// Only promote targets that were called at least this many times from a
+    /// call site.
+    pub min_count: u64,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            max_targets: 2,
+            min_count: 100,
+        }
+    }
+}
+
+/// Promote the hot targets of the `call_indirect`s in `module`, according to
+/// `profile` and `options`.
+///
+/// Targets whose table index doesn't hold a function of the type that the
+/// call site expects are left alone, since calling them traps. Returns the
+/// number of promoted call sites.
+pub fn run(module: &mut Module, profile: &Profile, options: &Options) -> usize {
+    let fixed = fixed_tables(module);
+    let mut promotions = Vec::new();
+    for (caller, func) in module.funcs.iter_local() {
+        let mut sites = Vec::new();
+        call_sites(func, func.entry_block(), &mut sites);
+        for (site, (seq, position, call)) in sites.into_iter().enumerate() {
+            if !fixed.contains(&call.table) {
+                continue;
+            }
+            let targets = hot_targets(module, &call, profile.counts(module, caller, site as u32));
+            let targets = targets
+                .into_iter()
+                .filter(|(_, count)| *count >= options.min_count)
+                .take(options.max_targets)
+                .map(|(target, _)| target)
+                .collect::<Vec<_>>();
+            if !targets.is_empty() {
+                promotions.push((caller, seq, position, call, targets));
+            }
+        }
+    }
+
+    // Replace later sites of each sequence first, so that the positions of
+    // earlier ones stay the same.
+    promotions.sort_by_key(|(caller, seq, position, _, _)| (*caller, *seq, *position));
+    let promoted = promotions.len();
+    for (caller, seq, position, call, targets) in promotions.into_iter().rev() {
+        promote(module, caller, seq, position, call, &targets);
+    }
+    promoted
+}
+
+/// The tables whose contents are the same for the whole lifetime of the
+/// module.
+fn fixed_tables(module: &Module) -> HashSet<TableId> {
+    #[derive(Default)]
+    struct Written(HashSet<TableId>);
+
+    impl<'instr> Visitor<'instr> for Written {
+        fn visit_table_set(&mut self, instr: &TableSet) {
+            self.0.insert(instr.table);
+        }
+
+        fn visit_table_fill(&mut self, instr: &TableFill) {
+            self.0.insert(instr.table);
+        }
+
+        fn visit_table_init(&mut self, instr: &TableInit) {
+            self.0.insert(instr.table);
+        }
+
+        fn visit_table_copy(&mut self, instr: &TableCopy) {
+            self.0.insert(instr.dst);
+        }
+    }
+
+    let mut written = Written::default();
+    for (_, func) in module.funcs.iter_local() {
+        dfs_in_order(&mut written, func, func.entry_block());
+    }
+    for export in module.exports.iter() {
+        if let ExportItem::Table(table) = export.item {
+            written.0.insert(table);
+        }
+    }
+    module
+        .tables
+        .iter()
+        .filter(|table| match &table.kind {
+            TableKind::Function(elements) => {
+                table.import.is_none() && elements.relative_elements.is_empty()
+            }
+            _ => false,
+        })
+        .map(|table| table.id())
+        .filter(|table| !written.0.contains(table))
+        .collect()
+}
+
+/// The `call_indirect`s in `seq` and the sequences nested in it, in the order
+/// they appear in the code section, along with where they are.
+fn call_sites(
+    func: &LocalFunction,
+    seq: InstrSeqId,
+    sites: &mut Vec<(InstrSeqId, usize, CallIndirect)>,
+) {
+    for (position, (instr, _)) in func.block(seq).instrs.iter().enumerate() {
+        match instr {
+            Instr::CallIndirect(call) => sites.push((seq, position, call.clone())),
+            Instr::Block(Block { seq }) | Instr::Loop(Loop { seq }) => {
+                call_sites(func, *seq, sites);
+            }
+            Instr::IfElse(IfElse {
+                consequent,
+                alternative,
+            }) => {
+                call_sites(func, *consequent, sites);
+                call_sites(func, *alternative, sites);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The targets of `call` in `counts` that can be called directly, from the
+/// most to the least frequent, with their number of calls.
+fn hot_targets(
+    module: &Module,
+    call: &CallIndirect,
+    counts: HashMap<u64, u64>,
+) -> Vec<((u64, FunctionId), u64)> {
+    let elements = match &module.tables.get(call.table).kind {
+        TableKind::Function(table) => &table.elements,
+        _ => return Vec::new(),
+    };
+    let expected = module.types.params_results(call.ty);
+    let mut targets = counts
+        .into_iter()
+        .filter_map(|(index, count)| {
+            let func = (*elements.get(index as usize)?)?;
+            if module.types.params_results(module.funcs.get(func).ty()) == expected {
+                Some(((index, func), count))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    targets.sort_by_key(|&((index, _), count)| (std::cmp::Reverse(count), index));
+    targets
+}
+
+/// Replace the `call_indirect` at `position` in `seq` of `caller` with
+/// guarded direct calls to `targets`.
+fn promote(
+    module: &mut Module,
+    caller: FunctionId,
+    seq: InstrSeqId,
+    position: usize,
+    call: CallIndirect,
+    targets: &[(u64, FunctionId)],
+) {
+    let index_type = match module.tables.get(call.table).index_type {
+        IndexType::I32 => ValType::I32,
+        IndexType::I64 => ValType::I64,
+    };
+    let (params, results) = module.types.params_results(call.ty);
+    let (params, results) = (params.to_vec(), results.to_vec());
+    let ty = InstrSeqType::new(&mut module.types, &[], &results);
+    let index = module.locals.add(index_type);
+    let args = params
+        .iter()
+        .map(|ty| module.locals.add(*ty))
+        .collect::<Vec<_>>();
+
+    let func = match &mut module.funcs.get_mut(caller).kind {
+        FunctionKind::Local(func) => func,
+        _ => unreachable!(),
+    };
+    let builder = func.builder_mut();
+    let (consequent, alternative) = {
+        let mut consequent = builder.dangling_instr_seq(ty);
+        direct(&mut consequent, &args, targets[0].1);
+        let consequent = consequent.id();
+        let mut alternative = builder.dangling_instr_seq(ty);
+        let index = (index, index_type);
+        chain(&mut alternative, ty, index, &args, &targets[1..], &call);
+        (consequent, alternative.id())
+    };
+
+    let loc = func.block(seq).instrs[position].1;
+    let mut instrs = vec![Instr::from(LocalSet { local: index })];
+    instrs.extend(args.iter().rev().map(|&local| LocalSet { local }.into()));
+    instrs.push(LocalGet { local: index }.into());
+    instrs.push(index_const(index_type, targets[0].0).into());
+    instrs.push(index_eq(index_type).into());
+    instrs.push(
+        IfElse {
+            consequent,
+            alternative,
+        }
+        .into(),
+    );
+    func.block_mut(seq).instrs.splice(
+        position..position + 1,
+        instrs.into_iter().map(|instr| (instr, loc)),
+    );
+}
+
+/// Call `func` with the arguments in `args`.
+fn direct(body: &mut InstrSeqBuilder, args: &[LocalId], func: FunctionId) {
+    for &arg in args {
+        body.local_get(arg);
+    }
+    body.call(func);
+}
+
+/// Call the first of `targets` directly if `index` is its table index, and
+/// so on, and make the original indirect `call` otherwise.
+fn chain(
+    body: &mut InstrSeqBuilder,
+    ty: InstrSeqType,
+    (index, index_type): (LocalId, ValType),
+    args: &[LocalId],
+    targets: &[(u64, FunctionId)],
+    call: &CallIndirect,
+) {
+    match targets.split_first() {
+        Some((&(slot, func), rest)) => {
+            body.local_get(index)
+                .instr(index_const(index_type, slot))
+                .instr(index_eq(index_type))
+                .if_else(
+                    ty,
+                    |then| direct(then, args, func),
+                    |else_| chain(else_, ty, (index, index_type), args, rest, call),
+                );
+        }
+        None => {
+            for &arg in args {
+                body.local_get(arg);
+            }
+            body.local_get(index).instr(call.clone());
+        }
+    }
+}
+
+fn index_const(index_type: ValType, index: u64) -> Const {
+    let value = match index_type {
+        ValType::I64 => Value::I64(index as i64),
+        _ => Value::I32(index as i32),
+    };
+    Const { value }
+}
+
+fn index_eq(index_type: ValType) -> Binop {
+    let op = match index_type {
+        ValType::I64 => BinaryOp::I64Eq,
+        _ => BinaryOp::I32Eq,
+    };
+    Binop { op }
+}
@@ -0,0 +1,274 @@
+//! Record and replay the results of imported functions.
+//!
+//! This pass virtualizes a module's imported functions for deterministic
+//! replay debugging. Every use of an imported function is redirected to a
+//! generated wrapper, which either
+//!
+//! * calls the import and records its arguments and results into a ring
+//!   buffer in linear memory, in `Mode::Record`, or
+//! * reads back, in `Mode::Replay`, the results recorded by a previous run
+//!   without calling the import at all.
+//!
+//! Both modes walk the buffer identically, so a recording made by one build of
+//! a module can be replayed by another build with the same imports. Since the
+//! buffer is a ring, recording keeps only the most recent calls once it fills
+//! up; for a faithful replay it must be large enough to hold the whole run.
+//!
+//! Each value takes up as many bytes as its type, with no padding in between.
+//! A value that doesn't fit before the end of the buffer is written at its
+//! start instead.
+
+use crate::ir::*;
+use crate::{FunctionBuilder, FunctionId, FunctionKind, GlobalId, InstrSeqBuilder};
+use crate::{Import, MemoryId, Module, Result, TableKind, ValType};
+use anyhow::bail;
+use std::collections::{HashMap, HashSet};
+
+/// Whether the generated wrappers record or replay calls to imports.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Call the imports, and record their arguments and results.
+    Record,
+    /// Don't call the imports, and replay their recorded results instead.
+    Replay,
+}
+
+/// Where and how to record or replay calls to imported functions.
+#[derive(Debug, Copy, Clone)]
+pub struct Config {
+    /// Whether to record or replay.
+    pub mode: Mode,
+    /// The memory holding the ring buffer.
+    pub memory: MemoryId,
+    /// The address of the start of the ring buffer.
+    pub base: u32,
+    /// The size of the ring buffer, in bytes.
+    pub capacity: u32,
+    /// A mutable `i32` global holding the current offset into the ring
+    /// buffer.
+    ///
+    /// It should start out as zero, both when recording and when replaying.
+    pub cursor: GlobalId,
+}
+
+/// Redirect all uses of imported functions in `module` through recording or
+/// replaying wrappers, as described by `config`.
+///
+/// Returns a map from each imported function to its wrapper.
+pub fn run(module: &mut Module, config: &Config) -> Result<HashMap<FunctionId, FunctionId>> {
+    let cursor = module.globals.get(config.cursor);
+    if cursor.ty != ValType::I32 || !cursor.mutable {
+        bail!("the ring buffer cursor must be a mutable `i32` global");
+    }
+    if config.capacity < 16 {
+        bail!("the ring buffer must have room for at least 16 bytes");
+    }
+
+    let imports = module
+        .funcs
+        .iter()
+        .filter_map(|f| match &f.kind {
+            FunctionKind::Import(i) => Some((f.id(), i.ty, i.import)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut wrappers = HashMap::new();
+    for (import, ty, import_id) in imports {
+        let (params, results) = module.types.params_results(ty);
+        let (params, results) = (params.to_vec(), results.to_vec());
+        if params.iter().chain(&results).any(|t| t.is_reference()) {
+            bail!(
+                "cannot record or replay `{}` since references can't be \
+                 stored in linear memory",
+                module.imports.get(import_id).name
+            );
+        }
+
+        let args = params
+            .iter()
+            .map(|ty| module.locals.add(*ty))
+            .collect::<Vec<_>>();
+        let rets = results
+            .iter()
+            .map(|ty| module.locals.add(*ty))
+            .collect::<Vec<_>>();
+
+        let mut builder = FunctionBuilder::new(&mut module.types, &params, &results);
+        let mut body = builder.func_body();
+        match config.mode {
+            Mode::Record => {
+                for (&arg, &ty) in args.iter().zip(&params) {
+                    config.write(&mut body, arg, ty);
+                }
+                for &arg in args.iter() {
+                    body.local_get(arg);
+                }
+                body.call(import);
+                for &ret in rets.iter().rev() {
+                    body.local_set(ret);
+                }
+                for (&ret, &ty) in rets.iter().zip(&results) {
+                    config.write(&mut body, ret, ty);
+                }
+                for &ret in rets.iter() {
+                    body.local_get(ret);
+                }
+            }
+            Mode::Replay => {
+                for &ty in params.iter() {
+                    config.skip(&mut body, ty);
+                }
+                for &ty in results.iter() {
+                    config.read(&mut body, ty);
+                }
+            }
+        }
+        let wrapper = builder.finish(args, &mut module.funcs);
+
+        let mode = match config.mode {
+            Mode::Record => "record",
+            Mode::Replay => "replay",
+        };
+        let Import {
+            module: m, name, ..
+        } = module.imports.get(import_id);
+        module.funcs.get_mut(wrapper).name = Some(format!("{}:{}.{}", mode, m, name));
+        wrappers.insert(import, wrapper);
+    }
+
+    redirect(module, &wrappers, &HashSet::new());
+    Ok(wrappers)
+}
+
+impl Config {
+    /// Wrap the cursor around to the start of the buffer if a value of type
+    /// `ty` doesn't fit before its end, then push the cursor.
+    fn wrap_cursor(&self, body: &mut InstrSeqBuilder, ty: ValType) {
+        body.global_get(self.cursor)
+            .i32_const(size_of(ty) as i32)
+            .binop(BinaryOp::I32Add)
+            .i32_const(self.capacity as i32)
+            .binop(BinaryOp::I32GtU)
+            .if_else(
+                None,
+                |then| {
+                    then.i32_const(0).global_set(self.cursor);
+                },
+                |_| {},
+            )
+            .global_get(self.cursor);
+    }
+
+    fn advance_cursor(&self, body: &mut InstrSeqBuilder, ty: ValType) {
+        body.global_get(self.cursor)
+            .i32_const(size_of(ty) as i32)
+            .binop(BinaryOp::I32Add)
+            .global_set(self.cursor);
+    }
+
+    fn memarg(&self) -> MemArg {
+        MemArg {
+            align: 1,
+            offset: self.base,
+            encoding: None,
+        }
+    }
+
+    fn write(&self, body: &mut InstrSeqBuilder, local: LocalId, ty: ValType) {
+        let kind = match ty {
+            ValType::I32 => StoreKind::I32 { atomic: false },
+            ValType::I64 => StoreKind::I64 { atomic: false },
+            ValType::F32 => StoreKind::F32,
+            ValType::F64 => StoreKind::F64,
+            ValType::V128 => StoreKind::V128,
+            ValType::Anyref => unreachable!(),
+            #[cfg(feature = "unstable")]
+            ValType::Cont { .. } => unreachable!(),
+        };
+        self.wrap_cursor(body, ty);
+        body.local_get(local)
+            .store(self.memory, kind, self.memarg());
+        self.advance_cursor(body, ty);
+    }
+
+    fn read(&self, body: &mut InstrSeqBuilder, ty: ValType) {
+        let kind = match ty {
+            ValType::I32 => LoadKind::I32 { atomic: false },
+            ValType::I64 => LoadKind::I64 { atomic: false },
+            ValType::F32 => LoadKind::F32,
+            ValType::F64 => LoadKind::F64,
+            ValType::V128 => LoadKind::V128,
+            ValType::Anyref => unreachable!(),
+            #[cfg(feature = "unstable")]
+            ValType::Cont { .. } => unreachable!(),
+        };
+        self.wrap_cursor(body, ty);
+        body.load(self.memory, kind, self.memarg());
+        self.advance_cursor(body, ty);
+    }
+
+    fn skip(&self, body: &mut InstrSeqBuilder, ty: ValType) {
+        self.wrap_cursor(body, ty);
+        body.drop();
+        self.advance_cursor(body, ty);
+    }
+}
+
+fn size_of(ty: ValType) -> u32 {
+    match ty {
+        ValType::I32 | ValType::F32 => 4,
+        ValType::I64 | ValType::F64 => 8,
+        ValType::V128 => 16,
+        ValType::Anyref => unreachable!(),
+        #[cfg(feature = "unstable")]
+        ValType::Cont { .. } => unreachable!(),
+    }
+}
+
+/// Replace every use of a wrapped function in function bodies and element
+/// segments, other than by its wrapper or in the bodies of the functions in
+/// `except`, with its wrapper.
+pub(crate) fn redirect(
+    module: &mut Module,
+    wrappers: &HashMap<FunctionId, FunctionId>,
+    except: &HashSet<FunctionId>,
+) {
+    struct Redirect<'a>(&'a HashMap<FunctionId, FunctionId>);
+
+    impl VisitorMut for Redirect<'_> {
+        fn visit_function_id_mut(&mut self, func: &mut FunctionId) {
+            if let Some(wrapper) = self.0.get(func) {
+                *func = *wrapper;
+            }
+        }
+    }
+
+    let redirect_list = |list: &mut Vec<Option<FunctionId>>| {
+        for func in list.iter_mut().flatten() {
+            if let Some(wrapper) = wrappers.get(func) {
+                *func = *wrapper;
+            }
+        }
+    };
+
+    let is_wrapper = wrappers.values().collect::<HashSet<_>>();
+    for (id, func) in module.funcs.iter_local_mut() {
+        if is_wrapper.contains(&id) || except.contains(&id) {
+            continue;
+        }
+        let entry = func.entry_block();
+        dfs_pre_order_mut(&mut Redirect(wrappers), func, entry);
+    }
+    for table in module.tables.iter_mut() {
+        if let TableKind::Function(table) = &mut table.kind {
+            redirect_list(&mut table.elements);
+            for (_, list) in table.relative_elements.iter_mut() {
+                redirect_list(list);
+            }
+        }
+    }
+    for elem in module.elements.iter_mut() {
+        redirect_list(&mut elem.members);
+    }
+}
@@ -0,0 +1,116 @@
+//! Reduce a module to a smaller one that is still interesting.
+//!
+//! Like `wasm-reduce`, this repeatedly tries shrinking the module, and keeps
+//! each attempt for which a user-supplied "interestingness" test still holds,
+//! for example because an engine still crashes on the module. The attempts
+//! are, from coarsest to finest:
+//!
+//! * removing exports, the start function and custom sections, along with
+//!   everything that only they used,
+//! * replacing the bodies of functions with `unreachable`, and
+//! * halving the contents of data segments.
+//!
+//! The whole sequence repeats until none of the attempts is kept anymore.
+
+use crate::ir::*;
+use crate::passes::gc;
+use crate::{DataId, ExportId, FunctionId, Module, Result};
+use anyhow::bail;
+
+/// Reduce `module` for as long as `interesting` holds for the reductions.
+///
+/// Each attempt is made on a clone of `module`, which is cheap, and `module`
+/// is only replaced with the clone when `interesting` holds for it. Returns
+/// how many attempts were kept.
+///
+/// Fails if `interesting` doesn't hold for `module` itself.
+pub fn run<F>(module: &mut Module, mut interesting: F) -> Result<usize>
+where
+    F: FnMut(&Module) -> bool,
+{
+    if !interesting(module) {
+        bail!("the module isn't interesting to begin with");
+    }
+
+    let mut kept = 0;
+    loop {
+        let before = kept;
+        let mut attempt = |module: &mut Module, reduce: &dyn Fn(&mut Module)| {
+            let mut reduced = module.clone();
+            reduce(&mut reduced);
+            gc::run(&mut reduced);
+            if interesting(&reduced) {
+                *module = reduced;
+                kept += 1;
+                true
+            } else {
+                false
+            }
+        };
+
+        for export in module
+            .exports
+            .iter()
+            .map(|e| e.id())
+            .collect::<Vec<ExportId>>()
+        {
+            attempt(module, &|m| m.exports.delete(export));
+        }
+        if module.start.is_some() {
+            attempt(module, &|m| m.start = None);
+        }
+        // Clones have their own ids for custom sections, so go by position.
+        let mut i = 0;
+        while i < module.customs.iter().count() {
+            let removed = attempt(module, &|m| {
+                let (id, _) = m.customs.iter().nth(i).unwrap();
+                m.customs.delete(id);
+            });
+            if !removed {
+                i += 1;
+            }
+        }
+
+        let funcs = module
+            .funcs
+            .iter_local()
+            .filter(|(_, f)| !is_unreachable(f.block(f.entry_block())))
+            .map(|(id, _)| id)
+            .collect::<Vec<FunctionId>>();
+        for func in funcs {
+            // Earlier attempts may have removed the function.
+            if module.funcs.iter_local().any(|(id, _)| id == func) {
+                attempt(module, &|m| make_unreachable(m, func));
+            }
+        }
+
+        let data = module
+            .data
+            .iter()
+            .filter(|d| !d.value.is_empty())
+            .map(|d| d.id())
+            .collect::<Vec<DataId>>();
+        for data in data {
+            if module.data.iter().any(|d| d.id() == data) {
+                attempt(module, &|m| {
+                    let value = &mut m.data.get_mut(data).value;
+                    value.truncate(value.len() / 2);
+                });
+            }
+        }
+
+        if kept == before {
+            return Ok(kept);
+        }
+    }
+}
+
+fn is_unreachable(seq: &InstrSeq) -> bool {
+    matches!(&seq.instrs[..], [(Instr::Unreachable(_), _)])
+}
+
+fn make_unreachable(module: &mut Module, func: FunctionId) {
+    let func = module.funcs.get_mut(func).kind.unwrap_local_mut();
+    let entry = func.entry_block();
+    func.block_mut(entry).instrs = vec![(Unreachable {}.into(), InstrLocId::default())];
+}
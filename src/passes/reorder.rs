@@ -0,0 +1,72 @@
+//! Reorder functions according to an execution profile.
+//!
+//! Calls to functions with small indices take fewer bytes, since function
+//! indices are LEB128-encoded, and engines that compile while streaming can
+//! start running a module earlier when the functions it needs first come
+//! first. This pass moves hot functions, according to a profile gathered by
+//! some external tool, to the front of the function index space and of the
+//! code section, hottest first.
+
+use crate::{FunctionKind, Module};
+use std::collections::HashMap;
+
+/// How hot each function of a module is.
+///
+/// Functions are identified either by name, or by their index in the function
+/// index space of the profiled module. For a module that was parsed and not
+/// modified since, that is also the index of their `FunctionId`.
+#[derive(Debug, Default, Clone)]
+pub struct Profile {
+    by_name: HashMap<String, u64>,
+    by_index: HashMap<u32, u64>,
+}
+
+impl Profile {
+    /// Create a new, empty profile.
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    /// Record the hotness of the function named `name`.
+    pub fn add_name(&mut self, name: impl Into<String>, hotness: u64) -> &mut Profile {
+        self.by_name.insert(name.into(), hotness);
+        self
+    }
+
+    /// Record the hotness of the function at `index` in the function index
+    /// space.
+    pub fn add_index(&mut self, index: u32, hotness: u64) -> &mut Profile {
+        self.by_index.insert(index, hotness);
+        self
+    }
+}
+
+/// Reorder the local functions of `module` by their hotness in `profile`.
+///
+/// Functions in the profile with a non-zero hotness are emitted first, from
+/// hottest to coldest, and the rest keep their default order. A function
+/// listed both by name and by index gets the larger of the two hotnesses.
+///
+/// This replaces any order previously set with
+/// `ModuleFunctions::set_emit_order`.
+pub fn run(module: &mut Module, profile: &Profile) {
+    let mut hot = module
+        .funcs
+        .iter()
+        .filter(|f| matches!(f.kind, FunctionKind::Local(_)))
+        .filter_map(|f| {
+            let by_name = f.name.as_ref().and_then(|n| profile.by_name.get(n));
+            let by_index = profile.by_index.get(&(f.id().index() as u32));
+            let hotness = *by_name.max(by_index)?;
+            if hotness == 0 {
+                None
+            } else {
+                Some((hotness, f.id()))
+            }
+        })
+        .collect::<Vec<_>>();
+    hot.sort_by_key(|(hotness, id)| (std::cmp::Reverse(*hotness), *id));
+    module
+        .funcs
+        .set_emit_order(hot.into_iter().map(|(_, id)| id));
+}
@@ -0,0 +1,280 @@
+//! Explain why items are kept by the gc pass.
+//!
+//! The gc pass keeps everything that is transitively referenced from a root,
+//! such as an export or the start function. When trying to shrink a module
+//! it's often more useful to know *why* an item is kept than just that it
+//! is: `Retained` finds, for every kept item, a shortest chain of references
+//! leading to it from a root, like `twiggy paths` does for emitted binaries.
+
+use crate::ir::*;
+use crate::passes::Roots;
+use crate::UntypedCustomSectionId;
+use crate::{ActiveDataLocation, DataId, DataKind, ElementId, ExportId, ExportItem};
+use crate::{FunctionId, FunctionKind, GlobalId, GlobalKind, ImportId, ImportKind};
+use crate::{InitExpr, MemoryId, Module, TableId, TableKind, TypeId};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+
+/// An item that the gc pass either keeps or removes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Item {
+    /// A function.
+    Function(FunctionId),
+    /// A table.
+    Table(TableId),
+    /// A memory.
+    Memory(MemoryId),
+    /// A global.
+    Global(GlobalId),
+    /// A data segment.
+    Data(DataId),
+    /// An element segment.
+    Element(ElementId),
+    /// A type.
+    Type(TypeId),
+    /// A tag.
+    #[cfg(feature = "unstable")]
+    Tag(crate::TagId),
+}
+
+/// Why an item is kept regardless of whether anything refers to it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Root {
+    /// The item is exported.
+    Export(ExportId),
+    /// The item is the start function.
+    Start,
+    /// The item is an imported memory or table that is initialized by this
+    /// module, which is an observable side effect.
+    Import(ImportId),
+    /// The item is a gc root of a custom section.
+    CustomSection(UntypedCustomSectionId),
+}
+
+/// A chain of references from a root to a kept item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetainPath {
+    /// The root the chain starts at.
+    pub root: Root,
+    /// The items along the chain, starting with the root item and ending with
+    /// the kept item. Each item refers to the next one.
+    pub items: Vec<Item>,
+}
+
+/// Shortest reference chains from the roots to every item kept by the gc
+/// pass.
+#[derive(Debug)]
+pub struct Retained {
+    parents: HashMap<Item, Parent>,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Parent {
+    Root(Root),
+    Item(Item),
+}
+
+impl Retained {
+    /// Find the kept items of `module`, along with why they are kept.
+    ///
+    /// This uses the same notion of roots and references as the gc pass, so
+    /// an item has a path exactly when `passes::gc::run` would keep it.
+    pub fn new(module: &Module) -> Retained {
+        let mut retained = Retained {
+            parents: HashMap::new(),
+        };
+        let mut queue = VecDeque::new();
+        let mut root = |item: Item, root: Root| {
+            if let Entry::Vacant(entry) = retained.parents.entry(item) {
+                entry.insert(Parent::Root(root));
+                queue.push_back(item);
+            }
+        };
+
+        for export in module.exports.iter() {
+            let item = match export.item {
+                ExportItem::Function(f) => Item::Function(f),
+                ExportItem::Table(t) => Item::Table(t),
+                ExportItem::Memory(m) => Item::Memory(m),
+                ExportItem::Global(g) => Item::Global(g),
+            };
+            root(item, Root::Export(export.id()));
+        }
+        if let Some(f) = module.start {
+            root(Item::Function(f), Root::Start);
+        }
+        for import in module.imports.iter() {
+            match import.kind {
+                ImportKind::Memory(m) if !module.memories.get(m).data_segments.is_empty() => {
+                    root(Item::Memory(m), Root::Import(import.id()));
+                }
+                ImportKind::Table(t) => match &module.tables.get(t).kind {
+                    TableKind::Function(init) => {
+                        if !init.elements.is_empty() || !init.relative_elements.is_empty() {
+                            root(Item::Table(t), Root::Import(import.id()));
+                        }
+                    }
+                    TableKind::Anyref(_) => {}
+                },
+                _ => {}
+            }
+        }
+        for (id, section) in module.customs.iter() {
+            let mut roots = Roots::new();
+            section.add_gc_roots(&mut roots);
+            let items = roots
+                .funcs
+                .iter()
+                .map(|f| Item::Function(*f))
+                .chain(roots.tables.iter().map(|t| Item::Table(*t)))
+                .chain(roots.memories.iter().map(|m| Item::Memory(*m)))
+                .chain(roots.globals.iter().map(|g| Item::Global(*g)));
+            for item in items {
+                root(item, Root::CustomSection(id));
+            }
+        }
+
+        let mut refs = Vec::new();
+        while let Some(item) = queue.pop_front() {
+            references(module, item, &mut refs);
+            for referenced in refs.drain(..) {
+                if let Entry::Vacant(entry) = retained.parents.entry(referenced) {
+                    entry.insert(Parent::Item(item));
+                    queue.push_back(referenced);
+                }
+            }
+        }
+        retained
+    }
+
+    /// Is `item` kept by the gc pass?
+    pub fn is_retained(&self, item: Item) -> bool {
+        self.parents.contains_key(&item)
+    }
+
+    /// Get a shortest chain of references from a root to `item`.
+    ///
+    /// Returns `None` if `item` is not kept.
+    pub fn path(&self, item: Item) -> Option<RetainPath> {
+        let mut items = vec![item];
+        let mut parent = *self.parents.get(&item)?;
+        loop {
+            match parent {
+                Parent::Root(root) => {
+                    items.reverse();
+                    return Some(RetainPath { root, items });
+                }
+                Parent::Item(item) => {
+                    items.push(item);
+                    parent = self.parents[&item];
+                }
+            }
+        }
+    }
+}
+
+/// Push the items directly referenced by `item` onto `refs`.
+fn references(module: &Module, item: Item, refs: &mut Vec<Item>) {
+    match item {
+        Item::Function(f) => {
+            let func = module.funcs.get(f);
+            refs.push(Item::Type(func.ty()));
+            match &func.kind {
+                FunctionKind::Local(func) => {
+                    let mut visitor = References { refs };
+                    dfs_in_order(&mut visitor, func, func.entry_block());
+                    #[cfg(feature = "unstable")]
+                    refs.extend(
+                        crate::passes::used::referenced_types(module, func)
+                            .into_iter()
+                            .map(Item::Type),
+                    );
+                }
+                FunctionKind::Import(_) => {}
+                FunctionKind::Uninitialized(_) => unreachable!(),
+            }
+        }
+        Item::Table(t) => match &module.tables.get(t).kind {
+            TableKind::Function(list) => {
+                refs.extend(list.elements.iter().flatten().map(|f| Item::Function(*f)));
+                for (global, list) in list.relative_elements.iter() {
+                    refs.push(Item::Global(*global));
+                    refs.extend(list.iter().flatten().map(|f| Item::Function(*f)));
+                }
+            }
+            TableKind::Anyref(_) => {}
+        },
+        Item::Memory(m) => {
+            let memory = module.memories.get(m);
+            refs.extend(memory.data_segments.iter().map(|d| Item::Data(*d)));
+        }
+        Item::Global(g) => {
+            let global = module.globals.get(g);
+            #[cfg(feature = "unstable")]
+            refs.extend(global.ty.referenced_type().map(Item::Type));
+            match &global.kind {
+                GlobalKind::Local(InitExpr::Global(global)) => refs.push(Item::Global(*global)),
+                GlobalKind::Local(InitExpr::Value(_)) | GlobalKind::Import(_) => {}
+            }
+        }
+        Item::Data(d) => {
+            if let DataKind::Active(a) = &module.data.get(d).kind {
+                refs.push(Item::Memory(a.memory));
+                if let ActiveDataLocation::Relative(g) = a.location {
+                    refs.push(Item::Global(g));
+                }
+            }
+        }
+        Item::Element(e) => {
+            let members = module.elements.get(e).members.iter().flatten();
+            refs.extend(members.map(|f| Item::Function(*f)));
+        }
+        Item::Type(ty) => {
+            #[cfg(feature = "unstable")]
+            refs.extend(module.types.get(ty).referenced_types().map(Item::Type));
+            #[cfg(not(feature = "unstable"))]
+            let _ = ty;
+        }
+        #[cfg(feature = "unstable")]
+        Item::Tag(t) => refs.push(Item::Type(module.tags.get(t).ty)),
+    }
+}
+
+struct References<'a> {
+    refs: &'a mut Vec<Item>,
+}
+
+impl<'expr> Visitor<'expr> for References<'_> {
+    fn visit_function_id(&mut self, &func: &FunctionId) {
+        self.refs.push(Item::Function(func));
+    }
+
+    fn visit_memory_id(&mut self, &m: &MemoryId) {
+        self.refs.push(Item::Memory(m));
+    }
+
+    fn visit_global_id(&mut self, &g: &GlobalId) {
+        self.refs.push(Item::Global(g));
+    }
+
+    fn visit_table_id(&mut self, &t: &TableId) {
+        self.refs.push(Item::Table(t));
+    }
+
+    fn visit_type_id(&mut self, &t: &TypeId) {
+        self.refs.push(Item::Type(t));
+    }
+
+    fn visit_data_id(&mut self, &d: &DataId) {
+        self.refs.push(Item::Data(d));
+    }
+
+    fn visit_element_id(&mut self, &e: &ElementId) {
+        self.refs.push(Item::Element(e));
+    }
+
+    #[cfg(feature = "unstable")]
+    fn visit_tag_id(&mut self, &t: &crate::TagId) {
+        self.refs.push(Item::Tag(t));
+    }
+}
@@ -0,0 +1,246 @@
+//! Convert between short `if`/`else` expressions and `select`.
+//!
+//! Code generated with `FunctionBuilder` is often branch heavy, with `if`/
+//! `else` blocks whose arms just compute a value. When both arms are pure and
+//! cheap, evaluating both unconditionally and picking one with `select` is
+//! both smaller and avoids a branch. In the other direction, a `select` whose
+//! operands are pure but expensive to compute can be turned back into an
+//! `if`/`else`, so that only one of them is evaluated.
+//!
+//! An expression is pure if it is built from constants, `local.get`,
+//! `global.get`, and non-trapping unary and binary operators. Nothing else is
+//! ever converted: arms with side effects or that might trap must stay
+//! conditional, and both operands of a `select` are always evaluated, so
+//! evaluating only one of them conditionally could skip side effects.
+
+use crate::ir::*;
+use crate::passes::cse::{binop_result, unop_result};
+use crate::{LocalFunction, Module, ModuleGlobals, ModuleLocals, ValType};
+use std::ops::Range;
+
+/// Which conversions to make.
+#[derive(Debug, Copy, Clone)]
+pub struct Options {
+    /// Convert `if`/`else` expressions into `select` when each arm is a pure
+    /// expression of at most this many instructions. Zero disables this
+    /// conversion.
+    pub max_select_arm: usize,
+
+    /// Convert `select`s into `if`/`else` expressions when both operands are
+    /// pure, and together take at least this many instructions. `None`
+    /// disables this conversion.
+    pub min_branch_operands: Option<usize>,
+}
+
+impl Options {
+    /// Options favoring code size: convert every pure `if`/`else` expression
+    /// into a `select`.
+    pub fn size() -> Options {
+        Options {
+            max_select_arm: usize::MAX,
+            min_branch_operands: None,
+        }
+    }
+
+    /// Options favoring speed: only convert cheap `if`/`else` expressions into
+    /// `select`s, and branch around expensive operands of `select`s.
+    pub fn speed() -> Options {
+        Options {
+            max_select_arm: 3,
+            min_branch_operands: Some(16),
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options::speed()
+    }
+}
+
+/// Convert between `if`/`else` expressions and `select`s in every local
+/// function in `module`, as configured by `options`.
+pub fn run(module: &mut Module, options: &Options) {
+    for (_, func) in module.funcs.iter_local_mut() {
+        let mut seqs = SeqIds(Vec::new());
+        dfs_in_order(&mut seqs, func, func.entry_block());
+        let mut convert = Convert {
+            func,
+            locals: &mut module.locals,
+            globals: &module.globals,
+            options,
+        };
+        for seq in seqs.0 {
+            convert.seq(seq);
+        }
+    }
+}
+
+struct SeqIds(Vec<InstrSeqId>);
+
+impl<'instr> Visitor<'instr> for SeqIds {
+    fn start_instr_seq(&mut self, seq: &'instr InstrSeq) {
+        self.0.push(seq.id());
+    }
+}
+
+struct Convert<'a> {
+    func: &'a mut LocalFunction,
+    locals: &'a mut ModuleLocals,
+    globals: &'a ModuleGlobals,
+    options: &'a Options,
+}
+
+impl Convert<'_> {
+    fn seq(&mut self, seq: InstrSeqId) {
+        let mut i = 0;
+        while i < self.func.block(seq).instrs.len() {
+            i = match &self.func.block(seq).instrs[i].0 {
+                Instr::IfElse(e) => self.select_if_else(seq, i, e.consequent, e.alternative),
+                Instr::Select(s) => self.branch_select(seq, i, s.ty),
+                _ => None,
+            }
+            .unwrap_or(i)
+                + 1;
+        }
+    }
+
+    /// Convert the `if`/`else` at `i` in `seq` into a `select`, if possible,
+    /// and return the new index of the `select`.
+    fn select_if_else(
+        &mut self,
+        seq: InstrSeqId,
+        i: usize,
+        consequent: InstrSeqId,
+        alternative: InstrSeqId,
+    ) -> Option<usize> {
+        let ty = match self.func.block(consequent).ty {
+            InstrSeqType::Simple(Some(ty)) => ty,
+            _ => return None,
+        };
+        let mut arms = Vec::new();
+        for arm in [consequent, alternative].iter() {
+            let instrs = &self.func.block(*arm).instrs;
+            if instrs.len() > self.options.max_select_arm
+                || pure_start(instrs, instrs.len()) != Some(0)
+            {
+                return None;
+            }
+            arms.extend(instrs.iter().cloned());
+        }
+
+        // Move the condition after the arms if it is pure, and otherwise save
+        // it in a local.
+        let loc = self.func.block(seq).instrs[i].1;
+        let instrs = &mut self.func.block_mut(seq).instrs;
+        let mut replacement = arms;
+        let start = match pure_start(instrs, i) {
+            Some(start) => {
+                replacement.extend(instrs[start..i].iter().cloned());
+                start
+            }
+            None => {
+                let local = self.locals.add(ValType::I32);
+                replacement.insert(0, (LocalSet { local }.into(), loc));
+                replacement.push((LocalGet { local }.into(), loc));
+                i
+            }
+        };
+        let ty = select_type(ty);
+        replacement.push((Select { ty }.into(), loc));
+        let end = start + replacement.len() - 1;
+        instrs.splice(start..i + 1, replacement);
+        Some(end)
+    }
+
+    /// Convert the `select` at `i` in `seq` into an `if`/`else`, if possible,
+    /// and return the new index of the `if`/`else`.
+    fn branch_select(&mut self, seq: InstrSeqId, i: usize, ty: Option<ValType>) -> Option<usize> {
+        let min = self.options.min_branch_operands?;
+        let instrs = &self.func.block(seq).instrs;
+        let condition = pure_start(instrs, i)?..i;
+        let alternative = pure_start(instrs, condition.start)?..condition.start;
+        let consequent = pure_start(instrs, alternative.start)?..alternative.start;
+        if consequent.len() + alternative.len() < min {
+            return None;
+        }
+        let ty = match ty {
+            Some(ty) => ty,
+            None => self.result_type(&instrs[consequent.end - 1].0)?,
+        };
+
+        let loc = instrs[i].1;
+        let start = consequent.start;
+        let consequent = self.arm(seq, ty, consequent);
+        let alternative = self.arm(seq, ty, alternative);
+        let instrs = &mut self.func.block_mut(seq).instrs;
+        let mut replacement = instrs[condition].to_vec();
+        replacement.push((
+            IfElse {
+                consequent,
+                alternative,
+            }
+            .into(),
+            loc,
+        ));
+        let end = start + replacement.len() - 1;
+        instrs.splice(start..i + 1, replacement);
+        Some(end)
+    }
+
+    /// Create a new instruction sequence of type `ty` holding a copy of the
+    /// instructions at `range` in `seq`.
+    fn arm(&mut self, seq: InstrSeqId, ty: ValType, range: Range<usize>) -> InstrSeqId {
+        let instrs = self.func.block(seq).instrs[range].to_vec();
+        let mut arm = self.func.builder_mut().dangling_instr_seq(ty);
+        *arm.instrs_mut() = instrs;
+        arm.id()
+    }
+
+    /// The type of the value produced by the pure instruction `instr`.
+    fn result_type(&self, instr: &Instr) -> Option<ValType> {
+        match instr {
+            Instr::Const(c) => Some(match c.value {
+                Value::I32(_) => ValType::I32,
+                Value::I64(_) => ValType::I64,
+                Value::F32(_) => ValType::F32,
+                Value::F64(_) => ValType::F64,
+                Value::V128(_) => ValType::V128,
+            }),
+            Instr::LocalGet(l) => Some(self.locals.get(l.local).ty()),
+            Instr::GlobalGet(g) => Some(self.globals.get(g.global).ty),
+            Instr::Unop(u) => unop_result(u.op),
+            Instr::Binop(b) => binop_result(b.op),
+            _ => None,
+        }
+    }
+}
+
+/// The type annotation of a `select` of values of type `ty`.
+fn select_type(ty: ValType) -> Option<ValType> {
+    match ty {
+        ValType::Anyref => Some(ty),
+        #[cfg(feature = "unstable")]
+        ValType::Cont { .. } => Some(ty),
+        _ => None,
+    }
+}
+
+/// Find where the pure expression producing the value on the top of the stack
+/// before `instrs[end]` starts, if there is one.
+fn pure_start(instrs: &[(Instr, InstrLocId)], end: usize) -> Option<usize> {
+    // The number of values still needed to complete the expression.
+    let mut needed = 1;
+    for (i, (instr, _)) in instrs[..end].iter().enumerate().rev() {
+        match instr {
+            Instr::Const(_) | Instr::LocalGet(_) | Instr::GlobalGet(_) => needed -= 1,
+            Instr::Unop(u) if unop_result(u.op).is_some() => {}
+            Instr::Binop(b) if binop_result(b.op).is_some() => needed += 1,
+            _ => return None,
+        }
+        if needed == 0 {
+            return Some(i);
+        }
+    }
+    None
+}
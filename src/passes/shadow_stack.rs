@@ -0,0 +1,290 @@
+//! Spill locals to a shadow stack in linear memory around call sites.
+//!
+//! Garbage collected language runtimes compiled to wasm can't scan the wasm
+//! stack for roots, since locals live outside of linear memory. This pass
+//! makes such roots visible: in every function that makes calls, each local
+//! selected by a user-supplied filter gets a slot in a stack frame allocated
+//! on a shadow stack in linear memory. Selected locals are stored to their
+//! slots before every call, so that a collection triggered by the callee can
+//! scan them, and are reloaded afterwards, so that a moving collector can
+//! update them.
+//!
+//! The shadow stack grows downwards, like LLVM's `__stack_pointer`, and the
+//! stack pointer global always points at the innermost frame.
+
+use crate::ir::*;
+use crate::map::IdHashSet;
+use crate::{GlobalId, InstrSeqBuilder, LocalFunction, MemoryId, Module, ModuleLocals};
+use crate::{ModuleTypes, Result, ValType};
+use anyhow::bail;
+use std::mem;
+
+/// Frames on the shadow stack are aligned to this many bytes.
+const FRAME_ALIGN: u32 = 16;
+
+/// Run the shadow stack spilling pass over every local function in `module`.
+///
+/// `memory` holds the shadow stack and `stack_pointer` is a mutable `i32`
+/// global pointing at its top. `filter` selects, for each function, which of
+/// its locals and parameters are spilled.
+///
+/// Spilling is conservative: a selected local is spilled at every call site in
+/// its function, not just where it is live. Functions without calls, or
+/// without any selected locals, are left untouched.
+pub fn run(
+    module: &mut Module,
+    memory: MemoryId,
+    stack_pointer: GlobalId,
+    filter: impl Fn(&Local) -> bool,
+) -> Result<()> {
+    let sp = module.globals.get(stack_pointer);
+    if sp.ty != ValType::I32 || !sp.mutable {
+        bail!("the shadow stack pointer must be a mutable `i32` global");
+    }
+
+    for (_, func) in module.funcs.iter_local_mut() {
+        let spilled = spilled_locals(&module.locals, func, &filter)?;
+        if spilled.slots.is_empty() || !has_calls(func) {
+            continue;
+        }
+        let spill = Spill {
+            memory,
+            stack_pointer,
+            slots: &spilled.slots,
+        };
+        spill.rewrite(&mut module.types, func, spilled.frame_size);
+    }
+    Ok(())
+}
+
+struct SpilledLocals {
+    slots: Vec<(LocalId, ValType, u32)>,
+    frame_size: u32,
+}
+
+/// Find the locals of `func` to spill, and lay out their slots in its frame.
+fn spilled_locals(
+    locals: &ModuleLocals,
+    func: &LocalFunction,
+    filter: &impl Fn(&Local) -> bool,
+) -> Result<SpilledLocals> {
+    struct CollectLocals<'a> {
+        seen: IdHashSet<Local>,
+        locals: &'a mut Vec<LocalId>,
+    }
+
+    impl<'instr> Visitor<'instr> for CollectLocals<'_> {
+        fn visit_local_id(&mut self, &local: &LocalId) {
+            if self.seen.insert(local) {
+                self.locals.push(local);
+            }
+        }
+    }
+
+    let mut ids = Vec::new();
+    let mut collect = CollectLocals {
+        seen: func.args.iter().cloned().collect(),
+        locals: &mut ids,
+    };
+    dfs_in_order(&mut collect, func, func.entry_block());
+    let ids = func.args.iter().chain(ids.iter());
+
+    let mut slots = Vec::new();
+    for id in ids {
+        let local = locals.get(*id);
+        if !filter(local) {
+            continue;
+        }
+        if local.ty().is_reference() {
+            bail!("cannot spill reference local {:?} to linear memory", id);
+        }
+        slots.push((*id, local.ty(), 0));
+    }
+
+    // Lay out the largest slots first, so that every slot is naturally
+    // aligned.
+    slots.sort_by_key(|&(_, ty, _)| std::cmp::Reverse(size_of(ty)));
+    let mut frame_size = 0;
+    for (_, ty, offset) in slots.iter_mut() {
+        *offset = frame_size;
+        frame_size += size_of(*ty);
+    }
+    let frame_size = (frame_size + FRAME_ALIGN - 1) & !(FRAME_ALIGN - 1);
+
+    Ok(SpilledLocals { slots, frame_size })
+}
+
+fn size_of(ty: ValType) -> u32 {
+    match ty {
+        ValType::I32 | ValType::F32 => 4,
+        ValType::I64 | ValType::F64 => 8,
+        ValType::V128 => 16,
+        ValType::Anyref => unreachable!(),
+        #[cfg(feature = "unstable")]
+        ValType::Cont { .. } => unreachable!(),
+    }
+}
+
+fn has_calls(func: &LocalFunction) -> bool {
+    struct HasCalls(bool);
+
+    impl<'instr> Visitor<'instr> for HasCalls {
+        fn visit_call(&mut self, _: &Call) {
+            self.0 = true;
+        }
+
+        fn visit_call_indirect(&mut self, _: &CallIndirect) {
+            self.0 = true;
+        }
+    }
+
+    let mut has_calls = HasCalls(false);
+    dfs_in_order(&mut has_calls, func, func.entry_block());
+    has_calls.0
+}
+
+struct Spill<'a> {
+    memory: MemoryId,
+    stack_pointer: GlobalId,
+    slots: &'a [(LocalId, ValType, u32)],
+}
+
+impl Spill<'_> {
+    fn rewrite(&self, types: &mut ModuleTypes, func: &mut LocalFunction, frame_size: u32) {
+        // Move the function's body into a block of its own, so that the frame
+        // can be popped after it no matter how it is left.
+        let entry = func.entry_block();
+        let results = types.results(func.ty()).to_vec();
+        let body_ty = InstrSeqType::new(types, &[], &results);
+        let body = func.builder_mut().dangling_instr_seq(body_ty).id();
+        let instrs = mem::take(&mut func.block_mut(entry).instrs);
+        func.block_mut(body).instrs = instrs;
+
+        let mut seqs = Vec::new();
+        dfs_in_order(&mut CollectSeqs(&mut seqs), func, body);
+        for seq in seqs {
+            let instrs = mem::take(&mut func.block_mut(seq).instrs);
+            let mut new_instrs = Vec::with_capacity(instrs.len());
+            for (instr, loc) in instrs {
+                match instr {
+                    Instr::Call(_) | Instr::CallIndirect(_) => {
+                        self.store_slots(&mut new_instrs);
+                        new_instrs.push((instr, loc));
+                        self.load_slots(&mut new_instrs);
+                    }
+                    // Returning is now branching out of the body, which leaves
+                    // the results on the stack just the same.
+                    Instr::Return(_) => new_instrs.push((Br { block: body }.into(), loc)),
+                    mut instr => {
+                        retarget(&mut instr, entry, body);
+                        new_instrs.push((instr, loc));
+                    }
+                }
+            }
+            func.block_mut(seq).instrs = new_instrs;
+        }
+
+        let mut builder = func.builder_mut().instr_seq(entry);
+        self.adjust_stack_pointer(&mut builder, BinaryOp::I32Sub, frame_size);
+        builder.instr(Block { seq: body });
+        self.adjust_stack_pointer(&mut builder, BinaryOp::I32Add, frame_size);
+    }
+
+    fn adjust_stack_pointer(&self, builder: &mut InstrSeqBuilder, op: BinaryOp, amount: u32) {
+        builder
+            .global_get(self.stack_pointer)
+            .i32_const(amount as i32)
+            .binop(op)
+            .global_set(self.stack_pointer);
+    }
+
+    fn store_slots(&self, instrs: &mut Vec<(Instr, InstrLocId)>) {
+        for &(local, ty, offset) in self.slots {
+            let kind = match ty {
+                ValType::I32 => StoreKind::I32 { atomic: false },
+                ValType::I64 => StoreKind::I64 { atomic: false },
+                ValType::F32 => StoreKind::F32,
+                ValType::F64 => StoreKind::F64,
+                ValType::V128 => StoreKind::V128,
+                ValType::Anyref => unreachable!(),
+                #[cfg(feature = "unstable")]
+                ValType::Cont { .. } => unreachable!(),
+            };
+            let store = Store {
+                memory: self.memory,
+                kind,
+                arg: self.memarg(ty, offset),
+            };
+            instrs.push((self.stack_pointer_get(), Default::default()));
+            instrs.push((LocalGet { local }.into(), Default::default()));
+            instrs.push((store.into(), Default::default()));
+        }
+    }
+
+    fn load_slots(&self, instrs: &mut Vec<(Instr, InstrLocId)>) {
+        for &(local, ty, offset) in self.slots {
+            let kind = match ty {
+                ValType::I32 => LoadKind::I32 { atomic: false },
+                ValType::I64 => LoadKind::I64 { atomic: false },
+                ValType::F32 => LoadKind::F32,
+                ValType::F64 => LoadKind::F64,
+                ValType::V128 => LoadKind::V128,
+                ValType::Anyref => unreachable!(),
+                #[cfg(feature = "unstable")]
+                ValType::Cont { .. } => unreachable!(),
+            };
+            let load = Load {
+                memory: self.memory,
+                kind,
+                arg: self.memarg(ty, offset),
+            };
+            instrs.push((self.stack_pointer_get(), Default::default()));
+            instrs.push((load.into(), Default::default()));
+            instrs.push((LocalSet { local }.into(), Default::default()));
+        }
+    }
+
+    fn stack_pointer_get(&self) -> Instr {
+        GlobalGet {
+            global: self.stack_pointer,
+        }
+        .into()
+    }
+
+    fn memarg(&self, ty: ValType, offset: u32) -> MemArg {
+        MemArg {
+            align: size_of(ty),
+            offset,
+            encoding: None,
+        }
+    }
+}
+
+struct CollectSeqs<'a>(&'a mut Vec<InstrSeqId>);
+
+impl<'instr> Visitor<'instr> for CollectSeqs<'_> {
+    fn start_instr_seq(&mut self, seq: &'instr InstrSeq) {
+        self.0.push(seq.id());
+    }
+}
+
+/// Point any branch to `from` at `to` instead.
+fn retarget(instr: &mut Instr, from: InstrSeqId, to: InstrSeqId) {
+    let retarget = |block: &mut InstrSeqId| {
+        if *block == from {
+            *block = to;
+        }
+    };
+    match instr {
+        Instr::Br(Br { block }) | Instr::BrIf(BrIf { block }) => retarget(block),
+        Instr::BrTable(BrTable { blocks, default }) => {
+            blocks.iter_mut().for_each(retarget);
+            retarget(default);
+        }
+        #[cfg(feature = "unstable")]
+        Instr::Resume(Resume { handlers, .. }) => {
+            handlers.iter_mut().for_each(|h| retarget(&mut h.block));
+        }
+        _ => {}
+    }
+}
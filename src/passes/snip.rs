@@ -0,0 +1,101 @@
+//! Replace the bodies of selected functions with `unreachable`.
+//!
+//! This is a port of [`wasm-snip`](https://github.com/rustwasm/wasm-snip) to a
+//! walrus pass. Snipping is useful for functions that are known never to be
+//! called at runtime but can't be proven dead statically, such as a Rust
+//! program's panicking and formatting infrastructure. Once their bodies are
+//! gone, everything that only they referenced is garbage collected.
+
+use crate::ir::{Instr, Unreachable};
+use crate::passes::gc;
+use crate::{FunctionKind, Module, Result};
+#[cfg(feature = "snip-patterns")]
+use regex::RegexSet;
+
+/// Which functions to snip.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// The names of functions to snip.
+    pub functions: Vec<String>,
+
+    /// Regular expressions; functions whose names match any of them are
+    /// snipped. Requires the `snip-patterns` feature.
+    #[cfg(feature = "snip-patterns")]
+    pub patterns: Vec<String>,
+
+    /// Snip Rust's `std::fmt` and `core::fmt` code.
+    pub snip_rust_fmt_code: bool,
+
+    /// Snip Rust's `std::panicking` and `core::panicking` code.
+    pub snip_rust_panicking_code: bool,
+}
+
+/// Snip the local functions in `module` selected by `options`, and then run
+/// the gc pass to remove the items that are no longer used.
+///
+/// Function names are matched against the "name" section, so functions
+/// without a name are never snipped. Imported functions are never snipped
+/// either.
+pub fn run(module: &mut Module, options: &Options) -> Result<()> {
+    let matcher = Matcher::new(options)?;
+    for func in module.funcs.iter_mut() {
+        match &func.name {
+            Some(name) if matcher.is_match(name) => {}
+            _ => continue,
+        }
+        if let FunctionKind::Local(local) = &mut func.kind {
+            log::debug!("snipping {:?}", func.name);
+            let entry = local.entry_block();
+            let unreachable: Instr = Unreachable {}.into();
+            local.block_mut(entry).instrs = vec![(unreachable, Default::default())];
+        }
+    }
+    gc::run(module);
+    Ok(())
+}
+
+struct Matcher<'a> {
+    functions: &'a [String],
+    // Substrings of the mangled and demangled names of the Rust code to snip.
+    rust_paths: Vec<&'static str>,
+    #[cfg(feature = "snip-patterns")]
+    patterns: RegexSet,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(options: &'a Options) -> Result<Matcher<'a>> {
+        let mut rust_paths = Vec::new();
+        if options.snip_rust_fmt_code {
+            rust_paths.extend(&["4core3fmt", "3std3fmt", "core::fmt::", "std::fmt::"]);
+        }
+        if options.snip_rust_panicking_code {
+            rust_paths.extend(&[
+                "4core9panicking",
+                "3std9panicking",
+                "core::panicking::",
+                "std::panicking::",
+            ]);
+        }
+        Ok(Matcher {
+            functions: &options.functions,
+            rust_paths,
+            #[cfg(feature = "snip-patterns")]
+            patterns: RegexSet::new(&options.patterns)?,
+        })
+    }
+
+    fn is_match(&self, name: &str) -> bool {
+        if self.functions.iter().any(|f| f == name)
+            || self.rust_paths.iter().any(|path| name.contains(path))
+        {
+            return true;
+        }
+        #[cfg(feature = "snip-patterns")]
+        {
+            if self.patterns.is_match(name) {
+                return true;
+            }
+        }
+        false
+    }
+}
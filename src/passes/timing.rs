@@ -0,0 +1,262 @@
+//! Time how long functions take, from within the module.
+//!
+//! This pass turns a module into a self-profiling build. Every selected
+//! function is wrapped with calls to a clock, like an imported
+//! `performance.now` or a cycle counter, and the wrapper accumulates how
+//! often the function was called and how much time it took in a table in
+//! linear memory. An exported `timing_dump` function reports the table to the
+//! embedder.
+//!
+//! Reading the clock can be expensive compared to small functions, so only
+//! every `Config::sample_every`th call of each function is timed. The table
+//! has an entry of `ENTRY_SIZE` bytes for each selected function, holding
+//!
+//! * the number of calls as an `i64` at offset 0,
+//! * the number of timed calls as an `i64` at offset 8, and
+//! * the total time of the timed calls at offset 16, with the type that the
+//!   clock returns.
+//!
+//! Times are inclusive: they include the time spent in callees, and
+//! recursive calls are timed on their own as well as part of their callers.
+
+use crate::ir::*;
+use crate::passes::record_replay::redirect;
+use crate::{ExportItem, Function, FunctionBuilder, FunctionId, FunctionKind, InstrSeqBuilder};
+use crate::{MemoryId, Module, Result, ValType};
+use anyhow::bail;
+use std::collections::{HashMap, HashSet};
+
+/// The size of each entry of the table, in bytes.
+pub const ENTRY_SIZE: u32 = 24;
+
+/// The name that the dump function is exported under.
+pub const DUMP_EXPORT: &str = "timing_dump";
+
+/// Where to keep the table, and how to time functions.
+#[derive(Debug, Copy, Clone)]
+pub struct Config {
+    /// The memory holding the table.
+    pub memory: MemoryId,
+    /// The address of the table, which must be 8-byte aligned and have room
+    /// for `ENTRY_SIZE` bytes per selected function. The table must start
+    /// out zeroed.
+    pub base: u32,
+    /// The clock, a function without parameters that returns the current
+    /// time as an `i64` or an `f64`.
+    pub now: FunctionId,
+    /// The function that `timing_dump` calls for each entry of the table,
+    /// with the entry's index, its number of calls and timed calls, and its
+    /// total time.
+    pub report: FunctionId,
+    /// Only time every this many calls of each function; 1 times all calls.
+    pub sample_every: u32,
+}
+
+/// Wrap every local function of `module` for which `filter` holds with the
+/// timing instrumentation described by `config`, and export `timing_dump`.
+///
+/// Returns the wrapped functions, in the order of their entries in the table.
+pub fn run(
+    module: &mut Module,
+    config: &Config,
+    filter: impl Fn(&Function) -> bool,
+) -> Result<Vec<FunctionId>> {
+    let time = match module
+        .types
+        .params_results(module.funcs.get(config.now).ty())
+    {
+        ([], [ValType::I64]) => ValType::I64,
+        ([], [ValType::F64]) => ValType::F64,
+        _ => bail!("the clock must take no parameters and return an `i64` or an `f64`"),
+    };
+    let report = [ValType::I32, ValType::I64, ValType::I64, time];
+    if module
+        .types
+        .params_results(module.funcs.get(config.report).ty())
+        != (&report[..], &[][..])
+    {
+        bail!(
+            "the report function must take an `i32`, two `i64`s and an `{}`, and return nothing",
+            time
+        );
+    }
+    if config.sample_every == 0 {
+        bail!("`sample_every` must be at least 1");
+    }
+    if config.base & 7 != 0 {
+        bail!("the table must be 8-byte aligned");
+    }
+
+    let funcs = module
+        .funcs
+        .iter()
+        .filter(|f| matches!(f.kind, FunctionKind::Local(_)))
+        .filter(|f| f.id() != config.now && f.id() != config.report && filter(f))
+        .map(|f| f.id())
+        .collect::<Vec<_>>();
+    let mut wrappers = HashMap::new();
+    for (i, &func) in funcs.iter().enumerate() {
+        let entry = config.entry(i)?;
+        let wrapper = config.wrap(module, func, entry, time);
+        wrappers.insert(func, wrapper);
+    }
+
+    redirect(module, &wrappers, &HashSet::new());
+    for export in module.exports.iter_mut() {
+        if let ExportItem::Function(func) = &mut export.item {
+            if let Some(wrapper) = wrappers.get(func) {
+                *func = *wrapper;
+            }
+        }
+    }
+    if let Some(start) = &mut module.start {
+        if let Some(wrapper) = wrappers.get(start) {
+            *start = *wrapper;
+        }
+    }
+
+    let dump = config.dump(module, funcs.len(), time)?;
+    module.exports.add(DUMP_EXPORT, dump);
+    Ok(funcs)
+}
+
+impl Config {
+    /// The address of the `i`th entry.
+    fn entry(&self, i: usize) -> Result<u32> {
+        match (i as u32)
+            .checked_mul(ENTRY_SIZE)
+            .and_then(|offset| offset.checked_add(self.base))
+            .and_then(|entry| entry.checked_add(ENTRY_SIZE))
+        {
+            Some(end) => Ok(end - ENTRY_SIZE),
+            None => bail!("the table doesn't fit in a 32-bit memory"),
+        }
+    }
+
+    fn memarg(&self, offset: u32) -> MemArg {
+        MemArg {
+            align: 8,
+            offset,
+            encoding: None,
+        }
+    }
+
+    fn load(&self, body: &mut InstrSeqBuilder, ty: ValType, address: u32) {
+        let kind = match ty {
+            ValType::I64 => LoadKind::I64 { atomic: false },
+            _ => LoadKind::F64,
+        };
+        body.i32_const(0)
+            .load(self.memory, kind, self.memarg(address));
+    }
+
+    fn store(&self, body: &mut InstrSeqBuilder, ty: ValType, address: u32) {
+        let kind = match ty {
+            ValType::I64 => StoreKind::I64 { atomic: false },
+            _ => StoreKind::F64,
+        };
+        body.store(self.memory, kind, self.memarg(address));
+    }
+
+    /// Add the value that `push` pushes to the `ty` at `address`.
+    fn add(
+        &self,
+        body: &mut InstrSeqBuilder,
+        ty: ValType,
+        address: u32,
+        push: impl FnOnce(&mut InstrSeqBuilder),
+    ) {
+        let add = match ty {
+            ValType::I64 => BinaryOp::I64Add,
+            _ => BinaryOp::F64Add,
+        };
+        body.i32_const(0);
+        self.load(body, ty, address);
+        push(body);
+        body.binop(add);
+        self.store(body, ty, address);
+    }
+
+    /// Make a function that counts and times calls to `func` in the entry at
+    /// `entry`.
+    fn wrap(&self, module: &mut Module, func: FunctionId, entry: u32, time: ValType) -> FunctionId {
+        let ty = module.funcs.get(func).ty();
+        let (params, results) = module.types.params_results(ty);
+        let (params, results) = (params.to_vec(), results.to_vec());
+        let args = params
+            .iter()
+            .map(|ty| module.locals.add(*ty))
+            .collect::<Vec<_>>();
+        let rets = results
+            .iter()
+            .map(|ty| module.locals.add(*ty))
+            .collect::<Vec<_>>();
+        let start = module.locals.add(time);
+        let sub = match time {
+            ValType::I64 => BinaryOp::I64Sub,
+            _ => BinaryOp::F64Sub,
+        };
+
+        let call = |body: &mut InstrSeqBuilder| {
+            for &arg in args.iter() {
+                body.local_get(arg);
+            }
+            body.call(func);
+            for &ret in rets.iter().rev() {
+                body.local_set(ret);
+            }
+        };
+        let timed_call = |body: &mut InstrSeqBuilder| {
+            body.call(self.now).local_set(start);
+            call(body);
+            self.add(body, time, entry + 16, |body| {
+                body.call(self.now).local_get(start).binop(sub);
+            });
+            self.add(body, ValType::I64, entry + 8, |body| {
+                body.i64_const(1);
+            });
+        };
+
+        let mut builder = FunctionBuilder::new(&mut module.types, &params, &results);
+        let mut body = builder.func_body();
+        self.add(&mut body, ValType::I64, entry, |body| {
+            body.i64_const(1);
+        });
+        if self.sample_every == 1 {
+            timed_call(&mut body);
+        } else {
+            self.load(&mut body, ValType::I64, entry);
+            body.i64_const(i64::from(self.sample_every))
+                .binop(BinaryOp::I64RemU)
+                .unop(UnaryOp::I64Eqz)
+                .if_else(None, timed_call, |body| call(body));
+        }
+        for &ret in rets.iter() {
+            body.local_get(ret);
+        }
+        let wrapper = builder.finish(args.clone(), &mut module.funcs);
+
+        let name = match &module.funcs.get(func).name {
+            Some(name) => format!("timing:{}", name),
+            None => format!("timing:{}", func.index()),
+        };
+        module.funcs.get_mut(wrapper).name = Some(name);
+        wrapper
+    }
+
+    /// Make the function that reports all `count` entries.
+    fn dump(&self, module: &mut Module, count: usize, time: ValType) -> Result<FunctionId> {
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[]);
+        builder.name(DUMP_EXPORT.to_string());
+        let mut body = builder.func_body();
+        for i in 0..count {
+            let entry = self.entry(i)?;
+            body.i32_const(i as i32);
+            self.load(&mut body, ValType::I64, entry);
+            self.load(&mut body, ValType::I64, entry + 8);
+            self.load(&mut body, time, entry + 16);
+            body.call(self.report);
+        }
+        Ok(builder.finish(vec![], &mut module.funcs))
+    }
+}
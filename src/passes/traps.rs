@@ -0,0 +1,157 @@
+//! Find the instructions of a module that may trap.
+//!
+//! Reviewing a module for safety, or instrumenting it to check operands
+//! before they can trap, starts with knowing where the traps may happen. This
+//! lists every instruction that may trap because of its operands or the state
+//! it accesses, without trying to prove that any of them won't, so something
+//! like `i32.div_u` by a constant non-zero divisor is listed as well.
+
+use crate::ir::*;
+use crate::{FunctionId, FunctionKind, Module};
+
+/// Why an instruction may trap.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TrapKind {
+    /// Integer division or remainder, which traps when dividing by zero, and
+    /// signed division, which also traps when the quotient overflows.
+    Division,
+    /// A truncation of a float to an integer that isn't saturating, which
+    /// traps on NaN and on values that the integer can't represent.
+    Conversion,
+    /// An access to memory, which traps when it is out of bounds, and atomic
+    /// accesses, which also trap when they are misaligned.
+    MemoryAccess,
+    /// An access to a table, which traps when it is out of bounds.
+    TableAccess,
+    /// An `unreachable`, which always traps.
+    Unreachable,
+    /// A `call_indirect`, which traps when the table index is out of bounds,
+    /// the table entry is null, or the function has the wrong signature.
+    IndirectCall,
+}
+
+/// An instruction that may trap, found with `trapping_sites`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TrappingSite {
+    /// The function the instruction is in.
+    pub func: FunctionId,
+
+    /// The instruction sequence the instruction is in.
+    pub seq: InstrSeqId,
+
+    /// The position of the instruction in its sequence.
+    pub index: usize,
+
+    /// Why the instruction may trap.
+    pub kind: TrapKind,
+
+    /// The offset of the instruction in the binary the module was parsed
+    /// from, or what `ModuleConfig::on_instr_loc` gave for it, if it was
+    /// parsed rather than added afterwards.
+    pub offset: Option<u32>,
+}
+
+/// Find the instructions of `module` that may trap.
+///
+/// The sites are listed function by function, in the order of
+/// `ModuleFunctions::iter`, and in the order of their instructions within a
+/// function, with the instructions of a `block`, `loop` or `if` listed
+/// before the ones after it.
+pub fn trapping_sites(module: &Module) -> Vec<TrappingSite> {
+    let mut sites = Vec::new();
+    for func in module.funcs.iter() {
+        let local = match &func.kind {
+            FunctionKind::Local(local) => local,
+            _ => continue,
+        };
+        let mut finder = Finder {
+            func: func.id(),
+            seqs: Vec::new(),
+            sites: &mut sites,
+        };
+        dfs_in_order(&mut finder, local, local.entry_block());
+    }
+    sites
+}
+
+/// The kind of trap that `instr` may cause, if any.
+fn trap_kind(instr: &Instr) -> Option<TrapKind> {
+    Some(match instr {
+        Instr::Binop(Binop {
+            op:
+                BinaryOp::I32DivS
+                | BinaryOp::I32DivU
+                | BinaryOp::I32RemS
+                | BinaryOp::I32RemU
+                | BinaryOp::I64DivS
+                | BinaryOp::I64DivU
+                | BinaryOp::I64RemS
+                | BinaryOp::I64RemU,
+        }) => TrapKind::Division,
+        Instr::Unop(Unop {
+            op:
+                UnaryOp::I32TruncSF32
+                | UnaryOp::I32TruncUF32
+                | UnaryOp::I32TruncSF64
+                | UnaryOp::I32TruncUF64
+                | UnaryOp::I64TruncSF32
+                | UnaryOp::I64TruncUF32
+                | UnaryOp::I64TruncSF64
+                | UnaryOp::I64TruncUF64,
+        }) => TrapKind::Conversion,
+        Instr::Load(_)
+        | Instr::Store(_)
+        | Instr::LoadSimd(_)
+        | Instr::AtomicRmw(_)
+        | Instr::Cmpxchg(_)
+        | Instr::AtomicNotify(_)
+        | Instr::AtomicWait(_)
+        | Instr::MemoryInit(_)
+        | Instr::MemoryCopy(_)
+        | Instr::MemoryFill(_) => TrapKind::MemoryAccess,
+        Instr::TableGet(_)
+        | Instr::TableSet(_)
+        | Instr::TableFill(_)
+        | Instr::TableInit(_)
+        | Instr::TableCopy(_) => TrapKind::TableAccess,
+        Instr::Unreachable(_) => TrapKind::Unreachable,
+        Instr::CallIndirect(_) => TrapKind::IndirectCall,
+        _ => return None,
+    })
+}
+
+struct Finder<'a> {
+    func: FunctionId,
+    // The sequences being visited, along with the position of the next
+    // instruction in each.
+    seqs: Vec<(InstrSeqId, usize)>,
+    sites: &'a mut Vec<TrappingSite>,
+}
+
+impl<'instr> Visitor<'instr> for Finder<'_> {
+    fn start_instr_seq(&mut self, seq: &'instr InstrSeq) {
+        self.seqs.push((seq.id(), 0));
+    }
+
+    fn end_instr_seq(&mut self, _: &'instr InstrSeq) {
+        self.seqs.pop();
+    }
+
+    fn visit_instr(&mut self, instr: &'instr Instr, loc: &'instr InstrLocId) {
+        let func = self.func;
+        let (seq, index) = self.seqs.last_mut().unwrap();
+        let site = trap_kind(instr).map(|kind| TrappingSite {
+            func,
+            seq: *seq,
+            index: *index,
+            kind,
+            offset: if loc.is_default() {
+                None
+            } else {
+                Some(loc.data())
+            },
+        });
+        *index += 1;
+        self.sites.extend(site);
+    }
+}
@@ -0,0 +1,379 @@
+//! Find the types of the values on the operand stack at each instruction of a
+//! function.
+//!
+//! Instrumentation that inserts code in the middle of a function must leave
+//! the operand stack the way it found it, and saving the values on the stack
+//! to locals and restoring them afterwards, for example, needs their types.
+//!
+//! The types at the end of a `block`, `loop` or `if` are given by its type,
+//! whether it is reached by falling through or by a branch, so each sequence
+//! is only walked once.
+
+use crate::ir::*;
+use crate::{LocalFunction, Module, TableId, ValType};
+use std::collections::{HashMap, HashSet};
+
+/// The types on the operand stack at each instruction of a function, found
+/// with `run`.
+#[derive(Debug, Clone, Default)]
+pub struct TypeStack {
+    // The types on the stack before each instruction of each sequence, or
+    // `None` where the instruction is unreachable.
+    before: HashMap<InstrSeqId, Vec<Option<Vec<ValType>>>>,
+    // How many values the sequences enclosing each sequence have on the
+    // stack.
+    base: HashMap<InstrSeqId, usize>,
+}
+
+impl TypeStack {
+    /// Get the types of the values on the stack just before the instruction
+    /// at `index` in `seq`, from the bottom of the stack up.
+    ///
+    /// These include the values that the sequences enclosing `seq` have on
+    /// the stack, below the parameters of `seq`. Returns `None` if the
+    /// instruction is unreachable, since the stack there has no fixed types.
+    /// Panics if there is no such instruction.
+    pub fn before(&self, seq: InstrSeqId, index: usize) -> Option<&[ValType]> {
+        self.before[&seq][index].as_deref()
+    }
+
+    /// Get the types of just the values that `seq` itself has on the stack
+    /// before the instruction at `index`, from the bottom of the stack up.
+    ///
+    /// These start with the parameters of `seq`, and are the values that
+    /// instructions inserted there could pop. Returns `None` if the
+    /// instruction is unreachable. Panics if there is no such instruction.
+    pub fn seq_before(&self, seq: InstrSeqId, index: usize) -> Option<&[ValType]> {
+        let base = self.base[&seq];
+        Some(&self.before(seq, index)?[base..])
+    }
+}
+
+/// Find the types on the operand stack at each instruction of `func`, a
+/// function of `module`.
+///
+/// `func` must be valid, since the types are found by following what each
+/// instruction pops and pushes, rather than by checking them.
+pub fn run(module: &Module, func: &LocalFunction) -> TypeStack {
+    let mut analysis = Analysis::new(module, func, false);
+    analysis.seq(func.entry_block(), Vec::new());
+    analysis.types
+}
+
+/// Find the parameters and results that the `block`, `loop` and `if`
+/// sequences of `func` should have for their instructions, for
+/// `Module::refinalize`.
+///
+/// Only the sequences whose results need to change are returned.
+pub(crate) fn refinalize(module: &Module, func: &LocalFunction) -> SeqTypes {
+    let mut analysis = Analysis::new(module, func, true);
+    analysis.seq(func.entry_block(), Vec::new());
+    analysis.refinalized.unwrap()
+}
+
+/// The parameters and results of instruction sequences.
+pub(crate) type SeqTypes = HashMap<InstrSeqId, (Vec<ValType>, Vec<ValType>)>;
+
+struct Analysis<'a> {
+    module: &'a Module,
+    func: &'a LocalFunction,
+    types: TypeStack,
+    // The sequences that reachable branches target.
+    branched: HashSet<InstrSeqId>,
+    // When refinalizing, the new types of the sequences so far.
+    refinalized: Option<SeqTypes>,
+}
+
+impl<'a> Analysis<'a> {
+    fn new(module: &'a Module, func: &'a LocalFunction, refinalize: bool) -> Analysis<'a> {
+        Analysis {
+            module,
+            func,
+            types: TypeStack::default(),
+            branched: HashSet::new(),
+            refinalized: if refinalize {
+                Some(HashMap::new())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Get the parameters and results of a sequence.
+    fn seq_type(&self, seq: InstrSeqId) -> (&[ValType], &[ValType]) {
+        if let Some((params, results)) = self.refinalized.as_ref().and_then(|r| r.get(&seq)) {
+            return (params, results);
+        }
+        match &self.func.block(seq).ty {
+            InstrSeqType::Simple(ty) => (&[], ty.as_ref().map_or(&[], std::slice::from_ref)),
+            InstrSeqType::MultiValue(ty) => self.module.types.params_results(*ty),
+        }
+    }
+
+    /// Walk `seq`, given the types on the stack at its start, and return the
+    /// types at its end, or `None` if its end is unreachable.
+    fn seq(&mut self, seq: InstrSeqId, stack: Vec<ValType>) -> Option<Vec<ValType>> {
+        let base = stack.len().saturating_sub(self.seq_type(seq).0.len());
+        self.types.base.insert(seq, base);
+        let func = self.func;
+        let instrs = &func.block(seq).instrs;
+        let mut before = Vec::with_capacity(instrs.len());
+        let mut stack = Some(stack);
+        for (instr, _) in instrs.iter() {
+            before.push(stack.clone());
+            stack = match stack {
+                Some(stack) => self.instr(instr, stack),
+                None => None,
+            };
+        }
+        self.types.before.insert(seq, before);
+        stack
+    }
+
+    /// When refinalizing, give `seqs`, which share a type, the results that
+    /// they leave on the stack at their `end`, given the stack at their start.
+    ///
+    /// Sequences whose end is unreachable keep their results, since only the
+    /// branches to them would tell what those are.
+    fn refine(&mut self, seqs: &[InstrSeqId], start: &[ValType], end: Option<Vec<ValType>>) {
+        let end = match (&self.refinalized, end) {
+            (Some(_), Some(end)) => end,
+            _ => return,
+        };
+        let (params, results) = self.seq_type(seqs[0]);
+        let base = start.len().saturating_sub(params.len()).min(end.len());
+        if end[base..] == *results {
+            return;
+        }
+        let ty = (params.to_vec(), end[base..].to_vec());
+        let refinalized = self.refinalized.as_mut().unwrap();
+        for seq in seqs {
+            refinalized.insert(*seq, ty.clone());
+        }
+    }
+
+    /// Get the types on the stack after `instr`, given the ones before it, or
+    /// `None` if the instructions after it are unreachable.
+    fn instr(&mut self, instr: &Instr, mut stack: Vec<ValType>) -> Option<Vec<ValType>> {
+        match instr {
+            Instr::Block(Block { seq }) | Instr::Loop(Loop { seq }) => {
+                let end = self.seq(*seq, stack.clone());
+                // Branches to a loop go back to its start, so only its end
+                // reaches the instructions after it.
+                let is_loop = matches!(instr, Instr::Loop(_));
+                if end.is_none() && (is_loop || !self.branched.contains(seq)) {
+                    return None;
+                }
+                self.refine(&[*seq], &stack, end);
+                let (params, results) = self.seq_type(*seq);
+                stack.truncate(stack.len().saturating_sub(params.len()));
+                stack.extend_from_slice(results);
+            }
+            Instr::IfElse(IfElse {
+                consequent,
+                alternative,
+            }) => {
+                stack.pop();
+                let consequent_end = self.seq(*consequent, stack.clone());
+                let alternative_end = self.seq(*alternative, stack.clone());
+                let end = consequent_end.or(alternative_end);
+                let branched = [consequent, alternative]
+                    .iter()
+                    .any(|seq| self.branched.contains(seq));
+                if end.is_none() && !branched {
+                    return None;
+                }
+                self.refine(&[*consequent, *alternative], &stack, end);
+                let (params, results) = self.seq_type(*consequent);
+                stack.truncate(stack.len().saturating_sub(params.len()));
+                stack.extend_from_slice(results);
+            }
+            // The value that `select` keeps is already in place below its
+            // other operands, and `local.tee` pushes back what it pops.
+            Instr::Select(_) => stack.truncate(stack.len().saturating_sub(2)),
+            Instr::LocalTee(_) => {}
+            Instr::Br(Br { block }) => {
+                self.branched.insert(*block);
+                return None;
+            }
+            Instr::BrIf(BrIf { block }) => {
+                self.branched.insert(*block);
+                stack.pop();
+            }
+            Instr::BrTable(BrTable { blocks, default }) => {
+                self.branched.extend(blocks.iter().chain(Some(default)));
+                return None;
+            }
+            #[cfg(feature = "unstable")]
+            Instr::Resume(Resume { handlers, .. }) => {
+                self.branched.extend(handlers.iter().map(|h| h.block));
+                let (pops, pushes) = self.signature(instr);
+                stack.truncate(stack.len().saturating_sub(pops));
+                stack.extend(pushes);
+            }
+            _ if instr.following_instructions_are_unreachable() => return None,
+            _ => {
+                let (pops, pushes) = self.signature(instr);
+                stack.truncate(stack.len().saturating_sub(pops));
+                stack.extend(pushes);
+            }
+        }
+        Some(stack)
+    }
+
+    /// Get how many operands `instr` pops, and the types of the results it
+    /// pushes.
+    fn signature(&self, instr: &Instr) -> (usize, Vec<ValType>) {
+        use ValType::*;
+        let module = self.module;
+        let call = |ty, extra: usize| {
+            let (params, results) = module.types.params_results(ty);
+            (params.len() + extra, results.to_vec())
+        };
+        match instr {
+            Instr::Call(Call { func }) => call(module.funcs.get(*func).ty(), 0),
+            Instr::CallIndirect(CallIndirect { ty, .. }) => call(*ty, 1),
+            Instr::LocalGet(LocalGet { local }) => (0, vec![module.locals.get(*local).ty()]),
+            Instr::GlobalGet(GlobalGet { global }) => (0, vec![module.globals.get(*global).ty]),
+            Instr::Const(Const { value }) => (0, vec![value_type(value)]),
+            Instr::Unop(Unop { op }) => (1, vec![unop_type(*op)]),
+            Instr::Binop(Binop { op }) => (2, vec![binop_type(*op)]),
+            Instr::LocalSet(_) | Instr::GlobalSet(_) | Instr::Drop(_) => (1, vec![]),
+            Instr::MemorySize(_) => (0, vec![I32]),
+            Instr::MemoryGrow(_) => (1, vec![I32]),
+            Instr::Load(Load { kind, .. }) => (1, vec![load_type(kind)]),
+            Instr::LoadSimd(_) => (1, vec![V128]),
+            Instr::Store(_) => (2, vec![]),
+            Instr::AtomicRmw(AtomicRmw { width, .. }) => (2, vec![atomic_type(width)]),
+            Instr::Cmpxchg(Cmpxchg { width, .. }) => (3, vec![atomic_type(width)]),
+            Instr::AtomicNotify(_) => (2, vec![I32]),
+            Instr::AtomicWait(_) => (3, vec![I32]),
+            Instr::TableGet(_) => (1, vec![Anyref]),
+            Instr::TableSet(_) => (2, vec![]),
+            Instr::TableGrow(TableGrow { table }) => (2, vec![self.table_index(*table)]),
+            Instr::TableSize(TableSize { table }) => (0, vec![self.table_index(*table)]),
+            Instr::MemoryInit(_)
+            | Instr::MemoryCopy(_)
+            | Instr::MemoryFill(_)
+            | Instr::TableFill(_)
+            | Instr::TableInit(_)
+            | Instr::TableCopy(_) => (3, vec![]),
+            Instr::RefNull(_) | Instr::RefFunc(_) => (0, vec![Anyref]),
+            Instr::RefIsNull(_) => (1, vec![I32]),
+            Instr::V128Bitselect(_) => (3, vec![V128]),
+            Instr::V128Swizzle(_) | Instr::V128Shuffle(_) => (2, vec![V128]),
+            #[cfg(feature = "unstable")]
+            Instr::GlobalAtomicGet(GlobalAtomicGet { global, .. }) => {
+                (0, vec![module.globals.get(*global).ty])
+            }
+            #[cfg(feature = "unstable")]
+            Instr::GlobalAtomicSet(_) => (1, vec![]),
+            #[cfg(feature = "unstable")]
+            Instr::ContNew(ContNew { ty }) => (
+                1,
+                vec![ValType::Cont {
+                    nullable: false,
+                    ty: *ty,
+                }],
+            ),
+            #[cfg(feature = "unstable")]
+            Instr::Suspend(Suspend { tag }) => call(module.tags.get(*tag).ty, 0),
+            #[cfg(feature = "unstable")]
+            Instr::Resume(Resume { ty, .. }) => match module.types.get(*ty).cont_of() {
+                Some(ty) => call(ty, 1),
+                None => (1, vec![]),
+            },
+            _ => (0, vec![]),
+        }
+    }
+
+    fn table_index(&self, table: TableId) -> ValType {
+        self.module.tables.get(table).index_type.value_type()
+    }
+}
+
+fn value_type(value: &Value) -> ValType {
+    match value {
+        Value::I32(_) => ValType::I32,
+        Value::I64(_) => ValType::I64,
+        Value::F32(_) => ValType::F32,
+        Value::F64(_) => ValType::F64,
+        Value::V128(_) => ValType::V128,
+    }
+}
+
+fn load_type(kind: &LoadKind) -> ValType {
+    match kind {
+        LoadKind::I32 { .. } | LoadKind::I32_8 { .. } | LoadKind::I32_16 { .. } => ValType::I32,
+        LoadKind::I64 { .. }
+        | LoadKind::I64_8 { .. }
+        | LoadKind::I64_16 { .. }
+        | LoadKind::I64_32 { .. } => ValType::I64,
+        LoadKind::F32 => ValType::F32,
+        LoadKind::F64 => ValType::F64,
+        LoadKind::V128 => ValType::V128,
+    }
+}
+
+fn atomic_type(width: &AtomicWidth) -> ValType {
+    match width {
+        AtomicWidth::I32 | AtomicWidth::I32_8 | AtomicWidth::I32_16 => ValType::I32,
+        _ => ValType::I64,
+    }
+}
+
+fn unop_type(op: UnaryOp) -> ValType {
+    use UnaryOp::*;
+    match op {
+        I32Eqz | I32Clz | I32Ctz | I32Popcnt | I64Eqz | I32WrapI64 | I32TruncSF32
+        | I32TruncUF32 | I32TruncSF64 | I32TruncUF64 | I32ReinterpretF32 | I32Extend8S
+        | I32Extend16S | I32TruncSSatF32 | I32TruncUSatF32 | I32TruncSSatF64 | I32TruncUSatF64 => {
+            ValType::I32
+        }
+        I64Clz | I64Ctz | I64Popcnt | I64ExtendSI32 | I64ExtendUI32 | I64TruncSF32
+        | I64TruncUF32 | I64TruncSF64 | I64TruncUF64 | I64ReinterpretF64 | I64Extend8S
+        | I64Extend16S | I64Extend32S | I64TruncSSatF32 | I64TruncUSatF32 | I64TruncSSatF64
+        | I64TruncUSatF64 => ValType::I64,
+        F32Abs | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt | F32ConvertSI32
+        | F32ConvertUI32 | F32ConvertSI64 | F32ConvertUI64 | F32DemoteF64 | F32ReinterpretI32 => {
+            ValType::F32
+        }
+        F64Abs | F64Neg | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt | F64ConvertSI32
+        | F64ConvertUI32 | F64ConvertSI64 | F64ConvertUI64 | F64PromoteF32 | F64ReinterpretI64 => {
+            ValType::F64
+        }
+        I8x16ExtractLaneS { .. }
+        | I8x16ExtractLaneU { .. }
+        | I16x8ExtractLaneS { .. }
+        | I16x8ExtractLaneU { .. }
+        | I32x4ExtractLane { .. }
+        | I8x16AnyTrue
+        | I8x16AllTrue
+        | I16x8AnyTrue
+        | I16x8AllTrue
+        | I32x4AnyTrue
+        | I32x4AllTrue
+        | I64x2AnyTrue
+        | I64x2AllTrue => ValType::I32,
+        I64x2ExtractLane { .. } => ValType::I64,
+        F32x4ExtractLane { .. } => ValType::F32,
+        F64x2ExtractLane { .. } => ValType::F64,
+        _ => ValType::V128,
+    }
+}
+
+fn binop_type(op: BinaryOp) -> ValType {
+    use BinaryOp::*;
+    match op {
+        I32Eq | I32Ne | I32LtS | I32LtU | I32GtS | I32GtU | I32LeS | I32LeU | I32GeS | I32GeU
+        | I64Eq | I64Ne | I64LtS | I64LtU | I64GtS | I64GtU | I64LeS | I64LeU | I64GeS | I64GeU
+        | F32Eq | F32Ne | F32Lt | F32Gt | F32Le | F32Ge | F64Eq | F64Ne | F64Lt | F64Gt | F64Le
+        | F64Ge | I32Add | I32Sub | I32Mul | I32DivS | I32DivU | I32RemS | I32RemU | I32And
+        | I32Or | I32Xor | I32Shl | I32ShrS | I32ShrU | I32Rotl | I32Rotr => ValType::I32,
+        I64Add | I64Sub | I64Mul | I64DivS | I64DivU | I64RemS | I64RemU | I64And | I64Or
+        | I64Xor | I64Shl | I64ShrS | I64ShrU | I64Rotl | I64Rotr => ValType::I64,
+        F32Add | F32Sub | F32Mul | F32Div | F32Min | F32Max | F32Copysign => ValType::F32,
+        F64Add | F64Sub | F64Mul | F64Div | F64Min | F64Max | F64Copysign => ValType::F64,
+        _ => ValType::V128,
+    }
+}
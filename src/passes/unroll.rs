@@ -0,0 +1,370 @@
+//! Fully unroll small loops with constant trip counts.
+//!
+//! Kernels like block ciphers and hashes are full of loops that run a fixed,
+//! small number of times, and unrolling them removes the compare and branch
+//! of every iteration, and lets engines schedule the iterations together.
+//! This pass unrolls loops of the shape that compilers emit for counted
+//! loops:
+//!
+//! ```text
+//! i32.const <init>
+//! local.set $i
+//! loop $l
+//!   <body>
+//!   local.get $i
+//!   i32.const <step>
+//!   i32.add
+//!   local.tee $i            ;; or `local.set $i`, `local.get $i`
+//!   i32.const <bound>
+//!   i32.lt_s                ;; or `i32.lt_u`, `i32.ne`
+//!   br_if $l
+//! end
+//! ```
+//!
+//! where the body doesn't write `$i` and doesn't branch back to `$l` itself.
+//! The loop becomes a block with one copy of the body per iteration, each
+//! followed by the increment of `$i`, so that `$i` has the same value as
+//! before after the loop.
+//!
+//! Unrolling trades code size for speed, so loops are only unrolled when
+//! that grows the code by at most `Options::max_growth`, according to a cost
+//! model.
+
+use crate::ir::*;
+use crate::{LocalFunction, Module};
+use std::collections::HashMap;
+
+/// Which loops to unroll.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Only unroll loops that run at most this many times.
+    pub max_trip_count: u32,
+
+    /// Only unroll loops when the cost of the copies of the loop's body,
+    /// beyond the first, is at most this much.
+    pub max_growth: u64,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            max_trip_count: 16,
+            max_growth: 512,
+        }
+    }
+}
+
+/// Unroll the counted loops of the local functions of `module` that
+/// `options` allows, with growth measured by `model`.
+///
+/// Inner loops are unrolled before the loops around them. Returns the number
+/// of unrolled loops.
+pub fn run(module: &mut Module, model: &impl InstrCost, options: &Options) -> usize {
+    let mut unrolled = 0;
+    for (_, func) in module.funcs.iter_local_mut() {
+        while let Some(counted) = find(func, model, options) {
+            unroll(func, counted);
+            unrolled += 1;
+        }
+    }
+    unrolled
+}
+
+/// A counted loop that can be unrolled: the `Loop` at `position` in `parent`,
+/// whose body is the first `body_len` instructions of `looping`.
+struct Counted {
+    parent: InstrSeqId,
+    position: usize,
+    looping: InstrSeqId,
+    body_len: usize,
+    counter: LocalId,
+    step: i32,
+    trips: u32,
+}
+
+/// Find the innermost counted loop in `func` that `options` allows
+/// unrolling.
+fn find(func: &LocalFunction, model: &impl InstrCost, options: &Options) -> Option<Counted> {
+    let mut seqs = Vec::new();
+    dfs_in_order(&mut CollectSeqs(&mut seqs), func, func.entry_block());
+    for &parent in seqs.iter().rev() {
+        let instrs = &func.block(parent).instrs;
+        for position in (0..instrs.len()).rev() {
+            let counted = match counted(func, parent, position, options) {
+                Some(counted) => counted,
+                None => continue,
+            };
+            let cost = cost(func, model, &func.block(counted.looping).instrs);
+            let growth = cost.saturating_mul(u64::from(counted.trips - 1));
+            if growth <= options.max_growth {
+                return Some(counted);
+            }
+        }
+    }
+    None
+}
+
+/// Match the counted loop shape at `position` in `parent`.
+fn counted(
+    func: &LocalFunction,
+    parent: InstrSeqId,
+    position: usize,
+    options: &Options,
+) -> Option<Counted> {
+    let parent_instrs = &func.block(parent).instrs;
+    let looping = match parent_instrs[position].0 {
+        Instr::Loop(Loop { seq }) => seq,
+        _ => return None,
+    };
+    let (init, counter) = match parent_instrs.get(position.checked_sub(2)?..position)? {
+        [(
+            Instr::Const(Const {
+                value: Value::I32(init),
+            }),
+            _,
+        ), (Instr::LocalSet(LocalSet { local }), _)] => (*init, *local),
+        _ => return None,
+    };
+    let seq = func.block(looping);
+    if seq.ty != InstrSeqType::Simple(None) {
+        return None;
+    }
+
+    let instrs = &seq.instrs;
+    let is_counter = |local: &LocalId| *local == counter;
+    let tail = |len: usize| instrs.get(instrs.len().checked_sub(len)?..);
+    let (step, bound, cmp, tail_len) = match (tail(7), tail(8)) {
+        (
+            Some(
+                [(Instr::LocalGet(get), _), (Instr::Const(step), _), (Instr::Binop(add), _), (Instr::LocalTee(tee), _), (Instr::Const(bound), _), (Instr::Binop(cmp), _), (Instr::BrIf(br), _)],
+            ),
+            _,
+        ) if is_counter(&get.local)
+            && is_counter(&tee.local)
+            && add.op == BinaryOp::I32Add
+            && br.block == looping =>
+        {
+            (step.value, bound.value, cmp.op, 7)
+        }
+        (
+            _,
+            Some(
+                [(Instr::LocalGet(get), _), (Instr::Const(step), _), (Instr::Binop(add), _), (Instr::LocalSet(set), _), (Instr::LocalGet(get2), _), (Instr::Const(bound), _), (Instr::Binop(cmp), _), (Instr::BrIf(br), _)],
+            ),
+        ) if is_counter(&get.local)
+            && is_counter(&set.local)
+            && is_counter(&get2.local)
+            && add.op == BinaryOp::I32Add
+            && br.block == looping =>
+        {
+            (step.value, bound.value, cmp.op, 8)
+        }
+        _ => return None,
+    };
+    let (step, bound) = match (step, bound) {
+        (Value::I32(step), Value::I32(bound)) => (step, bound),
+        _ => return None,
+    };
+    let body_len = instrs.len() - tail_len;
+    if !body_ok(func, &instrs[..body_len], counter, looping) {
+        return None;
+    }
+    let trips = trips(init, step, bound, cmp, options.max_trip_count)?;
+    Some(Counted {
+        parent,
+        position,
+        looping,
+        body_len,
+        counter,
+        step,
+        trips,
+    })
+}
+
+/// How many times a loop runs, counting from `init` by `step` while `cmp` of
+/// the counter and `bound` holds, if that is at most `max`.
+fn trips(init: i32, step: i32, bound: i32, cmp: BinaryOp, max: u32) -> Option<u32> {
+    let holds = |i: i32| match cmp {
+        BinaryOp::I32LtS => i < bound,
+        BinaryOp::I32LtU => (i as u32) < (bound as u32),
+        BinaryOp::I32Ne => i != bound,
+        _ => unreachable!(),
+    };
+    if !matches!(cmp, BinaryOp::I32LtS | BinaryOp::I32LtU | BinaryOp::I32Ne) {
+        return None;
+    }
+    // The body runs once before the first comparison.
+    let mut i = init;
+    for trips in 1..=max {
+        i = i.wrapping_add(step);
+        if !holds(i) {
+            return Some(trips);
+        }
+    }
+    None
+}
+
+/// Whether `instrs`, and the sequences nested in them, neither write
+/// `counter` nor branch to `looping`.
+fn body_ok(
+    func: &LocalFunction,
+    instrs: &[(Instr, InstrLocId)],
+    counter: LocalId,
+    looping: InstrSeqId,
+) -> bool {
+    instrs.iter().all(|(instr, _)| match instr {
+        Instr::LocalSet(LocalSet { local }) | Instr::LocalTee(LocalTee { local }) => {
+            *local != counter
+        }
+        Instr::Br(Br { block }) | Instr::BrIf(BrIf { block }) => *block != looping,
+        Instr::BrTable(BrTable { blocks, default }) => {
+            *default != looping && !blocks.contains(&looping)
+        }
+        #[cfg(feature = "unstable")]
+        Instr::Resume(Resume { handlers, .. }) => handlers.iter().all(|h| h.block != looping),
+        Instr::Block(Block { seq }) | Instr::Loop(Loop { seq }) => {
+            body_ok(func, &func.block(*seq).instrs, counter, looping)
+        }
+        Instr::IfElse(IfElse {
+            consequent,
+            alternative,
+        }) => {
+            body_ok(func, &func.block(*consequent).instrs, counter, looping)
+                && body_ok(func, &func.block(*alternative).instrs, counter, looping)
+        }
+        _ => true,
+    })
+}
+
+/// The cost of executing `instrs`, and the sequences nested in them, once.
+fn cost(func: &LocalFunction, model: &impl InstrCost, instrs: &[(Instr, InstrLocId)]) -> u64 {
+    instrs
+        .iter()
+        .map(|(instr, _)| {
+            let nested = match instr {
+                Instr::Block(Block { seq }) | Instr::Loop(Loop { seq }) => {
+                    cost(func, model, &func.block(*seq).instrs)
+                }
+                Instr::IfElse(IfElse {
+                    consequent,
+                    alternative,
+                }) => {
+                    cost(func, model, &func.block(*consequent).instrs)
+                        + cost(func, model, &func.block(*alternative).instrs)
+                }
+                _ => 0,
+            };
+            model.cost(instr) + nested
+        })
+        .sum()
+}
+
+/// Replace the counted loop with a block of copies of its body.
+fn unroll(func: &mut LocalFunction, counted: Counted) {
+    let mut instrs = std::mem::take(&mut func.block_mut(counted.looping).instrs);
+    let loc = instrs[counted.body_len].1;
+    instrs.truncate(counted.body_len);
+    let increment: [Instr; 4] = [
+        LocalGet {
+            local: counted.counter,
+        }
+        .into(),
+        Const {
+            value: Value::I32(counted.step),
+        }
+        .into(),
+        Binop {
+            op: BinaryOp::I32Add,
+        }
+        .into(),
+        LocalSet {
+            local: counted.counter,
+        }
+        .into(),
+    ];
+
+    let mut unrolled = Vec::new();
+    for trip in 0..counted.trips {
+        if trip + 1 == counted.trips {
+            unrolled.append(&mut instrs);
+        } else {
+            let copy = clone_instrs(func, &instrs, &mut HashMap::new());
+            unrolled.extend(copy);
+        }
+        unrolled.extend(increment.iter().cloned().map(|instr: Instr| (instr, loc)));
+    }
+    func.block_mut(counted.looping).instrs = unrolled;
+    func.block_mut(counted.parent).instrs[counted.position].0 = Block {
+        seq: counted.looping,
+    }
+    .into();
+}
+
+/// Copy `instrs`, along with the sequences nested in them, which are
+/// recorded in `copies`.
+fn clone_instrs(
+    func: &mut LocalFunction,
+    instrs: &[(Instr, InstrLocId)],
+    copies: &mut HashMap<InstrSeqId, InstrSeqId>,
+) -> Vec<(Instr, InstrLocId)> {
+    let mut cloned = Vec::with_capacity(instrs.len());
+    for (instr, loc) in instrs {
+        let mut instr = instr.clone();
+        match &mut instr {
+            Instr::Block(Block { seq }) | Instr::Loop(Loop { seq }) => copy_seq(func, seq, copies),
+            Instr::IfElse(IfElse {
+                consequent,
+                alternative,
+            }) => {
+                copy_seq(func, consequent, copies);
+                copy_seq(func, alternative, copies);
+            }
+            Instr::Br(Br { block }) | Instr::BrIf(BrIf { block }) => {
+                if let Some(copy) = copies.get(block) {
+                    *block = *copy;
+                }
+            }
+            Instr::BrTable(BrTable { blocks, default }) => {
+                for block in blocks.iter_mut().chain(Some(default)) {
+                    if let Some(copy) = copies.get(block) {
+                        *block = *copy;
+                    }
+                }
+            }
+            #[cfg(feature = "unstable")]
+            Instr::Resume(Resume { handlers, .. }) => {
+                for handler in handlers.iter_mut() {
+                    if let Some(copy) = copies.get(&handler.block) {
+                        handler.block = *copy;
+                    }
+                }
+            }
+            _ => {}
+        }
+        cloned.push((instr, *loc));
+    }
+    cloned
+}
+
+/// Replace `seq` with a copy of it.
+fn copy_seq(
+    func: &mut LocalFunction,
+    seq: &mut InstrSeqId,
+    copies: &mut HashMap<InstrSeqId, InstrSeqId>,
+) {
+    let ty = func.block(*seq).ty;
+    let copy = func.builder_mut().dangling_instr_seq(ty).id();
+    copies.insert(*seq, copy);
+    let instrs = func.block(*seq).instrs.clone();
+    let instrs = clone_instrs(func, &instrs, copies);
+    func.block_mut(copy).instrs = instrs;
+    *seq = copy;
+}
+
+struct CollectSeqs<'a>(&'a mut Vec<InstrSeqId>);
+
+impl<'instr> Visitor<'instr> for CollectSeqs<'_> {
+    fn start_instr_seq(&mut self, seq: &'instr InstrSeq) {
+        self.0.push(seq.id());
+    }
+}
@@ -1,18 +1,21 @@
 use crate::ir::*;
 use crate::map::IdHashSet;
-use crate::{ActiveDataLocation, Data, DataId, DataKind, Element, ExportItem, Function, InitExpr};
+use crate::{ActiveDataLocation, Data, DataId, DataKind, Element, ElementId};
+use crate::{CancellationToken, Module, Result, TableKind, Type, TypeId};
+use crate::{Export, ExportId, ExportItem, Function, InitExpr};
 use crate::{FunctionId, FunctionKind, Global, GlobalId};
 use crate::{GlobalKind, ImportKind, Memory, MemoryId, Table, TableId};
-use crate::{Module, TableKind, Type, TypeId};
 
 /// Set of all root used items in a wasm module.
 #[derive(Debug, Default)]
 pub struct Roots {
-    tables: Vec<TableId>,
-    funcs: Vec<FunctionId>,
-    globals: Vec<GlobalId>,
-    memories: Vec<MemoryId>,
+    pub(super) tables: Vec<TableId>,
+    pub(super) funcs: Vec<FunctionId>,
+    pub(super) globals: Vec<GlobalId>,
+    pub(super) memories: Vec<MemoryId>,
     datas: Vec<DataId>,
+    elements: Vec<ElementId>,
+    pub(super) ignored_exports: IdHashSet<Export>,
     used: Used,
 }
 
@@ -58,6 +61,15 @@ impl Roots {
         self
     }
 
+    /// Don't treat an export as a root.
+    ///
+    /// The exported item is still kept if anything else uses it, and
+    /// otherwise the gc pass removes it along with the export.
+    pub fn ignore_export(&mut self, export: ExportId) -> &mut Roots {
+        self.ignored_exports.insert(export);
+        self
+    }
+
     fn push_data(&mut self, data: DataId) -> &mut Roots {
         if self.used.data.insert(data) {
             log::trace!("data is used: {:?}", data);
@@ -65,6 +77,14 @@ impl Roots {
         }
         self
     }
+
+    fn push_element(&mut self, element: ElementId) -> &mut Roots {
+        if self.used.elements.insert(element) {
+            log::trace!("element is used: {:?}", element);
+            self.elements.push(element);
+        }
+        self
+    }
 }
 
 /// Finds the things within a module that are used.
@@ -88,16 +108,33 @@ pub struct Used {
     pub elements: IdHashSet<Element>,
     /// The module's used passive data segments.
     pub data: IdHashSet<Data>,
+    /// The module's used tags.
+    #[cfg(feature = "unstable")]
+    pub tags: IdHashSet<crate::Tag>,
 }
 
 impl Used {
-    /// Construct a new `Used` set for the given module.
-    pub fn new(module: &Module) -> Used {
+    /// Construct a new `Used` set for the given module, starting from `roots`
+    /// in addition to the module's own roots.
+    pub fn new(module: &Module, roots: Roots) -> Used {
+        Used::compute(module, roots, None).expect("computing can only fail when cancelled")
+    }
+
+    /// Like `new`, but fails with `ErrorKind::Cancelled` once `cancel` is
+    /// cancelled.
+    pub(crate) fn compute(
+        module: &Module,
+        roots: Roots,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Used> {
         log::debug!("starting to calculate used set");
-        let mut stack = Roots::default();
+        let mut stack = roots;
 
-        // All exports are roots
+        // All exports are roots, unless they are ignored
         for export in module.exports.iter() {
+            if stack.ignored_exports.contains(&export.id()) {
+                continue;
+            }
             match export.item {
                 ExportItem::Function(f) => stack.push_func(f),
                 ExportItem::Table(t) => stack.push_table(t),
@@ -148,8 +185,10 @@ impl Used {
             || stack.memories.len() > 0
             || stack.globals.len() > 0
             || stack.datas.len() > 0
+            || !stack.elements.is_empty()
         {
             while let Some(f) = stack.funcs.pop() {
+                CancellationToken::check(cancel)?;
                 let func = module.funcs.get(f);
                 stack.used.types.insert(func.ty());
 
@@ -157,6 +196,8 @@ impl Used {
                     FunctionKind::Local(func) => {
                         let mut visitor = UsedVisitor { stack: &mut stack };
                         dfs_in_order(&mut visitor, func, func.entry_block());
+                        #[cfg(feature = "unstable")]
+                        stack.used.types.extend(referenced_types(module, func));
                     }
                     FunctionKind::Import(_) => {}
                     FunctionKind::Uninitialized(_) => unreachable!(),
@@ -185,6 +226,11 @@ impl Used {
             }
 
             while let Some(t) = stack.globals.pop() {
+                #[cfg(feature = "unstable")]
+                stack
+                    .used
+                    .types
+                    .extend(module.globals.get(t).ty.referenced_type());
                 match &module.globals.get(t).kind {
                     GlobalKind::Import(_) => {}
                     GlobalKind::Local(InitExpr::Global(global)) => {
@@ -209,12 +255,64 @@ impl Used {
                     }
                 }
             }
+
+            while let Some(e) = stack.elements.pop() {
+                for func in module.elements.get(e).members.iter().flatten() {
+                    stack.push_func(*func);
+                }
+            }
+        }
+
+        // Tags refer to function types, and types can refer to other types,
+        // which must be kept as well.
+        #[cfg(feature = "unstable")]
+        {
+            for tag in stack.used.tags.iter() {
+                stack.used.types.insert(module.tags.get(*tag).ty);
+            }
+            let mut tys = stack.used.types.iter().copied().collect::<Vec<_>>();
+            while let Some(ty) = tys.pop() {
+                for referenced in module.types.get(ty).referenced_types() {
+                    if stack.used.types.insert(referenced) {
+                        tys.push(referenced);
+                    }
+                }
+            }
         }
 
-        stack.used
+        Ok(stack.used)
     }
 }
 
+/// The continuation types that the locals and blocks of `func` refer to.
+#[cfg(feature = "unstable")]
+pub(crate) fn referenced_types(module: &Module, func: &crate::LocalFunction) -> Vec<TypeId> {
+    struct Referenced<'a> {
+        module: &'a Module,
+        tys: Vec<TypeId>,
+    }
+
+    impl<'instr> Visitor<'instr> for Referenced<'_> {
+        fn start_instr_seq(&mut self, seq: &'instr InstrSeq) {
+            if let InstrSeqType::Simple(Some(ty)) = seq.ty {
+                self.tys.extend(ty.referenced_type());
+            }
+        }
+
+        fn visit_local_id(&mut self, &local: &LocalId) {
+            let ty = self.module.locals.get(local).ty();
+            self.tys.extend(ty.referenced_type());
+        }
+    }
+
+    let mut referenced = Referenced {
+        module,
+        tys: Vec::new(),
+    };
+    dfs_in_order(&mut referenced, func, func.entry_block());
+    referenced.tys
+}
+
 struct UsedVisitor<'a> {
     stack: &'a mut Roots,
 }
@@ -243,4 +341,13 @@ impl<'expr> Visitor<'expr> for UsedVisitor<'_> {
     fn visit_data_id(&mut self, &d: &DataId) {
         self.stack.push_data(d);
     }
+
+    fn visit_element_id(&mut self, &e: &ElementId) {
+        self.stack.push_element(e);
+    }
+
+    #[cfg(feature = "unstable")]
+    fn visit_tag_id(&mut self, &t: &crate::TagId) {
+        self.stack.used.tags.insert(t);
+    }
 }
@@ -5,8 +5,8 @@
 
 use crate::ir::*;
 use crate::ValType;
-use crate::{Function, FunctionKind, InitExpr, Result};
-use crate::{Global, GlobalKind, Memory, MemoryId, Module, Table, TableKind};
+use crate::{ErrorKind, Function, FunctionKind, InitExpr, ModuleConfig, Result};
+use crate::{Global, GlobalKind, IndexType, Memory, MemoryId, Module, Table, TableKind};
 use anyhow::{anyhow, bail, Context};
 use std::collections::HashSet;
 
@@ -15,6 +15,10 @@ use rayon::prelude::*;
 
 /// Validate a wasm module, returning an error if it fails to validate.
 pub fn run(module: &Module) -> Result<()> {
+    validate(module).map_err(|e| ErrorKind::Validate.classify(e))
+}
+
+fn validate(module: &Module) -> Result<()> {
     log::debug!("validating module");
 
     if module.config.only_stable_features {
@@ -24,13 +28,16 @@ pub fn run(module: &Module) -> Result<()> {
         if module.memories.iter().count() > 1 {
             bail!("multiple memories not allowed in the wasm spec yet");
         }
+        if module.tables.iter().any(|t| t.index_type == IndexType::I64) {
+            bail!("64-bit tables not allowed in the wasm spec yet");
+        }
     }
 
     for memory in module.memories.iter() {
         validate_memory(memory)?;
     }
     for table in module.tables.iter() {
-        validate_table(table)?;
+        validate_table(module, table)?;
     }
     for global in module.globals.iter() {
         validate_global(module, global)?;
@@ -79,6 +86,135 @@ pub fn run(module: &Module) -> Result<()> {
     bail!("{}", msg)
 }
 
+/// Validate a wasm binary against the specification, with wasmparser's
+/// validator, before it is parsed with `config`.
+///
+/// Proposals that walrus supports are enabled, unless `config` only allows
+/// stable features. Errors name the offset at which validation failed, and
+/// the function whose body contains it, if any.
+pub(crate) fn binary(wasm: &[u8], config: &ModuleConfig) -> Result<()> {
+    let proposals = !config.only_stable_features;
+    let config = wasmparser::ValidatingParserConfig {
+        operator_config: wasmparser::OperatorValidatorConfig {
+            enable_threads: proposals,
+            enable_reference_types: proposals,
+            enable_simd: proposals,
+            enable_bulk_memory: proposals,
+            enable_multi_value: proposals,
+        },
+    };
+    let err = match wasmparser::validate(wasm, Some(config)) {
+        Ok(()) => return Ok(()),
+        Err(err) => err,
+    };
+    #[cfg(feature = "unstable")]
+    {
+        if proposals && valid_with_unstable_proposals(wasm) {
+            return Ok(());
+        }
+    }
+    let kind = match unsupported_feature(err.message) {
+        Some(feature) => ErrorKind::Unsupported { feature },
+        None if decodes(wasm) => ErrorKind::Validate,
+        None => ErrorKind::Parse,
+    };
+    let msg = match function_at(wasm, err.offset) {
+        Some(index) => format!(
+            "invalid wasm at offset {:#x}, in the body of function {}: {}",
+            err.offset, index, err.message
+        ),
+        None => format!("invalid wasm at offset {:#x}: {}", err.offset, err.message),
+    };
+    Err(kind).context(msg)
+}
+
+/// Whether `wasm` is valid with the in-progress proposals behind the
+/// `unstable` feature enabled, which wasmparser's validator doesn't know
+/// about.
+///
+/// This only decides whether a module that wasmparser rejects is valid after
+/// all, and errors are still reported as wasmparser gives them. The newer
+/// wasmparser doesn't know about `pause` either, so modules with it still
+/// have to be parsed without strict validation.
+#[cfg(feature = "unstable")]
+fn valid_with_unstable_proposals(wasm: &[u8]) -> bool {
+    use wasmparser_next::WasmFeatures;
+    let features = WasmFeatures::WASM2
+        | WasmFeatures::THREADS
+        | WasmFeatures::SHARED_EVERYTHING_THREADS
+        | WasmFeatures::STACK_SWITCHING
+        | WasmFeatures::FUNCTION_REFERENCES
+        | WasmFeatures::EXCEPTIONS;
+    wasmparser_next::Validator::new_with_features(features)
+        .validate_all(wasm)
+        .is_ok()
+}
+
+/// The feature that isn't supported, or isn't enabled, according to one of
+/// wasmparser's validation error messages.
+fn unsupported_feature(message: &str) -> Option<&'static str> {
+    let features = [
+        ("threads support", "threads"),
+        ("reference types support", "reference-types"),
+        ("SIMD support", "simd"),
+        ("bulk memory support", "bulk-memory"),
+        ("multi-value", "multi-value"),
+        ("returns multiple values", "multi-value"),
+        ("Bad version number", "versions of wasm other than 1"),
+    ];
+    features
+        .iter()
+        .find(|(needle, _)| message.contains(needle))
+        .map(|(_, feature)| *feature)
+}
+
+/// Whether `wasm` decodes without errors when it isn't validated, so that an
+/// error validating it is about its contents rather than its encoding.
+fn decodes(wasm: &[u8]) -> bool {
+    use wasmparser::WasmDecoder;
+    let mut parser = wasmparser::Parser::new(wasm);
+    loop {
+        match parser.read() {
+            wasmparser::ParserState::EndWasm => return true,
+            wasmparser::ParserState::Error(_) => return false,
+            _ => {}
+        }
+    }
+}
+
+/// The index of the function whose body contains `offset` in `wasm`.
+fn function_at(wasm: &[u8], offset: usize) -> Option<u32> {
+    let mut reader = wasmparser::ModuleReader::new(wasm).ok()?;
+    let mut imports = 0;
+    while !reader.eof() {
+        let section = reader.read().ok()?;
+        match section.code {
+            wasmparser::SectionCode::Import => {
+                for import in section.get_import_section_reader().ok()? {
+                    if let wasmparser::ImportSectionEntryType::Function(_) = import.ok()?.ty {
+                        imports += 1;
+                    }
+                }
+            }
+            wasmparser::SectionCode::Code => {
+                for (i, body) in section
+                    .get_code_section_reader()
+                    .ok()?
+                    .into_iter()
+                    .enumerate()
+                {
+                    let range = body.ok()?.range();
+                    if range.start <= offset && offset < range.end {
+                        return Some(imports + i as u32);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
 fn validate_memory(m: &Memory) -> Result<()> {
     if m.shared && m.maximum.is_none() {
         bail!("shared memories must have a maximum size");
@@ -88,9 +224,22 @@ fn validate_memory(m: &Memory) -> Result<()> {
     Ok(())
 }
 
-fn validate_table(t: &Table) -> Result<()> {
+fn validate_table(module: &Module, t: &Table) -> Result<()> {
     validate_limits(t.initial, t.maximum, u32::max_value()).context("when validating a table")?;
 
+    // Offsets of active element segments have the table's index type.
+    if let TableKind::Function(table) = &t.kind {
+        let index_ty = t.index_type.value_type();
+        for (global, _) in table.relative_elements.iter() {
+            if module.globals.get(*global).ty != index_ty {
+                bail!(
+                    "element segment offset must have type {}, the index type of its table",
+                    index_ty
+                );
+            }
+        }
+    }
+
     // Ensure that the table element type is `anyfunc`. This does
     // nothing, but if new wasm versions and future parity-wasm releases
     // get support for new table types, this may need to actually do
@@ -193,6 +342,21 @@ impl Validate<'_> {
         }
     }
 
+    #[cfg(feature = "unstable")]
+    fn require_atomic_global(&mut self, g: crate::GlobalId) {
+        match self.module.globals.get(g).ty {
+            ValType::I32 | ValType::I64 => {}
+            _ => self.err("atomic global accesses require an `i32` or `i64` global"),
+        }
+    }
+
+    #[cfg(feature = "unstable")]
+    fn require_cont_type(&mut self, ty: crate::TypeId) {
+        if self.module.types.get(ty).cont_of().is_none() {
+            self.err("expected a continuation type");
+        }
+    }
+
     fn err(&mut self, msg: &str) {
         let mut err = anyhow!("{}", msg);
         if let Some(name) = &self.function.name {
@@ -242,4 +406,51 @@ impl<'a> Visitor<'a> for Validate<'a> {
             self.err("cannot mutate immutable global");
         }
     }
+
+    #[cfg(feature = "unstable")]
+    fn visit_cont_new(&mut self, e: &ContNew) {
+        self.require_cont_type(e.ty);
+    }
+
+    #[cfg(feature = "unstable")]
+    fn visit_resume(&mut self, e: &Resume) {
+        self.require_cont_type(e.ty);
+    }
+
+    #[cfg(feature = "unstable")]
+    fn visit_suspend(&mut self, e: &Suspend) {
+        let ty = self.module.tags.get(e.tag).ty;
+        if self.module.types.get(ty).cont_of().is_some() {
+            self.err("tags must have a function type");
+        }
+    }
+
+    #[cfg(feature = "unstable")]
+    fn visit_global_atomic_get(&mut self, e: &GlobalAtomicGet) {
+        self.require_atomic_global(e.global);
+    }
+
+    #[cfg(feature = "unstable")]
+    fn visit_global_atomic_set(&mut self, e: &GlobalAtomicSet) {
+        self.require_atomic_global(e.global);
+        if !self.module.globals.get(e.global).mutable {
+            self.err("cannot mutate immutable global");
+        }
+    }
+
+    fn visit_table_init(&mut self, e: &TableInit) {
+        if let TableKind::Anyref(_) = self.module.tables.get(e.table).kind {
+            self.err("cannot initialize an anyref table with an element segment");
+        }
+    }
+
+    fn visit_table_copy(&mut self, e: &TableCopy) {
+        let src = &self.module.tables.get(e.src).kind;
+        let dst = &self.module.tables.get(e.dst).kind;
+        match (src, dst) {
+            (TableKind::Function(_), TableKind::Function(_))
+            | (TableKind::Anyref(_), TableKind::Anyref(_)) => {}
+            _ => self.err("cannot copy between tables of different element types"),
+        }
+    }
 }
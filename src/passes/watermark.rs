@@ -0,0 +1,346 @@
+//! Embed watermarks in modules, and detect them again.
+//!
+//! A watermark is a short byte string, like a build or customer id, that is
+//! hidden in a module so that copies of it can be traced back to where they
+//! came from. Unlike a custom section, it isn't visible in the module's
+//! structure, and survives stripping custom sections and names, renaming
+//! exports and imports, and re-encoding the module. There are two methods:
+//!
+//! * `Method::Ordering` encodes the watermark in the relative order of pairs
+//!   of local functions. It adds nothing to the module, but tools that
+//!   reorder functions, including walrus when the module is emitted again
+//!   without an explicit order, destroy it.
+//! * `Method::Constants` adds `i64.const` instructions whose values are
+//!   dropped to the start of local functions. It survives reordering, but
+//!   optimizers that remove the dead constants destroy it.
+//!
+//! Both are keyed: which functions are paired and which constants are part
+//! of the watermark depends on a secret key, without which the watermark
+//! can't be found or forged. The watermark is embedded along with its
+//! length and a checksum, so that `detect` only finds watermarks that were
+//! really embedded with the same key.
+//!
+//! Watermarks should be embedded last, after all other transformations.
+
+use crate::emit::IdsToIndices;
+use crate::encode::Encoder;
+use crate::ir::{dfs_in_order, Const, Drop, Instr, InstrSeq, Local, Value, Visitor};
+use crate::map::IdHashMap;
+use crate::{FunctionId, FunctionKind, LocalFunction, Module, Result};
+use anyhow::bail;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hasher;
+
+/// How a watermark is embedded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// In the order of local functions.
+    Ordering,
+    /// In constants at the start of local functions.
+    Constants,
+}
+
+/// The longest watermark that can be embedded.
+pub const MAX_LEN: usize = 255;
+
+/// How many bytes long a watermark embedded with `method` can be in
+/// `module`.
+pub fn capacity(module: &Module, method: Method) -> usize {
+    match method {
+        // Each pair holds a bit, and the length and checksum take two bytes.
+        Method::Ordering => (pairs(module, 0).len() / 8).saturating_sub(2).min(MAX_LEN),
+        Method::Constants if module.funcs.iter_local().next().is_some() => MAX_LEN,
+        Method::Constants => 0,
+    }
+}
+
+/// Embed `watermark` in `module` with `method`, keyed by `key`.
+///
+/// Fails if the watermark is longer than `capacity` allows.
+///
+/// `Method::Ordering` replaces any order previously set with
+/// `ModuleFunctions::set_emit_order`.
+pub fn embed(module: &mut Module, method: Method, key: u64, watermark: &[u8]) -> Result<()> {
+    let available = capacity(module, method);
+    if watermark.len() > available {
+        bail!(
+            "a watermark of {} bytes doesn't fit in this module, which has room for {} bytes",
+            watermark.len(),
+            available
+        );
+    }
+    let framed = frame(key, watermark);
+    match method {
+        Method::Ordering => {
+            let mut order = Vec::new();
+            for (i, (a, b)) in pairs(module, key).into_iter().enumerate() {
+                let byte = framed.get(i / 8).copied().unwrap_or(0);
+                let bit = byte & (1 << (i % 8)) != 0;
+                let (first, second) = if bit { (b, a) } else { (a, b) };
+                order.push(first);
+                order.push(second);
+            }
+            module.funcs.set_emit_order(order);
+        }
+        Method::Constants => {
+            let funcs = keyed_functions(module, key);
+            for (i, &byte) in framed.iter().enumerate() {
+                let func = funcs[i % funcs.len()];
+                let func = match &mut module.funcs.get_mut(func).kind {
+                    FunctionKind::Local(func) => func,
+                    _ => unreachable!(),
+                };
+                let entry = func.entry_block();
+                let value = Value::I64(constant(key, i, byte) as i64);
+                func.builder_mut()
+                    .instr_seq(entry)
+                    .instr_at(0, Const { value })
+                    .instr_at(1, Drop {});
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Detect a watermark embedded in `module` by `embed` with `method` and
+/// `key`.
+///
+/// Returns `None` if there is no such watermark, or if it was damaged.
+pub fn detect(module: &Module, method: Method, key: u64) -> Option<Vec<u8>> {
+    let mut framed = Vec::new();
+    match method {
+        Method::Ordering => {
+            let positions = positions(module);
+            for (i, (a, b)) in pairs(module, key).into_iter().enumerate() {
+                if i % 8 == 0 {
+                    framed.push(0);
+                }
+                if positions[&a] > positions[&b] {
+                    *framed.last_mut().unwrap() |= 1 << (i % 8);
+                }
+            }
+        }
+        Method::Constants => {
+            let mut found = FindConstants::default();
+            for (_, func) in module.funcs.iter_local() {
+                dfs_in_order(&mut found, func, func.entry_block());
+            }
+            // Only the constants that the key vouches for count.
+            let bytes = found
+                .values
+                .into_iter()
+                .filter_map(|value| {
+                    let i = ((value >> 8) & 0xffff) as usize;
+                    let byte = value as u8;
+                    if constant(key, i, byte) == value {
+                        Some((i, byte))
+                    } else {
+                        None
+                    }
+                })
+                .collect::<HashMap<_, _>>();
+            for i in 0.. {
+                match bytes.get(&i) {
+                    Some(byte) => framed.push(*byte),
+                    None => break,
+                }
+            }
+        }
+    }
+    unframe(key, &framed)
+}
+
+/// Prefix `watermark` with its length, and suffix it with a keyed checksum.
+fn frame(key: u64, watermark: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(watermark.len() + 2);
+    framed.push(watermark.len() as u8);
+    framed.extend_from_slice(watermark);
+    framed.push(checksum(key, watermark));
+    framed
+}
+
+fn unframe(key: u64, framed: &[u8]) -> Option<Vec<u8>> {
+    let len = usize::from(*framed.first()?);
+    let watermark = framed.get(1..len + 1)?;
+    if *framed.get(len + 1)? == checksum(key, watermark) {
+        Some(watermark.to_vec())
+    } else {
+        None
+    }
+}
+
+fn checksum(key: u64, watermark: &[u8]) -> u8 {
+    let mut hasher = Fnv::new(key);
+    hasher.write(watermark);
+    hasher.finish() as u8
+}
+
+/// The constant holding the `i`th byte of a framed watermark: a keyed tag in
+/// the upper 40 bits, followed by 16 bits of `i` and the byte itself.
+fn constant(key: u64, i: usize, byte: u8) -> u64 {
+    let mut hasher = Fnv::new(key);
+    hasher.write_usize(i);
+    hasher.write_u8(byte);
+    (hasher.finish() << 24) | ((i as u64 & 0xffff) << 8) | u64::from(byte)
+}
+
+/// The local functions of `module`, sorted by a keyed hash of their bodies.
+fn keyed_functions(module: &Module, key: u64) -> Vec<FunctionId> {
+    let fingerprints = Fingerprints::new(module, key);
+    let mut funcs = module
+        .funcs
+        .iter_local()
+        .map(|(id, func)| (fingerprints.of(func), id))
+        .collect::<Vec<_>>();
+    funcs.sort();
+    funcs.into_iter().map(|(_, id)| id).collect()
+}
+
+/// Pairs of local functions whose order holds one bit each.
+///
+/// Functions are paired in the order of a keyed hash of their bodies, which
+/// doesn't change when indices, names or the order of functions do. Functions
+/// with identical hashes are left out, since they can't be told apart.
+fn pairs(module: &Module, key: u64) -> Vec<(FunctionId, FunctionId)> {
+    let fingerprints = Fingerprints::new(module, key);
+    let mut counts = HashMap::new();
+    let funcs = module
+        .funcs
+        .iter_local()
+        .map(|(id, func)| {
+            let fingerprint = fingerprints.of(func);
+            *counts.entry(fingerprint).or_insert(0) += 1;
+            (fingerprint, id)
+        })
+        .collect::<Vec<_>>();
+    let mut unique = funcs
+        .into_iter()
+        .filter(|(fingerprint, _)| counts[fingerprint] == 1)
+        .collect::<Vec<_>>();
+    unique.sort();
+    unique
+        .chunks_exact(2)
+        .map(|pair| (pair[0].1, pair[1].1))
+        .collect()
+}
+
+/// Where each local function is in the function index space.
+fn positions(module: &Module) -> HashMap<FunctionId, usize> {
+    let order = module.funcs.emit_order();
+    let listed = order.iter().collect::<HashSet<_>>();
+    order
+        .iter()
+        .copied()
+        .chain(
+            module
+                .funcs
+                .iter_local()
+                .map(|(id, _)| id)
+                .filter(|id| !listed.contains(id)),
+        )
+        .enumerate()
+        .map(|(i, id)| (id, i))
+        .collect()
+}
+
+/// Keyed hashes of the types and bodies of local functions, ignoring the ids
+/// of anything they refer to.
+///
+/// Bodies are hashed in their binary encoding, so the hashes only depend on
+/// the opcodes of instructions and the bits of their immediates, but with
+/// every item and local at index 0.
+struct Fingerprints<'a> {
+    module: &'a Module,
+    key: u64,
+    indices: IdsToIndices,
+    locals: IdHashMap<Local, u32>,
+}
+
+impl<'a> Fingerprints<'a> {
+    fn new(module: &'a Module, key: u64) -> Fingerprints<'a> {
+        Fingerprints {
+            module,
+            key,
+            indices: IdsToIndices::zeroed(module),
+            locals: module.locals.iter().map(|local| (local.id(), 0)).collect(),
+        }
+    }
+
+    fn of(&self, func: &LocalFunction) -> u64 {
+        let mut wasm = Vec::new();
+        let mut encoder = Encoder::new(&mut wasm);
+        let (params, results) = self.module.types.params_results(func.ty());
+        for tys in [params, results].iter() {
+            encoder.usize(tys.len());
+            for ty in tys.iter() {
+                ty.emit(&mut encoder, &self.indices);
+            }
+        }
+        func.emit_instructions(&self.indices, &self.locals, &mut encoder, None, true);
+
+        let mut hasher = Fnv::new(self.key);
+        hasher.write(&wasm);
+        hasher.finish()
+    }
+}
+
+/// Collects the constants that are dropped right away.
+#[derive(Default)]
+struct FindConstants {
+    values: Vec<u64>,
+}
+
+impl<'instr> Visitor<'instr> for FindConstants {
+    fn start_instr_seq(&mut self, seq: &'instr InstrSeq) {
+        for window in seq.instrs.windows(2) {
+            if let [(Instr::Const(c), _), (Instr::Drop(_), _)] = window {
+                if let Value::I64(value) = c.value {
+                    self.values.push(value as u64);
+                }
+            }
+        }
+    }
+}
+
+/// FNV-1a, seeded with the key, since the hashes have to be the same for
+/// every build that embeds or detects watermarks.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new(key: u64) -> Fnv {
+        let mut hasher = Fnv(0xcbf2_9ce4_8422_2325);
+        hasher.write_u64(key);
+        hasher
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    // Integers are hashed in the same byte order everywhere.
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
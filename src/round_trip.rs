@@ -0,0 +1,59 @@
+//! Checking that modules survive being emitted and parsed again.
+//!
+//! Emitting a module, parsing the result and emitting that again gives the
+//! same bytes for every module that walrus handles correctly, and walrus's
+//! own tests check this for their whole corpus. Pass authors can check it for
+//! the modules their passes produce, to catch IR that doesn't parse back, such
+//! as instructions with the wrong operand types, or that the emitter doesn't
+//! handle consistently.
+
+use crate::Module;
+use anyhow::{bail, Context};
+
+/// Check that `module` round trips, returning its emitted binary.
+///
+/// This emits `module`, parses the binary with the module's configuration,
+/// and emits the parsed module again. It fails if parsing fails, or if the
+/// second binary differs from the first.
+pub fn check(module: &mut Module) -> crate::Result<Vec<u8>> {
+    let wasm = module.emit_wasm();
+    let mut parsed = module
+        .config
+        .parse(&wasm)
+        .context("failed to parse the emitted module")?;
+    let again = parsed.emit_wasm();
+
+    let differs = wasm.iter().zip(&again).position(|(a, b)| a != b);
+    let offset = match differs {
+        Some(offset) => offset,
+        None if wasm.len() != again.len() => wasm.len().min(again.len()),
+        None => return Ok(wasm),
+    };
+    let section = parsed
+        .sections_layout()
+        .iter()
+        .rev()
+        .find(|section| section.range.start <= offset);
+    match section {
+        Some(section) => bail!(
+            "emitting the parsed module again changed byte {:#x}, in the `{}` section",
+            offset,
+            section.name
+        ),
+        None => bail!(
+            "emitting the parsed module again changed byte {:#x}, in the header",
+            offset
+        ),
+    }
+}
+
+/// Assert that `module` round trips, as checked by `check`.
+///
+/// # Panics
+///
+/// Panics with the reason if `module` doesn't round trip.
+pub fn assert_round_trips(module: &mut Module) {
+    if let Err(e) = check(module) {
+        panic!("module doesn't round trip: {:?}", e);
+    }
+}
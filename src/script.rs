@@ -0,0 +1,476 @@
+//! Recording edits to modules, to reapply them to later builds.
+//!
+//! A `Script` is a list of high-level edits, like renaming an export or
+//! deleting a function. Performing edits through a script both applies them
+//! to a module and records them. The script can then be saved as text and
+//! replayed on a newer build of the same module, for example to maintain a
+//! set of patches to a module that is rebuilt from time to time.
+//!
+//! Ids aren't stable from one build to the next, so edits refer to functions
+//! by their names in the `name` section, and to exports by their names.
+//!
+//! The text form of a script has one edit per line, with quoted names:
+//!
+//! ```text
+//! rename-export "old" "new"
+//! delete-export "name"
+//! rename-function "old" "new"
+//! delete-function "name"
+//! insert "function" 0 i32.const 1 local.get 0 call "log"
+//! ```
+//!
+//! Blank lines and lines starting with `#` are ignored.
+
+use crate::ir::*;
+use crate::{ExportId, ExportItem, FunctionId, FunctionKind, Module, Result, TableKind, ValType};
+use anyhow::{bail, Context};
+use std::fmt;
+
+/// A script of edits to a module.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    edits: Vec<Edit>,
+}
+
+/// An edit to a module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    /// Rename the export `from` to `to`.
+    RenameExport {
+        /// The current name of the export.
+        from: String,
+        /// The new name of the export.
+        to: String,
+    },
+    /// Delete the export `name`.
+    DeleteExport {
+        /// The name of the export.
+        name: String,
+    },
+    /// Rename the function `from` to `to`.
+    RenameFunction {
+        /// The current name of the function.
+        from: String,
+        /// The new name of the function.
+        to: String,
+    },
+    /// Delete the function `name`, along with its exports.
+    ///
+    /// The function must not be used by anything else.
+    DeleteFunction {
+        /// The name of the function.
+        name: String,
+    },
+    /// Insert `instrs` into the body of the function `function`, before the
+    /// instruction at `position` in its entry block.
+    ///
+    /// The inserted instructions must leave the stack as they found it.
+    Insert {
+        /// The name of the function.
+        function: String,
+        /// Where to insert the instructions in the function's entry block.
+        position: usize,
+        /// The instructions to insert.
+        instrs: Vec<ScriptInstr>,
+    },
+}
+
+/// An instruction that a script can insert.
+///
+/// Unlike `Instr`, these refer to functions by name rather than by id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptInstr {
+    /// `i32.const`
+    I32Const(i32),
+    /// `i64.const`
+    I64Const(i64),
+    /// `local.get` of the argument with the given index.
+    LocalGet(u32),
+    /// `call` of the function with the given name.
+    Call(String),
+    /// `drop`
+    Drop,
+}
+
+impl Script {
+    /// Create a new, empty script.
+    pub fn new() -> Script {
+        Script::default()
+    }
+
+    /// Get the edits of this script, in order.
+    pub fn edits(&self) -> &[Edit] {
+        &self.edits
+    }
+
+    /// Apply `edit` to `module`, and record it at the end of this script if
+    /// it applied.
+    pub fn perform(&mut self, module: &mut Module, edit: Edit) -> Result<()> {
+        edit.apply(module)?;
+        self.edits.push(edit);
+        Ok(())
+    }
+
+    /// Apply all of the edits of this script to `module`, in order.
+    ///
+    /// Stops at the first edit that fails to apply, leaving the edits before
+    /// it applied.
+    pub fn replay(&self, module: &mut Module) -> Result<()> {
+        for (i, edit) in self.edits.iter().enumerate() {
+            edit.apply(module)
+                .with_context(|| format!("failed to replay edit {}: `{}`", i + 1, edit))?;
+        }
+        Ok(())
+    }
+
+    /// Parse a script from its text form.
+    pub fn parse(text: &str) -> Result<Script> {
+        let mut edits = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let edit =
+                Edit::parse(line).with_context(|| format!("invalid edit on line {}", i + 1))?;
+            edits.push(edit);
+        }
+        Ok(Script { edits })
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for edit in self.edits.iter() {
+            writeln!(f, "{}", edit)?;
+        }
+        Ok(())
+    }
+}
+
+impl Edit {
+    /// Apply this edit to `module`.
+    pub fn apply(&self, module: &mut Module) -> Result<()> {
+        match self {
+            Edit::RenameExport { from, to } => {
+                let export = export(module, from)?;
+                module.exports.get_mut(export).name = to.clone();
+            }
+            Edit::DeleteExport { name } => {
+                let export = export(module, name)?;
+                module.exports.delete(export);
+            }
+            Edit::RenameFunction { from, to } => {
+                let func = function(module, from)?;
+                module.funcs.get_mut(func).name = Some(to.clone());
+            }
+            Edit::DeleteFunction { name } => {
+                let func = function(module, name)?;
+                if is_used(module, func) {
+                    bail!("function `{}` is still used", name);
+                }
+                let exports = module
+                    .exports
+                    .iter()
+                    .filter(|e| matches!(e.item, ExportItem::Function(f) if f == func))
+                    .map(|e| e.id())
+                    .collect::<Vec<_>>();
+                for export in exports {
+                    module.exports.delete(export);
+                }
+                module.funcs.delete(func);
+            }
+            Edit::Insert {
+                function: name,
+                position,
+                instrs,
+            } => {
+                let func = function(module, name)?;
+                let instrs = lower(module, func, instrs)?;
+                let func = match &mut module.funcs.get_mut(func).kind {
+                    FunctionKind::Local(func) => func,
+                    _ => bail!("function `{}` is not a local function", name),
+                };
+                let entry = func.entry_block();
+                let body = &mut func.block_mut(entry).instrs;
+                if *position > body.len() {
+                    bail!(
+                        "function `{}` has only {} instructions, can't insert at {}",
+                        name,
+                        body.len(),
+                        position
+                    );
+                }
+                let instrs = instrs.into_iter().map(|i| (i, InstrLocId::default()));
+                body.splice(*position..*position, instrs);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse(line: &str) -> Result<Edit> {
+        let mut tokens = Tokens::new(line);
+        let edit = match tokens.word()?.as_str() {
+            "rename-export" => Edit::RenameExport {
+                from: tokens.string()?,
+                to: tokens.string()?,
+            },
+            "delete-export" => Edit::DeleteExport {
+                name: tokens.string()?,
+            },
+            "rename-function" => Edit::RenameFunction {
+                from: tokens.string()?,
+                to: tokens.string()?,
+            },
+            "delete-function" => Edit::DeleteFunction {
+                name: tokens.string()?,
+            },
+            "insert" => {
+                let function = tokens.string()?;
+                let position = tokens.word()?.parse().context("invalid position")?;
+                let mut instrs = Vec::new();
+                while !tokens.is_empty() {
+                    instrs.push(ScriptInstr::parse(&mut tokens)?);
+                }
+                Edit::Insert {
+                    function,
+                    position,
+                    instrs,
+                }
+            }
+            other => bail!("unknown edit `{}`", other),
+        };
+        if !tokens.is_empty() {
+            bail!("unexpected tokens after the edit");
+        }
+        Ok(edit)
+    }
+}
+
+impl fmt::Display for Edit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Edit::RenameExport { from, to } => write!(f, "rename-export {:?} {:?}", from, to),
+            Edit::DeleteExport { name } => write!(f, "delete-export {:?}", name),
+            Edit::RenameFunction { from, to } => write!(f, "rename-function {:?} {:?}", from, to),
+            Edit::DeleteFunction { name } => write!(f, "delete-function {:?}", name),
+            Edit::Insert {
+                function,
+                position,
+                instrs,
+            } => {
+                write!(f, "insert {:?} {}", function, position)?;
+                for instr in instrs {
+                    write!(f, " {}", instr)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ScriptInstr {
+    fn parse(tokens: &mut Tokens) -> Result<ScriptInstr> {
+        Ok(match tokens.word()?.as_str() {
+            "i32.const" => ScriptInstr::I32Const(tokens.word()?.parse()?),
+            "i64.const" => ScriptInstr::I64Const(tokens.word()?.parse()?),
+            "local.get" => ScriptInstr::LocalGet(tokens.word()?.parse()?),
+            "call" => ScriptInstr::Call(tokens.string()?),
+            "drop" => ScriptInstr::Drop,
+            other => bail!("unknown instruction `{}`", other),
+        })
+    }
+}
+
+impl fmt::Display for ScriptInstr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptInstr::I32Const(value) => write!(f, "i32.const {}", value),
+            ScriptInstr::I64Const(value) => write!(f, "i64.const {}", value),
+            ScriptInstr::LocalGet(index) => write!(f, "local.get {}", index),
+            ScriptInstr::Call(name) => write!(f, "call {:?}", name),
+            ScriptInstr::Drop => write!(f, "drop"),
+        }
+    }
+}
+
+fn export(module: &Module, name: &str) -> Result<ExportId> {
+    match module.exports.iter().find(|e| e.name == name) {
+        Some(export) => Ok(export.id()),
+        None => bail!("no export named `{}`", name),
+    }
+}
+
+fn function(module: &Module, name: &str) -> Result<FunctionId> {
+    match module.funcs.by_name(name) {
+        Some(func) => Ok(func),
+        None => bail!("no function named `{}`", name),
+    }
+}
+
+/// Is `func` used by anything other than its own body and its exports?
+fn is_used(module: &Module, func: FunctionId) -> bool {
+    struct Uses {
+        func: FunctionId,
+        used: bool,
+    }
+
+    impl<'instr> Visitor<'instr> for Uses {
+        fn visit_function_id(&mut self, &func: &FunctionId) {
+            self.used |= func == self.func;
+        }
+    }
+
+    let mut uses = Uses { func, used: false };
+    for (id, local) in module.funcs.iter_local() {
+        if id != func {
+            dfs_in_order(&mut uses, local, local.entry_block());
+        }
+    }
+    let tables = module.tables.iter().any(|t| match &t.kind {
+        TableKind::Function(t) => t
+            .elements
+            .iter()
+            .chain(t.relative_elements.iter().flat_map(|(_, e)| e.iter()))
+            .any(|f| *f == Some(func)),
+        TableKind::Anyref(_) => false,
+    });
+    let elements = module
+        .elements
+        .iter()
+        .any(|e| e.members.contains(&Some(func)));
+    uses.used || tables || elements || module.start == Some(func)
+}
+
+/// Turn `instrs` into IR for insertion into `func`, checking that they leave
+/// the stack as they found it.
+fn lower(module: &Module, func: FunctionId, instrs: &[ScriptInstr]) -> Result<Vec<Instr>> {
+    let args = match &module.funcs.get(func).kind {
+        FunctionKind::Local(func) => &func.args,
+        _ => bail!("can only insert instructions into local functions"),
+    };
+
+    let mut stack = Vec::new();
+    let mut lowered = Vec::new();
+    for instr in instrs {
+        lowered.push(match instr {
+            ScriptInstr::I32Const(value) => {
+                stack.push(ValType::I32);
+                Const {
+                    value: Value::I32(*value),
+                }
+                .into()
+            }
+            ScriptInstr::I64Const(value) => {
+                stack.push(ValType::I64);
+                Const {
+                    value: Value::I64(*value),
+                }
+                .into()
+            }
+            ScriptInstr::LocalGet(index) => {
+                let local = match args.get(*index as usize) {
+                    Some(local) => *local,
+                    None => bail!("the function has no argument {}", index),
+                };
+                stack.push(module.locals.get(local).ty());
+                LocalGet { local }.into()
+            }
+            ScriptInstr::Call(name) => {
+                let callee = function(module, name)?;
+                let (params, results) = module.types.params_results(module.funcs.get(callee).ty());
+                for param in params.iter().rev() {
+                    if stack.pop() != Some(*param) {
+                        bail!("wrong arguments for the call to `{}`", name);
+                    }
+                }
+                stack.extend_from_slice(results);
+                Call { func: callee }.into()
+            }
+            ScriptInstr::Drop => {
+                if stack.pop().is_none() {
+                    bail!("nothing to drop");
+                }
+                Drop {}.into()
+            }
+        });
+    }
+    if !stack.is_empty() {
+        bail!("the inserted instructions must leave the stack as they found it");
+    }
+    Ok(lowered)
+}
+
+/// The tokens of a line of a script's text form: words and quoted strings.
+struct Tokens<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(line: &'a str) -> Tokens<'a> {
+        Tokens { rest: line.trim() }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rest.is_empty()
+    }
+
+    fn word(&mut self) -> Result<String> {
+        if self.rest.is_empty() || self.rest.starts_with('"') {
+            bail!("expected a word");
+        }
+        let end = self
+            .rest
+            .find(char::is_whitespace)
+            .unwrap_or(self.rest.len());
+        let word = self.rest[..end].to_string();
+        self.rest = self.rest[end..].trim_start();
+        Ok(word)
+    }
+
+    /// Parse a string quoted by its `Debug` formatting.
+    fn string(&mut self) -> Result<String> {
+        let mut chars = match self.rest.strip_prefix('"') {
+            Some(rest) => rest.char_indices(),
+            None => bail!("expected a quoted string"),
+        };
+        let mut string = String::new();
+        loop {
+            let c = match chars.next() {
+                Some((_, '"')) => break,
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, 'n')) => '\n',
+                    Some((_, 'r')) => '\r',
+                    Some((_, 't')) => '\t',
+                    Some((_, '0')) => '\0',
+                    Some((_, c @ '\\')) | Some((_, c @ '"')) | Some((_, c @ '\'')) => c,
+                    Some((_, 'u')) => {
+                        let code = chars
+                            .by_ref()
+                            .map(|(_, c)| c)
+                            .skip_while(|c| *c == '{')
+                            .take_while(|c| *c != '}')
+                            .collect::<String>();
+                        match u32::from_str_radix(&code, 16)
+                            .ok()
+                            .and_then(std::char::from_u32)
+                        {
+                            Some(c) => c,
+                            None => bail!("invalid unicode escape"),
+                        }
+                    }
+                    _ => bail!("invalid escape in string"),
+                },
+                Some((_, c)) => c,
+                None => bail!("unterminated string"),
+            };
+            string.push(c);
+        }
+        let rest = chars.as_str();
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            bail!("expected whitespace after a string");
+        }
+        self.rest = rest.trim_start();
+        Ok(string)
+    }
+}
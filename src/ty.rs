@@ -1,14 +1,16 @@
 //! WebAssembly function and value types.
 
-use crate::emit::{Emit, EmitContext};
+use crate::emit::{Emit, EmitContext, IdsToIndices};
 use crate::encode::Encoder;
 use crate::error::Result;
+use crate::parse::IndicesToIds;
 use crate::tombstone_arena::Tombstone;
 use anyhow::bail;
 use id_arena::Id;
 use std::cmp::Ordering;
 use std::fmt;
 use std::hash;
+use wasmparser::BinaryReader;
 
 /// An identifier for types.
 pub type TypeId = Id<Type>;
@@ -25,6 +27,14 @@ pub struct Type {
     // serialize the Type section.
     is_for_function_entry: bool,
 
+    // Whether or not this is a `shared` function type, as defined by the
+    // shared-everything-threads proposal.
+    shared: bool,
+
+    // If this is a continuation type, from the stack-switching proposal, the
+    // function type it is a continuation of.
+    cont_of: Option<TypeId>,
+
     /// An optional name for debugging.
     ///
     /// This is not really used by anything currently, but a theoretical WAT to
@@ -39,6 +49,8 @@ impl PartialEq for Type {
         self.params == rhs.params
             && self.results == rhs.results
             && self.is_for_function_entry == rhs.is_for_function_entry
+            && self.shared == rhs.shared
+            && self.cont_of == rhs.cont_of
     }
 }
 
@@ -52,9 +64,14 @@ impl PartialOrd for Type {
 
 impl Ord for Type {
     fn cmp(&self, rhs: &Type) -> Ordering {
-        self.params()
-            .cmp(rhs.params())
+        // Continuation types sort after function types, so that the function
+        // type they refer to is always emitted first.
+        let cont_key = |ty: &Type| ty.cont_of.map(|id| id.index());
+        cont_key(self)
+            .cmp(&cont_key(rhs))
+            .then_with(|| self.params().cmp(rhs.params()))
             .then_with(|| self.results().cmp(rhs.results()))
+            .then_with(|| self.shared.cmp(&rhs.shared))
     }
 }
 
@@ -65,6 +82,8 @@ impl hash::Hash for Type {
         self.params.hash(h);
         self.results.hash(h);
         self.is_for_function_entry.hash(h);
+        self.shared.hash(h);
+        self.cont_of.hash(h);
     }
 }
 
@@ -84,10 +103,32 @@ impl Type {
             params,
             results,
             is_for_function_entry: false,
+            shared: false,
+            cont_of: None,
             name: None,
         }
     }
 
+    /// Construct a new `shared` function type.
+    #[cfg(feature = "unstable")]
+    #[inline]
+    pub(crate) fn new_shared(id: TypeId, params: Box<[ValType]>, results: Box<[ValType]>) -> Type {
+        Type {
+            shared: true,
+            ..Type::new(id, params, results)
+        }
+    }
+
+    /// Construct a new continuation type of the given function type.
+    #[cfg(feature = "unstable")]
+    #[inline]
+    pub(crate) fn new_cont(id: TypeId, func_ty: TypeId) -> Type {
+        Type {
+            cont_of: Some(func_ty),
+            ..Type::new(id, Box::new([]), Box::new([]))
+        }
+    }
+
     /// Construct a new type for function entry blocks.
     #[inline]
     pub(crate) fn for_function_entry(id: TypeId, results: Box<[ValType]>) -> Type {
@@ -97,6 +138,8 @@ impl Type {
             params,
             results,
             is_for_function_entry: true,
+            shared: false,
+            cont_of: None,
             name: None,
         }
     }
@@ -119,6 +162,36 @@ impl Type {
         &*self.results
     }
 
+    /// Is this a `shared` function type?
+    ///
+    /// Shared types are part of the shared-everything-threads proposal, and
+    /// can only be created with the `unstable` feature enabled.
+    #[inline]
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// If this is a continuation type, get the function type it is a
+    /// continuation of.
+    ///
+    /// Continuation types are part of the stack-switching proposal, and can
+    /// only be created with the `unstable` feature enabled. They have no
+    /// parameters or results of their own.
+    #[inline]
+    pub fn cont_of(&self) -> Option<TypeId> {
+        self.cont_of
+    }
+
+    /// The types that this one refers to, which have to come before it in the
+    /// type section.
+    #[cfg(feature = "unstable")]
+    pub(crate) fn referenced_types(&self) -> impl Iterator<Item = TypeId> + '_ {
+        let refs = self.params.iter().chain(self.results.iter());
+        self.cont_of
+            .into_iter()
+            .chain(refs.filter_map(|ty| ty.referenced_type()))
+    }
+
     pub(crate) fn is_for_function_entry(&self) -> bool {
         self.is_for_function_entry
     }
@@ -127,6 +200,15 @@ impl Type {
 impl Emit for Type {
     fn emit(&self, cx: &mut EmitContext) {
         assert!(!self.is_for_function_entry());
+        if let Some(func_ty) = self.cont_of {
+            cx.encoder.byte(0x5d);
+            cx.encoder
+                .i64(i64::from(cx.indices.get_type_index(func_ty)));
+            return;
+        }
+        if self.shared {
+            cx.encoder.byte(0x65);
+        }
         cx.encoder.byte(0x60);
         cx.list(self.params.iter());
         cx.list(self.results.iter());
@@ -148,6 +230,17 @@ pub enum ValType {
     V128,
     /// The `anyref` opaque value type
     Anyref,
+    /// A reference to a continuation of the given continuation type, which
+    /// may be null if `nullable`.
+    ///
+    /// Part of the stack-switching proposal.
+    #[cfg(feature = "unstable")]
+    Cont {
+        /// Whether this reference may be null.
+        nullable: bool,
+        /// The continuation type referred to.
+        ty: TypeId,
+    },
 }
 
 impl ValType {
@@ -171,14 +264,86 @@ impl ValType {
         }
     }
 
-    pub(crate) fn emit(&self, encoder: &mut Encoder) {
+    /// Read a value type with `reader`, including the reference types that
+    /// `wasmparser` can't read.
+    pub(crate) fn read(reader: &mut BinaryReader, ids: &IndicesToIds) -> Result<ValType> {
+        #[cfg(feature = "unstable")]
+        {
+            let mut peek = reader.clone();
+            if let nullable @ (0x63 | 0x64) = peek.read_u8()? {
+                let ty = peek.read_var_s33()?;
+                if ty < 0 {
+                    bail!("unsupported reference type");
+                }
+                let ty = ids.get_type(ty as u32)?;
+                *reader = peek;
+                return Ok(ValType::Cont {
+                    nullable: nullable == 0x63,
+                    ty,
+                });
+            }
+        }
+        #[cfg(not(feature = "unstable"))]
+        let _ = ids;
+        ValType::parse(&reader.read_type()?)
+    }
+
+    /// If this is a continuation reference type, the continuation type it
+    /// refers to.
+    #[cfg(feature = "unstable")]
+    pub(crate) fn referenced_type(self) -> Option<TypeId> {
+        match self {
+            ValType::Cont { ty, .. } => Some(ty),
+            _ => None,
+        }
+    }
+
+    /// Is this a reference type?
+    pub(crate) fn is_reference(self) -> bool {
+        match self {
+            ValType::Anyref => true,
+            #[cfg(feature = "unstable")]
+            ValType::Cont { .. } => true,
+            ValType::I32 | ValType::I64 | ValType::F32 | ValType::F64 | ValType::V128 => false,
+        }
+    }
+
+    /// Whether values of this type may be used where values of type `other`
+    /// are expected.
+    pub(crate) fn is_subtype_of(self, other: ValType) -> bool {
+        match (self, other) {
+            #[cfg(feature = "unstable")]
+            (
+                ValType::Cont { nullable, ty },
+                ValType::Cont {
+                    nullable: other_nullable,
+                    ty: other_ty,
+                },
+            ) => ty == other_ty && (other_nullable || !nullable),
+            _ => self == other,
+        }
+    }
+
+    pub(crate) fn emit(&self, encoder: &mut Encoder, indices: &IdsToIndices) {
+        encoder.encode(&self.to_wasm_encoder(indices));
+    }
+
+    /// The `wasm-encoder` equivalent of this type.
+    pub(crate) fn to_wasm_encoder(self, indices: &IdsToIndices) -> wasm_encoder::ValType {
+        #[cfg(not(feature = "unstable"))]
+        let _ = indices;
         match self {
-            ValType::I32 => encoder.byte(0x7f),
-            ValType::I64 => encoder.byte(0x7e),
-            ValType::F32 => encoder.byte(0x7d),
-            ValType::F64 => encoder.byte(0x7c),
-            ValType::V128 => encoder.byte(0x7b),
-            ValType::Anyref => encoder.byte(0x6f),
+            ValType::I32 => wasm_encoder::ValType::I32,
+            ValType::I64 => wasm_encoder::ValType::I64,
+            ValType::F32 => wasm_encoder::ValType::F32,
+            ValType::F64 => wasm_encoder::ValType::F64,
+            ValType::V128 => wasm_encoder::ValType::V128,
+            ValType::Anyref => wasm_encoder::ValType::Ref(wasm_encoder::RefType::EXTERNREF),
+            #[cfg(feature = "unstable")]
+            ValType::Cont { nullable, ty } => wasm_encoder::ValType::Ref(wasm_encoder::RefType {
+                nullable,
+                heap_type: wasm_encoder::HeapType::Concrete(indices.get_type_index(ty)),
+            }),
         }
     }
 }
@@ -195,6 +360,11 @@ impl fmt::Display for ValType {
                 ValType::F64 => "f64",
                 ValType::V128 => "v128",
                 ValType::Anyref => "anyref",
+                #[cfg(feature = "unstable")]
+                ValType::Cont { nullable, ty } => {
+                    let null = if *nullable { "null " } else { "" };
+                    return write!(f, "(ref {}type[{}])", null, ty.index());
+                }
             }
         )
     }
@@ -202,6 +372,6 @@ impl fmt::Display for ValType {
 
 impl Emit for ValType {
     fn emit(&self, cx: &mut EmitContext) {
-        self.emit(&mut cx.encoder);
+        self.emit(&mut cx.encoder, cx.indices);
     }
 }
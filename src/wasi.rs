@@ -0,0 +1,129 @@
+//! Helpers for modules targeting [WASI](https://wasi.dev/).
+//!
+//! WASI functions are imported from one of the modules in `MODULES`, and are
+//! classified into the `Capability` they give access to by their name.
+
+use crate::{FunctionBuilder, Import, ImportKind, Module, ValType};
+use std::collections::BTreeSet;
+
+/// The module strings that WASI functions are imported from, newest first.
+pub const MODULES: &[&str] = &["wasi_snapshot_preview1", "wasi_unstable"];
+
+/// The errno that WASI functions return when they aren't supported.
+pub const ERRNO_NOSYS: u16 = 52;
+
+/// The kind of access to the outside world that a WASI function gives.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Capability {
+    /// Reading command line arguments, with `args_*`.
+    Args,
+    /// Reading environment variables, with `environ_*`.
+    Environ,
+    /// Reading clocks, with `clock_*`.
+    Clock,
+    /// Operating on file descriptors, including stdio, with `fd_*`.
+    FileDescriptors,
+    /// Operating on paths in the filesystem, with `path_*`.
+    Filesystem,
+    /// Waiting for events, with `poll_oneoff`.
+    Poll,
+    /// Exiting or signaling the process, with `proc_*`.
+    Process,
+    /// Yielding to other threads, with `sched_yield`.
+    Scheduling,
+    /// Getting random bytes, with `random_get`.
+    Random,
+    /// Operating on sockets, with `sock_*`.
+    Sockets,
+}
+
+impl Capability {
+    /// Get the capability that the WASI function `name` gives, if it is a
+    /// known WASI function.
+    pub fn of(name: &str) -> Option<Capability> {
+        let prefix = name.split('_').next().unwrap();
+        Some(match (prefix, name) {
+            ("args", _) => Capability::Args,
+            ("environ", _) => Capability::Environ,
+            ("clock", _) => Capability::Clock,
+            ("fd", _) => Capability::FileDescriptors,
+            ("path", _) => Capability::Filesystem,
+            (_, "poll_oneoff") => Capability::Poll,
+            ("proc", _) => Capability::Process,
+            (_, "sched_yield") => Capability::Scheduling,
+            (_, "random_get") => Capability::Random,
+            ("sock", _) => Capability::Sockets,
+            _ => return None,
+        })
+    }
+}
+
+/// Is `import` imported from WASI?
+pub fn is_wasi_import(import: &Import) -> bool {
+    MODULES.contains(&import.module.as_str())
+}
+
+/// Get the functions that `module` imports from WASI.
+pub fn imports(module: &Module) -> impl Iterator<Item = &Import> {
+    module
+        .imports
+        .iter()
+        .filter(|i| is_wasi_import(i) && matches!(i.kind, ImportKind::Function(_)))
+}
+
+/// Get the capabilities that the WASI functions imported by `module` give.
+///
+/// WASI functions with an unknown name aren't accounted for.
+pub fn capabilities(module: &Module) -> BTreeSet<Capability> {
+    imports(module)
+        .filter_map(|i| Capability::of(&i.name))
+        .collect()
+}
+
+/// Replace the WASI function `name` with a local function that returns
+/// `errno`, such as `ERRNO_NOSYS`, without doing anything.
+///
+/// Functions that don't return an errno, like `proc_exit`, trap instead.
+/// Returns whether `module` imported the function.
+pub fn stub(module: &mut Module, name: &str, errno: u16) -> bool {
+    let stubbed = imports(module)
+        .filter(|i| i.name == name)
+        .map(|i| i.id())
+        .collect::<Vec<_>>();
+    for &import in stubbed.iter() {
+        let func = match module.imports.get(import).kind {
+            ImportKind::Function(id) => id,
+            _ => unreachable!(),
+        };
+        let ty = module.funcs.get(func).ty();
+        let params = module.types.params(ty).to_vec();
+        let results = module.types.results(ty).to_vec();
+
+        let mut builder = FunctionBuilder::new(&mut module.types, &params, &results);
+        builder.name(name.to_string());
+        if results == [ValType::I32] {
+            builder.func_body().i32_const(errno.into());
+        } else {
+            builder.func_body().unreachable();
+        }
+        let args = params.iter().map(|ty| module.locals.add(*ty)).collect();
+        let stub = builder.finish(args, &mut module.funcs);
+        module.replace_imported_func(import, stub);
+    }
+    !stubbed.is_empty()
+}
+
+/// Rename the WASI function `from` to `to`, eg to retarget calls to a
+/// function that was renamed between WASI versions.
+///
+/// Returns whether `module` imported the function.
+pub fn rename(module: &mut Module, from: &str, to: &str) -> bool {
+    let renamed = module.imports.rename(|module, name| {
+        if MODULES.contains(&module) && name == from {
+            Some((module.to_string(), to.to_string()))
+        } else {
+            None
+        }
+    });
+    renamed > 0
+}